@@ -0,0 +1,313 @@
+//! Compares loading filters by joining their entities, buttons and triggers onto each
+//! filter against loading entities and buttons separately and grouping them by owner.
+//! The join returns entities × buttons × triggers rows for every filter, the grouped
+//! queries return entities + buttons rows.
+//!
+//! Runs against a real database. Set `BENCH_DATABASE_URL` to a disposable postgres
+//! database with the bot's migrations applied, the benchmark adds its filters to a chat no
+//! real chat can have and removes them again when done. Nothing is run if it's unset.
+
+use std::collections::{HashMap, HashSet};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use dijkstra::persist::core::{
+    button, entity,
+    entity::get_entity_groups,
+    messageentity::{self, DbMarkupType, EntityWithUser},
+};
+use sea_orm::{
+    ActiveValue::{NotSet, Set},
+    ColumnTrait, ConnectionTrait, Database, DatabaseConnection, DbBackend, EntityTrait,
+    FromQueryResult, QueryFilter, Statement,
+};
+use tokio::runtime::Runtime;
+
+const ENTITIES: i64 = 20;
+const BUTTONS: i32 = 20;
+const TRIGGERS: usize = 3;
+
+/// Chat the benchmark's filters are added to. Telegram never hands out positive ids this
+/// large, so real filters are left alone
+const BENCH_CHAT: i64 = i64::MAX - 3380;
+
+/// Filters of the benchmark chat with their entities, buttons and triggers joined on, as
+/// loaded before the entities and buttons were split out
+const JOIN_QUERY: &str = r#"
+    SELECT f.id, f.entity_id,
+        me.tg_type, me."offset", me.length, me.url, me."user", me.language, me.emoji_id,
+        me.owner_id,
+        b.button_text, b.callback_data, b.button_url, b.pos_x, b.pos_y, b.raw_text,
+        u.user_id, u.first_name, u.last_name, u.username, u.is_bot,
+        t.trigger
+    FROM filters f
+    LEFT JOIN entitylist e ON f.entity_id = e.id
+    LEFT JOIN message_entity me ON me.owner_id = e.id
+    LEFT JOIN button b ON b.owner_id = e.id
+    LEFT JOIN users u ON me."user" = u.user_id
+    LEFT JOIN triggers t ON t.filter_id = f.id
+    WHERE f.chat = $1
+    ORDER BY b.pos_x, b.pos_y
+"#;
+
+/// Filters of the benchmark chat with only their triggers joined on
+const FILTERS_QUERY: &str = r#"
+    SELECT f.id, f.entity_id, t.trigger
+    FROM filters f
+    LEFT JOIN triggers t ON t.filter_id = f.id
+    WHERE f.chat = $1
+"#;
+
+#[derive(FromQueryResult)]
+struct JoinRow {
+    id: i64,
+    entity_id: Option<i64>,
+
+    tg_type: Option<DbMarkupType>,
+    offset: Option<i64>,
+    length: Option<i64>,
+    url: Option<String>,
+    user: Option<i64>,
+    language: Option<String>,
+    emoji_id: Option<String>,
+    owner_id: Option<i64>,
+
+    button_text: Option<String>,
+    callback_data: Option<String>,
+    button_url: Option<String>,
+    pos_x: Option<i32>,
+    pos_y: Option<i32>,
+    raw_text: Option<String>,
+
+    user_id: Option<i64>,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    username: Option<String>,
+    is_bot: Option<bool>,
+
+    trigger: Option<String>,
+}
+
+#[derive(FromQueryResult)]
+struct FilterRow {
+    id: i64,
+    entity_id: Option<i64>,
+    trigger: Option<String>,
+}
+
+type Grouped = (
+    HashSet<EntityWithUser>,
+    HashSet<button::Model>,
+    HashSet<String>,
+);
+
+fn bench_statement(sql: &str) -> Statement {
+    Statement::from_sql_and_values(DbBackend::Postgres, sql, [BENCH_CHAT.into()])
+}
+
+async fn load_join(db: &DatabaseConnection) -> HashMap<i64, Grouped> {
+    let rows = JoinRow::find_by_statement(bench_statement(JOIN_QUERY))
+        .all(db)
+        .await
+        .unwrap();
+    rows.into_iter().fold(HashMap::new(), |mut acc, row| {
+        let (entities, buttons, triggers): &mut Grouped = acc.entry(row.id).or_default();
+        if let (Some(tg_type), Some(offset), Some(length), Some(owner_id)) =
+            (row.tg_type, row.offset, row.length, row.owner_id)
+        {
+            entities.insert(EntityWithUser {
+                tg_type,
+                offset,
+                length,
+                url: row.url,
+                user: row.user,
+                language: row.language,
+                emoji_id: row.emoji_id,
+                owner_id,
+                user_id: row.user_id,
+                first_name: row.first_name,
+                last_name: row.last_name,
+                username: row.username,
+                is_bot: row.is_bot,
+            });
+        }
+        if let (Some(button_text), Some(pos_x), Some(pos_y)) =
+            (row.button_text, row.pos_x, row.pos_y)
+        {
+            buttons.insert(button::Model {
+                button_text,
+                callback_data: row.callback_data,
+                button_url: row.button_url,
+                owner_id: row.entity_id,
+                pos_x,
+                pos_y,
+                raw_text: row.raw_text,
+            });
+        }
+        if let Some(trigger) = row.trigger {
+            triggers.insert(trigger);
+        }
+        acc
+    })
+}
+
+async fn load_grouped(db: &DatabaseConnection) -> HashMap<i64, Grouped> {
+    let rows = FilterRow::find_by_statement(bench_statement(FILTERS_QUERY))
+        .all(db)
+        .await
+        .unwrap();
+    let filters = rows.into_iter().fold(
+        HashMap::<i64, (Option<i64>, HashSet<String>)>::new(),
+        |mut acc, row| {
+            let (entity_id, triggers) = acc.entry(row.id).or_default();
+            *entity_id = row.entity_id;
+            if let Some(trigger) = row.trigger {
+                triggers.insert(trigger);
+            }
+            acc
+        },
+    );
+
+    let mut groups = get_entity_groups(db, filters.values().filter_map(|(id, _)| *id))
+        .await
+        .unwrap();
+    filters
+        .into_iter()
+        .map(|(id, (entity_id, triggers))| {
+            let (entities, buttons) = groups.take(entity_id);
+            (id, (entities, buttons, triggers))
+        })
+        .collect()
+}
+
+#[derive(FromQueryResult)]
+struct ListRow {
+    entity_id: Option<i64>,
+}
+
+/// Removes every filter the benchmark added, along with their entities, buttons and
+/// triggers
+async fn clear(db: &DatabaseConnection) {
+    let lists = ListRow::find_by_statement(bench_statement(
+        "SELECT entity_id FROM filters WHERE chat = $1",
+    ))
+    .all(db)
+    .await
+    .unwrap()
+    .into_iter()
+    .filter_map(|row| row.entity_id)
+    .collect::<Vec<i64>>();
+    for sql in [
+        "DELETE FROM triggers WHERE filter_id IN (SELECT id FROM filters WHERE chat = $1)",
+        "DELETE FROM filters WHERE chat = $1",
+    ] {
+        db.execute(bench_statement(sql)).await.unwrap();
+    }
+    messageentity::Entity::delete_many()
+        .filter(messageentity::Column::OwnerId.is_in(lists.iter().copied()))
+        .exec(db)
+        .await
+        .unwrap();
+    button::Entity::delete_many()
+        .filter(button::Column::OwnerId.is_in(lists.iter().copied()))
+        .exec(db)
+        .await
+        .unwrap();
+    entity::Entity::delete_many()
+        .filter(entity::Column::Id.is_in(lists))
+        .exec(db)
+        .await
+        .unwrap();
+}
+
+/// Adds `filters` filters to the benchmark chat, each with its own entities, buttons and
+/// triggers
+async fn seed(db: &DatabaseConnection, filters: usize) {
+    for _ in 0..filters {
+        let owner = entity::Entity::insert(entity::ActiveModel { id: NotSet })
+            .exec_with_returning(db)
+            .await
+            .unwrap()
+            .id;
+
+        messageentity::Entity::insert_many((0..ENTITIES).map(|offset| {
+            messageentity::ActiveModel {
+                tg_type: Set(DbMarkupType::Bold),
+                offset: Set(offset),
+                length: Set(4),
+                url: Set(None),
+                user: Set(None),
+                language: Set(None),
+                emoji_id: Set(None),
+                owner_id: Set(owner),
+            }
+        }))
+        .exec(db)
+        .await
+        .unwrap();
+
+        button::Entity::insert_many((0..BUTTONS).map(|pos_x| button::ActiveModel {
+            button_text: Set(format!("button {}", pos_x)),
+            callback_data: Set(None),
+            button_url: Set(Some("https://example.com".to_owned())),
+            owner_id: Set(Some(owner)),
+            pos_x: Set(pos_x),
+            pos_y: Set(0),
+            raw_text: Set(None),
+        }))
+        .exec(db)
+        .await
+        .unwrap();
+
+        let filter = db
+            .query_one(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                "INSERT INTO filters (chat, text, media_type, entity_id) \
+                 VALUES ($1, 'benchmark', 4, $2) RETURNING id",
+                [BENCH_CHAT.into(), owner.into()],
+            ))
+            .await
+            .unwrap()
+            .unwrap()
+            .try_get::<i64>("", "id")
+            .unwrap();
+        for trigger in 0..TRIGGERS {
+            db.execute(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                "INSERT INTO triggers (trigger, filter_id) VALUES ($1, $2)",
+                [format!("trigger {}", trigger).into(), filter.into()],
+            ))
+            .await
+            .unwrap();
+        }
+    }
+}
+
+fn bench_filters(c: &mut Criterion) {
+    let Ok(url) = std::env::var("BENCH_DATABASE_URL") else {
+        eprintln!("BENCH_DATABASE_URL is not set, skipping filter benchmarks");
+        return;
+    };
+    let rt = Runtime::new().unwrap();
+    let db = rt.block_on(Database::connect(url)).unwrap();
+
+    let mut group = c.benchmark_group("filters");
+    group.sample_size(10);
+    for filters in [100, 300] {
+        rt.block_on(async {
+            clear(&db).await;
+            seed(&db, filters).await;
+        });
+        assert_eq!(rt.block_on(load_join(&db)), rt.block_on(load_grouped(&db)));
+        group.bench_with_input(BenchmarkId::new("join", filters), &db, |b, db| {
+            b.iter(|| black_box(rt.block_on(load_join(db))))
+        });
+        group.bench_with_input(BenchmarkId::new("grouped", filters), &db, |b, db| {
+            b.iter(|| black_box(rt.block_on(load_grouped(db))))
+        });
+    }
+    group.finish();
+    rt.block_on(clear(&db));
+}
+
+criterion_group!(benches, bench_filters);
+criterion_main!(benches);
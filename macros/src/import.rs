@@ -133,170 +133,36 @@ pub fn autoimport<T: AsRef<str>>(input: T) -> TokenStream {
         .unzip();
     let doc_names = doc_globs.iter().map(|v| format!("{}.mud", v));
     //    assert!(module_globs.len() > 0);
-    let mods = module_globs.clone().into_iter();
-    let updates = module_globs.clone().into_iter();
-    let funcs = module_globs.iter();
-    let exports = module_globs.iter();
-    let imports = module_globs.iter();
+    let mods = module_globs.iter();
     let modules = module_globs.iter();
     let output = quote! {
         #( mod #mods; )*
-        use crate::util::string::Speak;
-        pub fn get_migrations() -> ::std::vec::Vec<::std::boxed::Box<dyn ::sea_orm_migration::prelude::MigrationTrait>> {
-            let mut v = ::std::vec::Vec::<::std::boxed::Box<dyn ::sea_orm_migration::prelude::MigrationTrait>>::new();
-            #(
-                if let Some(ref md) = #funcs::METADATA.state {
-                    v.append(&mut md.get_migrations());
-                }
-            )*
-            v
-        }
-
-        pub async fn all_export(chat: i64) -> crate::util::error::Result<crate::tg::import_export::RoseExport> {
-            let mut v = crate::tg::import_export::RoseExport::new();
-            #(
-                if let Some(ref md) = #exports::METADATA.state {
-                    if let (Some(export), Some(name)) = (md.export(chat).await?, md.supports_export()) {
-                        v.data.insert(name.to_owned(), export);
-                    }
-                }
-            )*
-            Ok(v)
-        }
-
-        pub async fn all_import(chat: i64, json: &str) -> crate::util::error::Result<crate::tg::import_export::RoseExport> {
-            let mut v: crate::tg::import_export::RoseExport = ::serde_json::from_str(json)?;
-            #(
-                if let Some(ref md) = #imports::METADATA.state {
-                    if let Some(name) = md.supports_export() {
-                        if let Some(value) = v.data.remove(name) {
-                            md.import(chat, value).await?;
-                        }
-                    }
-                }
-            )*
-            Ok(v)
-        }
 
-        pub fn get_metadata() -> ::std::vec::Vec<crate::metadata::Metadata> {
-            let mut metadata = Vec::new();
+        /// Every module compiled into the bot, including ones disabled in the config
+        pub fn builtin_modules() -> ::std::vec::Vec<&'static dyn crate::metadata::Module> {
+            let mut modules = ::std::vec::Vec::<&'static dyn crate::metadata::Module>::new();
             #(
-                if crate::statics::module_enabled(#module_names) {
-                 metadata.push((*#modules::METADATA).clone());
-                }
+                modules.push(&#modules::Module);
             )*
-
             #(
-                if crate::statics::module_enabled(#module_names) {
-                metadata.push(crate::metadata::Metadata {
-                        name: #doc_globs.to_owned(),
-                        priority: None,
-                        description: crate::metadata::markdownify(std::include_str!(#doc_names)),
-                        commands: ::std::collections::HashMap::new(),
-                        sections: #vecs,
-                        state: None
-                    });
-                }
-            )*
-
-            metadata
-        }
-
-        pub(crate) async fn process_updates(
-            update: ::botapi::gen_types::UpdateExt,
-            helps: ::std::sync::Arc<crate::tg::client::MetadataCollection>,
-            handler: crate::tg::client::UpdateHandler
-            ) -> crate::util::error::Result<()> {
-            match crate::tg::command::StaticContext::get_context(update).await.map(|v| v.yoke()) {
-                Ok(ctx) => {
-                    if let Err(err) = ctx.record_chat_member().await {
-                        log::warn!("failed to record chat member {}", err);
-                        err.record_stats();
-                    }
-
-                    ctx.handle_gbans().await;
-
-                    if let Err(err) = ctx.greeter_handle_update().await {
-                        log::warn!("Failed to greet user {}", err);
-                        err.record_stats();
-                    }
-
-                    if let Err(err) = ctx.handle_pending_action_update().await {
-                        log::warn!("failed to handle pending action: {}", err);
-                        err.record_stats();
-                    }
-
-                    let help = if let Some(&crate::tg::command::Cmd{cmd, ref args, message, lang, ..}) = ctx.cmd() {
-                         match cmd {
-                            "help" => crate::tg::client::show_help(&ctx, message, helps, args).await,
-                            "start" => match args.args.first().map(|a| a.get_text()) {
-                                Some(v) => {
-                                    let deep_args: Option<crate::tg::command::OwnedTextArgs> =
-                                        crate::tg::command::handle_deep_link(&ctx, crate::tg::client::help_key).await?;
-                                    let deep_args = deep_args.as_ref().map(|v| v.get_ref());
-                                    if let (Some("help"), Some(s)) = (v.get(0..4), v.get(4..)) {
-                                        let s = if s.len() > 0 {
-                                            Some(s)
-                                        } else {
-                                            None
-                                        };
-                                        crate::tg::client::show_help(&ctx, message, helps, args).await?;
-                                        Ok(true)
-                                    } else if let Some(deep) = deep_args {
-                                        crate::tg::client::show_help(&ctx, message, helps, &deep).await?;
-                                        Ok(true)
-                                    } else {
-                                        Ok(false)
-                                    }
-                                }
-
-                                None => {
-                                    log::info!("start with lang {:?}", lang);
-                                    message.reply(macros::lang_fmt!(lang, "startcmd")).await?;
-                                    Ok(true)
-                                }
-                            },
-                            _ => Ok(false),
-                        }
-                    } else {
-                       Ok(false)
-                    };
-                    match help {
-                        Ok(false) => {
-                            handler.handle_update(&ctx).await;
-                            #(
-                            if crate::statics::module_enabled(#module_names) {
-                                if let Err(err) = #updates::update_handler::handle_update(&ctx).await {
-                                    err.record_stats();
-                                    match err.get_message().await {
-                                        Err(err) => {
-                                            log::warn!("failed to send error message: {}, what the FLOOP", err);
-                                            err.record_stats();
-                                        }
-                                        Ok(v) => if ! v {
-                                            // if let Some(chat) = ctx.chat() {
-                                            //     if let Err(err) = chat.reply(err.to_string()).await {
-                                            //         log::warn!("triple fault! {}", err);
-                                            //     }
-                                            // }
-
-                                            log::warn!("handle_update {} error: {}", #updates::METADATA.name, err);
-                                        }
-                                    }
-                                }
+                {
+                    static DOCS: crate::once_cell::sync::Lazy<crate::metadata::DocModule> =
+                        crate::once_cell::sync::Lazy::new(|| crate::metadata::DocModule {
+                            name: #doc_globs,
+                            metadata: crate::metadata::Metadata {
+                                name: #doc_globs.to_owned(),
+                                priority: None,
+                                description: crate::metadata::markdownify(std::include_str!(#doc_names)),
+                                commands: ::std::collections::HashMap::new(),
+                                perms: ::std::collections::HashMap::new(),
+                                sections: #vecs,
+                                state: None
                             }
-                        )*}
-                       Ok(true) => (),
-                      Err(err)  => log::warn!("failed help {}", err)
-                    }
-
+                        });
+                    modules.push(&*DOCS);
                 }
-                Err(err) => {
-                    log::warn!("error when getting context {}", err);
-                    err.record_stats()
-                },
-            }
-            Ok(())
+            )*
+            modules
         }
     };
     output
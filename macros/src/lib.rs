@@ -204,6 +204,23 @@ pub fn get_langs(_: TokenStream) -> TokenStream {
 
     let ids: Vec<usize> = (0..STRINGS.len()).collect();
 
+    let locale = LOCALE.read().unwrap();
+    let tables = STRINGS.iter().map(|name| {
+        let v = format_ident!("{}", name.to_case(Case::UpperCamel));
+        let mut strings = locale
+            .langs
+            .get(name)
+            .expect("invalid language")
+            .strings
+            .iter()
+            .collect::<Vec<_>>();
+        strings.sort();
+        let (keys, values): (Vec<_>, Vec<_>) = strings.into_iter().unzip();
+        quote! {
+            Self::#v => &[ #( (#keys, #values) ),* ]
+        }
+    });
+
     let res = quote! {
         #[doc = "Autogenerated language files, edit the files in ./strings to change these"]
         pub mod langs {
@@ -241,6 +258,61 @@ pub fn get_langs(_: TokenStream) -> TokenStream {
                     &self
                 }
 
+                /// Languages alone have no chat to look up custom strings for, so lang_fmt!
+                /// with a language instead of a context always uses the compiled string
+                pub fn string_override(&self, _key: &str) -> Option<&str> {
+                    None
+                }
+
+                /// Gets the strings compiled into this language sorted by key, without the
+                /// english fallback for strings it doesn't translate
+                pub fn strings(&self) -> &'static [(&'static str, &'static str)] {
+                    match self {
+                        #( #tables ),*,
+                        Self::Invalid => &[]
+                    }
+                }
+
+                /// Gets the language used for strings this one doesn't translate. Every
+                /// chain of fallbacks ends at english, which has every string
+                pub fn fallback(&self) -> Option<Self> {
+                    match self {
+                        Self::En => None,
+                        _ => Some(Self::En)
+                    }
+                }
+
+                /// Gets a compiled string by key without falling back to other languages
+                pub fn get_own_string(&self, key: &str) -> Option<&'static str> {
+                    let strings = self.strings();
+                    strings
+                        .binary_search_by(|(k, _)| (*k).cmp(key))
+                        .ok()
+                        .map(|i| strings[i].1)
+                }
+
+                /// Gets a compiled string by key, following the fallback chain like lang_fmt!
+                pub fn get_string(&self, key: &str) -> Option<&'static str> {
+                    let mut lang = Some(*self);
+                    while let Some(l) = lang {
+                        if let Some(string) = l.get_own_string(key) {
+                            return Some(string);
+                        }
+                        lang = l.fallback();
+                    }
+                    None
+                }
+
+                /// Gets the keys english has that this language doesn't translate
+                pub fn missing_strings(&self) -> Vec<&'static str> {
+                    Self::En
+                        .strings()
+                        .iter()
+                        .map(|(key, _)| *key)
+                        .filter(|key| self.get_own_string(key).is_none())
+                        .collect()
+                }
+
                 pub fn into_code(self) -> &'static str {
                     match self {
                         #( #into ),*,
@@ -397,30 +469,47 @@ fn get_match(language: &Expr, key: LitStr, args: Punctuated<Expr, Comma>) -> imp
         .expect("invalid resource");
 
     let c = get_current_crate();
+    // arguments are evaluated once up front since either a chat's custom string or the
+    // compiled one can end up using them
+    let names = (0..args.len())
+        .map(|i| format_ident!("__arg{}", i))
+        .collect::<Vec<_>>();
     let arms = STRINGS
         .iter()
         .map(|thing| (thing, thing.to_case(Case::UpperCamel)))
         .map(|(u, v)| (u, format_ident!("{}", v)))
         .map(|(u, v)| {
-            let idents = args.iter();
+            let names = names.iter();
             if let Some(format) = locale.langs.get(u).unwrap().strings.get(&key.value()) {
                 quote! {
-                    #c ::langs::Lang::#v => format!(#format, #( #idents ),*)
+                    #c ::langs::Lang::#v => format!(#format, #( #names ),*)
                 }
             } else {
                 quote! {
-
-                     #c ::langs::Lang::#v => format!(#format, #( #idents ),*)
+                     #c ::langs::Lang::#v => {
+                        #c ::persist::metrics::count_string_fallback(#u, #key);
+                        format!(#format, #( #names ),*)
+                     }
                 }
             }
         });
 
     let c = get_current_crate();
+    let idents = args.iter();
+    let overrides = names.iter();
 
     quote! {
-        match #language.lang() {
-            #( #arms ),*,
-            #c ::langs::Lang::Invalid => "invalid".to_owned()
+        match ( #( &(#idents), )* ) {
+            ( #( #names, )* ) => match #language.string_override(#key) {
+                ::std::option::Option::Some(__format) => #c ::util::string::format_custom_string(
+                    __format,
+                    &[ #( #overrides as &dyn ::std::fmt::Display ),* ],
+                ),
+                ::std::option::Option::None => match #language.lang() {
+                    #( #arms ),*,
+                    #c ::langs::Lang::Invalid => "invalid".to_owned()
+                }
+            }
         }
     }
 }
@@ -435,19 +524,70 @@ pub fn lang_fmt(tokens: TokenStream) -> TokenStream {
     TokenStream::from(quote! { #m })
 }
 
+/// Registers a module's update handler. Generates the module's `Module` type implementing
+/// the dijkstra Module trait from the annotated function and the `METADATA` static generated
+/// by `metadata!`. Modules with settings on the settings panel or their own middleware pass
+/// them with `#[update_handler(settings = Settings, middleware = Enforce)]`
 #[proc_macro_attribute]
-pub fn update_handler(_: TokenStream, item: TokenStream) -> TokenStream {
+pub fn update_handler(attr: TokenStream, item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as syn::ItemFn);
+    let args = parse_macro_input!(
+        attr with syn::punctuated::Punctuated::<syn::MetaNameValue, syn::Token![,]>::parse_terminated
+    );
     let name = &input.sig.ident;
 
     let c = get_current_crate();
 
+    let mut settings = None;
+    let mut middleware = None;
+    for arg in args {
+        let value = arg.value;
+        if arg.path.is_ident("settings") {
+            settings = Some(quote! {
+                fn settings(&self) -> ::std::option::Option<&'static dyn #c::tg::settings::SettingsProvider> {
+                    ::std::option::Option::Some(&#value)
+                }
+            });
+        } else if arg.path.is_ident("middleware") {
+            middleware = Some(quote! {
+                fn middleware(&self) -> ::std::option::Option<&'static dyn #c::tg::middleware::Middleware> {
+                    ::std::option::Option::Some(&#value)
+                }
+            });
+        } else {
+            return syn::Error::new_spanned(
+                arg.path,
+                "expected settings = <provider> or middleware = <middleware>",
+            )
+            .to_compile_error()
+            .into();
+        }
+    }
+
     quote! {
         #input
-        pub mod update_handler {
-            pub async fn handle_update(context: & #c ::tg::command::Context) -> #c ::util::error::Result<()> {
-                super:: #name (context).await
+
+        /// This module, registered with the bot by discover_mods
+        pub struct Module;
+
+        #[#c::async_trait::async_trait]
+        impl #c::metadata::Module for Module {
+            fn name(&self) -> &'static str {
+                ::std::module_path!().rsplit("::").next().unwrap_or_default()
+            }
+
+            fn metadata(&self) -> &#c::metadata::Metadata {
+                &METADATA
             }
+
+            async fn handle_update(&self, context: &#c::tg::command::Context) -> #c::util::error::Result<()> {
+                #name(context).await
+            }
+
+            #settings
+
+            #middleware
         }
-    }.into()
+    }
+    .into()
 }
@@ -20,6 +20,41 @@ mod m20231029_015614_notes;
 mod m20231029_032907_notes_entity;
 mod m20231117_045213_taint;
 mod m20240220_230802_no_cycle;
+mod m20261016_000001_log_channel;
+mod m20261016_000002_button_domains;
+mod m20261017_000001_chats;
+mod m20261017_000002_chat_activity;
+mod m20261017_000003_birthdays;
+mod m20261017_000004_chat_timezone;
+mod m20261018_000001_join_policy;
+mod m20261018_000002_welcome_mute;
+mod m20261018_000003_antispam;
+mod m20261018_000004_spam_filter;
+mod m20261018_000005_link_domains;
+mod m20261018_000006_clean_commands;
+mod m20261019_000001_username_history;
+mod m20261019_000002_clean_service;
+mod m20261019_000003_voteban;
+mod m20261019_000004_admin_notes;
+mod m20261019_000005_shame;
+mod m20261019_000006_anti_channel;
+mod m20261019_000007_clean_linked;
+mod m20261019_000008_modlog;
+mod m20261019_000009_networks;
+mod m20261019_000010_chat_strings;
+mod m20261019_000011_entity_gc;
+mod m20261019_000012_confirm_actions;
+mod m20261019_000013_command_aliases;
+mod m20261019_000014_recurring_messages;
+mod m20261019_000015_welcome_messages;
+mod m20261019_000016_disable_fun;
+mod m20261019_000017_ocr_blocklists;
+mod m20261019_000018_nsfw_filter;
+mod m20261019_000019_announce_video_chats;
+mod m20261019_000020_chat_boosts;
+mod m20261019_000021_giveaways;
+mod m20261019_000022_network_links;
+mod m20261019_000023_username_history_chat;
 
 pub struct Migrator;
 
@@ -86,6 +121,41 @@ impl MigratorTrait for Migrator {
             Box::new(m20231029_015614_notes::Migration),
             Box::new(m20231029_032907_notes_entity::Migration),
             Box::new(m20240220_230802_no_cycle::Migration),
+            Box::new(m20261016_000001_log_channel::Migration),
+            Box::new(m20261016_000002_button_domains::Migration),
+            Box::new(m20261017_000001_chats::Migration),
+            Box::new(m20261017_000002_chat_activity::Migration),
+            Box::new(m20261017_000003_birthdays::Migration),
+            Box::new(m20261017_000004_chat_timezone::Migration),
+            Box::new(m20261018_000001_join_policy::Migration),
+            Box::new(m20261018_000002_welcome_mute::Migration),
+            Box::new(m20261018_000003_antispam::Migration),
+            Box::new(m20261018_000004_spam_filter::Migration),
+            Box::new(m20261018_000005_link_domains::Migration),
+            Box::new(m20261018_000006_clean_commands::Migration),
+            Box::new(m20261019_000001_username_history::Migration),
+            Box::new(m20261019_000002_clean_service::Migration),
+            Box::new(m20261019_000003_voteban::Migration),
+            Box::new(m20261019_000004_admin_notes::Migration),
+            Box::new(m20261019_000005_shame::Migration),
+            Box::new(m20261019_000006_anti_channel::Migration),
+            Box::new(m20261019_000007_clean_linked::Migration),
+            Box::new(m20261019_000008_modlog::Migration),
+            Box::new(m20261019_000009_networks::Migration),
+            Box::new(m20261019_000010_chat_strings::Migration),
+            Box::new(m20261019_000011_entity_gc::Migration),
+            Box::new(m20261019_000012_confirm_actions::Migration),
+            Box::new(m20261019_000013_command_aliases::Migration),
+            Box::new(m20261019_000014_recurring_messages::Migration),
+            Box::new(m20261019_000015_welcome_messages::Migration),
+            Box::new(m20261019_000016_disable_fun::Migration),
+            Box::new(m20261019_000017_ocr_blocklists::Migration),
+            Box::new(m20261019_000018_nsfw_filter::Migration),
+            Box::new(m20261019_000019_announce_video_chats::Migration),
+            Box::new(m20261019_000020_chat_boosts::Migration),
+            Box::new(m20261019_000021_giveaways::Migration),
+            Box::new(m20261019_000022_network_links::Migration),
+            Box::new(m20261019_000023_username_history_chat::Migration),
         ]);
         core_migrations
     }
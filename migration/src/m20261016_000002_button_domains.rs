@@ -0,0 +1,69 @@
+use dijkstra::persist::{
+    core::{button_domains, dialogs},
+    migrate::ManagerHelper,
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(button_domains::Entity)
+                    .col(
+                        ColumnDef::new(button_domains::Column::ChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(button_domains::Column::Domain)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(button_domains::Column::Allow)
+                            .boolean()
+                            .not_null(),
+                    )
+                    .primary_key(
+                        IndexCreateStatement::new()
+                            .col(button_domains::Column::ChatId)
+                            .col(button_domains::Column::Domain)
+                            .primary(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(dialogs::Entity)
+                    .add_column(
+                        ColumnDef::new(dialogs::Column::ButtonUrlStrict)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(dialogs::Entity)
+                    .drop_column(dialogs::Column::ButtonUrlStrict)
+                    .to_owned(),
+            )
+            .await?;
+        manager.drop_table_auto(button_domains::Entity).await
+    }
+}
@@ -0,0 +1,36 @@
+use dijkstra::persist::{core::chats, migrate::ManagerHelper};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(chats::Entity)
+                    .col(
+                        ColumnDef::new(chats::Column::ChatId)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(chats::Column::ChatType).text().not_null())
+                    .col(
+                        ColumnDef::new(chats::Column::Broadcast)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(chats::Entity).await
+    }
+}
@@ -0,0 +1,39 @@
+use dijkstra::persist::core::chats;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(chats::Entity)
+                    .add_column(ColumnDef::new(chats::Column::Title).text())
+                    .add_column(
+                        ColumnDef::new(chats::Column::LastActivity)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(chats::Entity)
+                    .drop_column(chats::Column::Title)
+                    .drop_column(chats::Column::LastActivity)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+}
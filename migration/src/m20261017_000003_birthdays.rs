@@ -0,0 +1,76 @@
+use dijkstra::persist::{
+    core::{birthday_settings, birthdays},
+    migrate::ManagerHelper,
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(birthdays::Entity)
+                    .col(
+                        ColumnDef::new(birthdays::Column::ChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(birthdays::Column::UserId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(birthdays::Column::Month)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(birthdays::Column::Day).integer().not_null())
+                    .primary_key(
+                        IndexCreateStatement::new()
+                            .col(birthdays::Column::ChatId)
+                            .col(birthdays::Column::UserId)
+                            .primary(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(birthday_settings::Entity)
+                    .col(
+                        ColumnDef::new(birthday_settings::Column::ChatId)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(birthday_settings::Column::Enabled)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .col(ColumnDef::new(birthday_settings::Column::Greeting).text())
+                    .col(
+                        ColumnDef::new(birthday_settings::Column::UtcOffset)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(birthday_settings::Entity).await?;
+        manager.drop_table_auto(birthdays::Entity).await
+    }
+}
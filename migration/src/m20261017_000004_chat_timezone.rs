@@ -0,0 +1,110 @@
+use dijkstra::persist::core::{birthday_settings, dialogs};
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{ConnectionTrait, DatabaseBackend, Statement},
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(Iden)]
+enum BirthdaySettings {
+    UtcOffset,
+}
+
+/// Timezones for utc offsets that aren't whole hours, other offsets map to Etc/GMT zones.
+/// Etc/GMT zones have the sign inverted, Etc/GMT-2 is two hours ahead of utc
+const PARTIAL_OFFSETS: &[(i32, &str)] = &[
+    (-570, "Pacific/Marquesas"),
+    (-210, "America/St_Johns"),
+    (210, "Asia/Tehran"),
+    (270, "Asia/Kabul"),
+    (330, "Asia/Kolkata"),
+    (345, "Asia/Kathmandu"),
+    (390, "Asia/Yangon"),
+    (525, "Australia/Eucla"),
+    (570, "Australia/Darwin"),
+    (765, "Pacific/Chatham"),
+];
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(dialogs::Entity)
+                    .add_column(ColumnDef::new(dialogs::Column::Timezone).text())
+                    .to_owned(),
+            )
+            .await?;
+
+        // birthdays now follow the chat timezone, so chats keep their birthday offset as
+        // their timezone unless they already set one
+        let partial = PARTIAL_OFFSETS
+            .iter()
+            .map(|(offset, tz)| format!("WHEN {} THEN '{}'", offset, tz))
+            .collect::<Vec<String>>()
+            .join(" ");
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                DatabaseBackend::Postgres,
+                format!(
+                    "UPDATE {dialogs} SET {timezone} = CASE b.{offset} {partial}
+                        ELSE CASE WHEN ROUND(b.{offset} / 60.0) > 0
+                            THEN 'Etc/GMT-' || ROUND(b.{offset} / 60.0)::integer
+                            ELSE 'Etc/GMT+' || (-ROUND(b.{offset} / 60.0))::integer
+                        END
+                    END
+                    FROM {settings} b
+                    WHERE {dialogs}.{chat} = b.{settings_chat}
+                        AND {dialogs}.{timezone} IS NULL
+                        AND b.{offset} <> 0;",
+                    dialogs = dialogs::Entity.to_string(),
+                    timezone = dialogs::Column::Timezone.to_string(),
+                    chat = dialogs::Column::ChatId.to_string(),
+                    settings = birthday_settings::Entity.to_string(),
+                    settings_chat = birthday_settings::Column::ChatId.to_string(),
+                    offset = BirthdaySettings::UtcOffset.to_string(),
+                ),
+            ))
+            .await?;
+
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(birthday_settings::Entity)
+                    .drop_column(BirthdaySettings::UtcOffset)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(birthday_settings::Entity)
+                    .add_column(
+                        ColumnDef::new(BirthdaySettings::UtcOffset)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(dialogs::Entity)
+                    .drop_column(dialogs::Column::Timezone)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+use dijkstra::persist::{
+    admin::welcomemute::{self, WelcomeMuteMode},
+    migrate::ManagerHelper,
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(welcomemute::Entity)
+                    .col(
+                        ColumnDef::new(welcomemute::Column::Chat)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(welcomemute::Column::Mode)
+                            .integer()
+                            .not_null()
+                            .default(WelcomeMuteMode::Off),
+                    )
+                    .col(ColumnDef::new(welcomemute::Column::KickTime).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(welcomemute::Entity).await
+    }
+}
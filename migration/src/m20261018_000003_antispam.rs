@@ -0,0 +1,44 @@
+use dijkstra::persist::{
+    admin::antispam::{self, AntiSpamAction},
+    migrate::ManagerHelper,
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(antispam::Entity)
+                    .col(
+                        ColumnDef::new(antispam::Column::Chat)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(antispam::Column::Enabled)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .col(
+                        ColumnDef::new(antispam::Column::Action)
+                            .integer()
+                            .not_null()
+                            .default(AntiSpamAction::Ban),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(antispam::Entity).await
+    }
+}
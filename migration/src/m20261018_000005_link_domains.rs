@@ -0,0 +1,44 @@
+use dijkstra::persist::{core::link_domains, migrate::ManagerHelper};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(link_domains::Entity)
+                    .col(
+                        ColumnDef::new(link_domains::Column::ChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(link_domains::Column::Domain)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(link_domains::Column::Allow)
+                            .boolean()
+                            .not_null(),
+                    )
+                    .primary_key(
+                        IndexCreateStatement::new()
+                            .col(link_domains::Column::ChatId)
+                            .col(link_domains::Column::Domain)
+                            .primary(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(link_domains::Entity).await
+    }
+}
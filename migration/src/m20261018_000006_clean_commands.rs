@@ -0,0 +1,39 @@
+use dijkstra::persist::core::dialogs;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(dialogs::Entity)
+                    .add_column(
+                        ColumnDef::new(dialogs::Column::CleanCommands)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .add_column(ColumnDef::new(dialogs::Column::CleanConfirmTime).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(dialogs::Entity)
+                    .drop_column(dialogs::Column::CleanCommands)
+                    .drop_column(dialogs::Column::CleanConfirmTime)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+}
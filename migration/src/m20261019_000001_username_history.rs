@@ -0,0 +1,51 @@
+use dijkstra::persist::{core::username_history, migrate::ManagerHelper};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(username_history::Entity)
+                    .col(
+                        ColumnDef::new(username_history::Column::UserId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(username_history::Column::Username)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(username_history::Column::FirstSeen)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(username_history::Column::LastSeen)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .primary_key(
+                        IndexCreateStatement::new()
+                            .col(username_history::Column::UserId)
+                            .col(username_history::Column::Username)
+                            .primary(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(username_history::Entity).await
+    }
+}
@@ -0,0 +1,43 @@
+use dijkstra::persist::core::dialogs;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(dialogs::Entity)
+                    .add_column(ColumnDef::new(dialogs::Column::VotebanThreshold).integer())
+                    .add_column(ColumnDef::new(dialogs::Column::VotebanDuration).big_integer())
+                    .add_column(
+                        ColumnDef::new(dialogs::Column::VotebanMute)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .add_column(ColumnDef::new(dialogs::Column::VotebanMinAge).integer())
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(dialogs::Entity)
+                    .drop_column(dialogs::Column::VotebanThreshold)
+                    .drop_column(dialogs::Column::VotebanDuration)
+                    .drop_column(dialogs::Column::VotebanMute)
+                    .drop_column(dialogs::Column::VotebanMinAge)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+}
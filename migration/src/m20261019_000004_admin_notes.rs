@@ -0,0 +1,59 @@
+use dijkstra::persist::{admin::adminnotes, migrate::ManagerHelper};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(adminnotes::Entity)
+                    .col(
+                        ColumnDef::new(adminnotes::Column::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(adminnotes::Column::UserId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(adminnotes::Column::ChatId).big_integer())
+                    .col(ColumnDef::new(adminnotes::Column::Federation).uuid())
+                    .col(
+                        ColumnDef::new(adminnotes::Column::Author)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(adminnotes::Column::Text).text().not_null())
+                    .col(
+                        ColumnDef::new(adminnotes::Column::Created)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("admin_notes_user")
+                    .table(adminnotes::Entity)
+                    .col(adminnotes::Column::UserId)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(adminnotes::Entity).await
+    }
+}
@@ -0,0 +1,71 @@
+use dijkstra::persist::{admin::shames, core::dialogs, migrate::ManagerHelper};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(dialogs::Entity)
+                    .add_column(ColumnDef::new(dialogs::Column::ShameMedia).text())
+                    .add_column(ColumnDef::new(dialogs::Column::ShameMediaType).integer())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(shames::Entity)
+                    .col(
+                        ColumnDef::new(shames::Column::ChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(shames::Column::UserId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(shames::Column::Count)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(shames::Column::LastShamed)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .primary_key(
+                        IndexCreateStatement::new()
+                            .col(shames::Column::ChatId)
+                            .col(shames::Column::UserId)
+                            .primary(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(shames::Entity).await?;
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(dialogs::Entity)
+                    .drop_column(dialogs::Column::ShameMedia)
+                    .drop_column(dialogs::Column::ShameMediaType)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use dijkstra::persist::{admin::modlog, migrate::ManagerHelper};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(modlog::Entity)
+                    .col(
+                        ColumnDef::new(modlog::Column::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(modlog::Column::ChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(modlog::Column::Actor).big_integer())
+                    .col(
+                        ColumnDef::new(modlog::Column::Target)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(modlog::Column::Action).integer().not_null())
+                    .col(ColumnDef::new(modlog::Column::Reason).text())
+                    .col(ColumnDef::new(modlog::Column::Duration).big_integer())
+                    .col(
+                        ColumnDef::new(modlog::Column::Created)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("modlog_chat_target")
+                    .table(modlog::Entity)
+                    .col(modlog::Column::ChatId)
+                    .col(modlog::Column::Target)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("modlog_chat_created")
+                    .table(modlog::Entity)
+                    .col(modlog::Column::ChatId)
+                    .col(modlog::Column::Created)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(modlog::Entity).await
+    }
+}
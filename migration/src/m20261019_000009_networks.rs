@@ -0,0 +1,98 @@
+use dijkstra::persist::{
+    admin::{network_chats, networks},
+    migrate::ManagerHelper,
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(networks::Entity)
+                    .col(
+                        ColumnDef::new(networks::Column::NetworkId)
+                            .uuid()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(networks::Column::Owner)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(networks::Column::Name).text().not_null())
+                    .col(
+                        ColumnDef::new(networks::Column::ShareApprovals)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .col(
+                        ColumnDef::new(networks::Column::ShareBlocklists)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .col(
+                        ColumnDef::new(networks::Column::ShareNotes)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(network_chats::Entity)
+                    .col(
+                        ColumnDef::new(network_chats::Column::ChatId)
+                            .big_integer()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(network_chats::Column::Network)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(network_chats::Column::ShareApprovals).boolean())
+                    .col(ColumnDef::new(network_chats::Column::ShareBlocklists).boolean())
+                    .col(ColumnDef::new(network_chats::Column::ShareNotes).boolean())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .from(network_chats::Entity, network_chats::Column::Network)
+                    .to(networks::Entity, networks::Column::NetworkId)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .name("fk_network_chats")
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("network_chats_network")
+                    .table(network_chats::Entity)
+                    .col(network_chats::Column::Network)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(network_chats::Entity).await?;
+        manager.drop_table_auto(networks::Entity).await
+    }
+}
@@ -0,0 +1,40 @@
+use dijkstra::persist::{core::chat_strings, migrate::ManagerHelper};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(chat_strings::Entity)
+                    .col(
+                        ColumnDef::new(chat_strings::Column::ChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(chat_strings::Column::Key).text().not_null())
+                    .col(
+                        ColumnDef::new(chat_strings::Column::Value)
+                            .text()
+                            .not_null(),
+                    )
+                    .primary_key(
+                        IndexCreateStatement::new()
+                            .col(chat_strings::Column::ChatId)
+                            .col(chat_strings::Column::Key)
+                            .primary(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(chat_strings::Entity).await
+    }
+}
@@ -0,0 +1,146 @@
+use dijkstra::{
+    persist::core::{button, entity, messageentity, notes, welcomes},
+    sea_orm::{DatabaseBackend, Statement},
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+async fn set_welcome_fks(
+    manager: &SchemaManager<'_>,
+    action: ForeignKeyAction,
+) -> Result<(), DbErr> {
+    for (name, column) in [
+        ("welcomes_entity_fk", welcomes::Column::WelcomeEntityId),
+        (
+            "welcomes_goodbye_entity_fk",
+            welcomes::Column::GoodbyeEntityId,
+        ),
+    ] {
+        manager
+            .drop_foreign_key(
+                ForeignKey::drop()
+                    .table(welcomes::Entity)
+                    .name(name)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name(name)
+                    .from(welcomes::Entity, column)
+                    .to(entity::Entity, entity::Column::Id)
+                    .on_delete(action.clone())
+                    .to_owned(),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // notes never had a foreign key, so some may point at entities that are gone
+        manager
+            .get_connection()
+            .query_one(Statement::from_string(
+                DatabaseBackend::Postgres,
+                format!(
+                    "UPDATE {notes} SET {col} = NULL WHERE {col} IS NOT NULL
+                    AND NOT EXISTS (SELECT 1 FROM {entity} WHERE {entity}.{id} = {notes}.{col});",
+                    notes = notes::Entity.to_string(),
+                    col = notes::Column::EntityId.to_string(),
+                    entity = entity::Entity.to_string(),
+                    id = entity::Column::Id.to_string(),
+                ),
+            ))
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("notes_entity_fk")
+                    .from(notes::Entity, notes::Column::EntityId)
+                    .to(entity::Entity, entity::Column::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await?;
+
+        set_welcome_fks(manager, ForeignKeyAction::SetNull).await?;
+
+        // deleting from entitylist looks up every referencing row, so index the owners
+        for index in [
+            Index::create()
+                .name("messageentity_owner_index")
+                .table(messageentity::Entity)
+                .col(messageentity::Column::OwnerId)
+                .to_owned(),
+            Index::create()
+                .name("button_owner_index")
+                .table(button::Entity)
+                .col(button::Column::OwnerId)
+                .to_owned(),
+            Index::create()
+                .name("notes_entity_index")
+                .table(notes::Entity)
+                .col(notes::Column::EntityId)
+                .to_owned(),
+            Index::create()
+                .name("welcomes_entity_index")
+                .table(welcomes::Entity)
+                .col(welcomes::Column::WelcomeEntityId)
+                .to_owned(),
+            Index::create()
+                .name("welcomes_goodbye_entity_index")
+                .table(welcomes::Entity)
+                .col(welcomes::Column::GoodbyeEntityId)
+                .to_owned(),
+        ] {
+            manager.create_index(index).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for index in [
+            IndexDropStatement::new()
+                .table(welcomes::Entity)
+                .name("welcomes_goodbye_entity_index")
+                .to_owned(),
+            IndexDropStatement::new()
+                .table(welcomes::Entity)
+                .name("welcomes_entity_index")
+                .to_owned(),
+            IndexDropStatement::new()
+                .table(notes::Entity)
+                .name("notes_entity_index")
+                .to_owned(),
+            IndexDropStatement::new()
+                .table(button::Entity)
+                .name("button_owner_index")
+                .to_owned(),
+            IndexDropStatement::new()
+                .table(messageentity::Entity)
+                .name("messageentity_owner_index")
+                .to_owned(),
+        ] {
+            manager.drop_index(index).await?;
+        }
+
+        set_welcome_fks(manager, ForeignKeyAction::NoAction).await?;
+
+        manager
+            .drop_foreign_key(
+                ForeignKey::drop()
+                    .table(notes::Entity)
+                    .name("notes_entity_fk")
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+}
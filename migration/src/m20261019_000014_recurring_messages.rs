@@ -0,0 +1,66 @@
+use dijkstra::persist::{core::recurring_messages, migrate::ManagerHelper};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(recurring_messages::Entity)
+                    .col(
+                        ColumnDef::new(recurring_messages::Column::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(recurring_messages::Column::ChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(recurring_messages::Column::Schedule)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(recurring_messages::Column::Text)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(recurring_messages::Column::Author)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(recurring_messages::Column::Created)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("recurring_messages_chat")
+                    .table(recurring_messages::Entity)
+                    .col(recurring_messages::Column::ChatId)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(recurring_messages::Entity).await
+    }
+}
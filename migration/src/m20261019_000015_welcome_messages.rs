@@ -0,0 +1,208 @@
+use dijkstra::{
+    persist::{
+        core::{entity, welcome_messages, welcomes},
+        migrate::ManagerHelper,
+    },
+    sea_orm::{DatabaseBackend, Statement},
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// MediaType::Text, for welcomes saved before media types were recorded
+const TEXT_MEDIA: i32 = 4;
+
+async fn execute(manager: &SchemaManager<'_>, sql: String) -> Result<(), DbErr> {
+    manager
+        .get_connection()
+        .execute(Statement::from_string(DatabaseBackend::Postgres, sql))
+        .await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(welcome_messages::Entity)
+                    .col(
+                        ColumnDef::new(welcome_messages::Column::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(welcome_messages::Column::Chat)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(welcome_messages::Column::Goodbye)
+                            .boolean()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(welcome_messages::Column::Text).text())
+                    .col(ColumnDef::new(welcome_messages::Column::MediaId).text())
+                    .col(
+                        ColumnDef::new(welcome_messages::Column::MediaType)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(welcome_messages::Column::EntityId).big_integer())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("welcome_messages_entity_fk")
+                            .from(welcome_messages::Entity, welcome_messages::Column::EntityId)
+                            .to(entity::Entity, entity::Column::Id)
+                            .on_delete(ForeignKeyAction::SetNull),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        for index in [
+            Index::create()
+                .name("welcome_messages_chat_index")
+                .table(welcome_messages::Entity)
+                .col(welcome_messages::Column::Chat)
+                .to_owned(),
+            Index::create()
+                .name("welcome_messages_entity_index")
+                .table(welcome_messages::Entity)
+                .col(welcome_messages::Column::EntityId)
+                .to_owned(),
+        ] {
+            manager.create_index(index).await?;
+        }
+
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(welcomes::Entity)
+                    .add_column(
+                        ColumnDef::new(welcomes::Column::Rotation)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // move each chat's only welcome and goodbye over, then empty the old columns
+        for (goodbye, text, media_id, media_type, entity_id) in [
+            (
+                false,
+                welcomes::Column::Text,
+                welcomes::Column::MediaId,
+                welcomes::Column::MediaType,
+                welcomes::Column::WelcomeEntityId,
+            ),
+            (
+                true,
+                welcomes::Column::GoodbyeText,
+                welcomes::Column::GoodbyeMediaId,
+                welcomes::Column::GoodbyeMediaType,
+                welcomes::Column::GoodbyeEntityId,
+            ),
+        ] {
+            execute(
+                manager,
+                format!(
+                    "INSERT INTO {messages} ({chat}, {m_goodbye}, {m_text}, {m_media_id}, {m_media_type}, {m_entity_id})
+                    SELECT {chat}, {goodbye}, {text}, {media_id}, COALESCE({media_type}, {TEXT_MEDIA}), {entity_id}
+                    FROM {welcome}
+                    WHERE {text} IS NOT NULL OR {media_id} IS NOT NULL OR {entity_id} IS NOT NULL;",
+                    messages = welcome_messages::Entity.to_string(),
+                    welcome = welcomes::Entity.to_string(),
+                    chat = welcomes::Column::Chat.to_string(),
+                    m_goodbye = welcome_messages::Column::Goodbye.to_string(),
+                    m_text = welcome_messages::Column::Text.to_string(),
+                    m_media_id = welcome_messages::Column::MediaId.to_string(),
+                    m_media_type = welcome_messages::Column::MediaType.to_string(),
+                    m_entity_id = welcome_messages::Column::EntityId.to_string(),
+                    text = text.to_string(),
+                    media_id = media_id.to_string(),
+                    media_type = media_type.to_string(),
+                    entity_id = entity_id.to_string(),
+                ),
+            )
+            .await?;
+            execute(
+                manager,
+                format!(
+                    "UPDATE {welcome} SET {text} = NULL, {media_id} = NULL, {media_type} = NULL, {entity_id} = NULL;",
+                    welcome = welcomes::Entity.to_string(),
+                    text = text.to_string(),
+                    media_id = media_id.to_string(),
+                    media_type = media_type.to_string(),
+                    entity_id = entity_id.to_string(),
+                ),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // only the oldest welcome and goodbye of each chat fit in the old columns
+        for (goodbye, text, media_id, media_type, entity_id) in [
+            (
+                false,
+                welcomes::Column::Text,
+                welcomes::Column::MediaId,
+                welcomes::Column::MediaType,
+                welcomes::Column::WelcomeEntityId,
+            ),
+            (
+                true,
+                welcomes::Column::GoodbyeText,
+                welcomes::Column::GoodbyeMediaId,
+                welcomes::Column::GoodbyeMediaType,
+                welcomes::Column::GoodbyeEntityId,
+            ),
+        ] {
+            execute(
+                manager,
+                format!(
+                    "UPDATE {welcome} SET {text} = m.{m_text}, {media_id} = m.{m_media_id},
+                        {media_type} = m.{m_media_type}, {entity_id} = m.{m_entity_id}
+                    FROM (
+                        SELECT DISTINCT ON ({m_chat}) * FROM {messages}
+                        WHERE {m_goodbye} = {goodbye} ORDER BY {m_chat}, {m_id}
+                    ) m
+                    WHERE {welcome}.{chat} = m.{m_chat};",
+                    messages = welcome_messages::Entity.to_string(),
+                    welcome = welcomes::Entity.to_string(),
+                    chat = welcomes::Column::Chat.to_string(),
+                    m_id = welcome_messages::Column::Id.to_string(),
+                    m_chat = welcome_messages::Column::Chat.to_string(),
+                    m_goodbye = welcome_messages::Column::Goodbye.to_string(),
+                    m_text = welcome_messages::Column::Text.to_string(),
+                    m_media_id = welcome_messages::Column::MediaId.to_string(),
+                    m_media_type = welcome_messages::Column::MediaType.to_string(),
+                    m_entity_id = welcome_messages::Column::EntityId.to_string(),
+                    text = text.to_string(),
+                    media_id = media_id.to_string(),
+                    media_type = media_type.to_string(),
+                    entity_id = entity_id.to_string(),
+                ),
+            )
+            .await?;
+        }
+
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(welcomes::Entity)
+                    .drop_column(welcomes::Column::Rotation)
+                    .to_owned(),
+            )
+            .await?;
+        manager.drop_table_auto(welcome_messages::Entity).await
+    }
+}
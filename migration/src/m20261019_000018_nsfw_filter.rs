@@ -0,0 +1,50 @@
+use dijkstra::persist::{
+    admin::{actions::ActionType, nsfwfilter},
+    migrate::ManagerHelper,
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(nsfwfilter::Entity)
+                    .col(
+                        ColumnDef::new(nsfwfilter::Column::Chat)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(nsfwfilter::Column::Enabled)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .col(
+                        ColumnDef::new(nsfwfilter::Column::Threshold)
+                            .integer()
+                            .not_null()
+                            .default(80),
+                    )
+                    .col(
+                        ColumnDef::new(nsfwfilter::Column::Action)
+                            .integer()
+                            .not_null()
+                            .default(ActionType::Delete),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(nsfwfilter::Entity).await
+    }
+}
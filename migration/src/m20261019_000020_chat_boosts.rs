@@ -0,0 +1,76 @@
+use dijkstra::persist::{
+    core::{chat_boosts, dialogs},
+    migrate::ManagerHelper,
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(chat_boosts::Entity)
+                    .col(
+                        ColumnDef::new(chat_boosts::Column::ChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(chat_boosts::Column::BoostId)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(chat_boosts::Column::UserId).big_integer())
+                    .col(
+                        ColumnDef::new(chat_boosts::Column::Added)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(chat_boosts::Column::Expires)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .primary_key(
+                        IndexCreateStatement::new()
+                            .col(chat_boosts::Column::ChatId)
+                            .col(chat_boosts::Column::BoostId)
+                            .primary(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(dialogs::Entity)
+                    .add_column(
+                        ColumnDef::new(dialogs::Column::BoosterPerks)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                TableAlterStatement::new()
+                    .table(dialogs::Entity)
+                    .drop_column(dialogs::Column::BoosterPerks)
+                    .to_owned(),
+            )
+            .await?;
+        manager.drop_table_auto(chat_boosts::Entity).await
+    }
+}
@@ -0,0 +1,125 @@
+use dijkstra::persist::{
+    core::{giveaway_entries, giveaways},
+    migrate::ManagerHelper,
+};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(giveaways::Entity)
+                    .col(
+                        ColumnDef::new(giveaways::Column::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(giveaways::Column::ChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(giveaways::Column::MessageId).big_integer())
+                    .col(ColumnDef::new(giveaways::Column::Prize).text().not_null())
+                    .col(
+                        ColumnDef::new(giveaways::Column::Winners)
+                            .integer()
+                            .not_null()
+                            .default(1),
+                    )
+                    .col(
+                        ColumnDef::new(giveaways::Column::Author)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(giveaways::Column::Ends)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(giveaways::Column::Ended)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .col(
+                        ColumnDef::new(giveaways::Column::Announced)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("giveaways_chat")
+                    .table(giveaways::Entity)
+                    .col(giveaways::Column::ChatId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(giveaway_entries::Entity)
+                    .col(
+                        ColumnDef::new(giveaway_entries::Column::GiveawayId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(giveaway_entries::Column::UserId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(giveaway_entries::Column::Entered)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(giveaway_entries::Column::Won)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .primary_key(
+                        IndexCreateStatement::new()
+                            .col(giveaway_entries::Column::GiveawayId)
+                            .col(giveaway_entries::Column::UserId)
+                            .primary(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("giveaway_entries_giveaway_fk")
+                            .from(
+                                giveaway_entries::Entity,
+                                giveaway_entries::Column::GiveawayId,
+                            )
+                            .to(giveaways::Entity, giveaways::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(giveaway_entries::Entity).await?;
+        manager.drop_table_auto(giveaways::Entity).await
+    }
+}
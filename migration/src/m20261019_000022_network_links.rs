@@ -0,0 +1,75 @@
+use dijkstra::persist::{
+    admin::{network_links, networks},
+    migrate::ManagerHelper,
+};
+use sea_orm::{ConnectionTrait, DatabaseBackend, Statement};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(network_links::Entity)
+                    .col(
+                        ColumnDef::new(network_links::Column::ChatId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(network_links::Column::OtherChat)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(network_links::Column::Network)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .primary_key(
+                        IndexCreateStatement::new()
+                            .col(network_links::Column::ChatId)
+                            .col(network_links::Column::OtherChat)
+                            .primary(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .from(network_links::Entity, network_links::Column::Network)
+                    .to(networks::Entity, networks::Column::NetworkId)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .name("fk_network_links")
+                    .to_owned(),
+            )
+            .await?;
+
+        // which chats agreed to share with which isn't known for existing networks, so they
+        // keep sharing between every pair of chats like before
+        manager
+            .get_connection()
+            .execute(Statement::from_string(
+                DatabaseBackend::Postgres,
+                r#"
+                INSERT INTO network_links (chat_id, other_chat, network)
+                SELECT a.chat_id, b.chat_id, a.network
+                FROM network_chats a JOIN network_chats b
+                    ON a.network = b.network AND a.chat_id <> b.chat_id
+                "#
+                .to_owned(),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(network_links::Entity).await
+    }
+}
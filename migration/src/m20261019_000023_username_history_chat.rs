@@ -0,0 +1,65 @@
+use dijkstra::persist::{core::username_history, migrate::ManagerHelper};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Builds the username history table, keyed by chat as well if `chat` is set
+fn history_table(chat: bool) -> TableCreateStatement {
+    let mut table = Table::create();
+    let mut key = IndexCreateStatement::new();
+    table.table(username_history::Entity);
+    if chat {
+        table.col(
+            ColumnDef::new(username_history::Column::ChatId)
+                .big_integer()
+                .not_null(),
+        );
+        key.col(username_history::Column::ChatId);
+    }
+    table
+        .col(
+            ColumnDef::new(username_history::Column::UserId)
+                .big_integer()
+                .not_null(),
+        )
+        .col(
+            ColumnDef::new(username_history::Column::Username)
+                .text()
+                .not_null(),
+        )
+        .col(
+            ColumnDef::new(username_history::Column::FirstSeen)
+                .timestamp_with_time_zone()
+                .not_null()
+                .default(Expr::current_timestamp()),
+        )
+        .col(
+            ColumnDef::new(username_history::Column::LastSeen)
+                .timestamp_with_time_zone()
+                .not_null()
+                .default(Expr::current_timestamp()),
+        )
+        .primary_key(
+            key.col(username_history::Column::UserId)
+                .col(username_history::Column::Username)
+                .primary(),
+        )
+        .to_owned()
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // usernames seen before this weren't tied to a chat, so the history starts over
+        manager.drop_table_auto(username_history::Entity).await?;
+        manager.create_table(history_table(true)).await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_table_auto(username_history::Entity).await?;
+        manager.create_table(history_table(false)).await?;
+        Ok(())
+    }
+}
@@ -1,24 +1,41 @@
+use crate::persist::db;
+use crate::persist::migrate::{run_migration_command, set_migrations, MigrationCommand};
 use crate::persist::redis::RedisPoolBuilder;
-use crate::statics;
-use crate::statics::{
-    Args, ARGS, CLIENT_BACKEND, CONFIG, CONFIG_BACKEND, DB_BACKEND, EXEC, REDIS_BACKEND,
-};
-use crate::tg::client::TgClient;
+use crate::statics::{Args, Config, ARGS, CONFIG, CONFIG_BACKEND, DB_BACKEND, EXEC, REDIS_BACKEND};
+use crate::tg::bots::{all_bots, init_bots};
+use crate::tg::client::{run_bots, TgClient};
+use crate::tg::write_behind;
+use crate::util::config::validate_callback_secret;
 use crate::util::error::{BotError, Result};
 use crate::{logger, DijkstraOpts};
+use arc_swap::ArcSwap;
 use clap::Parser;
 use confy::load_path;
-use nonblock_logger::JoinHandle;
 use prometheus::default_registry;
 use prometheus_hyper::Server;
-use sea_orm::{ConnectOptions, Database};
 use tokio::sync::Notify;
+use tracing_appender::non_blocking::WorkerGuard;
+
+/// Waits for ctrl-c, or on unix for SIGTERM as sent by service managers and containers
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => (),
+            _ = term.recv() => (),
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
 
 fn prometheus_serve() -> tokio::task::JoinHandle<Result<()>> {
     tokio::spawn(async move {
         Server::run(
             default_registry(),
-            CONFIG.logging.prometheus_hook,
+            CONFIG.load().logging.prometheus_hook,
             Notify::new().notified(),
         )
         .await?;
@@ -27,51 +44,85 @@ fn prometheus_serve() -> tokio::task::JoinHandle<Result<()>> {
 }
 
 impl DijkstraOpts {
-    async fn init_real(self) -> Result<JoinHandle> {
-        ARGS.set(Args::parse()).unwrap();
-        let config = if let Some(config) = self.config {
-            config
-        } else {
+    fn load_config(config: Option<Config>) -> Config {
+        config.unwrap_or_else(|| {
             load_path(&ARGS.get().unwrap().config).expect("failed to load config")
-        };
-        CONFIG_BACKEND.set(config).unwrap();
+        })
+    }
 
-        let db = Database::connect(ConnectOptions::new(
-            CONFIG.persistence.database_connection.to_owned(),
-        ))
-        .await?;
+    /// Runs a migration command against the configured database instead of starting the bot
+    async fn migrate(self, command: MigrationCommand) {
+        if let Some(migrations) = self.migrations {
+            set_migrations(migrations);
+        }
+        let config = Self::load_config(self.config);
+        let db = db::connect(&config.persistence.database_connection, &config.persistence)
+            .await
+            .expect("failed to connect to database");
+        if let Err(err) = run_migration_command(&db, command).await {
+            eprintln!("migration failed: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    async fn init_real(self) -> Result<WorkerGuard> {
+        let config = Self::load_config(self.config);
+        validate_callback_secret(&config)?;
+        CONFIG_BACKEND.set(ArcSwap::from_pointee(config)).unwrap();
+
+        let db = db::connect_all(&CONFIG.load().persistence).await?;
         DB_BACKEND.set(db).unwrap();
 
-        let log_handle = logger::setup_log();
+        let log_guard = logger::setup_log();
+        #[cfg(feature = "error-sink")]
+        crate::util::error_sink::init_sinks();
 
-        let client = if let Some(metadata) = self.modules {
-            TgClient::connect_mod(&CONFIG.bot_token, metadata, self.handler)
-        } else {
-            TgClient::connect(&CONFIG.bot_token)
-        };
-        CLIENT_BACKEND.set(client).unwrap();
+        let config = CONFIG.load_full();
+        let tokens = std::iter::once(&config.bot_token)
+            .chain(config.clones.iter().map(|clone| &clone.bot_token));
+        let bots = tokens
+            .map(|token| match self.modules {
+                Some(ref metadata) => {
+                    TgClient::connect_mod(token, metadata.clone(), self.handler.clone())
+                }
+                None => TgClient::connect(token),
+            })
+            .collect();
+        init_bots(bots);
 
         REDIS_BACKEND
             .set(
-                RedisPoolBuilder::new(&CONFIG.persistence.redis_connection)
+                RedisPoolBuilder::new(&CONFIG.load().persistence.redis_connection)
                     .build()
                     .await?,
             )
             .map_err(|_| BotError::generic("Failed to set RedisBackend"))?;
-        Ok(log_handle)
+        Ok(log_guard)
     }
 
-    /// Initialize and run the bot
+    /// Initialize and run the bot, or run a migration command and exit if one of the
+    /// migration flags was passed
     pub fn run(self) {
+        ARGS.set(Args::parse()).unwrap();
+        if let Some(command) = ARGS.get().unwrap().migration_command() {
+            EXEC.block_on(self.migrate(command));
+            return;
+        }
+
         EXEC.block_on(async move {
-            let mut log_handle = self.init_real().await.expect("failed to init state");
+            let log_guard = self.init_real().await.expect("failed to init state");
 
             let handle = prometheus_serve();
-            let me = statics::TG.client.get_me().await.unwrap();
-            statics::ME.set(me).unwrap();
-            statics::TG.run().await.unwrap();
-            handle.await.unwrap().unwrap();
-            log_handle.join();
+            for bot in all_bots() {
+                bot.fetch_me().await.expect("failed to get bot user");
+            }
+            tokio::select! {
+                res = run_bots() => res.unwrap(),
+                _ = shutdown_signal() => log::info!("shutting down"),
+            }
+            write_behind::flush().await;
+            handle.abort();
+            drop(log_guard);
         });
     }
 }
@@ -7,6 +7,7 @@
 //! Dijkstra is under heavy development and the API is not considered stable yet. Check back later for a future
 //! stable release.
 use metadata::Metadata;
+use persist::migrate::MigrationList;
 
 /// Utilities for keeping track of the module list and generating the help menu.
 pub mod metadata;
@@ -31,6 +32,7 @@ pub mod statics;
 
 use macros::get_langs;
 
+pub use async_trait;
 pub use botapi;
 pub use lazy_static;
 pub use macros;
@@ -54,6 +56,7 @@ pub struct DijkstraOpts {
     config: Option<Config>,
     modules: Option<Vec<Metadata>>,
     handler: UpdateHandler,
+    migrations: Option<MigrationList>,
 }
 
 impl Default for DijkstraOpts {
@@ -69,6 +72,7 @@ impl DijkstraOpts {
             config: None,
             modules: None,
             handler: UpdateHandler::new(),
+            migrations: None,
         }
     }
 
@@ -91,4 +95,12 @@ impl DijkstraOpts {
         self.handler = update_handler;
         self
     }
+
+    /// Sets the full list of migrations run by the --migrate, --migrate-down, and
+    /// --migration-status flags, usually `Migrator::migrations` from the migration crate.
+    /// Without this only the migrations from each module are run
+    pub fn migrations(mut self, migrations: MigrationList) -> Self {
+        self.migrations = Some(migrations);
+        self
+    }
 }
@@ -1,22 +1,31 @@
 //! Logging setup and configuration
 //!
-//! currently using nonblock_logger, we need to implement Serialize and Deserialize for log
-//! types to allow configuring logs via the configuration file
-
-use nonblock_logger::log::LevelFilter;
+//! Logs go through tracing. Records from the log crate are forwarded into tracing, so
+//! they are tagged with the span of the update they were logged while handling, see
+//! [`crate::util::trace`]. The filter uses the same directives as RUST_LOG and can be
+//! replaced while running with /logfilter. We need to implement Serialize and Deserialize
+//! for log types to allow configuring logs via the configuration file
 
+use log::LevelFilter;
+use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::util::error::BotError;
 
 #[cfg(not(test))]
-use nonblock_logger::{BaseConsumer, BaseFilter, BaseFormater, JoinHandle, NonblockLogger};
+use tracing_appender::non_blocking::WorkerGuard;
 
 #[cfg(not(test))]
-use std::io;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
 #[cfg(not(test))]
 use crate::statics::CONFIG;
 
-#[derive(Debug)]
+/// Handle for replacing the filter of the running subscriber
+static FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+#[derive(Debug, Clone)]
 pub struct LevelFilterWrapper(pub LevelFilter);
 
 impl Serialize for LevelFilterWrapper {
@@ -57,25 +66,62 @@ impl<'de> Deserialize<'de> for LevelFilterWrapper {
     }
 }
 
-/// Setup logging and start logger thread
+/// Setup logging and start the thread writing logs. Logs still buffered are flushed when
+/// the returned guard is dropped
 #[cfg(not(test))]
-pub(crate) fn setup_log() -> JoinHandle {
-    let formater = BaseFormater::new().local(true).color(true).level(4);
+pub(crate) fn setup_log() -> WorkerGuard {
+    let config = &CONFIG.load().logging;
+    let (writer, guard) = tracing_appender::non_blocking(std::io::stdout());
+    let filter = EnvFilter::try_new(config.get_filter()).unwrap_or_else(|err| {
+        eprintln!("invalid log filter, using log_level: {}", err);
+        EnvFilter::new(config.get_log_level().to_string().to_lowercase())
+    });
+    let (filter, handle) = reload::Layer::new(filter);
+    FILTER.set(handle).ok();
+
+    let registry = tracing_subscriber::registry().with(filter);
+    let res = if config.json {
+        registry
+            .with(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(false)
+                    .with_writer(writer),
+            )
+            .try_init()
+    } else {
+        registry.with(fmt::layer().with_writer(writer)).try_init()
+    };
+    if let Err(err) = res {
+        eprintln!("failed to init logging: {}", err);
+    }
+    guard
+}
+
+/// The filter currently in use, None if logging isn't set up
+pub fn get_log_filter() -> Option<String> {
+    FILTER
+        .get()
+        .and_then(|handle| handle.with_current(|filter| filter.to_string()).ok())
+}
 
-    let filter = BaseFilter::new()
-        .starts_with(true)
-        .max_level(CONFIG.logging.get_log_level());
-    let consumer = BaseConsumer::stdout(filter.max_level_get())
-        .chain(LevelFilter::Error, io::stderr())
-        .unwrap();
+/// Replaces the filter of the running subscriber, returning the filter it replaced
+pub fn set_log_filter(directives: &str) -> crate::util::error::Result<String> {
+    let filter = EnvFilter::try_new(directives).map_err(BotError::generic)?;
 
-    let logger = NonblockLogger::new()
-        .formater(formater)
-        .filter(filter)
-        .and_then(|l| l.consumer(consumer))
-        .unwrap();
-    logger
-        .spawn()
-        .map_err(|e| eprintln!("failed to init nonblock_logger: {:?}", e))
-        .unwrap()
+    // records from the log crate are dropped above the max level set when logging started
+    let level = filter
+        .max_level_hint()
+        .and_then(|level| level.to_string().parse().ok())
+        .unwrap_or(LevelFilter::Trace);
+    let handle = FILTER
+        .get()
+        .ok_or_else(|| BotError::generic("logging is not set up"))?;
+    let old = handle
+        .with_current(|filter| filter.to_string())
+        .map_err(BotError::generic)?;
+    handle.reload(filter).map_err(BotError::generic)?;
+    log::set_max_level(level);
+    Ok(old)
 }
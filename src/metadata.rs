@@ -1,5 +1,7 @@
 //! macros for modules to register themselves with the bot
-//! modules are registered with a name, description, and command list
+//! modules are registered with a name, description, and command list. Every module implements
+//! [`Module`], usually through the metadata! macro and the update_handler attribute, and the
+//! modules enabled in the config are collected into a [`ModuleRegistry`] on startup
 
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -30,7 +32,10 @@ pub fn markdownify<T: AsRef<str>>(description: T) -> String {
 }
 
 /// Macro for registering a module. Generates a metadata getter out of a name, description, and
-/// command list
+/// command list. Commands can list the admin permissions they need with
+/// `perms = [CanRestrictMembers]`, using the permission types from [`crate::tg::extract`], and
+/// the key of the string saying how they are used with `usage = "warntimeusage"`, which is
+/// shown in the help and by [`crate::tg::command::Context::usage_err`]
 #[macro_export]
 macro_rules! metadata {
     ($name:expr, $description:expr) => {
@@ -40,6 +45,8 @@ macro_rules! metadata {
                 priority: None,
                 description: $description.into(),
                 commands: ::std::collections::HashMap::new(),
+                perms: ::std::collections::HashMap::new(),
+                usage: ::std::collections::HashMap::new(),
                 sections: ::std::collections::HashMap::new(),
                 state: None
             });
@@ -47,7 +54,7 @@ macro_rules! metadata {
 
     ($name:expr, $description:expr
         $( , { sub = $sub:expr, content = $content:expr } )*
-        $( , { command = $command:expr, help = $help:expr } )*
+        $( , { command = $command:expr, help = $help:expr $( , usage = $usage:expr )? $( , perms = [ $( $perm:ident ),* ] )? } )*
     ) => {
         #[allow(unused_mut)]
         pub static METADATA: $crate::once_cell::sync::Lazy<$crate::metadata::Metadata> =
@@ -59,10 +66,23 @@ macro_rules! metadata {
                     priority: None,
                     description,
                     commands: ::std::collections::HashMap::new(),
+                    perms: ::std::collections::HashMap::new(),
+                    usage: ::std::collections::HashMap::new(),
                     sections: ::std::collections::HashMap::new(),
                     state: None
                 };
-                $(c.commands.insert($command.into(), $help.into());)*
+                $(
+                    c.commands.insert($command.into(), $help.into());
+                    $(
+                        c.usage.insert($command.into(), $usage.into());
+                    )?
+                    $(
+                        c.perms.insert(
+                            $command.into(),
+                            vec![$($crate::tg::extract::RequiredPermission::of::<$crate::tg::extract::$perm>()),*]
+                        );
+                    )?
+                )*
                 $(
                     let content = $crate::metadata::markdownify($content);
                     c.sections.insert($sub.into(), content.into());
@@ -73,7 +93,7 @@ macro_rules! metadata {
 
     ($name:expr, $description:expr, $serialize:expr
         $( , { sub = $sub:expr, content = $content:expr } )*
-        $( , { command = $command:expr, help = $help:expr } )*
+        $( , { command = $command:expr, help = $help:expr $( , usage = $usage:expr )? $( , perms = [ $( $perm:ident ),* ] )? } )*
     ) => {
         #[allow(unused_mut)]
         pub static METADATA: $crate::once_cell::sync::Lazy<$crate::metadata::Metadata> =
@@ -85,10 +105,23 @@ macro_rules! metadata {
                     priority: None,
                     description,
                     commands: ::std::collections::HashMap::new(),
+                    perms: ::std::collections::HashMap::new(),
+                    usage: ::std::collections::HashMap::new(),
                     sections: ::std::collections::HashMap::new(),
                     state: Some(::std::sync::Arc::new($serialize))
                 };
-                $(c.commands.insert($command.into(), $help.into());)*
+                $(
+                    c.commands.insert($command.into(), $help.into());
+                    $(
+                        c.usage.insert($command.into(), $usage.into());
+                    )?
+                    $(
+                        c.perms.insert(
+                            $command.into(),
+                            vec![$($crate::tg::extract::RequiredPermission::of::<$crate::tg::extract::$perm>()),*]
+                        );
+                    )?
+                )*
                 $(
                     let content = $crate::metadata::markdownify($content);
                     c.sections.insert($sub.into(), content.into());
@@ -99,7 +132,7 @@ macro_rules! metadata {
     };
     ($name:expr, $description:expr, $serialize:expr, $priority:expr
         $( , { sub = $sub:expr, content = $content:expr } )*
-        $( , { command = $command:expr, help = $help:expr } )*
+        $( , { command = $command:expr, help = $help:expr $( , usage = $usage:expr )? $( , perms = [ $( $perm:ident ),* ] )? } )*
     ) => {
         #[allow(unused_mut)]
         pub static METADATA: $crate::once_cell::sync::Lazy<$crate::metadata::Metadata> =
@@ -111,10 +144,23 @@ macro_rules! metadata {
                     priority: Some($priority),
                     description,
                     commands: ::std::collections::HashMap::new(),
+                    perms: ::std::collections::HashMap::new(),
+                    usage: ::std::collections::HashMap::new(),
                     sections: ::std::collections::HashMap::new(),
                     state: Some(::std::sync::Arc::new($serialize))
                 };
-                $(c.commands.insert($command.into(), $help.into());)*
+                $(
+                    c.commands.insert($command.into(), $help.into());
+                    $(
+                        c.usage.insert($command.into(), $usage.into());
+                    )?
+                    $(
+                        c.perms.insert(
+                            $command.into(),
+                            vec![$($crate::tg::extract::RequiredPermission::of::<$crate::tg::extract::$perm>()),*]
+                        );
+                    )?
+                )*
                 $(
                     let content = $crate::metadata::markdownify($content);
                     c.sections.insert($sub.into(), content.into());
@@ -130,6 +176,11 @@ pub use metadata;
 use regex::Regex;
 use sea_orm_migration::MigrationTrait;
 
+use crate::statics::module_enabled;
+use crate::tg::command::Context;
+use crate::tg::extract::RequiredPermission;
+use crate::tg::middleware::Middleware;
+use crate::tg::settings::SettingsProvider;
 use crate::util::error::Result;
 
 /// metadata for a single module
@@ -139,6 +190,11 @@ pub struct Metadata {
     pub priority: Option<i32>,
     pub description: String,
     pub commands: HashMap<String, String>,
+    /// admin permissions each command requires, checked before any module sees the command
+    pub perms: HashMap<String, Vec<RequiredPermission>>,
+    /// key of the string with how each command is used, shown in the help and when a command
+    /// is given arguments it doesn't accept
+    pub usage: HashMap<String, String>,
     pub sections: HashMap<String, String>,
     pub state: Option<Arc<dyn ModuleHelpers + Send + Sync>>,
 }
@@ -150,6 +206,8 @@ impl Metadata {
             priority,
             description,
             commands: HashMap::new(),
+            perms: HashMap::new(),
+            usage: HashMap::new(),
             sections: HashMap::new(),
             state: None,
         }
@@ -160,6 +218,11 @@ impl Metadata {
         self
     }
 
+    pub fn add_usage(mut self, command: String, usage: String) -> Self {
+        self.usage.insert(command, usage);
+        self
+    }
+
     pub fn add_section(mut self, sub: String, content: String) -> Self {
         self.sections.insert(sub, content);
         self
@@ -173,3 +236,126 @@ pub trait ModuleHelpers: std::fmt::Debug {
     fn supports_export(&self) -> Option<&'static str>;
     fn get_migrations(&self) -> Vec<Box<dyn MigrationTrait>>;
 }
+
+/// A module of the bot. Modules in src/modules get this implemented by the update_handler
+/// attribute using the `METADATA` from metadata!, so only the parts that differ between modules
+/// need to be written by hand
+#[async_trait]
+pub trait Module: Send + Sync {
+    /// Name of the module's source file, used to enable and disable it in the config
+    fn name(&self) -> &'static str;
+
+    /// Help text and commands shown in the help menu
+    fn metadata(&self) -> &Metadata;
+
+    /// Database migrations needed by this module
+    fn migrations(&self) -> Vec<Box<dyn MigrationTrait>> {
+        self.metadata()
+            .state
+            .as_ref()
+            .map(|state| state.get_migrations())
+            .unwrap_or_default()
+    }
+
+    /// Handles an update that wasn't already handled as a help or start command
+    async fn handle_update(&self, _ctx: &Context) -> Result<()> {
+        Ok(())
+    }
+
+    /// Settings shown on the /settings panel
+    fn settings(&self) -> Option<&'static dyn SettingsProvider> {
+        None
+    }
+
+    /// Checks run on every update before or after the modules, see [`crate::tg::middleware`]
+    fn middleware(&self) -> Option<&'static dyn Middleware> {
+        None
+    }
+
+    /// Name of this module's section in exported chat data, None if it doesn't export
+    fn supports_export(&self) -> Option<&'static str> {
+        self.metadata()
+            .state
+            .as_ref()
+            .and_then(|state| state.supports_export())
+    }
+
+    /// Exports a chat's data for this module
+    async fn export(&self, chat: i64) -> Result<Option<serde_json::Value>> {
+        match self.metadata().state {
+            Some(ref state) => state.export(chat).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Imports a chat's data for this module from a previous export
+    async fn import(&self, chat: i64, value: serde_json::Value) -> Result<()> {
+        match self.metadata().state {
+            Some(ref state) => state.import(chat, value).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// A module that only adds a page to the help menu, generated from a .mud file in src/modules
+pub struct DocModule {
+    pub name: &'static str,
+    pub metadata: Metadata,
+}
+
+impl Module for DocModule {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+/// The modules a bot runs. Built once on startup, so enabling or disabling modules needs a
+/// restart
+#[derive(Clone)]
+pub struct ModuleRegistry(Vec<&'static dyn Module>);
+
+impl ModuleRegistry {
+    /// Creates a registry from a list of modules, running them in order
+    pub fn new(modules: Vec<&'static dyn Module>) -> Self {
+        Self(modules)
+    }
+
+    /// Creates a registry of the built-in modules enabled in the config
+    pub fn builtin() -> Self {
+        Self(
+            crate::modules::builtin_modules()
+                .into_iter()
+                .filter(|module| module_enabled(module.name()))
+                .collect(),
+        )
+    }
+
+    /// Iterates over the modules in this registry
+    pub fn iter(&self) -> impl Iterator<Item = &'static dyn Module> + '_ {
+        self.0.iter().copied()
+    }
+
+    /// Gets the help menu metadata of every module
+    pub fn metadata(&self) -> Vec<Metadata> {
+        self.iter()
+            .map(|module| module.metadata().clone())
+            .collect()
+    }
+
+    /// Gets the settings panel providers of every module that has settings
+    pub fn settings(&self) -> Vec<&'static dyn SettingsProvider> {
+        self.iter().filter_map(|module| module.settings()).collect()
+    }
+}
+
+impl std::fmt::Debug for ModuleRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.iter().map(|module| module.name()))
+            .finish()
+    }
+}
@@ -1,13 +1,19 @@
-use crate::tg::command::Cmd;
+use crate::tg::admin_helpers::GetChat;
+use crate::tg::clean_commands::set_clean_commands;
+use crate::tg::command::{Cmd, TextArgs};
+use crate::tg::confirm::set_confirm_actions;
+use crate::tg::dialog::dialog_or_default;
 use crate::tg::markdown::EntityMessage;
 use crate::tg::permissions::*;
+use crate::tg::settings::open_settings;
 use crate::tg::user::GetUser;
-use crate::util::error::{BotError, SpeakErr};
+use crate::util::duration::parse_duration;
+use crate::util::error::{BotError, Fail, SpeakErr};
 use crate::{
     metadata::metadata,
     tg::command::Context,
     util::error::Result,
-    util::string::{get_chat_lang, Speak},
+    util::string::{get_chat_lang, Confirm, Lang, Speak},
 };
 
 use futures::{stream, StreamExt, TryStreamExt};
@@ -26,13 +32,50 @@ metadata!("Admin",
     The /admincache command is used to refresh the cached admin list if the admins of a group were
     changed recently. This is to avoid spamming the telegram api. Use this command if the bot
     does not correctly recognize an admin
+
+    /slowmode shows the current slow mode delay. Telegram only allows a fixed set of delays: off,
+    10s, 30s, 1m, 5m, 15m, and 1h. The bot api does not allow bots to change slow mode, so
+    when a valid delay is given the bot explains where to set it instead
+
+    /cleancommands on deletes commands from admins after they run. An optional delay sets how
+    long the bot's confirmations stay before they are deleted too, for example /cleancommands on 30s.
+    Permission errors and admin cache notices are deleted after the same delay, or after a minute
+    if none is given
+
+    /confirmactions on makes /ban and /fban post a prompt with Confirm and Cancel buttons instead
+    of running right away. Only the admin who sent the command can push them, and the prompt
+    expires after 30 seconds
+
+    /settings opens a panel with buttons for changing this chat's settings, like welcomes, warns,
+    and locks, without remembering each command. Only modules you have permission to change are
+    shown
     "#,
     { command = "admincache", help = "Refresh the cached list of admins" },
     { command = "admins", help = "Get a list of admins" },
     { command = "promote", help = "Promote a user to admin"},
-    { command = "demote", help = "Demote a user" }
+    { command = "demote", help = "Demote a user" },
+    { command = "slowmode", help = "Show the slow mode delay.", usage = "slowmodeusage" },
+    { command = "cleancommands", help = "Delete admin commands after they run.", usage = "cleancommandsusage" },
+    { command = "confirmactions", help = "Ask admins to confirm bans with a button first.", usage = "confirmactionsusage" },
+    { command = "settings", help = "Open a panel for changing this chat's settings" }
 );
 
+/// Slow mode delays in seconds accepted by telegram
+const SLOWMODE_DELAYS: &[u64] = &[0, 10, 30, 60, 300, 900, 3600];
+
+/// Parses a slow mode delay in seconds, returning None if telegram doesn't support it
+fn parse_slowmode(text: &str) -> Option<u64> {
+    let delay = match text.trim() {
+        "off" | "no" | "0" => 0,
+        text => parse_duration(text).ok()?.num_seconds() as u64,
+    };
+    SLOWMODE_DELAYS.contains(&delay).then_some(delay)
+}
+
+fn format_slowmode(lang: &Lang, delay: u64) -> String {
+    lang.format_duration(std::time::Duration::from_secs(delay))
+}
+
 async fn promote(context: &Context) -> Result<()> {
     context.check_permissions(|v| v.can_promote_members).await?;
     context
@@ -109,10 +152,8 @@ async fn listadmins(ctx: &Context) -> Result<()> {
 
 async fn admincache(ctx: &Context) -> Result<()> {
     ctx.is_group_or_die().await?;
-    let message = ctx.message()?;
-    let lang = get_chat_lang(message.get_chat().get_id()).await?;
     ctx.force_refresh_cached_admins().await?;
-    message.reply(lang_fmt!(lang, "refreshac")).await?;
+    ctx.reply_notice(lang_fmt!(ctx, "refreshac")).await?;
 
     Ok(())
 }
@@ -123,13 +164,132 @@ pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
     Ok(())
 }
 
+async fn slowmode<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    if args.text.trim().is_empty() {
+        let delay = chat
+            .get_chat_cached()
+            .await?
+            .get_slow_mode_delay()
+            .unwrap_or(0);
+        if delay > 0 {
+            ctx.reply(lang_fmt!(
+                ctx,
+                "slowmodecurrent",
+                format_slowmode(ctx.lang(), delay as u64)
+            ))
+            .await?;
+        } else {
+            ctx.reply(lang_fmt!(ctx, "slowmodeoff")).await?;
+        }
+        return Ok(());
+    }
+
+    ctx.check_permissions(|p| p.can_restrict_members).await?;
+    let Some(delay) = parse_slowmode(args.text) else {
+        return ctx.usage_err("slowmodeusage");
+    };
+    let delay = if delay == 0 {
+        "off".to_owned()
+    } else {
+        format_slowmode(ctx.lang(), delay)
+    };
+    ctx.fail(lang_fmt!(ctx, "slowmodeunsupported", delay))
+}
+
+fn format_seconds(lang: &Lang, secs: i64) -> String {
+    lang.format_duration(std::time::Duration::from_secs(secs as u64))
+}
+
+async fn cleancommands<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat;
+    let mut args = args.text.split_whitespace();
+    match args.next() {
+        None => {
+            let dialog = dialog_or_default(chat).await?;
+            let text = match (dialog.clean_commands, dialog.clean_confirm_time) {
+                (true, Some(time)) => {
+                    lang_fmt!(ctx, "cleancommandsontime", format_seconds(ctx.lang(), time))
+                }
+                (true, None) => lang_fmt!(ctx, "cleancommandson"),
+                (false, _) => lang_fmt!(ctx, "cleancommandsoff"),
+            };
+            ctx.reply(text).await?;
+        }
+        Some("on" | "yes") => {
+            ctx.check_permissions(|p| p.can_delete_messages.and(p.can_change_info))
+                .await?;
+            let time = args
+                .next()
+                .map(|time| {
+                    parse_duration(time)
+                        .map(|t| t.num_seconds())
+                        .map_err(|err| ctx.fail_err(err.describe(ctx.lang())))
+                })
+                .transpose()?;
+            set_clean_commands(chat, true, time).await?;
+            let text = match time {
+                Some(time) => {
+                    lang_fmt!(ctx, "cleancommandsontime", format_seconds(ctx.lang(), time))
+                }
+                None => lang_fmt!(ctx, "cleancommandson"),
+            };
+            ctx.confirm(text).await?;
+        }
+        Some("off" | "no") => {
+            ctx.check_permissions(|p| p.can_delete_messages.and(p.can_change_info))
+                .await?;
+            set_clean_commands(chat, false, None).await?;
+            ctx.confirm(lang_fmt!(ctx, "cleancommandsoff")).await?;
+        }
+        Some(_) => return ctx.usage_err("cleancommandsusage"),
+    }
+    Ok(())
+}
+
+async fn confirmactions<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat;
+    let enabled = match args.text.trim() {
+        "" => {
+            let text = if dialog_or_default(chat).await?.confirm_actions {
+                lang_fmt!(ctx, "confirmactionson")
+            } else {
+                lang_fmt!(ctx, "confirmactionsoff")
+            };
+            ctx.reply(text).await?;
+            return Ok(());
+        }
+        "on" | "yes" => true,
+        "off" | "no" => false,
+        _ => return ctx.usage_err("confirmactionsusage"),
+    };
+
+    ctx.check_permissions(|p| p.can_restrict_members.and(p.can_change_info))
+        .await?;
+    set_confirm_actions(chat, enabled).await?;
+    let text = if enabled {
+        lang_fmt!(ctx, "confirmactionson")
+    } else {
+        lang_fmt!(ctx, "confirmactionsoff")
+    };
+    ctx.confirm(text).await?;
+    Ok(())
+}
+
 async fn handle_command<'a>(ctx: &Context) -> Result<()> {
-    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
         match cmd {
             "admincache" => admincache(ctx).await,
             "admins" => listadmins(ctx).await,
             "promote" => promote(ctx).await,
             "demote" => demote(ctx).await,
+            "slowmode" => slowmode(ctx, args).await,
+            "cleancommands" => cleancommands(ctx, args).await,
+            "confirmactions" => confirmactions(ctx, args).await,
+            "settings" => open_settings(ctx).await,
             _ => Ok(()),
         }?;
     }
@@ -0,0 +1,111 @@
+use crate::metadata::metadata;
+use crate::tg::admin_helpers::DeleteAfterTime;
+use crate::tg::admin_notes::{add_admin_note, format_admin_notes, get_admin_notes};
+use crate::tg::command::{Cmd, Context};
+use crate::tg::extract::{InGroup, TargetUser};
+use crate::tg::federations::{is_fedadmin, is_fedmember};
+use crate::tg::user::{GetUser, Username};
+use crate::util::error::{Fail, Result};
+use crate::util::string::{Confirm, SendOptions, Speak};
+use macros::{lang_fmt, update_handler};
+
+metadata!("Admin Notes",
+    r#"
+    Keep private notes about users, like why someone is being watched. /adminnotes sends the
+    notes about a user to you in a private message, so start a conversation with me first, and
+    /info shows admins how many notes a user has. The /adminnote command is deleted once the
+    note is saved so the note isn't left in the chat.
+
+    Add \-\-fed to save a note for the chat's federation instead, so admins of every chat in
+    the federation see it. Only federation admins can write federation notes.
+
+    [*Example:]
+    /adminnote @user joined with a spam link in their bio
+    "#,
+    { command = "adminnote", help = "Write a private note about a user.", usage = "adminnoteusage", perms = [CanRestrictMembers] },
+    { command = "adminnotes", help = "List the private notes about a user", perms = [CanRestrictMembers] }
+);
+
+async fn adminnote(
+    ctx: &Context,
+    _: InGroup,
+    TargetUser { id: user, args }: TargetUser<'_>,
+) -> Result<()> {
+    let chat = ctx.try_get()?.chat.get_id();
+    let matches = args.map(|args| args.matches()).unwrap_or_default();
+    let text = matches
+        .positional()
+        .iter()
+        .map(|arg| arg.get_text())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if text.is_empty() {
+        return ctx.fail(lang_fmt!(ctx, "adminnoteempty"));
+    }
+
+    let author = ctx.get_real_from()?.get_id();
+    let (chat, fed) = if matches.flag("fed", 'f') {
+        let Some(fed) = is_fedmember(chat).await? else {
+            let name = ctx.try_get()?.chat.name_humanreadable();
+            return ctx.fail(lang_fmt!(ctx, "notinfed", name));
+        };
+        if !is_fedadmin(author, &fed).await? {
+            return ctx.fail(lang_fmt!(ctx, "notfedadmin", fed.to_string()));
+        }
+        (None, Some(fed))
+    } else {
+        (Some(chat), None)
+    };
+
+    add_admin_note(user, chat, fed, author, text).await?;
+    if let Err(err) = ctx.message()?.delete().await {
+        log::debug!("failed to delete adminnote command: {}", err);
+    }
+    let name = user.cached_name().await?;
+    ctx.confirm(lang_fmt!(ctx, "adminnoteadded", name)).await?;
+    Ok(())
+}
+
+async fn adminnotes(
+    ctx: &Context,
+    _: InGroup,
+    TargetUser { id: user, .. }: TargetUser<'_>,
+) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let name = user.cached_name().await?;
+    let notes = get_admin_notes(chat.get_id(), user).await?;
+    if notes.is_empty() {
+        ctx.reply(lang_fmt!(ctx, "noadminnotes", name)).await?;
+        return Ok(());
+    }
+    let mut message = lang_fmt!(ctx, "adminnotes", name, chat.name_humanreadable());
+    message.push_str(&format_admin_notes(chat.get_id(), ctx.lang(), &notes).await?);
+    let admin = ctx.get_real_from()?.get_id();
+    if admin
+        .speak_entities(&message, &[], SendOptions::default())
+        .await
+        .is_err()
+    {
+        return ctx.fail(lang_fmt!(ctx, "adminnotesnodm"));
+    }
+    ctx.confirm(lang_fmt!(ctx, "adminnotessent")).await?;
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+        match cmd {
+            "adminnote" => ctx.run(adminnote).await,
+            "adminnotes" => ctx.run(adminnotes).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use crate::metadata::metadata;
+use crate::statics::TG;
+use crate::tg::aliases::{
+    check_alias, get_command_aliases, remove_alias, resolve_alias, set_alias, AliasError,
+    MAX_ALIASES, UNALIASABLE_MODULES,
+};
+use crate::tg::command::{Cmd, Context};
+use crate::tg::extract::{CanChangeInfo, CommandArgs, InGroup, RequirePerm};
+use crate::util::error::{Fail, Result};
+use crate::util::error_codes::{INVALID_ARGUMENT, NOT_FOUND};
+use crate::util::string::Speak;
+use macros::{lang_fmt, update_handler};
+
+metadata!("Aliases",
+    r#"
+    Give commands other names in this chat. An alias runs its command with the same arguments
+    and the same permission checks, so /alias ban yeet lets admins ban users with /yeet.
+    Aliases can point at other aliases, but can't reuse the name of an existing command
+    "#,
+    { command = "alias", help = "Add an alias for a command.", usage = "aliasusage", perms = [CanChangeInfo] },
+    { command = "unalias", help = "Remove an alias.", usage = "unaliasusage", perms = [CanChangeInfo] },
+    { command = "aliases", help = "List the aliases in this chat" }
+);
+
+/// Whether a name is a command this bot knows, and if so whether it can be aliased.
+/// Commands from modules disabled on this bot or meant for the bot's owner can't be
+fn command_kind(name: &str) -> Option<bool> {
+    if matches!(name, "help" | "start") {
+        return Some(true);
+    }
+    if let Some(module) = TG
+        .registry
+        .iter()
+        .find(|module| module.metadata().commands.contains_key(name))
+    {
+        return Some(!UNALIASABLE_MODULES.contains(&module.metadata().name.as_str()));
+    }
+    crate::modules::builtin_modules()
+        .into_iter()
+        .any(|module| module.metadata().commands.contains_key(name))
+        .then_some(false)
+}
+
+async fn alias(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanChangeInfo>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let [command, name] = args.args.as_slice() else {
+        return ctx.usage_err("aliasusage");
+    };
+    let command = command
+        .get_text()
+        .trim_start_matches(['/', '!'])
+        .to_lowercase();
+    let name = name
+        .get_text()
+        .trim_start_matches(['/', '!'])
+        .to_lowercase();
+    let chat = ctx.try_get()?.chat.get_id();
+    let aliases = get_command_aliases(chat).await?;
+    match check_alias(&aliases, &name, &command, command_kind) {
+        Ok(()) => (),
+        Err(AliasError::InvalidName) => {
+            return ctx.fail_code(INVALID_ARGUMENT, lang_fmt!(ctx, "aliasinvalidname", name))
+        }
+        Err(AliasError::Shadows) => {
+            return ctx.fail_code(INVALID_ARGUMENT, lang_fmt!(ctx, "aliasshadows", name))
+        }
+        Err(AliasError::UnknownCommand) => {
+            return ctx.fail_code(NOT_FOUND, lang_fmt!(ctx, "aliasunknown", command))
+        }
+        Err(AliasError::Forbidden) => {
+            return ctx.fail_code(INVALID_ARGUMENT, lang_fmt!(ctx, "aliasforbidden", command))
+        }
+        Err(AliasError::Cycle) => {
+            return ctx.fail_code(
+                INVALID_ARGUMENT,
+                lang_fmt!(ctx, "aliascycle", name, command),
+            )
+        }
+        Err(AliasError::TooMany) => {
+            return ctx.fail_code(
+                INVALID_ARGUMENT,
+                lang_fmt!(ctx, "aliastoomany", MAX_ALIASES),
+            )
+        }
+    }
+    set_alias(chat, &name, &command).await?;
+    ctx.reply(lang_fmt!(ctx, "aliasset", name, command)).await?;
+    Ok(())
+}
+
+async fn unalias(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanChangeInfo>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let name = args
+        .text
+        .trim()
+        .trim_start_matches(['/', '!'])
+        .to_lowercase();
+    if name.is_empty() {
+        return ctx.usage_err("unaliasusage");
+    }
+    if remove_alias(ctx.try_get()?.chat.get_id(), &name).await? {
+        ctx.reply(lang_fmt!(ctx, "aliasremoved", name)).await?;
+    } else {
+        ctx.fail_code(NOT_FOUND, lang_fmt!(ctx, "aliasnotfound", name))?;
+    }
+    Ok(())
+}
+
+async fn aliases(ctx: &Context, _: InGroup) -> Result<()> {
+    let aliases = get_command_aliases(ctx.try_get()?.chat.get_id()).await?;
+    if aliases.is_empty() {
+        ctx.reply(lang_fmt!(ctx, "noaliases")).await?;
+        return Ok(());
+    }
+    let mut list = aliases
+        .iter()
+        .map(|(name, command)| {
+            let resolved = resolve_alias(&aliases, command);
+            if resolved == command {
+                format!("/{} → /{}", name, command)
+            } else {
+                format!("/{} → /{} (/{})", name, command, resolved)
+            }
+        })
+        .collect::<Vec<String>>();
+    list.sort();
+    ctx.reply(lang_fmt!(ctx, "aliaslist", list.join("\n")))
+        .await?;
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+        match cmd {
+            "alias" => ctx.run(alias).await,
+            "unalias" => ctx.run(unalias).await,
+            "aliases" => ctx.run(aliases).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(ctx: &Context) -> Result<()> {
+    handle_command(ctx).await?;
+    Ok(())
+}
@@ -0,0 +1,167 @@
+use crate::metadata::metadata;
+use crate::persist::core::dialogs::{self, AntiChannel};
+use crate::persist::redis::{default_cache_query, CachedQueryTrait};
+use crate::statics::{CONFIG, DB, TG};
+use crate::tg::admin_helpers::DeleteAfterTime;
+use crate::tg::command::{Cmd, Context, TextArgs};
+use crate::tg::dialog::{dialog_or_default, dialog_scope, get_dialog};
+use crate::tg::middleware::{Flow, Middleware};
+use crate::tg::permissions::IsGroupAdmin;
+use crate::util::error::{Fail, Result};
+use crate::util::string::{Confirm, Speak};
+use botapi::gen_types::{Chat, Message, UpdateExt};
+use chrono::Duration;
+use macros::{lang_fmt, update_handler};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::EntityTrait;
+
+metadata!("Anti Channel",
+    r#"
+    Stop spam sent on behalf of channels. Anyone can speak in a group as a channel they own,
+    which spammers use to dodge bans on their accounts. With this on, messages from channels
+    are deleted, or deleted with the channel banned from the chat. The chat's own linked
+    channel and admins speaking anonymously as the chat are never affected.
+
+    [*Examples]
+    [_delete messages from channels]
+    /antichannel on
+
+    [_delete messages and ban the channels]
+    /antichannel ban
+    "#,
+    { command = "antichannel", help = "Delete messages sent as channels.", usage = "antichannelusage" }
+);
+
+#[inline(always)]
+fn get_linked_key(chat: i64) -> String {
+    format!("linked:{}", chat)
+}
+
+/// Gets the channel linked to a chat, if it has one
+async fn get_linked_chat(chat: i64) -> Result<Option<i64>> {
+    let key = get_linked_key(chat);
+    default_cache_query(
+        |_, _| async move {
+            let info = TG.client.get_chat(chat).await?;
+            Ok(info.get_linked_chat_id())
+        },
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
+    )
+    .query(&key, &())
+    .await
+}
+
+/// Checks if a message was sent as a channel that isn't the chat itself or its linked channel
+async fn is_foreign_channel(message: &Message) -> Result<bool> {
+    let Some(sender) = message.get_sender_chat() else {
+        return Ok(false);
+    };
+    let chat = message.get_chat().get_id();
+    if sender.get_id() == chat || message.get_is_automatic_forward().unwrap_or(false) {
+        return Ok(false);
+    }
+    Ok(get_linked_chat(chat).await? != Some(sender.get_id()))
+}
+
+async fn set_anti_channel(chat: &Chat, action: AntiChannel) -> Result<()> {
+    let mut model = dialogs::Model::from_chat(chat).await?;
+    model.anti_channel = Set(action);
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_column(dialogs::Column::AntiChannel)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat.get_id()).invalidate().await?;
+    Ok(())
+}
+
+fn describe(ctx: &Context, action: AntiChannel) -> String {
+    match action {
+        AntiChannel::Off => lang_fmt!(ctx, "antichanneloff"),
+        AntiChannel::Delete => lang_fmt!(ctx, "antichanneldelete"),
+        AntiChannel::Ban => lang_fmt!(ctx, "antichannelban"),
+    }
+}
+
+async fn antichannel<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat;
+    let action = match args.text.trim() {
+        "" => {
+            let action = dialog_or_default(chat).await?.anti_channel;
+            ctx.reply(describe(ctx, action)).await?;
+            return Ok(());
+        }
+        "off" | "no" => AntiChannel::Off,
+        "on" | "yes" | "delete" => AntiChannel::Delete,
+        "ban" => AntiChannel::Ban,
+        _ => return ctx.usage_err("antichannelusage"),
+    };
+
+    ctx.check_permissions(|p| p.can_delete_messages.and(p.can_restrict_members))
+        .await?;
+    set_anti_channel(chat, action).await?;
+    ctx.confirm(describe(ctx, action)).await?;
+    Ok(())
+}
+
+/// Deletes messages sent as channels in chats with /antichannel on, banning the channel if
+/// the chat asked for it. Runs before locks so a channel message isn't acted on twice
+pub struct Enforce;
+
+#[async_trait::async_trait]
+impl Middleware for Enforce {
+    fn name(&self) -> &'static str {
+        "anti channel"
+    }
+
+    fn priority(&self) -> i32 {
+        -35
+    }
+
+    async fn before(&self, ctx: &Context) -> Result<Flow> {
+        let (UpdateExt::Message(ref message) | UpdateExt::EditedMessage(ref message)) =
+            ctx.update()
+        else {
+            return Ok(Flow::Continue);
+        };
+        let action = get_dialog(message.get_chat())
+            .await?
+            .map(|dialog| dialog.anti_channel)
+            .unwrap_or_default();
+        if action == AntiChannel::Off || !is_foreign_channel(message).await? {
+            return Ok(Flow::Continue);
+        }
+
+        if let (AntiChannel::Ban, Some(sender)) = (action, message.get_sender_chat()) {
+            TG.client
+                .build_ban_chat_sender_chat(message.get_chat().get_id(), sender.get_id())
+                .build()
+                .await?;
+        }
+        message.delete().await?;
+        Ok(Flow::Stop)
+    }
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
+        match cmd {
+            "antichannel" => antichannel(ctx, args).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler(middleware = Enforce)]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use crate::metadata::metadata;
+use crate::persist::admin::antispam::AntiSpamAction;
+use crate::statics::CONFIG;
+use crate::tg::admin_helpers::{UpdateHelpers, UserChanged};
+use crate::tg::command::{Cmd, Context, TextArgs};
+use crate::tg::external_bans::{
+    check_new_member, get_antispam, set_antispam_action, set_antispam_enabled,
+};
+use crate::tg::permissions::IsGroupAdmin;
+use crate::util::error::{Fail, Result};
+use crate::util::string::{Confirm, Speak};
+use macros::{lang_fmt, update_handler};
+
+metadata!("AntiSpam",
+    r#"
+    Check new members against banlists maintained outside the bot, like Combot Anti-Spam \(CAS\).
+    Members found on a list are banned by default, or kicked or muted if chosen with /antispamaction.
+    Hits are reported in the log channel if one is set
+    "#,
+    { command = "antispam", help = "Enable or disable checking new members.", usage = "antispamusage" },
+    { command = "antispamaction", help = "Set what happens to listed members.", usage = "antispamactionusage" }
+);
+
+async fn antispam<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    match args.text.trim() {
+        "" => {
+            let (enabled, action) = get_antispam(chat)
+                .await?
+                .map(|c| (c.enabled, c.action))
+                .unwrap_or_default();
+            let enabled = if enabled { "on" } else { "off" };
+            let config = CONFIG.load();
+            let lists = config.external_bans.banlists.len() + config.external_bans.cas as usize;
+            ctx.reply(lang_fmt!(
+                ctx,
+                "antispamstatus",
+                enabled,
+                action.get_name(),
+                lists
+            ))
+            .await?;
+        }
+        "on" | "yes" => {
+            ctx.check_permissions(|p| p.can_restrict_members).await?;
+            set_antispam_enabled(chat, true).await?;
+            ctx.confirm(lang_fmt!(ctx, "antispamon")).await?;
+        }
+        "off" | "no" => {
+            ctx.check_permissions(|p| p.can_restrict_members).await?;
+            set_antispam_enabled(chat, false).await?;
+            ctx.confirm(lang_fmt!(ctx, "antispamoff")).await?;
+        }
+        _ => return ctx.usage_err("antispamusage"),
+    }
+    Ok(())
+}
+
+async fn antispamaction<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let Some(action) = AntiSpamAction::from_str(args.text.trim()) else {
+        return ctx.fail(lang_fmt!(ctx, "invalidantispamaction"));
+    };
+    ctx.check_permissions(|p| p.can_restrict_members).await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    set_antispam_action(chat, action).await?;
+    ctx.confirm(lang_fmt!(ctx, "setantispamaction", action.get_name()))
+        .await?;
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
+        match cmd {
+            "antispam" => antispam(ctx, args).await,
+            "antispamaction" => antispamaction(ctx, args).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    if let Some(UserChanged::UserJoined(member)) = cmd.user_event() {
+        check_new_member(cmd, member).await?;
+    }
+    handle_command(cmd).await?;
+
+    Ok(())
+}
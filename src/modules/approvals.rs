@@ -3,7 +3,7 @@ use crate::tg::command::{Cmd, Context};
 use crate::tg::permissions::*;
 
 use crate::tg::markdown::EntityMessage;
-use crate::tg::user::{get_user, GetUser, Username};
+use crate::tg::user::{get_user, resolve_user, GetUser, Username};
 use crate::util::error::{BotError, Result, SpeakErr};
 
 use crate::metadata::metadata;
@@ -22,20 +22,10 @@ metadata!("Approvals",
 
 async fn cmd_approve<'a>(ctx: &Context) -> Result<()> {
     ctx.check_permissions(|p| p.can_restrict_members).await?;
-    ctx.action_user(|ctx, user, _| async move {
-        if let Some(user) = user.get_cached_user().await? {
-            approve(ctx.message()?.get_chat(), &user).await?;
-            let name = user.mention().await?;
-            ctx.reply_fmt(entity_fmt!(ctx, "approved", name)).await?;
-        }
-        Ok(())
-    })
-    .await
-    .speak_err_raw(ctx, |v| match v {
-        BotError::UserNotFound => Some(lang_fmt!(ctx, "failuser", "approve")),
-        _ => None,
-    })
-    .await?;
+    let (user, _) = resolve_user(ctx).await?;
+    approve(ctx.message()?.get_chat(), &user).await?;
+    let name = user.mention().await?;
+    ctx.reply_fmt(entity_fmt!(ctx, "approved", name)).await?;
     Ok(())
 }
 
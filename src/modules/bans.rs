@@ -1,18 +1,32 @@
 use crate::{
     metadata::metadata,
-    statics::TG,
+    persist::admin::modlog::ModAction,
+    statics::{CONFIG, TG},
     tg::{
         admin_helpers::*,
-        command::{Cmd, Context},
+        appeals::{begin_appeal, offer_appeal, submit_appeal, AppealTarget},
+        command::{Cmd, Context, TextArgs},
+        confirm::confirmed,
+        deeplink::DeepLink,
+        extract::{ActionArgs, CanRestrictMembers, InGroup, RequirePerm, TargetUser},
+        log_channel::send_log,
+        markdown::{EntityMessage, Escape},
+        mass_actions::{parse_user_list, run_mass_action, MassAction, MASS_ACTION_LIMIT},
+        modlog::record_action,
         permissions::*,
-        user::GetUser,
+        restrict::{describe_permissions, member_permissions, restricted_permissions, Restriction},
+        user::{GetUser, Username},
     },
     util::{
-        error::{BotError, Result, SpeakErr},
-        string::Speak,
+        error::{Fail, Result, SpeakErr},
+        error_codes::{INVALID_ARGUMENT, MISSING_ARGUMENT, PERMISSION_DENIED},
+        string::{get_chat_lang, Lang, SendOptions, Speak},
+        time::ChatTime,
     },
 };
-use botapi::gen_types::ChatPermissionsBuilder;
+use botapi::gen_types::{ChatMember, UpdateExt};
+use chrono::{DateTime, Duration, Utc};
+use futures::FutureExt;
 
 use macros::{entity_fmt, lang_fmt, update_handler};
 
@@ -23,193 +37,544 @@ metadata!("Bans",
     yellow terrorist app? This module is the solution!
     Mute or ban users, punish blue-texters with /kickme, etc
 
-    Ban and mute commands take an optional time parameter \(5m, 1d, etc\) and can either take a user
-    parameter by mention or @handle or by replying to the user's message.
+    Ban and mute commands take an optional time parameter \(5m, 1d, 1d12h, etc\) and can either take a user
+    parameter by mention or @handle or by replying to the user's message. Anything after the
+    time is the reason, which is shown in the chat and posted to the log channel. Banned users
+    who started the bot are sent the reason in a private message with a button to appeal, if
+    the chat has a log channel for appeals to go to. Add \-\-silent or \-s to /ban to delete
+    the command and ban without a reply or appeal.
 
     [*Examples]
     [_bans a user for 5 minutes]
     /ban @username 5m
 
+    [_silently bans a user for a day]
+    /ban @username 1d -s
+
     [_mutes a user forever]
     /mute @username
+
+    [_kicks a user with a reason]
+    /kick @username spamming links
+
+    [_stops a user sending media for a day, they can still send text]
+    /restrict @username media 1d
+
+    /restrict takes away media, polls, links \(link previews\) or all, and can take more than
+    one at a time. Other permissions the user has are left alone, unlike /mute
+
+    Banned users who have started the bot are sent a button to appeal their ban in the bot's dm.
+    Appeals are posted in the log channel if one is set, otherwise in the chat, and any admin who
+    can ban users can approve or deny them
+
+    /massban and /massunban act on a list of user ids, either after the command or in a text file
+    or message they reply to. Only the chat's creator can use them
     "#,
     { command = "kickme", help = "Send a free course on termux hacking"},
-    { command = "mute", help = "Mute a user"},
-    { command = "unmute", help = "Unmute a user"},
-    { command = "ban", help = "Bans a user"},
-    { command = "unban", help = "Unbans a user"},
-    { command = "kick", help = "Kicks a user, they can join again"}
+    { command = "banme", help = "Ban yourself, there's no coming back"},
+    { command = "mute", help = "Mute a user", perms = [CanRestrictMembers] },
+    { command = "unmute", help = "Unmute a user", perms = [CanRestrictMembers] },
+    { command = "ban", help = "Bans a user", perms = [CanRestrictMembers] },
+    { command = "unban", help = "Unbans a user", perms = [CanRestrictMembers] },
+    { command = "kick", help = "Kicks a user, they can join again", perms = [CanRestrictMembers] },
+    { command = "restrict", help = "Take away one kind of message from a user.", usage = "restrictusage", perms = [CanRestrictMembers] },
+    { command = "restrictions", help = "Show what a user is allowed to send" },
+    { command = "massban", help = "Ban a list of user ids, or the ids in a replied file. Creator only" },
+    { command = "massunban", help = "Unban a list of user ids, or the ids in a replied file. Creator only" }
 );
 
-pub async fn unban_cmd(ctx: &Context) -> Result<()> {
-    ctx.check_permissions(|p| p.can_restrict_members).await?;
-    ctx.action_user(|ctx, user, _| async move {
-        ctx.unban(user).await?;
-        let entity = user.mention().await?;
-        ctx.reply_fmt(entity_fmt!(ctx, "unbanned", entity)).await?;
-        Ok(())
-    })
-    .await
-    .speak_err_raw(ctx, |v| match v {
-        BotError::UserNotFound => Some(lang_fmt!(ctx, "failuser", "unban")),
-        _ => None,
-    })
-    .await?;
-    Ok(())
+/// Appends the reason for an action to its confirmation
+fn with_reason(ctx: &Context, mut message: EntityMessage, reason: Option<&str>) -> EntityMessage {
+    if let Some(reason) = reason {
+        message
+            .builder
+            .text(format!("\n{}", lang_fmt!(ctx, "actionreason", reason)));
+    }
+    message
 }
 
-pub async fn ban_cmd(ctx: &Context) -> Result<()> {
-    ctx.check_permissions(|p| p.can_restrict_members).await?;
-    let lang = ctx.try_get()?.lang;
-    ctx.action_user(|ctx, user, args| async move {
-        let duration = ctx.parse_duration(&args)?;
-        ctx.ban(user, duration, true)
-            .await
-            .speak_err_code(ctx.message()?.get_chat(), 400, |_| {
-                lang_fmt!(lang, "failuser", "ban")
-            })
-            .await?;
+/// Name of whoever sent the command, for the log channel
+fn actor_name(ctx: &Context) -> Result<String> {
+    let message = ctx.message()?;
+    let name = match (message.get_sender_chat(), message.get_from()) {
+        (Some(chat), _) => chat.name_humanreadable(),
+        (None, Some(user)) => user.name_humanreadable(),
+        (None, None) => String::new(),
+    };
+    Ok(name)
+}
 
-        Ok(())
-    })
-    .await
-    .speak_err_raw(ctx, |v| match v {
-        BotError::UserNotFound => Some(lang_fmt!(ctx, "failuser", "ban")),
-        _ => None,
-    })
-    .await?;
+/// Posts an action to the chat's log channel. Logging failures don't fail the action
+async fn log_action(ctx: &Context, mut text: String, reason: Option<&str>) -> Result<()> {
+    if let Some(reason) = reason {
+        text.push('\n');
+        text.push_str(&lang_fmt!(ctx, "actionreason", reason.escape(false)));
+    }
+    if let Err(err) = send_log(ctx.try_get()?.chat, text).await {
+        log::debug!("failed to log action: {}", err);
+    }
     Ok(())
 }
 
-pub async fn kick_cmd<'a>(ctx: &Context) -> Result<()> {
-    ctx.check_permissions(|p| p.can_restrict_members).await?;
-    ctx.action_user(|ctx, user, _| async move {
-        if let Some(chat) = ctx.chat() {
-            kick(user, chat.get_id()).await?;
-            let entity = user.mention().await?;
-            ctx.message()?
-                .reply_fmt(entity_fmt!(ctx, "kicked", entity))
-                .await?;
-        }
-        Ok(())
-    })
-    .await
-    .speak_err_raw(ctx, |v| match v {
-        BotError::UserNotFound => Some(lang_fmt!(ctx, "failuser", "kick")),
-        _ => None,
-    })
-    .await?;
+/// Records an action in the moderation log. Like the log channel, failures don't fail the
+/// action
+async fn record(
+    ctx: &Context,
+    user: i64,
+    action: ModAction,
+    reason: Option<&str>,
+    duration: Option<Duration>,
+) -> Result<()> {
+    if let Err(err) = record_action(ctx.message()?, user, action, reason, duration).await {
+        log::warn!("failed to record action: {}", err);
+    }
     Ok(())
 }
 
-pub async fn mute_cmd<'a>(ctx: &Context) -> Result<()> {
-    ctx.check_permissions(|p| p.can_restrict_members).await?;
-    let permissions = ChatPermissionsBuilder::new()
-        .set_can_send_messages(false)
-        .set_can_send_audios(false)
-        .set_can_send_documents(false)
-        .set_can_send_photos(false)
-        .set_can_send_videos(false)
-        .set_can_send_video_notes(false)
-        .set_can_send_polls(false)
-        .set_can_send_voice_notes(false)
-        .set_can_send_other_messages(false)
-        .build();
-    let lang = ctx.try_get()?.lang;
-    let user = ctx
-        .change_permissions_message(permissions)
+/// Tells a user in dm what happened to them. Users who never started the bot can't be
+/// reached, so failures are ignored
+async fn notify_user(user: i64, mut text: String, lang: &Lang, reason: Option<&str>) {
+    if let Some(reason) = reason {
+        text.push('\n');
+        text.push_str(&lang_fmt!(lang, "actionreason", reason));
+    }
+    if let Err(err) = user
+        .speak_entities(&text, &[], SendOptions::default())
+        .await
+    {
+        log::debug!("failed to notify {}: {}", user, err);
+    }
+}
+
+pub async fn unban_cmd(
+    ctx: &Context,
+    _: RequirePerm<CanRestrictMembers>,
+    TargetUser { id: user, .. }: TargetUser<'_>,
+) -> Result<()> {
+    let lang = ctx.lang();
+    ctx.unban(user)
         .await
         .speak_err_code(ctx.message()?.get_chat(), 400, |_| {
-            lang_fmt!(lang, "failmute")
+            lang_fmt!(lang, "failuser", "unban")
         })
         .await?;
-    if let Some(user) = user {
-        let mention = user.mention().await?;
+    ctx.reply_fmt(entity_fmt!(ctx, "unbanned", user.mention().await?))
+        .await?;
 
-        ctx.message()?
-            .reply_fmt(entity_fmt!(ctx, "muteuser", mention))
-            .await?;
-    } else {
-        ctx.reply(lang_fmt!(ctx, "usernotfound")).await?;
+    record(ctx, user, ModAction::Unban, None, None).await?;
+
+    let name = user.cached_name().await?;
+    let text = lang_fmt!(
+        ctx,
+        "logunbanned",
+        actor_name(ctx)?.escape(false),
+        name.escape(false)
+    );
+    log_action(ctx, text, None).await
+}
+
+pub async fn ban_cmd(
+    ctx: &Context,
+    _: RequirePerm<CanRestrictMembers>,
+    TargetUser { id: user, args }: TargetUser<'_>,
+    ActionArgs { duration, reason }: ActionArgs,
+) -> Result<()> {
+    let message = ctx.message()?;
+    let lang = ctx.lang();
+    if user.is_admin(message.get_chat()).await? {
+        return ctx.fail(lang_fmt!(ctx, "banadmin"));
+    }
+    if !confirmed(ctx, |ctx| async move { ctx.run(ban_cmd).await }.boxed()).await? {
+        return Ok(());
+    }
+    ctx.ban(user, duration, true)
+        .await
+        .speak_err_code(message.get_chat(), 400, |_| {
+            lang_fmt!(lang, "failuser", "ban")
+        })
+        .await?;
+
+    let chat = message.get_chat().get_id();
+    let until = match duration.and_then(|d| Utc::now().checked_add_signed(d)) {
+        Some(until) => Some(ChatTime::get(chat).await?.format(&until)),
+        None => None,
+    };
+    let name = user.cached_name().await?;
+    let actor = actor_name(ctx)?;
+    let text = match until {
+        Some(ref until) => lang_fmt!(
+            ctx,
+            "logtempbanned",
+            actor.escape(false),
+            name.escape(false),
+            until.escape(false)
+        ),
+        None => lang_fmt!(ctx, "logbanned", actor.escape(false), name.escape(false)),
+    };
+    log_action(ctx, text, reason.as_deref()).await?;
+    record(ctx, user, ModAction::Ban, reason.as_deref(), duration).await?;
+
+    let matches = args.as_ref().map(|a| a.matches()).unwrap_or_default();
+    if matches.flag("silent", 's') {
+        message.delete().await?;
+        return Ok(());
     }
 
-    //  message.reply(lang_fmt!(lang, "muteuser")).await?;
+    if let Err(err) = offer_appeal(user, AppealTarget::Chat(chat), reason.as_deref()).await {
+        log::debug!("failed to offer appeal to {}: {}", user, err);
+    }
+
+    let mention = user.mention().await?;
+    let reply = match until {
+        Some(until) => entity_fmt!(ctx, "tempbanned", mention, until),
+        None => entity_fmt!(ctx, "banned", mention),
+    };
+    ctx.reply_fmt(with_reason(ctx, reply, reason.as_deref()))
+        .await?;
 
     Ok(())
 }
 
-pub async fn unmute_cmd<'a>(ctx: &Context) -> Result<()> {
-    let message = ctx.message()?;
-    ctx.check_permissions(|p| p.can_restrict_members).await?;
-
-    let permissions = ChatPermissionsBuilder::new()
-        .set_can_send_messages(true)
-        .set_can_send_audios(true)
-        .set_can_send_documents(true)
-        .set_can_send_photos(true)
-        .set_can_send_videos(true)
-        .set_can_send_video_notes(true)
-        .set_can_send_polls(true)
-        .set_can_send_voice_notes(true)
-        .set_can_send_other_messages(true)
-        .build();
-
-    let lang = ctx.try_get()?.lang;
-    let user = ctx
-        .change_permissions_message(permissions)
+pub async fn kick_cmd(
+    ctx: &Context,
+    _: RequirePerm<CanRestrictMembers>,
+    TargetUser { id: user, .. }: TargetUser<'_>,
+    ActionArgs { reason, .. }: ActionArgs,
+) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    if user.is_admin(chat).await? {
+        return ctx.fail(lang_fmt!(ctx, "kickadmin"));
+    }
+    let lang = ctx.lang();
+    kick(user, chat.get_id())
         .await
-        .speak_err_code(message.get_chat(), 400, |_| lang_fmt!(lang, "failmute"))
+        .speak_err_code(chat, 400, |_| lang_fmt!(lang, "failuser", "kick"))
         .await?;
-    if let Some(user) = user {
-        let mention = user.mention().await?;
-        message
-            .reply_fmt(entity_fmt!(ctx, "unmuteuser", mention))
-            .await?;
-    } else {
-        ctx.reply(lang_fmt!(ctx, "usernotfound")).await?;
+
+    let entity = user.mention().await?;
+    let reply = entity_fmt!(ctx, "kicked", entity);
+    ctx.reply_fmt(with_reason(ctx, reply, reason.as_deref()))
+        .await?;
+
+    let user_lang = get_chat_lang(user).await?;
+    let dm = lang_fmt!(user_lang, "kickeddm", chat.name_humanreadable());
+    notify_user(user, dm, &user_lang, reason.as_deref()).await;
+
+    record(ctx, user, ModAction::Kick, reason.as_deref(), None).await?;
+
+    let name = user.cached_name().await?;
+    let text = lang_fmt!(
+        ctx,
+        "logkicked",
+        actor_name(ctx)?.escape(false),
+        name.escape(false)
+    );
+    log_action(ctx, text, reason.as_deref()).await
+}
+
+pub async fn mute_cmd(
+    ctx: &Context,
+    _: RequirePerm<CanRestrictMembers>,
+    TargetUser { id: user, .. }: TargetUser<'_>,
+    ActionArgs { duration, reason }: ActionArgs,
+) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    if user.is_admin(chat).await? {
+        return ctx.fail(lang_fmt!(ctx, "muteadmin"));
+    }
+    let lang = ctx.lang();
+    ctx.mute(user, chat, duration)
+        .await
+        .speak_err_code(chat, 400, |_| lang_fmt!(lang, "failmute"))
+        .await?;
+
+    let until = match duration.and_then(|d| Utc::now().checked_add_signed(d)) {
+        Some(until) => Some(ChatTime::get(chat.get_id()).await?.format(&until)),
+        None => None,
+    };
+    let name = user.cached_name().await?;
+    let actor = actor_name(ctx)?;
+    let text = match until {
+        Some(ref until) => lang_fmt!(
+            ctx,
+            "logtempmuted",
+            actor.escape(false),
+            name.escape(false),
+            until.escape(false)
+        ),
+        None => lang_fmt!(ctx, "logmuted", actor.escape(false), name.escape(false)),
+    };
+    log_action(ctx, text, reason.as_deref()).await?;
+    record(ctx, user, ModAction::Mute, reason.as_deref(), duration).await?;
+
+    let user_lang = get_chat_lang(user).await?;
+    let dm = match until {
+        Some(ref until) => lang_fmt!(user_lang, "tempmuteddm", chat.name_humanreadable(), until),
+        None => lang_fmt!(user_lang, "muteddm", chat.name_humanreadable()),
+    };
+    notify_user(user, dm, &user_lang, reason.as_deref()).await;
+
+    let mention = user.mention().await?;
+    let reply = match until {
+        Some(until) => entity_fmt!(ctx, "tempmuted", mention, until),
+        None => entity_fmt!(ctx, "muteuser", mention),
+    };
+    ctx.reply_fmt(with_reason(ctx, reply, reason.as_deref()))
+        .await?;
+
+    Ok(())
+}
+
+pub async fn unmute_cmd(
+    ctx: &Context,
+    _: RequirePerm<CanRestrictMembers>,
+    TargetUser { id: user, .. }: TargetUser<'_>,
+) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let lang = ctx.lang();
+    ctx.unmute(user, chat)
+        .await
+        .speak_err_code(chat, 400, |_| lang_fmt!(lang, "failmute"))
+        .await?;
+
+    let mention = user.mention().await?;
+    ctx.reply_fmt(entity_fmt!(ctx, "unmuteuser", mention))
+        .await?;
+
+    record(ctx, user, ModAction::Unmute, None, None).await?;
+
+    let name = user.cached_name().await?;
+    let text = lang_fmt!(
+        ctx,
+        "logunmuted",
+        actor_name(ctx)?.escape(false),
+        name.escape(false)
+    );
+    log_action(ctx, text, None).await
+}
+
+async fn restrict_cmd(
+    ctx: &Context,
+    _: RequirePerm<CanRestrictMembers>,
+    TargetUser { id: user, args }: TargetUser<'_>,
+) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let matches = args.map(|args| args.matches()).unwrap_or_default();
+    let mut restrictions = Vec::new();
+    let mut duration = None;
+    for arg in matches.positional() {
+        let arg = arg.get_text();
+        if let Some(restriction) = Restriction::from_arg(arg) {
+            restrictions.push(restriction);
+        } else if duration.is_none() {
+            duration = Some(ctx.parse_duration_arg(arg)?);
+        } else {
+            return ctx.fail(lang_fmt!(ctx, "restrictinvalid", arg));
+        }
     }
+    if restrictions.is_empty() {
+        return ctx.usage_err("restrictusage");
+    }
+
+    let permissions = restricted_permissions(chat, user, &restrictions).await?;
+    let lang = ctx.lang();
+    ctx.change_permissions_chat(user, chat, &permissions, duration)
+        .await
+        .speak_err_code(chat, 400, |_| lang_fmt!(lang, "failmute"))
+        .await?;
+
+    let mut message = entity_fmt!(ctx, "restricted", user.mention().await?);
+    message
+        .builder
+        .text(format!("\n{}", describe_permissions(lang, &permissions)));
+    ctx.reply_fmt(message).await?;
+
+    record(ctx, user, ModAction::Restrict, None, duration).await?;
+
+    let name = user.cached_name().await?;
+    let text = lang_fmt!(
+        ctx,
+        "logrestricted",
+        actor_name(ctx)?.escape(false),
+        name.escape(false)
+    );
+    log_action(ctx, text, None).await
+}
 
+async fn restrictions_cmd(
+    ctx: &Context,
+    _: InGroup,
+    TargetUser { id: user, .. }: TargetUser<'_>,
+) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let member = TG
+        .client
+        .build_get_chat_member(chat.get_id(), user)
+        .build()
+        .await?;
+    let mut message = entity_fmt!(ctx, "restrictions", user.mention().await?);
+    let permissions = member_permissions(&member).build();
+    message.builder.text(format!(
+        "\n{}",
+        describe_permissions(ctx.lang(), &permissions)
+    ));
+    if let ChatMember::ChatMemberRestricted(m) = member {
+        let until = Some(m.get_until_date()).filter(|until| *until > 0);
+        if let Some(until) = until.and_then(|until| DateTime::from_timestamp(until, 0)) {
+            let until = ChatTime::get(chat.get_id()).await?.format(&until);
+            message
+                .builder
+                .text(format!("\n{}", lang_fmt!(ctx, "restrictionsuntil", until)));
+        }
+    }
+    ctx.reply_fmt(message).await?;
     Ok(())
 }
 
-async fn kickme(ctx: &Context) -> Result<()> {
+/// Removes the user sending the command from the chat, banning them if `ban` is set
+async fn remove_self(ctx: &Context, ban: bool) -> Result<()> {
     ctx.is_group_or_die().await?;
     let message = ctx.message()?;
-    let v = ctx.try_get()?;
-    self_admin_or_die(message.get_chat()).await?;
-    if message.get_from().is_admin(message.get_chat()).await? {
-        message.reply(lang_fmt!(v.lang, "kickadmin")).await?;
-    } else if let Some(from) = message.get_from() {
-        TG.client()
-            .build_ban_chat_member(message.get_chat().get_id(), from.get_id())
-            .build()
-            .await?;
+    let chat = message.get_chat();
+    self_admin_or_die(chat).await?;
+    let Some(from) = message.get_from() else {
+        return Ok(());
+    };
+    if message.get_from().is_admin(chat).await? {
+        if ban {
+            return ctx.fail(lang_fmt!(ctx, "banmeadmin"));
+        }
+        return ctx.fail(lang_fmt!(ctx, "kickadmin"));
+    }
+    TG.client()
+        .build_ban_chat_member(chat.get_id(), from.get_id())
+        .build()
+        .await?;
+    if ban {
+        message.reply(lang_fmt!(ctx, "banme")).await?;
+        let text = lang_fmt!(ctx, "logbanme", from.name_humanreadable().escape(false));
+        log_action(ctx, text, None).await?;
+    } else {
         TG.client()
-            .build_unban_chat_member(message.get_chat().get_id(), from.get_id())
+            .build_unban_chat_member(chat.get_id(), from.get_id())
             .build()
             .await?;
-        message.reply(lang_fmt!(v.lang, "kickme")).await?;
+        message.reply(lang_fmt!(ctx, "kickme")).await?;
+    }
+    Ok(())
+}
+
+/// Checks that the sender of a bulk action is the chat's creator or a sudo user
+async fn creator_or_sudo(ctx: &Context) -> Result<()> {
+    let message = ctx.message()?;
+    let allowed = match message.get_from() {
+        Some(user) if CONFIG.load().admin.sudo_users.contains(&user.get_id()) => true,
+        Some(user) => matches!(
+            message.get_chat().is_user_admin(user.get_id()).await?,
+            Some(ChatMember::ChatMemberOwner(_))
+        ),
+        None => false,
+    };
+    if !allowed {
+        return ctx.fail_code(PERMISSION_DENIED, lang_fmt!(ctx, "masscreatoronly"));
+    }
+    Ok(())
+}
+
+async fn mass_action<'a>(ctx: &Context, args: &TextArgs<'a>, action: MassAction) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    creator_or_sudo(ctx).await?;
+    let message = ctx.message()?;
+    let text = match message.get_reply_to_message() {
+        Some(reply) => match reply.get_document() {
+            Some(document) => document.get_text().await?,
+            None => reply.get_text().unwrap_or_default().to_owned(),
+        },
+        None => args.text.to_owned(),
+    };
+    let list = parse_user_list(&text);
+    let total = list.users.len() + list.invalid.len();
+    if list.users.is_empty() {
+        return ctx.fail_code(MISSING_ARGUMENT, lang_fmt!(ctx, "massempty"));
+    }
+    if total > MASS_ACTION_LIMIT {
+        return ctx.fail_code(
+            INVALID_ARGUMENT,
+            lang_fmt!(ctx, "masstoomany", MASS_ACTION_LIMIT),
+        );
     }
 
+    let Some(status) = ctx.reply(lang_fmt!(ctx, "massstart", total)).await? else {
+        return Ok(());
+    };
+    let report = run_mass_action(message.get_chat(), list, action, &status, ctx.lang()).await?;
+    let actor = actor_name(ctx)?;
+    let text = match action {
+        MassAction::Ban => lang_fmt!(ctx, "masslogbanned", actor.escape(false), report.done),
+        MassAction::Unban => lang_fmt!(ctx, "masslogunbanned", actor.escape(false), report.done),
+    };
+    log_action(ctx, text, None).await?;
     Ok(())
 }
 
 async fn handle_command<'a>(ctx: &Context) -> Result<()> {
-    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
         match cmd {
-            "kickme" => kickme(ctx).await,
-            "mute" => mute_cmd(ctx).await,
-            "unmute" => unmute_cmd(ctx).await,
-            "ban" => ban_cmd(ctx).await,
-            "unban" => unban_cmd(ctx).await,
-            "kick" => kick_cmd(ctx).await,
+            "kickme" => remove_self(ctx, false).await,
+            "banme" => remove_self(ctx, true).await,
+            "mute" => ctx.run(mute_cmd).await,
+            "unmute" => ctx.run(unmute_cmd).await,
+            "ban" => ctx.run(ban_cmd).await,
+            "unban" => ctx.run(unban_cmd).await,
+            "kick" => ctx.run(kick_cmd).await,
+            "restrict" => ctx.run(restrict_cmd).await,
+            "restrictions" => ctx.run(restrictions_cmd).await,
+            "massban" => mass_action(ctx, args, MassAction::Ban).await,
+            "massunban" => mass_action(ctx, args, MassAction::Unban).await,
+            "start" => appeal_link(ctx).await,
             _ => Ok(()),
         }?;
     }
     Ok(())
 }
 
+/// Starts an appeal from an appeal deep link, so users banned before they ever started the
+/// bot can still appeal
+async fn appeal_link(ctx: &Context) -> Result<()> {
+    let Some(&DeepLink::Appeal(chat)) = ctx.deep_link().await? else {
+        return Ok(());
+    };
+    let Some(user) = ctx.message()?.get_from() else {
+        return Ok(());
+    };
+    let member = TG
+        .client
+        .build_get_chat_member(chat, user.get_id())
+        .build()
+        .await?;
+    if !matches!(member, ChatMember::ChatMemberBanned(_)) {
+        return ctx.fail(lang_fmt!(ctx, "appealnotbanned"));
+    }
+    if !begin_appeal(user.get_id(), AppealTarget::Chat(chat)).await? {
+        return ctx.fail(lang_fmt!(ctx, "appealpending"));
+    }
+    Ok(())
+}
+
+async fn handle_appeal(ctx: &Context) -> Result<()> {
+    if ctx.cmd().is_some() {
+        return Ok(());
+    }
+    if let UpdateExt::Message(message) = ctx.update() {
+        if let Some(user) = message.get_from().filter(|_| is_dm(message.get_chat())) {
+            submit_appeal(message, user).await?;
+        }
+    }
+    Ok(())
+}
+
 #[update_handler]
 pub async fn handle_update<'a>(ctx: &Context) -> Result<()> {
+    handle_appeal(ctx).await?;
     handle_command(ctx).await
 }
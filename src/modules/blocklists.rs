@@ -1,8 +1,10 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::metadata::ModuleHelpers;
 use crate::persist::admin::actions::ActionType;
 use crate::persist::admin::actions::FilterType;
+use crate::persist::core::dialogs;
 use crate::persist::redis::default_cache_query;
 use crate::persist::redis::CachedQueryTrait;
 use crate::persist::redis::RedisCache;
@@ -11,7 +13,6 @@ use crate::persist::redis::ToRedisStr;
 use crate::statics::CONFIG;
 use crate::statics::DB;
 use crate::statics::REDIS;
-use crate::tg::admin_helpers::parse_duration_str;
 use crate::tg::admin_helpers::ActionMessage;
 use crate::tg::admin_helpers::DeleteAfterTime;
 use crate::tg::admin_helpers::UpdateHelpers;
@@ -19,14 +20,24 @@ use crate::tg::command::Cmd;
 use crate::tg::command::Context;
 use crate::tg::command::PopSlice;
 use crate::tg::command::TextArgs;
+use crate::tg::log_channel::send_log;
+use crate::tg::markdown::Escape;
 use crate::tg::markdown::Header;
 use crate::tg::markdown::MarkupBuilder;
 use crate::tg::markdown::MarkupType;
+use crate::tg::networks::{network_scope, shared_chats, Share};
+use crate::tg::nsfw::{
+    self, get_nsfw_filter, nsfw_enabled, score_image, set_nsfw_action, set_nsfw_filter_enabled,
+};
+use crate::tg::ocr::{extract_text, ocr_enabled};
 use crate::tg::permissions::*;
+use crate::tg::spam::{
+    get_spam_filter, set_spam_action, set_spam_filter_enabled, DEFAULT_THRESHOLD,
+};
 
-use crate::tg::dialog::dialog_or_default;
+use crate::tg::dialog::{dialog_or_default, dialog_scope};
 
-use crate::tg::user::GetUser;
+use crate::tg::user::{GetUser, Username};
 use crate::util::error::BotError;
 use crate::util::error::Fail;
 use crate::util::error::Result;
@@ -34,16 +45,19 @@ use crate::util::error::Result;
 use crate::metadata::metadata;
 
 use crate::util::error::SpeakErr;
-use crate::util::glob::WildMatch;
+use crate::util::error_codes::{INVALID_ARGUMENT, MISSING_ARGUMENT};
 
 use crate::util::scripting::ModAction;
-use crate::util::string::Speak;
+use crate::util::string::{Confirm, Speak};
+use crate::util::triggers::{
+    next_generation, MatcherCache, TriggerMatcher, TriggerPattern, GENERATION_FIELD,
+};
+use botapi::gen_types::Chat;
 use botapi::gen_types::Message;
 use botapi::gen_types::User;
 use chrono::Duration;
 use entities::{blocklists, triggers};
 use futures::FutureExt;
-use humantime::format_duration;
 use itertools::Itertools;
 use sea_orm::ModelTrait;
 
@@ -125,7 +139,12 @@ metadata!("Blocklists",
     { command = "rmblocklist", help = "Stop a blocklist by trigger" },
     { command = "rmallblocklists", help = "Stop all blocklists" },
     { command = "scriptblocklist", help = "Adds a rhai script as a blocklist with a provided name" },
-    { command = "rmscriptblocklist", help = "Moves a script blocklist by name"}
+    { command = "rmscriptblocklist", help = "Moves a script blocklist by name"},
+    { command = "spamfilter", help = "Apply an action to messages scored as spam.", usage = "spamfilterusage" },
+    { command = "spamaction", help = "\\<action\\> {threshold}: Set the spam action and the score in percent that counts as spam" },
+    { command = "lockocrprofanity", help = "Also check text in images and stickers against the blocklists.", usage = "lockocrprofanityusage" },
+    { command = "nsfwfilter", help = "Apply an action to images and stickers scored as nsfw.", usage = "nsfwfilterusage" },
+    { command = "nsfwaction", help = "\\<action\\> {threshold}: Set the nsfw action and the score in percent that counts as nsfw" }
 );
 
 struct Migration;
@@ -589,14 +608,27 @@ fn get_blocklist_key(message: &Message, id: i64) -> String {
     format!("blockl:{}:{}", message.get_chat().get_id(), id)
 }
 
-fn get_blocklist_hash_key(chat: i64) -> String {
-    format!("bcache:{}", chat)
+/// The cached blocklists of a chat include the ones shared through its network, so they
+/// live in the network's cache scope
+async fn get_blocklist_hash_key(chat: i64) -> Result<String> {
+    network_scope(chat).key(format!("bcache:{}", chat)).await
+}
+
+/// Drops the cached blocklists of chats sharing blocklists with this one, so they pick up a
+/// change to this chat's blocklists
+async fn invalidate_shared_blocklists(chat: i64) -> Result<()> {
+    for other in shared_chats(chat, Share::Blocklists).await? {
+        let key = get_blocklist_hash_key(other).await?;
+        REDIS.sq(|q| q.del(&key)).await?;
+    }
+    Ok(())
 }
 
 async fn delete_script(ctx: &Context, script: String) -> Result<()> {
     ctx.check_permissions(|p| p.can_restrict_members.and(p.can_change_info))
         .await?;
-    let hash_key = get_blocklist_hash_key(ctx.message()?.chat.id);
+    let chat = ctx.message()?.chat.id;
+    let hash_key = get_blocklist_hash_key(chat).await?;
 
     DB.transaction::<_, (), BotError>(|tx| {
         async move {
@@ -610,7 +642,15 @@ async fn delete_script(ctx: &Context, script: String) -> Result<()> {
                 .into_iter()
                 .map(|(b, t)| (b, t.into_iter().map(|v| v.trigger).collect_vec()))
             {
-                REDIS.sq(|q| q.hdel(&hash_key, trigger)).await?;
+                REDIS
+                    .pipe(|p| {
+                        p.hdel(&hash_key, trigger).hset(
+                            &hash_key,
+                            GENERATION_FIELD,
+                            next_generation(),
+                        )
+                    })
+                    .await?;
                 blocklist.delete(tx).await?;
             }
 
@@ -619,6 +659,7 @@ async fn delete_script(ctx: &Context, script: String) -> Result<()> {
         .boxed()
     })
     .await?;
+    invalidate_shared_blocklists(chat).await?;
 
     ctx.reply("Blocklist stopped").await?;
 
@@ -634,7 +675,7 @@ async fn delete_trigger(ctx: &Context, trigger: String) -> Result<()> {
         async move {
             let message = c.message()?;
             let trigger = &trigger.to_lowercase();
-            let hash_key = get_blocklist_hash_key(message.get_chat().get_id());
+            let hash_key = get_blocklist_hash_key(message.get_chat().get_id()).await?;
             let filters = blocklists::Entity::find()
                 .find_with_related(triggers::Entity)
                 .filter(
@@ -670,6 +711,8 @@ async fn delete_trigger(ctx: &Context, trigger: String) -> Result<()> {
                     if let Some(id) = id {
                         let key = get_blocklist_key(message, id);
                         q.del(&key).await?;
+                        q.hset(&hash_key, GENERATION_FIELD, next_generation())
+                            .await?;
                         Ok(Some(id))
                     } else {
                         Ok(None)
@@ -682,6 +725,7 @@ async fn delete_trigger(ctx: &Context, trigger: String) -> Result<()> {
         .boxed()
     })
     .await?;
+    invalidate_shared_blocklists(ctx.message()?.get_chat().get_id()).await?;
     ctx.reply("Blocklist stopped").await?;
 
     Ok(())
@@ -696,14 +740,54 @@ async fn get_blocklist(message: &Message, id: i64) -> Result<Option<blocklists::
                 .await?;
             Ok(res)
         },
-        Duration::try_seconds(CONFIG.timing.cache_timeout).unwrap(),
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
     )
     .query(&get_blocklist_key(message, id), &())
     .await
 }
 
+/// A chat's blocklists compiled for matching
+struct Blocklists {
+    matcher: TriggerMatcher<i64>,
+
+    /// scripts run against every message, with the blocklist each belongs to
+    scripts: Vec<(String, i64)>,
+}
+
 lazy_static! {
     static ref WHITESPACE: Regex = Regex::new(r#"\s+|\S*"#).unwrap();
+    static ref MATCHERS: MatcherCache<Blocklists> = MatcherCache::default();
+}
+
+/// Gets the chat's blocklists compiled for matching, loading them into the cache if needed
+async fn get_matcher(message: &Message) -> Result<Option<Arc<Blocklists>>> {
+    update_cache_from_db(message).await?;
+    let hash_key = get_blocklist_hash_key(message.get_chat().get_id()).await?;
+    MATCHERS
+        .get(&hash_key, || async {
+            let triggers: HashMap<String, RedisStr> = REDIS.sq(|q| q.hgetall(&hash_key)).await?;
+            let mut patterns = Vec::with_capacity(triggers.len());
+            let mut scripts = Vec::new();
+            for (trigger, rs) in triggers {
+                if trigger == GENERATION_FIELD {
+                    continue;
+                }
+                let Ok((item, filtertype)) = rs.get::<(i64, FilterConfig)>() else {
+                    log::warn!("invalid cached blocklist trigger {}", trigger);
+                    continue;
+                };
+                match filtertype {
+                    FilterConfig::Text => patterns.push((TriggerPattern::Literal(trigger), item)),
+                    FilterConfig::Glob => patterns.push((TriggerPattern::Glob(trigger), item)),
+                    FilterConfig::Script(_) => scripts.push((trigger, item)),
+                }
+            }
+            Ok(Blocklists {
+                matcher: TriggerMatcher::new(patterns)?,
+                scripts,
+            })
+        })
+        .await
 }
 
 async fn search_cache(
@@ -711,135 +795,112 @@ async fn search_cache(
     message: &Message,
     text: &str,
 ) -> Result<Option<blocklists::Model>> {
-    update_cache_from_db(message).await?;
-    let hash_key = get_blocklist_hash_key(message.get_chat().get_id());
-    REDIS
-        .query(|mut q| async move {
-            let mut iter: redis::AsyncIter<(String, RedisStr)> = q.hscan(&hash_key).await?;
-            while let Some((key, rs)) = iter.next_item().await {
-                if key.is_empty() {
-                    continue;
-                }
-
-                let (item, filtertype): (i64, FilterConfig) = rs.get()?;
+    let Some(blocklists) = get_matcher(message).await? else {
+        return Ok(None);
+    };
+    if let Some((_, item, _)) = blocklists.matcher.matches(text).first() {
+        return get_blocklist(message, **item).await;
+    }
 
-                match filtertype {
-                    FilterConfig::Glob => {
-                        let glob = WildMatch::new(&key);
-                        if glob.matches(text) {
-                            return get_blocklist(message, item).await;
+    for (key, item) in blocklists.scripts.iter() {
+        let res: Result<Dynamic> =
+            ManagedRhai::new_mapper(key.clone(), &RHAI_ENGINE, (message.clone(),))
+                .post()
+                .await;
+
+        let res = match res {
+            Ok(action) => {
+                if action.is_bool() {
+                    if let Some(res) = action.try_cast::<bool>() {
+                        log::info!("handling bool script {}", res);
+                        if res {
+                            get_blocklist(message, *item).await
+                        } else {
+                            Ok(None)
                         }
+                    } else {
+                        Ok(None)
                     }
-                    FilterConfig::Text => {
-                        if text.contains(&key) {
-                            return get_blocklist(message, item).await;
+                } else {
+                    let model = get_blocklist(message, *item).await?;
+                    let tn = action.type_name();
+                    let res = match (action.try_cast::<ModAction>(), model) {
+                        (Some(ModAction::Reply(reply)), _) => {
+                            ctx.reply(reply).await?;
+                            None
                         }
-                    }
-                    FilterConfig::Script(_) => {
-                        let res: Result<Dynamic> = ManagedRhai::new_mapper(
-                            key,
-                            &RHAI_ENGINE,
-                            (message.clone(),),
-                        )
-                        .post()
-                        .await;
-
-                        let res = match res {
-                            Ok(action) => {
-                                if action.is_bool() {
-                                if let Some(res) = action.try_cast::<bool>() {
-                                    log::info!("handling bool script {}", res);
-                                    if res {
-                                        get_blocklist(message, item).await
-                                    } else {
-                                        Ok(None)
-                                    }
-                                } else {
-                                    Ok(None)
-                                }
-
-                                } else {
-                                let model = get_blocklist(message, item).await?;
-                                let tn = action.type_name();
-                                let res = match (action.try_cast::<ModAction>(), model) {
-                                    (Some(ModAction::Reply(reply)), _) => {
-                                        ctx.reply(reply).await?;
-                                        None
-                                    }
-                                    (Some(ModAction::Ignore), _) => None,
-                                    (Some(modaction), Some(mut model)) => {
-                                        if let Some(action) = modaction.get_action_type() {
-                                            model.action = action;
-                                        }
-                                        model.reason = modaction.to_reason();
-                                        Some(model)
-                                    }
-                                    (None, Some(mut model)) => {
-                                        model.action = ActionType::Delete;
-                                        model.reason = None;
-                                        ctx.reply(format!("Blocklist mapper function returned invalid type. Was {}, expected bool or ModAction", tn)).await?;
-                                        Some(model)
-                                    }
-                                    (_, None) => None,
-                                };
-
-                                Ok(res)
-
-                                }
+                        (Some(ModAction::Ignore), _) => None,
+                        (Some(modaction), Some(mut model)) => {
+                            if let Some(action) = modaction.get_action_type() {
+                                model.action = action;
                             }
-                            Err(err) => {
-                                let mut bl = get_blocklist(message, item).await?;
-                                if let Some(bl) = bl.as_mut() {
-                                    bl.action = ActionType::Delete;
-                                    bl.reason = None;
-                                    ctx.reply(format!(
-                                        "Failed to block message, rhai error: {}",
-                                        err
-                                    ))
-                                    .await?;
-                                }
-                                Ok(bl)
-                            }
-
-                        };
-                    if let Ok(res) = res {
-                        if res.is_some() {
-                            return Ok(res);
+                            model.reason = modaction.to_reason();
+                            Some(model)
                         }
-                    }
+                        (None, Some(mut model)) => {
+                            model.action = ActionType::Delete;
+                            model.reason = None;
+                            ctx.reply(format!("Blocklist mapper function returned invalid type. Was {}, expected bool or ModAction", tn)).await?;
+                            Some(model)
+                        }
+                        (_, None) => None,
+                    };
 
-                    }
+                    Ok(res)
                 }
             }
-            Ok(None)
-        })
-        .await
+            Err(err) => {
+                let mut bl = get_blocklist(message, *item).await?;
+                if let Some(bl) = bl.as_mut() {
+                    bl.action = ActionType::Delete;
+                    bl.reason = None;
+                    ctx.reply(format!("Failed to block message, rhai error: {}", err))
+                        .await?;
+                }
+                Ok(bl)
+            }
+        };
+        if let Ok(res) = res {
+            if res.is_some() {
+                return Ok(res);
+            }
+        }
+    }
+    Ok(None)
 }
 
 async fn update_cache_from_db(message: &Message) -> Result<()> {
-    let hash_key = get_blocklist_hash_key(message.get_chat().get_id());
+    let chat = message.get_chat().get_id();
+    let hash_key = get_blocklist_hash_key(chat).await?;
     let k: usize = REDIS.sq(|q| q.exists(&hash_key)).await?;
     if k == 0 {
-        let res = blocklists::Entity::find()
-            .filter(blocklists::Column::Chat.eq(message.get_chat().get_id()))
+        let mut chats = shared_chats(chat, Share::Blocklists).await?;
+        chats.push(chat);
+        let mut res = blocklists::Entity::find()
+            .filter(blocklists::Column::Chat.is_in(chats))
             .find_with_related(triggers::Entity)
             .all(*DB)
             .await?;
+        // the chat's own blocklists go last so they win over the network's for the same trigger
+        res.sort_by_key(|(filter, _)| filter.chat == chat);
         REDIS
             .try_pipe(|p| {
-                p.hset(&hash_key, "", 0);
+                p.hset(&hash_key, GENERATION_FIELD, next_generation());
                 for (filter, triggers) in res.into_iter() {
                     let key = get_blocklist_key(message, filter.id);
                     let filter_st = RedisStr::new(&filter)?;
                     p.set(&key, filter_st)
-                        .expire(&key, CONFIG.timing.cache_timeout);
+                        .expire(&key, CONFIG.load().timing.cache_timeout);
                     for trigger in triggers.into_iter() {
-                        p.hset(
-                            &hash_key,
-                            trigger.trigger,
-                            (filter.id, Some(filter.handle.as_ref())).to_redis()?,
-                        )
-                        .expire(&hash_key, CONFIG.timing.cache_timeout);
+                        let config = match trigger.filter_type {
+                            FilterType::Text => FilterConfig::Text,
+                            FilterType::Glob => FilterConfig::Glob,
+                            FilterType::Script => {
+                                FilterConfig::Script(filter.handle.clone().unwrap_or_default())
+                            }
+                        };
+                        p.hset(&hash_key, trigger.trigger, (filter.id, config).to_redis()?)
+                            .expire(&hash_key, CONFIG.load().timing.cache_timeout);
                     }
                 }
                 Ok(p)
@@ -920,7 +981,7 @@ async fn insert_blocklist(
         )
         .exec(*DB)
         .await?;
-    let hash_key = get_blocklist_hash_key(message.get_chat().get_id());
+    let hash_key = get_blocklist_hash_key(message.get_chat().get_id()).await?;
     let id = (model.id, filter_type).to_redis()?;
     let model_id = model.id;
     REDIS
@@ -928,10 +989,11 @@ async fn insert_blocklist(
             for trigger in triggers {
                 p.hset(&hash_key, trigger, &id);
             }
-            p
+            p.hset(&hash_key, GENERATION_FIELD, next_generation())
         })
         .await?;
     model.cache(get_blocklist_key(message, model_id)).await?;
+    invalidate_shared_blocklists(message.get_chat().get_id()).await?;
     Ok(())
 }
 
@@ -947,7 +1009,9 @@ async fn command_blocklist<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()>
         .header(true);
 
     let (body, _, _, header, footer) = cmd.build_filter().await;
-    let filters = match header.ok_or_else(|| ctx.fail_err("Header missing from filter command"))? {
+    let filters = match header
+        .ok_or_else(|| ctx.fail_err_code(MISSING_ARGUMENT, lang_fmt!(ctx, "errfilterheader")))?
+    {
         Header::List(st) => st,
         Header::Arg(st) => vec![st],
     };
@@ -958,30 +1022,20 @@ async fn command_blocklist<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()>
         match args.next() {
             Some("tmute") => (
                 ActionType::Mute,
-                args.next().and_then(|d| {
-                    parse_duration_str(d, message.get_chat().get_id(), message.message_id).ok()
-                }),
+                args.next().and_then(|d| ctx.parse_duration_arg(d).ok()),
             ),
 
             Some("tban") => (
                 ActionType::Ban,
-                args.next().and_then(|d| {
-                    parse_duration_str(d, message.get_chat().get_id(), message.message_id).ok()
-                }),
+                args.next().and_then(|d| ctx.parse_duration_arg(d).ok()),
             ),
             Some("twarn") => (
                 ActionType::Warn,
-                args.next().and_then(|d| {
-                    parse_duration_str(d, message.get_chat().get_id(), message.message_id).ok()
-                }),
+                args.next().and_then(|d| ctx.parse_duration_arg(d).ok()),
             ),
             None => (ActionType::Delete, None),
             _ => {
-                return Err(BotError::speak(
-                    "Invalid action",
-                    message.get_chat().get_id(),
-                    Some(message.message_id),
-                ));
+                return ctx.fail_code(INVALID_ARGUMENT, lang_fmt!(ctx, "errinvalidaction"));
             }
         }
     } else {
@@ -1026,15 +1080,14 @@ async fn script_blocklist<'a>(ctx: &Context) -> Result<()> {
     ctx.action_message(|ctx, am, args| async move {
         let (name, args) = args
             .and_then(|a| a.pop_slice())
-            .ok_or_else(|| ctx.fail_err("Need to provide a script name"))?;
+            .ok_or_else(|| ctx.fail_err_code(MISSING_ARGUMENT, lang_fmt!(ctx, "errscriptname")))?;
         let (message, text) = match am {
             ActionMessage::Me(message) => (message, args.text),
             ActionMessage::Reply(message) => (
                 message,
-                message
-                    .text
-                    .as_deref()
-                    .ok_or_else(|| ctx.fail_err("The replied message has no text"))?,
+                message.text.as_deref().ok_or_else(|| {
+                    ctx.fail_err_code(MISSING_ARGUMENT, lang_fmt!(ctx, "errreplynotext"))
+                })?,
             ),
         };
 
@@ -1098,68 +1151,374 @@ async fn warn(ctx: &Context, user: &User, reason: Option<String>) -> Result<()>
     Ok(())
 }
 
-async fn handle_trigger(ctx: &Context) -> Result<()> {
+/// Applies a blocklist action to the sender of a message and deletes it
+async fn apply_action(
+    ctx: &Context,
+    message: &Message,
+    user: &User,
+    action: &ActionType,
+    duration: Option<Duration>,
+    reason: Option<String>,
+) -> Result<()> {
+    let duration_str = if let Some(duration) = duration {
+        lang_fmt!(
+            ctx,
+            "duration",
+            ctx.lang().format_duration(duration.to_std()?)
+        )
+    } else {
+        String::new()
+    };
+    let reason_str = reason
+        .as_ref()
+        .map(|v| lang_fmt!(ctx, "reason", v))
+        .unwrap_or_default();
+    match action {
+        ActionType::Mute => {
+            ctx.mute(user.get_id(), ctx.try_get()?.chat, duration)
+                .await?;
+            let mention = user.mention().await?;
+            message
+                .reply_fmt(entity_fmt!(
+                    ctx,
+                    "blockmute",
+                    mention,
+                    duration_str,
+                    reason_str
+                ))
+                .await?;
+        }
+        ActionType::Ban => {
+            ctx.ban(user.get_id(), duration, true).await?;
+            let mention = user.mention().await?;
+            message
+                .reply_fmt(entity_fmt!(
+                    ctx,
+                    "blockban",
+                    mention,
+                    duration_str,
+                    reason_str
+                ))
+                .await?;
+        }
+        ActionType::Warn => {
+            warn(ctx, user, reason).await?;
+        }
+        ActionType::Shame => (),
+        ActionType::Delete => (),
+    }
+    message.delete().await?;
+    Ok(())
+}
+
+/// Text read out of a photo or sticker, if the chat turned on /lockocrprofanity
+async fn ocr_text(message: &Message) -> Result<Option<String>> {
+    if !ocr_enabled() || (message.get_photo().is_none() && message.get_sticker().is_none()) {
+        return Ok(None);
+    }
+    if !dialog_or_default(message.get_chat()).await?.ocr_blocklists {
+        return Ok(None);
+    }
+    // a broken ocr service shouldn't stop the rest of the blocklists
+    Ok(extract_text(message).await.unwrap_or_else(|err| {
+        log::warn!("failed to read text from image: {}", err);
+        None
+    }))
+}
+
+/// Applies the action of a matching blocklist, returning true if one matched
+async fn handle_trigger(ctx: &Context) -> Result<bool> {
     if let Some(message) = ctx.should_moderate().await {
         if let Some(user) = message.get_from() {
-            if let Some(text) = message.get_text() {
+            let ocr;
+            let text = match message.get_text() {
+                Some(text) => Some(text),
+                None => {
+                    ocr = ocr_text(message).await?;
+                    ocr.as_deref()
+                }
+            };
+            if let Some(text) = text {
                 if let Some(res) = search_cache(ctx, message, text).await? {
                     let duration = res.duration.and_then(Duration::try_seconds);
-                    let duration_str = if let Some(duration) = duration {
-                        lang_fmt!(ctx, "duration", format_duration(duration.to_std()?))
-                    } else {
-                        String::new()
-                    };
-                    let reason_str = res
-                        .reason
-                        .as_ref()
-                        .map(|v| lang_fmt!(ctx, "reason", v))
-                        .unwrap_or_default();
-                    match res.action {
-                        ActionType::Mute => {
-                            ctx.mute(user.get_id(), ctx.try_get()?.chat, duration)
-                                .await?;
-                            let mention = user.mention().await?;
-                            message
-                                .reply_fmt(entity_fmt!(
-                                    ctx,
-                                    "blockmute",
-                                    mention,
-                                    duration_str,
-                                    reason_str
-                                ))
-                                .await?;
-                        }
-                        ActionType::Ban => {
-                            ctx.ban(user.get_id(), duration, true).await?;
-                            let mention = user.mention().await?;
-                            message
-                                .reply_fmt(entity_fmt!(
-                                    ctx,
-                                    "blockban",
-                                    mention,
-                                    duration_str,
-                                    reason_str
-                                ))
-                                .await?;
-                        }
-                        ActionType::Warn => {
-                            warn(ctx, user, res.reason).await?;
-                        }
-                        ActionType::Shame => (),
-                        ActionType::Delete => (),
-                    }
-                    message.delete().await?;
+                    apply_action(ctx, message, user, &res.action, duration, res.reason).await?;
+                    return Ok(true);
                 }
             }
         }
     }
 
+    Ok(false)
+}
+
+/// Applies the chat's spam action to messages scored at or above its threshold
+async fn handle_spam(ctx: &Context) -> Result<()> {
+    let Some(score) = ctx.spam_score().await? else {
+        return Ok(());
+    };
+    let Some(message) = ctx.should_moderate().await else {
+        return Ok(());
+    };
+    let Some(user) = message.get_from() else {
+        return Ok(());
+    };
+    let Some(settings) = get_spam_filter(message.get_chat().get_id()).await? else {
+        return Ok(());
+    };
+    if score.percent() < settings.threshold {
+        return Ok(());
+    }
+
+    let reason = lang_fmt!(
+        ctx,
+        "spamreason",
+        score.check.unwrap_or_default(),
+        score.percent()
+    );
+    apply_action(ctx, message, user, &settings.action, None, Some(reason)).await
+}
+
+/// Applies the chat's nsfw action to images scored at or above its threshold, returning
+/// true if the action was applied
+async fn handle_nsfw(ctx: &Context) -> Result<bool> {
+    let Some(message) = ctx.should_moderate().await else {
+        return Ok(false);
+    };
+    let Some(user) = message.get_from() else {
+        return Ok(false);
+    };
+    if message.get_photo().is_none() && message.get_sticker().is_none() {
+        return Ok(false);
+    }
+    let Some(settings) = get_nsfw_filter(message.get_chat().get_id()).await? else {
+        return Ok(false);
+    };
+    if !settings.enabled {
+        return Ok(false);
+    }
+    // a broken classifier shouldn't stop the rest of the blocklists
+    let score = match score_image(message).await {
+        Ok(Some(score)) => score,
+        Ok(None) => return Ok(false),
+        Err(err) => {
+            log::warn!("failed to score image as nsfw: {}", err);
+            return Ok(false);
+        }
+    };
+    if score < settings.threshold {
+        return Ok(false);
+    }
+
+    let reason = lang_fmt!(ctx, "nsfwreason", score);
+    apply_action(ctx, message, user, &settings.action, None, Some(reason)).await?;
+    let text = lang_fmt!(
+        ctx,
+        "nsfwlog",
+        user.name_humanreadable().escape(false),
+        user.get_id(),
+        score,
+        settings.action.get_name()
+    );
+    if let Err(err) = send_log(message.get_chat(), text).await {
+        log::debug!("failed to log nsfw action: {}", err);
+    }
+    Ok(true)
+}
+
+async fn nsfwfilter<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    match args.text.trim() {
+        "" => {
+            let (enabled, threshold, action) = get_nsfw_filter(chat)
+                .await?
+                .map(|s| (s.enabled, s.threshold, s.action.get_name().to_owned()))
+                .unwrap_or_else(|| {
+                    (
+                        false,
+                        nsfw::DEFAULT_THRESHOLD,
+                        ActionType::Delete.get_name().to_owned(),
+                    )
+                });
+            let enabled = if enabled { "on" } else { "off" };
+            ctx.reply(lang_fmt!(
+                ctx,
+                "nsfwfilterstatus",
+                enabled,
+                action,
+                threshold
+            ))
+            .await?;
+        }
+        "on" | "yes" => {
+            ctx.check_permissions(|p| p.can_restrict_members.and(p.can_change_info))
+                .await?;
+            if !nsfw_enabled() {
+                return ctx.fail(lang_fmt!(ctx, "nsfwunavailable"));
+            }
+            set_nsfw_filter_enabled(chat, true).await?;
+            ctx.confirm(lang_fmt!(ctx, "nsfwfilteron")).await?;
+        }
+        "off" | "no" => {
+            ctx.check_permissions(|p| p.can_restrict_members.and(p.can_change_info))
+                .await?;
+            set_nsfw_filter_enabled(chat, false).await?;
+            ctx.confirm(lang_fmt!(ctx, "nsfwfilteroff")).await?;
+        }
+        _ => return ctx.usage_err("nsfwfilterusage"),
+    }
+    Ok(())
+}
+
+async fn nsfwaction<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let mut words = args.text.split_whitespace();
+    let action = ActionType::from_str_err(words.next().unwrap_or_default(), || {
+        ctx.fail_err(lang_fmt!(ctx, "invalidnsfwaction"))
+    })?;
+    let threshold = match words.next() {
+        Some(threshold) => match threshold.trim_end_matches('%').parse::<i32>() {
+            Ok(threshold) if (1..=100).contains(&threshold) => Some(threshold),
+            _ => return ctx.fail(lang_fmt!(ctx, "invalidnsfwthreshold")),
+        },
+        None => None,
+    };
+    ctx.check_permissions(|p| p.can_restrict_members.and(p.can_change_info))
+        .await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    let settings = set_nsfw_action(chat, action, threshold).await?;
+    ctx.confirm(lang_fmt!(
+        ctx,
+        "setnsfwaction",
+        settings.action.get_name(),
+        settings.threshold
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn spamfilter<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    match args.text.trim() {
+        "" => {
+            let (enabled, threshold, action) = get_spam_filter(chat)
+                .await?
+                .map(|s| (s.enabled, s.threshold, s.action.get_name().to_owned()))
+                .unwrap_or_else(|| {
+                    (
+                        false,
+                        DEFAULT_THRESHOLD,
+                        ActionType::Delete.get_name().to_owned(),
+                    )
+                });
+            let enabled = if enabled { "on" } else { "off" };
+            ctx.reply(lang_fmt!(
+                ctx,
+                "spamfilterstatus",
+                enabled,
+                action,
+                threshold
+            ))
+            .await?;
+        }
+        "on" | "yes" => {
+            ctx.check_permissions(|p| p.can_restrict_members.and(p.can_change_info))
+                .await?;
+            set_spam_filter_enabled(chat, true).await?;
+            ctx.confirm(lang_fmt!(ctx, "spamfilteron")).await?;
+        }
+        "off" | "no" => {
+            ctx.check_permissions(|p| p.can_restrict_members.and(p.can_change_info))
+                .await?;
+            set_spam_filter_enabled(chat, false).await?;
+            ctx.confirm(lang_fmt!(ctx, "spamfilteroff")).await?;
+        }
+        _ => return ctx.usage_err("spamfilterusage"),
+    }
+    Ok(())
+}
+
+async fn spamaction<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let mut words = args.text.split_whitespace();
+    let action = ActionType::from_str_err(words.next().unwrap_or_default(), || {
+        ctx.fail_err(lang_fmt!(ctx, "invalidspamaction"))
+    })?;
+    let threshold = match words.next() {
+        Some(threshold) => match threshold.trim_end_matches('%').parse::<i32>() {
+            Ok(threshold) if (1..=100).contains(&threshold) => Some(threshold),
+            _ => return ctx.fail(lang_fmt!(ctx, "invalidspamthreshold")),
+        },
+        None => None,
+    };
+    ctx.check_permissions(|p| p.can_restrict_members.and(p.can_change_info))
+        .await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    let settings = set_spam_action(chat, action, threshold).await?;
+    ctx.confirm(lang_fmt!(
+        ctx,
+        "setspamaction",
+        settings.action.get_name(),
+        settings.threshold
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Turns matching blocklists against text read from images on or off
+async fn set_ocr_blocklists(chat: &Chat, enabled: bool) -> Result<()> {
+    let mut model = dialogs::Model::from_chat(chat).await?;
+    model.ocr_blocklists = Set(enabled);
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_column(dialogs::Column::OcrBlocklists)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat.get_id()).invalidate().await?;
+    Ok(())
+}
+
+async fn lockocrprofanity<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat;
+    let enabled = match args.text.trim() {
+        "" => {
+            let text = if dialog_or_default(chat).await?.ocr_blocklists {
+                lang_fmt!(ctx, "ocron")
+            } else {
+                lang_fmt!(ctx, "ocroff")
+            };
+            ctx.reply(text).await?;
+            return Ok(());
+        }
+        "on" | "yes" => true,
+        "off" | "no" => false,
+        _ => return ctx.usage_err("lockocrprofanityusage"),
+    };
+
+    ctx.check_permissions(|p| p.can_restrict_members.and(p.can_change_info))
+        .await?;
+    if enabled && !ocr_enabled() {
+        return ctx.fail(lang_fmt!(ctx, "ocrunavailable"));
+    }
+    set_ocr_blocklists(chat, enabled).await?;
+    let text = if enabled {
+        lang_fmt!(ctx, "ocron")
+    } else {
+        lang_fmt!(ctx, "ocroff")
+    };
+    ctx.confirm(text).await?;
     Ok(())
 }
 
 async fn list_triggers(message: &Message) -> Result<()> {
     message.check_permissions(|p| p.can_manage_chat).await?;
-    let hash_key = get_blocklist_hash_key(message.get_chat().get_id());
+    let hash_key = get_blocklist_hash_key(message.get_chat().get_id()).await?;
     update_cache_from_db(message).await?;
     let res: Option<HashMap<String, RedisStr>> = REDIS.sq(|q| q.hgetall(&hash_key)).await?;
     if let Some(map) = res {
@@ -1210,8 +1569,9 @@ async fn delete_all(chat: i64) -> Result<()> {
         .exec(*DB)
         .await?;
 
-    let key = get_blocklist_hash_key(chat);
+    let key = get_blocklist_hash_key(chat).await?;
     REDIS.sq(|q| q.del(&key)).await?;
+    invalidate_shared_blocklists(chat).await?;
     Ok(())
 }
 
@@ -1237,11 +1597,18 @@ async fn handle_command<'a>(ctx: &Context) -> Result<()> {
             "rmscriptblocklist" => delete_script(ctx, args.text.to_owned()).await?,
             "blocklist" => list_triggers(message).await?,
             "rmallblocklists" => stopall(ctx, ctx.message()?.get_chat().get_id()).await?,
-            _ => handle_trigger(ctx).await?,
+            "spamfilter" => spamfilter(ctx, args).await?,
+            "spamaction" => spamaction(ctx, args).await?,
+            "lockocrprofanity" => lockocrprofanity(ctx, args).await?,
+            "nsfwfilter" => nsfwfilter(ctx, args).await?,
+            "nsfwaction" => nsfwaction(ctx, args).await?,
+            _ => (),
         };
     }
 
-    handle_trigger(ctx).await?;
+    if !handle_trigger(ctx).await? && !handle_nsfw(ctx).await? {
+        handle_spam(ctx).await?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+
+use crate::metadata::metadata;
+use crate::persist::core::dialogs;
+use crate::statics::DB;
+use crate::tg::boosts::{boost_source_user, get_boosts, record_boost, remove_boost};
+use crate::tg::command::{Cmd, Context};
+use crate::tg::dialog::{dialog_or_default, dialog_scope};
+use crate::tg::extract::{CommandArgs, InGroup};
+use crate::tg::log_channel::send_log;
+use crate::tg::markdown::Escape;
+use crate::tg::user::{GetUser, Username};
+use crate::util::error::Result;
+use crate::util::string::{Confirm, Speak};
+use crate::util::time::ChatTime;
+use botapi::gen_types::{Chat, UpdateExt};
+use chrono::{DateTime, Utc};
+use macros::{lang_fmt, update_handler};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::EntityTrait;
+
+metadata!("Boosts",
+    r#"
+    See who is boosting the chat, and give them perks. Boosts are recorded as they come in, so
+    boosts from before the bot was added to the chat aren't known until the booster boosts
+    again. With /boosterperks on, boosters are exempt from locks
+    "#,
+    { command = "boosters", help = "List the chat's current boosters" },
+    { command = "boosterperks", help = "Exempt boosters from locks.", usage = "boosterperksusage" }
+);
+
+async fn set_booster_perks(chat: &Chat, perks: bool) -> Result<()> {
+    let mut model = dialogs::Model::from_chat(chat).await?;
+    model.booster_perks = Set(perks);
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_column(dialogs::Column::BoosterPerks)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat.get_id()).invalidate().await?;
+    Ok(())
+}
+
+async fn boosters(ctx: &Context, _: InGroup) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let boosts = get_boosts(chat.get_id()).await?;
+    if boosts.is_empty() {
+        ctx.reply(lang_fmt!(ctx, "noboosters")).await?;
+        return Ok(());
+    }
+
+    // each user's boosts, with the last one to expire
+    let mut users = BTreeMap::<Option<i64>, (usize, DateTime<Utc>)>::new();
+    for boost in boosts.iter() {
+        let entry = users.entry(boost.user_id).or_insert((0, boost.expires));
+        entry.0 += 1;
+        entry.1 = entry.1.max(boost.expires);
+    }
+    let local = ChatTime::get(chat.get_id()).await?;
+    let mut list = Vec::with_capacity(users.len());
+    for (user, (count, expires)) in users {
+        let name = match user {
+            Some(user) => user.cached_name().await?.escape(false).into_owned(),
+            None => lang_fmt!(ctx, "unclaimedboost"),
+        };
+        let expires = local.format_lang(&expires, ctx.lang());
+        list.push(lang_fmt!(ctx, "boosterline", name, count, expires));
+    }
+    ctx.reply(lang_fmt!(
+        ctx,
+        "listboosters",
+        chat.name_humanreadable().escape(false),
+        boosts.len(),
+        list.join("\n")
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn boosterperks(ctx: &Context, _: InGroup, CommandArgs(args): CommandArgs<'_>) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let perks = match args.text.trim() {
+        "" => {
+            let text = if dialog_or_default(chat).await?.booster_perks {
+                lang_fmt!(ctx, "boosterperkson")
+            } else {
+                lang_fmt!(ctx, "boosterperksoff")
+            };
+            ctx.reply(text).await?;
+            return Ok(());
+        }
+        "on" | "yes" => true,
+        "off" | "no" => false,
+        _ => return ctx.usage_err("boosterperksusage"),
+    };
+
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    set_booster_perks(chat, perks).await?;
+    let text = if perks {
+        lang_fmt!(ctx, "boosterperkson")
+    } else {
+        lang_fmt!(ctx, "boosterperksoff")
+    };
+    ctx.confirm(text).await?;
+    Ok(())
+}
+
+async fn handle_boost(ctx: &Context) -> Result<()> {
+    match ctx.update() {
+        UpdateExt::ChatBoost(update) => {
+            record_boost(update).await?;
+            if let Some(user) = boost_source_user(update.get_boost().get_source()) {
+                let text = lang_fmt!(ctx, "boostadded", user.name_humanreadable().escape(false));
+                if let Err(err) = send_log(update.get_chat(), text).await {
+                    log::warn!("failed to log boost {}", err);
+                    err.record_stats();
+                }
+            }
+        }
+        UpdateExt::RemovedChatBoost(update) => {
+            remove_boost(update).await?;
+            if let Some(user) = boost_source_user(update.get_source()) {
+                let text = lang_fmt!(ctx, "boostremoved", user.name_humanreadable().escape(false));
+                if let Err(err) = send_log(update.get_chat(), text).await {
+                    log::warn!("failed to log removed boost {}", err);
+                    err.record_stats();
+                }
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+        match cmd {
+            "boosters" => ctx.run(boosters).await,
+            "boosterperks" => ctx.run(boosterperks).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+    handle_boost(cmd).await?;
+
+    Ok(())
+}
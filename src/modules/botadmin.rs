@@ -0,0 +1,341 @@
+use crate::logger::{get_log_filter, set_log_filter};
+use crate::metadata::metadata;
+use crate::persist::admin::{fbans, warns};
+use crate::persist::core::{chats, users};
+use crate::persist::keys::audit_scope;
+use crate::persist::metrics::{metric_last_day, Metric, START_TIME, UPDATES_COUNTER};
+use crate::statics::{DB, TG};
+use crate::tg::admin_helpers::GetChat;
+use crate::tg::bot_commands::register_commands;
+use crate::tg::command::{Cmd, Context, TextArgs};
+use crate::tg::entity_gc::gc_stats;
+use crate::tg::markdown::EntityMessage;
+use crate::tg::permissions::IsGroupAdmin;
+use crate::tg::user::forget_db_chat;
+use crate::util::config::reload_config;
+use crate::util::error::{Fail, Result, SpeakErr};
+use crate::util::string::{should_ignore_chat, Lang, Speak};
+use botapi::bot::Part;
+use botapi::gen_types::FileData;
+use chrono::Utc;
+use macros::{lang_fmt, update_handler};
+use sea_orm::{EntityTrait, PaginatorTrait, QueryOrder};
+
+metadata!("Bot Administration",
+    r#"
+    Commands for the owner of the bot to manage the chats the bot is in and monitor usage.
+    Every chat the bot sees is tracked along with its last activity.
+    "#,
+    { command = "stats", help = "Sudo only: show usage statistics" },
+    { command = "chatlist", help = "Sudo only: get a file listing every chat the bot is in" },
+    { command = "leavechat", help = "Sudo only: leave a chat.", usage = "leavechatusage" },
+    { command = "reloadconfig", help = "Sudo only: reload the config file and list what changed" },
+    { command = "setcommands", help = "Sudo only: register the bot's commands with telegram for autocomplete" },
+    { command = "missingstrings", help = "Sudo only: list the strings a language doesn't translate yet.", usage = "missingstringsusage" },
+    { command = "gcstats", help = "Sudo only: show how many formatting entities and buttons are stored and what the garbage collector removed" },
+    { command = "cachedebug", help = "Sudo only: list a chat's cached keys with their sizes and ttls.", usage = "cachedebugusage" },
+    { command = "logfilter", help = "Sudo only: show or change which logs are written, using RUST\\_LOG syntax.", usage = "logfilterusage" }
+);
+
+async fn chatlist(ctx: &Context) -> Result<()> {
+    ctx.check_permissions(|p| p.is_sudo).await?;
+    let chats = chats::Entity::find()
+        .order_by_desc(chats::Column::LastActivity)
+        .all(*DB)
+        .await?;
+    let mut list = String::new();
+    for chat in chats {
+        let members = chat
+            .chat_id
+            .get_member_count_cached()
+            .await
+            .map(|v| v.to_string())
+            .unwrap_or_else(|_| "?".to_owned());
+        list.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            chat.chat_id,
+            chat.chat_type,
+            members,
+            chat.last_activity.format("%Y-%m-%d %H:%M UTC"),
+            chat.title.as_deref().unwrap_or("")
+        ));
+    }
+
+    let chat = ctx.try_get()?.chat.get_id();
+    let bytes = FileData::Part(Part::text(list).file_name("chats.txt"));
+    if !should_ignore_chat(chat).await? {
+        TG.client.build_send_document(chat, bytes).build().await?;
+    }
+    Ok(())
+}
+
+async fn leavechat<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.check_permissions(|p| p.is_sudo).await?;
+    let chat = match args.text.trim().parse::<i64>() {
+        Ok(chat) => chat,
+        Err(_) => return ctx.fail(lang_fmt!(ctx, "invalidchatid")),
+    };
+    TG.client
+        .build_leave_chat(chat)
+        .build()
+        .await
+        .speak_err(ctx, |e| lang_fmt!(ctx, "failleavechat", e))
+        .await?;
+    forget_db_chat(chat).await?;
+    ctx.reply(lang_fmt!(ctx, "leftchat", chat)).await?;
+    Ok(())
+}
+
+async fn reloadconfig(ctx: &Context) -> Result<()> {
+    ctx.check_permissions(|p| p.is_sudo).await?;
+    let changes = match reload_config() {
+        Ok(changes) => changes,
+        Err(err) => return ctx.fail(lang_fmt!(ctx, "reloadfailed", err)),
+    };
+    if changes.is_empty() {
+        ctx.reply(lang_fmt!(ctx, "reloadnochanges")).await?;
+        return Ok(());
+    }
+
+    let table = changes
+        .iter()
+        .map(|c| {
+            let mark = if c.applied { "+" } else { "!" };
+            format!("{} {}: {} -> {}", mark, c.name, c.old, c.new)
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    let mut message = EntityMessage::new(ctx.try_get()?.chat.get_id());
+    message
+        .builder
+        .bold(lang_fmt!(ctx, "reloadheader"))
+        .text("\n");
+    message.builder.pre(table, String::new(), None);
+    if changes.iter().any(|c| !c.applied) {
+        message.builder.text(lang_fmt!(ctx, "reloadrestart"));
+    }
+    ctx.reply_fmt(message).await?;
+    Ok(())
+}
+
+async fn setcommands(ctx: &Context) -> Result<()> {
+    ctx.check_permissions(|p| p.is_sudo).await?;
+    register_commands(&TG)
+        .await
+        .speak_err(ctx, |e| lang_fmt!(ctx, "setcommandsfailed", e))
+        .await?;
+    ctx.reply(lang_fmt!(ctx, "setcommands")).await?;
+    Ok(())
+}
+
+async fn stats(ctx: &Context) -> Result<()> {
+    ctx.check_permissions(|p| p.is_sudo).await?;
+    let users = users::Entity::find().count(*DB).await?;
+    let chats = chats::Entity::find().count(*DB).await?;
+    let warns = warns::Entity::find().count(*DB).await?;
+    let fbans = fbans::Entity::find().count(*DB).await?;
+    let commands = metric_last_day(Metric::Command).await?;
+    let updates = metric_last_day(Metric::Update).await?;
+    let duplicates = metric_last_day(Metric::DuplicateUpdate).await?;
+    let uptime = Utc::now() - *START_TIME;
+    let throughput = UPDATES_COUNTER.get() as f64 / uptime.num_seconds().max(1) as f64;
+
+    let rows = [
+        ("Users", users.to_string()),
+        ("Chats", chats.to_string()),
+        ("Warns", warns.to_string()),
+        ("Fbans", fbans.to_string()),
+        ("Commands (24h)", commands.to_string()),
+        ("Updates (24h)", updates.to_string()),
+        ("Duplicates (24h)", duplicates.to_string()),
+        ("Updates/s", format!("{:.2}", throughput)),
+        (
+            "Uptime",
+            format!(
+                "{}d {}h {}m",
+                uptime.num_days(),
+                uptime.num_hours() % 24,
+                uptime.num_minutes() % 60
+            ),
+        ),
+    ];
+    let table = rows
+        .iter()
+        .map(|(name, value)| format!("{:<16}{}", name, value))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let mut message = EntityMessage::new(ctx.try_get()?.chat.get_id());
+    message
+        .builder
+        .bold(lang_fmt!(ctx, "statsheader"))
+        .text("\n");
+    message.builder.pre(table, String::new(), None);
+    ctx.reply_fmt(message).await?;
+    Ok(())
+}
+
+async fn gcstats(ctx: &Context) -> Result<()> {
+    ctx.check_permissions(|p| p.is_sudo).await?;
+    let stats = gc_stats().await?;
+    let last = match stats.last {
+        Some(ref run) => format!(
+            "{} ({}/{}/{})",
+            run.time.format("%Y-%m-%d %H:%M UTC"),
+            run.removed.lists,
+            run.removed.entities,
+            run.removed.buttons
+        ),
+        None => "never".to_owned(),
+    };
+    let rows = [
+        ("Entity lists", stats.lists.to_string()),
+        ("Entities", stats.entities.to_string()),
+        ("Buttons", stats.buttons.to_string()),
+        ("Orphaned lists", stats.orphans.to_string()),
+        ("Last collected", last),
+        (
+            "Removed total",
+            format!(
+                "{}/{}/{}",
+                stats.total.lists, stats.total.entities, stats.total.buttons
+            ),
+        ),
+    ];
+    let table = rows
+        .iter()
+        .map(|(name, value)| format!("{:<16}{}", name, value))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let mut message = EntityMessage::new(ctx.try_get()?.chat.get_id());
+    message
+        .builder
+        .bold(lang_fmt!(ctx, "gcstatsheader"))
+        .text("\n");
+    message.builder.pre(table, String::new(), None);
+    ctx.reply_fmt(message).await?;
+    Ok(())
+}
+
+/// Most keys listed by /cachedebug
+const CACHEDEBUG_LIMIT: usize = 50;
+
+async fn cachedebug<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.check_permissions(|p| p.is_sudo).await?;
+    let chat = match args.text.trim().parse::<i64>() {
+        Ok(chat) => chat,
+        Err(_) => return ctx.fail(lang_fmt!(ctx, "invalidchatid")),
+    };
+    let keys = audit_scope(chat).await?;
+    if keys.is_empty() {
+        ctx.reply(lang_fmt!(ctx, "cachedebugempty", chat)).await?;
+        return Ok(());
+    }
+    let prefix = format!("c:{}:", chat);
+    let mut table = keys
+        .iter()
+        .take(CACHEDEBUG_LIMIT)
+        .map(|info| {
+            let size = info
+                .size
+                .map(|size| size.to_string())
+                .unwrap_or_else(|| "?".to_owned());
+            let ttl = if info.ttl < 0 {
+                "none".to_owned()
+            } else {
+                format!("{}s", info.ttl)
+            };
+            format!(
+                "{}{:<28}{:<6}{:>8} {}",
+                if info.missing_ttl() { "!" } else { " " },
+                info.key.strip_prefix(&prefix).unwrap_or(&info.key),
+                info.kind,
+                size,
+                ttl
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    if keys.len() > CACHEDEBUG_LIMIT {
+        table.push_str(&format!("\n... {} more", keys.len() - CACHEDEBUG_LIMIT));
+    }
+
+    let mut message = EntityMessage::new(ctx.try_get()?.chat.get_id());
+    message
+        .builder
+        .bold(lang_fmt!(ctx, "cachedebugheader", chat, keys.len()))
+        .text("\n");
+    message.builder.pre(table, String::new(), None);
+    ctx.reply_fmt(message).await?;
+    Ok(())
+}
+
+async fn logfilter<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.check_permissions(|p| p.is_sudo).await?;
+    let directives = args.text.trim();
+    if directives.is_empty() {
+        let current = get_log_filter().unwrap_or_default();
+        ctx.reply(lang_fmt!(ctx, "logfilter", current)).await?;
+        return Ok(());
+    }
+    let old = match set_log_filter(directives) {
+        Ok(old) => old,
+        Err(err) => return ctx.fail(lang_fmt!(ctx, "logfilterfailed", err)),
+    };
+    ctx.reply(lang_fmt!(ctx, "logfilterset", old, directives))
+        .await?;
+    Ok(())
+}
+
+async fn missingstrings<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.check_permissions(|p| p.is_sudo).await?;
+    let code = args.text.trim();
+    let lang = match Lang::from_code(code) {
+        Lang::Invalid => return ctx.fail(lang_fmt!(ctx, "invalidlang")),
+        lang => lang,
+    };
+    let missing = lang.missing_strings();
+    if missing.is_empty() {
+        ctx.reply(lang_fmt!(ctx, "nomissingstrings", code)).await?;
+        return Ok(());
+    }
+    let total = Lang::En.strings().len();
+    let translated = (total - missing.len()) * 100 / total;
+    ctx.reply(lang_fmt!(
+        ctx,
+        "missingstrings",
+        code,
+        missing.len(),
+        total,
+        translated,
+        missing.join(", ")
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
+        match cmd {
+            "stats" => stats(ctx).await,
+            "chatlist" => chatlist(ctx).await,
+            "leavechat" => leavechat(ctx, args).await,
+            "reloadconfig" => reloadconfig(ctx).await,
+            "setcommands" => setcommands(ctx).await,
+            "missingstrings" => missingstrings(ctx, args).await,
+            "gcstats" => gcstats(ctx).await,
+            "cachedebug" => cachedebug(ctx, args).await,
+            "logfilter" => logfilter(ctx, args).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use crate::metadata::metadata;
+use crate::persist::core::chats;
+use crate::statics::DB;
+use crate::tg::admin_helpers::is_dm;
+use crate::tg::broadcast::start_broadcast;
+use crate::tg::command::{Cmd, Context, TextArgs};
+use crate::tg::markdown::MarkupBuilder;
+use crate::tg::permissions::IsGroupAdmin;
+use crate::util::error::{BotError, Fail, Result, SpeakErr};
+use crate::util::string::{Confirm, Speak};
+use chrono::Utc;
+use macros::{lang_fmt, update_handler};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+metadata!("Broadcast",
+    r#"
+    The owner of the bot can send announcements to every chat the bot is in. Announcements
+    support the same formatting as notes. Admins can opt their chat out of announcements.
+    "#,
+    { command = "broadcast", help = "Sudo only: send an announcement to every chat" },
+    { command = "announcements", help = "Receive announcements from the bot owner.", usage = "announcementsusage" }
+);
+
+async fn set_broadcast(ctx: &Context, broadcast: bool) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let model = chats::ActiveModel {
+        chat_id: Set(chat.get_id()),
+        chat_type: Set(chat.get_tg_type().to_owned()),
+        broadcast: Set(broadcast),
+        title: Set(chat.get_title().map(|v| v.to_owned())),
+        last_activity: Set(Utc::now()),
+    };
+    chats::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(chats::Column::ChatId)
+                .update_column(chats::Column::Broadcast)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+    Ok(())
+}
+
+async fn announcements<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    if !is_dm(ctx.try_get()?.chat) {
+        ctx.check_permissions(|p| p.can_change_info).await?;
+    }
+    match args.text.trim() {
+        "on" | "yes" => {
+            set_broadcast(ctx, true).await?;
+            ctx.confirm(lang_fmt!(ctx, "broadcastson")).await?;
+        }
+        "off" | "no" => {
+            set_broadcast(ctx, false).await?;
+            ctx.confirm(lang_fmt!(ctx, "broadcastsoff")).await?;
+        }
+        _ => return ctx.usage_err("announcementsusage"),
+    }
+    Ok(())
+}
+
+async fn broadcast<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.check_permissions(|p| p.is_sudo).await?;
+    if args.text.trim().is_empty() {
+        return ctx.fail(lang_fmt!(ctx, "broadcastempty"));
+    }
+    let (text, entities, buttons) = MarkupBuilder::new(None)
+        .filling(false)
+        .header(false)
+        .set_text(args.text.to_owned())
+        .build_murkdown()
+        .await
+        .speak(ctx, lang_fmt!(ctx, "failmurk"))
+        .await?;
+
+    let chats = chats::Entity::find()
+        .filter(chats::Column::Broadcast.eq(true))
+        .all(*DB)
+        .await?;
+    let total = chats.len();
+    let status = ctx
+        .reply(lang_fmt!(ctx, "broadcastprogress", total, 0, 0))
+        .await?
+        .ok_or_else(|| BotError::Generic("failed to send broadcast status".to_owned()))?;
+    let markup = (!buttons.get().is_empty()).then(|| buttons.build());
+    let chats = chats.into_iter().map(|chat| chat.chat_id).collect();
+    start_broadcast(chats, text, entities, markup, &status, *ctx.lang()).await?;
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
+        match cmd {
+            "broadcast" => broadcast(ctx, args).await,
+            "announcements" => announcements(ctx, args).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+
+    Ok(())
+}
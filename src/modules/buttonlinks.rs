@@ -0,0 +1,134 @@
+use crate::metadata::metadata;
+use crate::tg::command::{Cmd, Context, TextArgs};
+use crate::tg::dialog::get_dialog;
+use crate::tg::permissions::IsGroupAdmin;
+use crate::tg::url_guard::{
+    get_domain_rules, normalize_domain, remove_domain_rule, set_domain_rule, set_strict_buttons,
+};
+use crate::util::error::{Fail, Result};
+use crate::util::string::{Confirm, Speak};
+use macros::{lang_fmt, update_handler};
+
+metadata!("Button Links",
+    r#"
+    Protect members from phishing links planted in notes, welcomes, and filters. Whenever
+    buttons are saved their links are checked against this chat's allowlist and denylist, and
+    links to domains pretending to be telegram are flagged automatically. The admin saving the
+    buttons is warned about any flagged links, or with strict mode enabled saving is refused.
+
+    If the allowlist is not empty only allowed domains may be linked. Allowing a domain also
+    allows all of its subdomains.
+    "#,
+    { command = "allowdomain", help = "Allow buttons to link to a domain.", usage = "allowdomainusage" },
+    { command = "denydomain", help = "Refuse buttons linking to a domain.", usage = "denydomainusage" },
+    { command = "rmdomain", help = "Remove a domain from the allowlist or denylist" },
+    { command = "domains", help = "List allowed and denied domains" },
+    { command = "strictbuttons", help = "Refuse to save flagged buttons instead of warning.", usage = "strictbuttonsusage" }
+);
+
+fn domain_arg<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<String> {
+    let domain = normalize_domain(args.text);
+    if domain.is_empty() || domain.contains(char::is_whitespace) || domain.contains('/') {
+        ctx.fail(lang_fmt!(ctx, "invaliddomain"))
+    } else {
+        Ok(domain)
+    }
+}
+
+async fn set_domain<'a>(ctx: &Context, args: &TextArgs<'a>, allow: bool) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    let domain = domain_arg(ctx, args)?;
+    set_domain_rule(chat, &domain, allow).await?;
+    let text = if allow {
+        lang_fmt!(ctx, "allowdomain", domain)
+    } else {
+        lang_fmt!(ctx, "denydomain", domain)
+    };
+    ctx.confirm(text).await?;
+    Ok(())
+}
+
+async fn rmdomain<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    let domain = domain_arg(ctx, args)?;
+    if !remove_domain_rule(chat, &domain).await? {
+        return ctx.fail(lang_fmt!(ctx, "nodomain", domain));
+    }
+    ctx.confirm(lang_fmt!(ctx, "rmdomain", domain)).await?;
+    Ok(())
+}
+
+async fn domains(ctx: &Context) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat;
+    let rules = get_domain_rules(chat.get_id()).await?;
+    let list = |allow: bool| {
+        let list = rules
+            .iter()
+            .filter(|r| r.allow == allow)
+            .map(|r| r.domain.as_str())
+            .collect::<Vec<&str>>();
+        if list.is_empty() {
+            "none".to_owned()
+        } else {
+            list.join(", ")
+        }
+    };
+    let strict = get_dialog(chat)
+        .await?
+        .map(|d| d.button_url_strict)
+        .unwrap_or(false);
+    let strict = if strict { "on" } else { "off" };
+    ctx.reply(lang_fmt!(
+        ctx,
+        "domainlist",
+        list(true),
+        list(false),
+        strict
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn strictbuttons<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    let chat = ctx.try_get()?.chat;
+    match args.text.trim() {
+        "on" | "yes" => {
+            set_strict_buttons(chat, true).await?;
+            ctx.confirm(lang_fmt!(ctx, "strictbuttonson")).await?;
+        }
+        "off" | "no" => {
+            set_strict_buttons(chat, false).await?;
+            ctx.confirm(lang_fmt!(ctx, "strictbuttonsoff")).await?;
+        }
+        _ => return ctx.usage_err("strictbuttonsusage"),
+    }
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
+        match cmd {
+            "allowdomain" => set_domain(ctx, args, true).await,
+            "denydomain" => set_domain(ctx, args, false).await,
+            "rmdomain" => rmdomain(ctx, args).await,
+            "domains" => domains(ctx).await,
+            "strictbuttons" => strictbuttons(ctx, args).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+
+    Ok(())
+}
@@ -6,10 +6,11 @@ use crate::tg::greetings::send_captcha;
 use crate::tg::permissions::IsGroupAdmin;
 use crate::tg::rosemd::{RoseMdDecompiler, RoseMdParser};
 use crate::util::error::{Fail, Result, SpeakErr};
+use crate::util::error_codes::MISSING_ARGUMENT;
 use crate::util::scripting::{ManagedRhai, RHAI_ENGINE};
 use crate::util::string::Speak;
 use botapi::gen_types::Message;
-use macros::update_handler;
+use macros::{lang_fmt, update_handler};
 use rhai::Dynamic;
 use sea_orm_migration::MigrationTrait;
 
@@ -121,7 +122,8 @@ async fn handle_markdown(message: &Message) -> Result<bool> {
 
 async fn map_script(ctx: &Context) -> Result<()> {
     ctx.action_message(|ctx, am, args| async move {
-        let args = args.ok_or_else(|| ctx.fail_err("missing arg"))?;
+        let args = args
+            .ok_or_else(|| ctx.fail_err_code(MISSING_ARGUMENT, lang_fmt!(ctx, "errmissingarg")))?;
         let text = args.text.to_owned();
         let message = match am {
             ActionMessage::Me(m) => m,
@@ -1,31 +1,27 @@
 use crate::metadata::metadata;
 
 use crate::persist::admin::captchastate::CaptchaType;
-use crate::persist::redis::RedisStr;
 use crate::statics::REDIS;
 
 use crate::tg::command::{ArgSlice, Cmd, Context, TextArgs};
-use crate::tg::greetings::{get_callback_key, get_captcha_auth_key, send_captcha};
+use crate::tg::deeplink::DeepLink;
+use crate::tg::greetings::{get_captcha_auth_key, send_captcha_to};
 use crate::tg::permissions::*;
-use crate::tg::user::Username;
 use crate::util::error::Fail;
 use crate::util::error::Result;
-use crate::util::string::Speak;
-use base64::engine::general_purpose;
-use base64::Engine;
-use botapi::gen_types::{Chat, User};
+use crate::util::error_codes::INVALID_ARGUMENT;
+use crate::util::string::{Confirm, Speak};
 use macros::{lang_fmt, update_handler};
 use redis::AsyncCommands;
 use sea_orm_migration::MigrationName;
-use uuid::Uuid;
 
 metadata!("Captcha",
     r#"
        Set a captcha in the group to keep bots out. Supports two security levels, text and button.
     "#,
-    { command = "captcha", help = "Enabled or disables captcha. Usage: /captcha \\<on/off\\>" },
+    { command = "captcha", help = "Enabled or disables captcha.", usage = "captchausage" },
     { command = "captchamode", help = "Sets the captcha mode to either button or text"},
-    { command = "captchakick", help = "Sets the timeout for removing users who haven't solved the captcha. off to disable"}
+    { command = "captchakick", help = "Sets the timeout for removing users who haven't solved the captcha.", usage = "captchakickusage"}
 
 );
 
@@ -44,14 +40,14 @@ async fn captchakick_cmd<'a>(ctx: &Context, args: &'a TextArgs<'a>) -> Result<()
     match args.as_slice() {
         ArgSlice { text: "off", .. } => {
             ctx.captchakick(None).await?;
-            message.reply(lang_fmt!(ctx, "enablekick")).await?;
+            message.confirm(lang_fmt!(ctx, "enablekick")).await?;
         }
         slice => {
             if let Some(time) = ctx.parse_duration(&Some(slice))? {
                 ctx.captchakick(Some(time.num_seconds())).await?;
-                message.reply(lang_fmt!(ctx, "disablekick")).await?;
+                message.confirm(lang_fmt!(ctx, "disablekick")).await?;
             } else {
-                message.reply(lang_fmt!(ctx, "invalidargument")).await?;
+                return ctx.usage_err("captchakickusage");
             }
         }
     }
@@ -71,34 +67,33 @@ async fn handle_command<'a>(ctx: &Context) -> Result<()> {
                 captchakick_cmd(ctx, args).await?;
             }
             "captchamode" => {
-                let t = CaptchaType::from_str(
-                    args.args.first().map(|a| a.get_text()).unwrap_or(""),
-                    message.get_chat().get_id(),
-                    message.message_id,
-                )?;
+                let t = CaptchaType::parse(args.args.first().map(|a| a.get_text()).unwrap_or(""))
+                    .ok_or_else(|| {
+                    ctx.fail_err_code(INVALID_ARGUMENT, lang_fmt!(ctx, "errcaptchatype"))
+                })?;
                 ctx.captchamode(t).await?;
             }
             "captcha" => match args.args.first().map(|a| a.get_text()) {
                 Some("on") => ctx.enable_captcha().await?,
                 Some("off") => ctx.disable_captcha().await?,
-                _ => return ctx.fail("Invalid argument, use on or off"),
+                _ => return ctx.usage_err("captchausage"),
             },
             "start" => {
-                if let (Some(user), Some(u)) =
-                    (message.get_from(), args.args.first().map(|a| a.get_text()))
+                if let (Some(user), Some(&DeepLink::Captcha { chat, user: owner })) =
+                    (message.get_from(), ctx.deep_link().await?)
                 {
-                    let base = general_purpose::URL_SAFE_NO_PAD.decode(u)?;
-                    let base = Uuid::from_slice(base.as_slice())?;
-                    let key = get_callback_key(&base.to_string());
-                    let base: Option<RedisStr> = REDIS.sq(|q| q.get(&key)).await?;
-                    if let Some(base) = base {
-                        let (cchat, cuser): (Chat, User) = base.get()?;
-                        let key = get_captcha_auth_key(cuser.get_id(), cchat.get_id());
+                    // links posted for one member only work for them, links from a mass join
+                    // welcome work for any member still waiting
+                    if owner.map(|owner| owner == user.get_id()).unwrap_or(true) {
+                        let key = get_captcha_auth_key(user.get_id(), chat);
                         if REDIS.sq(|q| q.exists(&key)).await? {
-                            log::info!("chat {}", cchat.name_humanreadable());
-                            if cuser.get_id() == user.get_id() {
-                                send_captcha(message, cchat, ctx).await?;
-                            }
+                            send_captcha_to(
+                                message.get_chat().get_id(),
+                                Some(message.get_message_id()),
+                                chat,
+                                ctx.lang(),
+                            )
+                            .await?;
                         } else {
                             ctx.reply(lang_fmt!(ctx, "captchanotauthorized")).await?;
                         }
@@ -0,0 +1,265 @@
+use crate::metadata::metadata;
+use crate::persist::core::dialogs;
+use crate::statics::DB;
+use crate::tg::admin_helpers::DeleteAfterTime;
+use crate::tg::command::{Cmd, Context, TextArgs};
+use crate::tg::dialog::{dialog_or_default, dialog_scope, get_dialog};
+use crate::tg::middleware::{Flow, Middleware};
+use crate::tg::permissions::IsGroupAdmin;
+use crate::util::error::{Fail, Result};
+use crate::util::string::{Confirm, Speak};
+use botapi::gen_types::{Chat, Message, UpdateExt};
+use macros::{lang_fmt, update_handler};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::EntityTrait;
+
+metadata!("Clean Service",
+    r#"
+    Delete the service messages telegram posts when members join or leave, messages are pinned,
+    or the chat's title or photo changes. Pick the kinds to delete:
+    [*join]: members joining or being added
+    [*leave]: members leaving or being removed
+    [*pin]: pinned messages
+    [*title]: title changes
+    [*photo]: photo changes
+
+    [*Examples]
+    [_delete join and leave messages]
+    /cleanservice join leave
+
+    [_delete every kind]
+    /cleanservice all
+
+    [_stop deleting service messages]
+    /cleanservice off
+
+    Discussion groups get a copy of every post from their linked channel, which comments
+    reply to. /cleanlinked on deletes these copies for groups that don't want the posts
+    repeated in the chat.
+    "#,
+    { command = "cleanservice", help = "Delete service messages.", usage = "cleanserviceusage" },
+    { command = "cleanlinked", help = "Delete posts forwarded from the linked channel.", usage = "cleanlinkedusage" }
+);
+
+/// A kind of service message, stored as a bit in the dialog's clean_service mask
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ServiceKind {
+    Join,
+    Leave,
+    Pin,
+    Title,
+    Photo,
+}
+
+impl ServiceKind {
+    const ALL: [Self; 5] = [Self::Join, Self::Leave, Self::Pin, Self::Title, Self::Photo];
+
+    fn bit(self) -> i32 {
+        1 << self as i32
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Join => "join",
+            Self::Leave => "leave",
+            Self::Pin => "pin",
+            Self::Title => "title",
+            Self::Photo => "photo",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// Gets the kind of a service message, None for regular messages
+    fn of(message: &Message) -> Option<Self> {
+        if message.get_new_chat_members().is_some() {
+            Some(Self::Join)
+        } else if message.get_left_chat_member().is_some() {
+            Some(Self::Leave)
+        } else if message.get_pinned_message().is_some() {
+            Some(Self::Pin)
+        } else if message.get_new_chat_title().is_some() {
+            Some(Self::Title)
+        } else if message.get_new_chat_photo().is_some()
+            || message.get_delete_chat_photo().is_some()
+        {
+            Some(Self::Photo)
+        } else {
+            None
+        }
+    }
+}
+
+fn mask_names(mask: i32) -> String {
+    ServiceKind::ALL
+        .into_iter()
+        .filter(|kind| mask & kind.bit() != 0)
+        .map(|kind| kind.name())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+async fn set_clean_service(chat: &Chat, mask: i32) -> Result<()> {
+    let mut model = dialogs::Model::from_chat(chat).await?;
+    model.clean_service = Set(mask);
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_column(dialogs::Column::CleanService)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat.get_id()).invalidate().await?;
+    Ok(())
+}
+
+async fn set_clean_linked(chat: &Chat, clean: bool) -> Result<()> {
+    let mut model = dialogs::Model::from_chat(chat).await?;
+    model.clean_linked = Set(clean);
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_column(dialogs::Column::CleanLinked)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat.get_id()).invalidate().await?;
+    Ok(())
+}
+
+async fn cleanlinked<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat;
+    let clean = match args.text.trim() {
+        "" => {
+            let text = if dialog_or_default(chat).await?.clean_linked {
+                lang_fmt!(ctx, "cleanlinkedon")
+            } else {
+                lang_fmt!(ctx, "cleanlinkedoff")
+            };
+            ctx.reply(text).await?;
+            return Ok(());
+        }
+        "on" | "yes" => true,
+        "off" | "no" => false,
+        _ => return ctx.fail(lang_fmt!(ctx, "cleanlinkedinvalid")),
+    };
+
+    ctx.check_permissions(|p| p.can_delete_messages.and(p.can_change_info))
+        .await?;
+    set_clean_linked(chat, clean).await?;
+    let text = if clean {
+        lang_fmt!(ctx, "cleanlinkedon")
+    } else {
+        lang_fmt!(ctx, "cleanlinkedoff")
+    };
+    ctx.confirm(text).await?;
+    Ok(())
+}
+
+async fn cleanservice<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat;
+    let words = args.text.split_whitespace().collect::<Vec<&str>>();
+    let mask = match words.as_slice() {
+        [] => {
+            let mask = dialog_or_default(chat).await?.clean_service;
+            let text = if mask == 0 {
+                lang_fmt!(ctx, "cleanserviceoff")
+            } else {
+                lang_fmt!(ctx, "cleanservicecurrent", mask_names(mask))
+            };
+            ctx.reply(text).await?;
+            return Ok(());
+        }
+        ["off" | "no"] => 0,
+        ["all" | "on" | "yes"] => ServiceKind::ALL.iter().fold(0, |m, kind| m | kind.bit()),
+        kinds => {
+            let mut mask = 0;
+            for kind in kinds {
+                match ServiceKind::from_name(kind) {
+                    Some(kind) => mask |= kind.bit(),
+                    None => return ctx.fail(lang_fmt!(ctx, "cleanserviceinvalid", kind)),
+                }
+            }
+            mask
+        }
+    };
+
+    ctx.check_permissions(|p| p.can_delete_messages.and(p.can_change_info))
+        .await?;
+    set_clean_service(chat, mask).await?;
+    let text = if mask == 0 {
+        lang_fmt!(ctx, "cleanserviceoff")
+    } else {
+        lang_fmt!(ctx, "cleanservicecurrent", mask_names(mask))
+    };
+    ctx.confirm(text).await?;
+    Ok(())
+}
+
+/// Deletes service messages of the kinds the chat chose. Runs after greetings so welcomes
+/// replying to a join message are sent before it is deleted, and lets the update continue
+/// so modules watching joins still see it. Posts from the linked channel are deleted too if
+/// the chat asked for it
+pub struct Enforce;
+
+#[async_trait::async_trait]
+impl Middleware for Enforce {
+    fn name(&self) -> &'static str {
+        "clean service"
+    }
+
+    fn priority(&self) -> i32 {
+        20
+    }
+
+    async fn before(&self, ctx: &Context) -> Result<Flow> {
+        if let UpdateExt::Message(ref message) = ctx.update() {
+            if let Some(kind) = ServiceKind::of(message) {
+                let mask = get_dialog(message.get_chat())
+                    .await?
+                    .map(|dialog| dialog.clean_service)
+                    .unwrap_or(0);
+                if mask & kind.bit() != 0 {
+                    message.delete().await?;
+                }
+            } else if message.get_is_automatic_forward().unwrap_or(false) {
+                let clean = get_dialog(message.get_chat())
+                    .await?
+                    .map(|dialog| dialog.clean_linked)
+                    .unwrap_or(false);
+                if clean {
+                    message.delete().await?;
+                    return Ok(Flow::Stop);
+                }
+            }
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
+        match cmd {
+            "cleanservice" => cleanservice(ctx, args).await,
+            "cleanlinked" => cleanlinked(ctx, args).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler(middleware = Enforce)]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+
+    Ok(())
+}
@@ -2,18 +2,22 @@ use crate::persist::admin::{fbans, federations};
 use crate::persist::core::users;
 use crate::statics::{DB, TG};
 use crate::tg::admin_helpers::{FileGetter, StrOption};
+use crate::tg::appeals::{offer_appeal, AppealTarget};
 use crate::tg::command::{Cmd, Context, TextArgs};
+use crate::tg::confirm::confirmed;
 use crate::tg::federations::{
-    create_federation, fban_user, fstat, get_fed, get_feds, is_fedadmin, is_fedmember, join_fed,
-    subfed, try_update_fban_cache, update_fed,
+    count_fed_chats, create_federation, fban_user, fstat, get_fban, get_fed, get_feds, is_fedadmin,
+    is_fedmember, join_fed, subfed, try_update_fban_cache, update_fed,
 };
 use crate::tg::permissions::IsGroupAdmin;
 use crate::tg::user::{GetUser, Username};
 use crate::util::error::{BotError, Fail, Result, SpeakErr};
+use crate::util::error_codes::{NOT_FOUND, UNSUPPORTED_MEDIA};
 use crate::util::string::should_ignore_chat;
 use crate::{metadata::metadata, util::string::Speak};
 use botapi::bot::Part;
-use botapi::gen_types::{FileData, Message};
+use botapi::gen_types::{FileData, Message, User};
+use futures::FutureExt;
 use itertools::Itertools;
 use macros::{entity_fmt, lang_fmt, update_handler};
 use sea_orm::ActiveValue::{NotSet, Set};
@@ -32,18 +36,41 @@ metadata!("Federations",
     in that federation. Federations can subscribe to other federations to receive their bans \(but not
     their actual ban list \)
     "#,
-    { command = "fban", help = "Bans a user in the current chat's federation" },
+    { command = "fban", help = "Bans a user in the current chat's federation. Add \\-\\-dryrun to see what the ban would affect first" },
     { command = "joinfed", help = "Joins a chat to a federation. Only one fed per chat" },
     { command = "newfed", help = "Create a new federation with yourself as the owner" },
     { command = "myfeds", help = "Get a list of feds you are either the owner or admin of" },
     { command = "fpromote", help = "Promote another user as fedadmin. They need to click the message sent to confirm the promotion" },
     { command = "unfban", help = "Unban a user in the current chat's federation" },
     { command = "renamefed", help = "Rename your federation" },
-    { command = "subfed", help = "Usage: subfed \\<uuid\\>: subscribes your federation to a new fed's id" },
+    { command = "subfed", help = "Subscribes your federation to a new fed's id.", usage = "subfedusage" },
     { command = "fedimport", help = "Import a list of fbans to your current federation using Rose bot's json format" },
     { command = "fedexport", help = "Export your federation's fbans in Rose bot's json format" }
 );
 
+/// Tells the admin what an fban would do without banning anyone
+async fn fban_dry_run(ctx: &Context, user: &User, fed: &Uuid) -> Result<()> {
+    let chats = count_fed_chats(fed).await?;
+    let text = if get_fban(user.get_id(), fed).await?.is_some() {
+        entity_fmt!(
+            ctx,
+            "fbandryrunexisting",
+            user.mention().await?,
+            fed.to_string()
+        )
+    } else {
+        entity_fmt!(
+            ctx,
+            "fbandryrun",
+            user.mention().await?,
+            fed.to_string(),
+            chats.to_string()
+        )
+    };
+    ctx.reply_fmt(text).await?;
+    Ok(())
+}
+
 async fn fban(ctx: &Context) -> Result<()> {
     if ctx.message()?.get_sender_chat().is_some() {
         return ctx.fail(lang_fmt!(ctx, "anonban"));
@@ -56,12 +83,23 @@ async fn fban(ctx: &Context) -> Result<()> {
                 if is_fedadmin(user.get_id(), &fed).await?
                     || ctx.check_permissions(|p| p.is_support).await.is_ok()
                 {
+                    if args.as_ref().is_some_and(|args| args.matches().dry_run()) {
+                        return fban_dry_run(ctx, &user, &fed).await;
+                    }
+                    if !confirmed(ctx, |ctx| async move { fban(&ctx).await }.boxed()).await? {
+                        return Ok(());
+                    }
                     let mut model = fbans::Model::new(&user, fed);
                     model.reason = args
                         .map(|v| v.text.trim().to_owned())
                         .and_then(|v| (!v.is_empty()).then_some(v));
                     let reason = model.reason.clone();
                     fban_user(model, &user).await?;
+                    if let Err(err) =
+                        offer_appeal(user.get_id(), AppealTarget::Fed(fed), reason.as_deref()).await
+                    {
+                        log::debug!("failed to offer appeal to {}: {}", user.get_id(), err);
+                    }
                     if let Some(reason) = reason {
                         ctx.reply_fmt(entity_fmt!(
                             ctx,
@@ -221,13 +259,9 @@ async fn subfed_cmd<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
     let chat = ctx.try_get()?.chat.get_id();
     if let Some(user) = ctx.message()?.get_from() {
         let sub = Uuid::parse_str(args.text)?;
-        let fed = get_fed(user.get_id()).await?.ok_or_else(|| {
-            BotError::speak(
-                "You currently do not have a fed",
-                chat,
-                Some(message.message_id),
-            )
-        })?;
+        let fed = get_fed(user.get_id())
+            .await?
+            .ok_or_else(|| ctx.fail_err_code(NOT_FOUND, lang_fmt!(ctx, "nofed")))?;
         subfed(&fed.fed_id, &sub).await?;
         ctx.reply(lang_fmt!(ctx, "subscribefed", fed.fed_id, sub))
             .await?;
@@ -338,7 +372,7 @@ async fn set_fban_list(ctx: &Context, fed: &Uuid, message: &Message) -> Result<u
 
         Ok(res)
     } else {
-        ctx.fail("Message is not a file")
+        ctx.fail_code(UNSUPPORTED_MEDIA, lang_fmt!(ctx, "errnotfile"))
     }
 }
 
@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 use crate::metadata::metadata;
 use crate::metadata::ModuleHelpers;
@@ -13,22 +14,30 @@ use crate::statics::DB;
 use crate::statics::REDIS;
 use crate::tg::button::InlineKeyboardBuilder;
 use crate::tg::command::*;
+use crate::tg::extract::{CanChangeInfo, CommandArgs, InGroup, RequirePerm};
 use crate::tg::markdown::get_markup_for_buttons;
 use crate::tg::markdown::Header;
 use crate::tg::markdown::MarkupBuilder;
 use crate::tg::markdown::MarkupType;
 use crate::tg::permissions::*;
+use crate::tg::url_guard::check_button_urls;
+use crate::tg::user::Username;
 use crate::util::error::BotError;
 use crate::util::error::Fail;
 use crate::util::error::Result;
+use crate::util::error_codes::MISSING_ARGUMENT;
 use crate::util::string::AlignCharBoundry;
 use crate::util::string::Speak;
+use crate::util::triggers::{
+    next_generation, MatcherCache, TriggerMatcher, TriggerPattern, GENERATION_FIELD,
+};
 use botapi::gen_types::Message;
 use botapi::gen_types::MessageEntity;
 use entities::{filters, triggers};
 use futures::FutureExt;
 use itertools::Itertools;
 use lazy_static::__Deref;
+use lazy_static::lazy_static;
 use macros::entity_fmt;
 use macros::lang_fmt;
 use macros::update_handler;
@@ -54,11 +63,13 @@ metadata!("Filters",
     { command = "filter", help = "\\<trigger\\> \\<reply\\>: Trigger a reply when soemone says something" },
     { command = "filters", help = "List all filters" },
     { command = "stop", help = "Stop a filter" },
-    { command = "stopall", help = "Stop all filters" }
+    { command = "stopall", help = "Stop all filters" },
+    { command = "copyfilters", help = "Copy filters from another chat you are an admin of, keeping triggers this chat already has.", usage = "copyfiltersusage", perms = [CanChangeInfo] }
 );
 
 struct Migration;
 struct MigrationEntityInDb;
+struct MigrationEntityIndex;
 
 impl MigrationName for Migration {
     fn name(&self) -> &str {
@@ -72,6 +83,12 @@ impl MigrationName for MigrationEntityInDb {
     }
 }
 
+impl MigrationName for MigrationEntityIndex {
+    fn name(&self) -> &str {
+        "m20261019_000003_filters_entity_index"
+    }
+}
+
 pub mod entities {
     use crate::persist::{core::entity, migrate::ManagerHelper};
     use ::sea_orm_migration::prelude::*;
@@ -213,6 +230,34 @@ pub mod entities {
         }
     }
 
+    #[async_trait::async_trait]
+    impl MigrationTrait for super::MigrationEntityIndex {
+        async fn up(&self, manager: &SchemaManager) -> std::result::Result<(), DbErr> {
+            manager
+                .create_index(
+                    IndexCreateStatement::new()
+                        .name("filters_entity_index")
+                        .table(filters::Entity)
+                        .col(filters::Column::EntityId)
+                        .to_owned(),
+                )
+                .await?;
+            Ok(())
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> std::result::Result<(), DbErr> {
+            manager
+                .drop_index(
+                    IndexDropStatement::new()
+                        .table(filters::Entity)
+                        .name("filters_entity_index")
+                        .to_owned(),
+                )
+                .await?;
+            Ok(())
+        }
+    }
+
     pub mod triggers {
         use sea_orm::entity::prelude::*;
         use serde::{Deserialize, Serialize};
@@ -251,14 +296,12 @@ pub mod entities {
         use super::triggers;
         use crate::{
             persist::core::{
-                button, entity,
-                media::*,
-                messageentity::{self, DbMarkupType, EntityWithUser},
-                users,
+                button, entity::get_entity_groups, media::*, messageentity::EntityWithUser,
             },
+            persist::db::ReadReplica,
             statics::DB,
         };
-        use sea_orm::{entity::prelude::*, FromQueryResult, QueryOrder, QuerySelect};
+        use sea_orm::{entity::prelude::*, FromQueryResult, QuerySelect};
         use sea_query::{IntoCondition, JoinType};
         use serde::{Deserialize, Serialize};
 
@@ -311,104 +354,29 @@ pub mod entities {
         impl ActiveModelBehavior for ActiveModel {}
 
         #[derive(FromQueryResult)]
-        struct FiltersWithEntities {
+        struct FilterWithTrigger {
             //filter fields
-            pub id: Option<i64>,
-            pub chat: Option<i64>,
+            pub id: i64,
+            pub chat: i64,
             pub text: Option<String>,
             pub media_id: Option<String>,
-            pub media_type: Option<MediaType>,
+            pub media_type: MediaType,
             pub entity_id: Option<i64>,
 
-            //button fields
-            pub button_text: Option<String>,
-            pub callback_data: Option<String>,
-            pub button_url: Option<String>,
-            pub pos_x: Option<i32>,
-            pub pos_y: Option<i32>,
-            pub raw_text: Option<String>,
-
-            // entity fields
-            pub tg_type: Option<DbMarkupType>,
-            pub offset: Option<i64>,
-            pub length: Option<i64>,
-            pub url: Option<String>,
-            pub user: Option<i64>,
-            pub language: Option<String>,
-            pub emoji_id: Option<String>,
-
-            // user fields
-            pub user_id: Option<i64>,
-            pub first_name: Option<String>,
-            pub last_name: Option<String>,
-            pub username: Option<String>,
-            pub is_bot: Option<bool>,
-
             // trigger fields
             pub trigger: Option<String>,
             pub filter_id: Option<i64>,
         }
 
-        impl FiltersWithEntities {
-            fn get(
-                self,
-            ) -> (
-                Option<Model>,
-                Option<button::Model>,
-                Option<EntityWithUser>,
-                Option<triggers::Model>,
-            ) {
-                let button = if let (Some(button_text), Some(owner_id), Some(pos_x), Some(pos_y)) =
-                    (self.button_text, self.entity_id, self.pos_x, self.pos_y)
-                {
-                    Some(button::Model {
-                        button_text,
-                        owner_id: Some(owner_id),
-                        callback_data: self.callback_data,
-                        button_url: self.button_url,
-                        pos_x,
-                        pos_y,
-                        raw_text: self.raw_text,
-                    })
-                } else {
-                    None
-                };
-
-                let filter = if let (Some(id), Some(chat), Some(media_type)) =
-                    (self.id, self.chat, self.media_type)
-                {
-                    Some(Model {
-                        id,
-                        chat,
-                        media_type,
-                        text: self.text,
-                        media_id: self.media_id,
-                        entity_id: self.entity_id,
-                    })
-                } else {
-                    None
-                };
-
-                let entity = if let (Some(tg_type), Some(offset), Some(length), Some(owner_id)) =
-                    (self.tg_type, self.offset, self.length, self.entity_id)
-                {
-                    Some(EntityWithUser {
-                        tg_type,
-                        offset,
-                        length,
-                        url: self.url,
-                        language: self.language,
-                        emoji_id: self.emoji_id,
-                        user: self.user,
-                        owner_id,
-                        user_id: self.user_id,
-                        first_name: self.first_name,
-                        last_name: self.last_name,
-                        username: self.username,
-                        is_bot: self.is_bot,
-                    })
-                } else {
-                    None
+        impl FilterWithTrigger {
+            fn get(self) -> (Model, Option<triggers::Model>) {
+                let filter = Model {
+                    id: self.id,
+                    chat: self.chat,
+                    media_type: self.media_type,
+                    text: self.text,
+                    media_id: self.media_id,
+                    entity_id: self.entity_id,
                 };
 
                 let trigger =
@@ -418,7 +386,7 @@ pub mod entities {
                         None
                     };
 
-                (filter, button, entity, trigger)
+                (filter, trigger)
             }
         }
 
@@ -445,69 +413,37 @@ pub mod entities {
                     Column::MediaType,
                     Column::EntityId,
                 ])
-                .columns([
-                    messageentity::Column::TgType,
-                    messageentity::Column::Offset,
-                    messageentity::Column::Length,
-                    messageentity::Column::Url,
-                    messageentity::Column::User,
-                    messageentity::Column::Language,
-                    messageentity::Column::EmojiId,
-                    messageentity::Column::OwnerId,
-                ])
                 .columns([triggers::Column::Trigger, triggers::Column::FilterId])
-                .columns([
-                    button::Column::ButtonText,
-                    button::Column::CallbackData,
-                    button::Column::ButtonUrl,
-                    button::Column::PosX,
-                    button::Column::PosY,
-                    button::Column::RawText,
-                ])
-                .column_as(button::Column::OwnerId, "b_owner_id")
-                .columns([
-                    users::Column::UserId,
-                    users::Column::FirstName,
-                    users::Column::LastName,
-                    users::Column::Username,
-                    users::Column::IsBot,
-                ])
-                .join(JoinType::LeftJoin, Relation::Entities.def())
-                .join(JoinType::LeftJoin, entity::Relation::EntitiesRev.def())
-                .join(JoinType::LeftJoin, entity::Relation::ButtonsRev.def())
-                .join(JoinType::LeftJoin, messageentity::Relation::Users.def())
                 .join(JoinType::LeftJoin, Relation::Triggers.def())
                 .filter(filter)
-                .order_by_asc(button::Column::PosX)
-                .order_by_asc(button::Column::PosY)
-                .into_model::<FiltersWithEntities>()
-                .all(*DB)
+                .into_model::<FilterWithTrigger>()
+                .all(DB.read())
                 .await?;
 
-            let res = res.into_iter().map(|v| v.get()).fold(
-                FiltersMap::new(),
-                |mut acc, (filter, button, entity, trigger)| {
-                    if let Some(filter) = filter {
-                        let (entitylist, buttonlist, triggerlist) = acc
-                            .entry(filter)
-                            .or_insert_with(|| (HashSet::new(), HashSet::new(), HashSet::new()));
-
-                        if let Some(button) = button {
-                            buttonlist.insert(button);
-                        }
-
-                        if let Some(entity) = entity {
-                            entitylist.insert(entity);
-                        }
-
-                        if let Some(trigger) = trigger {
-                            triggerlist.insert(trigger);
-                        }
+            let filters = res.into_iter().map(|v| v.get()).fold(
+                HashMap::<Model, HashSet<triggers::Model>>::new(),
+                |mut acc, (filter, trigger)| {
+                    let triggerlist = acc.entry(filter).or_default();
+                    if let Some(trigger) = trigger {
+                        triggerlist.insert(trigger);
                     }
                     acc
                 },
             );
 
+            let mut groups = get_entity_groups(
+                DB.read(),
+                filters.keys().filter_map(|filter| filter.entity_id),
+            )
+            .await?;
+            let res = filters
+                .into_iter()
+                .map(|(filter, triggerlist)| {
+                    let (entitylist, buttonlist) = groups.take(filter.entity_id);
+                    (filter, (entitylist, buttonlist, triggerlist))
+                })
+                .collect::<FiltersMap>();
+
             log::info!("got {} filters from db", res.len());
             Ok(res)
         }
@@ -515,7 +451,11 @@ pub mod entities {
 }
 
 pub fn get_migrations() -> Vec<Box<dyn MigrationTrait>> {
-    vec![Box::new(Migration), Box::new(MigrationEntityInDb)]
+    vec![
+        Box::new(Migration),
+        Box::new(MigrationEntityInDb),
+        Box::new(MigrationEntityIndex),
+    ]
 }
 
 #[derive(Debug)]
@@ -540,12 +480,16 @@ impl ModuleHelpers for Helper {
     }
 }
 
-fn get_filter_key(message: &Message, id: i64) -> String {
-    format!("filter:{}:{}", message.get_chat().get_id(), id)
+fn get_filter_key(chat: i64, id: i64) -> String {
+    format!("filter:{}:{}", chat, id)
 }
 
 fn get_filter_hash_key(message: &Message) -> String {
-    format!("fcache:{}", message.get_chat().get_id())
+    get_chat_filter_hash_key(message.get_chat().get_id())
+}
+
+fn get_chat_filter_hash_key(chat: i64) -> String {
+    format!("fcache:{}", chat)
 }
 
 async fn delete_trigger(ctx: &Context, trigger: &str) -> Result<()> {
@@ -562,8 +506,10 @@ async fn delete_trigger(ctx: &Context, trigger: &str) -> Result<()> {
                 .query(|mut q| async move {
                     let id: Option<i64> = q.hdel(&hash_key, trigger).await?;
                     if let Some(id) = id {
-                        let key = get_filter_key(message, id);
+                        let key = get_filter_key(message.get_chat().get_id(), id);
                         q.del(&key).await?;
+                        q.hset(&hash_key, GENERATION_FIELD, next_generation())
+                            .await?;
                     }
                     Ok(())
                 })
@@ -608,7 +554,7 @@ async fn delete_trigger(ctx: &Context, trigger: &str) -> Result<()> {
 }
 
 async fn get_filter(
-    message: &Message,
+    chat: i64,
     id: i64,
 ) -> Result<
     Option<(
@@ -617,7 +563,7 @@ async fn get_filter(
         Option<InlineKeyboardBuilder>,
     )>,
 > {
-    let filter_key = get_filter_key(message, id);
+    let filter_key = get_filter_key(chat, id);
     let v: Option<RedisStr> = REDIS.sq(|q| q.get(&filter_key)).await?;
     if let Some(v) = v {
         log::info!("cache hit");
@@ -643,7 +589,7 @@ async fn get_filter(
             REDIS
                 .try_pipe(|p| {
                     Ok(p.set(&filter_key, map.to_redis()?)
-                        .expire(&filter_key, CONFIG.timing.cache_timeout))
+                        .expire(&filter_key, CONFIG.load().timing.cache_timeout))
                 })
                 .await?;
         }
@@ -651,8 +597,51 @@ async fn get_filter(
     }
 }
 
+lazy_static! {
+    static ref MATCHERS: MatcherCache<TriggerMatcher<i64>> = MatcherCache::default();
+}
+
+/// Whether a trigger found at byte `idx` of a message stands apart from the words around it
+fn is_trigger_match(text: &str, key: &str, mut idx: usize) -> bool {
+    if idx == 0 && idx + key.len() == text.len() {
+        return true;
+    }
+    if idx == 0 {
+        idx = 1;
+    }
+    let mut keylen = if key.len() + 1 < text.len() {
+        key.len() + idx
+    } else {
+        text.len() - 1
+    };
+
+    idx = text.align_char_boundry(idx - 1);
+
+    keylen = text.align_char_boundry(keylen);
+
+    let ws = &text[idx..keylen];
+    ws.starts_with(|c: char| c.is_whitespace()) || ws.ends_with(|c: char| c.is_whitespace())
+}
+
+/// Gets the chat's triggers compiled for matching, loading them into the cache if needed
+async fn get_matcher(chat: i64) -> Result<Option<Arc<TriggerMatcher<i64>>>> {
+    update_cache_from_db(chat).await?;
+    let hash_key = get_chat_filter_hash_key(chat);
+    MATCHERS
+        .get(&hash_key, || async {
+            let triggers: HashMap<String, i64> = REDIS.sq(|q| q.hgetall(&hash_key)).await?;
+            TriggerMatcher::new(
+                triggers
+                    .into_iter()
+                    .filter(|(trigger, _)| trigger != GENERATION_FIELD)
+                    .map(|(trigger, id)| (TriggerPattern::Literal(trigger), id)),
+            )
+        })
+        .await
+}
+
 async fn search_cache(
-    message: &Message,
+    chat: i64,
     text: &str,
 ) -> Result<
     Option<(
@@ -661,55 +650,31 @@ async fn search_cache(
         Option<InlineKeyboardBuilder>,
     )>,
 > {
-    update_cache_from_db(message).await?;
-    let hash_key = get_filter_hash_key(message);
-    REDIS
-        .query(|mut q| async move {
-            let mut iter: redis::AsyncIter<(String, i64)> = q.hscan(&hash_key).await?;
-            while let Some((key, item)) = iter.next_item().await {
-                log::info!("search cache {}", item);
-                let t = text.to_lowercase();
-                if let Some(mut idx) = t.find(&key) {
-                    if idx == 0 && idx + key.len() == text.len() {
-                        return get_filter(message, item).await;
-                    }
-                    if idx == 0 {
-                        idx = 1;
-                    }
-                    let mut keylen = if key.len() + 1 < text.len() {
-                        key.len() + idx
-                    } else {
-                        text.len() - 1
-                    };
-
-                    idx = text.align_char_boundry(idx - 1);
-
-                    keylen = text.align_char_boundry(keylen);
-
-                    let ws = &text[idx..keylen];
-                    if ws.starts_with(|c: char| c.is_whitespace())
-                        || ws.ends_with(|c: char| c.is_whitespace())
-                    {
-                        return get_filter(message, item).await;
-                    }
-                }
+    let Some(matcher) = get_matcher(chat).await? else {
+        return Ok(None);
+    };
+    let t = text.to_lowercase();
+    for (key, item, idx) in matcher.matches(&t) {
+        log::info!("search cache {}", item);
+        if let Some(idx) = idx {
+            if is_trigger_match(text, key, idx) {
+                return get_filter(chat, *item).await;
             }
-            Ok(None)
-        })
-        .await
+        }
+    }
+    Ok(None)
 }
 
-async fn update_cache_from_db(message: &Message) -> Result<()> {
-    let hash_key = get_filter_hash_key(message);
+async fn update_cache_from_db(chat: i64) -> Result<()> {
+    let hash_key = get_chat_filter_hash_key(chat);
     if !REDIS.sq(|q| q.exists(&hash_key)).await? {
-        let res = filters::get_filters_join(filters::Column::Chat.eq(message.get_chat().get_id()))
-            .await?;
+        let res = filters::get_filters_join(filters::Column::Chat.eq(chat)).await?;
 
         REDIS
             .try_pipe(|p| {
-                p.hset(&hash_key, "", 0);
+                p.hset(&hash_key, GENERATION_FIELD, next_generation());
                 for (filter, (entities, buttons, triggers)) in res.into_iter() {
-                    let key = get_filter_key(message, filter.id);
+                    let key = get_filter_key(chat, filter.id);
                     log::info!("triggers {}", triggers.len());
                     let kb = get_markup_for_buttons(buttons.into_iter().collect());
                     let entities = entities
@@ -718,10 +683,10 @@ async fn update_cache_from_db(message: &Message) -> Result<()> {
                         .map(|(k, v)| k.to_entity(v))
                         .collect_vec();
                     p.set(&key, (&filter, entities, kb).to_redis()?)
-                        .expire(&key, CONFIG.timing.cache_timeout);
+                        .expire(&key, CONFIG.load().timing.cache_timeout);
                     for trigger in triggers.iter() {
                         p.hset(&hash_key, trigger.trigger.to_owned(), filter.id)
-                            .expire(&hash_key, CONFIG.timing.cache_timeout);
+                            .expire(&hash_key, CONFIG.load().timing.cache_timeout);
                     }
                 }
                 Ok(p)
@@ -748,9 +713,9 @@ async fn command_filter<'a>(c: &Context, args: &TextArgs<'a>) -> Result<()> {
                     .build_filter()
                     .await;
 
-                let filters = match header
-                    .ok_or_else(|| ctx.fail_err("Header missing from filter command"))?
-                {
+                let filters = match header.ok_or_else(|| {
+                    ctx.fail_err_code(MISSING_ARGUMENT, lang_fmt!(ctx, "errfilterheader"))
+                })? {
                     Header::List(st) => st,
                     Header::Arg(st) => vec![st],
                 };
@@ -790,8 +755,9 @@ async fn command_filter<'a>(c: &Context, args: &TextArgs<'a>) -> Result<()> {
                     .into_tuple()
                     .all(tx)
                     .await?;
-                let (id, media_type) = get_media_type(message)?;
+                let (id, media_type) = get_media_type(message).await?;
 
+                check_button_urls(ctx.message()?, &buttons).await?;
                 let entity_id = entity::insert(tx, &entities, buttons.clone()).await?;
                 let model = filters::ActiveModel {
                     id: ActiveValue::NotSet,
@@ -814,6 +780,7 @@ async fn command_filter<'a>(c: &Context, args: &TextArgs<'a>) -> Result<()> {
                             filters::Column::Chat,
                             filters::Column::MediaId,
                             filters::Column::MediaType,
+                            filters::Column::EntityId,
                         ])
                         .to_owned(),
                     )
@@ -841,7 +808,7 @@ async fn command_filter<'a>(c: &Context, args: &TextArgs<'a>) -> Result<()> {
                 .exec(tx)
                 .await?;
 
-                let key = get_filter_key(message, model.id);
+                let key = get_filter_key(message.get_chat().get_id(), model.id);
                 let model_id = model.id;
 
                 let hash_key = get_filter_hash_key(message);
@@ -854,7 +821,7 @@ async fn command_filter<'a>(c: &Context, args: &TextArgs<'a>) -> Result<()> {
                         for trigger in triggers {
                             p.hset(&hash_key, trigger, model_id);
                         }
-                        p
+                        p.hset(&hash_key, GENERATION_FIELD, next_generation())
                     })
                     .await?;
 
@@ -878,8 +845,11 @@ async fn command_filter<'a>(c: &Context, args: &TextArgs<'a>) -> Result<()> {
 
 async fn handle_trigger(ctx: &Context) -> Result<()> {
     let message = ctx.message()?;
+    let Some(chat) = ctx.settings_chat() else {
+        return Ok(());
+    };
     if let Some(text) = message.get_text() {
-        if let Some((res, extra_entities, extra_buttons)) = search_cache(message, text).await? {
+        if let Some((res, extra_entities, extra_buttons)) = search_cache(chat, text).await? {
             SendMediaReply::new(ctx, res.media_type)
                 .button_callback(|_, _| async move { Ok(()) }.boxed())
                 .text(res.text)
@@ -895,7 +865,7 @@ async fn handle_trigger(ctx: &Context) -> Result<()> {
 
 async fn list_triggers(message: &Message) -> Result<()> {
     let hash_key = get_filter_hash_key(message);
-    update_cache_from_db(message).await?;
+    update_cache_from_db(message.get_chat().get_id()).await?;
     let res: Option<HashMap<String, i64>> = REDIS.sq(|q| q.hgetall(&hash_key)).await?;
     if let Some(map) = res {
         let vals = map
@@ -925,6 +895,125 @@ async fn stopall(ctx: &Context) -> Result<()> {
     Ok(())
 }
 
+/// Copies every filter from one chat to another. Triggers the destination already has are
+/// left alone, and filters with none of their triggers left are skipped. Returns how many
+/// filters were copied and skipped
+async fn copy_filters(from: i64, to: i64) -> Result<(usize, usize)> {
+    let existing: HashSet<String> = triggers::Entity::find()
+        .select_only()
+        .column(triggers::Column::Trigger)
+        .join(
+            sea_query::JoinType::InnerJoin,
+            triggers::Relation::Filters.def(),
+        )
+        .filter(filters::Column::Chat.eq(to))
+        .into_tuple::<String>()
+        .all(*DB)
+        .await?
+        .into_iter()
+        .collect();
+    let source = filters::get_filters_join(filters::Column::Chat.eq(from)).await?;
+    let total = source.len();
+
+    let copied = DB
+        .deref()
+        .transaction::<_, usize, BotError>(move |tx| {
+            async move {
+                let mut copied = 0;
+                for (filter, (entities, buttons, filter_triggers)) in source {
+                    let filter_triggers = filter_triggers
+                        .into_iter()
+                        .map(|t| t.trigger)
+                        .filter(|t| !existing.contains(t))
+                        .collect_vec();
+                    if filter_triggers.is_empty() {
+                        continue;
+                    }
+
+                    let entities = entities
+                        .into_iter()
+                        .map(|e| e.get())
+                        .map(|(e, u)| e.to_entity(u))
+                        .collect_vec();
+                    let buttons = get_markup_for_buttons(buttons.into_iter().collect());
+                    let entity_id =
+                        entity::insert(tx, &entities, buttons.unwrap_or_default()).await?;
+                    let model = filters::ActiveModel {
+                        id: ActiveValue::NotSet,
+                        chat: ActiveValue::Set(to),
+                        text: ActiveValue::Set(filter.text),
+                        media_id: ActiveValue::Set(filter.media_id),
+                        media_type: ActiveValue::Set(filter.media_type),
+                        entity_id: ActiveValue::Set(entity_id),
+                    };
+                    let model = filters::Entity::insert(model)
+                        .on_conflict(
+                            OnConflict::columns([
+                                filters::Column::Text,
+                                filters::Column::Chat,
+                                filters::Column::MediaId,
+                            ])
+                            .update_columns([
+                                filters::Column::Text,
+                                filters::Column::Chat,
+                                filters::Column::MediaId,
+                                filters::Column::MediaType,
+                                filters::Column::EntityId,
+                            ])
+                            .to_owned(),
+                        )
+                        .exec_with_returning(tx)
+                        .await?;
+
+                    triggers::Entity::insert_many(filter_triggers.into_iter().map(|trigger| {
+                        triggers::Model {
+                            trigger,
+                            filter_id: model.id,
+                        }
+                        .into_active_model()
+                    }))
+                    .on_conflict(
+                        OnConflict::columns([
+                            triggers::Column::Trigger,
+                            triggers::Column::FilterId,
+                        ])
+                        .update_columns([triggers::Column::Trigger, triggers::Column::FilterId])
+                        .to_owned(),
+                    )
+                    .exec(tx)
+                    .await?;
+                    copied += 1;
+                }
+                Ok(copied)
+            }
+            .boxed()
+        })
+        .await?;
+
+    let key = get_chat_filter_hash_key(to);
+    REDIS.sq(|q| q.del(&key)).await?;
+    Ok((copied, total - copied))
+}
+
+async fn copy_filters_cmd(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanChangeInfo>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let from = other_chat_or_die(ctx, args.text).await?;
+    let (copied, skipped) = copy_filters(from.get_id(), ctx.try_get()?.chat.get_id()).await?;
+    ctx.reply(lang_fmt!(
+        ctx,
+        "copiedfilters",
+        copied,
+        from.name_humanreadable(),
+        skipped
+    ))
+    .await?;
+    Ok(())
+}
+
 async fn handle_command(ctx: &Context) -> Result<()> {
     if let Some(&Cmd {
         cmd,
@@ -938,6 +1027,7 @@ async fn handle_command(ctx: &Context) -> Result<()> {
             "stop" => delete_trigger(ctx, args.text).await?,
             "filters" => list_triggers(message).await?,
             "stopall" => stopall(ctx).await?,
+            "copyfilters" => ctx.run(copy_filters_cmd).await?,
             _ => handle_trigger(ctx).await?,
         };
     } else if ctx.message().is_ok() {
@@ -0,0 +1,279 @@
+use crate::metadata::metadata;
+use crate::persist::core::dialogs;
+use crate::statics::{DB, ME, TG};
+use crate::tg::command::{Cmd, Context, TextArgs};
+use crate::tg::dialog::{dialog_or_default, dialog_scope};
+use crate::tg::extract::{CommandArgs, InGroup};
+use crate::tg::markdown::pick_variant;
+use crate::tg::permissions::{BotPermissions, IsGroupAdmin};
+use crate::tg::settings::{Setting, SettingKind, SettingValue, SettingsProvider};
+use crate::tg::user::{resolve_user_target, GetUser};
+use crate::util::error::{BotError, Fail, Result};
+use crate::util::error_codes::INVALID_ARGUMENT;
+use crate::util::string::{should_ignore_chat, Confirm, Speak};
+use async_trait::async_trait;
+use botapi::gen_types::{Chat, ReplyParametersBuilder};
+use macros::{entity_fmt, lang_fmt, update_handler};
+use rand::Rng;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::EntityTrait;
+
+metadata!("Fun",
+    r#"
+    Silly commands for passing the time. Admins who'd rather keep the chat serious can turn
+    them off with /fun off.
+
+    /roll on its own throws a die, /roll darts, basketball, football, bowling, or slots
+    throws something else. /roll 2d6 rolls any number of dice with any number of sides
+    "#,
+    { command = "roll", help = "Roll a die.", usage = "rollusage" },
+    { command = "flip", help = "Flip a coin" },
+    { command = "8ball", help = "Ask the magic 8 ball a question" },
+    { command = "slap", help = "Slap someone.", usage = "slapusage" },
+    { command = "fun", help = "Turn the fun commands on or off.", usage = "funusage" }
+);
+
+/// Most dice /roll NdM can roll at once
+const MAX_DICE: u32 = 20;
+
+/// Most sides a die rolled with /roll NdM can have
+const MAX_SIDES: u32 = 1000;
+
+/// Emoji telegram animates with sendDice
+fn dice_emoji(name: &str) -> Option<&'static str> {
+    match name {
+        "" | "dice" | "die" => Some("🎲"),
+        "darts" => Some("🎯"),
+        "basketball" => Some("🏀"),
+        "football" | "soccer" => Some("⚽"),
+        "bowling" => Some("🎳"),
+        "slots" => Some("🎰"),
+        _ => None,
+    }
+}
+
+/// Parses dice notation like 2d6 or d20 into the number of dice and their sides
+fn parse_dice(text: &str) -> Option<(u32, u32)> {
+    let (count, sides) = text.to_lowercase().split_once('d').map(|(count, sides)| {
+        (
+            if count.is_empty() {
+                Some(1)
+            } else {
+                count.parse().ok()
+            },
+            sides.parse().ok(),
+        )
+    })?;
+    match (count?, sides?) {
+        (count @ 1..=MAX_DICE, sides @ 1..=MAX_SIDES) => Some((count, sides)),
+        _ => None,
+    }
+}
+
+/// Turns the fun commands off or back on
+async fn set_fun_disabled(chat: &Chat, disabled: bool) -> Result<()> {
+    let mut model = dialogs::Model::from_chat(chat).await?;
+    model.disable_fun = Set(disabled);
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_column(dialogs::Column::DisableFun)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat.get_id()).invalidate().await?;
+    Ok(())
+}
+
+async fn roll<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    let name = args.text.trim();
+    if let Some(emoji) = dice_emoji(name) {
+        let message = ctx.message()?;
+        let chat = message.get_chat().get_id();
+        if !should_ignore_chat(chat).await? {
+            TG.client
+                .build_send_dice(chat)
+                .emoji(emoji)
+                .reply_parameters(&ReplyParametersBuilder::new(message.get_message_id()).build())
+                .build()
+                .await?;
+        }
+        return Ok(());
+    }
+
+    let Some((count, sides)) = parse_dice(name) else {
+        return ctx.fail_code(
+            INVALID_ARGUMENT,
+            lang_fmt!(ctx, "invaliddice", MAX_DICE, MAX_SIDES),
+        );
+    };
+    let rolls = {
+        let mut rng = rand::thread_rng();
+        (0..count)
+            .map(|_| rng.gen_range(1..=sides))
+            .collect::<Vec<u32>>()
+    };
+    let total = rolls.iter().sum::<u32>();
+    let rolls = rolls
+        .iter()
+        .map(|roll| roll.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    ctx.reply(lang_fmt!(ctx, "diceroll", name, rolls, total))
+        .await?;
+    Ok(())
+}
+
+async fn flip(ctx: &Context) -> Result<()> {
+    let text = if rand::thread_rng().gen_bool(0.5) {
+        lang_fmt!(ctx, "coinheads")
+    } else {
+        lang_fmt!(ctx, "cointails")
+    };
+    ctx.reply(text).await?;
+    Ok(())
+}
+
+async fn eightball<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    if args.text.trim().is_empty() {
+        return ctx.fail(lang_fmt!(ctx, "eightballquestion"));
+    }
+    let (answer, _) = pick_variant(lang_fmt!(ctx, "eightballanswers"), Vec::new());
+    ctx.reply(lang_fmt!(ctx, "eightball", answer)).await?;
+    Ok(())
+}
+
+async fn slap(ctx: &Context) -> Result<()> {
+    let from = ctx.get_real_from()?;
+    let target = match resolve_user_target(ctx).await {
+        Err(BotError::UserNotFound) => return ctx.fail(lang_fmt!(ctx, "usernotfound")),
+        res => res?.0,
+    };
+    let (slapper, target) = match target {
+        Some(target) if target.get_id() != from.get_id() => {
+            (from.mention().await?, target.get_id().mention().await?)
+        }
+        // slapping nobody or yourself gets you slapped by the bot
+        _ => (ME.get().unwrap().mention().await?, from.mention().await?),
+    };
+    let (action, _) = pick_variant(lang_fmt!(ctx, "slapactions"), Vec::new());
+    let (object, _) = pick_variant(lang_fmt!(ctx, "slapobjects"), Vec::new());
+    ctx.reply_fmt(entity_fmt!(ctx, "slap", slapper, action, target, object))
+        .await?;
+    Ok(())
+}
+
+async fn fun(ctx: &Context, _: InGroup, CommandArgs(args): CommandArgs<'_>) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let disabled = match args.text.trim() {
+        "" => {
+            let text = if dialog_or_default(chat).await?.disable_fun {
+                lang_fmt!(ctx, "funoff")
+            } else {
+                lang_fmt!(ctx, "funon")
+            };
+            ctx.reply(text).await?;
+            return Ok(());
+        }
+        "on" | "yes" => false,
+        "off" | "no" => true,
+        _ => return ctx.usage_err("funusage"),
+    };
+
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    set_fun_disabled(chat, disabled).await?;
+    let text = if disabled {
+        lang_fmt!(ctx, "funoff")
+    } else {
+        lang_fmt!(ctx, "funon")
+    };
+    ctx.confirm(text).await?;
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
+        if cmd == "fun" {
+            return ctx.run(fun).await;
+        }
+        if !matches!(cmd, "roll" | "flip" | "8ball" | "slap") {
+            return Ok(());
+        }
+        if let Some(chat) = ctx.chat() {
+            if dialog_or_default(chat).await?.disable_fun {
+                return Ok(());
+            }
+        }
+        match cmd {
+            "roll" => roll(ctx, args).await,
+            "flip" => flip(ctx).await,
+            "8ball" => eightball(ctx, args).await,
+            "slap" => slap(ctx).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+/// Shows whether the fun commands are on in the /settings panel
+pub struct Settings;
+
+#[async_trait]
+impl SettingsProvider for Settings {
+    fn module(&self) -> &'static str {
+        "fun"
+    }
+
+    fn title(&self) -> &'static str {
+        "Fun"
+    }
+
+    fn allowed(&self, permissions: &BotPermissions) -> bool {
+        permissions.can_change_info
+    }
+
+    async fn get_settings(&self, chat: &Chat) -> Result<Vec<Setting>> {
+        let dialog = dialog_or_default(chat).await?;
+        Ok(vec![Setting {
+            id: "enabled".to_owned(),
+            name: "Fun commands".to_owned(),
+            kind: SettingKind::Toggle(!dialog.disable_fun),
+        }])
+    }
+
+    async fn set_setting(&self, chat: &Chat, setting: &str, value: SettingValue) -> Result<()> {
+        match (setting, value) {
+            ("enabled", SettingValue::Toggle(enabled)) => set_fun_disabled(chat, !enabled).await,
+            _ => Err(BotError::generic(format!(
+                "invalid fun setting {}",
+                setting
+            ))),
+        }
+    }
+}
+
+#[update_handler(settings = Settings)]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dice_notation() {
+        assert_eq!(parse_dice("2d6"), Some((2, 6)));
+        assert_eq!(parse_dice("D20"), Some((1, 20)));
+        assert_eq!(parse_dice("0d6"), None);
+        assert_eq!(parse_dice("2d0"), None);
+        assert_eq!(parse_dice("100d6"), None);
+        assert_eq!(parse_dice("2x6"), None);
+        assert_eq!(parse_dice("darts"), None);
+    }
+}
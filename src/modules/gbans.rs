@@ -1,11 +1,11 @@
-use macros::{lang_fmt, update_handler};
+use macros::update_handler;
 
 use crate::persist::admin::gbans;
 use crate::tg::command::{Cmd, Context};
 use crate::tg::federations::gban_user;
 use crate::tg::permissions::IsGroupAdmin;
-use crate::tg::user::GetUser;
-use crate::util::error::{BotError, Result, SpeakErr};
+use crate::tg::user::resolve_user;
+use crate::util::error::Result;
 use crate::{metadata::metadata, util::string::Speak};
 
 metadata!("Global Bans",
@@ -19,47 +19,21 @@ metadata!("Global Bans",
 
 async fn ungban(ctx: &Context) -> Result<()> {
     ctx.check_permissions(|p| p.is_support).await?;
-    ctx.action_user(|ctx, user, _| async move {
-        if let Some(user) = user.get_cached_user().await? {
-            ctx.ungban_user(user.get_id()).await?;
-            ctx.reply("user ungbanned").await?;
-        } else {
-            ctx.reply("user not found").await?;
-        }
-
-        Ok(())
-    })
-    .await
-    .speak_err_raw(ctx, |v| match v {
-        BotError::UserNotFound => Some(lang_fmt!(ctx, "failuser", "ungban")),
-        _ => None,
-    })
-    .await?;
+    let (user, _) = resolve_user(ctx).await?;
+    ctx.ungban_user(user.get_id()).await?;
+    ctx.reply("user ungbanned").await?;
     Ok(())
 }
+
 async fn gban(ctx: &Context) -> Result<()> {
     ctx.check_permissions(|p| p.is_support).await?;
-    ctx.action_user(|ctx, user, args| async move {
-        if let Some(user) = user.get_cached_user().await? {
-            let mut model = gbans::Model::new(user.get_id());
-
-            model.reason = args
-                .map(|v| v.text.trim().to_owned())
-                .and_then(|v| (!v.is_empty()).then_some(v));
-            gban_user(model, user).await?;
-            ctx.reply("user gbanned").await?;
-        } else {
-            ctx.reply("user not found").await?;
-        }
-
-        Ok(())
-    })
-    .await
-    .speak_err_raw(ctx, |v| match v {
-        BotError::UserNotFound => Some(lang_fmt!(ctx, "failuser", "gban")),
-        _ => None,
-    })
-    .await?;
+    let (user, args) = resolve_user(ctx).await?;
+    let mut model = gbans::Model::new(user.get_id());
+    model.reason = args
+        .map(|v| v.text.trim().to_owned())
+        .and_then(|v| (!v.is_empty()).then_some(v));
+    gban_user(model, user).await?;
+    ctx.reply("user gbanned").await?;
     Ok(())
 }
 
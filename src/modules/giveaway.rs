@@ -0,0 +1,141 @@
+use crate::metadata::metadata;
+use crate::tg::command::{Cmd, Context};
+use crate::tg::extract::{CanChangeInfo, CommandArgs, InGroup, RequirePerm};
+use crate::tg::giveaways::{
+    get_giveaway_by_message, last_giveaway, reroll_giveaway, start_giveaway, MAX_WINNERS,
+};
+use crate::util::duration::parse_duration;
+use crate::util::error::{Fail, Result};
+use crate::util::error_codes::{INVALID_ARGUMENT, NOT_FOUND};
+use chrono::Duration;
+use macros::{lang_fmt, update_handler};
+
+metadata!("Giveaways",
+    r#"
+    Give things away to members of the chat. /giveaway posts the prize with a button to
+    enter, and when the time is up the winners are drawn at random and announced. Each member
+    can enter once, and bots can't enter at all. For more than one winner, put how many after
+    the duration:
+    /giveaway 1d a signed copy of the book
+    /giveaway 12h winners=3 stickers of your choice
+
+    If a winner doesn't claim their prize, reply to the giveaway with /reroll to draw someone
+    else. Without a reply the chat's last giveaway is rerolled
+    "#,
+    { command = "giveaway", help = "Start a giveaway.", usage = "giveawayusage", perms = [CanChangeInfo] },
+    { command = "reroll", help = "Draw new winners for a giveaway that ended.", usage = "rerollusage", perms = [CanChangeInfo] }
+);
+
+/// Splits /giveaway's arguments into how long it runs, how many winners are drawn, and the
+/// prize
+fn parse_giveaway(text: &str) -> Option<(Duration, i32, String)> {
+    let mut words = text.split_whitespace().peekable();
+    let duration = parse_duration(words.next()?).ok()?;
+    let winners = match words.peek().and_then(|word| word.strip_prefix("winners=")) {
+        Some(winners) => {
+            let winners = winners.parse::<i32>().ok()?;
+            words.next();
+            winners
+        }
+        None => 1,
+    };
+    let prize = words.collect::<Vec<&str>>().join(" ");
+    (duration > Duration::zero() && (1..=MAX_WINNERS).contains(&winners) && !prize.is_empty())
+        .then_some((duration, winners, prize))
+}
+
+async fn giveaway(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanChangeInfo>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    if args.text.trim().is_empty() {
+        return ctx.usage_err("giveawayusage");
+    }
+    let Some((duration, winners, prize)) = parse_giveaway(args.text) else {
+        return ctx.fail_code(
+            INVALID_ARGUMENT,
+            lang_fmt!(ctx, "giveawayinvalid", MAX_WINNERS),
+        );
+    };
+    let chat = ctx.try_get()?.chat.get_id();
+    let author = ctx.get_real_from()?.get_id();
+    start_giveaway(chat, ctx.lang(), author, duration, winners, prize).await?;
+    Ok(())
+}
+
+async fn reroll(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanChangeInfo>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let count = match args.text.trim() {
+        "" => 1,
+        count => match count.parse::<i32>() {
+            Ok(count) if (1..=MAX_WINNERS).contains(&count) => count,
+            _ => {
+                return ctx.fail_code(
+                    INVALID_ARGUMENT,
+                    lang_fmt!(ctx, "giveawaybadcount", MAX_WINNERS),
+                )
+            }
+        },
+    };
+    let chat = ctx.try_get()?.chat.get_id();
+    let giveaway = match ctx.message()?.get_reply_to_message() {
+        Some(reply) => get_giveaway_by_message(chat, reply.get_message_id()).await?,
+        None => last_giveaway(chat).await?,
+    };
+    let Some(giveaway) = giveaway else {
+        return ctx.fail_code(NOT_FOUND, lang_fmt!(ctx, "giveawaynotfound"));
+    };
+    if !giveaway.ended {
+        return ctx.fail(lang_fmt!(ctx, "giveawaynotended"));
+    }
+    reroll_giveaway(&giveaway, count as usize).await?;
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+        match cmd {
+            "giveaway" => ctx.run(giveaway).await,
+            "reroll" => ctx.run(reroll).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn giveaway_args() {
+        assert_eq!(
+            parse_giveaway("1d a signed copy"),
+            Some((
+                Duration::try_days(1).unwrap(),
+                1,
+                "a signed copy".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse_giveaway("12h winners=3 stickers"),
+            Some((Duration::try_hours(12).unwrap(), 3, "stickers".to_owned()))
+        );
+        assert_eq!(parse_giveaway("1d"), None);
+        assert_eq!(parse_giveaway("soon a prize"), None);
+        assert_eq!(parse_giveaway("1d winners=0 a prize"), None);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::metadata::metadata;
+use crate::tg::birthdays::{
+    get_birthday_settings, parse_birthday, remove_birthday, set_birthday, set_birthday_greeting,
+    set_birthdays_enabled,
+};
+use crate::tg::command::{Cmd, Context, TextArgs};
+use crate::tg::permissions::IsGroupAdmin;
+use crate::util::error::{Fail, Result};
+use crate::util::string::{Confirm, Speak};
+use crate::util::time::ChatTime;
+use chrono::Month;
+use macros::{lang_fmt, update_handler};
+
+metadata!("Birthdays",
+    r#"
+    Members can register their birthday and if enabled by an admin a greeting is posted in the
+    chat on that day. Greetings support the same formatting and fillings as welcome messages,
+    for example \{mention\} or \{first\}. Days start at midnight in the chat's timezone, see /settz.
+    "#,
+    { command = "setbday", help = "Register your birthday in this chat.", usage = "setbdayusage" },
+    { command = "rmbday", help = "Remove your birthday from this chat" },
+    { command = "birthdays", help = "Enable or disable birthday greetings.", usage = "birthdaysusage" },
+    { command = "bdaygreeting", help = "Set the birthday greeting, or reset it to the default without arguments" }
+);
+
+async fn setbday<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    let user = ctx.get_real_from()?.get_id();
+    let Some((month, day)) = parse_birthday(args.text) else {
+        return ctx.fail(lang_fmt!(ctx, "invalidbday"));
+    };
+    set_birthday(chat, user, month, day).await?;
+    let month = Month::try_from(month as u8)
+        .map(|m| m.name())
+        .unwrap_or_default();
+    ctx.confirm(lang_fmt!(ctx, "setbday", month, day)).await?;
+    Ok(())
+}
+
+async fn rmbday(ctx: &Context) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    let user = ctx.get_real_from()?.get_id();
+    if !remove_birthday(chat, user).await? {
+        return ctx.fail(lang_fmt!(ctx, "nobday"));
+    }
+    ctx.confirm(lang_fmt!(ctx, "rmbday")).await?;
+    Ok(())
+}
+
+async fn birthdays<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    match args.text.trim() {
+        "" => {
+            let enabled = get_birthday_settings(chat)
+                .await?
+                .map(|s| s.enabled)
+                .unwrap_or(false);
+            let enabled = if enabled { "on" } else { "off" };
+            let tz = ChatTime::get(chat).await?.tz();
+            ctx.reply(lang_fmt!(ctx, "bdaystatus", enabled, tz.name()))
+                .await?;
+        }
+        "on" | "yes" => {
+            ctx.check_permissions(|p| p.can_change_info).await?;
+            set_birthdays_enabled(chat, true).await?;
+            ctx.confirm(lang_fmt!(ctx, "bdayson")).await?;
+        }
+        "off" | "no" => {
+            ctx.check_permissions(|p| p.can_change_info).await?;
+            set_birthdays_enabled(chat, false).await?;
+            ctx.confirm(lang_fmt!(ctx, "bdaysoff")).await?;
+        }
+        _ => return ctx.usage_err("birthdaysusage"),
+    }
+    Ok(())
+}
+
+async fn bdaygreeting<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    let greeting = args.text.trim();
+    if greeting.is_empty() {
+        set_birthday_greeting(chat, None).await?;
+        ctx.confirm(lang_fmt!(ctx, "resetbdaygreeting")).await?;
+    } else {
+        set_birthday_greeting(chat, Some(greeting.to_owned())).await?;
+        ctx.confirm(lang_fmt!(ctx, "setbdaygreeting")).await?;
+    }
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
+        match cmd {
+            "setbday" => setbday(ctx, args).await,
+            "rmbday" => rmbday(ctx).await,
+            "birthdays" => birthdays(ctx, args).await,
+            "bdaygreeting" => bdaygreeting(ctx, args).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+
+    Ok(())
+}
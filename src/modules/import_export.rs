@@ -17,16 +17,17 @@ use crate::tg::markdown::EntityMessage;
 use crate::tg::permissions::IsGroupAdmin;
 use crate::tg::user::Username;
 use crate::util::error::{Fail, Result};
+use crate::util::error_codes::UNKNOWN_USER;
 use crate::util::string::{should_ignore_chat, Speak};
 
-use super::{all_export, all_import};
+use super::{all_export, all_import, plan_import};
 
 metadata!("Import/Export",
     r#"
     Import and export data from select modules in a format compatible with a certain feminine
     flower-based bot on telegram.
     "#,
-    { command = "import", help = "Import data for the current chat" },
+    { command = "import", help = "Import data for the current chat. Add \\-\\-dryrun to see what would be replaced first" },
     { command = "export", help = "Export data for the current chat"}
 );
 
@@ -106,7 +107,7 @@ async fn get_taint_menu(ctx: &Context) -> Result<()> {
         message
             .get_from()
             .map(|u| u.get_id())
-            .ok_or_else(|| message.fail_err("User does not exist"))?,
+            .ok_or_else(|| ctx.fail_err_code(UNKNOWN_USER, lang_fmt!(ctx, "errunknownuser")))?,
         "button",
     )?;
 
@@ -145,6 +146,26 @@ async fn update_taint<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
     Ok(())
 }
 
+/// Tells the admin which of the chat's data an import would replace, without importing
+async fn import_dry_run(ctx: &Context, json: &str) -> Result<()> {
+    let (replaced, ignored) = plan_import(json)?;
+    let mut text = if replaced.is_empty() {
+        lang_fmt!(ctx, "importdryrunempty")
+    } else {
+        lang_fmt!(ctx, "importdryrun")
+    };
+    for (section, count) in replaced {
+        text.push('\n');
+        text.push_str(&lang_fmt!(ctx, "importdryrunsection", section, count));
+    }
+    if !ignored.is_empty() {
+        text.push('\n');
+        text.push_str(&lang_fmt!(ctx, "importdryrunignored", ignored.join(", ")));
+    }
+    ctx.reply(text).await?;
+    Ok(())
+}
+
 #[update_handler]
 pub async fn handle_update(ctx: &Context) -> Result<()> {
     if let Some(&Cmd {
@@ -171,10 +192,13 @@ pub async fn handle_update(ctx: &Context) -> Result<()> {
             "import" => {
                 ctx.check_permissions(|p| p.can_change_info.and(p.can_restrict_members))
                     .await?;
-                ctx.action_message(|ctx, message, _| async move {
+                ctx.action_message(|ctx, message, args| async move {
                     let message = message.message();
                     if let Some(file) = message.get_document() {
                         let text = file.get_text().await?;
+                        if args.map(|args| args.matches().dry_run()).unwrap_or(false) {
+                            return import_dry_run(ctx, &text).await;
+                        }
                         all_import(message.get_chat().get_id(), &text).await?;
                         let taint = taint::Entity::find()
                             .filter(taint::Column::Chat.eq(message.get_chat().get_id()))
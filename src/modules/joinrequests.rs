@@ -0,0 +1,88 @@
+use crate::metadata::metadata;
+use crate::persist::core::dialogs::JoinPolicy;
+use crate::tg::command::{Cmd, Context, TextArgs};
+use crate::tg::dialog::dialog_or_default;
+use crate::tg::join_requests::{handle_join_request, set_join_policy};
+use crate::tg::permissions::IsGroupAdmin;
+use crate::util::error::{Fail, Result};
+use crate::util::string::{Confirm, Speak};
+use botapi::gen_types::UpdateExt;
+use macros::{lang_fmt, update_handler};
+
+metadata!("Join Requests",
+    r#"
+    Handle requests to join chats that require admin approval to join. Choose one policy:
+    [*off]: leave requests for admins to handle in telegram
+    [*all]: approve every request
+    [*age \<days\>]: approve accounts older than the given number of days, other requests are
+    posted for admins. Account age is estimated from the account's id
+    [*captcha]: the bot sends a captcha to the user and approves them once it is solved
+    [*admins]: post every request with approve and decline buttons
+
+    Requests for admins are posted in the log channel if one is set, otherwise in the chat
+    "#,
+    { command = "joinpolicy", help = "Set the join request policy.", usage = "joinpolicyusage" }
+);
+
+fn policy_name(policy: JoinPolicy, min_age: Option<i32>) -> String {
+    match policy {
+        JoinPolicy::Off => "off".to_owned(),
+        JoinPolicy::ApproveAll => "all".to_owned(),
+        JoinPolicy::MinAge => format!("age {}", min_age.unwrap_or(0)),
+        JoinPolicy::Captcha => "captcha".to_owned(),
+        JoinPolicy::Admins => "admins".to_owned(),
+    }
+}
+
+async fn joinpolicy<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat;
+    let mut words = args.text.split_whitespace();
+    let (policy, min_age) = match (words.next(), words.next()) {
+        (None, _) => {
+            let dialog = dialog_or_default(chat).await?;
+            let name = policy_name(dialog.join_policy, dialog.join_min_age);
+            ctx.reply(lang_fmt!(ctx, "currentjoinpolicy", name)).await?;
+            return Ok(());
+        }
+        (Some("off"), None) => (JoinPolicy::Off, None),
+        (Some("all"), None) => (JoinPolicy::ApproveAll, None),
+        (Some("age"), Some(days)) => match days.parse::<i32>() {
+            Ok(days) if days > 0 => (JoinPolicy::MinAge, Some(days)),
+            _ => return ctx.fail(lang_fmt!(ctx, "invalidjoinage")),
+        },
+        (Some("captcha"), None) => (JoinPolicy::Captcha, None),
+        (Some("admins"), None) => (JoinPolicy::Admins, None),
+        _ => return ctx.fail(lang_fmt!(ctx, "invalidjoinpolicy")),
+    };
+
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    set_join_policy(chat, policy, min_age).await?;
+    ctx.confirm(lang_fmt!(
+        ctx,
+        "setjoinpolicy",
+        policy_name(policy, min_age)
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
+        match cmd {
+            "joinpolicy" => joinpolicy(ctx, args).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    if let UpdateExt::ChatJoinRequest(request) = cmd.update() {
+        handle_join_request(cmd, request).await?;
+    }
+    handle_command(cmd).await?;
+
+    Ok(())
+}
@@ -1,9 +1,20 @@
 use crate::statics::TG;
-use crate::tg::command::{Cmd, Context};
+use crate::tg::admin_helpers::is_dm;
+use crate::tg::birthdays::{get_birthday_settings, schedule_birthdays};
+use crate::tg::bots;
+use crate::tg::command::{Cmd, Context, PopSlice, TextArgs};
+use crate::tg::extract::{CanChangeInfo, CommandArgs, InGroup, RequirePerm};
+use crate::tg::permissions::IsGroupAdmin;
+use crate::tg::recurring::reschedule_chat;
 
 use crate::tg::user::{GetChat, RecordChat};
-use crate::util::error::BotError;
-use crate::util::string::{set_chat_lang, should_ignore_chat, Lang, Speak};
+use crate::util::error::{BotError, Fail};
+use crate::util::error_codes::NOT_FOUND;
+use crate::util::string::{
+    count_placeholders, get_chat_lang, get_custom_strings, reset_custom_string, set_chat_lang,
+    set_custom_string, should_ignore_chat, Confirm, Lang, Speak,
+};
+use crate::util::time::{parse_timezone, set_chat_tz, ChatTime};
 use crate::{
     metadata::metadata,
     tg::dialog::{Conversation, ConversationState},
@@ -18,9 +29,18 @@ use uuid::Uuid;
 metadata! {
     "Language",
     r#"This bot supports automatic translations! Set the language for the current chat
-    using this module
+    using this module. The chat's timezone can also be set here, it is used when showing
+    or entering times for warns, bans, scheduled commands, birthdays, and logs.
+
+    Admins can also replace any of my messages with their own text. Messages are named by
+    the same keys used in the translation files, like needtobeadmin for the message shown to
+    users missing admin rights
     "#,
-    { command = "setlang", help = "Set languge" }
+    { command = "setlang", help = "Set languge" },
+    { command = "settz", help = "Set the timezone for this chat.", usage = "settzusage" },
+    { command = "setstring", help = "Replace one of my messages in this chat with your own text, {} marks where values like names go.", usage = "setstringusage", perms = [CanChangeInfo] },
+    { command = "resetstring", help = "Go back to the default text for a message.", usage = "resetstringusage", perms = [CanChangeInfo] },
+    { command = "strings", help = "List the messages replaced in this chat" }
 }
 
 inline_lang! {
@@ -33,10 +53,13 @@ async fn handle_terminal_state(
     chat: i64,
     reply: i64,
 ) -> Result<()> {
-    let chat = chat
-        .get_chat()
-        .await?
-        .ok_or_else(|| BotError::speak("Chat not found", chat, Some(reply)))?;
+    let Some(chat) = chat.get_chat().await? else {
+        let lang = get_chat_lang(chat).await?;
+        return Err(
+            BotError::speak(lang_fmt!(lang, "errchatnotfound"), chat, Some(reply))
+                .with_code(NOT_FOUND),
+        );
+    };
     if let Some(state) = conv.get_state(&current) {
         let lang = Lang::from_code(&state.content);
 
@@ -63,7 +86,7 @@ async fn get_lang_conversation(message: &Message, current: &Lang) -> Result<Conv
         message.get_chat().get_id(),
         message.get_from().map(|u| u.get_id()).ok_or_else(|| {
             BotError::speak(
-                "user is not a user... what",
+                lang_fmt!(current, "errunknownuser"),
                 message.get_chat().get_id(),
                 Some(message.message_id),
             )
@@ -83,7 +106,7 @@ async fn get_lang_conversation(message: &Message, current: &Lang) -> Result<Conv
     state.state_callback(move |uuid, conv| {
         log::info!("conversation state {}", uuid);
         if uuid != start {
-            tokio::spawn(async move {
+            bots::spawn(async move {
                 if let Err(err) = handle_terminal_state(uuid, conv, id, message_id).await {
                     log::warn!("terminal state error {}", err);
                     err.record_stats();
@@ -97,29 +120,123 @@ async fn get_lang_conversation(message: &Message, current: &Lang) -> Result<Conv
     Ok(state)
 }
 
+async fn settz<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let name = args.text.trim();
+    if name.is_empty() {
+        let tz = ChatTime::get(chat.get_id()).await?.tz();
+        ctx.reply(lang_fmt!(ctx, "currenttz", tz.name())).await?;
+        return Ok(());
+    }
+
+    if !is_dm(chat) {
+        ctx.check_permissions(|p| p.can_change_info).await?;
+    }
+    let Some(tz) = parse_timezone(name) else {
+        return ctx.fail(lang_fmt!(ctx, "invalidtz", name));
+    };
+    set_chat_tz(chat, tz).await?;
+    if let Some(settings) = get_birthday_settings(chat.get_id()).await? {
+        schedule_birthdays(&settings).await?;
+    }
+    reschedule_chat(chat.get_id()).await?;
+    ctx.confirm(lang_fmt!(ctx, "settz", tz.name())).await?;
+    Ok(())
+}
+
+async fn setlang(message: &Message, lang: &Lang) -> Result<()> {
+    let conv = get_lang_conversation(message, lang).await?;
+
+    if should_ignore_chat(message.get_chat().get_id()).await? {
+        return Ok(());
+    }
+    TG.client()
+        .build_send_message(
+            message.get_chat().get_id(),
+            &conv.get_current().await?.content,
+        )
+        .reply_markup(&botapi::gen_types::EReplyMarkup::InlineKeyboardMarkup(
+            conv.get_current_markup(3).await?,
+        ))
+        .build()
+        .await?;
+    Ok(())
+}
+
+async fn setstring(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanChangeInfo>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let Some((key, value)) = args.pop_slice().filter(|(_, v)| !v.text.is_empty()) else {
+        return ctx.usage_err("setstringusage");
+    };
+    let key = key.get_text();
+    let Some(default) = ctx.lang().get_string(key) else {
+        return ctx.fail(lang_fmt!(ctx, "invalidstringkey", key));
+    };
+    let expected = count_placeholders(default);
+    if count_placeholders(value.text) > expected {
+        return ctx.fail(lang_fmt!(ctx, "stringtoomanyargs", key, expected));
+    }
+    set_custom_string(ctx.try_get()?.chat.get_id(), key, value.text.to_owned()).await?;
+    ctx.reply(lang_fmt!(ctx, "setstring", key)).await?;
+    Ok(())
+}
+
+async fn resetstring(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanChangeInfo>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let key = args.text.trim();
+    if key.is_empty() {
+        return ctx.usage_err("resetstringusage");
+    }
+    if reset_custom_string(ctx.try_get()?.chat.get_id(), key).await? {
+        ctx.reply(lang_fmt!(ctx, "resetstring", key)).await?;
+    } else {
+        ctx.reply(lang_fmt!(ctx, "stringnotset", key)).await?;
+    }
+    Ok(())
+}
+
+async fn strings(ctx: &Context, _: InGroup) -> Result<()> {
+    let strings = get_custom_strings(ctx.try_get()?.chat.get_id()).await?;
+    if strings.is_empty() {
+        ctx.reply(lang_fmt!(ctx, "nocustomstrings")).await?;
+        return Ok(());
+    }
+    let mut strings = strings.into_iter().collect::<Vec<_>>();
+    strings.sort();
+    let list = strings
+        .into_iter()
+        .map(|(key, value)| format!("{}: {}", key, value))
+        .collect::<Vec<String>>()
+        .join("\n");
+    ctx.reply(lang_fmt!(ctx, "customstrings", list)).await?;
+    Ok(())
+}
+
 async fn handle_command(ctx: &Context) -> Result<()> {
     if let Some(&Cmd {
-        cmd: "setlang",
+        cmd,
         message,
         lang,
+        ref args,
         ..
     }) = ctx.cmd()
     {
-        let conv = get_lang_conversation(message, lang).await?;
-
-        if should_ignore_chat(message.get_chat().get_id()).await? {
-            return Ok(());
-        }
-        TG.client()
-            .build_send_message(
-                message.get_chat().get_id(),
-                &conv.get_current().await?.content,
-            )
-            .reply_markup(&botapi::gen_types::EReplyMarkup::InlineKeyboardMarkup(
-                conv.get_current_markup(3).await?,
-            ))
-            .build()
-            .await?;
+        match cmd {
+            "setlang" => setlang(message, lang).await,
+            "settz" => settz(ctx, args).await,
+            "setstring" => ctx.run(setstring).await,
+            "resetstring" => ctx.run(resetstring).await,
+            "strings" => ctx.run(strings).await,
+            _ => Ok(()),
+        }?;
     }
     Ok(())
 }
@@ -1,15 +1,23 @@
-use self::entities::{default_locks, locks};
+use self::entities::{default_locks, locks, topic_locks};
 use crate::metadata::ModuleHelpers;
 use crate::persist::admin::actions::ActionType;
 use crate::persist::redis::{default_cache_query, CachedQueryTrait, RedisCache};
 use crate::statics::{CONFIG, DB, REDIS};
 use crate::tg::admin_helpers::{ban_message, is_approved, UpdateHelpers};
+use crate::tg::boosts::has_booster_perks;
 use crate::tg::command::{Cmd, Context, TextArg, TextArgs};
 use crate::tg::dialog::is_chat_member;
+use crate::tg::middleware::{Flow, Middleware};
 use crate::tg::permissions::*;
+use crate::tg::settings::{Setting, SettingKind, SettingValue, SettingsProvider};
+use crate::tg::url_guard::{
+    get_link_rules, has_flagged_link, message_urls, normalize_domain, remove_link_rule,
+    set_link_rule,
+};
 use crate::tg::user::{get_user_username, Username};
-use crate::util::error::{BotError, Result};
-use crate::util::string::{get_chat_lang, Lang};
+use crate::util::error::{BotError, Fail, Result};
+use crate::util::error_codes::INVALID_ARGUMENT;
+use crate::util::string::{get_chat_lang, Confirm, Lang};
 use crate::{metadata::metadata, statics::TG, util::string::Speak};
 use botapi::gen_types::{Chat, Message, UpdateExt};
 use chrono::Duration;
@@ -21,7 +29,7 @@ use redis::AsyncCommands;
 use sea_orm::prelude::*;
 use sea_orm::sea_query::OnConflict;
 use sea_orm::ActiveValue::{NotSet, Set};
-use sea_orm::EntityTrait;
+use sea_orm::{ActiveEnum, EntityTrait, Iterable};
 use sea_orm_migration::{MigrationName, MigrationTrait};
 
 metadata!("Locks",
@@ -33,13 +41,22 @@ metadata!("Locks",
     { command = "lock", help = "Engage a lock" },
     { command = "unlock", help = "Disable a lock"},
     { command = "locks", help = "Get a list of active locks"},
-    { command = "lockaction", help = "Set the action when a user sends a locked item"}
+    { command = "lockaction", help = "Set the action when a user sends a locked item"},
+    { command = "lockinvitelinks", help = "Lock links to groups and channels.", usage = "lockinvitelinksusage" },
+    { command = "allowlink", help = "Allow links to a domain with the domains lock.", usage = "allowlinkusage" },
+    { command = "denylink", help = "Block links to a domain with the domains lock.", usage = "denylinkusage" },
+    { command = "rmlink", help = "Remove a domain from the link allowlist or denylist" },
+    { command = "linkdomains", help = "List allowed and denied link domains" },
+    { command = "topiclock", help = "Engage a lock only in the current forum topic" },
+    { command = "topicunlock", help = "Disable a lock engaged with /topiclock" },
+    { command = "topiclocks", help = "Get a list of locks engaged in the current forum topic" }
 );
 
 pub mod entities {
     use self::locks::LockAction;
     use super::Migration;
     use super::MigrationActionType;
+    use super::MigrationTopicLocks;
 
     use crate::persist::admin::actions::ActionType;
     use crate::persist::migrate::ManagerHelper;
@@ -154,6 +171,47 @@ pub mod entities {
         }
     }
 
+    #[async_trait::async_trait]
+    impl MigrationTrait for MigrationTopicLocks {
+        async fn up(&self, manager: &SchemaManager) -> std::result::Result<(), DbErr> {
+            manager
+                .create_table(
+                    Table::create()
+                        .table(topic_locks::Entity)
+                        .col(
+                            ColumnDef::new(topic_locks::Column::Chat)
+                                .big_integer()
+                                .not_null(),
+                        )
+                        .col(
+                            ColumnDef::new(topic_locks::Column::Thread)
+                                .big_integer()
+                                .not_null(),
+                        )
+                        .col(
+                            ColumnDef::new(topic_locks::Column::LockType)
+                                .integer()
+                                .not_null(),
+                        )
+                        .primary_key(
+                            IndexCreateStatement::new()
+                                .col(topic_locks::Column::Chat)
+                                .col(topic_locks::Column::Thread)
+                                .col(topic_locks::Column::LockType)
+                                .primary(),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+            Ok(())
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> std::result::Result<(), DbErr> {
+            manager.drop_table_auto(topic_locks::Entity).await?;
+            Ok(())
+        }
+    }
+
     pub mod default_locks {
 
         use sea_orm::entity::prelude::*;
@@ -219,6 +277,8 @@ pub mod entities {
             InviteLink,
             #[sea_orm(num_value = 11)]
             ExtUsers,
+            #[sea_orm(num_value = 12)]
+            Domain,
         }
 
         impl LockType {
@@ -235,6 +295,7 @@ pub mod entities {
                     Self::Sticker => "Stickers",
                     Self::InviteLink => "Links to groups or channels",
                     Self::ExtUsers => "Users not participating in this chat",
+                    Self::Domain => "Links to denied or unlisted domains",
                 }
             }
         }
@@ -266,10 +327,35 @@ pub mod entities {
         pub enum Relation {}
         impl ActiveModelBehavior for ActiveModel {}
     }
+
+    /// Locks engaged in a single forum topic, on top of the chat's own locks. These always
+    /// use the chat's default lock action
+    pub mod topic_locks {
+        use sea_orm::entity::prelude::*;
+        use serde::{Deserialize, Serialize};
+
+        use super::locks::LockType;
+
+        #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+        #[sea_orm(table_name = "topic_locks")]
+        pub struct Model {
+            #[sea_orm(primary_key)]
+            pub chat: i64,
+            #[sea_orm(primary_key)]
+            pub thread: i64,
+            #[sea_orm(primary_key)]
+            pub lock_type: LockType,
+        }
+
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+        pub enum Relation {}
+        impl ActiveModelBehavior for ActiveModel {}
+    }
 }
 
 pub struct Migration;
 pub struct MigrationActionType;
+pub struct MigrationTopicLocks;
 
 impl MigrationName for Migration {
     fn name(&self) -> &str {
@@ -283,6 +369,12 @@ impl MigrationName for MigrationActionType {
     }
 }
 
+impl MigrationName for MigrationTopicLocks {
+    fn name(&self) -> &str {
+        "m20261016_000002_create_topic_locks"
+    }
+}
+
 macro_rules! locks {
     ( $(
         $( lock!( $name:expr, $description:expr, $lock:expr, $predicate:expr ) )?
@@ -349,11 +441,11 @@ macro_rules! locks {
             cmd: &Option<&'a Cmd<'a>>,
             chat: i64,
         ) -> (Option<LockType>, Option<ActionType>) {
-            if let Some(&Cmd { ref args, message, .. }) = cmd {
+            if let Some(&Cmd { ref args, message, lang, .. }) = cmd {
                 let action = args
                     .args
                     .get(1)
-                    .map(|v| ActionType::from_str(v.get_text(), chat, message.message_id).ok())
+                    .map(|v| ActionType::from_str(v.get_text(), lang, chat, message.message_id).ok())
                     .flatten();
                 let arg = match args.args.first() {
                     $(
@@ -429,6 +521,7 @@ locks! {
     lock!("sticker", "Stickers", LockType::Sticker, |message| message.get_sticker().is_some());
     async_lock!("invitelink", "Invite Links", LockType::InviteLink, |message| is_invite(message));
     async_lock!("external_users", "External Users", LockType::ExtUsers, |message| is_out_of_chat_user(message));
+    async_lock!("domains", "Links to domains not allowed by /allowlink and /denylink", LockType::Domain, |message| is_flagged_link(message));
 
 }
 
@@ -438,14 +531,21 @@ pub(crate) fn get_lock_key(chat: i64, locktype: &LockType) -> String {
 }
 
 pub fn get_migrations() -> Vec<Box<dyn MigrationTrait>> {
-    vec![Box::new(Migration), Box::new(MigrationActionType)]
+    vec![
+        Box::new(Migration),
+        Box::new(MigrationActionType),
+        Box::new(MigrationTopicLocks),
+    ]
 }
 
 fn is_tg_link<T: AsRef<str>>(url: T) -> bool {
     let url = url.as_ref();
     let url = url.strip_prefix("http://").unwrap_or(url);
     let url = url.strip_prefix("https://").unwrap_or(url);
-    url.starts_with("t.me") || url.starts_with("tg://")
+    url.starts_with("t.me")
+        || url.starts_with("telegram.me")
+        || url.starts_with("telegram.dog")
+        || url.starts_with("tg://")
 }
 
 fn is_out_of_chat_user(message: &'_ Message) -> BoxFuture<'_, Result<bool>> {
@@ -491,28 +591,22 @@ fn is_out_of_chat_user(message: &'_ Message) -> BoxFuture<'_, Result<bool>> {
     .boxed()
 }
 
+fn is_flagged_link(message: &Message) -> BoxFuture<'_, Result<bool>> {
+    has_flagged_link(message).boxed()
+}
+
 fn is_invite(message: &Message) -> BoxFuture<'_, Result<bool>> {
     async move {
+        if message_urls(message).iter().any(is_tg_link) {
+            return Ok(true);
+        }
         if let Some(entities) = message.get_entities() {
             for entity in entities {
-                match entity.get_tg_type() {
-                    "text_link" => {
-                        if let Some(url) = entity.get_url() {
-                            return Ok(is_tg_link(url));
-                        }
-                    }
-                    "mention" => {
-                        if let Some(user) = message.get_text() {
-                            //TODO: cache this manybe?
-                            return Ok(TG.client.get_chat(user.to_owned()).await.is_ok());
-                        }
-                    }
-                    "url" => {
-                        if let Some(url) = message.get_text() {
-                            return Ok(is_tg_link(url));
-                        }
+                if entity.get_tg_type() == "mention" {
+                    if let Some(user) = message.get_text() {
+                        //TODO: cache this manybe?
+                        return Ok(TG.client.get_chat(user.to_owned()).await.is_ok());
                     }
-                    _ => (),
                 }
             }
         }
@@ -543,6 +637,53 @@ impl ModuleHelpers for Helper {
     }
 }
 
+/// Shows a toggle for every lock on the /settings panel
+pub struct Settings;
+
+#[async_trait::async_trait]
+impl SettingsProvider for Settings {
+    fn module(&self) -> &'static str {
+        "locks"
+    }
+
+    fn title(&self) -> &'static str {
+        "Locks"
+    }
+
+    fn allowed(&self, permissions: &BotPermissions) -> bool {
+        permissions.can_delete_messages && permissions.can_change_info
+    }
+
+    async fn get_settings(&self, chat: &Chat) -> Result<Vec<Setting>> {
+        let enabled = locks::Entity::find()
+            .filter(locks::Column::Chat.eq(chat.get_id()))
+            .all(*DB)
+            .await?;
+        let settings = LockType::iter()
+            .map(|locktype| Setting {
+                id: locktype.to_value().to_string(),
+                name: locktype.get_name().to_owned(),
+                kind: SettingKind::Toggle(enabled.iter().any(|lock| lock.lock_type == locktype)),
+            })
+            .collect();
+        Ok(settings)
+    }
+
+    async fn set_setting(&self, chat: &Chat, setting: &str, value: SettingValue) -> Result<()> {
+        let locktype = setting
+            .parse()
+            .ok()
+            .and_then(|v| LockType::try_from_value(&v).ok());
+        match (locktype, value) {
+            (Some(locktype), SettingValue::Toggle(true)) => set_lock(chat.get_id(), locktype).await,
+            (Some(locktype), SettingValue::Toggle(false)) => {
+                clear_lock(chat.get_id(), locktype).await
+            }
+            _ => Err(BotError::generic(format!("invalid lock {}", setting))),
+        }
+    }
+}
+
 async fn get_lock(message: &Message, locktype: LockType) -> Result<Option<locks::Model>> {
     let chat = message.get_chat().get_id();
     let key = get_lock_key(chat, &locktype);
@@ -551,14 +692,13 @@ async fn get_lock(message: &Message, locktype: LockType) -> Result<Option<locks:
             let res = locks::Entity::find_by_id((chat, locktype)).one(*DB).await?;
             Ok(res)
         },
-        Duration::try_seconds(CONFIG.timing.cache_timeout).unwrap(),
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
     )
     .query(&key, &())
     .await
 }
 
-async fn clear_lock(message: &Message, locktype: LockType) -> Result<()> {
-    let chat = message.get_chat().get_id();
+async fn clear_lock(chat: i64, locktype: LockType) -> Result<()> {
     let key = get_lock_key(chat, &locktype);
     locks::Entity::delete_by_id((chat, locktype))
         .exec(*DB)
@@ -567,10 +707,10 @@ async fn clear_lock(message: &Message, locktype: LockType) -> Result<()> {
     Ok(())
 }
 
-async fn set_lock(message: &Message, locktype: LockType) -> Result<()> {
-    let key = get_lock_key(message.get_chat().get_id(), &locktype);
+async fn set_lock(chat: i64, locktype: LockType) -> Result<()> {
+    let key = get_lock_key(chat, &locktype);
     let model = locks::ActiveModel {
-        chat: Set(message.get_chat().get_id()),
+        chat: Set(chat),
         lock_type: Set(locktype),
         lock_action: NotSet,
         reason: NotSet,
@@ -587,6 +727,91 @@ async fn set_lock(message: &Message, locktype: LockType) -> Result<()> {
     Ok(())
 }
 
+#[inline(always)]
+fn get_topic_locks_key(chat: i64, thread: i64) -> String {
+    format!("tlocks:{}:{}", chat, thread)
+}
+
+async fn get_topic_locks(chat: i64, thread: i64) -> Result<Vec<LockType>> {
+    let key = get_topic_locks_key(chat, thread);
+    default_cache_query(
+        |_, _| async move {
+            let locks = topic_locks::Entity::find()
+                .filter(topic_locks::Column::Chat.eq(chat))
+                .filter(topic_locks::Column::Thread.eq(thread))
+                .all(*DB)
+                .await?
+                .into_iter()
+                .map(|lock| lock.lock_type)
+                .collect::<Vec<LockType>>();
+            Ok(Some(locks))
+        },
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
+    )
+    .query(&key, &())
+    .await
+    .map(|v| v.unwrap_or_default())
+}
+
+async fn set_topic_lock(chat: i64, thread: i64, locktype: LockType) -> Result<()> {
+    let model = topic_locks::ActiveModel {
+        chat: Set(chat),
+        thread: Set(thread),
+        lock_type: Set(locktype),
+    };
+    topic_locks::Entity::insert(model)
+        .on_conflict(
+            OnConflict::columns([
+                topic_locks::Column::Chat,
+                topic_locks::Column::Thread,
+                topic_locks::Column::LockType,
+            ])
+            .update_column(topic_locks::Column::LockType)
+            .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+    REDIS
+        .sq(|q| q.del(&get_topic_locks_key(chat, thread)))
+        .await?;
+    Ok(())
+}
+
+async fn clear_topic_lock(chat: i64, thread: i64, locktype: LockType) -> Result<bool> {
+    let res = topic_locks::Entity::delete_by_id((chat, thread, locktype))
+        .exec(*DB)
+        .await?;
+    REDIS
+        .sq(|q| q.del(&get_topic_locks_key(chat, thread)))
+        .await?;
+    Ok(res.rows_affected > 0)
+}
+
+/// Gets the action for a lock engaged in the message's chat or in its forum topic, or None
+/// if the lock isn't engaged there
+async fn get_engaged_action(message: &Message, locktype: &LockType) -> Result<Option<ActionType>> {
+    let action = if let Some(lock) = get_lock(message, locktype.clone()).await? {
+        lock.lock_action
+    } else if let Some(thread) = message.thread() {
+        let chat = message.get_chat().get_id();
+        if get_topic_locks(chat, thread).await?.contains(locktype) {
+            None
+        } else {
+            return Ok(None);
+        }
+    } else {
+        return Ok(None);
+    };
+
+    if let Some(action) = action {
+        Ok(Some(action))
+    } else {
+        Ok(Some(
+            get_default_settings(message.get_chat()).await?.lock_action,
+        ))
+    }
+}
+
 #[inline(always)]
 fn get_default_key(chat: &Chat) -> String {
     format!("daction:{}", chat.get_id())
@@ -608,7 +833,7 @@ async fn get_default_settings(chat: &Chat) -> Result<default_locks::Model> {
                     .await?;
             Ok(Some(model))
         },
-        Duration::try_seconds(CONFIG.timing.cache_timeout).unwrap(),
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
     )
     .query(&key, &())
     .await
@@ -664,9 +889,9 @@ async fn handle_lock<'a>(message: &Message, cmd: &Option<&Cmd<'a>>, lang: &Lang)
         (Some(lock), None) => {
             let t = lock.get_name().to_owned();
 
-            set_lock(message, lock).await?;
+            set_lock(message.get_chat().get_id(), lock).await?;
             message
-                .reply(lang_fmt!(
+                .confirm(lang_fmt!(
                     lang,
                     "setlock",
                     t,
@@ -677,7 +902,7 @@ async fn handle_lock<'a>(message: &Message, cmd: &Option<&Cmd<'a>>, lang: &Lang)
         (Some(lock), Some(action)) => {
             let reply = lang_fmt!(lang, "setlockaction", action.get_name());
             set_lock_action(message, lock, action).await?;
-            message.reply(reply).await?;
+            message.confirm(reply).await?;
         }
         _ => {
             message.reply(lang_fmt!(lang, "locknotspec")).await?;
@@ -692,8 +917,10 @@ async fn handle_unlock<'a>(ctx: &Context, cmd: &Option<&Cmd<'a>>) -> Result<()>
     let lang = ctx.lang();
     if let (Some(lock), _) = locktype_from_args(cmd, message.get_chat().get_id()) {
         let name = lock.get_name().to_owned();
-        clear_lock(message, lock).await?;
-        message.reply(lang_fmt!(lang, "clearedlock", name)).await?;
+        clear_lock(message.get_chat().get_id(), lock).await?;
+        message
+            .confirm(lang_fmt!(lang, "clearedlock", name))
+            .await?;
     } else {
         message.reply(lang_fmt!(lang, "locknotspec")).await?;
     }
@@ -723,6 +950,74 @@ async fn handle_list(message: &Message) -> Result<()> {
     Ok(())
 }
 
+/// Gets the forum topic a topic lock command was sent in, failing outside of topics
+fn topic_or_die(ctx: &Context) -> Result<i64> {
+    match ctx.thread() {
+        Some(thread) => Ok(thread),
+        None => ctx.fail(lang_fmt!(ctx, "notopic")),
+    }
+}
+
+async fn handle_topic_lock<'a>(ctx: &Context, cmd: &Option<&Cmd<'a>>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_delete_messages.and(p.can_change_info))
+        .await?;
+    let thread = topic_or_die(ctx)?;
+    let chat = ctx.try_get()?.chat.get_id();
+    match locktype_from_args(cmd, chat) {
+        (Some(lock), _) => {
+            let name = lock.get_name().to_owned();
+            set_topic_lock(chat, thread, lock).await?;
+            ctx.confirm(lang_fmt!(ctx, "settopiclock", name)).await?;
+        }
+        _ => {
+            ctx.reply(lang_fmt!(ctx, "locknotspec")).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_topic_unlock<'a>(ctx: &Context, cmd: &Option<&Cmd<'a>>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_restrict_members).await?;
+    let thread = topic_or_die(ctx)?;
+    let chat = ctx.try_get()?.chat.get_id();
+    match locktype_from_args(cmd, chat) {
+        (Some(lock), _) => {
+            let name = lock.get_name().to_owned();
+            if clear_topic_lock(chat, thread, lock).await? {
+                ctx.confirm(lang_fmt!(ctx, "clearedtopiclock", name))
+                    .await?;
+            } else {
+                return ctx.fail(lang_fmt!(ctx, "notopiclock", name));
+            }
+        }
+        _ => {
+            ctx.reply(lang_fmt!(ctx, "locknotspec")).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_topic_list(ctx: &Context) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_restrict_members).await?;
+    let thread = topic_or_die(ctx)?;
+    let chat = ctx.try_get()?.chat.get_id();
+    let locks = get_topic_locks(chat, thread).await?;
+    if locks.is_empty() {
+        ctx.reply(lang_fmt!(ctx, "notopiclocks")).await?;
+    } else {
+        let print = locks
+            .iter()
+            .map(|v| format!("\t-{}", v.get_name()))
+            .collect::<Vec<String>>()
+            .join("\n");
+        ctx.reply(lang_fmt!(ctx, "topiclocklist", print)).await?;
+    }
+    Ok(())
+}
+
 async fn lock_action<'a>(message: &Message, args: &TextArgs<'a>) -> Result<()> {
     message
         .check_permissions(|p| p.can_restrict_members)
@@ -731,16 +1026,106 @@ async fn lock_action<'a>(message: &Message, args: &TextArgs<'a>) -> Result<()> {
     let lang = get_chat_lang(chat_id).await?;
     if let Some(arg) = args.args.first() {
         let action = ActionType::from_str_err(arg.get_text(), || {
-            BotError::speak("Invalid action", chat_id, Some(message.message_id))
+            BotError::speak(
+                lang_fmt!(lang, "errinvalidaction"),
+                chat_id,
+                Some(message.message_id),
+            )
+            .with_code(INVALID_ARGUMENT)
         })?;
         set_default_action(message.get_chat(), action).await?;
-        message.reply(lang_fmt!(lang, "setdefaultaction")).await?;
+        message.confirm(lang_fmt!(lang, "setdefaultaction")).await?;
     } else {
         message.reply(lang_fmt!(lang, "noactionarg")).await?;
     }
     Ok(())
 }
 
+async fn lock_invite_links<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_delete_messages.and(p.can_change_info))
+        .await?;
+    let message = ctx.message()?;
+    match args.args.first() {
+        Some(action) => {
+            let action = ActionType::from_str_err(action.get_text(), || {
+                ctx.fail_err(lang_fmt!(ctx, "invalidlinkaction"))
+            })?;
+            let name = action.get_name().to_owned();
+            set_lock_action(message, LockType::InviteLink, action).await?;
+            ctx.confirm(lang_fmt!(ctx, "setlockaction", name)).await?;
+        }
+        None => {
+            set_lock(message.get_chat().get_id(), LockType::InviteLink).await?;
+            ctx.confirm(lang_fmt!(
+                ctx,
+                "setlock",
+                LockType::InviteLink.get_name(),
+                message.get_chat().name_humanreadable()
+            ))
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+fn link_domain_arg<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<String> {
+    let domain = normalize_domain(args.text);
+    if domain.is_empty() || domain.contains(char::is_whitespace) || domain.contains('/') {
+        ctx.fail(lang_fmt!(ctx, "invaliddomain"))
+    } else {
+        Ok(domain)
+    }
+}
+
+async fn set_link_domain<'a>(ctx: &Context, args: &TextArgs<'a>, allow: bool) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    let domain = link_domain_arg(ctx, args)?;
+    set_link_rule(chat, &domain, allow).await?;
+    let text = if allow {
+        lang_fmt!(ctx, "allowlink", domain)
+    } else {
+        lang_fmt!(ctx, "denylink", domain)
+    };
+    ctx.confirm(text).await?;
+    Ok(())
+}
+
+async fn rm_link_domain<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    let domain = link_domain_arg(ctx, args)?;
+    if !remove_link_rule(chat, &domain).await? {
+        return ctx.fail(lang_fmt!(ctx, "nodomain", domain));
+    }
+    ctx.confirm(lang_fmt!(ctx, "rmdomain", domain)).await?;
+    Ok(())
+}
+
+async fn link_domains(ctx: &Context) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    let rules = get_link_rules(chat).await?;
+    let list = |allow: bool| {
+        let list = rules
+            .iter()
+            .filter(|r| r.allow == allow)
+            .map(|r| r.domain.as_str())
+            .collect::<Vec<&str>>();
+        if list.is_empty() {
+            "none".to_owned()
+        } else {
+            list.join(", ")
+        }
+    };
+    ctx.reply(lang_fmt!(ctx, "linkdomainlist", list(true), list(false)))
+        .await?;
+    Ok(())
+}
+
 async fn cmd_available(ctx: &Context) -> Result<()> {
     let available = ["[*Available locks]:".to_owned()]
         .into_iter()
@@ -771,6 +1156,14 @@ async fn handle_command(ctx: &Context) -> Result<()> {
             "locks" => handle_list(message).await?,
             "lockaction" => lock_action(message, args).await?,
             "available" => cmd_available(ctx).await?,
+            "lockinvitelinks" => lock_invite_links(ctx, args).await?,
+            "allowlink" => set_link_domain(ctx, args, true).await?,
+            "denylink" => set_link_domain(ctx, args, false).await?,
+            "rmlink" => rm_link_domain(ctx, args).await?,
+            "linkdomains" => link_domains(ctx).await?,
+            "topiclock" => handle_topic_lock(ctx, &command).await?,
+            "topicunlock" => handle_topic_unlock(ctx, &command).await?,
+            "topiclocks" => handle_topic_list(ctx).await?,
             _ => (),
         };
     }
@@ -789,13 +1182,8 @@ where
     F: for<'b> FnOnce(&'b Message) -> bool,
 {
     if p(message) {
-        if let Some(newaction) = get_lock(message, locktype.clone()).await? {
-            let newaction = if let Some(action) = newaction.lock_action {
-                Some(action)
-            } else {
-                Some(get_default_settings(message.get_chat()).await?.lock_action)
-            };
-
+        if let Some(newaction) = get_engaged_action(message, &locktype).await? {
+            let newaction = Some(newaction);
             if newaction > *action {
                 *action = newaction;
             }
@@ -819,13 +1207,8 @@ where
 {
     match p(message).await {
         Ok(true) => {
-            if let Some(newaction) = get_lock(message, locktype.clone()).await? {
-                let newaction = if let Some(action) = newaction.lock_action {
-                    Some(action)
-                } else {
-                    Some(get_default_settings(message.get_chat()).await?.lock_action)
-                };
-
+            if let Some(newaction) = get_engaged_action(message, &locktype).await? {
+                let newaction = Some(newaction);
                 if newaction > *action {
                     *action = newaction;
                 }
@@ -848,14 +1231,19 @@ async fn handle_message_event(
     ctx: &Context,
     action: ActionType,
     locks: &[LockType],
-) -> Result<()> {
+) -> Result<bool> {
     if let Some(user) = message.get_from() {
         if is_approved(message.get_chat(), user.id).await? {
-            return Ok(());
+            return Ok(false);
         }
     }
     if message.get_from().is_admin(message.get_chat()).await? {
-        return Ok(());
+        return Ok(false);
+    }
+    if let Some(user) = message.get_from() {
+        if has_booster_perks(message.get_chat(), user.get_id()).await? {
+            return Ok(false);
+        }
     }
     let default = get_default_settings(message.get_chat()).await?;
     let lang = ctx.try_get()?.lang;
@@ -893,22 +1281,43 @@ async fn handle_message_event(
         .build_delete_message(message.get_chat().get_id(), message.get_message_id())
         .build()
         .await?;
-    Ok(())
+    Ok(true)
 }
 
-async fn handle_user_event(update: &UpdateExt, ctx: &Context) -> Result<()> {
+/// Returns true if the update was a locked message that got deleted
+async fn handle_user_event(update: &UpdateExt, ctx: &Context) -> Result<bool> {
     if let (Some(action), locks) = action_from_update(update).await? {
         if let Some(message) = update.should_moderate().await {
-            handle_message_event(message, ctx, action, &locks).await?;
+            return handle_message_event(message, ctx, action, &locks).await;
         }
     }
-    Ok(())
+    Ok(false)
 }
 
-#[update_handler]
-pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
-    handle_user_event(cmd.update(), cmd).await?;
-    handle_command(cmd).await?;
+/// Enforces locks before other modules see a message, so locked messages are never handled
+/// as commands, filters, or notes
+pub struct Enforce;
 
-    Ok(())
+#[async_trait::async_trait]
+impl Middleware for Enforce {
+    fn name(&self) -> &'static str {
+        "locks"
+    }
+
+    fn priority(&self) -> i32 {
+        -30
+    }
+
+    async fn before(&self, ctx: &Context) -> Result<Flow> {
+        if handle_user_event(ctx.update(), ctx).await? {
+            Ok(Flow::Stop)
+        } else {
+            Ok(Flow::Continue)
+        }
+    }
+}
+
+#[update_handler(settings = Settings, middleware = Enforce)]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await
 }
@@ -0,0 +1,97 @@
+use crate::metadata::metadata;
+use crate::statics::{ME, TG};
+use crate::tg::command::{Cmd, Context, TextArgs};
+use crate::tg::log_channel::{get_log_channel, set_log_channel};
+use crate::tg::permissions::IsGroupAdmin;
+use crate::tg::user::Username;
+use crate::util::error::{Fail, Result, SpeakErr};
+use crate::util::string::Speak;
+use botapi::gen_types::ChatMember;
+use macros::{lang_fmt, update_handler};
+
+metadata!("Log Channel",
+    r#"
+    Report automated and scheduled actions taken in this chat to a channel. Add the bot to
+    the channel as an admin that can post messages, then run /setlog with the channel id.
+    Only admins of the channel can send this chat's logs to it.
+    "#,
+    { command = "setlog", help = "Sets the log channel.", usage = "setlogusage" },
+    { command = "unsetlog", help = "Stops sending logs to the log channel" },
+    { command = "logchannel", help = "Shows the current log channel" }
+);
+
+async fn setlog<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    let chat = ctx.try_get()?.chat;
+    let channel = match str::parse::<i64>(args.text.trim()) {
+        Ok(channel) => channel,
+        Err(_) => return ctx.usage_err("setlogusage"),
+    };
+
+    let me = ME.get().unwrap().get_id();
+    let user = ctx.get_real_from()?.get_id();
+    let can_post = match TG.client.build_get_chat_member(channel, me).build().await {
+        Ok(ChatMember::ChatMemberAdministrator(admin)) => {
+            admin.get_can_post_messages().unwrap_or(false)
+        }
+        _ => false,
+    };
+    if !can_post {
+        return ctx.fail(lang_fmt!(ctx, "logchannelcantpost"));
+    }
+    let is_admin = matches!(
+        TG.client.build_get_chat_member(channel, user).build().await,
+        Ok(ChatMember::ChatMemberOwner(_) | ChatMember::ChatMemberAdministrator(_))
+    );
+    if !is_admin {
+        return ctx.fail(lang_fmt!(ctx, "logchannelnotadmin"));
+    }
+
+    channel
+        .speak(lang_fmt!(ctx, "logchannelhello", chat.name_humanreadable()))
+        .await
+        .speak_err(ctx, |e| lang_fmt!(ctx, "logchannelfail", e))
+        .await?;
+
+    set_log_channel(chat, Some(channel)).await?;
+    ctx.reply(lang_fmt!(ctx, "logchannelset", channel)).await?;
+    Ok(())
+}
+
+async fn unsetlog(ctx: &Context) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    set_log_channel(ctx.try_get()?.chat, None).await?;
+    ctx.reply(lang_fmt!(ctx, "logchannelunset")).await?;
+    Ok(())
+}
+
+async fn logchannel(ctx: &Context) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let text = match get_log_channel(ctx.try_get()?.chat).await? {
+        Some(channel) => lang_fmt!(ctx, "logchannelcurrent", channel),
+        None => lang_fmt!(ctx, "logchannelnone"),
+    };
+    ctx.reply(text).await?;
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
+        match cmd {
+            "setlog" => setlog(ctx, args).await,
+            "unsetlog" => unsetlog(ctx).await,
+            "logchannel" => logchannel(ctx).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+
+    Ok(())
+}
@@ -1,18 +1,25 @@
 use macros::{lang_fmt, update_handler};
 
-use crate::tg::command::{Cmd, Context};
+use crate::tg::admin_helpers::is_dm;
+use crate::tg::admin_notes::get_admin_notes;
+use crate::tg::command::{Cmd, Context, TextArgs};
 use crate::tg::dialog::get_user_chats;
 use crate::tg::markdown::EntityMessage;
-use crate::tg::permissions::IsGroupAdmin;
-use crate::tg::user::GetUser;
-use crate::util::error::{BotError, Result, SpeakErr};
+use crate::tg::permissions::{IsAdmin, IsGroupAdmin};
+use crate::tg::user::{get_username_history, resolve_user_target, GetUser};
+use crate::util::error::{BotError, Fail, Result, SpeakErr};
+use crate::util::error_codes::find_error_code;
+use crate::util::time::ChatTime;
 use crate::{metadata::metadata, util::string::Speak};
 
 metadata!("Misc",
    r#"
     Random helper functions to make your life easier.
     "#,
-   { command = "id", help = "Gets the id for a user" }
+   { command = "id", help = "Gets the id for a user" },
+   { command = "info", help = "\\<user\\>: Shows what I know about a user, or about you if no user is given" },
+   { command = "usernames", help = "\\<user\\>: Lists usernames a user has had in this chat and when they were last seen" },
+   { command = "error", help = "\\<code\\>: Explains an error code like E002 shown after one of my error messages" }
 );
 
 async fn get_id(ctx: &Context) -> Result<()> {
@@ -33,6 +40,69 @@ async fn get_id(ctx: &Context) -> Result<()> {
     Ok(())
 }
 
+async fn usernames(ctx: &Context) -> Result<()> {
+    ctx.check_permissions(|p| p.can_restrict_members).await?;
+    let chat = ctx.message()?.get_chat().get_id();
+    let target = match resolve_user_target(ctx).await {
+        Err(BotError::UserNotFound) => return ctx.fail(lang_fmt!(ctx, "usernotfound")),
+        res => res?.0,
+    };
+    let Some(target) = target else {
+        return ctx.fail(lang_fmt!(ctx, "specifyuser"));
+    };
+    let user = target.get_id();
+    let name = user.cached_name().await?;
+    let history = get_username_history(chat, user).await?;
+    if history.is_empty() {
+        ctx.reply(lang_fmt!(ctx, "nousernames", name)).await?;
+        return Ok(());
+    }
+
+    let time = ChatTime::get(chat).await?;
+    let mut message = lang_fmt!(ctx, "usernames", name);
+    for entry in history {
+        message.push_str(&format!(
+            "\n@{} - {}",
+            entry.username,
+            time.format(&entry.last_seen)
+        ));
+    }
+    ctx.reply(message).await?;
+    Ok(())
+}
+
+async fn info(ctx: &Context) -> Result<()> {
+    let target = match resolve_user_target(ctx).await {
+        Err(BotError::UserNotFound) => return ctx.fail(lang_fmt!(ctx, "usernotfound")),
+        res => res?.0,
+    };
+    let user = match target {
+        Some(target) => target.get_id(),
+        None => ctx.get_real_from()?.get_id(),
+    };
+    let name = user.cached_name().await?;
+    let mut message = lang_fmt!(ctx, "userinfo", name, user);
+    if let Some(username) = user
+        .get_cached_user()
+        .await?
+        .and_then(|u| u.get_username().map(|u| u.to_owned()))
+    {
+        message.push('\n');
+        message.push_str(&lang_fmt!(ctx, "userinfousername", username));
+    }
+
+    let chat = ctx.message()?.get_chat();
+    if !is_dm(chat) && ctx.message()?.get_from().is_admin(chat).await? {
+        let notes = get_admin_notes(chat.get_id(), user).await?;
+        if !notes.is_empty() {
+            message.push('\n');
+            message.push_str(&lang_fmt!(ctx, "userinfoadminnotes", notes.len()));
+        }
+    }
+    ctx.reply(message).await?;
+    Ok(())
+}
+
 pub async fn allchats(ctx: &Context) -> Result<()> {
     ctx.check_permissions(|p| p.is_support).await?;
     ctx.action_user(|ctx, user, _| async move {
@@ -57,12 +127,29 @@ pub async fn allchats(ctx: &Context) -> Result<()> {
     Ok(())
 }
 
+async fn explain_error<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    let text = args.text.trim();
+    if text.is_empty() {
+        return ctx.usage_err("errorusage");
+    }
+    let Some(code) = find_error_code(text) else {
+        return ctx.fail(lang_fmt!(ctx, "errorunknown", text));
+    };
+    let help = ctx.lang().get_string(code.help).unwrap_or_default();
+    ctx.reply(lang_fmt!(ctx, "errorexplain", code, help))
+        .await?;
+    Ok(())
+}
+
 #[update_handler]
 pub async fn handle_update(ctx: &Context) -> Result<()> {
-    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
         match cmd {
             "id" => get_id(ctx).await?,
+            "info" => info(ctx).await?,
             "allchats" => allchats(ctx).await?,
+            "usernames" => usernames(ctx).await?,
+            "error" => explain_error(ctx, args).await?,
             _ => (),
         }
     }
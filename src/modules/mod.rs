@@ -1,3 +1,78 @@
 use macros::discover_mods;
 
+use crate::metadata::Module;
+use crate::statics::TG;
+use crate::tg::import_export::RoseExport;
+use crate::tg::settings::SettingsProvider;
+use crate::util::error::Result;
+
 discover_mods!("./src/modules");
+
+/// Database migrations for every built-in module, including disabled ones so the schema
+/// doesn't depend on the config
+pub fn get_migrations() -> Vec<Box<dyn sea_orm_migration::MigrationTrait>> {
+    builtin_modules()
+        .into_iter()
+        .flat_map(|module| module.migrations())
+        .collect()
+}
+
+/// Exports a chat's data from every built-in module that supports exporting
+pub async fn all_export(chat: i64) -> Result<RoseExport> {
+    let mut v = RoseExport::new();
+    for module in builtin_modules() {
+        if let Some(name) = module.supports_export() {
+            if let Some(export) = module.export(chat).await? {
+                v.data.insert(name.to_owned(), export);
+            }
+        }
+    }
+    Ok(v)
+}
+
+/// Imports a chat's data into every built-in module with a section in the export, returning
+/// the sections no module used
+pub async fn all_import(chat: i64, json: &str) -> Result<RoseExport> {
+    let mut v: RoseExport = serde_json::from_str(json)?;
+    for module in builtin_modules() {
+        if let Some(name) = module.supports_export() {
+            if let Some(value) = v.data.remove(name) {
+                module.import(chat, value).await?;
+            }
+        }
+    }
+    Ok(v)
+}
+
+/// Works out what importing a chat's data would do without importing it. Returns the sections
+/// a module would replace with how many items each has, and the sections no module uses
+pub fn plan_import(json: &str) -> Result<(Vec<(&'static str, usize)>, Vec<String>)> {
+    let mut v: RoseExport = serde_json::from_str(json)?;
+    let mut replaced = Vec::new();
+    for module in builtin_modules() {
+        if let Some(name) = module.supports_export() {
+            if let Some(value) = v.data.remove(name) {
+                replaced.push((name, count_items(&value)));
+            }
+        }
+    }
+    Ok((replaced, v.data.into_keys().collect()))
+}
+
+/// Counts the items in an exported section, the length of its lists
+fn count_items(value: &serde_json::Value) -> usize {
+    match value {
+        serde_json::Value::Array(items) => items.len(),
+        serde_json::Value::Object(fields) => fields
+            .values()
+            .filter_map(|field| field.as_array())
+            .map(|items| items.len())
+            .sum(),
+        _ => 0,
+    }
+}
+
+/// Modules with settings on the /settings panel, leaving out disabled modules
+pub fn settings_providers() -> Vec<&'static dyn SettingsProvider> {
+    TG.registry.settings()
+}
@@ -0,0 +1,79 @@
+use crate::metadata::metadata;
+use crate::tg::command::{Cmd, Context};
+use crate::tg::extract::{InGroup, TargetUser};
+use crate::tg::markdown::Escape;
+use crate::tg::modlog::{format_modlog, get_recent_actions, get_user_history};
+use crate::tg::user::{GetUser, Username};
+use crate::util::error::Result;
+use crate::util::string::Speak;
+use macros::{lang_fmt, update_handler};
+
+metadata!("Mod Log",
+    r#"
+    Look back at what admins have done. Bans, mutes, kicks, restrictions and warns done with
+    commands are recorded along with who did them and why, so you can check whether a user
+    has been in trouble before. Unlike the log channel this keeps working after the messages
+    have scrolled away.
+
+    [*Examples]
+    [_show everything done to a user]
+    /history @username
+
+    [_show the latest actions in the chat]
+    /modlog
+    "#,
+    { command = "history", help = "Show the moderation history of a user.", usage = "historyusage", perms = [CanRestrictMembers] },
+    { command = "modlog", help = "Show the latest moderation actions in the chat", perms = [CanRestrictMembers] }
+);
+
+async fn history(
+    ctx: &Context,
+    _: InGroup,
+    TargetUser { id: user, .. }: TargetUser<'_>,
+) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let name = user.cached_name().await?.escape(false).into_owned();
+    let entries = get_user_history(chat.get_id(), user).await?;
+    if entries.is_empty() {
+        ctx.reply(lang_fmt!(ctx, "nohistory", name)).await?;
+        return Ok(());
+    }
+    let mut message = lang_fmt!(
+        ctx,
+        "history",
+        name,
+        chat.name_humanreadable().escape(false)
+    );
+    message.push_str(&format_modlog(chat.get_id(), ctx.lang(), &entries).await?);
+    ctx.reply(message).await?;
+    Ok(())
+}
+
+async fn modlog(ctx: &Context, _: InGroup) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let entries = get_recent_actions(chat.get_id()).await?;
+    if entries.is_empty() {
+        ctx.reply(lang_fmt!(ctx, "nomodlog")).await?;
+        return Ok(());
+    }
+    let mut message = lang_fmt!(ctx, "modlog", chat.name_humanreadable().escape(false));
+    message.push_str(&format_modlog(chat.get_id(), ctx.lang(), &entries).await?);
+    ctx.reply(message).await?;
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+        match cmd {
+            "history" => ctx.run(history).await,
+            "modlog" => ctx.run(modlog).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(ctx: &Context) -> Result<()> {
+    handle_command(ctx).await
+}
@@ -0,0 +1,204 @@
+use crate::metadata::metadata;
+use crate::tg::command::{Cmd, Context};
+use crate::tg::extract::{CanChangeInfo, CommandArgs, InGroup, RequirePerm};
+use crate::tg::networks::{
+    get_chat_network, link_chat, set_chat_share, set_network_default, unlink_chat, LinkResult,
+    Share,
+};
+use crate::tg::permissions::other_chat_or_die;
+use crate::tg::user::{get_chat, Username};
+use crate::util::error::{Fail, Result};
+use crate::util::string::Speak;
+use macros::{lang_fmt, update_handler};
+
+metadata!("Networks",
+    r#"
+    Link related chats into a network. Chats in a network share approvals and blocklists,
+    and notes if the network is set up to. Lighter than a federation: there are no network
+    bans, a network just saves setting up the same things in every chat.
+
+    Each share is on or off for the whole network, and each chat can override that for
+    itself. Something is only shared between two chats if both of them share it and they
+    were linked with each other. Linking a chat into a network doesn't link it with the
+    network's other chats, an admin of both chats has to link each pair.
+
+    [*Examples]
+    [_link another chat with this chat, adding it to this chat's network]
+    /linkchat -1001234567890
+
+    [_stop sharing this chat's blocklists, whatever the network does]
+    /networkshare blocklists off
+
+    [_share notes in every chat that doesn't say otherwise]
+    /networkdefault notes on
+    "#,
+    { command = "linkchat", help = "Link a chat with this chat, adding it to this chat's network or starting one if needed.", usage = "linkchatusage", perms = [CanChangeInfo] },
+    { command = "unlinkchat", help = "Remove this chat from its network", perms = [CanChangeInfo] },
+    { command = "network", help = "Show this chat's network and what it shares" },
+    { command = "networkshare", help = "Override what this chat shares.", usage = "networkshareusage", perms = [CanChangeInfo] },
+    { command = "networkdefault", help = "Set what chats in the network share unless they override it, network owner only.", usage = "networkdefaultusage" }
+);
+
+fn parse_share(ctx: &Context, name: &str) -> Result<Share> {
+    match Share::from_name(name) {
+        Some(share) => Ok(share),
+        None => ctx.fail(lang_fmt!(ctx, "networkinvalidshare")),
+    }
+}
+
+async fn linkchat(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanChangeInfo>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let other = other_chat_or_die(ctx, args.text).await?;
+    let chat = ctx.try_get()?.chat;
+    let Some(owner) = ctx.message()?.get_from() else {
+        return ctx.fail(lang_fmt!(ctx, "networkanon"));
+    };
+    let res = link_chat(
+        chat.get_id(),
+        other.get_id(),
+        owner.get_id(),
+        chat.name_humanreadable(),
+    )
+    .await?;
+    let text = match res {
+        LinkResult::Linked => lang_fmt!(ctx, "networklinked", other.name_humanreadable()),
+        LinkResult::AlreadyLinked => {
+            lang_fmt!(ctx, "networkalreadylinked", other.name_humanreadable())
+        }
+        LinkResult::OtherNetwork => {
+            lang_fmt!(ctx, "networkothernetwork", other.name_humanreadable())
+        }
+    };
+    ctx.reply(text).await?;
+    Ok(())
+}
+
+async fn unlinkchat(ctx: &Context, _: InGroup, _: RequirePerm<CanChangeInfo>) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    if unlink_chat(chat.get_id()).await? {
+        ctx.reply(lang_fmt!(ctx, "networkunlinked")).await?;
+    } else {
+        ctx.reply(lang_fmt!(ctx, "nonetwork")).await?;
+    }
+    Ok(())
+}
+
+async fn network(ctx: &Context, _: InGroup) -> Result<()> {
+    let chat = ctx.try_get()?.chat.get_id();
+    let Some(network) = get_chat_network(chat).await? else {
+        ctx.reply(lang_fmt!(ctx, "nonetwork")).await?;
+        return Ok(());
+    };
+
+    let mut chats = Vec::with_capacity(network.chats.len());
+    for member in network.chats.iter().filter(|c| c.chat_id != chat) {
+        let name = match get_chat(member.chat_id).await? {
+            Some(c) => c.name_humanreadable(),
+            None => member.chat_id.to_string(),
+        };
+        let entry = if network.linked(chat, member.chat_id) {
+            lang_fmt!(ctx, "networkchatlinked", name, member.chat_id)
+        } else {
+            lang_fmt!(ctx, "networkchatnotlinked", name, member.chat_id)
+        };
+        chats.push(format!("\t- {}", entry));
+    }
+    let shares = [Share::Approvals, Share::Blocklists, Share::Notes]
+        .into_iter()
+        .map(|share| {
+            let state = if network.shares(chat, share) {
+                lang_fmt!(ctx, "networkon")
+            } else {
+                lang_fmt!(ctx, "networkoff")
+            };
+            format!("\t- {}: {}", share, state)
+        })
+        .collect::<Vec<String>>();
+    ctx.reply(lang_fmt!(
+        ctx,
+        "networkinfo",
+        network.network.name,
+        chats.join("\n"),
+        shares.join("\n")
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn networkshare(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanChangeInfo>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let chat = ctx.try_get()?.chat.get_id();
+    let (share, value) = match args.args.as_slice() {
+        [share, value] => (share.get_text(), value.get_text()),
+        _ => return ctx.usage_err("networkshareusage"),
+    };
+    let share = parse_share(ctx, share)?;
+    let value = match value {
+        "on" => Some(true),
+        "off" => Some(false),
+        "default" => None,
+        _ => return ctx.usage_err("networkshareusage"),
+    };
+    if get_chat_network(chat).await?.is_none() {
+        return ctx.fail(lang_fmt!(ctx, "nonetwork"));
+    }
+    set_chat_share(chat, share, value).await?;
+    ctx.reply(lang_fmt!(ctx, "networkshareset", share)).await?;
+    Ok(())
+}
+
+async fn networkdefault(
+    ctx: &Context,
+    _: InGroup,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let chat = ctx.try_get()?.chat.get_id();
+    let (share, value) = match args.args.as_slice() {
+        [share, value] => (share.get_text(), value.get_text()),
+        _ => return ctx.usage_err("networkdefaultusage"),
+    };
+    let share = parse_share(ctx, share)?;
+    let value = match value {
+        "on" => true,
+        "off" => false,
+        _ => return ctx.usage_err("networkdefaultusage"),
+    };
+    let Some(network) = get_chat_network(chat).await? else {
+        return ctx.fail(lang_fmt!(ctx, "nonetwork"));
+    };
+    let user = ctx.message()?.get_from().map(|u| u.get_id());
+    if user != Some(network.network.owner) {
+        return ctx.fail(lang_fmt!(ctx, "networknotowner"));
+    }
+    set_network_default(network.network.network_id, share, value).await?;
+    ctx.reply(lang_fmt!(ctx, "networkdefaultset", share))
+        .await?;
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+        match cmd {
+            "linkchat" => ctx.run(linkchat).await,
+            "unlinkchat" => ctx.run(unlinkchat).await,
+            "network" => ctx.run(network).await,
+            "networkshare" => ctx.run(networkshare).await,
+            "networkdefault" => ctx.run(networkdefault).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(ctx: &Context) -> Result<()> {
+    handle_command(ctx).await
+}
@@ -2,43 +2,59 @@ use crate::metadata::{metadata, ModuleHelpers};
 use crate::persist::redis::RedisCache;
 use crate::statics::{DB, REDIS, TG};
 
-use crate::tg::admin_helpers::IntoChatUser;
+use crate::tg::admin_helpers::{ChatUser, IntoChatUser};
 use crate::tg::button::{InlineKeyboardBuilder, OnPush};
-use crate::tg::command::{
-    get_content, handle_deep_link, Cmd, Context, InputType, TextArg, TextArgs,
-};
+use crate::tg::command::{get_content, Cmd, Context, InputType, TextArg, TextArgs};
+use crate::tg::deeplink::DeepLink;
 
+use crate::tg::extract::{CanChangeInfo, CommandArgs, InGroup, RequirePerm};
 use crate::tg::import_export::{is_tainted, set_taint_vec};
-use crate::tg::markdown::{button_deeplink_key, MarkupBuilder};
+use crate::tg::markdown::{retro_fillings, MarkupBuilder};
 use crate::tg::notes::{
-    clear_notes, get_hash_key, get_note_by_name, handle_transition, refresh_notes,
+    clear_notes, copy_notes, find_inline_notes, get_hash_key, get_note_by_name, handle_transition,
+    refresh_notes,
 };
-use crate::tg::permissions::IsGroupAdmin;
+use crate::tg::parse_mode::{to_markdown_v2, ParseMode};
+use crate::tg::permissions::{other_chat_or_die, IsGroupAdmin};
 use crate::tg::rosemd::{RoseMdDecompiler, RoseMdParser};
-use crate::tg::user::Username;
+use crate::tg::url_guard::check_button_urls;
+use crate::tg::user::{get_chat, Username};
 use crate::util::error::{BotError, Fail, Result, SpeakErr};
+use crate::util::error_codes::{MISSING_ARGUMENT, NOT_FOUND};
 use crate::util::string::Speak;
 use ::sea_orm_migration::prelude::*;
-use botapi::gen_types::MessageEntity;
+use botapi::gen_types::{
+    InlineKeyboardMarkup, InlineQuery, InlineQueryResult, InlineQueryResultArticleBuilder,
+    InlineQueryResultCachedAudioBuilder, InlineQueryResultCachedDocumentBuilder,
+    InlineQueryResultCachedGifBuilder, InlineQueryResultCachedPhotoBuilder,
+    InlineQueryResultCachedStickerBuilder, InlineQueryResultCachedVideoBuilder,
+    InlineQueryResultCachedVoiceBuilder, InputMessageContent, InputTextMessageContentBuilder,
+    MessageEntity, UpdateExt, User,
+};
 use futures::FutureExt;
 use macros::{lang_fmt, update_handler};
 use redis::AsyncCommands;
 use sea_orm::ActiveValue::{NotSet, Set};
-use sea_orm::{ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter};
+use sea_orm::{ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter, TransactionTrait};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-use crate::persist::core::{entity, media::*, notes};
+use crate::persist::core::{entity, media::*, notes, save_with_entities};
 
 metadata!("Notes",
     r#"
     Easily store and retrive text, media, and other content by keywords.
     Useful for storing answers to often asked questions or searching uploaded media.
+
+    Notes can also be sent in any chat by typing my username followed by \#notename. This
+    finds notes saved in our private chat and notes of chats you are an admin of.
     "#,
     Helper,
     { command = "save", help = "Saves a note" },
     { command = "get", help = "Get a note" },
     { command = "delete", help = "Delete a note" },
-    { command = "notes", help = "List all notes for the current chat"}
+    { command = "notes", help = "List all notes for the current chat"},
+    { command = "copynotes", help = "Copy notes from another chat you are an admin of, keeping notes this chat already has.", usage = "copynotesusage", perms = [CanChangeInfo] }
 );
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -54,6 +70,12 @@ struct NotesItem {
     text: String,
     #[serde(rename = "type")]
     note_type: i64,
+    /// Set when text uses one of telegram's parse modes instead of rose's markdown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parse_mode: Option<ParseMode>,
+    /// Buttons in rose's markdown, only used along with parse_mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    buttons: Option<String>,
 }
 
 #[derive(Debug)]
@@ -63,7 +85,7 @@ struct Helper;
 impl ModuleHelpers for Helper {
     async fn export(&self, chat: i64) -> Result<Option<serde_json::Value>> {
         let notes = refresh_notes(chat).await?;
-        let items: Vec<NotesItem> = notes
+        let items = notes
             .into_iter()
             .map(|(note, (model, entities, buttons))| {
                 let buttons = if let Some(buttons) = buttons {
@@ -77,17 +99,19 @@ impl ModuleHelpers for Helper {
                 } else {
                     ""
                 };
-                let text = RoseMdDecompiler::new(text, &entities, buttons.get_inline_keyboard())
-                    .decompile()
-                    .replace('\n', "\\n");
-                NotesItem {
+                let text = to_markdown_v2(text, &entities)?;
+                let buttons =
+                    RoseMdDecompiler::new("", &[], buttons.get_inline_keyboard()).decompile();
+                Ok(NotesItem {
                     data_id: model.media_id.unwrap_or_else(String::new),
                     name: note,
                     text,
                     note_type: model.media_type.get_rose_type(),
-                }
+                    parse_mode: Some(ParseMode::MarkdownV2),
+                    buttons: Some(buttons).filter(|b| !b.is_empty()),
+                })
             })
-            .collect();
+            .collect::<Result<Vec<NotesItem>>>()?;
 
         let out = ExportNotes {
             private_notes: false,
@@ -100,11 +124,21 @@ impl ModuleHelpers for Helper {
     async fn import(&self, chat: i64, value: serde_json::Value) -> Result<()> {
         let notes: ExportNotes = serde_json::from_value(value)?;
         clear_notes(chat).await?;
+        let tx = DB.begin().await?;
         let mut res = Vec::new();
         for note in notes.notes {
-            let (text, entities, buttons) =
-                RoseMdParser::new(&note.text.replace("\\n", "\n"), true).parse();
-            let entity_id = entity::insert(*DB, &entities, buttons).await?;
+            let (text, entities, buttons) = match note.parse_mode {
+                Some(parse_mode) => {
+                    let (text, entities) = parse_mode.parse(&note.text)?;
+                    let buttons = note
+                        .buttons
+                        .map(|buttons| RoseMdParser::new(&buttons, true).parse().2)
+                        .unwrap_or_default();
+                    (text, entities, buttons)
+                }
+                None => RoseMdParser::new(&note.text.replace("\\n", "\n"), true).parse(),
+            };
+            let entity_id = entity::insert(&tx, &entities, buttons).await?;
 
             let model = notes::Model {
                 name: note.name,
@@ -126,7 +160,8 @@ impl ModuleHelpers for Helper {
         let taint = res.iter().filter_map(|v| v.get_taint(Some(v.name.clone())));
         set_taint_vec(taint.collect()).await?;
         let res = res.into_iter().map(|v| v.into_active_model());
-        notes::Entity::insert_many(res).exec(*DB).await?;
+        notes::Entity::insert_many(res).exec(&tx).await?;
+        tx.commit().await?;
 
         refresh_notes(chat).await?;
         Ok(())
@@ -141,15 +176,20 @@ impl ModuleHelpers for Helper {
     }
 }
 
-async fn get_model<'a>(ctx: &'a Context, args: &'a TextArgs<'a>) -> Result<notes::Model> {
+/// Builds a note from a command along with the entities and buttons to save with it. The
+/// entity id is set once those are saved
+async fn get_model<'a>(
+    ctx: &'a Context,
+    args: &'a TextArgs<'a>,
+) -> Result<(notes::Model, Vec<MessageEntity>, InlineKeyboardBuilder)> {
     let message = ctx.message()?;
-    let input_type = get_content(message, args)?;
+    let input_type = get_content(ctx, args)?;
     let res = match input_type {
         InputType::Reply(name, text, message) => {
             let chatuser = message.get_chatuser();
-            let (media_id, media_type) = get_media_type(message)?;
+            let (media_id, media_type) = get_media_type(message).await?;
             let text = text.map(Some).unwrap_or_else(|| message.get_caption());
-            let (text, entity_id) = if let Some(text) = text {
+            let (text, entities, buttons) = if let Some(text) = text {
                 let extra = message.get_entities().map(|v| v.to_owned());
 
                 let md = MarkupBuilder::new(extra)
@@ -162,28 +202,29 @@ async fn get_model<'a>(ctx: &'a Context, args: &'a TextArgs<'a>) -> Result<notes
                     .await
                     .speak(ctx, lang_fmt!(ctx, "failmurk"))
                     .await?;
-                let entity_id = entity::insert(*DB, &entities, buttons).await?;
-                (Some(text), entity_id)
+                check_button_urls(ctx.message()?, &buttons).await?;
+                (Some(text), entities, buttons)
             } else {
-                (None, None)
+                (None, Vec::new(), InlineKeyboardBuilder::default())
             };
-            notes::Model {
+            let model = notes::Model {
                 name: (*name).to_owned(),
                 chat: message.get_chat().get_id(),
                 text,
                 media_id,
                 media_type,
                 protect: false,
-                entity_id,
-            }
+                entity_id: None,
+            };
+            (model, entities, buttons)
         }
 
         InputType::Command(name, content, message) => {
-            let (media_id, media_type) = get_media_type(message)?;
+            let (media_id, media_type) = get_media_type(message).await?;
             let chatuser = message.get_chatuser();
             let content = content.map(Some).unwrap_or_else(|| message.get_caption());
 
-            let (text, entity_id) = if let Some(text) = content {
+            let (text, entities, buttons) = if let Some(text) = content {
                 log::info!("content {}", text);
 
                 let extra = message.get_entities().map(|v| v.to_owned());
@@ -198,20 +239,21 @@ async fn get_model<'a>(ctx: &'a Context, args: &'a TextArgs<'a>) -> Result<notes
                     .await
                     .speak(ctx, lang_fmt!(ctx, "failmurk"))
                     .await?;
-                let entity_id = entity::insert(*DB, &entities, buttons).await?;
-                (Some(text), entity_id)
+                check_button_urls(ctx.message()?, &buttons).await?;
+                (Some(text), entities, buttons)
             } else {
-                (None, None)
+                (None, Vec::new(), InlineKeyboardBuilder::default())
             };
-            notes::Model {
+            let model = notes::Model {
                 name: (*name).to_owned(),
                 chat: message.get_chat().get_id(),
                 text,
                 media_id,
                 media_type,
                 protect: false,
-                entity_id,
-            }
+                entity_id: None,
+            };
+            (model, entities, buttons)
         }
     };
 
@@ -226,12 +268,11 @@ async fn handle_command<'a>(ctx: &Context) -> Result<()> {
             "delete" => delete(ctx, args).await,
             "notes" => list_notes(ctx).await,
             "clearnotes" => clear_notes_cmd(ctx).await,
+            "copynotes" => ctx.run(copy_notes_cmd).await,
             "start" => {
-                let note: Option<(i64, String)> =
-                    handle_deep_link(ctx, button_deeplink_key).await?;
-                if let Some((chat, note)) = note {
-                    log::info!("handling note deep link {} {}", chat, note);
-                    print_chat(ctx, note, chat).await?;
+                if let Some(DeepLink::Note { chat, name }) = ctx.deep_link().await? {
+                    log::info!("handling note deep link {} {}", chat, name);
+                    print_chat(ctx, name.clone(), *chat).await?;
                 }
                 Ok(())
             }
@@ -241,6 +282,25 @@ async fn handle_command<'a>(ctx: &Context) -> Result<()> {
     Ok(())
 }
 
+async fn copy_notes_cmd(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanChangeInfo>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let from = other_chat_or_die(ctx, args.text).await?;
+    let (copied, skipped) = copy_notes(from.get_id(), ctx.try_get()?.chat.get_id()).await?;
+    ctx.reply(lang_fmt!(
+        ctx,
+        "copiednotes",
+        copied,
+        from.name_humanreadable(),
+        skipped
+    ))
+    .await?;
+    Ok(())
+}
+
 async fn print_note(
     ctx: &Context,
     note: notes::Model,
@@ -269,10 +329,6 @@ async fn print_note(
             let c = c.clone();
             async move {
                 button.on_push(move |b| async move {
-                    TG.client
-                        .build_answer_callback_query(b.get_id())
-                        .build()
-                        .await?;
                     handle_transition(&c, note_chat, note, b).await?;
                     Ok(())
                 });
@@ -291,7 +347,10 @@ async fn print_note(
 }
 
 async fn print(message: &Context, name: String) -> Result<()> {
-    print_chat(message, name, message.message()?.get_chat().get_id()).await
+    match message.settings_chat() {
+        Some(chat) => print_chat(message, name, chat).await,
+        None => Ok(()),
+    }
 }
 
 async fn clear_notes_cmd(ctx: &Context) -> Result<()> {
@@ -311,13 +370,12 @@ async fn print_chat(ctx: &Context, name: String, chat: i64) -> Result<()> {
         print_note(ctx, note, entities, buttons, chat).await?;
         Ok(())
     } else {
-        ctx.fail("Note not found")
+        ctx.fail_code(NOT_FOUND, lang_fmt!(ctx, "errnotenotfound"))
     }
 }
 
 async fn get<'a>(ctx: &Context) -> Result<()> {
     ctx.is_group_or_die().await?;
-    let message = ctx.message()?;
     if let Some(Cmd { ref args, .. }) = ctx.cmd() {
         let name = match args.args.first() {
             Some(TextArg::Arg(name)) => Some(name),
@@ -327,11 +385,7 @@ async fn get<'a>(ctx: &Context) -> Result<()> {
         if let Some(name) = name {
             print(ctx, (*name).to_owned()).await
         } else {
-            Err(BotError::speak(
-                "missing note name, try again weenie",
-                message.get_chat().get_id(),
-                Some(message.message_id),
-            ))
+            ctx.fail_code(MISSING_ARGUMENT, lang_fmt!(ctx, "errgetnotename"))
         }
     } else {
         Err(BotError::Generic("not a command".to_owned()))
@@ -347,7 +401,7 @@ async fn delete_by_id(name: String, chat: i64) -> Result<()> {
 
 async fn delete<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
     ctx.check_permissions(|p| p.can_change_info).await?;
-    let model = get_model(ctx, args).await?;
+    let (model, _, _) = get_model(ctx, args).await?;
     let name = model.name.clone();
     delete_by_id(model.name, ctx.message()?.get_chat().get_id()).await?;
     ctx.reply(format!("Deleted note {}", name)).await?;
@@ -374,26 +428,38 @@ async fn save<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
     ctx.check_permissions(|p| p.can_change_info).await?;
     let message = ctx.message()?;
     let chat = message.get_chat().name_humanreadable();
-    let model = get_model(ctx, args).await?;
+    let (model, entities, buttons) = get_model(ctx, args).await?;
     let key = format!("note:{}:{}", message.get_chat().get_id(), model.name);
     log::info!("save key: {}", key);
     let hash_key = get_hash_key(message.get_chat().get_id());
     REDIS.sq(|q| q.del(&hash_key)).await?;
     let name = model.name.clone();
-    notes::Entity::insert(model.cache(key).await?)
-        .on_conflict(
-            OnConflict::columns([notes::Column::Name, notes::Column::Chat])
-                .update_columns([
-                    notes::Column::Text,
-                    notes::Column::MediaId,
-                    notes::Column::MediaType,
-                    notes::Column::Protect,
-                    notes::Column::EntityId,
-                ])
-                .to_owned(),
-        )
-        .exec(*DB)
-        .await?;
+    let model = save_with_entities(&entities, &buttons, |tx, entity_id| {
+        let model = notes::Model {
+            entity_id,
+            ..model.clone()
+        };
+        async move {
+            notes::Entity::insert(model.clone().into_active_model())
+                .on_conflict(
+                    OnConflict::columns([notes::Column::Name, notes::Column::Chat])
+                        .update_columns([
+                            notes::Column::Text,
+                            notes::Column::MediaId,
+                            notes::Column::MediaType,
+                            notes::Column::Protect,
+                            notes::Column::EntityId,
+                        ])
+                        .to_owned(),
+                )
+                .exec(tx)
+                .await?;
+            Ok(model)
+        }
+        .boxed()
+    })
+    .await?;
+    model.cache(key).await?;
 
     message
         .reply(lang_fmt!(ctx, "savednote", name, chat))
@@ -401,8 +467,159 @@ async fn save<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
     Ok(())
 }
 
+/// Turns a note's buttons into ones that work outside of its chat. Buttons opening other notes
+/// become deep links and buttons that need a callback in the chat are dropped
+async fn inline_buttons(
+    chat: i64,
+    buttons: Option<InlineKeyboardBuilder>,
+) -> Result<InlineKeyboardMarkup> {
+    let mut rows = Vec::new();
+    for row in buttons.map(|b| b.into_inner()).unwrap_or_default() {
+        let mut res = Vec::new();
+        for mut button in row {
+            let note = button
+                .raw_text
+                .as_deref()
+                .and_then(|text| text.strip_prefix('#'))
+                .filter(|note| !note.is_empty());
+            if let Some(note) = note {
+                let link = DeepLink::Note {
+                    chat,
+                    name: note.to_owned(),
+                };
+                button.button_url = Some(link.url().await?);
+                button.callback_data = None;
+            }
+            if button.button_url.is_some() {
+                res.push(button.to_button());
+            }
+        }
+        if !res.is_empty() {
+            rows.push(res);
+        }
+    }
+    Ok(InlineKeyboardMarkup::new(rows))
+}
+
+/// Builds the inline result for a note, None if the note can't be sent inline
+async fn inline_note(
+    user: &User,
+    note: notes::Model,
+    entities: Vec<MessageEntity>,
+    mut buttons: Option<InlineKeyboardBuilder>,
+) -> Result<Option<InlineQueryResult>> {
+    if let Some(media_id) = note.media_id.as_ref() {
+        if is_tainted(media_id, crate::tg::notes::MODULE_NAME, note.chat).await? {
+            return Ok(None);
+        }
+    }
+    let text = note.text.unwrap_or_default();
+    let (text, entities) = match get_chat(note.chat).await? {
+        Some(chat) => {
+            let chatuser = ChatUser { chat: &chat, user };
+            retro_fillings(text, entities, buttons.as_mut(), &chatuser).await?
+        }
+        None => (text, entities),
+    };
+    let markup = inline_buttons(note.chat, buttons).await?;
+    let id = Uuid::new_v4().to_string();
+    let name = note.name;
+    let result = match (note.media_type, note.media_id) {
+        (MediaType::Text, _) if text.is_empty() => return Ok(None),
+        (MediaType::Text, _) => InlineQueryResult::InlineQueryResultArticle(
+            InlineQueryResultArticleBuilder::new(
+                id,
+                name,
+                InputMessageContent::InputTextMessageContent(
+                    InputTextMessageContentBuilder::new(text)
+                        .set_entities(entities)
+                        .build(),
+                ),
+            )
+            .set_reply_markup(markup)
+            .build(),
+        ),
+        (_, None) => return Ok(None),
+        (MediaType::Sticker, Some(media)) => InlineQueryResult::InlineQueryResultCachedSticker(
+            InlineQueryResultCachedStickerBuilder::new(id, media)
+                .set_reply_markup(markup)
+                .build(),
+        ),
+        (MediaType::Photo, Some(media)) => InlineQueryResult::InlineQueryResultCachedPhoto(
+            InlineQueryResultCachedPhotoBuilder::new(id, media)
+                .set_title(name)
+                .set_caption(text)
+                .set_caption_entities(entities)
+                .set_reply_markup(markup)
+                .build(),
+        ),
+        (MediaType::Document, Some(media)) => InlineQueryResult::InlineQueryResultCachedDocument(
+            InlineQueryResultCachedDocumentBuilder::new(id, name, media)
+                .set_caption(text)
+                .set_caption_entities(entities)
+                .set_reply_markup(markup)
+                .build(),
+        ),
+        (MediaType::Video, Some(media)) => InlineQueryResult::InlineQueryResultCachedVideo(
+            InlineQueryResultCachedVideoBuilder::new(id, media, name)
+                .set_caption(text)
+                .set_caption_entities(entities)
+                .set_reply_markup(markup)
+                .build(),
+        ),
+        (MediaType::Audio, Some(media)) => InlineQueryResult::InlineQueryResultCachedAudio(
+            InlineQueryResultCachedAudioBuilder::new(id, media)
+                .set_caption(text)
+                .set_caption_entities(entities)
+                .set_reply_markup(markup)
+                .build(),
+        ),
+        (MediaType::Animation, Some(media)) => InlineQueryResult::InlineQueryResultCachedGif(
+            InlineQueryResultCachedGifBuilder::new(id, media)
+                .set_title(name)
+                .set_caption(text)
+                .set_caption_entities(entities)
+                .set_reply_markup(markup)
+                .build(),
+        ),
+        (MediaType::Voice, Some(media)) => InlineQueryResult::InlineQueryResultCachedVoice(
+            InlineQueryResultCachedVoiceBuilder::new(id, media, name)
+                .set_caption(text)
+                .set_caption_entities(entities)
+                .set_reply_markup(markup)
+                .build(),
+        ),
+    };
+    Ok(Some(result))
+}
+
+/// Answers inline queries like `#notename` with the matching notes the user can send
+async fn handle_inline(query: &InlineQuery) -> Result<()> {
+    let Some(prefix) = query.get_query().strip_prefix('#') else {
+        return Ok(());
+    };
+    let user = query.get_from();
+    let notes = find_inline_notes(user.get_id(), prefix.trim()).await?;
+    let mut results = Vec::with_capacity(notes.len());
+    for (note, entities, buttons) in notes {
+        if let Some(result) = inline_note(user, note, entities, buttons).await? {
+            results.push(result);
+        }
+    }
+    TG.client
+        .build_answer_inline_query(query.get_id(), &results)
+        .is_personal(true)
+        .cache_time(0)
+        .build()
+        .await?;
+    Ok(())
+}
+
 #[update_handler]
 pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    if let UpdateExt::InlineQuery(query) = cmd.update() {
+        return handle_inline(query).await;
+    }
     if let Ok(message) = cmd.message() {
         let c = cmd.clone();
         cmd.handle_taint(crate::tg::notes::MODULE_NAME, |taint, new_id| {
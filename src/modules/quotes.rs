@@ -0,0 +1,70 @@
+use crate::metadata::metadata;
+use crate::statics::TG;
+use crate::tg::command::{Cmd, Context};
+use crate::tg::render::{claim_quote, quote_message, quotes_enabled, release_quote, render_quote};
+use crate::util::error::{Fail, Result};
+use crate::util::error_codes::{MISSING_ARGUMENT, UNSUPPORTED_MEDIA};
+use crate::util::string::should_ignore_chat;
+use botapi::gen_types::{FileData, ReplyParametersBuilder};
+use macros::{lang_fmt, update_handler};
+
+metadata!("Quotes",
+    r#"
+    Turn messages into stickers. Reply to a message with /q and it's drawn as a quote with
+    the sender's name, avatar, and formatting. Forwarded messages are quoted as their
+    original sender
+    "#,
+    { command = "q", help = "Reply to a message to make it into a quote sticker" }
+);
+
+async fn quote(ctx: &Context) -> Result<()> {
+    if !quotes_enabled() {
+        return ctx.fail(lang_fmt!(ctx, "quotesdisabled"));
+    }
+    let message = ctx.message()?;
+    let Some(reply) = message.get_reply_to_message() else {
+        return ctx.fail_code(MISSING_ARGUMENT, lang_fmt!(ctx, "quotereply"));
+    };
+    let Some(quote) = quote_message(reply).await? else {
+        return ctx.fail_code(UNSUPPORTED_MEDIA, lang_fmt!(ctx, "quotenotext"));
+    };
+    let chat = message.get_chat().get_id();
+    let user = ctx.get_real_from()?.get_id();
+    if !claim_quote(chat, user).await? {
+        return ctx.fail(lang_fmt!(ctx, "quotecooldown"));
+    }
+
+    let image = match render_quote(&[quote]).await {
+        Ok(image) => image,
+        Err(err) => {
+            release_quote(chat, user).await?;
+            return Err(err);
+        }
+    };
+    if should_ignore_chat(chat).await? {
+        return Ok(());
+    }
+    TG.client
+        .build_send_sticker(chat, FileData::Bytes(image))
+        .reply_parameters(&ReplyParametersBuilder::new(reply.get_message_id()).build())
+        .build()
+        .await?;
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+        match cmd {
+            "q" => quote(ctx).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+
+    Ok(())
+}
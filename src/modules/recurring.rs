@@ -0,0 +1,143 @@
+use crate::metadata::metadata;
+use crate::tg::command::{Cmd, Context};
+use crate::tg::extract::{CanChangeInfo, CommandArgs, InGroup, RequirePerm};
+use crate::tg::recurring::{
+    add_recurring, get_recurring, remove_recurring, Schedule, ScheduleError, MAX_RECURRING,
+};
+use crate::util::error::{Fail, Result};
+use crate::util::error_codes::{INVALID_ARGUMENT, NOT_FOUND};
+use crate::util::string::Speak;
+use crate::util::time::ChatTime;
+use chrono::Utc;
+use macros::{lang_fmt, update_handler};
+use std::str::FromStr;
+
+metadata!("Recurring",
+    r#"
+    Post announcements on a schedule. A schedule is one of:
+    daily 09:00
+    weekly mon 09:00
+    every 6h
+    or a cron expression like 30 9 \* \* 1\-5 for 9:30 on weekdays.
+    Times are in the chat's timezone, see /settz. Announcements can use the same formatting as
+    notes, and can't be posted more than once an hour
+    "#,
+    { command = "addrecurring", help = "Add an announcement.", usage = "addrecurringusage", perms = [CanChangeInfo] },
+    { command = "recurring", help = "List announcements, or delete one.", usage = "recurringusage", perms = [CanChangeInfo] }
+);
+
+/// Longest part of an announcement shown in /recurring list
+const PREVIEW_LENGTH: usize = 40;
+
+async fn addrecurring(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanChangeInfo>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let (schedule, text) = match Schedule::parse_prefix(args.text) {
+        Ok((_, "")) => return ctx.usage_err("addrecurringusage"),
+        Ok((schedule, text)) => (schedule, text),
+        Err(ScheduleError::TooFrequent) => {
+            return ctx.fail_code(INVALID_ARGUMENT, lang_fmt!(ctx, "recurringtoofrequent"))
+        }
+        Err(ScheduleError::Invalid) => {
+            return ctx.fail_code(INVALID_ARGUMENT, lang_fmt!(ctx, "recurringinvalid"))
+        }
+    };
+    let full = args.text.trim_start();
+    let spec = full[..full.len() - text.len()].trim_end();
+    let chat = ctx.try_get()?.chat.get_id();
+    if get_recurring(chat).await?.len() >= MAX_RECURRING {
+        return ctx.fail(lang_fmt!(ctx, "recurringtoomany", MAX_RECURRING));
+    }
+    let local = ChatTime::get(chat).await?;
+    let now = Utc::now();
+    let Some(next) = schedule.next_run(now, now, &local) else {
+        return ctx.fail_code(INVALID_ARGUMENT, lang_fmt!(ctx, "recurringnever"));
+    };
+    let author = ctx.get_real_from()?.get_id();
+    let model = add_recurring(chat, author, spec, text.to_owned()).await?;
+    ctx.reply(lang_fmt!(
+        ctx,
+        "recurringadded",
+        model.id,
+        local.format_lang(&next, ctx.lang())
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn list(ctx: &Context, chat: i64) -> Result<()> {
+    let recurring = get_recurring(chat).await?;
+    if recurring.is_empty() {
+        ctx.reply(lang_fmt!(ctx, "norecurring")).await?;
+        return Ok(());
+    }
+    let local = ChatTime::get(chat).await?;
+    let now = Utc::now();
+    let list = recurring
+        .into_iter()
+        .map(|model| {
+            let next = Schedule::from_str(&model.schedule)
+                .ok()
+                .and_then(|s| s.next_run(now, model.created, &local))
+                .map(|next| local.format_lang(&next, ctx.lang()))
+                .unwrap_or_else(|| "-".to_owned());
+            let mut preview = model.text.chars().take(PREVIEW_LENGTH).collect::<String>();
+            if model.text.chars().count() > PREVIEW_LENGTH {
+                preview.push('…');
+            }
+            format!("{}: {} ({}) {}", model.id, model.schedule, next, preview)
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    ctx.reply(lang_fmt!(ctx, "recurringlist", list)).await?;
+    Ok(())
+}
+
+async fn recurring(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanChangeInfo>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let chat = ctx.try_get()?.chat.get_id();
+    let words = args
+        .args
+        .iter()
+        .map(|a| a.get_text())
+        .collect::<Vec<&str>>();
+    match words.as_slice() {
+        [] | ["list"] => list(ctx, chat).await,
+        ["delete", id] => {
+            let Ok(id) = id.trim_start_matches('#').parse::<i64>() else {
+                return ctx.usage_err("recurringusage");
+            };
+            if remove_recurring(chat, id).await? {
+                ctx.reply(lang_fmt!(ctx, "recurringdeleted", id)).await?;
+                Ok(())
+            } else {
+                ctx.fail_code(NOT_FOUND, lang_fmt!(ctx, "recurringnotfound", id))
+            }
+        }
+        _ => ctx.usage_err("recurringusage"),
+    }
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+        match cmd {
+            "addrecurring" => ctx.run(addrecurring).await,
+            "recurring" => ctx.run(recurring).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(ctx: &Context) -> Result<()> {
+    handle_command(ctx).await?;
+    Ok(())
+}
@@ -38,7 +38,7 @@ pub async fn report(ctx: &Context) -> Result<()> {
             if let Some(chat) = ctx.chat() {
                 if let Some(user) = user {
                     if user.is_admin(chat).await? {
-                        return ctx.fail("I am not going to report an admin, what the FLOOP");
+                        return ctx.fail(lang_fmt!(ctx, "reportadmin"));
                     }
                     let mut admins = ctx
                         .message()?
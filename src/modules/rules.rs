@@ -4,8 +4,8 @@ use crate::persist::core::rules;
 use crate::persist::redis::{default_cache_query, CachedQueryTrait, RedisCache};
 use crate::statics::{CONFIG, DB};
 
-use crate::tg::command::{handle_deep_link, Cmd, Context};
-use crate::tg::markdown::rules_deeplink_key;
+use crate::tg::command::{Cmd, Context};
+use crate::tg::deeplink::DeepLink;
 use crate::tg::permissions::IsGroupAdmin;
 use crate::util::error::Result;
 use crate::util::string::{Lang, Speak};
@@ -26,10 +26,10 @@ metadata!("Rules",
     { command = "rules", help = "Gets the rules in dm"}
 );
 
-fn rules_model(ctx: &Context) -> Result<rules::Model> {
+async fn rules_model(ctx: &Context) -> Result<rules::Model> {
     let message = ctx.message()?;
     let (text, media_id, media_type) = if let Some(message) = message.get_reply_to_message() {
-        let (media_id, media_type) = get_media_type(message)?;
+        let (media_id, media_type) = get_media_type(message).await?;
 
         (
             message.get_text().map(|t| t.to_owned()),
@@ -37,7 +37,7 @@ fn rules_model(ctx: &Context) -> Result<rules::Model> {
             media_type,
         )
     } else {
-        let (media_id, media_type) = get_media_type(message)?;
+        let (media_id, media_type) = get_media_type(message).await?;
         let text = ctx.cmd().map(|Cmd { ref args, .. }| args.text.to_owned());
         (text, media_id, media_type)
     };
@@ -62,7 +62,7 @@ async fn save_rule<'a>(ctx: &Context) -> Result<()> {
     ctx.check_permissions(|p| p.can_change_info).await?;
     let message = ctx.message()?;
     let key = get_rules_key(message.get_chat().get_id());
-    let model = rules_model(ctx)?;
+    let model = rules_model(ctx).await?;
     rules::Entity::insert(model.cache(&key).await?)
         .on_conflict(
             OnConflict::column(rules::Column::ChatId)
@@ -107,7 +107,7 @@ async fn get_rule(chat_id: i64) -> Result<Option<rules::Model>> {
             let r = rules::Entity::find_by_id(chat_id).one(*DB).await?;
             Ok(r)
         },
-        Duration::try_seconds(CONFIG.timing.cache_timeout).unwrap(),
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
     )
     .query(&key, &())
     .await?;
@@ -121,8 +121,7 @@ pub async fn handle_update(ctx: &Context) -> Result<()> {
             "setrules" => save_rule(ctx).await,
             "rules" => rules(ctx).await,
             "start" => {
-                let key: Option<i64> = handle_deep_link(ctx, rules_deeplink_key).await?;
-                if let Some(chat_id) = key {
+                if let Some(&DeepLink::Rules(chat_id)) = ctx.deep_link().await? {
                     let rules = if let Some(rules) = get_rule(chat_id).await? {
                         rules
                     } else {
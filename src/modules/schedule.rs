@@ -0,0 +1,96 @@
+use crate::metadata::metadata;
+use crate::tg::command::{Cmd, Context, TextArgs};
+use crate::tg::markdown::Escape;
+use crate::tg::permissions::IsGroupAdmin;
+use crate::tg::scheduler::{cancel_job, get_chat_jobs, log_cancelled};
+use crate::tg::user::Username;
+use crate::util::error::{Fail, Result};
+use crate::util::string::Speak;
+use crate::util::time::ChatTime;
+use macros::{lang_fmt, update_handler};
+use uuid::Uuid;
+
+metadata!("Scheduling",
+    r#"
+    Schedule settings changes for later. Add a time to the end of a settings command and it
+    will be run as if you sent it at that time. Times are in the chat's timezone, which is UTC
+    unless changed with /settz. Examples:
+    /lock links at 22:00
+    /unlock links at tomorrow 08:00
+    /warnlimit 5 from friday
+    /warnmode ban in 2h
+
+    Supported commands are lock, unlock, lockaction, warnlimit, warntime, warnmode, captcha,
+    captchamode, captchakick, and welcome. Executed commands are reported to the log channel
+    if one is set.
+    "#,
+    { command = "schedules", help = "List pending scheduled commands" },
+    { command = "unschedule", help = "Cancel a scheduled command by id" }
+);
+
+async fn schedules(ctx: &Context) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    let chat = ctx.try_get()?.chat;
+    let jobs = get_chat_jobs(chat.get_id()).await?;
+    let local = ChatTime::get(chat.get_id()).await?;
+    if jobs.is_empty() {
+        ctx.reply(lang_fmt!(ctx, "noschedules")).await?;
+    } else {
+        let list = jobs
+            .iter()
+            .map(|job| {
+                format!(
+                    "{}: {}\n[`{}]",
+                    local.format(&job.run_at),
+                    job.describe().escape(false),
+                    job.id
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n\n");
+        ctx.reply(lang_fmt!(
+            ctx,
+            "listschedules",
+            chat.name_humanreadable(),
+            list
+        ))
+        .await?;
+    }
+    Ok(())
+}
+
+async fn unschedule<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    let chat = ctx.try_get()?.chat;
+    let id = match Uuid::parse_str(args.text.trim()) {
+        Ok(id) => id,
+        Err(_) => return ctx.fail(lang_fmt!(ctx, "schedulenotfound")),
+    };
+    if !cancel_job(chat.get_id(), &id).await? {
+        return ctx.fail(lang_fmt!(ctx, "schedulenotfound"));
+    }
+    let by = ctx.get_real_from()?.name_humanreadable();
+    log_cancelled(chat, &id, &by).await?;
+    ctx.reply(lang_fmt!(ctx, "unscheduled", id)).await?;
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
+        match cmd {
+            "schedules" => schedules(ctx).await,
+            "unschedule" => unschedule(ctx, args).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+
+    Ok(())
+}
@@ -3,9 +3,10 @@ use crate::tg::admin_helpers::ActionMessage;
 use crate::tg::command::{Cmd, Context};
 use crate::tg::markdown::MarkupType;
 use crate::util::error::{Fail, Result, SpeakErr};
+use crate::util::error_codes::MISSING_ARGUMENT;
 use crate::util::scripting::{ManagedRhai, RHAI_ENGINE};
 use crate::util::string::Speak;
-use macros::{entity_fmt, update_handler};
+use macros::{entity_fmt, lang_fmt, update_handler};
 use rhai::Dynamic;
 
 metadata!(
@@ -75,7 +76,8 @@ metadata!(
 
 async fn map_script(ctx: &Context) -> Result<()> {
     ctx.action_message(|ctx, am, args| async move {
-        let args = args.ok_or_else(|| ctx.fail_err("missing arg"))?;
+        let args = args
+            .ok_or_else(|| ctx.fail_err_code(MISSING_ARGUMENT, lang_fmt!(ctx, "errmissingarg")))?;
         let text = args.text.to_owned();
         let message = match am {
             ActionMessage::Me(m) => m,
@@ -0,0 +1,210 @@
+use crate::metadata::metadata;
+use crate::tg::admin_helpers::is_dm;
+use crate::tg::button::ButtonAction;
+use crate::tg::command::{Cmd, Context};
+use crate::tg::dialog::{Conversation, ConversationState};
+use crate::tg::extract::{CanChangeInfo, InGroup, RequirePerm};
+use crate::tg::markdown::{EntityMessage, Escape};
+use crate::tg::permissions::IsAdmin;
+use crate::tg::setup::SetupChoice;
+use crate::tg::user::Username;
+use crate::util::error::Result;
+use crate::util::string::{get_chat_lang, get_langs, Lang, Speak};
+use botapi::gen_types::{Chat, ChatMember, EReplyMarkup, UpdateExt, User};
+use macros::{lang_fmt, update_handler};
+
+metadata!("Setup",
+    r#"
+    Walks admins through setting up a chat. When I'm added to a group, the admin who added me
+    gets a message with buttons to pick the chat's language, whether new members are welcomed,
+    whether they have to solve a captcha and whether to use a log channel. The message is sent
+    in private, or in the group if the admin hasn't started me yet. The recommended choice for
+    each step is marked, and everything can be changed later with the usual commands
+    "#,
+    { command = "setup", help = "Go through the setup again", perms = [CanChangeInfo] }
+);
+
+/// Whether welcomes are recommended for new chats
+const WELCOME_DEFAULT: bool = true;
+
+/// Whether captchas are recommended for new chats
+const CAPTCHA_DEFAULT: bool = false;
+
+fn choice_name(lang: &Lang, name: String, recommended: bool) -> String {
+    if recommended {
+        lang_fmt!(lang, "setuprecommended", name)
+    } else {
+        name
+    }
+}
+
+/// Builds the setup for a chat, to be posted in `posted`. Every language gets its own copy of
+/// the later steps, so they are asked in the language that was picked. Choices are applied
+/// by the stored buttons when their step is entered
+async fn setup_conversation(chat: &Chat, posted: i64, user: &User) -> Result<Conversation> {
+    let current = get_chat_lang(chat.get_id()).await?;
+    let suggested = user
+        .get_language_code()
+        .map(Lang::from_code)
+        .filter(|lang| *lang != Lang::Invalid)
+        .unwrap_or(current);
+    let name = chat.name_humanreadable();
+    let name = name.escape(false);
+    let mut state = ConversationState::new_prefix(
+        "setup".to_owned(),
+        lang_fmt!(suggested, "setupstart", name),
+        posted,
+        user.get_id(),
+        &format!("setup{}", chat.get_id()),
+    )?;
+    state.owner_only();
+
+    let chat = chat.get_id();
+    let start = state.get_start()?.state_id;
+    for lang in get_langs() {
+        let code = lang.into_code();
+        let on = lang_fmt!(lang, "setupon");
+        let off = lang_fmt!(lang, "setupoff");
+        let step = |state: &mut ConversationState, text: String, choice: SetupChoice| {
+            let id = state.add_state(text);
+            state.on_enter(id, ButtonAction::Setup { chat, choice });
+            id
+        };
+
+        let welcome = step(
+            &mut state,
+            lang_fmt!(lang, "setupwelcome"),
+            SetupChoice::Lang(lang),
+        );
+        state.add_transition(
+            start,
+            welcome,
+            code.to_owned(),
+            choice_name(&suggested, code.to_owned(), lang == suggested),
+        );
+
+        let captcha = lang_fmt!(lang, "setupcaptcha");
+        let captcha_on = step(&mut state, captcha.clone(), SetupChoice::Welcome(true));
+        let captcha_off = step(&mut state, captcha, SetupChoice::Welcome(false));
+        state.add_transition(
+            welcome,
+            captcha_on,
+            "on".to_owned(),
+            choice_name(&lang, on.clone(), WELCOME_DEFAULT),
+        );
+        state.add_transition(
+            welcome,
+            captcha_off,
+            "off".to_owned(),
+            choice_name(&lang, off.clone(), !WELCOME_DEFAULT),
+        );
+
+        let log = lang_fmt!(lang, "setuplog", name);
+        let log_on = step(&mut state, log.clone(), SetupChoice::Captcha(true));
+        let log_off = step(&mut state, log, SetupChoice::Captcha(false));
+        for captcha in [captcha_on, captcha_off] {
+            state.add_transition(
+                captcha,
+                log_on,
+                "on".to_owned(),
+                choice_name(&lang, on.clone(), CAPTCHA_DEFAULT),
+            );
+            state.add_transition(
+                captcha,
+                log_off,
+                "off".to_owned(),
+                choice_name(&lang, off.clone(), !CAPTCHA_DEFAULT),
+            );
+        }
+
+        let done = lang_fmt!(lang, "setupdone", name);
+        let done_log = step(&mut state, done.clone(), SetupChoice::LogSet);
+        let done_nolog = step(&mut state, done, SetupChoice::NoLog);
+        for log in [log_on, log_off] {
+            state.add_transition(
+                log,
+                done_log,
+                "log".to_owned(),
+                lang_fmt!(lang, "setuplogset"),
+            );
+            state.add_transition(
+                log,
+                done_nolog,
+                "nolog".to_owned(),
+                lang_fmt!(lang, "setupnolog"),
+            );
+        }
+    }
+
+    let conversation = state.build();
+    conversation.write_self().await?;
+    Ok(conversation)
+}
+
+async fn post_setup(conversation: &Conversation, posted: i64) -> Result<()> {
+    let text = conversation.get_current_text().await?;
+    let markup = conversation.get_current_markup(3).await?;
+    posted
+        .speak_fmt(
+            EntityMessage::from_text(posted, text)
+                .reply_markup(EReplyMarkup::InlineKeyboardMarkup(markup)),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Starts the setup when an admin adds the bot to a group, in private if the admin has
+/// started the bot and in the group otherwise
+async fn handle_added(ctx: &Context) -> Result<()> {
+    let UpdateExt::MyChatMember(member) = ctx.update() else {
+        return Ok(());
+    };
+    let added = matches!(
+        member.get_old_chat_member(),
+        ChatMember::ChatMemberLeft(_) | ChatMember::ChatMemberBanned(_)
+    ) && matches!(
+        member.get_new_chat_member(),
+        ChatMember::ChatMemberMember(_) | ChatMember::ChatMemberAdministrator(_)
+    );
+    let chat = member.get_chat();
+    if !added || is_dm(chat) || chat.get_tg_type() == "channel" {
+        return Ok(());
+    }
+    let user = member.get_from();
+    if user.get_is_bot() || !user.is_admin(chat).await? {
+        return Ok(());
+    }
+
+    let conversation = setup_conversation(chat, user.get_id(), user).await?;
+    if let Err(err) = post_setup(&conversation, user.get_id()).await {
+        log::debug!("couldn't send setup in private: {}", err);
+        let conversation = setup_conversation(chat, chat.get_id(), user).await?;
+        post_setup(&conversation, chat.get_id()).await?;
+    }
+    Ok(())
+}
+
+async fn setup(ctx: &Context, _: InGroup, _: RequirePerm<CanChangeInfo>) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let user = ctx.get_real_from()?;
+    let conversation = setup_conversation(chat, chat.get_id(), user).await?;
+    post_setup(&conversation, chat.get_id()).await
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+        match cmd {
+            "setup" => ctx.run(setup).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+    handle_added(cmd).await?;
+
+    Ok(())
+}
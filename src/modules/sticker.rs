@@ -6,23 +6,28 @@ use crate::persist::redis as r;
 use crate::statics::{DB, REDIS, TG};
 use crate::tg::admin_helpers::is_dm;
 use crate::tg::admin_helpers::is_dm_or_die;
+use crate::tg::button::{callback_data, CallbackReply, OnPush};
 use crate::tg::command::TextArg;
 use crate::tg::command::{Cmd, Context};
 use crate::tg::dialog::ConversationState;
 use crate::tg::dialog::{drop_converstaion, Conversation};
 use crate::tg::dialog::{get_conversation, replace_conversation};
-use crate::tg::user::GetUser;
-use crate::util::error::BotError;
-use crate::util::string::Speak;
+use crate::tg::markdown::{EntityMessage, Escape};
+use crate::tg::permissions::{IsAdmin, IsGroupAdmin};
+use crate::tg::user::{GetUser, Username};
+use crate::util::error::{BotError, Fail};
+use crate::util::error_codes::{MISSING_ARGUMENT, NOT_FOUND};
+use crate::util::string::{get_chat_lang, Speak};
 use ::redis::AsyncCommands;
 use ::sea_orm::entity::prelude::*;
-use ::sea_orm::{ActiveModelTrait, IntoActiveModel, QuerySelect, Set};
+use ::sea_orm::{ActiveModelTrait, IntoActiveModel, NotSet, QuerySelect, Set};
 use ::sea_orm_migration::prelude::*;
-use macros::update_handler;
+use macros::{lang_fmt, update_handler};
 
 use crate::util::error::Result;
 use botapi::gen_types::{
-    InlineQuery, InlineQueryResult, InlineQueryResultCachedSticker, Message, UpdateExt,
+    CallbackQuery, InlineKeyboardButtonBuilder, InlineQuery, InlineQueryResult,
+    InlineQueryResultCachedSticker, MaybeInaccessibleMessage, Message, UpdateExt,
 };
 use log::info;
 use r::{scope_key_by_chatuser, RedisStr};
@@ -30,6 +35,7 @@ use r::{scope_key_by_chatuser, RedisStr};
 const KEY_TYPE_TAG: &str = "wc:tag";
 const KEY_TYPE_STICKER_ID: &str = "wc:stickerid";
 const KEY_TYPE_STICKER_NAME: &str = "wc:stickername";
+const KEY_TYPE_BOARD: &str = "wc:board";
 
 // conversation state machine globals
 const UPLOAD_CMD: &str = "upload";
@@ -44,16 +50,34 @@ const STATE_NAME: &str = "Send a name for this sticker";
 const STATE_TAGS: &str = "Send tags for this sticker, one at a time. Send /done to stop";
 const STATE_DONE: &str = "Successfully uploaded sticker";
 
+const BOARD_USAGE: &str = r#"Usage:
+/board add \<tags\>: reply to a sticker to submit it to this chat's board
+/board list: list stickers on the board
+/board pending: list stickers waiting for approval
+/board remove \<uuid\>: remove a sticker from the board
+/board use: search this chat's board in inline mode
+/board leave: go back to searching your own stickers"#;
+
 metadata!("Sticker Organizer",
     r#"
     Use this bot in inline mode to organize your stickers
+
+    Groups can also keep a shared sticker board. Any member can submit a sticker with tags
+    using /board add, and once an admin approves it everyone in the chat can find it in inline
+    mode after running /board use in the chat.
     "#,
     Helper,
     { command = "upload", help = "Uploads a sticker" },
     { command = "list", help = "Lists available stickers"},
-    { command = "deletesticker", help = "Deletes a sticker by uuid"}
+    { command = "deletesticker", help = "Deletes a sticker by uuid"},
+    { command = "board", help = "Manage this chat's sticker board. Run without arguments for usage"}
 );
 
+#[inline(always)]
+fn get_board_key(user: i64) -> String {
+    format!("{}:{}", KEY_TYPE_BOARD, user)
+}
+
 fn upload_sticker_conversation(message: &Message) -> Result<Conversation> {
     let mut conversation = ConversationState::new(
         UPLOAD_CMD.to_string(),
@@ -92,14 +116,23 @@ fn upload_sticker_conversation(message: &Message) -> Result<Conversation> {
 
 struct Migration;
 
+struct MigrationBoards;
+
 impl MigrationName for Migration {
     fn name(&self) -> &str {
         "m20220412_000001_create_stickertag"
     }
 }
 
+impl MigrationName for MigrationBoards {
+    fn name(&self) -> &str {
+        "m20261016_000001_sticker_boards"
+    }
+}
+
 pub mod entities {
     use crate::persist::migrate::ManagerHelper;
+    use ::sea_orm::{DatabaseBackend, Statement};
     use ::sea_orm_migration::prelude::*;
     #[async_trait::async_trait]
     impl MigrationTrait for super::Migration {
@@ -176,6 +209,148 @@ pub mod entities {
             Ok(())
         }
     }
+    #[async_trait::async_trait]
+    impl MigrationTrait for super::MigrationBoards {
+        async fn up(&self, manager: &SchemaManager) -> std::result::Result<(), DbErr> {
+            manager
+                .drop_foreign_key(
+                    ForeignKey::drop()
+                        .table(tags::Entity)
+                        .name("sticker_id_fk")
+                        .to_owned(),
+                )
+                .await?;
+
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(stickers::Entity)
+                        .add_column(ColumnDef::new(stickers::Column::ChatId).big_integer())
+                        .add_column(
+                            ColumnDef::new(stickers::Column::Approved)
+                                .boolean()
+                                .not_null()
+                                .default(true),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(tags::Entity)
+                        .add_column(ColumnDef::new(tags::Column::ChatId).big_integer())
+                        .to_owned(),
+                )
+                .await?;
+
+            // existing stickers are personal collections, scoped to the owner's dm
+            let table = stickers::Entity.to_string();
+            let chat = stickers::Column::ChatId.to_string();
+            let owner = stickers::Column::OwnerId.to_string();
+            let unique_id = stickers::Column::UniqueId.to_string();
+            let tags_table = tags::Entity.to_string();
+            let tags_chat = tags::Column::ChatId.to_string();
+            let tags_owner = tags::Column::OwnerId.to_string();
+            for statement in [
+                format!("UPDATE {table} SET {chat} = {owner}"),
+                format!("ALTER TABLE {table} ALTER COLUMN {chat} SET NOT NULL"),
+                format!("UPDATE {tags_table} SET {tags_chat} = {tags_owner}"),
+                format!("ALTER TABLE {tags_table} ALTER COLUMN {tags_chat} SET NOT NULL"),
+                format!("ALTER TABLE {table} DROP CONSTRAINT {table}_pkey"),
+                format!("ALTER TABLE {table} ADD PRIMARY KEY ({unique_id}, {chat})"),
+            ] {
+                manager
+                    .get_connection()
+                    .query_one(Statement::from_string(DatabaseBackend::Postgres, statement))
+                    .await?;
+            }
+
+            manager
+                .create_foreign_key(
+                    ForeignKey::create()
+                        .name("sticker_id_fk")
+                        .from(
+                            tags::Entity,
+                            (tags::Column::StickerId, tags::Column::ChatId),
+                        )
+                        .to(
+                            stickers::Entity,
+                            (stickers::Column::UniqueId, stickers::Column::ChatId),
+                        )
+                        .on_delete(ForeignKeyAction::Cascade)
+                        .to_owned(),
+                )
+                .await?;
+
+            Ok(())
+        }
+
+        async fn down(&self, manager: &SchemaManager) -> std::result::Result<(), DbErr> {
+            manager
+                .drop_foreign_key(
+                    ForeignKey::drop()
+                        .table(tags::Entity)
+                        .name("sticker_id_fk")
+                        .to_owned(),
+                )
+                .await?;
+
+            // board stickers can't be represented without the chat scope, drop them
+            let table = stickers::Entity.to_string();
+            let chat = stickers::Column::ChatId.to_string();
+            let owner = stickers::Column::OwnerId.to_string();
+            let unique_id = stickers::Column::UniqueId.to_string();
+            let tags_table = tags::Entity.to_string();
+            let tags_chat = tags::Column::ChatId.to_string();
+            let tags_owner = tags::Column::OwnerId.to_string();
+            for statement in [
+                format!("DELETE FROM {tags_table} WHERE {tags_chat} <> {tags_owner}"),
+                format!("DELETE FROM {table} WHERE {chat} <> {owner}"),
+                format!("ALTER TABLE {table} DROP CONSTRAINT {table}_pkey"),
+                format!("ALTER TABLE {table} ADD PRIMARY KEY ({unique_id})"),
+            ] {
+                manager
+                    .get_connection()
+                    .query_one(Statement::from_string(DatabaseBackend::Postgres, statement))
+                    .await?;
+            }
+
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(stickers::Entity)
+                        .drop_column(stickers::Column::ChatId)
+                        .drop_column(stickers::Column::Approved)
+                        .to_owned(),
+                )
+                .await?;
+
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(tags::Entity)
+                        .drop_column(tags::Column::ChatId)
+                        .to_owned(),
+                )
+                .await?;
+
+            manager
+                .create_foreign_key(
+                    ForeignKey::create()
+                        .name("sticker_id_fk")
+                        .from(tags::Entity, tags::Column::StickerId)
+                        .to(stickers::Entity, stickers::Column::UniqueId)
+                        .on_delete(ForeignKeyAction::Cascade)
+                        .to_owned(),
+                )
+                .await?;
+
+            Ok(())
+        }
+    }
+
     pub mod tags {
         use sea_orm::entity::prelude::*;
         use serde::{Deserialize, Serialize};
@@ -187,6 +362,7 @@ pub mod entities {
             pub id: i64,
             pub sticker_id: String,
             pub owner_id: i64,
+            pub chat_id: i64,
             #[sea_orm(column_type = "Text")]
             pub tag: String,
         }
@@ -195,6 +371,7 @@ pub mod entities {
         pub struct ModelRedis {
             pub sticker_id: String,
             pub owner_id: i64,
+            pub chat_id: i64,
             pub tag: String,
         }
 
@@ -202,8 +379,8 @@ pub mod entities {
         pub enum Relation {
             #[sea_orm(
                 belongs_to = "super::stickers::Entity",
-                from = "Column::StickerId",
-                to = "super::stickers::Column::UniqueId"
+                from = "(Column::StickerId, Column::ChatId)",
+                to = "(super::stickers::Column::UniqueId, super::stickers::Column::ChatId)"
             )]
             Stickers,
         }
@@ -226,11 +403,16 @@ pub mod entities {
         pub struct Model {
             #[sea_orm(primary_key, auto_increment = false)]
             pub unique_id: String,
+            /// chat this sticker belongs to. For personal collections this is the owner's id
+            #[sea_orm(primary_key, auto_increment = false)]
+            pub chat_id: i64,
             pub owner_id: i64,
             #[sea_orm(unique)]
             pub uuid: Uuid,
             #[sea_orm(column_type = "Text", nullable)]
             pub chosen_name: Option<String>,
+            #[sea_orm(default = true)]
+            pub approved: bool,
         }
 
         #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -250,7 +432,7 @@ pub mod entities {
 }
 
 pub fn get_migrations() -> Vec<Box<dyn MigrationTrait>> {
-    vec![Box::new(Migration)]
+    vec![Box::new(Migration), Box::new(MigrationBoards)]
 }
 
 #[derive(Debug)]
@@ -276,6 +458,10 @@ impl ModuleHelpers for Helper {
 }
 
 async fn handle_inline(query: &InlineQuery) -> Result<()> {
+    // #notename queries are answered by notes
+    if query.get_query().starts_with('#') {
+        return Ok(());
+    }
     let id = query.get_from().get_id();
     let key = query.get_query().to_owned();
 
@@ -286,13 +472,18 @@ async fn handle_inline(query: &InlineQuery) -> Result<()> {
         }
     }
     let key = format!("%{}%", key);
+    let board_key = get_board_key(id);
+    let scope: Option<i64> = REDIS.sq(|q| q.get(&board_key)).await?;
+    let scope = scope.unwrap_or(id);
     let stickers = entities::stickers::Entity::find()
         .join(
             sea_orm::JoinType::InnerJoin,
             entities::stickers::Relation::Tags.def(),
         )
         .group_by(entities::stickers::Column::UniqueId)
-        .filter(entities::stickers::Column::OwnerId.eq(id))
+        .group_by(entities::stickers::Column::ChatId)
+        .filter(entities::stickers::Column::ChatId.eq(scope))
+        .filter(entities::stickers::Column::Approved.eq(true))
         .filter(entities::tags::Column::Tag.like(&key))
         .limit(10)
         .all(*DB)
@@ -318,6 +509,7 @@ async fn handle_inline(query: &InlineQuery) -> Result<()> {
 async fn handle_message(ctx: &Context) -> Result<()> {
     let cmd = ctx.try_get()?.command.as_ref();
     handle_command(ctx.message()?, cmd).await?;
+    handle_board(ctx).await?;
     handle_conversation(ctx.message()?).await?;
 
     Ok(())
@@ -367,8 +559,12 @@ async fn delete_sticker(message: &Message, args: &[TextArg<'_>]) -> Result<()> {
     if let Some(TextArg::Arg(uuid)) = args.first() {
         log::info!("uuid {}", uuid);
         let uuid = Uuid::from_str(uuid)?;
+        let owner = message
+            .get_from()
+            .ok_or_else(|| BotError::conversation_err("not a user"))?;
         entities::stickers::Entity::delete_many()
             .filter(entities::stickers::Column::Uuid.eq(uuid))
+            .filter(entities::stickers::Column::ChatId.eq(owner.get_id()))
             .exec(*DB)
             .await?;
 
@@ -383,7 +579,7 @@ async fn list_stickers(message: &Message) -> Result<()> {
     drop_converstaion(message).await?;
     if let Some(sender) = message.get_from() {
         let stickers = entities::stickers::Entity::find()
-            .filter(entities::stickers::Column::OwnerId.eq(sender.get_id()))
+            .filter(entities::stickers::Column::ChatId.eq(sender.get_id()))
             .all(*DB)
             .await?;
         let stickers = stickers
@@ -461,9 +657,11 @@ async fn conv_moretags(conversation: Conversation, message: &Message) -> Result<
 
             let sticker = entities::stickers::ActiveModel {
                 unique_id: Set(sticker_id),
+                chat_id: Set(user.get_id()),
                 owner_id: Set(user.get_id()),
                 uuid: Set(Uuid::new_v4()),
                 chosen_name: Set(Some(stickername)),
+                approved: Set(true),
             };
 
             sticker.insert(*DB).await?;
@@ -477,6 +675,7 @@ async fn conv_moretags(conversation: Conversation, message: &Message) -> Result<
             let tag = RedisStr::new(&ModelRedis {
                 sticker_id,
                 owner_id: user.get_id(),
+                chat_id: user.get_id(),
                 tag: text.to_string(),
             })?;
 
@@ -513,3 +712,221 @@ async fn handle_conversation(message: &Message) -> Result<()> {
     }
     Ok(())
 }
+
+async fn handle_board(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd {
+        cmd: "board",
+        ref args,
+        ..
+    }) = ctx.cmd()
+    {
+        let mut args = args.args.iter().map(|a| a.get_text());
+        let sub = args.next();
+        if sub == Some("leave") {
+            return board_leave(ctx).await;
+        }
+        ctx.is_group_or_die().await?;
+        match sub {
+            Some("add") => board_add(ctx, args.collect()).await,
+            Some("list") => board_list(ctx, true).await,
+            Some("pending") => board_list(ctx, false).await,
+            Some("remove") => board_remove(ctx, args.next()).await,
+            Some("use") => board_use(ctx).await,
+            _ => ctx.reply(BOARD_USAGE).await.map(|_| ()),
+        }?;
+    }
+    Ok(())
+}
+
+async fn board_add(ctx: &Context, tags: Vec<&str>) -> Result<()> {
+    let message = ctx.message()?;
+    let chat = message.get_chat();
+    let user = ctx.get_real_from()?;
+    let sticker = message
+        .get_reply_to_message()
+        .and_then(|m| m.get_sticker())
+        .ok_or_else(|| ctx.fail_err_code(MISSING_ARGUMENT, lang_fmt!(ctx, "errstickerreply")))?;
+    if tags.is_empty() {
+        return ctx.fail_code(MISSING_ARGUMENT, lang_fmt!(ctx, "errstickertags"));
+    }
+
+    let unique_id = sticker.get_file_id().to_owned();
+    let chat_id = chat.get_id();
+    if entities::stickers::Entity::find_by_id((unique_id.clone(), chat_id))
+        .one(*DB)
+        .await?
+        .is_some()
+    {
+        return ctx.fail(lang_fmt!(ctx, "errstickerexists"));
+    }
+
+    let approved = user.is_admin(chat).await?;
+    let uuid = Uuid::new_v4();
+    entities::stickers::ActiveModel {
+        unique_id: Set(unique_id.clone()),
+        chat_id: Set(chat_id),
+        owner_id: Set(user.get_id()),
+        uuid: Set(uuid),
+        chosen_name: NotSet,
+        approved: Set(approved),
+    }
+    .insert(*DB)
+    .await?;
+
+    let tags = tags.into_iter().map(|tag| entities::tags::ActiveModel {
+        id: NotSet,
+        sticker_id: Set(unique_id.clone()),
+        owner_id: Set(user.get_id()),
+        chat_id: Set(chat_id),
+        tag: Set(tag.to_owned()),
+    });
+    entities::tags::Entity::insert_many(tags).exec(*DB).await?;
+
+    if approved {
+        ctx.reply(lang_fmt!(ctx, "boardadded")).await?;
+        return Ok(());
+    }
+
+    let mut text = EntityMessage::new(chat_id);
+    text.builder.text(lang_fmt!(
+        ctx,
+        "boardsubmitted",
+        user.name_humanreadable_unescape().escape(false)
+    ));
+    let approve = InlineKeyboardButtonBuilder::new(lang_fmt!(ctx, "boardapprove"))
+        .set_callback_data(callback_data(None))
+        .build();
+    let reject = InlineKeyboardButtonBuilder::new(lang_fmt!(ctx, "boardreject"))
+        .set_callback_data(callback_data(None))
+        .build();
+    approve.on_push_multi(move |cb| async move { review_board_sticker(cb, uuid, true).await });
+    reject.on_push_multi(move |cb| async move { review_board_sticker(cb, uuid, false).await });
+    text.builder.buttons.button(approve);
+    text.builder.buttons.button(reject);
+    ctx.reply_fmt(text).await?;
+    Ok(())
+}
+
+async fn review_board_sticker(
+    cb: CallbackQuery,
+    uuid: Uuid,
+    approve: bool,
+) -> Result<(bool, CallbackReply)> {
+    let Some(MaybeInaccessibleMessage::Message(message)) = cb.get_message() else {
+        return Ok((true, CallbackReply::default()));
+    };
+    let chat = message.get_chat();
+    let lang = get_chat_lang(chat.get_id()).await?;
+    if !cb.get_from().get_permissions(chat).await?.can_change_info {
+        return Ok((
+            false,
+            CallbackReply::alert(lang_fmt!(lang, "boardnotadmin")),
+        ));
+    }
+
+    let text = if approve {
+        entities::stickers::Entity::update_many()
+            .filter(entities::stickers::Column::Uuid.eq(uuid))
+            .filter(entities::stickers::Column::ChatId.eq(chat.get_id()))
+            .set(entities::stickers::ActiveModel {
+                unique_id: NotSet,
+                chat_id: NotSet,
+                owner_id: NotSet,
+                uuid: NotSet,
+                chosen_name: NotSet,
+                approved: Set(true),
+            })
+            .exec(*DB)
+            .await?;
+        lang_fmt!(lang, "boardapproved")
+    } else {
+        entities::stickers::Entity::delete_many()
+            .filter(entities::stickers::Column::Uuid.eq(uuid))
+            .filter(entities::stickers::Column::ChatId.eq(chat.get_id()))
+            .exec(*DB)
+            .await?;
+        lang_fmt!(lang, "boardrejected")
+    };
+
+    TG.client
+        .build_edit_message_reply_markup()
+        .message_id(message.get_message_id())
+        .chat_id(chat.get_id())
+        .build()
+        .await?;
+    TG.client
+        .build_edit_message_text(&text)
+        .message_id(message.get_message_id())
+        .chat_id(chat.get_id())
+        .build()
+        .await?;
+    Ok((true, CallbackReply::default()))
+}
+
+async fn board_list(ctx: &Context, approved: bool) -> Result<()> {
+    if !approved {
+        ctx.check_permissions(|p| p.can_change_info).await?;
+    }
+    let chat = ctx.try_get()?.chat;
+    let stickers = entities::stickers::Entity::find()
+        .filter(entities::stickers::Column::ChatId.eq(chat.get_id()))
+        .filter(entities::stickers::Column::Approved.eq(approved))
+        .find_with_related(entities::tags::Entity)
+        .all(*DB)
+        .await?;
+    let header = if approved {
+        lang_fmt!(ctx, "boardlist", chat.name_humanreadable().escape(false))
+    } else {
+        lang_fmt!(ctx, "boardpending", chat.name_humanreadable().escape(false))
+    };
+    let stickers = stickers.into_iter().fold(header, |mut s, (sticker, tags)| {
+        let tags = tags
+            .into_iter()
+            .map(|t| t.tag)
+            .collect::<Vec<String>>()
+            .join(", ");
+        s.push_str(format!("\n - {} {}", tags.escape(false), sticker.uuid).as_str());
+        s
+    });
+    ctx.reply(stickers).await?;
+    Ok(())
+}
+
+async fn board_remove(ctx: &Context, uuid: Option<&str>) -> Result<()> {
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    let uuid = uuid
+        .and_then(|u| Uuid::from_str(u).ok())
+        .ok_or_else(|| ctx.fail_err_code(MISSING_ARGUMENT, lang_fmt!(ctx, "errstickeruuid")))?;
+    let res = entities::stickers::Entity::delete_many()
+        .filter(entities::stickers::Column::Uuid.eq(uuid))
+        .filter(entities::stickers::Column::ChatId.eq(ctx.try_get()?.chat.get_id()))
+        .exec(*DB)
+        .await?;
+    if res.rows_affected == 0 {
+        return ctx.fail_code(NOT_FOUND, lang_fmt!(ctx, "errstickermissing"));
+    }
+    ctx.reply(lang_fmt!(ctx, "boardremoved")).await?;
+    Ok(())
+}
+
+async fn board_use(ctx: &Context) -> Result<()> {
+    let user = ctx.get_real_from()?;
+    let chat = ctx.try_get()?.chat;
+    let key = get_board_key(user.get_id());
+    REDIS.sq(|q| q.set(&key, chat.get_id())).await?;
+    ctx.reply(lang_fmt!(
+        ctx,
+        "boarduse",
+        chat.name_humanreadable().escape(false)
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn board_leave(ctx: &Context) -> Result<()> {
+    let user = ctx.get_real_from()?;
+    let key = get_board_key(user.get_id());
+    REDIS.sq(|q| q.del(&key)).await?;
+    ctx.reply(lang_fmt!(ctx, "boardleave")).await?;
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use crate::metadata::metadata;
+use crate::statics::TG;
+use crate::tg::command::{Cmd, Context};
+use crate::tg::extract::{CanManageTopics, CommandArgs, InGroup, RequirePerm};
+use crate::tg::permissions::self_admin_or_die;
+use crate::util::error::{Fail, Result};
+use crate::util::string::{Confirm, Speak};
+use botapi::gen_types::Message;
+use macros::{lang_fmt, update_handler};
+
+metadata!("Topics",
+    r#"
+    Manage forum topics without leaving the chat. Commands that change a topic work on the
+    topic they are sent in.
+    "#,
+    { command = "newtopic", help = "Create a forum topic.", usage = "newtopicusage", perms = [CanManageTopics] },
+    { command = "renametopic", help = "Rename the current topic.", usage = "renametopicusage", perms = [CanManageTopics] },
+    { command = "closetopic", help = "Close the current topic", perms = [CanManageTopics] },
+    { command = "reopentopic", help = "Reopen the current topic", perms = [CanManageTopics] },
+    { command = "topicicon", help = "Set the current topic's icon to a custom emoji sent with the command or in the replied message. Use /topicicon clear to remove it", perms = [CanManageTopics] }
+);
+
+/// Gets the chat and forum topic a command was sent in, failing outside of forum topics
+async fn topic_or_die(ctx: &Context) -> Result<(i64, i64)> {
+    let chat = ctx.try_get()?.chat;
+    if !chat.get_is_forum().unwrap_or(false) {
+        return ctx.fail(lang_fmt!(ctx, "notforum"));
+    }
+    let Some(thread) = ctx.thread() else {
+        return ctx.fail(lang_fmt!(ctx, "notopic"));
+    };
+    self_admin_or_die(chat).await?;
+    Ok((chat.get_id(), thread))
+}
+
+/// Finds the first custom emoji in a message, or else in the message it replies to
+fn custom_emoji(message: &Message) -> Option<String> {
+    let reply = message.get_reply_to_message();
+    message
+        .get_entities()
+        .into_iter()
+        .chain(reply.and_then(|reply| reply.get_entities()))
+        .flat_map(|entities| entities.iter())
+        .find_map(|entity| entity.get_custom_emoji_id().map(|id| id.to_owned()))
+}
+
+async fn new_topic(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanManageTopics>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    if !chat.get_is_forum().unwrap_or(false) {
+        return ctx.fail(lang_fmt!(ctx, "notforum"));
+    }
+    let name = args.text.trim();
+    if name.is_empty() {
+        return ctx.fail(lang_fmt!(ctx, "topicnoname"));
+    }
+    self_admin_or_die(chat).await?;
+    let topic = TG
+        .client
+        .build_create_forum_topic(chat.get_id(), name)
+        .build()
+        .await?;
+    ctx.reply(lang_fmt!(ctx, "topiccreated", topic.get_name()))
+        .await?;
+    Ok(())
+}
+
+async fn rename_topic(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanManageTopics>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let (chat, thread) = topic_or_die(ctx).await?;
+    let name = args.text.trim();
+    if name.is_empty() {
+        return ctx.fail(lang_fmt!(ctx, "topicnoname"));
+    }
+    TG.client
+        .build_edit_forum_topic(chat, thread)
+        .name(name)
+        .build()
+        .await?;
+    ctx.confirm(lang_fmt!(ctx, "topicrenamed", name)).await?;
+    Ok(())
+}
+
+async fn close_topic(ctx: &Context, _: InGroup, _: RequirePerm<CanManageTopics>) -> Result<()> {
+    let (chat, thread) = topic_or_die(ctx).await?;
+    // reply before closing, members can't post in closed topics
+    ctx.reply(lang_fmt!(ctx, "topicclosed")).await?;
+    TG.client
+        .build_close_forum_topic(chat, thread)
+        .build()
+        .await?;
+    Ok(())
+}
+
+async fn reopen_topic(ctx: &Context, _: InGroup, _: RequirePerm<CanManageTopics>) -> Result<()> {
+    let (chat, thread) = topic_or_die(ctx).await?;
+    TG.client
+        .build_reopen_forum_topic(chat, thread)
+        .build()
+        .await?;
+    ctx.reply(lang_fmt!(ctx, "topicreopened")).await?;
+    Ok(())
+}
+
+async fn topic_icon(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanManageTopics>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let (chat, thread) = topic_or_die(ctx).await?;
+    let message = ctx.message()?;
+    let icon = if args.text.trim() == "clear" {
+        String::new()
+    } else if let Some(icon) = custom_emoji(message) {
+        icon
+    } else {
+        return ctx.fail(lang_fmt!(ctx, "topicnoicon"));
+    };
+    TG.client
+        .build_edit_forum_topic(chat, thread)
+        .icon_custom_emoji_id(&icon)
+        .build()
+        .await?;
+    if icon.is_empty() {
+        ctx.confirm(lang_fmt!(ctx, "topiciconcleared")).await?;
+    } else {
+        ctx.confirm(lang_fmt!(ctx, "topiciconset")).await?;
+    }
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+        match cmd {
+            "newtopic" => ctx.run(new_topic).await,
+            "renametopic" => ctx.run(rename_topic).await,
+            "closetopic" => ctx.run(close_topic).await,
+            "reopentopic" => ctx.run(reopen_topic).await,
+            "topicicon" => ctx.run(topic_icon).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(ctx: &Context) -> Result<()> {
+    handle_command(ctx).await
+}
@@ -0,0 +1,183 @@
+use crate::metadata::metadata;
+use crate::persist::core::dialogs;
+use crate::statics::DB;
+use crate::tg::command::{Cmd, Context};
+use crate::tg::dialog::{dialog_or_default, dialog_scope};
+use crate::tg::extract::{CanManageVideoChats, CommandArgs, InGroup, RequirePerm};
+use crate::tg::markdown::Escape;
+use crate::tg::scheduler::parse_schedule_time;
+use crate::tg::video_chats::{handle_video_chat, schedule_video_chat_reminder, MAX_REMINDERS};
+use crate::util::duration::parse_duration;
+use crate::util::error::{Fail, Result};
+use crate::util::error_codes::INVALID_ARGUMENT;
+use crate::util::string::{Confirm, Speak};
+use crate::util::time::ChatTime;
+use botapi::gen_types::{Chat, UpdateExt};
+use chrono::{DateTime, Utc};
+use macros::{lang_fmt, update_handler};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::EntityTrait;
+
+metadata!("Video chats",
+    r#"
+    Keep track of video chats. Video chats being scheduled, started, and ended, and members
+    being invited to them, are reported to the log channel if one is set. With /vcannounce on
+    they are announced in the chat as well.
+
+    Bots can't schedule video chats themselves, so /vcschedule posts a reminder in the chat
+    at the given time instead, with an optional note. Times are in the chat's timezone:
+    /vcschedule 20:00 weekly call
+    /vcschedule friday 18:00
+    /vcschedule in 2h
+    Pending reminders are listed by /schedules and can be cancelled with /unschedule
+    "#,
+    { command = "vcannounce", help = "Announce video chats in the chat.", usage = "vcannounceusage" },
+    { command = "vcschedule", help = "Post a video chat reminder later.", usage = "vcscheduleusage", perms = [CanManageVideoChats] }
+);
+
+/// Splits /vcschedule's arguments into when to post the reminder and its note
+fn parse_reminder(
+    text: &str,
+    now: DateTime<Utc>,
+    local: &ChatTime,
+) -> Option<(DateTime<Utc>, String)> {
+    let words = text.split_whitespace().collect::<Vec<&str>>();
+    let (time, used) = match words.as_slice() {
+        ["in", duration, ..] => (now.checked_add_signed(parse_duration(duration).ok()?)?, 2),
+        // a time spec is at most a day, "at", and a time of day
+        _ => (1..=words.len().min(3)).rev().find_map(|len| {
+            parse_schedule_time(&words[..len].join(" "), now, local).map(|time| (time, len))
+        })?,
+    };
+    Some((time, words[used..].join(" ")))
+}
+
+async fn set_announce_video_chats(chat: &Chat, announce: bool) -> Result<()> {
+    let mut model = dialogs::Model::from_chat(chat).await?;
+    model.announce_video_chats = Set(announce);
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_column(dialogs::Column::AnnounceVideoChats)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat.get_id()).invalidate().await?;
+    Ok(())
+}
+
+async fn vcannounce(ctx: &Context, _: InGroup, CommandArgs(args): CommandArgs<'_>) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let announce = match args.text.trim() {
+        "" => {
+            let text = if dialog_or_default(chat).await?.announce_video_chats {
+                lang_fmt!(ctx, "vcannounceon")
+            } else {
+                lang_fmt!(ctx, "vcannounceoff")
+            };
+            ctx.reply(text).await?;
+            return Ok(());
+        }
+        "on" | "yes" => true,
+        "off" | "no" => false,
+        _ => return ctx.usage_err("vcannounceusage"),
+    };
+
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    set_announce_video_chats(chat, announce).await?;
+    let text = if announce {
+        lang_fmt!(ctx, "vcannounceon")
+    } else {
+        lang_fmt!(ctx, "vcannounceoff")
+    };
+    ctx.confirm(text).await?;
+    Ok(())
+}
+
+async fn vcschedule(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanManageVideoChats>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    if args.text.trim().is_empty() {
+        return ctx.usage_err("vcscheduleusage");
+    }
+    let chat = ctx.try_get()?.chat.get_id();
+    let local = ChatTime::get(chat).await?;
+    let Some((run_at, note)) = parse_reminder(args.text, Utc::now(), &local) else {
+        return ctx.usage_err("vcscheduleusage");
+    };
+    let note = (!note.is_empty()).then_some(note);
+    let Some(job) = schedule_video_chat_reminder(chat, run_at, note.clone()).await? else {
+        return ctx.fail_code(
+            INVALID_ARGUMENT,
+            lang_fmt!(ctx, "vctoomanyreminders", MAX_REMINDERS),
+        );
+    };
+    let time = local.format_lang(&run_at, ctx.lang());
+    let text = match note {
+        Some(note) => lang_fmt!(ctx, "vcschedulednote", time, note.escape(false), job.id),
+        None => lang_fmt!(ctx, "vcreminderset", time, job.id),
+    };
+    ctx.reply(text).await?;
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+        match cmd {
+            "vcannounce" => ctx.run(vcannounce).await,
+            "vcschedule" => ctx.run(vcschedule).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    handle_command(cmd).await?;
+    if let UpdateExt::Message(ref message) = cmd.update() {
+        handle_video_chat(cmd.lang(), message).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn reminder_times() {
+        let now = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let local = ChatTime::default();
+        assert_eq!(
+            parse_reminder("20:00 weekly call", now, &local),
+            Some((
+                Utc.with_ymd_and_hms(2024, 5, 1, 20, 0, 0).unwrap(),
+                "weekly call".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse_reminder("friday at 18:00", now, &local),
+            Some((
+                Utc.with_ymd_and_hms(2024, 5, 3, 18, 0, 0).unwrap(),
+                String::new()
+            ))
+        );
+        assert_eq!(
+            parse_reminder("in 2h", now, &local),
+            Some((
+                Utc.with_ymd_and_hms(2024, 5, 1, 14, 0, 0).unwrap(),
+                String::new()
+            ))
+        );
+        assert_eq!(parse_reminder("someday", now, &local), None);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::metadata::metadata;
+use crate::persist::core::dialogs;
+use crate::statics::DB;
+use crate::tg::command::{Cmd, Context};
+use crate::tg::dialog::{dialog_or_default, dialog_scope};
+use crate::tg::extract::{InGroup, TargetUser};
+use crate::tg::permissions::BotPermissions;
+use crate::tg::settings::{Setting, SettingKind, SettingValue, SettingsProvider};
+use crate::tg::voteban::{handle_poll_answer, start_vote, vote_duration, DEFAULT_MIN_AGE};
+use crate::util::error::{BotError, Result};
+use async_trait::async_trait;
+use botapi::gen_types::{Chat, UpdateExt};
+use macros::update_handler;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::EntityTrait;
+
+metadata!("Vote Ban",
+    r#"
+    Let members vote to remove someone. /voteban posts a poll in the chat, and once enough
+    members vote yes the user is banned, or muted if the chat prefers. Votes that don't pass
+    in time are closed.
+
+    Only members of the chat whose accounts are old enough can start a vote or have their
+    vote counted, admins can't be voted on, and any admin who can restrict members can veto a
+    vote with the button on the poll.
+
+    Votes are off until an admin sets the number of yes votes needed on the /settings panel,
+    along with how long votes stay open, the minimum account age, and whether to ban or mute
+    "#,
+    { command = "voteban", help = "Start a vote to ban a user.", usage = "votebanusage" }
+);
+
+/// Most yes votes the /settings panel can require
+const MAX_THRESHOLD: i64 = 50;
+
+/// Longest a vote can stay open, in minutes
+const MAX_DURATION: i64 = 60;
+
+/// Highest minimum account age the /settings panel can set, in days
+const MAX_MIN_AGE: i64 = 30;
+
+async fn voteban(ctx: &Context, _: InGroup, target: TargetUser<'_>) -> Result<()> {
+    start_vote(ctx, target.id).await
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+        match cmd {
+            "voteban" => ctx.run(voteban).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+/// Updates the voteban columns of a chat's dialog from a setting on the panel
+async fn set_voteban(chat: &Chat, setting: &str, value: SettingValue) -> Result<()> {
+    let mut model = dialogs::Model::from_chat(chat).await?;
+    let column = match (setting, value) {
+        ("threshold", SettingValue::Number(0)) => {
+            model.voteban_threshold = Set(None);
+            dialogs::Column::VotebanThreshold
+        }
+        ("threshold", SettingValue::Number(threshold)) if threshold > 0 => {
+            model.voteban_threshold = Set(Some(threshold as i32));
+            dialogs::Column::VotebanThreshold
+        }
+        ("duration", SettingValue::Number(minutes)) if minutes > 0 => {
+            model.voteban_duration = Set(Some(minutes * 60));
+            dialogs::Column::VotebanDuration
+        }
+        ("minage", SettingValue::Number(days)) if days >= 0 => {
+            model.voteban_min_age = Set(Some(days as i32));
+            dialogs::Column::VotebanMinAge
+        }
+        ("action", SettingValue::Choice(action)) => {
+            model.voteban_mute = Set(action == "mute");
+            dialogs::Column::VotebanMute
+        }
+        _ => {
+            return Err(BotError::generic(format!(
+                "invalid voteban setting {}",
+                setting
+            )))
+        }
+    };
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_column(column)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat.get_id()).invalidate().await?;
+    Ok(())
+}
+
+/// Shows the vote threshold, duration, minimum account age, and action on the /settings panel
+pub struct Settings;
+
+#[async_trait]
+impl SettingsProvider for Settings {
+    fn module(&self) -> &'static str {
+        "voteban"
+    }
+
+    fn title(&self) -> &'static str {
+        "Vote Ban"
+    }
+
+    fn allowed(&self, permissions: &BotPermissions) -> bool {
+        permissions.can_restrict_members
+    }
+
+    async fn get_settings(&self, chat: &Chat) -> Result<Vec<Setting>> {
+        let dialog = dialog_or_default(chat).await?;
+        let action = if dialog.voteban_mute { "mute" } else { "ban" };
+        Ok(vec![
+            Setting {
+                id: "threshold".to_owned(),
+                name: "Votes needed (0 is off)".to_owned(),
+                kind: SettingKind::Number {
+                    value: dialog.voteban_threshold.unwrap_or(0).into(),
+                    min: 0,
+                    max: MAX_THRESHOLD,
+                },
+            },
+            Setting {
+                id: "duration".to_owned(),
+                name: "Minutes open".to_owned(),
+                kind: SettingKind::Number {
+                    value: vote_duration(&dialog).num_minutes().max(1),
+                    min: 1,
+                    max: MAX_DURATION,
+                },
+            },
+            Setting {
+                id: "minage".to_owned(),
+                name: "Minimum account age (days)".to_owned(),
+                kind: SettingKind::Number {
+                    value: dialog.voteban_min_age.unwrap_or(DEFAULT_MIN_AGE).into(),
+                    min: 0,
+                    max: MAX_MIN_AGE,
+                },
+            },
+            Setting {
+                id: "action".to_owned(),
+                name: "Action".to_owned(),
+                kind: SettingKind::Choice {
+                    value: action.to_owned(),
+                    options: vec!["ban", "mute"],
+                },
+            },
+        ])
+    }
+
+    async fn set_setting(&self, chat: &Chat, setting: &str, value: SettingValue) -> Result<()> {
+        set_voteban(chat, setting, value).await
+    }
+}
+
+#[update_handler(settings = Settings)]
+pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
+    if let UpdateExt::PollAnswer(ref answer) = cmd.update() {
+        handle_poll_answer(cmd, answer).await?;
+    }
+    handle_command(cmd).await?;
+
+    Ok(())
+}
@@ -1,14 +1,23 @@
+use crate::persist::admin::modlog::ModAction;
+use crate::persist::core::media::{GetMediaId, MediaType};
 use crate::tg::command::{Cmd, Context};
-use crate::tg::markdown::remove_fillings;
+use crate::tg::dialog::dialog_or_default;
+use crate::tg::extract::{CommandArgs, InGroup, TargetUser};
+use crate::tg::markdown::{remove_fillings, Escape};
+use crate::tg::modlog::record_action;
+use crate::tg::settings::{Setting, SettingKind, SettingValue, SettingsProvider};
+use crate::tg::shame::{get_shame_list, set_shame_media};
 use crate::tg::user::{GetUser, Username};
 use crate::util::error::{BotError, Fail, SpeakErr};
 
 use crate::{
-    metadata::metadata, tg::admin_helpers::*, tg::command::TextArgs, tg::permissions::*,
-    util::error::Result, util::string::Speak,
+    metadata::metadata, tg::admin_helpers::*, tg::permissions::*, util::error::Result,
+    util::string::Confirm, util::string::Speak,
 };
 
-use humantime::format_duration;
+use async_trait::async_trait;
+use botapi::gen_types::Chat;
+use chrono::Utc;
 use macros::{entity_fmt, lang_fmt, update_handler};
 
 metadata!("Warns",
@@ -19,44 +28,37 @@ metadata!("Warns",
     After a user gets a set amount of warnings \(default 3\) the action specified by the /warnmode will
     be applied. The default action is to mute the user.
 
+    In shame mode the user isn't restricted at all. Instead their warns are posted on a wall of
+    shame along with a sticker or gif picked with /shamemedia, and their warns start over.
+
     "#,
-    { command = "warn", help = "Warns a user"},
+    { command = "warn", help = "Warns a user", perms = [CanRestrictMembers] },
     { command = "warns", help = "Get warn count of a user"},
-    { command = "clearwarns", help = "Delete all warns for a user"},
-    { command = "warntime", help = "Sets time before warns expire. Usage: /warntime 6m for 6 minutes.
-        Use /warntime clear to never expire"},
-    { command = "warnmode", help = "Set the action when max warns are reached. Can be 'mute', 'ban' or 'shame'"},
-    { command = "warnlimit", help = "Sets the number of warns before an action is taken." }
+    { command = "clearwarns", help = "Delete all warns for a user", perms = [CanRestrictMembers] },
+    { command = "warntime", help = "Sets time before warns expire, 6m for 6 minutes. Use /warntime clear to never expire.", usage = "warntimeusage", perms = [CanRestrictMembers] },
+    { command = "warnmode", help = "Set the action when max warns are reached. Can be 'mute', 'ban' or 'shame'", perms = [CanRestrictMembers] },
+    { command = "warnlimit", help = "Sets the number of warns before an action is taken.", perms = [CanRestrictMembers] },
+    { command = "shamelist", help = "Show the users who reached the warn limit most often in shame mode" },
+    { command = "shamemedia", help = "Reply to a sticker or gif to post it with the wall of shame. Use /shamemedia clear to remove it", perms = [CanRestrictMembers] }
 );
 
-pub async fn warn(context: &Context) -> Result<()> {
-    context
-        .check_permissions(|p| p.can_restrict_members)
-        .await?;
+pub async fn warn(ctx: &Context, TargetUser { id: user, args }: TargetUser<'_>) -> Result<()> {
+    if user.is_admin(ctx.message()?.get_chat()).await? {
+        return ctx.fail(lang_fmt!(ctx.try_get()?.lang, "warnadmin"));
+    }
 
-    context
-        .action_user(|ctx, user, args| async move {
-            if user.is_admin(ctx.message()?.get_chat()).await? {
-                return ctx.fail(lang_fmt!(ctx.try_get()?.lang, "warnadmin"));
-            }
+    let reason = args.and_then(|a| {
+        if !a.args.is_empty() {
+            Some(a.text.trim())
+        } else {
+            None
+        }
+    });
 
-            let reason = args.and_then(|a| {
-                if !a.args.is_empty() {
-                    Some(a.text.trim())
-                } else {
-                    None
-                }
-            });
-
-            ctx.warn_with_action(user, reason, None).await?;
-            Ok(())
-        })
-        .await
-        .speak_err_raw(context, |v| match v {
-            BotError::UserNotFound => Some(lang_fmt!(context, "failuser", "warn")),
-            _ => None,
-        })
-        .await?;
+    ctx.warn_with_action(user, reason, None).await?;
+    if let Err(err) = record_action(ctx.message()?, user, ModAction::Warn, reason, None).await {
+        log::warn!("failed to record warn: {}", err);
+    }
     Ok(())
 }
 
@@ -69,14 +71,23 @@ pub async fn warns(context: &Context) -> Result<()> {
         context
             .action_user(|ctx, user, _| async move {
                 let warns = get_warns(ctx.try_get()?.chat, user).await?;
+                let now = Utc::now();
                 let list = warns
                     .into_iter()
                     .map(|w| {
-                        lang_fmt!(
-                            lang,
-                            "warnsline",
-                            w.reason.unwrap_or_else(|| lang_fmt!(lang, "noreason"))
-                        )
+                        let reason = w.reason.unwrap_or_else(|| lang_fmt!(lang, "noreason"));
+                        match w
+                            .expires
+                            .map(|expires| (expires - now).num_seconds().max(0))
+                        {
+                            Some(left) => lang_fmt!(
+                                lang,
+                                "warnslineexpires",
+                                reason,
+                                lang.format_duration(std::time::Duration::from_secs(left as u64))
+                            ),
+                            None => lang_fmt!(lang, "warnsline", reason),
+                        }
                     })
                     .map(|v| remove_fillings(&v))
                     .collect::<Vec<String>>()
@@ -97,60 +108,50 @@ pub async fn warns(context: &Context) -> Result<()> {
     Ok(())
 }
 
-pub async fn clear<'a>(ctx: &Context) -> Result<()> {
-    let message = ctx.message()?;
-    ctx.is_group_or_die().await?;
-    self_admin_or_die(message.get_chat()).await?;
-    ctx.check_permissions(|p| p.can_restrict_members).await?;
-    ctx.action_user(|ctx, user, _| async move {
-        clear_warns(ctx.message()?.get_chat(), user).await?;
-
-        ctx.reply_fmt(entity_fmt!(ctx, "clearwarns", user.mention().await?))
-            .await?;
-        Ok(())
-    })
-    .await
-    .speak_err_raw(ctx, |v| match v {
-        BotError::UserNotFound => Some(lang_fmt!(ctx, "failuser", "clear warns for")),
-        _ => None,
-    })
-    .await?;
-
+pub async fn clear(
+    ctx: &Context,
+    _: InGroup,
+    TargetUser { id: user, .. }: TargetUser<'_>,
+) -> Result<()> {
+    let chat = ctx.message()?.get_chat();
+    self_admin_or_die(chat).await?;
+    clear_warns(chat, user).await?;
+    ctx.confirm_fmt(entity_fmt!(ctx, "clearwarns", user.mention().await?))
+        .await?;
     Ok(())
 }
 
-async fn set_time<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
-    ctx.check_permissions(|p| p.can_restrict_members).await?;
+async fn set_time(ctx: &Context, CommandArgs(args): CommandArgs<'_>) -> Result<()> {
     let message = ctx.message()?;
     let chat = ctx.try_get()?.chat.name_humanreadable();
     if let Ok(Some(time)) = ctx.parse_duration(&Some(args.as_slice())) {
         set_warn_time(message.get_chat(), Some(time.num_seconds())).await?;
-        let time = format_duration(time.to_std()?);
-        message.reply(format!("Set warn time to {}", time)).await?;
+        let time = ctx.lang().format_duration(time.to_std()?);
+        message
+            .confirm(format!("Set warn time to {}", time))
+            .await?;
     } else if args.text.trim() == "clear" {
         set_warn_time(message.get_chat(), None).await?;
         message
-            .reply(lang_fmt!(ctx.lang(), "cleartime", chat))
+            .confirm(lang_fmt!(ctx.lang(), "cleartime", chat))
             .await?;
     } else {
-        message.reply(lang_fmt!(ctx.lang(), "specifytime")).await?;
+        return ctx.usage_err("warntimeusage");
     }
     Ok(())
 }
 
-async fn cmd_warn_mode<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
-    ctx.check_permissions(|p| p.can_restrict_members).await?;
+async fn cmd_warn_mode(ctx: &Context, CommandArgs(args): CommandArgs<'_>) -> Result<()> {
     let message = ctx.message()?;
     let chat = ctx.try_get()?.chat.name_humanreadable();
     set_warn_mode(message.get_chat(), args.text).await?;
     message
-        .reply(lang_fmt!(ctx.lang(), "warnmode", args.text, chat))
+        .confirm(lang_fmt!(ctx.lang(), "warnmode", args.text, chat))
         .await?;
     Ok(())
 }
 
-async fn cmd_warn_limit<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
-    ctx.check_permissions(|p| p.can_restrict_members).await?;
+async fn cmd_warn_limit(ctx: &Context, CommandArgs(args): CommandArgs<'_>) -> Result<()> {
     let message = ctx.message()?;
     let chat = ctx.try_get()?.chat.name_humanreadable();
     match str::parse(args.text.trim()) {
@@ -158,7 +159,12 @@ async fn cmd_warn_limit<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
             if num > 0 {
                 set_warn_limit(message.get_chat(), num).await?;
                 message
-                    .reply(lang_fmt!(ctx.lang(), "warnlimit", num, chat))
+                    .confirm(lang_fmt!(
+                        ctx.lang(),
+                        "warnlimit",
+                        ctx.lang().format_number(num),
+                        chat
+                    ))
                     .await?;
             } else {
                 message.reply(lang_fmt!(ctx.lang(), "negwarns")).await?;
@@ -171,22 +177,128 @@ async fn cmd_warn_limit<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
     Ok(())
 }
 
+async fn shamelist(ctx: &Context, _: InGroup) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let list = get_shame_list(chat.get_id()).await?;
+    if list.is_empty() {
+        ctx.reply(lang_fmt!(ctx, "noshames")).await?;
+        return Ok(());
+    }
+    let mut message = lang_fmt!(ctx, "shamelist", chat.name_humanreadable().escape(false));
+    for (place, shame) in list.into_iter().enumerate() {
+        let name = shame
+            .user_id
+            .cached_name()
+            .await?
+            .escape(false)
+            .into_owned();
+        message.push('\n');
+        message.push_str(&lang_fmt!(
+            ctx,
+            "shamelistline",
+            place + 1,
+            name,
+            shame.count
+        ));
+    }
+    ctx.reply(message).await?;
+    Ok(())
+}
+
+async fn shamemedia(ctx: &Context, _: InGroup, CommandArgs(args): CommandArgs<'_>) -> Result<()> {
+    let message = ctx.message()?;
+    if args.text.trim() == "clear" {
+        set_shame_media(message.get_chat(), None).await?;
+        message.confirm(lang_fmt!(ctx, "shamemediacleared")).await?;
+        return Ok(());
+    }
+    let media = message
+        .get_reply_to_message()
+        .and_then(|reply| reply.get_media_id())
+        .filter(|(_, kind)| matches!(kind, MediaType::Sticker | MediaType::Animation));
+    let Some((media_id, media_type)) = media else {
+        return ctx.fail(lang_fmt!(ctx, "shamemediainvalid"));
+    };
+    set_shame_media(message.get_chat(), Some((media_id.to_owned(), media_type))).await?;
+    message.confirm(lang_fmt!(ctx, "shamemediaset")).await?;
+    Ok(())
+}
+
 async fn handle_command<'a>(ctx: &Context) -> Result<()> {
-    if let Some(&Cmd { cmd, ref args, .. }) = ctx.cmd() {
+    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
         match cmd {
-            "warn" => warn(ctx).await,
+            "warn" => ctx.run(warn).await,
             "warns" => warns(ctx).await,
-            "clearwarns" => clear(ctx).await,
-            "warntime" => set_time(ctx, args).await,
-            "warnmode" => cmd_warn_mode(ctx, args).await,
-            "warnlimit" => cmd_warn_limit(ctx, args).await,
+            "clearwarns" => ctx.run(clear).await,
+            "warntime" => ctx.run(set_time).await,
+            "warnmode" => ctx.run(cmd_warn_mode).await,
+            "warnlimit" => ctx.run(cmd_warn_limit).await,
+            "shamelist" => ctx.run(shamelist).await,
+            "shamemedia" => ctx.run(shamemedia).await,
             _ => Ok(()),
         }?;
     }
     Ok(())
 }
 
-#[update_handler]
+/// Highest warn limit the /settings panel steps up to, /warnlimit can go higher
+const PANEL_MAX_WARNS: i64 = 100;
+
+/// Shows the warn limit and warn mode on the /settings panel
+pub struct Settings;
+
+#[async_trait]
+impl SettingsProvider for Settings {
+    fn module(&self) -> &'static str {
+        "warns"
+    }
+
+    fn title(&self) -> &'static str {
+        "Warns"
+    }
+
+    fn allowed(&self, permissions: &BotPermissions) -> bool {
+        permissions.can_restrict_members
+    }
+
+    async fn get_settings(&self, chat: &Chat) -> Result<Vec<Setting>> {
+        let dialog = dialog_or_default(chat).await?;
+        Ok(vec![
+            Setting {
+                id: "limit".to_owned(),
+                name: "Warn limit".to_owned(),
+                kind: SettingKind::Number {
+                    value: dialog.warn_limit.into(),
+                    min: 1,
+                    max: PANEL_MAX_WARNS,
+                },
+            },
+            Setting {
+                id: "mode".to_owned(),
+                name: "Warn mode".to_owned(),
+                kind: SettingKind::Choice {
+                    value: dialog.action_type.get_name().to_owned(),
+                    options: vec!["mute", "ban", "shame"],
+                },
+            },
+        ])
+    }
+
+    async fn set_setting(&self, chat: &Chat, setting: &str, value: SettingValue) -> Result<()> {
+        match (setting, value) {
+            ("limit", SettingValue::Number(limit)) if limit > 0 => {
+                set_warn_limit(chat, limit as i32).await
+            }
+            ("mode", SettingValue::Choice(mode)) => set_warn_mode(chat, &mode).await,
+            _ => Err(BotError::generic(format!(
+                "invalid warns setting {}",
+                setting
+            ))),
+        }
+    }
+}
+
+#[update_handler(settings = Settings)]
 pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
     handle_command(cmd).await?;
 
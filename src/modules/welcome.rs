@@ -1,17 +1,28 @@
+use crate::persist::admin::welcomemute::{self, WelcomeMuteMode};
 use crate::persist::core::media::get_media_type;
-use crate::persist::core::{entity, welcomes};
-use crate::statics::{DB, REDIS};
+use crate::persist::core::welcomes::WelcomeRotation;
+use crate::persist::core::{save_with_entities, welcome_messages, welcomes};
+use crate::statics::DB;
+use crate::tg::button::InlineKeyboardBuilder;
 use crate::tg::command::{Cmd, Context, TextArgs};
+use crate::tg::greetings::{
+    empty_welcome_settings, get_welcome_mute, set_welcome_enabled, set_welcome_mute,
+    welcome_mute_kick_time, welcome_scope,
+};
 use crate::tg::markdown::MarkupBuilder;
 use crate::tg::permissions::*;
-use crate::util::error::{BotError, Result};
-use crate::util::string::Lang;
+use crate::tg::settings::{Setting, SettingKind, SettingValue, SettingsProvider};
+use crate::tg::url_guard::check_button_urls;
+use crate::util::error::{BotError, Fail, Result};
+use crate::util::error_codes::{INVALID_ARGUMENT, NOT_FOUND};
+use crate::util::string::{Confirm, Lang};
 use crate::{metadata::metadata, util::string::Speak};
-use botapi::gen_types::Message;
+use async_trait::async_trait;
+use botapi::gen_types::{Chat, Message, MessageEntity};
+use futures::FutureExt;
 use macros::{lang_fmt, update_handler};
-use redis::AsyncCommands;
 use sea_orm::entity::ActiveValue::{NotSet, Set};
-use sea_orm::EntityTrait;
+use sea_orm::{ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder};
 
 use sea_query::OnConflict;
 
@@ -24,30 +35,63 @@ metadata!("Welcome",
     [*Example:]  
     /welcome on  
     /setwelcome Hi there \{mention\}, welcome to \{chatname\}
-    
+
+    Reply to a photo, video, sticker, or gif with /setwelcome or /setgoodbye to send that media
+    instead, with its caption as the message.
+
+    A chat can have several welcome and goodbye messages, added with /addwelcome and
+    /addgoodbye. By default the first one is always sent, /welcomerotation random picks one at
+    random for each member and /welcomerotation rotate sends each in turn.
+
+    Welcome mute keeps bots quiet by muting new members until they push an "I'm human" button
+    attached to the welcome message. In strict mode members who don't push it in time are kicked.
+    Captchas take precedence over welcome mute if both are enabled.
+
+    [*Example:]
+    /welcomemute strict 10m
     "#,
-    { command = "welcome", help = "Usage: welcome \\<on/off\\>. Enables or disables welcome" },
+    { command = "welcome", help = "Enables or disables welcome.", usage = "welcomeusage" },
     { command = "setwelcome", help = "Sets the welcome text. Reply to a message or media to set"},
-    { command = "setgoodbye", help = "Sets the goodbye message for when a user leaves"},
-    { command = "resetwelcome", help = "Resets welcome and goodbye messages to default" }
+    { command = "setgoodbye", help = "Sets the goodbye message for when a user leaves. Reply to a message or media to set"},
+    { command = "addwelcome", help = "Adds another welcome message, see /welcomerotation for how one is picked. Reply to a message or media to add"},
+    { command = "addgoodbye", help = "Adds another goodbye message. Reply to a message or media to add"},
+    { command = "welcomes", help = "Lists the welcome and goodbye messages with their ids" },
+    { command = "rmwelcome", help = "Removes a welcome or goodbye message.", usage = "rmwelcomeusage" },
+    { command = "welcomerotation", help = "Picks how one of several welcome or goodbye messages is chosen.", usage = "welcomerotationusage" },
+    { command = "resetwelcome", help = "Resets welcome and goodbye messages to default" },
+    { command = "welcomemute", help = "Mute new members until they push a button.", usage = "welcomemuteusage" }
 );
 
+/// Most welcome or goodbye messages a chat can have
+const MAX_GREETINGS: usize = 10;
+
+/// Longest part of a message shown in /welcomes
+const PREVIEW_LENGTH: usize = 40;
+
+/// Builds a welcome or goodbye message from a command along with the entities and buttons
+/// to save with it. The entity id is set once those are saved
 async fn get_model<'a>(
     message: &'a Message,
     args: &'a TextArgs<'a>,
     goodbye: bool,
-) -> Result<welcomes::ActiveModel> {
+) -> Result<(
+    welcome_messages::ActiveModel,
+    Vec<MessageEntity>,
+    InlineKeyboardBuilder,
+)> {
+    let command = message;
     let (message, text, extra) = if let Some(message) = message.get_reply_to_message() {
-        (
-            message,
-            message.get_text(),
-            message.get_entities().map(|v| v.to_owned()),
-        )
+        // media keeps its text in the caption
+        let (text, entities) = match message.get_text() {
+            Some(text) => (Some(text), message.get_entities()),
+            None => (message.get_caption(), message.get_caption_entities()),
+        };
+        (message, text, entities.map(|v| v.to_owned()))
     } else {
         (message, Some(args.text), None)
     };
 
-    let (text, entity_id) = if let Some(text) = text {
+    let (text, entities, buttons) = if let Some(text) = text {
         let (text, entities, buttons) = MarkupBuilder::new(extra)
             .set_text(text.to_owned())
             .filling(false)
@@ -55,141 +99,285 @@ async fn get_model<'a>(
             .build_murkdown_nofail()
             .await;
         log::info!("welcome get with buttons {:?}", buttons.get());
-        let entity_id = entity::insert(*DB, &entities, buttons).await?;
-        (Some(text), entity_id)
+        check_button_urls(command, &buttons).await?;
+        (Some(text), entities, buttons)
     } else {
-        (None, None)
+        (None, Vec::new(), InlineKeyboardBuilder::default())
     };
-    let (media_id, media_type) = get_media_type(message)?;
-    let res = if goodbye {
-        welcomes::ActiveModel {
-            chat: Set(message.get_chat().get_id()),
-            text: NotSet,
-            media_id: NotSet,
-            media_type: NotSet,
-            goodbye_text: Set(text.map(|t| t.to_owned())),
-            goodbye_media_id: Set(media_id),
-            goodbye_media_type: Set(Some(media_type)),
-            enabled: NotSet,
-            welcome_entity_id: NotSet,
-            goodbye_entity_id: Set(entity_id),
-        }
-    } else {
-        welcomes::ActiveModel {
-            chat: Set(message.get_chat().get_id()),
-            text: Set(text.map(|t| t.to_owned())),
-            media_id: Set(media_id),
-            media_type: Set(Some(media_type)),
-            goodbye_text: NotSet,
-            goodbye_media_id: NotSet,
-            goodbye_media_type: NotSet,
-            enabled: NotSet,
-            welcome_entity_id: Set(entity_id),
-            goodbye_entity_id: NotSet,
-        }
+    let (media_id, media_type) = get_media_type(message).await?;
+    let res = welcome_messages::ActiveModel {
+        id: NotSet,
+        chat: Set(message.get_chat().get_id()),
+        goodbye: Set(goodbye),
+        text: Set(text),
+        media_id: Set(media_id),
+        media_type: Set(media_type),
+        entity_id: NotSet,
     };
 
-    Ok(res)
+    Ok((res, entities, buttons))
 }
 
-async fn enable_welcome<'a>(message: &Message, args: &TextArgs<'a>, lang: &Lang) -> Result<()> {
-    message.check_permissions(|p| p.can_change_info).await?;
-    let key = format!("welcome:{}", message.get_chat().get_id());
-    let enabled = match args.args.first().map(|v| v.get_text()) {
-        Some("on") => Ok(true),
-        Some("off") => Ok(false),
-        Some("yes") => Ok(true),
-        Some("no") => Ok(false),
-        _ => Err(BotError::speak(
-            lang_fmt!(lang, "welcomeinvalid"),
-            message.get_chat().get_id(),
-            Some(message.message_id),
-        )),
-    }?;
-    let model = welcomes::ActiveModel {
-        chat: Set(message.get_chat().get_id()),
-        text: NotSet,
-        media_id: NotSet,
-        media_type: NotSet,
-        goodbye_text: NotSet,
-        goodbye_media_id: NotSet,
-        goodbye_media_type: NotSet,
-        enabled: Set(enabled),
-        welcome_entity_id: NotSet,
-        goodbye_entity_id: NotSet,
-    };
+/// Saves a welcome or goodbye message together with its entities and buttons. With
+/// `replace` the chat's other messages of the same kind are removed
+async fn save_greeting(
+    model: welcome_messages::ActiveModel,
+    entities: &Vec<MessageEntity>,
+    buttons: &InlineKeyboardBuilder,
+    replace: bool,
+) -> Result<welcome_messages::Model> {
+    save_with_entities(entities, buttons, |tx, entity_id| {
+        let mut model = model.clone();
+        model.entity_id = Set(entity_id);
+        async move {
+            let (chat, goodbye) = (model.chat.clone().unwrap(), model.goodbye.clone().unwrap());
+            welcomes::Entity::insert(empty_welcome_settings(chat))
+                .on_conflict(
+                    OnConflict::column(welcomes::Column::Chat)
+                        .do_nothing()
+                        .to_owned(),
+                )
+                .exec_without_returning(tx)
+                .await?;
+            if replace {
+                welcome_messages::Entity::delete_many()
+                    .filter(
+                        welcome_messages::Column::Chat
+                            .eq(chat)
+                            .and(welcome_messages::Column::Goodbye.eq(goodbye)),
+                    )
+                    .exec(tx)
+                    .await?;
+            }
+            let model = welcome_messages::Entity::insert(model)
+                .exec_with_returning(tx)
+                .await?;
+            Ok(model)
+        }
+        .boxed()
+    })
+    .await
+}
+
+async fn count_greetings(chat: i64, goodbye: bool) -> Result<usize> {
+    let count = welcome_messages::Entity::find()
+        .filter(
+            welcome_messages::Column::Chat
+                .eq(chat)
+                .and(welcome_messages::Column::Goodbye.eq(goodbye)),
+        )
+        .count(*DB)
+        .await?;
+    Ok(count as usize)
+}
+
+async fn set_welcome_rotation(chat: i64, rotation: WelcomeRotation) -> Result<()> {
+    let mut model = empty_welcome_settings(chat);
+    model.rotation = Set(rotation);
 
     welcomes::Entity::insert(model)
         .on_conflict(
             OnConflict::column(welcomes::Column::Chat)
-                .update_column(welcomes::Column::Enabled)
+                .update_column(welcomes::Column::Rotation)
                 .to_owned(),
         )
-        .exec_with_returning(*DB)
+        .exec_without_returning(*DB)
         .await?;
-    REDIS.sq(|q| q.del(&key)).await?;
-    message.reply("Enabled welcome").await?;
+    welcome_scope(chat).invalidate().await?;
     Ok(())
 }
 
-async fn set_goodbye<'a>(message: &Message, args: &TextArgs<'a>, lang: &Lang) -> Result<()> {
+async fn enable_welcome<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    let message = ctx.message()?;
     message.check_permissions(|p| p.can_change_info).await?;
-    let model = get_model(message, args, true).await?;
-    let key = format!("welcome:{}", message.get_chat().get_id());
-    log::info!("save goodbye: {}", key);
-    let model = welcomes::Entity::insert(model)
-        .on_conflict(
-            OnConflict::columns([welcomes::Column::Chat])
-                .update_columns([
-                    welcomes::Column::GoodbyeText,
-                    welcomes::Column::GoodbyeMediaId,
-                    welcomes::Column::GoodbyeMediaType,
-                    welcomes::Column::WelcomeEntityId,
-                    welcomes::Column::GoodbyeEntityId,
-                ])
-                .to_owned(),
-        )
-        .exec_with_returning(*DB)
-        .await?;
-    let text = if let Some(text) = model.text.as_ref() {
-        lang_fmt!(lang, "setgoodbye", text)
-    } else {
-        lang_fmt!(lang, "setgoodbye", "*media*")
+    let enabled = match args.args.first().map(|v| v.get_text()) {
+        Some("on") => true,
+        Some("off") => false,
+        Some("yes") => true,
+        Some("no") => false,
+        _ => return ctx.usage_err("welcomeusage"),
     };
-    REDIS.sq(|q| q.del(&key)).await?;
+    set_welcome_enabled(message.get_chat().get_id(), enabled).await?;
+    message.confirm("Enabled welcome").await?;
+    Ok(())
+}
 
+/// Sets the only welcome or goodbye, or adds another one with `add`
+async fn set_greeting<'a>(
+    message: &Message,
+    args: &TextArgs<'a>,
+    lang: &Lang,
+    goodbye: bool,
+    add: bool,
+) -> Result<()> {
+    message.check_permissions(|p| p.can_change_info).await?;
+    let chat = message.get_chat().get_id();
+    if add && count_greetings(chat, goodbye).await? >= MAX_GREETINGS {
+        return message.fail_code(
+            INVALID_ARGUMENT,
+            lang_fmt!(lang, "toomanygreetings", MAX_GREETINGS),
+        );
+    }
+
+    let (model, entities, buttons) = get_model(message, args, goodbye).await?;
+    let model = save_greeting(model, &entities, &buttons, !add).await?;
+    welcome_scope(chat).invalidate().await?;
+
+    let text = model.text.as_deref().unwrap_or("*media*");
+    let text = match (goodbye, add) {
+        (false, false) => lang_fmt!(lang, "setwelcome", text),
+        (true, false) => lang_fmt!(lang, "setgoodbye", text),
+        (false, true) => lang_fmt!(lang, "addwelcome", model.id, text),
+        (true, true) => lang_fmt!(lang, "addgoodbye", model.id, text),
+    };
     message.reply(text).await?;
     Ok(())
 }
 
-async fn set_welcome<'a>(message: &Message, args: &TextArgs<'a>, lang: &Lang) -> Result<()> {
-    message.check_permissions(|p| p.can_change_info).await?;
+fn describe_greeting(message: &welcome_messages::Model) -> String {
+    match message.text.as_deref() {
+        Some(text) if !text.is_empty() => {
+            let mut preview = text.chars().take(PREVIEW_LENGTH).collect::<String>();
+            if text.chars().count() > PREVIEW_LENGTH {
+                preview.push('…');
+            }
+            preview
+        }
+        _ => "*media*".to_owned(),
+    }
+}
 
-    let model = get_model(message, args, false).await?;
-    let key = format!("welcome:{}", message.get_chat().get_id());
-    log::info!("save welcome: {}", key);
-    let model = welcomes::Entity::insert(model)
-        .on_conflict(
-            OnConflict::columns([welcomes::Column::Chat])
-                .update_columns([
-                    welcomes::Column::Text,
-                    welcomes::Column::MediaId,
-                    welcomes::Column::MediaType,
-                    welcomes::Column::WelcomeEntityId,
-                    welcomes::Column::GoodbyeEntityId,
-                ])
-                .to_owned(),
+async fn list_greetings(ctx: &Context) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    let messages = welcome_messages::Entity::find()
+        .filter(welcome_messages::Column::Chat.eq(chat))
+        .order_by_asc(welcome_messages::Column::Id)
+        .all(*DB)
+        .await?;
+    let rotation = welcomes::Entity::find_by_id(chat)
+        .one(*DB)
+        .await?
+        .map(|w| w.rotation)
+        .unwrap_or_default();
+    let list = |goodbye: bool| {
+        let list = messages
+            .iter()
+            .filter(|m| m.goodbye == goodbye)
+            .map(|m| format!("{}: {}", m.id, describe_greeting(m)))
+            .collect::<Vec<String>>();
+        if list.is_empty() {
+            lang_fmt!(ctx, "nogreetings")
+        } else {
+            list.join("\n")
+        }
+    };
+    ctx.reply(lang_fmt!(
+        ctx,
+        "listgreetings",
+        list(false),
+        list(true),
+        rotation.get_name()
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn remove_greeting<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    let Ok(id) = args.text.trim().parse::<i64>() else {
+        return ctx.usage_err("rmwelcomeusage");
+    };
+    let res = welcome_messages::Entity::delete_many()
+        .filter(
+            welcome_messages::Column::Chat
+                .eq(chat)
+                .and(welcome_messages::Column::Id.eq(id)),
         )
-        .exec_with_returning(*DB)
+        .exec(*DB)
         .await?;
+    if res.rows_affected == 0 {
+        return ctx.fail_code(NOT_FOUND, lang_fmt!(ctx, "greetingnotfound", id));
+    }
+    welcome_scope(chat).invalidate().await?;
+    ctx.confirm(lang_fmt!(ctx, "rmwelcome", id)).await?;
+    Ok(())
+}
 
-    let text = if let Some(text) = model.text.as_ref() {
-        lang_fmt!(lang, "setwelcome", text)
-    } else {
-        lang_fmt!(lang, "setwelcome", "*media*")
+async fn welcome_rotation<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat.get_id();
+    let name = args.text.trim();
+    if name.is_empty() {
+        let rotation = welcomes::Entity::find_by_id(chat)
+            .one(*DB)
+            .await?
+            .map(|w| w.rotation)
+            .unwrap_or_default();
+        ctx.reply(lang_fmt!(ctx, "currentrotation", rotation.get_name()))
+            .await?;
+        return Ok(());
+    }
+    let Some(rotation) = WelcomeRotation::from_name(name) else {
+        return ctx.fail_code(INVALID_ARGUMENT, lang_fmt!(ctx, "invalidrotation"));
     };
-    REDIS.sq(|q| q.del(&key)).await?;
-    message.reply(text).await?;
+    ctx.check_permissions(|p| p.can_change_info).await?;
+    set_welcome_rotation(chat, rotation).await?;
+    ctx.confirm(lang_fmt!(ctx, "setrotation", rotation.get_name()))
+        .await?;
+    Ok(())
+}
+
+fn describe_welcome_mute(lang: &Lang, config: &welcomemute::Model) -> String {
+    match config.mode {
+        WelcomeMuteMode::Strict => {
+            let time = welcome_mute_kick_time(config).to_std().unwrap_or_default();
+            format!(
+                "{} ({})",
+                config.mode.get_name(),
+                lang.format_duration(time)
+            )
+        }
+        mode => mode.get_name().to_owned(),
+    }
+}
+
+async fn welcome_mute<'a>(ctx: &Context, args: &TextArgs<'a>) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let message = ctx.message()?;
+    let chat = message.get_chat().get_id();
+    let mut words = args.text.split_whitespace();
+    let (mode, kick_time) = match (words.next(), words.next()) {
+        (None, _) => {
+            let mode = get_welcome_mute(chat)
+                .await?
+                .map(|c| describe_welcome_mute(ctx.lang(), &c))
+                .unwrap_or_else(|| WelcomeMuteMode::Off.get_name().to_owned());
+            ctx.reply(lang_fmt!(ctx, "currentwelcomemute", mode))
+                .await?;
+            return Ok(());
+        }
+        (Some("on" | "yes"), None) => (WelcomeMuteMode::On, None),
+        (Some("off" | "no"), None) => (WelcomeMuteMode::Off, None),
+        (Some("strict"), time) => {
+            let time = time.map(|t| ctx.parse_duration_arg(t)).transpose()?;
+            (WelcomeMuteMode::Strict, time)
+        }
+        _ => return ctx.fail(lang_fmt!(ctx, "invalidwelcomemute")),
+    };
+
+    ctx.check_permissions(|p| p.can_change_info.and(p.can_restrict_members))
+        .await?;
+    let config = set_welcome_mute(chat, mode, kick_time.map(|t| t.num_seconds())).await?;
+    ctx.confirm(lang_fmt!(
+        ctx,
+        "setwelcomemute",
+        describe_welcome_mute(ctx.lang(), &config)
+    ))
+    .await?;
     Ok(())
 }
 
@@ -203,10 +391,16 @@ async fn handle_command(ctx: &Context) -> Result<()> {
     }) = ctx.cmd()
     {
         match cmd {
-            "setwelcome" => set_welcome(message, args, lang).await?,
-            "setgoodbye" => set_goodbye(message, args, lang).await?,
-            "welcome" => enable_welcome(message, args, lang).await?,
+            "setwelcome" => set_greeting(message, args, lang, false, false).await?,
+            "setgoodbye" => set_greeting(message, args, lang, true, false).await?,
+            "addwelcome" => set_greeting(message, args, lang, false, true).await?,
+            "addgoodbye" => set_greeting(message, args, lang, true, true).await?,
+            "welcomes" => list_greetings(ctx).await?,
+            "rmwelcome" => remove_greeting(ctx, args).await?,
+            "welcomerotation" => welcome_rotation(ctx, args).await?,
+            "welcome" => enable_welcome(ctx, args).await?,
             "resetwelcome" => reset_welcome(message, lang).await?,
+            "welcomemute" => welcome_mute(ctx, args).await?,
             _ => (),
         };
     }
@@ -216,15 +410,81 @@ async fn handle_command(ctx: &Context) -> Result<()> {
 async fn reset_welcome(message: &Message, lang: &Lang) -> Result<()> {
     message.check_permissions(|p| p.can_change_info).await?;
     let chat = message.get_chat().get_id();
-    let key = format!("welcome:{}", chat);
 
+    welcome_messages::Entity::delete_many()
+        .filter(welcome_messages::Column::Chat.eq(chat))
+        .exec(*DB)
+        .await?;
     welcomes::Entity::delete_by_id(chat).exec(*DB).await?;
-    REDIS.sq(|q| q.del(&key)).await?;
-    message.reply(lang_fmt!(lang, "resetwelcome")).await?;
+    welcome_scope(message.get_chat().get_id())
+        .invalidate()
+        .await?;
+    message.confirm(lang_fmt!(lang, "resetwelcome")).await?;
     Ok(())
 }
 
-#[update_handler]
+/// Shows whether welcomes are enabled and how they rotate on the /settings panel
+pub struct Settings;
+
+#[async_trait]
+impl SettingsProvider for Settings {
+    fn module(&self) -> &'static str {
+        "welcome"
+    }
+
+    fn title(&self) -> &'static str {
+        "Welcome"
+    }
+
+    fn allowed(&self, permissions: &BotPermissions) -> bool {
+        permissions.can_change_info
+    }
+
+    async fn get_settings(&self, chat: &Chat) -> Result<Vec<Setting>> {
+        let welcome = welcomes::Entity::find_by_id(chat.get_id()).one(*DB).await?;
+        let (enabled, rotation) = welcome
+            .map(|welcome| (welcome.enabled, welcome.rotation))
+            .unwrap_or_default();
+        Ok(vec![
+            Setting {
+                id: "enabled".to_owned(),
+                name: "Welcome messages".to_owned(),
+                kind: SettingKind::Toggle(enabled),
+            },
+            Setting {
+                id: "rotation".to_owned(),
+                name: "Welcome rotation".to_owned(),
+                kind: SettingKind::Choice {
+                    value: rotation.get_name().to_owned(),
+                    options: vec!["first", "random", "rotate"],
+                },
+            },
+        ])
+    }
+
+    async fn set_setting(&self, chat: &Chat, setting: &str, value: SettingValue) -> Result<()> {
+        match (setting, value) {
+            ("enabled", SettingValue::Toggle(enabled)) => {
+                set_welcome_enabled(chat.get_id(), enabled).await
+            }
+            ("rotation", SettingValue::Choice(rotation)) => {
+                match WelcomeRotation::from_name(&rotation) {
+                    Some(rotation) => set_welcome_rotation(chat.get_id(), rotation).await,
+                    None => Err(BotError::generic(format!(
+                        "invalid welcome rotation {}",
+                        rotation
+                    ))),
+                }
+            }
+            _ => Err(BotError::generic(format!(
+                "invalid welcome setting {}",
+                setting
+            ))),
+        }
+    }
+}
+
+#[update_handler(settings = Settings)]
 pub async fn handle_update<'a>(cmd: &Context) -> Result<()> {
     handle_command(cmd).await?;
     Ok(())
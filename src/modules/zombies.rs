@@ -0,0 +1,79 @@
+use crate::metadata::metadata;
+use crate::tg::command::{Cmd, Context};
+use crate::tg::extract::{CanRestrictMembers, CommandArgs, InGroup, RequirePerm};
+use crate::tg::permissions::self_admin_or_die;
+use crate::tg::scheduler::{schedule_job, Job, JobKind};
+use crate::tg::zombies::{claim_scan, release_scan};
+use crate::util::error::{Fail, Result};
+use crate::util::string::Speak;
+use chrono::Utc;
+use macros::{lang_fmt, update_handler};
+
+metadata!("Zombies",
+    r#"
+    Find and remove deleted accounts. Telegram doesn't let bots list members, so only members
+    I have seen in the chat are checked. Checking is slow in big chats to stay within
+    telegram's limits, the status message is updated as it goes.
+    "#,
+    { command = "zombies", help = "Count deleted accounts in the chat. Use /zombies clean to kick them, add \\-\\-dryrun to see who would be kicked first", perms = [CanRestrictMembers] }
+);
+
+async fn zombies(
+    ctx: &Context,
+    _: InGroup,
+    _: RequirePerm<CanRestrictMembers>,
+    CommandArgs(args): CommandArgs<'_>,
+) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let matches = args.matches();
+    let clean = match matches.positional() {
+        [] => false,
+        [arg] if arg.get_text() == "clean" => true,
+        _ => return ctx.usage_err("zombiesusage"),
+    };
+    let dry_run = matches.dry_run();
+    if clean && !dry_run {
+        self_admin_or_die(chat).await?;
+    }
+    if !claim_scan(chat.get_id()).await? {
+        return ctx.fail(lang_fmt!(ctx, "zombiesrunning"));
+    }
+
+    let status = match ctx.reply(lang_fmt!(ctx, "zombiesstart")).await {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            release_scan(chat.get_id()).await?;
+            return Ok(());
+        }
+        Err(err) => {
+            release_scan(chat.get_id()).await?;
+            return Err(err);
+        }
+    };
+    let kind = JobKind::ZombieScan {
+        clean,
+        dry_run,
+        status,
+        lang: *ctx.lang(),
+    };
+    if let Err(err) = schedule_job(&Job::new(chat.get_id(), Utc::now(), kind)).await {
+        release_scan(chat.get_id()).await?;
+        return Err(err);
+    }
+    Ok(())
+}
+
+async fn handle_command(ctx: &Context) -> Result<()> {
+    if let Some(&Cmd { cmd, .. }) = ctx.cmd() {
+        match cmd {
+            "zombies" => ctx.run(zombies).await,
+            _ => Ok(()),
+        }?;
+    }
+    Ok(())
+}
+
+#[update_handler]
+pub async fn handle_update<'a>(ctx: &Context) -> Result<()> {
+    handle_command(ctx).await
+}
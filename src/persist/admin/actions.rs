@@ -5,7 +5,9 @@
 use std::hash::Hash;
 
 use crate::util::error::BotError;
+use crate::util::string::Lang;
 use chrono::Utc;
+use macros::lang_fmt;
 use sea_orm::{entity::prelude::*, IntoActiveValue};
 use serde::{Deserialize, Serialize};
 
@@ -71,11 +73,12 @@ pub enum Relation {}
 impl ActionType {
     pub fn from_str<T: AsRef<str>>(
         s: T,
+        lang: &Lang,
         chat: i64,
         reply: i64,
     ) -> crate::util::error::Result<Self> {
         Self::from_str_err(s.as_ref(), || {
-            BotError::speak(format!("Invalid action {}", s.as_ref()), chat, Some(reply))
+            BotError::speak(lang_fmt!(lang, "errinvalidaction"), chat, Some(reply))
         })
     }
     pub fn get_name(&self) -> &str {
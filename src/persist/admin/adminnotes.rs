@@ -0,0 +1,29 @@
+//! ORM type for private notes admins keep about users. A note belongs either to a single
+//! chat or to a federation, in which case it is shown in every chat of the federation
+
+use chrono::Utc;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "admin_notes")]
+pub struct Model {
+    #[sea_orm(primary_key, autoincrement = true)]
+    pub id: i64,
+    /// the user the note is about
+    pub user_id: i64,
+    /// chat the note belongs to, None for federation notes
+    pub chat_id: Option<i64>,
+    /// federation the note belongs to, None for chat notes
+    pub federation: Option<Uuid>,
+    /// the admin who wrote the note
+    pub author: i64,
+    #[sea_orm(column_type = "Text")]
+    pub text: String,
+    pub created: chrono::DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,63 @@
+//! ORM type for per-chat settings for checking new members against external banlists
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    EnumIter,
+    DeriveActiveEnum,
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Debug,
+    Default,
+    DeriveIden,
+)]
+#[sea_orm(rs_type = "i32", db_type = "Integer")]
+pub enum AntiSpamAction {
+    #[default]
+    #[sea_orm(num_value = 1)]
+    Ban,
+    #[sea_orm(num_value = 2)]
+    Kick,
+    #[sea_orm(num_value = 3)]
+    Mute,
+}
+
+impl AntiSpamAction {
+    pub fn from_str(text: &str) -> Option<Self> {
+        match text {
+            "ban" => Some(Self::Ban),
+            "kick" => Some(Self::Kick),
+            "mute" => Some(Self::Mute),
+            _ => None,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Ban => "ban",
+            Self::Kick => "kick",
+            Self::Mute => "mute",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "antispam")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub chat: i64,
+    #[sea_orm(default = false)]
+    pub enabled: bool,
+    pub action: AntiSpamAction,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
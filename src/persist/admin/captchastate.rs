@@ -1,8 +1,6 @@
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::util::error::BotError;
-
 #[derive(
     EnumIter, DeriveActiveEnum, Serialize, Deserialize, Clone, PartialEq, Debug, DeriveIden,
 )]
@@ -15,11 +13,12 @@ pub enum CaptchaType {
 }
 
 impl CaptchaType {
-    pub fn from_str(text: &str, chat: i64, reply: i64) -> crate::util::error::Result<Self> {
+    /// Parses a captcha type from its name in the /captchamode command
+    pub fn parse(text: &str) -> Option<Self> {
         match text {
-            "button" => Ok(CaptchaType::Button),
-            "text" => Ok(CaptchaType::Text),
-            _ => Err(BotError::speak("Invalid button type", chat, Some(reply))),
+            "button" => Some(CaptchaType::Button),
+            "text" => Some(CaptchaType::Text),
+            _ => None,
         }
     }
 
@@ -1,4 +1,6 @@
 pub mod actions;
+pub mod adminnotes;
+pub mod antispam;
 pub mod approvals;
 pub mod authorized;
 pub mod captchastate;
@@ -6,4 +8,12 @@ pub mod fbans;
 pub mod fedadmin;
 pub mod federations;
 pub mod gbans;
+pub mod modlog;
+pub mod network_chats;
+pub mod network_links;
+pub mod networks;
+pub mod nsfwfilter;
+pub mod shames;
+pub mod spamfilter;
 pub mod warns;
+pub mod welcomemute;
@@ -0,0 +1,51 @@
+//! ORM type for the moderation log. Every ban, mute, kick, restriction and warn done with a
+//! command is recorded here so admins can look back at a user's history in the chat, unlike
+//! the log channel which only shows actions as they happen
+
+use chrono::Utc;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    EnumIter, DeriveActiveEnum, Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq, Hash,
+)]
+#[sea_orm(rs_type = "i32", db_type = "Integer")]
+pub enum ModAction {
+    #[sea_orm(num_value = 1)]
+    Ban,
+    #[sea_orm(num_value = 2)]
+    Unban,
+    #[sea_orm(num_value = 3)]
+    Kick,
+    #[sea_orm(num_value = 4)]
+    Mute,
+    #[sea_orm(num_value = 5)]
+    Unmute,
+    #[sea_orm(num_value = 6)]
+    Restrict,
+    #[sea_orm(num_value = 7)]
+    Warn,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "modlog")]
+pub struct Model {
+    #[sea_orm(primary_key, autoincrement = true)]
+    pub id: i64,
+    pub chat_id: i64,
+    /// the admin who took the action, None for anonymous admins
+    pub actor: Option<i64>,
+    /// the user the action was taken against
+    pub target: i64,
+    pub action: ModAction,
+    #[sea_orm(column_type = "Text")]
+    pub reason: Option<String>,
+    /// length of temporary actions in seconds, None if permanent
+    pub duration: Option<i64>,
+    pub created: chrono::DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
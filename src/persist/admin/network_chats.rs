@@ -0,0 +1,37 @@
+//! ORM type for the chats in a network. Each chat can be in one network at a time, and can
+//! override what the network shares by default for itself
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "network_chats")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub chat_id: i64,
+    pub network: Uuid,
+    /// overrides of the network's defaults, None to use the network's
+    pub share_approvals: Option<bool>,
+    pub share_blocklists: Option<bool>,
+    pub share_notes: Option<bool>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::networks::Entity",
+        from = "Column::Network",
+        to = "super::networks::Column::NetworkId",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Network,
+}
+
+impl Related<super::networks::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Network.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
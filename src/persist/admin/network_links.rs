@@ -0,0 +1,36 @@
+//! ORM type for links between chats in a network. Two chats only share with each other once an
+//! admin of both has linked them, so adding a chat to a network doesn't make it share with
+//! chats whose admins never agreed to it. Each link is stored once in each direction
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "network_links")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub chat_id: i64,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub other_chat: i64,
+    pub network: Uuid,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::networks::Entity",
+        from = "Column::Network",
+        to = "super::networks::Column::NetworkId",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Network,
+}
+
+impl Related<super::networks::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Network.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,47 @@
+//! ORM type for chat networks. A network is a group of chats run by the same admins sharing
+//! approvals, blocklists and optionally notes. Much lighter than a federation, there are no
+//! network admins or bans, just the chats and what they share by default
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "networks")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub network_id: Uuid,
+    /// the user who created the network, the only one who can change its defaults
+    pub owner: i64,
+    #[sea_orm(column_type = "Text")]
+    pub name: String,
+    pub share_approvals: bool,
+    pub share_blocklists: bool,
+    pub share_notes: bool,
+}
+
+impl Model {
+    pub fn new(owner: i64, name: String) -> Self {
+        Self {
+            network_id: Uuid::new_v4(),
+            owner,
+            name,
+            share_approvals: true,
+            share_blocklists: true,
+            share_notes: false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "super::network_chats::Entity")]
+    Chats,
+}
+
+impl Related<super::network_chats::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Chats.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
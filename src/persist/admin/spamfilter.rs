@@ -0,0 +1,24 @@
+//! ORM type for per-chat settings for acting on messages scored as spam
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::actions::ActionType;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "spam_filter")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub chat: i64,
+    #[sea_orm(default = false)]
+    pub enabled: bool,
+    /// score in percent at or above which a message is treated as spam
+    #[sea_orm(default = 80)]
+    pub threshold: i32,
+    pub action: ActionType,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,56 @@
+//! ORM type for muting new members until they prove they are human
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    EnumIter,
+    DeriveActiveEnum,
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Debug,
+    Default,
+    DeriveIden,
+)]
+#[sea_orm(rs_type = "i32", db_type = "Integer")]
+pub enum WelcomeMuteMode {
+    #[default]
+    #[sea_orm(num_value = 0)]
+    Off,
+    /// Mute new members until they push the button on the welcome message
+    #[sea_orm(num_value = 1)]
+    On,
+    /// Like On, but kick members who don't push the button in time
+    #[sea_orm(num_value = 2)]
+    Strict,
+}
+
+impl WelcomeMuteMode {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::On => "on",
+            Self::Strict => "strict",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "welcome_mute")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub chat: i64,
+    pub mode: WelcomeMuteMode,
+    /// seconds before members who didn't push the button are kicked in strict mode
+    pub kick_time: Option<i64>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
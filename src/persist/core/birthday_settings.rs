@@ -0,0 +1,21 @@
+//! ORM type for per-chat birthday greeting settings
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "birthday_settings")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub chat_id: i64,
+    #[sea_orm(default = false)]
+    pub enabled: bool,
+    /// murkdown greeting, the default greeting is used if unset
+    #[sea_orm(column_type = "Text")]
+    pub greeting: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,24 @@
+//! ORM type for boosts of a chat. The bot api can't list a chat's boosters, so boosts are
+//! recorded from chat_boost updates as they come in
+
+use chrono::Utc;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "chat_boosts")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub chat_id: i64,
+    #[sea_orm(primary_key, column_type = "Text")]
+    pub boost_id: String,
+    /// user who boosted, None for unclaimed giveaway prizes
+    pub user_id: Option<i64>,
+    pub added: chrono::DateTime<Utc>,
+    pub expires: chrono::DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,26 @@
+//! ORM type for every chat the bot has seen. Used for sending announcements to all chats
+//! and for listing the chats the bot is in
+
+use chrono::Utc;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "chats")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub chat_id: i64,
+    #[sea_orm(column_type = "Text")]
+    pub chat_type: String,
+    /// false if the chat opted out of announcements
+    #[sea_orm(default = true)]
+    pub broadcast: bool,
+    #[sea_orm(column_type = "Text")]
+    pub title: Option<String>,
+    pub last_activity: chrono::DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,21 @@
+//! ORM type for command aliases a chat's admins have defined. Each alias names a command, or
+//! another alias, that runs when the alias is sent in the chat
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "command_aliases")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub chat_id: i64,
+    #[sea_orm(primary_key, column_type = "Text")]
+    pub alias: String,
+    #[sea_orm(column_type = "Text")]
+    pub command: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -3,12 +3,77 @@
 
 use crate::tg::admin_helpers::is_dm_info;
 use crate::util::error::Fail;
+use crate::util::error_codes::CHAT_PERMISSIONS;
+use crate::util::string::get_chat_lang;
 use crate::{persist::admin::actions::ActionType, statics::TG};
 use botapi::gen_types::{Chat, ChatPermissionsBuilder};
+use macros::lang_fmt;
 use sea_orm::entity::prelude::*;
-use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::ActiveValue::Set;
 use serde::{Deserialize, Serialize};
 
+/// What to do with requests to join a chat
+#[derive(
+    EnumIter,
+    DeriveActiveEnum,
+    Serialize,
+    Deserialize,
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    Hash,
+)]
+#[sea_orm(rs_type = "i32", db_type = "Integer")]
+pub enum JoinPolicy {
+    /// Leave requests for admins to handle in telegram
+    #[default]
+    #[sea_orm(num_value = 0)]
+    Off,
+    /// Approve every request
+    #[sea_orm(num_value = 1)]
+    ApproveAll,
+    /// Approve requests from accounts older than the configured number of days
+    #[sea_orm(num_value = 2)]
+    MinAge,
+    /// Approve requests once the user solves a captcha in the bot's dm
+    #[sea_orm(num_value = 3)]
+    Captcha,
+    /// Post requests with approve and decline buttons for admins
+    #[sea_orm(num_value = 4)]
+    Admins,
+}
+
+/// What to do with messages sent on behalf of channels other than the chat's linked channel
+#[derive(
+    EnumIter,
+    DeriveActiveEnum,
+    Serialize,
+    Deserialize,
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    Hash,
+)]
+#[sea_orm(rs_type = "i32", db_type = "Integer")]
+pub enum AntiChannel {
+    /// Let channels speak
+    #[default]
+    #[sea_orm(num_value = 0)]
+    Off,
+    /// Delete their messages
+    #[sea_orm(num_value = 1)]
+    Delete,
+    /// Delete their messages and ban the channel from the chat
+    #[sea_orm(num_value = 2)]
+    Ban,
+}
+
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "dialogs")]
 pub struct Model {
@@ -38,6 +103,76 @@ pub struct Model {
     pub warn_time: Option<i64>,
     pub action_type: ActionType,
     pub federation: Option<Uuid>,
+    pub log_channel: Option<i64>,
+    #[sea_orm(default = false)]
+    #[serde(default)]
+    pub button_url_strict: bool,
+    /// IANA timezone name, utc if unset
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[sea_orm(default = JoinPolicy::Off)]
+    #[serde(default)]
+    pub join_policy: JoinPolicy,
+    /// minimum account age in days for JoinPolicy::MinAge
+    #[serde(default)]
+    pub join_min_age: Option<i32>,
+    /// delete commands from admins after they run
+    #[sea_orm(default = false)]
+    #[serde(default)]
+    pub clean_commands: bool,
+    /// seconds before confirmations are deleted when cleaning commands, uses the config if unset
+    #[serde(default)]
+    pub clean_confirm_time: Option<i64>,
+    /// bitmask of service message kinds deleted by /cleanservice
+    #[sea_orm(default = 0)]
+    #[serde(default)]
+    pub clean_service: i32,
+    /// yes votes needed to pass a /voteban, /voteban is disabled if unset
+    #[serde(default)]
+    pub voteban_threshold: Option<i32>,
+    /// seconds a /voteban stays open, uses the default if unset
+    #[serde(default)]
+    pub voteban_duration: Option<i64>,
+    /// mute instead of ban when a /voteban passes
+    #[sea_orm(default = false)]
+    #[serde(default)]
+    pub voteban_mute: bool,
+    /// minimum account age in days to start or vote in a /voteban, uses the default if unset
+    #[serde(default)]
+    pub voteban_min_age: Option<i32>,
+    /// file id of the sticker or gif posted with the wall of shame
+    #[serde(default)]
+    pub shame_media: Option<String>,
+    #[serde(default)]
+    pub shame_media_type: Option<crate::persist::core::media::MediaType>,
+    /// what to do with messages from channels, see /antichannel
+    #[sea_orm(default = AntiChannel::Off)]
+    #[serde(default)]
+    pub anti_channel: AntiChannel,
+    /// delete posts the linked channel forwards into the chat
+    #[sea_orm(default = false)]
+    #[serde(default)]
+    pub clean_linked: bool,
+    /// ask admins to confirm high-impact commands with a button, see /confirmactions
+    #[sea_orm(default = false)]
+    #[serde(default)]
+    pub confirm_actions: bool,
+    /// ignore the fun commands like /8ball and /slap, see /fun
+    #[sea_orm(default = false)]
+    #[serde(default)]
+    pub disable_fun: bool,
+    /// run text read from images and stickers through the blocklists, see /lockocrprofanity
+    #[sea_orm(default = false)]
+    #[serde(default)]
+    pub ocr_blocklists: bool,
+    /// announce video chats starting and ending in the chat itself, see /vcannounce
+    #[sea_orm(default = false)]
+    #[serde(default)]
+    pub announce_video_chats: bool,
+    /// exempt boosters of the chat from locks, see /boosterperks
+    #[sea_orm(default = false)]
+    #[serde(default)]
+    pub booster_perks: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -72,23 +207,35 @@ impl Related<crate::persist::admin::fbans::Entity> for Entity {
     }
 }
 
+impl ActiveModel {
+    /// Upsert of a chat's dialog that only sets its id and type. Set the columns being
+    /// changed on top of it, everything else is left as it is
+    pub fn for_id(chat_id: i64, chat_type: &str) -> Self {
+        Self {
+            chat_id: Set(chat_id),
+            chat_type: Set(chat_type.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    pub fn for_chat(chat: &Chat) -> Self {
+        Self::for_id(chat.get_id(), chat.get_tg_type())
+    }
+}
+
 impl Model {
     pub async fn from_chat(chat: &Chat) -> crate::util::error::Result<ActiveModel> {
         let chat = TG.client.get_chat(chat.get_id()).await?;
         let def = &ChatPermissionsBuilder::new().build();
         let permissions = if is_dm_info(&chat) {
             def
+        } else if let Some(permissions) = chat.get_permissions() {
+            permissions
         } else {
-            chat.get_permissions()
-                .ok_or_else(|| chat.fail_err("failed to get chat permissions"))?
+            let lang = get_chat_lang(chat.get_id()).await?;
+            return chat.fail_code(CHAT_PERMISSIONS, lang_fmt!(lang, "errchatpermissions"));
         };
         let res = ActiveModel {
-            chat_id: Set(chat.get_id()),
-            language: NotSet,
-            chat_type: Set(chat.get_tg_type().to_owned()),
-            warn_limit: NotSet,
-            action_type: NotSet,
-            warn_time: NotSet,
             can_send_messages: Set(permissions.get_can_send_messages().unwrap_or(true)),
             can_send_audio: Set(permissions.get_can_send_audios().unwrap_or(true)),
             can_send_video: Set(permissions.get_can_send_videos().unwrap_or(true)),
@@ -98,7 +245,7 @@ impl Model {
             can_send_voice_note: Set(permissions.get_can_send_voice_notes().unwrap_or(true)),
             can_send_poll: Set(permissions.get_can_send_polls().unwrap_or(true)),
             can_send_other: Set(permissions.get_can_send_other_messages().unwrap_or(true)),
-            federation: NotSet,
+            ..Self::for_id(chat.get_id(), chat.get_tg_type())
         };
         Ok(res)
     }
@@ -1,12 +1,22 @@
+use std::collections::{HashMap, HashSet};
+
 use botapi::gen_types::MessageEntity;
-use futures::{stream, StreamExt, TryStreamExt};
-use sea_orm::{entity::prelude::*, ActiveValue, IntoActiveModel};
-use sea_query::OnConflict;
+use futures::{future::BoxFuture, stream, StreamExt, TryStreamExt};
+use sea_orm::{
+    entity::prelude::*, ActiveValue, DatabaseTransaction, IntoActiveModel, IsolationLevel,
+    QueryOrder, QuerySelect, TransactionTrait,
+};
+use sea_query::{JoinType, OnConflict};
 use serde::{Deserialize, Serialize};
 
+use crate::statics::DB;
 use crate::tg::button::InlineKeyboardBuilder;
+use crate::util::error::{BotError, Result};
+
+/// Times a save is tried before giving up on serialization failures
+const SAVE_ATTEMPTS: usize = 3;
 
-use super::{button, messageentity};
+use super::{button, messageentity, messageentity::EntityWithUser, users};
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "entitylist")]
 pub struct Model {
@@ -62,7 +72,7 @@ pub async fn insert<T>(
     conn: &T,
     entities: &Vec<MessageEntity>,
     buttons: InlineKeyboardBuilder,
-) -> crate::util::error::Result<Option<i64>>
+) -> Result<Option<i64>>
 where
     T: ConnectionTrait,
 {
@@ -142,4 +152,183 @@ where
     }
 }
 
+/// Inserts entities and buttons, then the row owning them using the new entity id, in a
+/// single serializable transaction. Nothing is written if any insert fails. The transaction
+/// is retried from the start on serialization failures, so `parent` may be called more than
+/// once
+pub async fn save_with_entities<F, R>(
+    entities: &Vec<MessageEntity>,
+    buttons: &InlineKeyboardBuilder,
+    parent: F,
+) -> Result<R>
+where
+    F: for<'c> Fn(&'c DatabaseTransaction, Option<i64>) -> BoxFuture<'c, Result<R>> + Send + Sync,
+    R: Send,
+{
+    let mut attempt = 1;
+    loop {
+        let tx = DB
+            .begin_with_config(Some(IsolationLevel::Serializable), None)
+            .await?;
+        let res = async {
+            let entity_id = insert(&tx, entities, buttons.clone()).await?;
+            parent(&tx, entity_id).await
+        }
+        .await;
+        let err = match res {
+            Ok(res) => match tx.commit().await {
+                Ok(()) => return Ok(res),
+                Err(err) => BotError::from(err),
+            },
+            Err(err) => {
+                tx.rollback().await?;
+                err
+            }
+        };
+        if attempt >= SAVE_ATTEMPTS || !err.is_serialization_failure() {
+            return Err(err);
+        }
+        log::info!("retrying save after serialization failure: {}", err);
+        attempt += 1;
+    }
+}
+
+/// Entities and buttons of several entity lists, grouped by the list owning them
+#[derive(Default, Debug)]
+pub struct EntityGroups {
+    entities: HashMap<i64, HashSet<EntityWithUser>>,
+    buttons: HashMap<i64, HashSet<button::Model>>,
+}
+
+impl EntityGroups {
+    /// Groups entities and buttons by their owner, skipping buttons without one
+    pub fn new(entities: Vec<EntityWithUser>, buttons: Vec<button::Model>) -> Self {
+        let mut groups = Self::default();
+        for entity in entities {
+            groups
+                .entities
+                .entry(entity.owner_id)
+                .or_default()
+                .insert(entity);
+        }
+        for button in buttons {
+            if let Some(owner) = button.owner_id {
+                groups.buttons.entry(owner).or_default().insert(button);
+            }
+        }
+        groups
+    }
+
+    /// Removes and returns the entities and buttons of one list, empty if it has none
+    pub fn take(&mut self, id: Option<i64>) -> (HashSet<EntityWithUser>, HashSet<button::Model>) {
+        let Some(id) = id else {
+            return (HashSet::new(), HashSet::new());
+        };
+        (
+            self.entities.remove(&id).unwrap_or_default(),
+            self.buttons.remove(&id).unwrap_or_default(),
+        )
+    }
+}
+
+/// Loads the entities and buttons of several lists with one query each. Joining both onto
+/// the rows that own them returns every entity paired with every button, which gets slow
+/// for messages with a lot of either
+pub async fn get_entity_groups<C, I>(conn: &C, ids: I) -> Result<EntityGroups>
+where
+    C: ConnectionTrait,
+    I: IntoIterator<Item = i64>,
+{
+    let ids = ids.into_iter().collect::<HashSet<i64>>();
+    if ids.is_empty() {
+        return Ok(EntityGroups::default());
+    }
+
+    let entities = messageentity::Entity::find()
+        .select_only()
+        .columns([
+            messageentity::Column::TgType,
+            messageentity::Column::Offset,
+            messageentity::Column::Length,
+            messageentity::Column::Url,
+            messageentity::Column::User,
+            messageentity::Column::Language,
+            messageentity::Column::EmojiId,
+            messageentity::Column::OwnerId,
+        ])
+        .columns([
+            users::Column::UserId,
+            users::Column::FirstName,
+            users::Column::LastName,
+            users::Column::Username,
+            users::Column::IsBot,
+        ])
+        .join(JoinType::LeftJoin, messageentity::Relation::Users.def())
+        .filter(messageentity::Column::OwnerId.is_in(ids.iter().copied()))
+        .into_model::<EntityWithUser>()
+        .all(conn)
+        .await?;
+
+    let buttons = button::Entity::find()
+        .filter(button::Column::OwnerId.is_in(ids))
+        .order_by_asc(button::Column::PosX)
+        .order_by_asc(button::Column::PosY)
+        .all(conn)
+        .await?;
+
+    Ok(EntityGroups::new(entities, buttons))
+}
+
 impl ActiveModelBehavior for ActiveModel {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::persist::core::messageentity::DbMarkupType;
+
+    fn entity(owner_id: i64) -> EntityWithUser {
+        EntityWithUser {
+            tg_type: DbMarkupType::Bold,
+            offset: 0,
+            length: 1,
+            url: None,
+            user: None,
+            language: None,
+            emoji_id: None,
+            owner_id,
+            user_id: None,
+            first_name: None,
+            last_name: None,
+            username: None,
+            is_bot: None,
+        }
+    }
+
+    fn button(owner_id: Option<i64>, pos_x: i32) -> button::Model {
+        button::Model {
+            button_text: "button".to_owned(),
+            callback_data: None,
+            button_url: None,
+            owner_id,
+            pos_x,
+            pos_y: 0,
+            raw_text: None,
+        }
+    }
+
+    #[test]
+    fn groups_by_owner() {
+        let mut groups = EntityGroups::new(
+            vec![entity(1), entity(2)],
+            vec![button(Some(1), 0), button(Some(1), 1), button(None, 0)],
+        );
+        let (entities, buttons) = groups.take(Some(1));
+        assert_eq!(entities.len(), 1);
+        assert_eq!(buttons.len(), 2);
+        let (entities, buttons) = groups.take(Some(2));
+        assert_eq!(entities.len(), 1);
+        assert!(buttons.is_empty());
+        let (entities, buttons) = groups.take(None);
+        assert!(entities.is_empty() && buttons.is_empty());
+    }
+}
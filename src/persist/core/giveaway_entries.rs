@@ -0,0 +1,35 @@
+//! ORM type for members entered in a giveaway
+
+use chrono::Utc;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "giveaway_entries")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub giveaway_id: i64,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub user_id: i64,
+    pub entered: chrono::DateTime<Utc>,
+    /// whether the member was drawn as a winner, so rerolls pick someone else
+    pub won: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::giveaways::Entity",
+        from = "Column::GiveawayId",
+        to = "super::giveaways::Column::Id"
+    )]
+    Giveaway,
+}
+
+impl Related<super::giveaways::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Giveaway.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,41 @@
+//! ORM type for giveaways. Members enter by pushing the button on the giveaway's post, and
+//! winners are drawn from the entries when the giveaway ends
+
+use chrono::Utc;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "giveaways")]
+pub struct Model {
+    #[sea_orm(primary_key, autoincrement = true)]
+    pub id: i64,
+    pub chat_id: i64,
+    /// id of the post members enter with, set once it is sent
+    pub message_id: Option<i64>,
+    #[sea_orm(column_type = "Text")]
+    pub prize: String,
+    /// how many winners are drawn
+    pub winners: i32,
+    /// the admin who started the giveaway
+    pub author: i64,
+    pub ends: chrono::DateTime<Utc>,
+    /// whether the giveaway stopped taking entries and winners were drawn
+    pub ended: bool,
+    /// whether the winners were announced, the draw is retried until they are
+    pub announced: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "super::giveaway_entries::Entity")]
+    Entries,
+}
+
+impl Related<super::giveaway_entries::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Entries.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
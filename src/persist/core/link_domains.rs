@@ -0,0 +1,20 @@
+//! ORM type for per-chat allowlists and denylists of domains that links in messages are
+//! checked against by the domains lock
+
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "link_domains")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub chat_id: i64,
+    #[sea_orm(primary_key, column_type = "Text")]
+    pub domain: String,
+    pub allow: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -3,20 +3,24 @@ use crate::{
     tg::{
         admin_helpers::{is_dm, IntoChatUser},
         button::InlineKeyboardBuilder,
-        command::{post_deep_link, Context},
-        markdown::{button_deeplink_key, retro_fillings, EntityMessage, MarkupBuilder},
+        command::Context,
+        deeplink::DeepLink,
+        markdown::{retro_fillings, EntityMessage, MarkupBuilder},
+        media::{reply_media, send_media, SendableMedia},
     },
     util::{
         error::{BotError, Fail, Result},
-        string::should_ignore_chat,
+        error_codes::{INTERNAL, UNSUPPORTED_MEDIA},
+        string::{get_chat_lang, should_ignore_chat},
     },
 };
 use botapi::gen_types::{
-    EReplyMarkup, FileData, InlineKeyboardButton, InputFile, InputMedia, InputMediaAudioBuilder,
-    InputMediaDocumentBuilder, InputMediaPhotoBuilder, InputMediaVideoBuilder,
-    LinkPreviewOptionsBuilder, Message, MessageEntity, ReplyParametersBuilder,
+    EReplyMarkup, InlineKeyboardButton, InputFile, InputMedia, InputMediaAnimationBuilder,
+    InputMediaAudioBuilder, InputMediaDocumentBuilder, InputMediaPhotoBuilder,
+    InputMediaVideoBuilder, Message, MessageEntity,
 };
 use futures::future::BoxFuture;
+use macros::lang_fmt;
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -30,6 +34,11 @@ impl GetMediaId for Message {
             return Some((image.get_file_id(), MediaType::Photo));
         }
 
+        // animations also set the document field, so check them first
+        if let Some(animation) = self.get_animation() {
+            return Some((animation.get_file_id(), MediaType::Animation));
+        }
+
         if let Some(document) = self.get_document() {
             return Some((document.get_file_id(), MediaType::Document));
         }
@@ -42,6 +51,10 @@ impl GetMediaId for Message {
             return Some((video.get_file_id(), MediaType::Video));
         }
 
+        if let Some(voice) = self.get_voice() {
+            return Some((voice.get_file_id(), MediaType::Voice));
+        }
+
         None
     }
 }
@@ -63,6 +76,10 @@ pub enum MediaType {
     Video,
     #[sea_orm(num_value = 6)]
     Audio,
+    #[sea_orm(num_value = 7)]
+    Animation,
+    #[sea_orm(num_value = 8)]
+    Voice,
 }
 
 impl std::fmt::Display for MediaType {
@@ -74,6 +91,8 @@ impl std::fmt::Display for MediaType {
             Self::Text => f.write_str("text"),
             Self::Video => f.write_str("video"),
             Self::Audio => f.write_str("audio"),
+            Self::Animation => f.write_str("animation"),
+            Self::Voice => f.write_str("voice"),
         }
     }
 }
@@ -88,6 +107,8 @@ impl MediaType {
             Self::Video => 3,
             Self::Text => 0,
             Self::Audio => 6,
+            Self::Animation => 9,
+            Self::Voice => 4,
         }
     }
 
@@ -98,13 +119,15 @@ impl MediaType {
             2 => Self::Photo,
             8 => Self::Document,
             3 => Self::Video,
+            9 => Self::Animation,
+            4 => Self::Voice,
             _ => Self::Text,
         }
     }
 }
 
 /// Returns a tuple containing the MediaType and caption if exists for the provided message
-pub fn get_media_type(message: &Message) -> Result<(Option<String>, MediaType)> {
+pub async fn get_media_type(message: &Message) -> Result<(Option<String>, MediaType)> {
     if let Some(photo) = message
         .get_photo()
         .and_then(|p| p.first().map(|v| v.to_owned()))
@@ -112,19 +135,33 @@ pub fn get_media_type(message: &Message) -> Result<(Option<String>, MediaType)>
         Ok((Some(photo.get_file_id().to_owned()), MediaType::Photo))
     } else if let Some(sticker) = message.get_sticker().map(|s| s.get_file_id().to_owned()) {
         Ok((Some(sticker), MediaType::Sticker))
+    } else if let Some(animation) = message.get_animation().map(|a| a.get_file_id().to_owned()) {
+        Ok((Some(animation), MediaType::Animation))
     } else if let Some(document) = message.get_document().map(|d| d.get_file_id().to_owned()) {
         Ok((Some(document), MediaType::Document))
     } else if let Some(video) = message.get_video().map(|v| v.get_file_id().to_owned()) {
         Ok((Some(video), MediaType::Video))
     } else if let Some(audio) = message.get_audio().map(|v| v.get_file_id().to_owned()) {
         Ok((Some(audio), MediaType::Audio))
+    } else if let Some(voice) = message.get_voice().map(|v| v.get_file_id().to_owned()) {
+        Ok((Some(voice), MediaType::Voice))
     } else if message.get_text().is_some() {
         Ok((None, MediaType::Text))
     } else {
-        message.fail("invalid")
+        let lang = get_chat_lang(message.get_chat().get_id()).await?;
+        message.fail_code(UNSUPPORTED_MEDIA, lang_fmt!(lang, "errinvalidmedia"))
     }
 }
 
+fn invalid_media(ctx: &Context) -> BotError {
+    ctx.fail_err_code(UNSUPPORTED_MEDIA, lang_fmt!(ctx, "errinvalidmedia"))
+}
+
+/// Error for a media reply built without something it needs
+fn internal_error(ctx: &Context) -> BotError {
+    ctx.fail_err_code(INTERNAL, lang_fmt!(ctx, "errinternal"))
+}
+
 /// Helper type for sending media referenced from database with optional InlineKeyboardMarkup
 // and formatted captions
 pub struct SendMediaReply<'a, F>
@@ -205,7 +242,7 @@ where
             let buttonlist = self
                 .buttons
                 .as_mut()
-                .ok_or_else(|| self.context.fail_err("Missing buttons"))?;
+                .ok_or_else(|| internal_error(self.context))?;
             for l in buttonlist.get_mut() {
                 for b in l.iter_mut() {
                     if let Some(ref button) = b.raw_text {
@@ -216,7 +253,7 @@ where
                             b.callback_data = Some(Uuid::new_v4().to_string());
                             self.callback
                                 .as_ref()
-                                .ok_or_else(|| self.context.fail_err("no callback"))?(
+                                .ok_or_else(|| internal_error(self.context))?(
                                 tail.to_owned(),
                                 &b.clone().to_button(),
                             )
@@ -228,7 +265,12 @@ where
                             let chat = chat.chat.get_id();
                             let tail = &button[1..];
 
-                            let url = post_deep_link((chat, tail), button_deeplink_key).await?;
+                            let url = DeepLink::Note {
+                                chat,
+                                name: tail.to_owned(),
+                            }
+                            .url()
+                            .await?;
                             b.button_url = Some(url);
                         };
                     }
@@ -267,9 +309,7 @@ where
         } else {
             self.note_button().await?;
             let text = self.text;
-            let callback = self
-                .callback
-                .ok_or_else(|| self.context.fail_err("Need to set callback"))?;
+            let callback = self.callback.ok_or_else(|| internal_error(self.context))?;
 
             let chat = current_message.get_chat().get_id();
             if should_ignore_chat(chat).await? {
@@ -295,6 +335,7 @@ where
             } else {
                 MarkupBuilder::new(None)
                     .set_text(text)
+                    .variants(true)
                     .filling(false)
                     .header(false)
                     .callback(callback)
@@ -310,27 +351,28 @@ where
             };
 
             let input_media = match self.media_type {
-                MediaType::Sticker => {
+                // stickers and voice messages can't be edited in, so replace the message
+                MediaType::Sticker | MediaType::Voice => {
                     TG.client
                         .build_delete_message(chat, current_message.get_message_id())
                         .build()
                         .await?;
-                    TG.client()
-                        .build_send_sticker(
-                            chat,
-                            FileData::String(
-                                self.media_id
-                                    .ok_or_else(|| current_message.fail_err("invalid media"))?,
-                            ),
-                        )
-                        .build()
-                        .await?;
+                    send_media(
+                        chat,
+                        SendableMedia {
+                            media_id: self.media_id,
+                            media_type: self.media_type,
+                            caption: text,
+                            entities,
+                            buttons: Some(EReplyMarkup::InlineKeyboardMarkup(buttons.clone())),
+                        },
+                    )
+                    .await?;
                     None
                 }
                 MediaType::Photo => Some(InputMedia::InputMediaPhoto(
                     InputMediaPhotoBuilder::new(Some(InputFile::String(
-                        self.media_id
-                            .ok_or_else(|| current_message.fail_err("invalid media"))?,
+                        self.media_id.ok_or_else(|| invalid_media(self.context))?,
                     )))
                     .set_caption(text)
                     .set_caption_entities(entities)
@@ -338,8 +380,7 @@ where
                 )),
                 MediaType::Document => Some(InputMedia::InputMediaDocument(
                     InputMediaDocumentBuilder::new(Some(InputFile::String(
-                        self.media_id
-                            .ok_or_else(|| current_message.fail_err("invalid media"))?,
+                        self.media_id.ok_or_else(|| invalid_media(self.context))?,
                     )))
                     .set_caption(text)
                     .set_caption_entities(entities)
@@ -347,8 +388,7 @@ where
                 )),
                 MediaType::Video => Some(InputMedia::InputMediaVideo(
                     InputMediaVideoBuilder::new(Some(InputFile::String(
-                        self.media_id
-                            .ok_or_else(|| current_message.fail_err("invalid media"))?,
+                        self.media_id.ok_or_else(|| invalid_media(self.context))?,
                     )))
                     .set_caption(text)
                     .set_caption_entities(entities)
@@ -367,8 +407,15 @@ where
                 }
                 MediaType::Audio => Some(InputMedia::InputMediaAudio(
                     InputMediaAudioBuilder::new(Some(InputFile::String(
-                        self.media_id
-                            .ok_or_else(|| current_message.fail_err("invalid media"))?,
+                        self.media_id.ok_or_else(|| invalid_media(self.context))?,
+                    )))
+                    .set_caption(text)
+                    .set_caption_entities(entities)
+                    .build(),
+                )),
+                MediaType::Animation => Some(InputMedia::InputMediaAnimation(
+                    InputMediaAnimationBuilder::new(Some(InputFile::String(
+                        self.media_id.ok_or_else(|| invalid_media(self.context))?,
                     )))
                     .set_caption(text)
                     .set_caption_entities(entities)
@@ -430,6 +477,7 @@ where
             } else {
                 MarkupBuilder::new(None)
                     .set_text(text)
+                    .variants(true)
                     .filling(false)
                     .header(false)
                     .callback(callback)
@@ -442,95 +490,17 @@ where
                 buttons = extra_buttons;
             }
 
-            let buttons = EReplyMarkup::InlineKeyboardMarkup(buttons.build());
-
-            match self.media_type {
-                MediaType::Sticker => {
-                    TG.client()
-                        .build_send_sticker(
-                            chat,
-                            FileData::String(
-                                self.media_id
-                                    .ok_or_else(|| self.context.fail_err("invalid media"))?,
-                            ),
-                        )
-                        .build()
-                        .await
-                }
-                MediaType::Photo => {
-                    TG.client()
-                        .build_send_photo(
-                            chat,
-                            FileData::String(
-                                self.media_id
-                                    .ok_or_else(|| self.context.fail_err("invalid media"))?,
-                            ),
-                        )
-                        .caption(&text)
-                        .caption_entities(&entities)
-                        .reply_markup(&buttons)
-                        .build()
-                        .await
-                }
-                MediaType::Document => {
-                    TG.client()
-                        .build_send_document(
-                            chat,
-                            FileData::String(
-                                self.media_id
-                                    .ok_or_else(|| self.context.fail_err("invalid media"))?,
-                            ),
-                        )
-                        .reply_markup(&buttons)
-                        .caption_entities(&entities)
-                        .caption(&text)
-                        .build()
-                        .await
-                }
-                MediaType::Video => {
-                    TG.client()
-                        .build_send_video(
-                            chat,
-                            FileData::String(
-                                self.media_id
-                                    .ok_or_else(|| self.context.fail_err("invalid media"))?,
-                            ),
-                        )
-                        .caption(&text)
-                        .reply_markup(&buttons)
-                        .caption_entities(&entities)
-                        .build()
-                        .await
-                }
-                MediaType::Audio => {
-                    TG.client
-                        .build_send_audio(
-                            chat,
-                            FileData::String(
-                                self.media_id
-                                    .ok_or_else(|| self.context.fail_err("invalid media"))?,
-                            ),
-                        )
-                        .caption(&text)
-                        .reply_markup(&buttons)
-                        .caption_entities(&entities)
-                        .build()
-                        .await
-                }
-                MediaType::Text => {
-                    TG.client()
-                        .build_send_message(chat, &text)
-                        .reply_markup(&buttons)
-                        .entities(&entities)
-                        .link_preview_options(
-                            &LinkPreviewOptionsBuilder::new()
-                                .set_is_disabled(true)
-                                .build(),
-                        )
-                        .build()
-                        .await
-                }
-            }?;
+            send_media(
+                chat,
+                SendableMedia {
+                    media_id: self.media_id,
+                    media_type: self.media_type,
+                    caption: text,
+                    entities,
+                    buttons: Some(EReplyMarkup::InlineKeyboardMarkup(buttons.build())),
+                },
+            )
+            .await?;
         }
         Ok(())
     }
@@ -565,6 +535,7 @@ where
         } else {
             MarkupBuilder::new(None)
                 .set_text(text)
+                .variants(true)
                 .filling(false)
                 .header(false)
                 .callback(callback)
@@ -577,111 +548,17 @@ where
             buttons = extra_buttons;
         }
 
-        let buttons = EReplyMarkup::InlineKeyboardMarkup(buttons.build());
-
-        match self.media_type {
-            MediaType::Sticker => {
-                TG.client()
-                    .build_send_sticker(
-                        chat,
-                        FileData::String(
-                            self.media_id
-                                .ok_or_else(|| self.context.fail_err("invalid media"))?,
-                        ),
-                    )
-                    .reply_parameters(
-                        &ReplyParametersBuilder::new(message.get_message_id()).build(),
-                    )
-                    .build()
-                    .await
-            }
-            MediaType::Photo => {
-                TG.client()
-                    .build_send_photo(
-                        chat,
-                        FileData::String(
-                            self.media_id
-                                .ok_or_else(|| message.fail_err("invalid media"))?,
-                        ),
-                    )
-                    .caption(&text)
-                    .caption_entities(&entities)
-                    .reply_markup(&buttons)
-                    .reply_parameters(
-                        &ReplyParametersBuilder::new(message.get_message_id()).build(),
-                    )
-                    .build()
-                    .await
-            }
-            MediaType::Document => {
-                TG.client()
-                    .build_send_document(
-                        chat,
-                        FileData::String(
-                            self.media_id
-                                .ok_or_else(|| message.fail_err("invalid media"))?,
-                        ),
-                    )
-                    .reply_markup(&buttons)
-                    .caption_entities(&entities)
-                    .caption(&text)
-                    .reply_parameters(
-                        &ReplyParametersBuilder::new(message.get_message_id()).build(),
-                    )
-                    .build()
-                    .await
-            }
-            MediaType::Video => {
-                TG.client()
-                    .build_send_video(
-                        chat,
-                        FileData::String(
-                            self.media_id
-                                .ok_or_else(|| message.fail_err("invalid media"))?,
-                        ),
-                    )
-                    .reply_parameters(
-                        &ReplyParametersBuilder::new(message.get_message_id()).build(),
-                    )
-                    .caption(&text)
-                    .reply_markup(&buttons)
-                    .caption_entities(&entities)
-                    .build()
-                    .await
-            }
-
-            MediaType::Audio => {
-                TG.client
-                    .build_send_audio(
-                        chat,
-                        FileData::String(
-                            self.media_id
-                                .ok_or_else(|| self.context.fail_err("invalid media"))?,
-                        ),
-                    )
-                    .caption(&text)
-                    .reply_markup(&buttons)
-                    .caption_entities(&entities)
-                    .build()
-                    .await
-            }
-            MediaType::Text => {
-                TG.client()
-                    .build_send_message(chat, &text)
-                    .reply_parameters(
-                        &ReplyParametersBuilder::new(message.get_message_id()).build(),
-                    )
-                    .reply_markup(&buttons)
-                    .entities(&entities)
-                    .link_preview_options(
-                        &LinkPreviewOptionsBuilder::new()
-                            .set_is_disabled(true)
-                            .build(),
-                    )
-                    .build()
-                    .await
-            }
-        }?;
+        reply_media(
+            message,
+            SendableMedia {
+                media_id: self.media_id,
+                media_type: self.media_type,
+                caption: text,
+                entities,
+                buttons: Some(EReplyMarkup::InlineKeyboardMarkup(buttons.build())),
+            },
+        )
+        .await?;
 
         Ok(())
     }
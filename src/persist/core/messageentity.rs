@@ -24,7 +24,7 @@ pub struct Model {
     pub owner_id: i64,
 }
 
-#[derive(FromQueryResult, Debug, PartialEq, Eq, Hash)]
+#[derive(FromQueryResult, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct EntityWithUser {
     // entity fields
     pub tg_type: DbMarkupType,
@@ -1,17 +1,32 @@
+pub mod birthday_settings;
+pub mod birthdays;
 pub mod button;
+pub mod button_domains;
+pub mod chat_boosts;
 pub mod chat_members;
+pub mod chat_strings;
 pub mod chat_type;
+pub mod chats;
+pub mod command_aliases;
 pub mod conversation_states;
 pub mod conversation_transitions;
 pub mod conversations;
 pub mod dialogs;
 pub mod entity;
+pub mod giveaway_entries;
+pub mod giveaways;
+pub mod link_domains;
 pub mod media;
 pub mod messageentity;
 pub mod module_schemas;
 pub mod notes;
 pub mod prelude;
+pub mod recurring_messages;
 pub mod rules;
 pub mod taint;
+pub mod username_history;
 pub mod users;
+pub mod welcome_messages;
 pub mod welcomes;
+
+pub use entity::save_with_entities;
@@ -7,6 +7,7 @@ use crate::{
         messageentity::{self, DbMarkupType, EntityWithUser},
         users,
     },
+    persist::db::ReadReplica,
     statics::DB,
 };
 
@@ -216,7 +217,7 @@ where
         .order_by_asc(button::Column::PosX)
         .order_by_asc(button::Column::PosY)
         .into_model::<FiltersWithEntities>()
-        .all(*DB)
+        .all(DB.read())
         .await?;
 
     let res = res.into_iter().map(|v| v.get()).fold(
@@ -0,0 +1,28 @@
+//! ORM type for announcements posted in a chat on a recurring schedule. The schedule is kept
+//! as the text the admin wrote and parsed again each time the next run is worked out
+
+use chrono::Utc;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, DeriveEntityModel)]
+#[sea_orm(table_name = "recurring_messages")]
+pub struct Model {
+    #[sea_orm(primary_key, autoincrement = true)]
+    pub id: i64,
+    pub chat_id: i64,
+    /// when to post, like "daily 09:00" or a cron expression
+    #[sea_orm(column_type = "Text")]
+    pub schedule: String,
+    /// murkdown source of the announcement
+    #[sea_orm(column_type = "Text")]
+    pub text: String,
+    /// the admin who added the announcement
+    pub author: i64,
+    pub created: chrono::DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
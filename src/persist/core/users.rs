@@ -31,6 +31,9 @@ impl From<Model> for User {
         if let Some(name) = value.last_name {
             builder = builder.set_last_name(name);
         }
+        if let Some(username) = value.username {
+            builder = builder.set_username(username);
+        }
         builder.build()
     }
 }
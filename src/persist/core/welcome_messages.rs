@@ -0,0 +1,33 @@
+//! ORM type for a chat's welcome and goodbye messages. A chat can have several of each,
+//! which one is sent is decided by the rotation in the chat's welcome settings
+
+use crate::persist::core::media::*;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize, Hash, Eq)]
+#[sea_orm(table_name = "welcome_messages")]
+pub struct Model {
+    #[sea_orm(primary_key, autoincrement = true)]
+    pub id: i64,
+    pub chat: i64,
+    /// sent when members leave instead of when they join
+    pub goodbye: bool,
+    #[sea_orm(column_type = "Text")]
+    pub text: Option<String>,
+    pub media_id: Option<String>,
+    pub media_type: MediaType,
+    pub entity_id: Option<i64>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::persist::core::entity::Entity",
+        from = "Column::EntityId",
+        to = "crate::persist::core::entity::Column::Id"
+    )]
+    Entities,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,256 @@
+//! Per-chat redis keys. Every key holding something about a single chat is built through
+//! [`RedisScope`], so it lives under `c:<chat>:` and belongs to a [`KeyClass`] saying how
+//! long keys of its kind are kept. Having every chat's keys under one prefix lets /cachedebug
+//! list them and lets whatever clears a chat's cache find them all with one scan.
+
+use std::fmt::{self, Display};
+use std::ops::Deref;
+
+use redis::{AsyncCommands, Pipeline, RedisWrite, ToRedisArgs};
+
+use crate::statics::{CONFIG, REDIS};
+use crate::util::error::Result;
+
+/// How long keys of a class live
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ttl {
+    /// expire after a fixed number of seconds
+    Seconds(i64),
+    /// expire after the configured cache timeout
+    CacheTimeout,
+    /// expiry is managed by whatever writes the key, or it never expires
+    Unmanaged,
+}
+
+impl Ttl {
+    /// Seconds keys with this policy should live for, None if the policy doesn't say
+    pub fn seconds(&self) -> Option<i64> {
+        match self {
+            Self::Seconds(seconds) => Some(*seconds),
+            Self::CacheTimeout => Some(CONFIG.load().timing.cache_timeout),
+            Self::Unmanaged => None,
+        }
+    }
+}
+
+/// A kind of per-chat key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyClass {
+    pub name: &'static str,
+    pub ttl: Ttl,
+}
+
+impl KeyClass {
+    pub const fn new(name: &'static str, ttl: Ttl) -> Self {
+        Self { name, ttl }
+    }
+}
+
+/// The chat's admins as seen by one bot
+pub const ADMINS: KeyClass = KeyClass::new("ca", Ttl::Seconds(48 * 60 * 60));
+
+/// Lock stopping admins from refreshing the admin cache too often
+pub const ADMINS_REFRESH: KeyClass = KeyClass::new("frca", Ttl::Seconds(10 * 60));
+
+/// The chat's info from telegram
+pub const CHAT_INFO: KeyClass = KeyClass::new("gcch", Ttl::Seconds(15));
+
+/// The chat's member count from telegram
+pub const MEMBER_COUNT: KeyClass = KeyClass::new("mcount", Ttl::Seconds(60 * 60));
+
+/// A user's stored admin action
+pub const ACTIONS: KeyClass = KeyClass::new("act", Ttl::CacheTimeout);
+
+/// A user's warns
+pub const WARNS: KeyClass = KeyClass::new("warns", Ttl::CacheTimeout);
+
+/// Whether a user is approved
+pub const APPROVALS: KeyClass = KeyClass::new("ap", Ttl::CacheTimeout);
+
+/// Generation counter of a [`crate::persist::redis::CacheScope`], which must outlive
+/// everything cached under it
+pub const GENERATION: KeyClass = KeyClass::new("cgen", Ttl::Unmanaged);
+
+/// Every key class, used to tell which class a key belongs to
+pub const ALL: &[KeyClass] = &[
+    ADMINS,
+    ADMINS_REFRESH,
+    CHAT_INFO,
+    MEMBER_COUNT,
+    ACTIONS,
+    WARNS,
+    APPROVALS,
+    GENERATION,
+];
+
+/// Key builder for one chat
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RedisScope {
+    chat: i64,
+}
+
+/// Gets the key builder for a chat
+#[inline(always)]
+pub fn scope(chat: i64) -> RedisScope {
+    RedisScope { chat }
+}
+
+impl RedisScope {
+    /// Key of a class holding one thing for the whole chat
+    pub fn key(&self, class: KeyClass) -> ScopedKey {
+        ScopedKey {
+            key: format!("c:{}:{}", self.chat, class.name),
+            class,
+        }
+    }
+
+    /// Key of a class holding one thing per id, like a user, in the chat
+    pub fn key_for<D: Display>(&self, class: KeyClass, id: D) -> ScopedKey {
+        ScopedKey {
+            key: format!("c:{}:{}:{}", self.chat, class.name, id),
+            class,
+        }
+    }
+
+    /// Pattern matching every key in this scope
+    pub fn pattern(&self) -> String {
+        format!("c:{}:*", self.chat)
+    }
+
+    /// Finds the class of a key in this scope
+    pub fn class_of(&self, key: &str) -> Option<KeyClass> {
+        let prefix = format!("c:{}:", self.chat);
+        let name = key.strip_prefix(&prefix)?.split(':').next()?;
+        ALL.iter().find(|class| class.name == name).copied()
+    }
+}
+
+/// A key built by [`RedisScope`], usable anywhere redis takes a key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScopedKey {
+    key: String,
+    class: KeyClass,
+}
+
+impl ScopedKey {
+    /// Seconds this key should live for, from its class
+    pub fn ttl(&self) -> Option<i64> {
+        self.class.ttl.seconds()
+    }
+
+    pub fn class(&self) -> KeyClass {
+        self.class
+    }
+
+    /// Adds an expiry from the key's class to a pipeline, if the class has one
+    pub fn expire<'a>(&self, pipe: &'a mut Pipeline) -> &'a mut Pipeline {
+        match self.ttl() {
+            Some(ttl) => pipe.expire(self, ttl),
+            None => pipe,
+        }
+    }
+}
+
+impl Deref for ScopedKey {
+    type Target = str;
+    fn deref(&self) -> &Self::Target {
+        &self.key
+    }
+}
+
+impl AsRef<str> for ScopedKey {
+    fn as_ref(&self) -> &str {
+        &self.key
+    }
+}
+
+impl Display for ScopedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.key)
+    }
+}
+
+impl From<ScopedKey> for String {
+    fn from(value: ScopedKey) -> Self {
+        value.key
+    }
+}
+
+impl ToRedisArgs for ScopedKey {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + RedisWrite,
+    {
+        self.key.write_redis_args(out)
+    }
+}
+
+/// A cached key as reported by /cachedebug
+pub struct KeyInfo {
+    pub key: String,
+    pub kind: String,
+
+    /// bytes used by the key, None if redis doesn't support MEMORY USAGE
+    pub size: Option<i64>,
+
+    /// seconds left before the key expires, -1 if it never does
+    pub ttl: i64,
+    pub class: Option<KeyClass>,
+}
+
+impl KeyInfo {
+    /// Whether the key has no expiry even though its class says it should
+    pub fn missing_ttl(&self) -> bool {
+        self.ttl < 0 && self.class.and_then(|class| class.ttl.seconds()).is_some()
+    }
+}
+
+/// Lists every key in a chat's scope along with its type, size, and remaining ttl
+pub async fn audit_scope(chat: i64) -> Result<Vec<KeyInfo>> {
+    let scope = scope(chat);
+    let pattern = scope.pattern();
+    let keys: Vec<String> = REDIS
+        .query(|mut q| async move {
+            let mut keys = Vec::new();
+            let mut iter = q.scan_match::<_, String>(&pattern).await?;
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+            Ok(keys)
+        })
+        .await?;
+
+    let mut info = Vec::with_capacity(keys.len());
+    for key in keys {
+        let (kind, ttl): (String, i64) = REDIS.pipe(|p| p.cmd("TYPE").arg(&key).ttl(&key)).await?;
+        let size: Option<i64> = REDIS
+            .pipe(|p| p.cmd("MEMORY").arg("USAGE").arg(&key))
+            .await
+            .ok()
+            .and_then(|(size,): (Option<i64>,)| size);
+        info.push(KeyInfo {
+            class: scope.class_of(&key),
+            key,
+            kind,
+            size,
+            ttl,
+        });
+    }
+    info.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(info)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scoped_keys() {
+        let scope = scope(-100);
+        assert_eq!(&*scope.key(CHAT_INFO), "c:-100:gcch");
+        assert_eq!(&*scope.key_for(WARNS, 5), "c:-100:warns:5");
+        assert_eq!(scope.class_of("c:-100:warns:5"), Some(WARNS));
+        assert_eq!(scope.class_of("c:-101:warns:5"), None);
+        assert_eq!(scope.class_of("c:-100:nope"), None);
+    }
+}
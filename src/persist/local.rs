@@ -0,0 +1,135 @@
+//! In-process cache in front of redis for lookups made on nearly every message, like a
+//! chat's language or whether a user is an admin. Entries live for a short time and are
+//! dropped early when something changes them: writers call [`LocalCache::invalidate`],
+//! which publishes the entry's key on a redis channel every instance listens to.
+//!
+//! An instance that misses an invalidation, because its subscription dropped or an
+//! invalidation raced a load, serves the stale entry until it expires, so the ttl should
+//! stay short. The cache is off unless `local_cache.enabled` is set, in which case every
+//! lookup goes straight to redis like before.
+
+use std::any::Any;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use lazy_static::lazy_static;
+use moka::sync::Cache;
+use redis::AsyncCommands;
+
+use crate::statics::{CONFIG, REDIS};
+use crate::util::error::Result;
+
+/// Channel invalidated keys are published on
+const INVALIDATE_CHANNEL: &str = "localcache:invalidate";
+
+/// Wait before resubscribing after losing the subscription
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref LOCAL: Cache<String, Arc<dyn Any + Send + Sync>> = {
+        let config = &CONFIG.load().local_cache;
+        Cache::builder()
+            .max_capacity(config.capacity)
+            .time_to_live(Duration::from_millis(config.ttl))
+            .build()
+    };
+}
+
+/// Whether lookups should use the local cache
+#[inline(always)]
+pub fn enabled() -> bool {
+    CONFIG.load().local_cache.enabled
+}
+
+/// A kind of value in the local cache. Every kind shares the same memory limit, keys are
+/// prefixed with the kind's name so they don't collide
+pub struct LocalCache<V> {
+    name: &'static str,
+    value: PhantomData<fn() -> V>,
+}
+
+impl<V> LocalCache<V>
+where
+    V: Clone + Send + Sync + 'static,
+{
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            value: PhantomData,
+        }
+    }
+
+    #[inline(always)]
+    fn get_key(&self, key: &str) -> String {
+        format!("{}:{}", self.name, key)
+    }
+
+    /// Returns the cached value for `key`, loading and caching it with `load` on a miss.
+    /// Calls `load` every time if the cache is disabled
+    pub async fn get_or<F, Fut>(&self, key: &str, load: F) -> Result<V>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V>>,
+    {
+        if !enabled() {
+            return load().await;
+        }
+        let key = self.get_key(key);
+        if let Some(value) = LOCAL.get(&key).and_then(|v| v.downcast_ref::<V>().cloned()) {
+            return Ok(value);
+        }
+        let value = load().await?;
+        LOCAL.insert(key, Arc::new(value.clone()));
+        Ok(value)
+    }
+
+    /// Drops `key` from this instance's cache and tells every other instance to drop it
+    /// too. Call after writing whatever the key caches
+    pub async fn invalidate(&self, key: &str) -> Result<()> {
+        if !enabled() {
+            return Ok(());
+        }
+        let key = self.get_key(key);
+        LOCAL.invalidate(&key);
+        let _: i64 = REDIS.sq(|q| q.publish(INVALIDATE_CHANNEL, &key)).await?;
+        Ok(())
+    }
+}
+
+/// Drops keys published by other instances until the subscription fails
+async fn listen() -> Result<()> {
+    let client = redis::Client::open(CONFIG.load().persistence.redis_connection.as_str())?;
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.subscribe(INVALIDATE_CHANNEL).await?;
+
+    // anything invalidated while we weren't subscribed is still cached
+    LOCAL.invalidate_all();
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let key: String = message.get_payload()?;
+        LOCAL.invalidate(&key);
+    }
+    Ok(())
+}
+
+/// Start the background task applying invalidations from other instances
+pub fn spawn_invalidation_listener() -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            if enabled() {
+                match listen().await {
+                    Ok(()) => log::warn!("local cache invalidation subscription closed"),
+                    Err(err) => {
+                        log::warn!("local cache invalidation subscription failed: {}", err);
+                        err.record_stats();
+                    }
+                }
+                LOCAL.invalidate_all();
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    })
+}
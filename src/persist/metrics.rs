@@ -1,13 +1,98 @@
 //! Counters and functions for collecting usage metrics and error reporting
 //! mainly used with prometheus
 
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use lazy_static::lazy_static;
-use prometheus::{register_int_counter, IntCounter};
+use prometheus::{register_int_counter, register_int_counter_vec, IntCounter, IntCounterVec};
+use redis::AsyncCommands;
+
+use crate::statics::REDIS;
+use crate::util::error::Result;
+
+/// hourly buckets are kept a little longer than a day so the oldest bucket is complete
+const BUCKET_EXPIRY: i64 = 25 * 60 * 60;
+
 //counters
 lazy_static! {
     /// map of counters for telegram error codes, lazy initialized, one per http error code
     pub static ref ERROR_CODES_MAP: DashMap<i64, IntCounter> = DashMap::new();
+
+    /// counter for updates received from telegram
+    pub static ref UPDATES_COUNTER: IntCounter =
+        register_int_counter!("updates", "Updates received").unwrap();
+
+    /// counter for commands handled
+    pub static ref COMMANDS_COUNTER: IntCounter =
+        register_int_counter!("commands", "Commands handled").unwrap();
+
+    /// counter for updates dropped because they were already handled
+    pub static ref DUPLICATE_UPDATES_COUNTER: IntCounter =
+        register_int_counter!("duplicate_updates", "Duplicate updates dropped").unwrap();
+
+    /// counter for strings shown in english because the chat's language doesn't translate
+    /// them, by language and string key
+    pub static ref STRING_FALLBACKS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "string_fallbacks",
+        "Strings shown in a fallback language",
+        &["lang", "key"]
+    )
+    .unwrap();
+
+    /// time the bot was started, used for averages over the lifetime of the process
+    pub static ref START_TIME: DateTime<Utc> = Utc::now();
+}
+
+/// Events counted both with prometheus and in hourly buckets in redis, so that counts
+/// over the last day are shared between restarts and multiple instances
+#[derive(Clone, Copy, Debug)]
+pub enum Metric {
+    Update,
+    Command,
+    DuplicateUpdate,
+}
+
+impl Metric {
+    fn counter(&self) -> &'static IntCounter {
+        match self {
+            Self::Update => &UPDATES_COUNTER,
+            Self::Command => &COMMANDS_COUNTER,
+            Self::DuplicateUpdate => &DUPLICATE_UPDATES_COUNTER,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Update => "upd",
+            Self::Command => "cmd",
+            Self::DuplicateUpdate => "dup",
+        }
+    }
+}
+
+#[inline(always)]
+fn get_metric_key(metric: Metric, hour: i64) -> String {
+    format!("mstat:{}:{}", metric.name(), hour)
+}
+
+/// Counts a single event
+pub async fn count_metric(metric: Metric) -> Result<()> {
+    metric.counter().inc();
+    let key = get_metric_key(metric, Utc::now().timestamp() / 3600);
+    REDIS
+        .pipe(|q| q.incr(&key, 1).expire(&key, BUCKET_EXPIRY))
+        .await?;
+    Ok(())
+}
+
+/// Gets the number of events in the last 24 hours
+pub async fn metric_last_day(metric: Metric) -> Result<i64> {
+    let hour = Utc::now().timestamp() / 3600;
+    let keys = (0..24)
+        .map(|h| get_metric_key(metric, hour - h))
+        .collect::<Vec<String>>();
+    let counts: Vec<Option<i64>> = REDIS.sq(|q| q.mget(&keys)).await?;
+    Ok(counts.into_iter().flatten().sum())
 }
 
 /// register a http error code returned from telegra, lazy-initializing a prometheus counter
@@ -18,3 +103,12 @@ pub fn count_error_code(err: i64) {
     });
     counter.value().inc();
 }
+
+/// register a string lang_fmt! had to show in a fallback language, so translators can see
+/// which strings are shown untranslated the most
+pub fn count_string_fallback(lang: &str, key: &str) {
+    log::debug!("string {} missing in {}, falling back", key, lang);
+    STRING_FALLBACKS_COUNTER
+        .with_label_values(&[lang, key])
+        .inc();
+}
@@ -1,9 +1,66 @@
-//! Migration helpers for handling migrations from modules
+//! Migration helpers for handling migrations from modules, and for running migrations from
+//! the command line instead of starting the bot
 
 use async_trait::async_trait;
+use once_cell::sync::OnceCell;
+use sea_orm::DatabaseConnection;
 use sea_orm_migration::manager::SchemaManager;
 use sea_orm_migration::prelude::*;
-use sea_orm_migration::DbErr;
+use sea_orm_migration::{DbErr, MigrationStatus};
+
+/// Returns every migration the bot needs, in the order they should be applied
+pub type MigrationList = fn() -> Vec<Box<dyn MigrationTrait>>;
+
+static MIGRATIONS: OnceCell<MigrationList> = OnceCell::new();
+
+/// What to do with migrations when the bot is started with one of the migration flags
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationCommand {
+    /// apply every pending migration
+    Up,
+    /// roll back this many applied migrations
+    Down(u32),
+    /// only list migrations
+    Status,
+}
+
+/// Migrator for the command line. Uses the migration list set by the bot, or only the
+/// migrations from each module's get_migrations if none was set
+struct BotMigrator;
+
+impl MigratorTrait for BotMigrator {
+    fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+        MIGRATIONS
+            .get()
+            .map(|migrations| migrations())
+            .unwrap_or_else(crate::modules::get_migrations)
+    }
+}
+
+/// Sets the full list of migrations used by the migration flags
+pub(crate) fn set_migrations(migrations: MigrationList) {
+    let _ = MIGRATIONS.set(migrations);
+}
+
+/// Runs a migration command and prints every migration with whether it is applied
+pub async fn run_migration_command(
+    db: &DatabaseConnection,
+    command: MigrationCommand,
+) -> Result<(), DbErr> {
+    match command {
+        MigrationCommand::Up => BotMigrator::up(db, None).await?,
+        MigrationCommand::Down(steps) => BotMigrator::down(db, Some(steps)).await?,
+        MigrationCommand::Status => (),
+    }
+    for migration in BotMigrator::get_migration_with_status(db).await? {
+        let status = match migration.status() {
+            MigrationStatus::Applied => "applied",
+            MigrationStatus::Pending => "pending",
+        };
+        println!("{} {}", status, migration.name());
+    }
+    Ok(())
+}
 
 /// Shortcut to drop table if exists
 pub async fn remove_table<'a, T>(manager: &SchemaManager<'a>, table: T) -> Result<(), DbErr>
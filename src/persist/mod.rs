@@ -1,5 +1,8 @@
 pub mod admin;
 pub mod core;
+pub mod db;
+pub mod keys;
+pub mod local;
 pub mod metrics;
 pub mod migrate;
 pub mod redis;
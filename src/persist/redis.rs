@@ -10,6 +10,7 @@
 //! which makes serializing keys with msgpack hard. This crate contains a workaround for this that
 
 use crate::{
+    persist::keys::{self, ScopedKey},
     statics::CONFIG,
     util::{
         callback::{CacheCallback, CacheMissCallback},
@@ -188,6 +189,70 @@ where
     }
 }
 
+/// Generation tag for cached queries about one kind of entity in one chat. Cached keys
+/// include the generation they were cached at, so invalidating the scope makes every
+/// cached query in it miss without writers having to know which keys exist. Keys from old
+/// generations are never read again and expire on their own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheScope {
+    chat: i64,
+    entity: &'static str,
+}
+
+impl CacheScope {
+    pub fn new(chat: i64, entity: &'static str) -> Self {
+        Self { chat, entity }
+    }
+
+    #[inline(always)]
+    fn generation_key(&self) -> ScopedKey {
+        keys::scope(self.chat).key_for(keys::GENERATION, self.entity)
+    }
+
+    /// Gets the current generation, 0 if the scope was never invalidated
+    pub async fn generation(&self) -> Result<u64> {
+        let key = self.generation_key();
+        let generation: Option<u64> = REDIS.sq(|q| q.get(&key)).await?;
+        Ok(generation.unwrap_or(0))
+    }
+
+    /// Gets the key to cache a query under in this scope's current generation
+    pub async fn key<K: AsRef<str>>(&self, key: K) -> Result<String> {
+        Ok(format!("{}@{}", key.as_ref(), self.generation().await?))
+    }
+
+    /// Reads values cached under several keys in this scope's current generation. Values
+    /// are None for keys that aren't cached. Every key is sent to redis by name so this
+    /// also works against a cluster, where keys built inside a script can't be routed
+    pub async fn get_all<K: AsRef<str>>(&self, keys: &[K]) -> Result<Vec<Option<RedisStr>>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let generation = self.generation_key();
+        let keys = keys.iter().map(|k| k.as_ref()).collect::<Vec<&str>>();
+        REDIS
+            .query(|mut q| async move {
+                let generation: Option<u64> = q.get(&generation).await?;
+                let generation = generation.unwrap_or(0);
+                let mut pipe = redis::pipe();
+                for key in keys {
+                    pipe.get(format!("{}@{}", key, generation));
+                }
+                let res: Vec<Option<RedisStr>> = pipe.query_async(q.deref_mut()).await?;
+                Ok(res)
+            })
+            .await
+    }
+
+    /// Invalidates every cached query in this scope. Call this after writing to the
+    /// database so the next read sees the change
+    pub async fn invalidate(&self) -> Result<()> {
+        let key = self.generation_key();
+        let _: u64 = REDIS.sq(|q| q.incr(&key, 1)).await?;
+        Ok(())
+    }
+}
+
 /// Maps redis errors to types we support
 pub fn error_mapper(_: RedisError) -> BotError {
     BotError::conversation_err("some redis error")
@@ -393,7 +458,27 @@ where
             .collect()
     }
 
+    /// Sets a key only if it doesn't exist yet, expiring after `seconds`. Sent as a single
+    /// SET NX EX so a key that already exists keeps its expiry. Returns true if the key was set
+    pub async fn set_nx_ex<K>(&self, key: K, seconds: i64) -> Result<bool>
+    where
+        K: ToRedisArgs,
+    {
+        let (set,): (Option<String>,) = self
+            .pipe(|p| {
+                p.cmd("SET")
+                    .arg(key)
+                    .arg(true)
+                    .arg("NX")
+                    .arg("EX")
+                    .arg(seconds)
+            })
+            .await?;
+        Ok(set.is_some())
+    }
+
     /// construct and run a redis pipeline using the provided closure
+    #[tracing::instrument(name = "redis", level = "trace", skip_all)]
     pub async fn pipe<T, R>(&self, func: T) -> Result<R>
     where
         for<'a> T: FnOnce(&'a mut Pipeline) -> &'a mut Pipeline,
@@ -408,6 +493,7 @@ where
 
     /// construct and run a redis pipeline using the provided closure
     /// any Err type returned will abort without running the query
+    #[tracing::instrument(name = "redis", level = "trace", skip_all)]
     pub async fn try_pipe<T, R>(&self, func: T) -> Result<R>
     where
         for<'a> T: FnOnce(&'a mut Pipeline) -> Result<&'a mut Pipeline>,
@@ -421,6 +507,7 @@ where
     }
 
     /// Run a single redis query
+    #[tracing::instrument(name = "redis", level = "trace", skip_all)]
     pub async fn sq<'a, T, R>(&'a self, func: T) -> Result<R>
     where
         T: for<'b> FnOnce(&'b mut PooledConnection<'a, C>) -> RedisFuture<'b, R> + Send,
@@ -431,6 +518,7 @@ where
 
     /// Run one or more redis queries using the connection provided to the
     /// closure
+    #[tracing::instrument(name = "redis", level = "trace", skip_all)]
     pub async fn query<'a, T, R, Fut>(&'a self, func: T) -> Result<R>
     where
         T: FnOnce(PooledConnection<'a, C>) -> Fut + Send,
@@ -536,7 +624,7 @@ where
     async fn cache<K: AsRef<str> + Send>(self, key: K) -> Result<V> {
         self.cache_duration(
             key,
-            Duration::try_seconds(CONFIG.timing.cache_timeout).unwrap(),
+            Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
         )
         .await
     }
@@ -575,7 +663,7 @@ where
         self.join_duration(
             key,
             join,
-            Duration::try_seconds(CONFIG.timing.cache_timeout).unwrap(),
+            Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
         )
         .await
     }
@@ -611,7 +699,7 @@ where
         self.join_single_duration(
             key,
             join,
-            Duration::try_seconds(CONFIG.timing.cache_timeout).unwrap(),
+            Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
         )
         .await
     }
@@ -4,8 +4,6 @@
 //! this module depends on the `static` module for access to the database, redis,
 //! and telegram client.
 
-use std::collections::VecDeque;
-
 use crate::{
     persist::{
         admin::{
@@ -13,14 +11,19 @@ use crate::{
             approvals, warns,
         },
         core::{dialogs, users},
+        keys::{self, ScopedKey},
         redis::{
-            default_cache_query, CachedQuery, CachedQueryTrait, RedisCache, RedisStr, ToRedisStr,
+            default_cache_query, CacheScope, CachedQuery, CachedQueryTrait, RedisCache, RedisStr,
+            ToRedisStr,
         },
     },
     statics::{CONFIG, DB, ME, REDIS, TG},
     util::{
+        duration::parse_duration,
         error::{BotError, Fail, Result, SpeakErr},
-        string::{get_chat_lang, AlignCharBoundry, Speak},
+        error_codes::{CHAT_PERMISSIONS, INVALID_ARGUMENT},
+        string::{get_chat_lang, Speak},
+        time::ChatTime,
     },
 };
 
@@ -33,7 +36,6 @@ use bytes::Bytes;
 use chrono::{DateTime, Duration, Utc};
 use futures::Future;
 
-use lazy_static::lazy_static;
 use macros::{entity_fmt, lang_fmt};
 use redis::AsyncCommands;
 use reqwest::Response;
@@ -42,49 +44,51 @@ use sea_orm::{
     IntoActiveModel, ModelTrait, PaginatorTrait, QueryFilter,
 };
 
-use uuid::Uuid;
-
 use super::{
-    button::OnPush,
-    command::{ArgSlice, Context, Entities, EntityArg, PopSlice},
-    dialog::{dialog_or_default, get_dialog_key},
+    bots,
+    button::{callback_data, CallbackReply, OnPush},
+    command::{ArgSlice, Context},
+    dialog::{dialog_or_default, dialog_scope},
     markdown::MarkupType,
+    networks::{network_scope, shared_chats, Share},
     permissions::{GetCachedAdmins, IsAdmin, IsGroupAdmin},
-    user::{get_user_username, GetUser, Username},
+    scheduler::{delete_message_later, schedule_job, Job, JobKind},
+    user::{resolve_user_target, GetUser, Username},
 };
 
-lazy_static! {
-    static ref VECDEQUE: Entities<'static> = VecDeque::new();
-}
-
-#[inline(always)]
-fn get_chat_key(chat: i64) -> String {
-    format!("gcch:{}", chat)
-}
-
 #[async_trait]
 pub trait GetChat {
     async fn get_chat_cached(&self) -> Result<ChatFullInfo>;
+    async fn get_member_count_cached(&self) -> Result<i64>;
     async fn refresh_chat(&self) -> Result<()>;
 }
 
 #[async_trait]
 impl GetChat for i64 {
     async fn get_chat_cached(&self) -> Result<ChatFullInfo> {
-        let key = get_chat_key(*self);
-        REDIS
-            .query(|mut q| async move {
-                let chat: Option<RedisStr> = q.get(&key).await?;
-                if let Some(chat) = chat {
-                    Ok(chat.get()?)
-                } else {
-                    let c = TG.client.get_chat(*self).await?;
-                    q.set(&key, c.to_redis()?).await?;
-                    q.expire(&key, 15).await?;
-                    Ok(c)
-                }
-            })
-            .await
+        let key = keys::scope(*self).key(keys::CHAT_INFO);
+        let chat: Option<RedisStr> = REDIS.sq(|q| q.get(&key)).await?;
+        if let Some(chat) = chat {
+            Ok(chat.get()?)
+        } else {
+            let c = TG.client.get_chat(*self).await?;
+            REDIS
+                .try_pipe(|q| Ok(key.expire(q.set(&key, c.to_redis()?))))
+                .await?;
+            Ok(c)
+        }
+    }
+
+    async fn get_member_count_cached(&self) -> Result<i64> {
+        let key = keys::scope(*self).key(keys::MEMBER_COUNT);
+        let count: Option<i64> = REDIS.sq(|q| q.get(&key)).await?;
+        if let Some(count) = count {
+            Ok(count)
+        } else {
+            let count = TG.client.build_get_chat_member_count(*self).build().await?;
+            REDIS.pipe(|q| key.expire(q.set(&key, count))).await?;
+            Ok(count)
+        }
     }
 
     async fn refresh_chat(&self) -> Result<()> {
@@ -177,6 +181,10 @@ impl UpdateHelpers for UpdateExt {
     async fn should_moderate(&self) -> Option<&'_ Message> {
         match self {
             UpdateExt::Message(ref message) | UpdateExt::EditedMessage(ref message) => {
+                // posts from the linked channel copied into its discussion group
+                if message.get_is_automatic_forward().unwrap_or(false) {
+                    return None;
+                }
                 if message.is_group_admin().await.unwrap_or(false) {
                     return None;
                 }
@@ -220,7 +228,8 @@ impl UpdateHelpers for UpdateExt {
 /// Meant to be used as an extension trait
 #[async_trait]
 pub trait DeleteAfterTime {
-    /// Delete the object after the specified duration
+    /// Delete the object after the specified duration. The deletion is queued as a job so
+    /// it survives restarts
     fn delete_after_time(&self, duration: Duration);
     async fn delete(&self) -> Result<()>;
 }
@@ -228,21 +237,13 @@ pub trait DeleteAfterTime {
 #[async_trait]
 impl DeleteAfterTime for Message {
     fn delete_after_time(&self, duration: Duration) {
-        let chat_id = self.get_chat().get_id();
-        let message_id = self.get_message_id();
-
-        tokio::spawn(async move {
-            tokio::time::sleep(duration.to_std()?).await;
-            if let Err(err) = TG
-                .client
-                .build_delete_message(chat_id, message_id)
-                .build()
-                .await
-            {
-                BotError::from(err).record_stats();
-            }
+        let chat = self.get_chat().get_id();
+        let message = self.get_message_id();
 
-            Ok::<(), BotError>(())
+        bots::spawn(async move {
+            if let Err(err) = delete_message_later(chat, message, duration).await {
+                err.record_stats();
+            }
         });
     }
 
@@ -303,13 +304,33 @@ pub fn is_dm_info(chat: &ChatFullInfo) -> bool {
 }
 
 /// Gets the redis key string for caching admin actins
-fn get_action_key(user: i64, chat: i64) -> String {
-    format!("act:{}:{}", user, chat)
+fn get_action_key(user: i64, chat: i64) -> ScopedKey {
+    keys::scope(chat).key_for(keys::ACTIONS, user)
 }
 
 /// Gets the redis key string for caching warns
-fn get_warns_key(user: i64, chat: i64) -> String {
-    format!("warns:{}:{}", user, chat)
+fn get_warns_key(user: i64, chat: i64) -> ScopedKey {
+    keys::scope(chat).key_for(keys::WARNS, user)
+}
+
+/// Cache scope for every user's stored action in a chat
+#[inline(always)]
+fn actions_scope(chat: i64) -> CacheScope {
+    CacheScope::new(chat, "actions")
+}
+
+/// Cache scope for every user's warns in a chat
+#[inline(always)]
+fn warns_scope(chat: i64) -> CacheScope {
+    CacheScope::new(chat, "warns")
+}
+
+/// Drops every cached warn and action in a chat, for writes that change more than one user
+/// or the settings they were computed with
+pub async fn invalidate_warn_caches(chat: i64) -> Result<()> {
+    warns_scope(chat).invalidate().await?;
+    actions_scope(chat).invalidate().await?;
+    Ok(())
 }
 
 /// Kicks a user from the specified chat. This is implemented
@@ -341,66 +362,93 @@ pub async fn kick_message(message: &Message) -> Result<()> {
     Ok(())
 }
 
-/// Parse a std::chrono::Duration from a human readable string (5m, 4d, etc)
-pub fn parse_duration_str(arg: &str, chat: i64, reply: i64) -> Result<Option<Duration>> {
-    let end = arg.align_char_boundry(arg.len() - 1);
-    let head = &arg[0..end];
-    let tail = &arg[end..];
-    log::info!("head {} tail {}", head, tail);
-    let head = match str::parse::<i64>(head) {
-        Err(_) => return Err(BotError::speak("Enter a number", chat, Some(reply))),
-        Ok(res) => res,
-    };
-    let res = match tail {
-        "m" => Duration::try_minutes(head),
-        "h" => Duration::try_hours(head),
-        "d" => Duration::try_days(head),
-        _ => return Err(BotError::speak("Invalid time spec", chat, Some(reply))),
+/// Telegram treats restrictions shorter than this many seconds as permanent
+const MIN_RESTRICT_SECONDS: i64 = 30;
+
+/// Telegram treats bans longer than this many seconds as permanent
+const MAX_BAN_SECONDS: i64 = 366 * 24 * 60 * 60;
+
+/// Raises the end of a restriction to at least 30 seconds from now, since telegram treats
+/// shorter restrictions as permanent
+fn restrict_until(until: DateTime<Utc>) -> DateTime<Utc> {
+    until.max(Utc::now() + Duration::try_seconds(MIN_RESTRICT_SECONDS).unwrap())
+}
+
+/// Bans a user, lifting the ban at `until` if set. Telegram lifts bans of up to a year by
+/// itself, longer bans are made permanent and lifted by a job
+pub async fn ban_until(chat: i64, user: i64, until: Option<DateTime<Utc>>) -> Result<()> {
+    let builder = TG.client().build_ban_chat_member(chat, user);
+    match until.map(restrict_until) {
+        Some(until) if (until - Utc::now()).num_seconds() <= MAX_BAN_SECONDS => {
+            builder.until_date(until.timestamp()).build().await?;
+        }
+        Some(until) => {
+            builder.build().await?;
+            schedule_job(&Job::new(chat, until, JobKind::Unban { user })).await?;
+        }
+        None => {
+            builder.build().await?;
+        }
     }
-    .ok_or_else(|| BotError::speak("time out of range", chat, Some(reply)))?;
+    Ok(())
+}
 
-    let res = if res.num_seconds() < 30 {
-        Duration::try_seconds(30).unwrap()
-    } else {
-        res
-    };
+/// Bans a channel posting in a chat, queueing a job to lift the ban at `until` since
+/// telegram doesn't support temporary channel bans
+pub async fn ban_sender_chat_until(
+    chat: i64,
+    sender: i64,
+    until: Option<DateTime<Utc>>,
+) -> Result<()> {
+    TG.client()
+        .build_ban_chat_sender_chat(chat, sender)
+        .build()
+        .await?;
+    if let Some(until) = until {
+        schedule_job(&Job::new(chat, until, JobKind::UnbanSenderChat { sender })).await?;
+    }
+    Ok(())
+}
 
-    Ok(Some(res))
+/// Lifts a ban that ran out, clearing any pending ban so it isn't applied again when the
+/// user is next seen
+pub async fn expire_ban(chat: i64, user: i64) -> Result<()> {
+    TG.client()
+        .build_unban_chat_member(chat, user)
+        .only_if_banned(true)
+        .build()
+        .await?;
+    let key = actions_scope(chat).key(get_action_key(user, chat)).await?;
+    actions::Entity::delete_many()
+        .filter(actions::Column::ChatId.eq(chat))
+        .filter(actions::Column::UserId.eq(user))
+        .filter(actions::Column::IsBanned.eq(true))
+        .exec(*DB)
+        .await?;
+    REDIS.sq(|q| q.del(&key)).await?;
+    Ok(())
 }
+
 /// Sets the duration after which warns expire for the provided chat
 pub async fn set_warn_time(chat: &Chat, time: Option<i64>) -> Result<()> {
     let chat_id = chat.get_id();
 
     let model = dialogs::ActiveModel {
-        chat_id: Set(chat_id),
-        language: NotSet,
-        chat_type: Set(chat.get_tg_type().to_owned()),
-        warn_limit: NotSet,
-        action_type: NotSet,
         warn_time: Set(time),
-        can_send_messages: NotSet,
-        can_send_audio: NotSet,
-        can_send_video: NotSet,
-        can_send_photo: NotSet,
-        can_send_document: NotSet,
-        can_send_video_note: NotSet,
-        can_send_voice_note: NotSet,
-        can_send_poll: NotSet,
-        can_send_other: NotSet,
-        federation: NotSet,
+        ..dialogs::ActiveModel::for_chat(chat)
     };
 
-    let key = get_dialog_key(chat_id);
-    let model = dialogs::Entity::insert(model)
+    dialogs::Entity::insert(model)
         .on_conflict(
             OnConflict::column(dialogs::Column::ChatId)
                 .update_column(dialogs::Column::WarnTime)
                 .to_owned(),
         )
-        .exec_with_returning(*DB)
+        .exec(*DB)
         .await?;
 
-    model.cache(key).await?;
+    dialog_scope(chat_id).invalidate().await?;
+    invalidate_warn_caches(chat_id).await?;
     Ok(())
 }
 
@@ -409,35 +457,21 @@ pub async fn set_warn_limit(chat: &Chat, limit: i32) -> Result<()> {
     let chat_id = chat.get_id();
 
     let model = dialogs::ActiveModel {
-        chat_id: Set(chat_id),
-        language: NotSet,
-        chat_type: Set(chat.get_tg_type().to_owned()),
         warn_limit: Set(limit),
-        action_type: NotSet,
-        warn_time: NotSet,
-        can_send_messages: NotSet,
-        can_send_audio: NotSet,
-        can_send_video: NotSet,
-        can_send_photo: NotSet,
-        can_send_document: NotSet,
-        can_send_video_note: NotSet,
-        can_send_voice_note: NotSet,
-        can_send_poll: NotSet,
-        can_send_other: NotSet,
-        federation: NotSet,
+        ..dialogs::ActiveModel::for_chat(chat)
     };
 
-    let key = get_dialog_key(chat_id);
-    let model = dialogs::Entity::insert(model)
+    dialogs::Entity::insert(model)
         .on_conflict(
             OnConflict::column(dialogs::Column::ChatId)
                 .update_column(dialogs::Column::WarnLimit)
                 .to_owned(),
         )
-        .exec_with_returning(*DB)
+        .exec(*DB)
         .await?;
 
-    model.cache(key).await?;
+    dialog_scope(chat_id).invalidate().await?;
+    invalidate_warn_caches(chat_id).await?;
     Ok(())
 }
 
@@ -449,39 +483,31 @@ pub async fn set_warn_mode(chat: &Chat, mode: &str) -> Result<()> {
         "mute" => Ok(ActionType::Mute),
         "ban" => Ok(ActionType::Ban),
         "shame" => Ok(ActionType::Shame),
-        _ => chat.fail(format!("Invalid mode {}", mode)),
+        _ => {
+            let lang = get_chat_lang(chat_id).await?;
+            chat.fail_code(
+                INVALID_ARGUMENT,
+                lang_fmt!(lang, "errinvalidwarnmode", mode),
+            )
+        }
     }?;
 
     let model = dialogs::ActiveModel {
-        chat_id: Set(chat_id),
-        language: NotSet,
-        chat_type: Set(chat.get_tg_type().to_owned()),
-        warn_limit: NotSet,
         action_type: Set(mode),
-        warn_time: NotSet,
-        can_send_messages: NotSet,
-        can_send_audio: NotSet,
-        can_send_video: NotSet,
-        can_send_photo: NotSet,
-        can_send_document: NotSet,
-        can_send_video_note: NotSet,
-        can_send_voice_note: NotSet,
-        can_send_poll: NotSet,
-        can_send_other: NotSet,
-        federation: NotSet,
+        ..dialogs::ActiveModel::for_chat(chat)
     };
 
-    let key = get_dialog_key(chat_id);
-    let model = dialogs::Entity::insert(model)
+    dialogs::Entity::insert(model)
         .on_conflict(
             OnConflict::column(dialogs::Column::ChatId)
                 .update_column(dialogs::Column::ActionType)
                 .to_owned(),
         )
-        .exec_with_returning(*DB)
+        .exec(*DB)
         .await?;
 
-    model.cache(key).await?;
+    dialog_scope(chat_id).invalidate().await?;
+    invalidate_warn_caches(chat_id).await?;
     Ok(())
 }
 
@@ -490,7 +516,7 @@ pub async fn set_warn_mode(chat: &Chat, mode: &str) -> Result<()> {
 pub async fn get_action(chat: &Chat, user: &User) -> Result<Option<actions::Model>> {
     let chat = chat.get_id();
     let user = user.get_id();
-    let key = get_action_key(user, chat);
+    let key = actions_scope(chat).key(get_action_key(user, chat)).await?;
     let res = default_cache_query(
         move |_, _| async move {
             let res = actions::Entity::find_by_id((user, chat)).one(*DB).await?;
@@ -503,18 +529,16 @@ pub async fn get_action(chat: &Chat, user: &User) -> Result<Option<actions::Mode
     Ok(res)
 }
 
-pub async fn warn_shame(message: &Message, _user: i64, _count: i32) -> Result<()> {
-    message.reply("shaming not implemented").await?;
-
-    Ok(())
-}
-
 /// Gets a list of all warns for the current user in the given chat (from message)
 pub async fn get_warns(chat: &Chat, user_id: i64) -> Result<Vec<warns::Model>> {
     let chat_id = chat.get_id();
-    let key = get_warns_key(user_id, chat_id);
+    let key = warns_scope(chat_id)
+        .key(get_warns_key(user_id, chat_id))
+        .await?;
     let r = CachedQuery::new(
         |_, _| async move {
+            // the cache is dropped on every warn, so this usually runs right after a write
+            // the replica may not have yet
             let count = warns::Entity::find()
                 .filter(
                     warns::Column::UserId
@@ -543,7 +567,7 @@ pub async fn get_warns(chat: &Chat, user_id: i64) -> Result<Vec<warns::Model>> {
                         let ins = RedisStr::new(&v)?;
                         q.sadd(key, ins);
                     }
-                    Ok(q.expire(key, CONFIG.timing.cache_timeout))
+                    Ok(q.expire(key, CONFIG.load().timing.cache_timeout))
                 })
                 .await?;
             Ok(warns)
@@ -551,34 +575,28 @@ pub async fn get_warns(chat: &Chat, user_id: i64) -> Result<Vec<warns::Model>> {
     )
     .query(&key, &())
     .await?;
-    let mut res = Vec::<warns::Model>::new();
-    for warn in r {
-        if let Some(expire) = &warn.expires {
-            if Utc::now().timestamp() > expire.timestamp() {
-                log::info!("warn expired!");
-                let args = RedisStr::new(&warn)?;
-                REDIS.sq(|q| q.srem(&key, &args)).await?;
-                warn.delete(*DB).await?;
-            } else {
-                res.push(warn);
-            }
-        } else {
-            res.push(warn);
-        }
-    }
+    // expired warns are deleted and logged by the warn decay task, until then they are hidden
+    let now = Utc::now();
+    let res = r
+        .into_iter()
+        .filter(|warn| warn.expires.map(|expires| expires > now).unwrap_or(true))
+        .collect();
     Ok(res)
 }
 
 /// Gets the number of warns a user has in the given chat (from message)
 pub async fn get_warns_count(message: &Message, user: i64) -> Result<i32> {
     let chat_id = message.get_chat().get_id();
-    let key = get_warns_key(user, message.get_chat().get_id());
+    let key = warns_scope(message.get_chat().get_id())
+        .key(get_warns_key(user, message.get_chat().get_id()))
+        .await?;
     let v: Option<i32> = REDIS.sq(|q| q.scard(&key)).await?;
     if let Some(v) = v {
         Ok(v)
     } else {
         let r = CachedQuery::new(
             |_, _| async move {
+                // read from the primary since warn_user checks the limit with this
                 let count = warns::Entity::find()
                     .filter(
                         warns::Column::UserId
@@ -601,10 +619,16 @@ pub async fn get_warns_count(message: &Message, user: i64) -> Result<i32> {
     }
 }
 
+/// Drops a user's cached warns in a chat so they are loaded from the database again
+pub async fn invalidate_warns(chat: i64, user: i64) -> Result<()> {
+    let key = warns_scope(chat).key(get_warns_key(user, chat)).await?;
+    REDIS.sq(|q| q.del(&key)).await?;
+    Ok(())
+}
+
 /// Removes all warns from a user in a chat
 pub async fn clear_warns(chat: &Chat, user: i64) -> Result<()> {
-    let key = get_warns_key(user, chat.get_id());
-    REDIS.sq(|q| q.del(&key)).await?;
+    invalidate_warns(chat.get_id(), user).await?;
     warns::Entity::delete_many()
         .filter(
             warns::Column::ChatId
@@ -616,9 +640,21 @@ pub async fn clear_warns(chat: &Chat, user: i64) -> Result<()> {
     Ok(())
 }
 
-#[inline(always)]
-fn get_approval_key(chat: &Chat, user: i64) -> String {
-    format!("ap:{}:{}", chat.get_id(), user)
+/// Approvals can come from other chats in the network, so their keys live in the network's
+/// cache scope
+async fn get_approval_key(chat: i64, user: i64) -> Result<String> {
+    network_scope(chat)
+        .key(keys::scope(chat).key_for(keys::APPROVALS, user))
+        .await
+}
+
+/// Drops cached approvals of a user in chats sharing approvals with this one
+async fn invalidate_shared_approvals(chat: i64, user: i64) -> Result<()> {
+    for other in shared_chats(chat, Share::Approvals).await? {
+        let key = get_approval_key(other, user).await?;
+        REDIS.sq(|q| q.del(&key)).await?;
+    }
+    Ok(())
 }
 
 pub async fn insert_user(user: &User) -> Result<users::Model> {
@@ -652,7 +688,10 @@ pub async fn approve(chat: &Chat, user: &User) -> Result<()> {
             chat: chat.get_id(),
             user: user.get_id(),
         }
-        .join_single(get_approval_key(chat, user.get_id()), Some(testmodel))
+        .join_single(
+            get_approval_key(chat.get_id(), user.get_id()).await?,
+            Some(testmodel),
+        )
         .await?
         .0,
     )
@@ -664,6 +703,7 @@ pub async fn approve(chat: &Chat, user: &User) -> Result<()> {
     .exec(*DB)
     .await?;
 
+    invalidate_shared_approvals(chat.get_id(), user.get_id()).await?;
     Ok(())
 }
 
@@ -676,17 +716,18 @@ pub async fn unapprove(chat: &Chat, user: i64) -> Result<()> {
     .exec(*DB)
     .await?;
 
-    let key = get_approval_key(chat, user);
+    let key = get_approval_key(chat.get_id(), user).await?;
 
     REDIS.sq(|q| q.del(&key)).await?;
+    invalidate_shared_approvals(chat.get_id(), user).await?;
     Ok(())
 }
 
 /// Checks if a user should be ignored when applying moderation. All modules should honor
-/// this when moderating
+/// this when moderating. Approvals in chats sharing them through a network count too
 pub async fn is_approved(chat: &Chat, user_id: i64) -> Result<bool> {
     let chat_id = chat.get_id();
-    let key = get_approval_key(chat, user_id);
+    let key = get_approval_key(chat_id, user_id).await?;
     let res = default_cache_query(
         |_, _| async move {
             let res = approvals::Entity::find_by_id((chat_id, user_id))
@@ -694,10 +735,22 @@ pub async fn is_approved(chat: &Chat, user_id: i64) -> Result<bool> {
                 .all(*DB)
                 .await?
                 .pop();
+            if let Some((res, _)) = res {
+                return Ok(Some(res));
+            }
 
-            Ok(res.map(|(res, _)| res))
+            let shared = shared_chats(chat_id, Share::Approvals).await?;
+            if shared.is_empty() {
+                return Ok(None);
+            }
+            let res = approvals::Entity::find()
+                .filter(approvals::Column::Chat.is_in(shared))
+                .filter(approvals::Column::User.eq(user_id))
+                .one(*DB)
+                .await?;
+            Ok(res)
         },
-        Duration::try_seconds(CONFIG.timing.cache_timeout).unwrap(),
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
     )
     .query(&key, &())
     .await?
@@ -776,9 +829,10 @@ fn merge_permissions(
 pub async fn change_chat_permissions(chat: &Chat, permissions: &ChatPermissions) -> Result<()> {
     let current_perms = TG.client.get_chat(chat.get_id()).await?;
     let mut new = ChatPermissionsBuilder::new();
-    let old = current_perms
-        .get_permissions()
-        .ok_or_else(|| chat.fail_err("failed to get chat permissions"))?;
+    let Some(old) = current_perms.get_permissions() else {
+        let lang = get_chat_lang(chat.get_id()).await?;
+        return chat.fail_code(CHAT_PERMISSIONS, lang_fmt!(lang, "errchatpermissions"));
+    };
     new = merge_permissions(old, new);
     new = merge_permissions(permissions, new);
     let new = new.build();
@@ -793,24 +847,11 @@ pub async fn change_chat_permissions(chat: &Chat, permissions: &ChatPermissions)
 /// Bans the sender of a message, transparently handling anonymous channels.
 /// if a duration is provided, the ban will be lifted after the duration
 pub async fn ban_message(message: &Message, duration: Option<Duration>) -> Result<()> {
+    let until = duration.and_then(|v| Utc::now().checked_add_signed(v));
     if let Some(senderchat) = message.get_sender_chat() {
-        TG.client()
-            .build_ban_chat_sender_chat(message.get_chat().get_id(), senderchat.get_id())
-            .build()
-            .await?;
+        ban_sender_chat_until(message.get_chat().get_id(), senderchat.get_id(), until).await?;
     } else if let Some(user) = message.get_from() {
-        if let Some(duration) = duration.and_then(|v| Utc::now().checked_add_signed(v)) {
-            TG.client()
-                .build_ban_chat_member(message.get_chat().get_id(), user.get_id())
-                .until_date(duration.timestamp())
-                .build()
-                .await?;
-        } else {
-            TG.client()
-                .build_ban_chat_member(message.get_chat().get_id(), user.get_id())
-                .build()
-                .await?;
-        }
+        ban_until(message.get_chat().get_id(), user.get_id(), until).await?;
     }
     Ok(())
 }
@@ -865,41 +906,18 @@ impl Context {
         Ok(())
     }
 
-    /// Parse an std::chrono::Duration from a argument list
-    pub fn parse_duration(&self, args: &Option<ArgSlice<'_>>) -> Result<Option<Duration>> {
-        if let Some(args) = args {
-            if let Some(thing) = args.args.first() {
-                let end = thing
-                    .get_text()
-                    .align_char_boundry(thing.get_text().len() - 1);
-                let head = &thing.get_text()[0..end];
-                let tail = &thing.get_text()[end..];
-                log::info!("head {} tail {}", head, tail);
-                let head = match str::parse::<i64>(head) {
-                    Err(_) => return self.fail("Enter a number"),
-                    Ok(res) => res,
-                };
-                let res = match tail {
-                    "m" => Duration::try_minutes(head),
-                    "h" => Duration::try_hours(head),
-                    "d" => Duration::try_days(head),
-                    _ => return self.fail("Invalid time spec"),
-                }
-                .ok_or_else(|| self.fail_err(lang_fmt!(self, "dateoutofrange", head)))?;
-
-                let res = if res.num_seconds() < 30 {
-                    Duration::try_seconds(30).unwrap()
-                } else {
-                    res
-                };
+    /// Parse a duration like 5m or 1d2h30m from a single argument, failing with a
+    /// localized error
+    pub fn parse_duration_arg(&self, arg: &str) -> Result<Duration> {
+        parse_duration(arg).map_err(|err| self.fail_err(err.describe(self.lang())))
+    }
 
-                Ok(Some(res))
-            } else {
-                Ok(None)
-            }
-        } else {
-            Ok(None)
-        }
+    /// Parse an std::chrono::Duration from the first argument in an argument list
+    pub fn parse_duration(&self, args: &Option<ArgSlice<'_>>) -> Result<Option<Duration>> {
+        args.as_ref()
+            .and_then(|args| args.args.first())
+            .map(|arg| self.parse_duration_arg(arg.get_text()))
+            .transpose()
     }
 
     /// If the current chat is a group or supergroup (i.e. not a dm)
@@ -951,7 +969,12 @@ impl Context {
 
         let mention = user.mention().await?;
         message
-            .reply_fmt(entity_fmt!(self, "warnmute", count.to_string(), mention))
+            .reply_fmt(entity_fmt!(
+                self,
+                "warnmute",
+                self.lang().format_number(count),
+                mention
+            ))
             .await?;
 
         Ok(())
@@ -1108,7 +1131,10 @@ impl Context {
         } else if user.is_admin(chat).await? {
             self.fail(lang_fmt!(self.try_get()?.lang, "muteadmin"))
         } else {
-            if let Some(time) = time.and_then(|t| Utc::now().checked_add_signed(t)) {
+            let time = time
+                .and_then(|t| Utc::now().checked_add_signed(t))
+                .map(restrict_until);
+            if let Some(time) = time {
                 TG.client()
                     .build_restrict_chat_member(chat.get_id(), user, permissions)
                     .until_date(time.timestamp())
@@ -1120,7 +1146,6 @@ impl Context {
                     .build()
                     .await?;
             }
-            let time = time.and_then(|t| Utc::now().checked_add_signed(t));
             update_actions_permissions(user, chat, permissions, time).await?;
             Ok(())
         }
@@ -1212,86 +1237,14 @@ impl Context {
         Fut: Future<Output = Result<()>>,
     {
         let message = self.message()?;
-        let args = self.try_get()?.command.as_ref().map(|a| &a.args);
-        log::info!("action_message {:?}", args);
-        let entities = self
-            .try_get()?
-            .command
-            .as_ref()
-            .map(|e| &e.entities)
-            .unwrap_or_else(|| &VECDEQUE);
-
-        if let (Some(user), Some(message)) = (
-            message.get_reply_to_message().and_then(|v| v.get_from()),
-            message.get_reply_to_message(),
-        ) {
-            action(
-                self,
-                Some(user.get_id()),
-                args.map(|a| a.as_slice()),
-                ActionMessage::Reply(message),
-            )
-            .await?;
-            Ok(Some(user.get_id()))
-        } else {
-            match entities.front() {
-                Some(EntityArg::Mention(name)) => {
-                    let user = get_user_username(name)
-                        .await?
-                        .ok_or_else(|| BotError::UserNotFound)?;
-                    action(
-                        self,
-                        Some(user.get_id()),
-                        args.and_then(|a| a.pop_slice_tail()),
-                        ActionMessage::Me(self.message()?),
-                    )
-                    .await?;
-                    Ok(Some(user.get_id()))
-                }
-                Some(EntityArg::TextMention(user)) => {
-                    action(
-                        self,
-                        Some(user.get_id()),
-                        args.and_then(|a| a.pop_slice_tail()),
-                        ActionMessage::Me(self.message()?),
-                    )
-                    .await?;
-                    Ok(Some(user.get_id()))
-                }
-                _ => match args.and_then(|v| v.args.first().map(|v| str::parse(v.get_text()))) {
-                    Some(Ok(v)) => {
-                        action(
-                            self,
-                            Some(v),
-                            args.and_then(|a| a.pop_slice_tail()),
-                            ActionMessage::Me(self.message()?),
-                        )
-                        .await?;
-                        Ok(Some(v))
-                    }
-                    Some(Err(_)) => {
-                        action(
-                            self,
-                            None,
-                            args.and_then(|a| a.pop_slice_tail()),
-                            ActionMessage::Me(self.message()?),
-                        )
-                        .await?;
-                        Ok(None)
-                    }
-                    None => {
-                        action(
-                            self,
-                            None,
-                            args.and_then(|a| a.pop_slice_tail()),
-                            ActionMessage::Me(self.message()?),
-                        )
-                        .await?;
-                        Ok(None)
-                    }
-                },
-            }
-        }
+        let (target, args) = resolve_user_target(self).await?;
+        let id = target.map(|t| t.get_id());
+        let message = match message.get_reply_to_message() {
+            Some(reply) if reply.get_from().is_some() => ActionMessage::Reply(reply),
+            _ => ActionMessage::Me(message),
+        };
+        action(self, id, args, message).await?;
+        Ok(id)
     }
 
     /// Issue a warning to a user, speaking in the chat as required. If the warn count
@@ -1319,7 +1272,7 @@ impl Context {
             match dialog.action_type {
                 actions::ActionType::Mute => self.warn_mute(user, count, duration).await,
                 actions::ActionType::Ban => self.warn_ban(user, count, duration).await,
-                actions::ActionType::Shame => warn_shame(message, user, count).await,
+                actions::ActionType::Shame => self.warn_shame(user, count).await,
                 actions::ActionType::Warn => Ok(()),
                 actions::ActionType::Delete => Ok(()),
             }?;
@@ -1330,16 +1283,16 @@ impl Context {
                     self,
                     "warnreason",
                     name,
-                    count.to_string(),
-                    dialog.warn_limit.to_string()
+                    lang.format_number(count),
+                    lang.format_number(dialog.warn_limit)
                 )
             } else {
                 entity_fmt!(
                     self,
                     "warn",
                     name,
-                    count.to_string(),
-                    dialog.warn_limit.to_string()
+                    lang.format_number(count),
+                    lang.format_number(dialog.warn_limit)
                 )
             };
             text.builder.filling = true;
@@ -1354,14 +1307,17 @@ impl Context {
             let button_text = lang_fmt!(lang, "removewarn");
 
             let button = InlineKeyboardButtonBuilder::new(button_text)
-                .set_callback_data(Uuid::new_v4().to_string())
+                .set_callback_data(callback_data(None))
                 .build();
+            let expires = model.expires;
             let model = model.id;
             button.on_push_multi(move |cb| async move {
                 if let Some(MaybeInaccessibleMessage::Message(message)) = cb.get_message() {
                     let chat = message.get_chat();
                     if cb.get_from().is_admin(chat).await? {
-                        let key = get_warns_key(user, chat.get_id());
+                        let key = warns_scope(chat.get_id())
+                            .key(get_warns_key(user, chat.get_id()))
+                            .await?;
                         if let Some(res) = warns::Entity::find_by_id(model).one(*DB).await? {
                             let st = RedisStr::new(&res)?;
                             res.delete(*DB).await?;
@@ -1379,29 +1335,25 @@ impl Context {
                             .chat_id(chat.get_id())
                             .build()
                             .await?;
-                        TG.client
-                            .build_answer_callback_query(cb.get_id())
-                            .build()
-                            .await?;
 
-                        Ok(true)
+                        Ok((true, CallbackReply::default()))
                     } else {
-                        TG.client
-                            .build_answer_callback_query(cb.get_id())
-                            .show_alert(true)
-                            .text("User is not admin")
-                            .build()
-                            .await?;
-                        Ok(false)
+                        Ok((false, CallbackReply::alert("User is not admin")))
                     }
                 } else {
-                    Ok(true)
+                    Ok((true, CallbackReply::default()))
                 }
             });
 
             if let Some(reason) = reason {
                 text.builder.text(reason);
             }
+            if let Some(expires) = expires {
+                let expires = ChatTime::get(message.get_chat().get_id())
+                    .await?
+                    .format_lang(&expires, &lang);
+                text.builder.text(lang_fmt!(lang, "warnexpires", expires));
+            }
             text.builder.buttons.button(button);
             message.reply_fmt(text).await?;
         }
@@ -1418,7 +1370,7 @@ impl Context {
             .reply_fmt(entity_fmt!(
                 self,
                 "warnban",
-                count.to_string(),
+                self.lang().format_number(count),
                 user.mention().await?,
             ))
             .await?;
@@ -1430,11 +1382,9 @@ impl Context {
     pub async fn ban(&self, user: i64, duration: Option<Duration>, silent: bool) -> Result<()> {
         let message = self.message()?;
         let lang = get_chat_lang(message.get_chat().get_id()).await?;
+        let until = duration.and_then(|v| Utc::now().checked_add_signed(v));
         if let Some(senderchat) = message.get_sender_chat() {
-            TG.client()
-                .build_ban_chat_sender_chat(message.get_chat().get_id(), senderchat.get_id())
-                .build()
-                .await?;
+            ban_sender_chat_until(message.get_chat().get_id(), senderchat.get_id(), until).await?;
             if !silent {
                 let name = senderchat.name_humanreadable();
                 if let Some(user) = user.get_cached_user().await? {
@@ -1462,18 +1412,7 @@ impl Context {
 
         if silent { err.silent().await } else { err }?;
 
-        if let Some(duration) = duration.and_then(|v| Utc::now().checked_add_signed(v)) {
-            TG.client()
-                .build_ban_chat_member(message.get_chat().get_id(), user)
-                .until_date(duration.timestamp())
-                .build()
-                .await?;
-        } else {
-            TG.client()
-                .build_ban_chat_member(message.get_chat().get_id(), user)
-                .build()
-                .await?;
-        }
+        ban_until(message.get_chat().get_id(), user, until).await?;
 
         let mention = user.mention().await?;
 
@@ -1514,11 +1453,13 @@ pub async fn warn_user(
         .exec_with_returning(*DB)
         .await?;
     let m = RedisStr::new(&model)?;
-    let key = get_warns_key(user, chat_id);
+    let key = warns_scope(chat_id)
+        .key(get_warns_key(user, chat_id))
+        .await?;
     let (_, _, count): ((), (), usize) = REDIS
         .pipe(|p| {
             p.sadd(&key, m)
-                .expire(&key, CONFIG.timing.cache_timeout)
+                .expire(&key, CONFIG.load().timing.cache_timeout)
                 .scard(&key)
         })
         .await?;
@@ -1535,7 +1476,9 @@ pub async fn update_actions_ban(
     banned: bool,
     expires: Option<DateTime<Utc>>,
 ) -> Result<()> {
-    let key = get_action_key(user.get_id(), chat.get_id());
+    let key = actions_scope(chat.get_id())
+        .key(get_action_key(user.get_id(), chat.get_id()))
+        .await?;
 
     let active = actions::ActiveModel {
         user_id: Set(user.get_id()),
@@ -1644,7 +1587,9 @@ pub async fn get_file_text(path: &str) -> Result<String> {
 /// Sets the 'pending' flag on a stored action. Pending actions are applied the next time a user is seen
 /// actions without pending set are ignored
 pub async fn update_actions_pending(chat: &Chat, user: &User, pending: bool) -> Result<()> {
-    let key = get_action_key(user.get_id(), chat.get_id());
+    let key = actions_scope(chat.get_id())
+        .key(get_action_key(user.get_id(), chat.get_id()))
+        .await?;
 
     let active = actions::ActiveModel {
         user_id: Set(user.get_id()),
@@ -1686,7 +1631,9 @@ pub async fn update_actions_permissions(
     permissions: &ChatPermissions,
     expires: Option<DateTime<Utc>>,
 ) -> Result<()> {
-    let key = get_action_key(user, chat.get_id());
+    let key = actions_scope(chat.get_id())
+        .key(get_action_key(user, chat.get_id()))
+        .await?;
 
     let active = actions::ActiveModel {
         user_id: Set(user),
@@ -1753,7 +1700,9 @@ pub async fn update_actions_permissions(
 
 /// Updates the current actions with a raw ORM model
 pub async fn update_actions(actions: actions::Model) -> Result<()> {
-    let key = get_action_key(actions.user_id, actions.chat_id);
+    let key = actions_scope(actions.chat_id)
+        .key(get_action_key(actions.user_id, actions.chat_id))
+        .await?;
 
     actions::Entity::insert(actions.cache(key).await?.into_active_model())
         .on_conflict(
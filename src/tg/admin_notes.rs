@@ -0,0 +1,92 @@
+//! Private notes admins keep about users, like a watchlist of suspected ban evaders. Notes
+//! are written per chat, or for the chat's federation so every chat in it sees them. Notes
+//! are only sent to admins in private, /info just shows admins how many there are
+
+use chrono::Utc;
+use macros::lang_fmt;
+use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::{ColumnTrait, Condition, EntityTrait, QueryFilter, QueryOrder};
+use uuid::Uuid;
+
+use crate::persist::admin::adminnotes;
+use crate::persist::db::ReadReplica;
+use crate::statics::DB;
+use crate::util::error::Result;
+use crate::util::string::Lang;
+use crate::util::time::ChatTime;
+
+use super::federations::is_fedmember;
+use super::user::GetUser;
+
+/// Saves a note about a user, either for a chat or for a federation
+pub async fn add_admin_note(
+    user: i64,
+    chat: Option<i64>,
+    federation: Option<Uuid>,
+    author: i64,
+    text: String,
+) -> Result<adminnotes::Model> {
+    let model = adminnotes::ActiveModel {
+        id: NotSet,
+        user_id: Set(user),
+        chat_id: Set(chat),
+        federation: Set(federation),
+        author: Set(author),
+        text: Set(text),
+        created: Set(Utc::now()),
+    };
+    let model = adminnotes::Entity::insert(model)
+        .exec_with_returning(*DB)
+        .await?;
+    Ok(model)
+}
+
+/// Gets the notes about a user visible in a chat, both the chat's own notes and the notes
+/// of its federation, oldest first
+pub async fn get_admin_notes(chat: i64, user: i64) -> Result<Vec<adminnotes::Model>> {
+    let mut visible = Condition::any().add(adminnotes::Column::ChatId.eq(chat));
+    if let Some(fed) = is_fedmember(chat).await? {
+        visible = visible.add(adminnotes::Column::Federation.eq(fed));
+    }
+    let notes = adminnotes::Entity::find()
+        .filter(adminnotes::Column::UserId.eq(user))
+        .filter(visible)
+        .order_by_asc(adminnotes::Column::Created)
+        .all(DB.read())
+        .await?;
+    Ok(notes)
+}
+
+/// Formats notes one per line with their author and when they were written in the chat's
+/// timezone
+pub async fn format_admin_notes(
+    chat: i64,
+    lang: &Lang,
+    notes: &[adminnotes::Model],
+) -> Result<String> {
+    let time = ChatTime::get(chat).await?;
+    let mut res = String::new();
+    for note in notes {
+        let author = note.author.cached_name().await?;
+        let line = if note.federation.is_some() {
+            lang_fmt!(
+                lang,
+                "adminnotefed",
+                time.format(&note.created),
+                author,
+                note.text
+            )
+        } else {
+            lang_fmt!(
+                lang,
+                "adminnoteline",
+                time.format(&note.created),
+                author,
+                note.text
+            )
+        };
+        res.push('\n');
+        res.push_str(&line);
+    }
+    Ok(res)
+}
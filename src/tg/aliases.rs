@@ -0,0 +1,221 @@
+//! Per-chat command aliases. Admins can give a command another name with /alias, and the
+//! command parser swaps the alias for the command before any module sees it. Permission
+//! checks, clean commands, and everything else keyed on the command name see the real
+//! command, so an alias can't be used to get around them.
+//!
+//! Aliases may point at other aliases as long as that doesn't make a cycle, and can't reuse
+//! the name of an existing command or point at sudo commands
+
+use std::collections::HashMap;
+
+use chrono::Duration;
+use lazy_static::lazy_static;
+use redis::AsyncCommands;
+use regex::Regex;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter};
+
+use crate::persist::core::command_aliases;
+use crate::persist::redis::{default_cache_query, CachedQueryTrait};
+use crate::statics::{DB, REDIS};
+use crate::util::error::Result;
+
+use super::dialog::dialog_scope;
+
+/// Most aliases a chat can have
+pub const MAX_ALIASES: usize = 50;
+
+/// Modules whose commands can't be aliased, since they're for the bot's owner
+pub const UNALIASABLE_MODULES: &[&str] = &["Bot Administration"];
+
+lazy_static! {
+    static ref ALIAS_NAME: Regex = Regex::new(r"^[a-z0-9_]{1,32}$").unwrap();
+}
+
+/// A chat's aliases, from alias to the command or alias it runs
+pub type CommandAliases = HashMap<String, String>;
+
+#[inline(always)]
+fn get_aliases_key(chat: i64) -> String {
+    format!("cals:{}", chat)
+}
+
+/// Gets the aliases defined in a chat
+pub async fn get_command_aliases(chat: i64) -> Result<CommandAliases> {
+    let key = dialog_scope(chat).key(get_aliases_key(chat)).await?;
+    let res = default_cache_query(
+        |_, _| async move {
+            let aliases = command_aliases::Entity::find()
+                .filter(command_aliases::Column::ChatId.eq(chat))
+                .all(*DB)
+                .await?
+                .into_iter()
+                .map(|a| (a.alias, a.command))
+                .collect::<CommandAliases>();
+            Ok(Some(aliases))
+        },
+        Duration::try_hours(12).unwrap(),
+    )
+    .query(&key, &())
+    .await?;
+    Ok(res.unwrap_or_default())
+}
+
+async fn invalidate_aliases(chat: i64) -> Result<()> {
+    let key = dialog_scope(chat).key(get_aliases_key(chat)).await?;
+    REDIS.sq(|q| q.del(&key)).await?;
+    Ok(())
+}
+
+/// Adds or replaces an alias in a chat
+pub async fn set_alias(chat: i64, alias: &str, command: &str) -> Result<()> {
+    command_aliases::Entity::insert(
+        command_aliases::Model {
+            chat_id: chat,
+            alias: alias.to_owned(),
+            command: command.to_owned(),
+        }
+        .into_active_model(),
+    )
+    .on_conflict(
+        OnConflict::columns([
+            command_aliases::Column::ChatId,
+            command_aliases::Column::Alias,
+        ])
+        .update_column(command_aliases::Column::Command)
+        .to_owned(),
+    )
+    .exec(*DB)
+    .await?;
+    invalidate_aliases(chat).await
+}
+
+/// Removes an alias, returning false if the chat didn't have it
+pub async fn remove_alias(chat: i64, alias: &str) -> Result<bool> {
+    let res = command_aliases::Entity::delete_by_id((chat, alias.to_owned()))
+        .exec(*DB)
+        .await?;
+    invalidate_aliases(chat).await?;
+    Ok(res.rows_affected > 0)
+}
+
+/// Follows aliases from `cmd` to the command they run. Names that aren't aliases are
+/// returned as they are. Cycles are refused when aliases are created, but stop after every
+/// alias was followed once just in case
+pub fn resolve_alias<'a>(aliases: &'a CommandAliases, cmd: &'a str) -> &'a str {
+    let mut cmd = cmd;
+    for _ in 0..=aliases.len() {
+        match aliases.get(cmd) {
+            Some(next) => cmd = next,
+            None => return cmd,
+        }
+    }
+    cmd
+}
+
+/// Why an alias can't be created
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AliasError {
+    /// the alias isn't a valid command name
+    InvalidName,
+    /// the alias is already the name of a command
+    Shadows,
+    /// the target isn't a command or alias in this chat
+    UnknownCommand,
+    /// the target is a command that can't be aliased
+    Forbidden,
+    /// the alias would end up running itself
+    Cycle,
+    /// the chat has too many aliases
+    TooMany,
+}
+
+/// Checks whether `alias` can be made to run `target`. `commands` says whether a name is a
+/// command and if it may be aliased
+pub fn check_alias<F>(
+    aliases: &CommandAliases,
+    alias: &str,
+    target: &str,
+    commands: F,
+) -> std::result::Result<(), AliasError>
+where
+    F: Fn(&str) -> Option<bool>,
+{
+    if !ALIAS_NAME.is_match(alias) {
+        return Err(AliasError::InvalidName);
+    }
+    if commands(alias).is_some() {
+        return Err(AliasError::Shadows);
+    }
+    if !aliases.contains_key(alias) && aliases.len() >= MAX_ALIASES {
+        return Err(AliasError::TooMany);
+    }
+    let mut next = target;
+    for _ in 0..=aliases.len() {
+        if next == alias {
+            return Err(AliasError::Cycle);
+        }
+        match aliases.get(next) {
+            Some(cmd) => next = cmd,
+            None => break,
+        }
+    }
+    match commands(next) {
+        Some(true) => Ok(()),
+        Some(false) => Err(AliasError::Forbidden),
+        None => Err(AliasError::UnknownCommand),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn commands(name: &str) -> Option<bool> {
+        match name {
+            "ban" | "kick" => Some(true),
+            "stats" => Some(false),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn resolves_chains() {
+        let aliases = CommandAliases::from([
+            ("yeet".to_owned(), "ban".to_owned()),
+            ("begone".to_owned(), "yeet".to_owned()),
+        ]);
+        assert_eq!(resolve_alias(&aliases, "begone"), "ban");
+        assert_eq!(resolve_alias(&aliases, "kick"), "kick");
+    }
+
+    #[test]
+    fn checks_aliases() {
+        let aliases = CommandAliases::from([
+            ("yeet".to_owned(), "ban".to_owned()),
+            ("begone".to_owned(), "yeet".to_owned()),
+        ]);
+        assert_eq!(check_alias(&aliases, "boot", "kick", commands), Ok(()));
+        assert_eq!(check_alias(&aliases, "boot", "begone", commands), Ok(()));
+        assert_eq!(
+            check_alias(&aliases, "yeet", "begone", commands),
+            Err(AliasError::Cycle)
+        );
+        assert_eq!(
+            check_alias(&aliases, "kick", "ban", commands),
+            Err(AliasError::Shadows)
+        );
+        assert_eq!(
+            check_alias(&aliases, "numbers", "stats", commands),
+            Err(AliasError::Forbidden)
+        );
+        assert_eq!(
+            check_alias(&aliases, "boot", "nope", commands),
+            Err(AliasError::UnknownCommand)
+        );
+        assert_eq!(
+            check_alias(&aliases, "Boot!", "kick", commands),
+            Err(AliasError::InvalidName)
+        );
+    }
+}
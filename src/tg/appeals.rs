@@ -0,0 +1,330 @@
+//! Ban appeals. When a user is banned in a chat or fbanned in a federation the bot tries to
+//! dm them a button to appeal. Pushing it asks for the appeal in the dm, which is then posted
+//! with approve and deny buttons to the chat's log channel, or sent to the federation's owner
+//! for fbans. Approving an appeal lifts the ban and the user is told the outcome either way.
+//! Appeals are only offered for bans made with a command, and only if there is somewhere to
+//! send them. Each ban can be appealed once per [`APPEAL_COOLDOWN`].
+//!
+//! Users without a dm open with the bot can't be reached, so they just don't get the button
+
+use botapi::gen_types::{
+    CallbackQuery, EReplyMarkup, InlineKeyboardButtonBuilder, MaybeInaccessibleMessage, Message,
+    User,
+};
+use macros::lang_fmt;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::persist::redis::RedisStr;
+use crate::statics::{CONFIG, REDIS, TG};
+use crate::util::error::Result;
+use crate::util::string::{get_chat_lang, should_ignore_chat, Lang, SendOptions, Speak};
+
+use super::button::{
+    callback_data, persist_action, ButtonAction, CallbackReply, InlineKeyboardBuilder,
+};
+use super::federations::{get_fed_by_id, is_fedadmin, remove_fban};
+use super::log_channel::get_log_channel;
+use super::permissions::IsAdmin;
+use super::user::{GetChat, Username};
+
+/// Seconds before a user can appeal the same ban again
+const APPEAL_COOLDOWN: i64 = 60 * 60 * 24;
+
+/// Where a ban being appealed came from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppealTarget {
+    /// A ban in a single chat
+    Chat(i64),
+    /// An fban in a federation
+    Fed(Uuid),
+}
+
+impl AppealTarget {
+    fn key_part(&self) -> String {
+        match self {
+            Self::Chat(chat) => chat.to_string(),
+            Self::Fed(fed) => fed.to_string(),
+        }
+    }
+}
+
+/// Key for the appeal a user is currently writing in the bot's dm
+#[inline(always)]
+fn get_writing_key(user: i64) -> String {
+    format!("appeal:{}", user)
+}
+
+/// Key marking an appeal waiting on a decision, so each ban is only appealed once at a time
+#[inline(always)]
+fn get_open_key(target: &AppealTarget, user: i64) -> String {
+    format!("appealopen:{}:{}", target.key_part(), user)
+}
+
+/// Key marking a recently sent appeal, so users can't flood admins with appeals
+#[inline(always)]
+fn get_cooldown_key(target: &AppealTarget, user: i64) -> String {
+    format!("appealcd:{}:{}", target.key_part(), user)
+}
+
+/// Gets a name for the chat or federation to show users
+async fn target_name(target: &AppealTarget) -> Result<String> {
+    let name = match target {
+        AppealTarget::Chat(chat) => chat
+            .get_chat()
+            .await?
+            .map(|c| c.name_humanreadable())
+            .unwrap_or_else(|| chat.to_string()),
+        AppealTarget::Fed(fed) => get_fed_by_id(fed)
+            .await?
+            .map(|f| f.fed_name)
+            .unwrap_or_else(|| fed.to_string()),
+    };
+    Ok(name)
+}
+
+/// Gets the chat appeals for a target are sent to, along with the language to use there.
+/// Returns None if there is nowhere to send them. Appeals are never posted in the chat the
+/// user was banned from, so chats without a log channel don't get any
+async fn appeal_destination(target: &AppealTarget) -> Result<Option<(i64, Lang)>> {
+    let dest = match target {
+        AppealTarget::Chat(chat) => match chat.get_chat().await? {
+            Some(c) => get_log_channel(&c).await?.map(|log| (log, *chat)),
+            None => None,
+        },
+        AppealTarget::Fed(fed) => get_fed_by_id(fed).await?.map(|f| (f.owner, f.owner)),
+    };
+    match dest {
+        Some((dest, lang_chat)) if !should_ignore_chat(dest).await? => {
+            Ok(Some((dest, get_chat_lang(lang_chat).await?)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// DMs a banned user a button to appeal their ban, along with the reason for the ban if
+/// one was given. Nothing is sent if appeals have nowhere to go. This fails if the user never
+/// started the bot, which callers are expected to ignore
+pub async fn offer_appeal(user: i64, target: AppealTarget, reason: Option<&str>) -> Result<()> {
+    if appeal_destination(&target).await?.is_none() {
+        return Ok(());
+    }
+    let lang = get_chat_lang(user).await?;
+    let button = InlineKeyboardButtonBuilder::new(lang_fmt!(lang, "appealbutton"))
+        .set_callback_data(callback_data(None))
+        .build();
+    persist_action(&button, &ButtonAction::Appeal { target }).await?;
+    let mut buttons = InlineKeyboardBuilder::default();
+    buttons.button(button);
+
+    let name = target_name(&target).await?;
+    let text = match reason {
+        Some(reason) => lang_fmt!(lang, "appealofferreason", name, reason),
+        None => lang_fmt!(lang, "appealoffer", name),
+    };
+    TG.client
+        .build_send_message(user, &text)
+        .reply_markup(&EReplyMarkup::InlineKeyboardMarkup(buttons.build()))
+        .build()
+        .await?;
+    Ok(())
+}
+
+/// Asks a banned user for the text of their appeal in the bot's dm. Returns false without
+/// asking if they already have an appeal waiting on a decision or appealed too recently
+pub(crate) async fn begin_appeal(user: i64, target: AppealTarget) -> Result<bool> {
+    let open = get_open_key(&target, user);
+    let cooldown = get_cooldown_key(&target, user);
+    let (pending, recent): (bool, bool) = REDIS.pipe(|q| q.exists(&open).exists(&cooldown)).await?;
+    if pending || recent {
+        return Ok(false);
+    }
+
+    let lang = get_chat_lang(user).await?;
+    let key = get_writing_key(user);
+    let r = RedisStr::new(&target)?;
+    REDIS
+        .pipe(|q| {
+            q.set(&key, r)
+                .expire(&key, CONFIG.load().timing.cache_timeout)
+        })
+        .await?;
+    user.speak_entities(
+        &lang_fmt!(lang, "appealprompt"),
+        &[],
+        SendOptions::default(),
+    )
+    .await?;
+    Ok(true)
+}
+
+/// Handles a banned user pushing the appeal button, waiting for their appeal's text. The
+/// button is used up once the appeal is started
+pub(crate) async fn appeal_pushed(
+    callback: &CallbackQuery,
+    target: AppealTarget,
+) -> Result<(bool, CallbackReply)> {
+    let user = callback.get_from().get_id();
+    if !begin_appeal(user, target).await? {
+        let lang = get_chat_lang(user).await?;
+        return Ok((
+            false,
+            CallbackReply::alert(lang_fmt!(lang, "appealpending")),
+        ));
+    }
+    Ok((true, CallbackReply::default()))
+}
+
+/// Posts the appeal in a dm message from a user who pushed an appeal button. Returns false
+/// if the user wasn't writing an appeal
+pub async fn submit_appeal(message: &Message, user: &User) -> Result<bool> {
+    let Some(text) = message.get_text() else {
+        return Ok(false);
+    };
+    let key = get_writing_key(user.get_id());
+    let (target, _): (Option<RedisStr>, i64) = REDIS.pipe(|q| q.get(&key).del(&key)).await?;
+    let Some(target) = target else {
+        return Ok(false);
+    };
+    let target: AppealTarget = target.get()?;
+    let lang = get_chat_lang(user.get_id()).await?;
+    let Some((dest, dest_lang)) = appeal_destination(&target).await? else {
+        user.get_id()
+            .speak_entities(
+                &lang_fmt!(lang, "appealnowhere"),
+                &[],
+                SendOptions::default(),
+            )
+            .await?;
+        return Ok(true);
+    };
+
+    let mut buttons = InlineKeyboardBuilder::default();
+    for (approve, name) in [
+        (true, lang_fmt!(dest_lang, "approveappeal")),
+        (false, lang_fmt!(dest_lang, "denyappeal")),
+    ] {
+        let button = InlineKeyboardButtonBuilder::new(name)
+            .set_callback_data(callback_data(None))
+            .build();
+        let action = ButtonAction::AppealDecision {
+            target,
+            user: user.get_id(),
+            approve,
+        };
+        persist_action(&button, &action).await?;
+        buttons.button(button);
+    }
+
+    let open = get_open_key(&target, user.get_id());
+    let cooldown = get_cooldown_key(&target, user.get_id());
+    REDIS
+        .pipe(|q| {
+            q.set(&open, true)
+                .expire(&open, CONFIG.load().timing.cache_timeout)
+                .set(&cooldown, true)
+                .expire(&cooldown, APPEAL_COOLDOWN)
+        })
+        .await?;
+
+    let appeal = lang_fmt!(
+        dest_lang,
+        "appeal",
+        user.name_humanreadable(),
+        user.get_id(),
+        target_name(&target).await?,
+        text
+    );
+    TG.client
+        .build_send_message(dest, &appeal)
+        .reply_markup(&EReplyMarkup::InlineKeyboardMarkup(buttons.build()))
+        .build()
+        .await?;
+    user.get_id()
+        .speak_entities(&lang_fmt!(lang, "appealsent"), &[], SendOptions::default())
+        .await?;
+    Ok(true)
+}
+
+/// Checks if the user pushing a decision button may lift bans for the target
+async fn can_decide(from: &User, target: &AppealTarget) -> Result<bool> {
+    match target {
+        AppealTarget::Chat(chat) => match chat.get_chat().await? {
+            Some(chat) => Ok(from.get_permissions(&chat).await?.can_restrict_members),
+            None => Ok(false),
+        },
+        AppealTarget::Fed(fed) => Ok(get_fed_by_id(fed)
+            .await?
+            .map(|f| f.owner == from.get_id())
+            .unwrap_or(false)
+            || is_fedadmin(from.get_id(), fed).await?),
+    }
+}
+
+/// Handles an admin approving or denying a posted appeal
+pub(crate) async fn appeal_decision_pushed(
+    callback: &CallbackQuery,
+    target: AppealTarget,
+    user: i64,
+    approve: bool,
+) -> Result<(bool, CallbackReply)> {
+    let from = callback.get_from();
+    let lang = match target {
+        AppealTarget::Chat(chat) => get_chat_lang(chat).await?,
+        AppealTarget::Fed(_) => get_chat_lang(from.get_id()).await?,
+    };
+    if !can_decide(from, &target).await? {
+        return Ok((false, CallbackReply::alert(lang_fmt!(lang, "appealnoperm"))));
+    }
+
+    let open = get_open_key(&target, user);
+    let removed: i64 = REDIS.sq(|q| q.del(&open)).await?;
+    if removed == 0 {
+        return Ok((true, CallbackReply::toast(lang_fmt!(lang, "appealhandled"))));
+    }
+
+    if approve {
+        match target {
+            AppealTarget::Chat(chat) => {
+                TG.client
+                    .build_unban_chat_member(chat, user)
+                    .only_if_banned(true)
+                    .build()
+                    .await?;
+            }
+            AppealTarget::Fed(fed) => {
+                remove_fban(user, &fed).await?;
+            }
+        }
+    }
+
+    let name = target_name(&target).await?;
+    let user_lang = get_chat_lang(user).await?;
+    let outcome = if approve {
+        lang_fmt!(user_lang, "appealapproved", name)
+    } else {
+        lang_fmt!(user_lang, "appealdenied", name)
+    };
+    if let Err(err) = user
+        .speak_entities(&outcome, &[], SendOptions::default())
+        .await
+    {
+        log::debug!("failed to send appeal outcome to {}: {}", user, err);
+    }
+
+    let text = if approve {
+        lang_fmt!(lang, "appealapprovedby", user, from.name_humanreadable())
+    } else {
+        lang_fmt!(lang, "appealdeniedby", user, from.name_humanreadable())
+    };
+    if let Some(MaybeInaccessibleMessage::Message(message)) = callback.get_message() {
+        TG.client
+            .build_edit_message_text(&text)
+            .chat_id(message.get_chat().get_id())
+            .message_id(message.get_message_id())
+            .build()
+            .await?;
+    }
+    Ok((true, CallbackReply::default()))
+}
@@ -0,0 +1,235 @@
+//! Birthday greetings. Users register the day of their birthday in a chat and if the chat
+//! enabled greetings a message is posted there on that day. Each chat with greetings enabled
+//! owns a single scheduler job set for the next midnight in the chat's timezone, which
+//! posts the day's greetings and reschedules itself for the following midnight
+
+use botapi::gen_types::{ChatMember, EReplyMarkup};
+use chrono::{Datelike, NaiveDate, Utc};
+use macros::lang_fmt;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+use crate::persist::core::{birthday_settings, birthdays};
+use crate::statics::{DB, TG};
+use crate::util::error::Result;
+use crate::util::string::{get_chat_lang, should_ignore_chat};
+use crate::util::time::ChatTime;
+
+use super::admin_helpers::ChatUser;
+use super::markdown::MarkupBuilder;
+use super::scheduler::{cancel_job, get_chat_jobs, schedule_job, Job, JobKind};
+use super::user::get_chat;
+
+/// Parses a birthday in MM-DD format. February 29th is accepted
+pub fn parse_birthday(text: &str) -> Option<(u32, u32)> {
+    let (month, day) = text.trim().split_once('-')?;
+    let month = month.parse().ok()?;
+    let day = day.parse().ok()?;
+    NaiveDate::from_ymd_opt(2024, month, day).map(|_| (month, day))
+}
+
+/// Days of the month whose birthdays are celebrated on `date`. Birthdays on February 29th
+/// are celebrated on the 28th in years without one
+pub fn celebrated_days(date: NaiveDate) -> Vec<u32> {
+    if date.month() == 2 && date.day() == 28 && date.with_day(29).is_none() {
+        vec![28, 29]
+    } else {
+        vec![date.day()]
+    }
+}
+
+/// Registers a user's birthday in a chat, replacing any previous date
+pub async fn set_birthday(chat: i64, user: i64, month: u32, day: u32) -> Result<()> {
+    let model = birthdays::ActiveModel {
+        chat_id: Set(chat),
+        user_id: Set(user),
+        month: Set(month as i32),
+        day: Set(day as i32),
+    };
+    birthdays::Entity::insert(model)
+        .on_conflict(
+            OnConflict::columns([birthdays::Column::ChatId, birthdays::Column::UserId])
+                .update_columns([birthdays::Column::Month, birthdays::Column::Day])
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+    Ok(())
+}
+
+/// Removes a user's birthday from a chat. Returns false if none was registered
+pub async fn remove_birthday(chat: i64, user: i64) -> Result<bool> {
+    let res = birthdays::Entity::delete_by_id((chat, user))
+        .exec(*DB)
+        .await?;
+    Ok(res.rows_affected > 0)
+}
+
+/// Gets the birthday settings for a chat
+pub async fn get_birthday_settings(chat: i64) -> Result<Option<birthday_settings::Model>> {
+    let res = birthday_settings::Entity::find_by_id(chat).one(*DB).await?;
+    Ok(res)
+}
+
+async fn update_settings(
+    model: birthday_settings::ActiveModel,
+    column: birthday_settings::Column,
+) -> Result<birthday_settings::Model> {
+    let model = birthday_settings::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(birthday_settings::Column::ChatId)
+                .update_column(column)
+                .to_owned(),
+        )
+        .exec_with_returning(*DB)
+        .await?;
+    Ok(model)
+}
+
+/// Enables or disables birthday greetings in a chat
+pub async fn set_birthdays_enabled(chat: i64, enabled: bool) -> Result<()> {
+    let model = birthday_settings::ActiveModel {
+        chat_id: Set(chat),
+        enabled: Set(enabled),
+        greeting: NotSet,
+    };
+    let model = update_settings(model, birthday_settings::Column::Enabled).await?;
+    schedule_birthdays(&model).await
+}
+
+/// Sets the murkdown greeting for a chat, or resets it to the default with None
+pub async fn set_birthday_greeting(chat: i64, greeting: Option<String>) -> Result<()> {
+    let model = birthday_settings::ActiveModel {
+        chat_id: Set(chat),
+        enabled: NotSet,
+        greeting: Set(greeting),
+    };
+    update_settings(model, birthday_settings::Column::Greeting).await?;
+    Ok(())
+}
+
+/// Replaces the chat's pending birthday job with one at the next local midnight, or just
+/// cancels it if greetings are disabled
+pub async fn schedule_birthdays(settings: &birthday_settings::Model) -> Result<()> {
+    let chat = settings.chat_id;
+    for job in get_chat_jobs(chat).await? {
+        if let JobKind::Birthdays = job.kind {
+            cancel_job(chat, &job.id).await?;
+        }
+    }
+
+    if settings.enabled {
+        let run_at = ChatTime::get(chat).await?.next_midnight(Utc::now());
+        schedule_job(&Job::new(chat, run_at, JobKind::Birthdays)).await?;
+    }
+    Ok(())
+}
+
+async fn greet(settings: &birthday_settings::Model, user: i64) -> Result<()> {
+    let chat_id = settings.chat_id;
+    let member = TG
+        .client
+        .build_get_chat_member(chat_id, user)
+        .build()
+        .await?;
+    let user = match member {
+        ChatMember::ChatMemberMember(m) => m.get_user().to_owned(),
+        ChatMember::ChatMemberAdministrator(m) => m.get_user().to_owned(),
+        ChatMember::ChatMemberOwner(m) => m.get_user().to_owned(),
+        ChatMember::ChatMemberRestricted(m) if m.get_is_member() => m.get_user().to_owned(),
+        _ => return Ok(()),
+    };
+    let Some(chat) = get_chat(chat_id).await? else {
+        return Ok(());
+    };
+
+    let text = if let Some(ref greeting) = settings.greeting {
+        greeting.clone()
+    } else {
+        let lang = get_chat_lang(chat_id).await?;
+        lang_fmt!(lang, "bdaydefault")
+    };
+
+    let (text, entities, buttons) = MarkupBuilder::new(None)
+        .set_text(text)
+        .filling(true)
+        .header(false)
+        .chatuser(Some(&ChatUser {
+            chat: &chat,
+            user: &user,
+        }))
+        .build_murkdown_nofail()
+        .await;
+
+    let mut message = TG
+        .client
+        .build_send_message(chat_id, &text)
+        .entities(&entities);
+    let markup =
+        (!buttons.get().is_empty()).then(|| EReplyMarkup::InlineKeyboardMarkup(buttons.build()));
+    if let Some(ref markup) = markup {
+        message = message.reply_markup(markup);
+    }
+    message.build().await?;
+    Ok(())
+}
+
+/// Posts greetings for every birthday today in the chat's local time, then schedules the
+/// next day. Run by the scheduler at each local midnight
+pub(crate) async fn run_birthdays(chat: i64) -> Result<()> {
+    let Some(settings) = get_birthday_settings(chat).await? else {
+        return Ok(());
+    };
+    if !settings.enabled {
+        return Ok(());
+    }
+
+    // reschedule first so a failed greeting doesn't end greetings for good
+    schedule_birthdays(&settings).await?;
+    if should_ignore_chat(chat).await? {
+        return Ok(());
+    }
+
+    let today = ChatTime::get(chat).await?.today();
+    let users = birthdays::Entity::find()
+        .filter(
+            birthdays::Column::ChatId
+                .eq(chat)
+                .and(birthdays::Column::Month.eq(today.month() as i32))
+                .and(birthdays::Column::Day.is_in(celebrated_days(today))),
+        )
+        .all(*DB)
+        .await?;
+
+    for birthday in users {
+        if let Err(err) = greet(&settings, birthday.user_id).await {
+            log::warn!("failed to post birthday greeting in {}: {}", chat, err);
+            err.record_stats();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn birthdays() {
+        assert_eq!(parse_birthday("04-12"), Some((4, 12)));
+        assert_eq!(parse_birthday("02-29"), Some((2, 29)));
+        assert_eq!(parse_birthday("02-30"), None);
+        assert_eq!(parse_birthday("13-01"), None);
+        assert_eq!(parse_birthday("april"), None);
+    }
+
+    #[test]
+    fn leap_birthdays() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(celebrated_days(date(2023, 2, 28)), vec![28, 29]);
+        assert_eq!(celebrated_days(date(2024, 2, 28)), vec![28]);
+        assert_eq!(celebrated_days(date(2024, 2, 29)), vec![29]);
+        assert_eq!(celebrated_days(date(2023, 4, 12)), vec![12]);
+    }
+}
@@ -0,0 +1,215 @@
+//! Chat boosts. The bot api has no way to list the boosters of a chat, only the boosts of
+//! a single user, so boosts are recorded from chat_boost and removed_chat_boost updates.
+//! Boosts can also end without an update, so while a chat has boosts a job refreshes them
+//! once a day by asking telegram for the current boosts of every recorded booster.
+//!
+//! Chats can give boosters perks with /boosterperks, currently being exempt from locks.
+
+use std::collections::HashSet;
+
+use botapi::gen_types::{
+    Chat, ChatBoost, ChatBoostRemoved, ChatBoostSource, ChatBoostUpdated, User,
+};
+use chrono::{DateTime, Duration, Utc};
+use redis::AsyncCommands;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
+
+use crate::persist::core::chat_boosts;
+use crate::persist::redis::{default_cache_query, CachedQueryTrait};
+use crate::statics::{CONFIG, DB, REDIS, TG};
+use crate::util::error::Result;
+
+use super::dialog::dialog_or_default;
+use super::scheduler::{cancel_job, get_chat_jobs, schedule_job, Job, JobKind};
+
+/// Time between refreshes of a chat's boosts
+const REFRESH_INTERVAL_HOURS: i64 = 24;
+
+#[inline(always)]
+fn get_boosts_key(chat: i64) -> String {
+    format!("boosts:{}", chat)
+}
+
+/// Gets the user behind a boost, None for unclaimed giveaway prizes
+pub fn boost_source_user(source: &ChatBoostSource) -> Option<&User> {
+    match source {
+        ChatBoostSource::ChatBoostSourcePremium(source) => Some(source.get_user()),
+        ChatBoostSource::ChatBoostSourceGiftCode(source) => Some(source.get_user()),
+        ChatBoostSource::ChatBoostSourceGiveaway(source) => source.get_user(),
+    }
+}
+
+fn boost_model(chat: i64, boost: &ChatBoost) -> chat_boosts::ActiveModel {
+    chat_boosts::ActiveModel {
+        chat_id: Set(chat),
+        boost_id: Set(boost.get_boost_id().to_owned()),
+        user_id: Set(boost_source_user(boost.get_source()).map(|user| user.get_id())),
+        added: Set(DateTime::from_timestamp(boost.get_add_date(), 0).unwrap_or_else(Utc::now)),
+        expires: Set(
+            DateTime::from_timestamp(boost.get_expiration_date(), 0).unwrap_or_else(Utc::now)
+        ),
+    }
+}
+
+async fn invalidate_boosts(chat: i64) -> Result<()> {
+    let key = get_boosts_key(chat);
+    REDIS.sq(|q| q.del(&key)).await?;
+    Ok(())
+}
+
+async fn save_boosts(chat: i64, boosts: &[ChatBoost]) -> Result<()> {
+    if boosts.is_empty() {
+        return Ok(());
+    }
+    chat_boosts::Entity::insert_many(boosts.iter().map(|boost| boost_model(chat, boost)))
+        .on_conflict(
+            OnConflict::columns([chat_boosts::Column::ChatId, chat_boosts::Column::BoostId])
+                .update_columns([chat_boosts::Column::UserId, chat_boosts::Column::Expires])
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+    Ok(())
+}
+
+/// Records a new or changed boost, making sure the chat's boosts get refreshed
+pub async fn record_boost(update: &ChatBoostUpdated) -> Result<()> {
+    let chat = update.get_chat().get_id();
+    save_boosts(chat, std::slice::from_ref(update.get_boost())).await?;
+    invalidate_boosts(chat).await?;
+    schedule_boost_refresh(chat, false).await
+}
+
+/// Forgets a boost that was removed
+pub async fn remove_boost(update: &ChatBoostRemoved) -> Result<()> {
+    let chat = update.get_chat().get_id();
+    chat_boosts::Entity::delete_many()
+        .filter(
+            chat_boosts::Column::ChatId
+                .eq(chat)
+                .and(chat_boosts::Column::BoostId.eq(update.get_boost_id())),
+        )
+        .exec(*DB)
+        .await?;
+    invalidate_boosts(chat).await
+}
+
+/// Gets the chat's boosts that haven't expired, oldest first
+pub async fn get_boosts(chat: i64) -> Result<Vec<chat_boosts::Model>> {
+    let key = get_boosts_key(chat);
+    let boosts: Vec<chat_boosts::Model> = default_cache_query(
+        |_, _| async move {
+            let res = chat_boosts::Entity::find()
+                .filter(chat_boosts::Column::ChatId.eq(chat))
+                .order_by_asc(chat_boosts::Column::Added)
+                .all(*DB)
+                .await?;
+            Ok(Some(res))
+        },
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
+    )
+    .query(&key, &())
+    .await?
+    .unwrap_or_default();
+    let now = Utc::now();
+    Ok(boosts.into_iter().filter(|b| b.expires > now).collect())
+}
+
+/// Whether a user is currently boosting a chat
+pub async fn is_booster(chat: i64, user: i64) -> Result<bool> {
+    Ok(get_boosts(chat)
+        .await?
+        .iter()
+        .any(|boost| boost.user_id == Some(user)))
+}
+
+/// Whether a user gets the chat's booster perks
+pub async fn has_booster_perks(chat: &Chat, user: i64) -> Result<bool> {
+    Ok(dialog_or_default(chat).await?.booster_perks && is_booster(chat.get_id(), user).await?)
+}
+
+/// Cancels the pending refresh of a chat's boosts
+async fn cancel_boost_refresh(chat: i64) -> Result<()> {
+    for job in get_chat_jobs(chat).await? {
+        if let JobKind::RefreshBoosts = job.kind {
+            cancel_job(chat, &job.id).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Schedules the next refresh of a chat's boosts unless one is pending. With `replace` a
+/// pending refresh is moved to a full interval from now
+async fn schedule_boost_refresh(chat: i64, replace: bool) -> Result<()> {
+    if replace {
+        cancel_boost_refresh(chat).await?;
+    } else if get_chat_jobs(chat)
+        .await?
+        .iter()
+        .any(|job| matches!(job.kind, JobKind::RefreshBoosts))
+    {
+        return Ok(());
+    }
+    let run_at = Utc::now() + Duration::try_hours(REFRESH_INTERVAL_HOURS).unwrap();
+    schedule_job(&Job::new(chat, run_at, JobKind::RefreshBoosts)).await
+}
+
+/// Asks telegram for the current boosts of every recorded booster, dropping boosts that
+/// ended. Refreshes stop once a chat has no boosts left. Run by the scheduler
+pub(crate) async fn run_refresh_boosts(chat: i64) -> Result<()> {
+    // reschedule first so a failed refresh doesn't end refreshes for good
+    schedule_boost_refresh(chat, true).await?;
+    let now = Utc::now();
+    chat_boosts::Entity::delete_many()
+        .filter(
+            chat_boosts::Column::ChatId
+                .eq(chat)
+                .and(chat_boosts::Column::Expires.lte(now)),
+        )
+        .exec(*DB)
+        .await?;
+    let users = chat_boosts::Entity::find()
+        .filter(chat_boosts::Column::ChatId.eq(chat))
+        .all(*DB)
+        .await?
+        .into_iter()
+        .filter_map(|boost| boost.user_id)
+        .collect::<HashSet<i64>>();
+
+    for user in users {
+        let boosts = match TG
+            .client
+            .build_get_user_chat_boosts(chat, user)
+            .build()
+            .await
+        {
+            Ok(boosts) => boosts,
+            Err(err) => {
+                log::warn!("failed to refresh boosts for {} in {}: {}", user, chat, err);
+                continue;
+            }
+        };
+        let boosts = boosts.get_boosts();
+        chat_boosts::Entity::delete_many()
+            .filter(
+                chat_boosts::Column::ChatId
+                    .eq(chat)
+                    .and(chat_boosts::Column::UserId.eq(user))
+                    .and(
+                        chat_boosts::Column::BoostId
+                            .is_not_in(boosts.iter().map(|boost| boost.get_boost_id())),
+                    ),
+            )
+            .exec(*DB)
+            .await?;
+        save_boosts(chat, boosts).await?;
+    }
+    invalidate_boosts(chat).await?;
+
+    if get_boosts(chat).await?.is_empty() {
+        cancel_boost_refresh(chat).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,140 @@
+//! Registering the bot's commands with telegram so clients can autocomplete them. Commands
+//! come from the metadata of every enabled module. Commands that declare admin permissions
+//! are only shown to chat admins, everything else is shown in private chats and groups.
+//! Help strings only exist in english, so the commands are registered without a language
+//! code and every client gets the same descriptions
+
+use botapi::gen_types::{
+    BotCommand, BotCommandBuilder, BotCommandScope, BotCommandScopeAllChatAdministratorsBuilder,
+    BotCommandScopeAllGroupChatsBuilder, BotCommandScopeAllPrivateChatsBuilder,
+};
+
+use crate::metadata::{markdownify, ModuleRegistry};
+use crate::util::error::Result;
+
+use super::client::TgClient;
+
+/// Most commands telegram accepts for a single scope
+const MAX_COMMANDS: usize = 100;
+
+/// Longest description telegram accepts for a command
+const MAX_DESCRIPTION: usize = 256;
+
+/// Checks if a command name is one telegram accepts, 1-32 lowercase letters, digits, or
+/// underscores
+fn valid_command(command: &str) -> bool {
+    (1..=32).contains(&command.len())
+        && command
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Turns a help string into a single line of plain text short enough for telegram
+fn command_description(help: &str) -> String {
+    let help = markdownify(help).replace('\\', "");
+    let help = help.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if help.chars().count() > MAX_DESCRIPTION {
+        let mut help = help.chars().take(MAX_DESCRIPTION - 3).collect::<String>();
+        help.push_str("...");
+        help
+    } else {
+        help
+    }
+}
+
+/// Gets the commands of every module in the registry sorted by name. Commands with required
+/// permissions are left out unless `admin` is set
+fn command_list(registry: &ModuleRegistry, admin: bool) -> Vec<(String, String)> {
+    let mut commands = registry
+        .iter()
+        .flat_map(|module| {
+            let metadata = module.metadata();
+            metadata
+                .commands
+                .iter()
+                .filter(|(command, _)| admin || !metadata.perms.contains_key(*command))
+                .map(|(command, help)| (command.to_owned(), command_description(help)))
+                .collect::<Vec<(String, String)>>()
+        })
+        .filter(|(command, help)| valid_command(command) && !help.is_empty())
+        .collect::<Vec<(String, String)>>();
+    commands.sort();
+    commands.dedup_by(|(a, _), (b, _)| a == b);
+    commands.truncate(MAX_COMMANDS);
+    commands
+}
+
+fn bot_commands(commands: Vec<(String, String)>) -> Vec<BotCommand> {
+    commands
+        .into_iter()
+        .map(|(command, description)| BotCommandBuilder::new(command, description).build())
+        .collect()
+}
+
+/// Registers the commands of a bot's modules with telegram for private chats, groups, and
+/// chat admins
+pub async fn register_commands(bot: &TgClient) -> Result<()> {
+    let scopes = [
+        (
+            BotCommandScope::BotCommandScopeAllPrivateChats(
+                BotCommandScopeAllPrivateChatsBuilder::new("all_private_chats".to_owned()).build(),
+            ),
+            false,
+        ),
+        (
+            BotCommandScope::BotCommandScopeAllGroupChats(
+                BotCommandScopeAllGroupChatsBuilder::new("all_group_chats".to_owned()).build(),
+            ),
+            false,
+        ),
+        (
+            BotCommandScope::BotCommandScopeAllChatAdministrators(
+                BotCommandScopeAllChatAdministratorsBuilder::new(
+                    "all_chat_administrators".to_owned(),
+                )
+                .build(),
+            ),
+            true,
+        ),
+    ];
+
+    for (scope, admin) in scopes {
+        let commands = bot_commands(command_list(&bot.registry, admin));
+        bot.client
+            .build_set_my_commands(&commands)
+            .scope(&scope)
+            .build()
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn command_names() {
+        assert!(valid_command("warnlimit"));
+        assert!(valid_command("set_commands2"));
+        assert!(!valid_command(""));
+        assert!(!valid_command("Warn"));
+        assert!(!valid_command("warn-limit"));
+        assert!(!valid_command(&"a".repeat(33)));
+    }
+
+    #[test]
+    fn descriptions_fit() {
+        assert_eq!(
+            command_description("Allow links. Usage: /allowlink \\<domain\\>"),
+            "Allow links. Usage: /allowlink <domain>"
+        );
+        assert_eq!(
+            command_description("Sets time.\n        Use /warntime clear"),
+            "Sets time. Use /warntime clear"
+        );
+        let long = command_description(&"word ".repeat(100));
+        assert_eq!(long.chars().count(), MAX_DESCRIPTION);
+        assert!(long.ends_with("..."));
+    }
+}
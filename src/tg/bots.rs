@@ -0,0 +1,97 @@
+//! Running several bots from one process. The main bot_token and every entry in `clones` get
+//! their own client, identity, button callbacks, admin cache, and webhook, while sharing the
+//! database, redis, and background tasks. The bot handling the current update is kept in a
+//! task local so code using `TG` and `ME` talks to the right bot without passing it around.
+//! Work spawned while handling an update should use [`spawn`] to keep the same bot. Anything
+//! running outside an update, like the scheduler, uses the main bot unless told otherwise
+
+use std::future::Future;
+use std::net::SocketAddr;
+
+use lazy_static::lazy_static;
+use once_cell::sync::OnceCell;
+use tokio::task::JoinHandle;
+
+use crate::statics::CONFIG;
+use crate::util::error::{BotError, Result};
+use crate::util::trace::propagate;
+
+use super::client::TgClient;
+
+tokio::task_local! {
+    static CURRENT: &'static TgClient;
+}
+
+lazy_static! {
+    static ref BOTS: OnceCell<Vec<TgClient>> = OnceCell::new();
+}
+
+/// Sets the bots run by this process. The first one is the main bot
+pub(crate) fn init_bots(bots: Vec<TgClient>) {
+    if BOTS.set(bots).is_err() {
+        panic!("bots already initialized");
+    }
+}
+
+/// Gets every bot run by this process, starting with the main bot
+pub fn all_bots() -> &'static [TgClient] {
+    BOTS.get().expect("bots not initialized")
+}
+
+/// Gets the main bot, the one configured with bot_token
+pub fn main_bot() -> &'static TgClient {
+    &all_bots()[0]
+}
+
+/// Gets the bot handling the current update, falling back to the main bot. None if the bots
+/// haven't been started yet
+pub fn try_current_bot() -> Option<&'static TgClient> {
+    CURRENT
+        .try_with(|bot| *bot)
+        .ok()
+        .or_else(|| BOTS.get().and_then(|bots| bots.first()))
+}
+
+/// Gets the bot handling the current update, falling back to the main bot
+pub fn current_bot() -> &'static TgClient {
+    try_current_bot().expect("bots not initialized")
+}
+
+/// Gets a bot run by this process from its user id
+pub fn get_bot(id: i64) -> Option<&'static TgClient> {
+    BOTS.get()?.iter().find(|bot| bot.bot_id() == id)
+}
+
+/// Runs a future with `bot` as the current bot
+pub async fn with_bot<F: Future>(bot: &'static TgClient, fut: F) -> F::Output {
+    CURRENT.scope(bot, fut).await
+}
+
+/// Spawns a task that keeps the current bot and trace id
+pub fn spawn<F>(fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(CURRENT.scope(current_bot(), propagate(fut)))
+}
+
+/// Gets the url and socket a bot's webhook uses. Clones need their own since each bot gets
+/// its own webhook
+pub(crate) fn webhook_target(bot: &TgClient) -> Result<(String, SocketAddr)> {
+    let config = CONFIG.load();
+    if bot.token == config.bot_token {
+        return Ok((config.webhook.webhook_url.clone(), config.webhook.listen));
+    }
+    config
+        .clones
+        .iter()
+        .find(|clone| clone.bot_token == bot.token)
+        .and_then(|clone| Some((clone.webhook_url.clone()?, clone.listen?)))
+        .ok_or_else(|| {
+            BotError::generic(format!(
+                "clone bot {} needs webhook_url and listen set when webhooks are enabled",
+                bot.bot_id()
+            ))
+        })
+}
@@ -0,0 +1,184 @@
+//! Delivery of announcements from the bot owner. Each chat gets its own scheduled job, so
+//! a broadcast carries on after a restart and one failing chat doesn't hold up the rest. Progress is counted in redis and shown by editing a status message
+
+use botapi::gen_types::{EReplyMarkup, InlineKeyboardMarkup, Message, MessageEntity};
+use chrono::Utc;
+use macros::lang_fmt;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::persist::redis::{RedisStr, ToRedisStr};
+use crate::statics::{BROADCAST_GOVERNER, REDIS, TG};
+use crate::util::error::{BotError, Result};
+use crate::util::string::Lang;
+
+use super::scheduler::{schedule_job, Job, JobKind};
+
+/// Telegram's error code for chats the bot was removed from or blocked by, which no retry
+/// will fix
+const FORBIDDEN: i64 = 403;
+
+/// How many chats are sent to between progress updates
+const PROGRESS_INTERVAL: i64 = 25;
+
+/// Seconds a broadcast's progress is kept for, long enough for every retry to finish
+const PROGRESS_SECONDS: i64 = 60 * 60 * 24;
+
+#[inline(always)]
+fn get_progress_key(broadcast: &Uuid) -> String {
+    format!("bcast:{}", broadcast)
+}
+
+#[inline(always)]
+fn get_status_key(broadcast: &Uuid) -> String {
+    format!("bcast:status:{}", broadcast)
+}
+
+/// A broadcast to a single chat
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BroadcastSend {
+    /// id shared by every chat's job in the same broadcast, used to count progress
+    pub broadcast: Uuid,
+    pub chat: i64,
+    pub text: String,
+    pub entities: Vec<MessageEntity>,
+    pub markup: Option<InlineKeyboardMarkup>,
+}
+
+/// Where and how to report a broadcast's progress
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct BroadcastStatus {
+    chat: i64,
+    message: i64,
+    total: i64,
+    lang: Lang,
+}
+
+/// Queues a broadcast to every chat in `chats`, reporting progress by editing `status`
+pub async fn start_broadcast(
+    chats: Vec<i64>,
+    text: String,
+    entities: Vec<MessageEntity>,
+    markup: Option<InlineKeyboardMarkup>,
+    status: &Message,
+    lang: Lang,
+) -> Result<()> {
+    if chats.is_empty() {
+        TG.client
+            .build_edit_message_text(&lang_fmt!(lang, "broadcastdone", 0, 0))
+            .chat_id(status.get_chat().get_id())
+            .message_id(status.get_message_id())
+            .build()
+            .await?;
+        return Ok(());
+    }
+    let broadcast = Uuid::new_v4();
+    let status = BroadcastStatus {
+        chat: status.get_chat().get_id(),
+        message: status.get_message_id(),
+        total: chats.len() as i64,
+        lang,
+    };
+    let key = get_status_key(&broadcast);
+    REDIS
+        .try_pipe(|p| {
+            Ok(p.set(&key, status.to_redis()?)
+                .expire(&key, PROGRESS_SECONDS))
+        })
+        .await?;
+    for chat in chats {
+        let send = BroadcastSend {
+            broadcast,
+            chat,
+            text: text.clone(),
+            entities: entities.clone(),
+            markup: markup.clone(),
+        };
+        let job = Job::new(chat, Utc::now(), JobKind::Broadcast(send));
+        if let Err(err) = schedule_job(&job).await {
+            log::warn!("failed to queue broadcast to {}: {}", chat, err);
+            err.record_stats();
+            if let Err(err) = record_result(&broadcast, false).await {
+                err.record_stats();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Counts a chat as sent or failed, updating the status message every few chats and once
+/// every chat is done
+async fn record_result(broadcast: &Uuid, sent: bool) -> Result<()> {
+    let key = get_progress_key(broadcast);
+    let field = if sent { "sent" } else { "failed" };
+    let (_, _, sent, failed): ((), (), Option<i64>, Option<i64>) = REDIS
+        .pipe(|p| {
+            p.hincr(&key, field, 1)
+                .expire(&key, PROGRESS_SECONDS)
+                .hget(&key, "sent")
+                .hget(&key, "failed")
+        })
+        .await?;
+    let (sent, failed) = (sent.unwrap_or(0), failed.unwrap_or(0));
+
+    let status: Option<RedisStr> = REDIS.sq(|q| q.get(&get_status_key(broadcast))).await?;
+    let Some(status) = status else {
+        return Ok(());
+    };
+    let status: BroadcastStatus = status.get()?;
+    let lang = status.lang;
+    let done = sent + failed;
+    let text = if done >= status.total {
+        lang_fmt!(lang, "broadcastdone", sent, failed)
+    } else if done % PROGRESS_INTERVAL == 0 {
+        lang_fmt!(lang, "broadcastprogress", status.total, sent, failed)
+    } else {
+        return Ok(());
+    };
+    TG.client
+        .build_edit_message_text(&text)
+        .chat_id(status.chat)
+        .message_id(status.message)
+        .build()
+        .await?;
+    Ok(())
+}
+
+/// Sends a broadcast to one chat. Chats the bot can't post in are counted as failed right
+/// away, other errors are left to the scheduler to retry
+pub async fn send_broadcast(send: &BroadcastSend) -> Result<()> {
+    BROADCAST_GOVERNER.until_ready().await;
+    let markup = send
+        .markup
+        .as_ref()
+        .map(|markup| EReplyMarkup::InlineKeyboardMarkup(markup.clone()));
+    let mut message = TG
+        .client
+        .build_send_message(send.chat, &send.text)
+        .entities(&send.entities);
+    if let Some(ref markup) = markup {
+        message = message.reply_markup(markup);
+    }
+    if let Err(err) = message.build().await {
+        let err = BotError::from(err);
+        if err.get_tg_code() != Some(FORBIDDEN) {
+            return Err(err);
+        }
+        log::debug!("can't broadcast to {}: {}", send.chat, err);
+        return record_result(&send.broadcast, false).await;
+    }
+
+    // the message went out, so failing to show progress mustn't retry the job
+    if let Err(err) = record_result(&send.broadcast, true).await {
+        log::warn!("failed to update broadcast progress: {}", err);
+        err.record_stats();
+    }
+    Ok(())
+}
+
+/// Counts a chat the broadcast couldn't be sent to after every retry
+pub async fn broadcast_failed(send: &BroadcastSend) -> Result<()> {
+    log::warn!("failed to broadcast to {}", send.chat);
+    record_result(&send.broadcast, false).await
+}
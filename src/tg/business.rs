@@ -0,0 +1,115 @@
+//! Telegram Business. Business accounts can connect the bot to answer their private chats,
+//! and messages in those chats arrive as business_message updates. These are handed to
+//! modules as regular messages, with the message's business connection id set so replies
+//! are sent on behalf of the business account.
+//!
+//! Customers shouldn't be able to run the bot's commands, or configure anything, from the
+//! business account's chats, so business messages never parse as commands and only a few
+//! modules see them at all. Those modules use the settings of the business account owner's
+//! own chat with the bot, so notes and filters saved there answer every business chat. Only
+//! users with a business account connected to the bot can change the settings of their chat
+//! with it, and messages the owner sends in their business chats aren't answered.
+
+use botapi::gen_types::{BusinessConnection, Message, UpdateExt};
+use chrono::Duration;
+use macros::lang_fmt;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+
+use crate::persist::redis::{default_cache_query, CachedQueryTrait};
+use crate::statics::{CONFIG, REDIS, TG};
+use crate::util::error::Result;
+use crate::util::string::{get_chat_lang, Speak};
+
+/// Modules that handle messages from business chats
+pub const BUSINESS_MODULES: &[&str] = &["Notes", "Filters"];
+
+/// What the bot needs to know about a business connection
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+struct BusinessChat {
+    /// the business account owner's chat with the bot
+    user_chat_id: i64,
+
+    /// whether the bot can currently send messages on the account's behalf
+    can_reply: bool,
+}
+
+impl From<&BusinessConnection> for BusinessChat {
+    fn from(connection: &BusinessConnection) -> Self {
+        Self {
+            user_chat_id: connection.get_user_chat_id(),
+            can_reply: connection.get_is_enabled() && connection.get_can_reply(),
+        }
+    }
+}
+
+#[inline(always)]
+fn get_business_key(connection: &str) -> String {
+    format!("bconn:{}", connection)
+}
+
+#[inline(always)]
+fn get_owner_key(user: i64) -> String {
+    format!("bowner:{}", user)
+}
+
+/// Checks if a user has a business account the bot can currently reply for
+pub async fn is_business_owner(user: i64) -> Result<bool> {
+    let key = get_owner_key(user);
+    let owner: bool = REDIS.sq(|q| q.exists(&key)).await?;
+    Ok(owner)
+}
+
+/// Gets the owner's chat with the bot for a business connection, None if the bot can't
+/// reply through it
+pub async fn get_business_chat(connection: &str) -> Result<Option<i64>> {
+    let key = get_business_key(connection);
+    let chat: Option<BusinessChat> = default_cache_query(
+        |_, _| async move {
+            let connection = TG
+                .client
+                .build_get_business_connection(connection)
+                .build()
+                .await?;
+            Ok(Some(BusinessChat::from(&connection)))
+        },
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
+    )
+    .query(&key, &())
+    .await?;
+    Ok(chat
+        .filter(|chat| chat.can_reply)
+        .map(|chat| chat.user_chat_id))
+}
+
+/// Gets the owner's chat for a message from a business chat. None for other messages, or
+/// if the bot can't reply through the message's connection
+pub async fn message_business_chat(message: &Message) -> Result<Option<i64>> {
+    match message.get_business_connection_id() {
+        Some(connection) => get_business_chat(connection).await,
+        None => Ok(None),
+    }
+}
+
+/// Forgets a connection that was changed and tells its owner whether the bot is now
+/// answering their business chats
+pub async fn handle_business_connection(update: &UpdateExt) -> Result<()> {
+    let UpdateExt::BusinessConnection(connection) = update else {
+        return Ok(());
+    };
+    let key = get_business_key(connection.get_id());
+    REDIS.sq(|q| q.del(&key)).await?;
+
+    let chat = connection.get_user_chat_id();
+    let owner = get_owner_key(connection.get_user().get_id());
+    let lang = get_chat_lang(chat).await?;
+    let text = if BusinessChat::from(connection).can_reply {
+        REDIS.sq(|q| q.set(&owner, chat)).await?;
+        lang_fmt!(lang, "businessconnected")
+    } else {
+        REDIS.sq(|q| q.del(&owner)).await?;
+        lang_fmt!(lang, "businessdisconnected")
+    };
+    chat.speak(text).await?;
+    Ok(())
+}
@@ -2,18 +2,375 @@
 //! handling callbacks for clicked buttons, and handling deep links
 
 use crate::persist::core::button;
-use crate::statics::ME;
+use crate::persist::redis::RedisStr;
+use crate::statics::{CONFIG, ME, REDIS};
 use crate::util::error::Result;
 use crate::{statics::TG, util::error::BotError};
 use botapi::gen_types::{
     CallbackQuery, InlineKeyboardButton, InlineKeyboardButtonBuilder, InlineKeyboardMarkup,
+    UpdateExt,
 };
 
-use futures::Future;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use futures::future::BoxFuture;
+use futures::{Future, FutureExt};
+use hmac::{Hmac, Mac};
+use redis::{AsyncCommands, Script};
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::ops::DerefMut;
+use uuid::Uuid;
+
+use super::appeals::{appeal_decision_pushed, appeal_pushed, AppealTarget};
+use super::command::StaticContext;
+use super::dialog::transition_stored;
+use super::giveaways::enter_pushed;
+use super::greetings::{captcha_correct, captcha_incorrect, human_pushed};
+use super::join_requests::join_request_pushed;
+use super::settings::{settings_pushed, SettingsPage};
+use super::setup::{setup_pushed, SetupChoice};
+use super::voteban::veto_pushed;
 
 const MAX_BUTTONS: usize = 8;
 
+/// Bytes of the hmac kept in callback data, telegram limits callback data to 64 bytes
+const SIGNATURE_LEN: usize = 16;
+
+/// Gets the key callback data is signed with. The bot doesn't start without one, see
+/// [`crate::util::config::validate_callback_secret`]
+pub(crate) fn callback_key() -> Vec<u8> {
+    CONFIG
+        .load()
+        .callback_secret
+        .as_ref()
+        .expect("callback_secret is checked on startup")
+        .as_bytes()
+        .to_owned()
+}
+
+/// Result of checking callback data from a pushed button
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallbackAuth {
+    /// The data was generated by us and the user is allowed to push the button
+    Valid,
+    /// The data was generated by us but the button belongs to another user
+    WrongUser,
+    /// The data was not generated by us or was tampered with
+    Invalid,
+}
+
+fn callback_mac(key: &[u8], id: &str, user: Option<i64>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(id.as_bytes());
+    if let Some(user) = user {
+        mac.update(&user.to_be_bytes());
+    }
+    mac
+}
+
+fn verify_signature(key: &[u8], id: &str, user: Option<i64>, sig: &str) -> bool {
+    URL_SAFE_NO_PAD
+        .decode(sig)
+        .ok()
+        .filter(|sig| sig.len() == SIGNATURE_LEN)
+        .map(|sig| {
+            callback_mac(key, id, user)
+                .verify_truncated_left(&sig)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+fn make_callback_data(key: &[u8], user: Option<i64>) -> String {
+    let id = Uuid::new_v4().simple().to_string();
+    let bound = if user.is_some() { 'u' } else { 'a' };
+    let sig = callback_mac(key, &id, user).finalize().into_bytes();
+    format!(
+        "{}{}{}",
+        id,
+        bound,
+        URL_SAFE_NO_PAD.encode(&sig[..SIGNATURE_LEN])
+    )
+}
+
+fn check_callback_data(key: &[u8], data: &str, user: i64) -> CallbackAuth {
+    let id_len = Uuid::nil().simple().to_string().len();
+    let (id, bound, sig) = match (data.get(..id_len), data.get(id_len..id_len + 1)) {
+        (Some(id), Some(bound)) => (id, bound, &data[id_len + 1..]),
+        _ => return CallbackAuth::Invalid,
+    };
+
+    match bound {
+        "a" if verify_signature(key, id, None, sig) => CallbackAuth::Valid,
+        "u" if verify_signature(key, id, Some(user), sig) => CallbackAuth::Valid,
+        "u" => CallbackAuth::WrongUser,
+        _ => CallbackAuth::Invalid,
+    }
+}
+
+/// Generates opaque signed callback data for a button. If a user is provided the button
+/// only works for that user. Callbacks registered with [`OnPush`] are only called for data
+/// that passes [`verify_callback_data`]
+pub fn callback_data(user: Option<i64>) -> String {
+    make_callback_data(&callback_key(), user)
+}
+
+/// Checks that callback data was generated by [`callback_data`] and that the user pushing
+/// the button is allowed to
+pub fn verify_callback_data(data: &str, user: i64) -> CallbackAuth {
+    check_callback_data(&callback_key(), data, user)
+}
+
+#[inline(always)]
+fn get_callback_limit_key(data: &str, user: i64) -> String {
+    format!("cbrl:{}:{}", user, data)
+}
+
+/// Returns true if a user is pressing a button too often. Each user gets a token bucket
+/// per button holding `callback_burst` presses refilled at one press every
+/// `callback_interval` milliseconds, so repeated presses of multi-use buttons like help
+/// navigation can't be used to hammer the bot
+pub async fn callback_throttled(data: &str, user: i64) -> Result<bool> {
+    let key = get_callback_limit_key(data, user);
+    let allowed: i64 = REDIS
+        .query(|mut q| async move {
+            let allowed: i64 = Script::new(
+                r#"
+                    local cap = tonumber(ARGV[1])
+                    local interval = tonumber(ARGV[2])
+                    local time = redis.call("time")
+                    local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+                    local state = redis.call("hmget", KEYS[1], "tokens", "ts")
+                    local tokens = tonumber(state[1]) or cap
+                    local ts = tonumber(state[2]) or now
+                    tokens = math.min(cap, tokens + (now - ts) / interval)
+                    local allowed = 0
+                    if tokens >= 1 then
+                        tokens = tokens - 1
+                        allowed = 1
+                    end
+                    redis.call("hset", KEYS[1], "tokens", tostring(tokens), "ts", now)
+                    redis.call("pexpire", KEYS[1], cap * interval)
+                    return allowed
+                "#,
+            )
+            .key(&key)
+            .arg(CONFIG.load().timing.callback_burst)
+            .arg(CONFIG.load().timing.callback_interval)
+            .invoke_async(q.deref_mut())
+            .await?;
+            Ok(allowed)
+        })
+        .await?;
+    Ok(allowed == 0)
+}
+
+/// Answer sent to telegram after a button press is handled, optionally showing the user a
+/// toast or an alert. Every button press is answered so clients don't show a loading
+/// indicator forever, callbacks only need to return a reply to show text
+#[derive(Clone, Debug, Default)]
+pub struct CallbackReply {
+    text: Option<String>,
+    alert: bool,
+}
+
+impl CallbackReply {
+    /// Shows a short notification at the top of the chat
+    pub fn toast<T: Into<String>>(text: T) -> Self {
+        Self {
+            text: Some(text.into()),
+            alert: false,
+        }
+    }
+
+    /// Shows an alert the user has to dismiss
+    pub fn alert<T: Into<String>>(text: T) -> Self {
+        Self {
+            text: Some(text.into()),
+            alert: true,
+        }
+    }
+
+    /// Answers the callback query with this reply
+    pub async fn answer(&self, callback: &CallbackQuery) -> Result<()> {
+        let mut answer = TG
+            .client
+            .build_answer_callback_query(callback.get_id())
+            .show_alert(self.alert);
+        if let Some(text) = self.text.as_ref() {
+            answer = answer.text(text);
+        }
+        answer.build().await?;
+        Ok(())
+    }
+}
+
+impl From<()> for CallbackReply {
+    fn from(_: ()) -> Self {
+        Self::default()
+    }
+}
+
+/// Return value of callbacks registered with [`OnPush::on_push_multi`]. Either a bool that
+/// is true once the callback should be removed, or that bool with a reply to show the user
+pub trait MultiReply: Send + 'static {
+    fn into_reply(self) -> (bool, CallbackReply);
+}
+
+impl MultiReply for bool {
+    fn into_reply(self) -> (bool, CallbackReply) {
+        (self, CallbackReply::default())
+    }
+}
+
+impl MultiReply for (bool, CallbackReply) {
+    fn into_reply(self) -> (bool, CallbackReply) {
+        self
+    }
+}
+
+/// Serializable description of what a button does. Unlike callbacks registered with
+/// [`OnPush`], which only live in memory, actions stored with [`persist_action`] keep
+/// working after the bot restarts
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub enum ButtonAction {
+    /// Moves a stored conversation, like the help menu, to a new state
+    Transition {
+        conversation: Uuid,
+        state: Uuid,
+        row_limit: usize,
+    },
+    /// Unmutes the user pushing the button in the chat the button was sent in
+    UnmuteMe,
+    /// A choice on a text captcha for a chat
+    Captcha { chat: i64, correct: bool },
+    /// A new member muted by welcome mute confirming they are human
+    HumanCheck { user: i64 },
+    /// An admin's decision on a join request posted for review
+    JoinRequest { chat: i64, user: i64, approve: bool },
+    /// A banned user starting an appeal from the bot's dm
+    Appeal { target: AppealTarget },
+    /// An admin's decision on a posted ban appeal
+    AppealDecision {
+        target: AppealTarget,
+        user: i64,
+        approve: bool,
+    },
+    /// A button on a chat's /settings panel
+    Settings { chat: i64, page: SettingsPage },
+    /// An admin vetoing a /voteban
+    VoteBanVeto { poll: String },
+    /// A member entering a /giveaway
+    GiveawayEnter { id: i64 },
+    /// A choice on the setup posted when the bot is added to a chat
+    Setup { chat: i64, choice: SetupChoice },
+}
+
+impl ButtonAction {
+    /// Handles a push of a button with this action. Returns true if the action is done
+    /// and should be removed, along with the reply to answer the push with. Boxed since
+    /// stored transitions can run the actions of the states they enter
+    pub(crate) fn run(
+        self,
+        callback: CallbackQuery,
+    ) -> BoxFuture<'static, Result<(bool, CallbackReply)>> {
+        async move {
+            let ctx = StaticContext::get_context(UpdateExt::CallbackQuery(callback.clone()))
+                .await?
+                .yoke();
+            match self {
+                Self::Transition {
+                    conversation,
+                    state,
+                    row_limit,
+                } => {
+                    let reply =
+                        transition_stored(conversation, state, row_limit, &callback).await?;
+                    Ok((false, reply))
+                }
+                Self::UnmuteMe => {
+                    ctx.authorize_user(callback.get_from().get_id(), ctx.try_get()?.chat)
+                        .await?;
+                    Ok((true, CallbackReply::default()))
+                }
+                Self::Captcha {
+                    chat,
+                    correct: true,
+                } => {
+                    captcha_correct(&ctx, &callback, chat).await?;
+                    Ok((true, CallbackReply::default()))
+                }
+                Self::Captcha {
+                    chat,
+                    correct: false,
+                } => captcha_incorrect(&ctx, &callback, chat).await,
+                Self::HumanCheck { user } => human_pushed(&ctx, &callback, user).await,
+                Self::JoinRequest {
+                    chat,
+                    user,
+                    approve,
+                } => join_request_pushed(&callback, chat, user, approve).await,
+                Self::Appeal { target } => appeal_pushed(&callback, target).await,
+                Self::AppealDecision {
+                    target,
+                    user,
+                    approve,
+                } => appeal_decision_pushed(&callback, target, user, approve).await,
+                Self::Settings { chat, page } => settings_pushed(&callback, chat, page).await,
+                Self::VoteBanVeto { poll } => veto_pushed(&callback, &poll).await,
+                Self::GiveawayEnter { id } => enter_pushed(&callback, id).await,
+                Self::Setup { chat, choice } => setup_pushed(&callback, chat, choice).await,
+            }
+        }
+        .boxed()
+    }
+}
+
+#[inline(always)]
+fn get_button_action_key(data: &str) -> String {
+    format!("bact:{}", data)
+}
+
+/// Stores an action for a callback button so pushing it is handled even if the bot
+/// restarts first. Actions expire along with other cached data
+pub async fn persist_action(button: &InlineKeyboardButton, action: &ButtonAction) -> Result<()> {
+    persist_action_for(button, action, CONFIG.load().timing.cache_timeout).await
+}
+
+/// Stores an action for a callback button like [`persist_action`], for buttons that need to
+/// keep working for `expire` seconds
+pub async fn persist_action_for(
+    button: &InlineKeyboardButton,
+    action: &ButtonAction,
+    expire: i64,
+) -> Result<()> {
+    if let Some(data) = button.get_callback_data() {
+        let key = get_button_action_key(data);
+        let action = RedisStr::new(action)?;
+        REDIS
+            .pipe(|q| q.set(&key, action).expire(&key, expire))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Runs the stored action for a button that has no callback registered in memory
+pub async fn run_button_action(data: &str, callback: CallbackQuery) -> Result<CallbackReply> {
+    let key = get_button_action_key(data);
+    let action: Option<RedisStr> = REDIS.sq(|q| q.get(&key)).await?;
+    if let Some(action) = action {
+        let action: ButtonAction = action.get()?;
+        let (done, reply) = action.run(callback).await?;
+        if done {
+            REDIS.sq(|q| q.del(&key)).await?;
+        }
+        Ok(reply)
+    } else {
+        Ok(CallbackReply::default())
+    }
+}
+
 /// Builds an inline keyboard with buttons for attaching to a message
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct InlineKeyboardBuilder(Vec<Vec<button::Model>>);
@@ -148,34 +505,39 @@ impl InlineKeyboardBuilder {
 }
 
 /// Extension trait for registing callback on buttons.
-/// Beware, this calls functions in static contexts
+/// Beware, this calls functions in static contexts. Callbacks don't need to answer the
+/// callback query, it is answered with the [`CallbackReply`] they return
 pub trait OnPush {
     /// Register a button callback that is only called once, then unregistered
-    fn on_push<F, Fut>(&self, func: F)
+    fn on_push<F, Fut, R>(&self, func: F)
     where
         F: FnOnce(CallbackQuery) -> Fut + Sync + Send + 'static,
-        Fut: Future<Output = Result<()>> + Send + 'static;
+        Fut: Future<Output = Result<R>> + Send + 'static,
+        R: Into<CallbackReply> + Send + 'static;
 
-    /// Register a button callback that is called until it returns false
-    fn on_push_multi<F, Fut>(&self, func: F)
+    /// Register a button callback that is called until it returns true
+    fn on_push_multi<F, Fut, R>(&self, func: F)
     where
         F: Fn(CallbackQuery) -> Fut + Sync + Send + 'static,
-        Fut: Future<Output = Result<bool>> + Send + 'static;
+        Fut: Future<Output = Result<R>> + Send + 'static,
+        R: MultiReply;
 }
 
 impl OnPush for InlineKeyboardButton {
-    fn on_push<'a, F, Fut>(&self, func: F)
+    fn on_push<'a, F, Fut, R>(&self, func: F)
     where
         F: FnOnce(CallbackQuery) -> Fut + Sync + Send + 'static,
-        Fut: Future<Output = Result<()>> + Send + 'static,
+        Fut: Future<Output = Result<R>> + Send + 'static,
+        R: Into<CallbackReply> + Send + 'static,
     {
         TG.register_button(self, func);
     }
 
-    fn on_push_multi<'a, F, Fut>(&self, func: F)
+    fn on_push_multi<'a, F, Fut, R>(&self, func: F)
     where
         F: Fn(CallbackQuery) -> Fut + Sync + Send + 'static,
-        Fut: Future<Output = Result<bool>> + Send + 'static,
+        Fut: Future<Output = Result<R>> + Send + 'static,
+        R: MultiReply,
     {
         TG.register_button_multi(self, func);
     }
@@ -185,6 +547,33 @@ impl OnPush for InlineKeyboardButton {
 mod test {
 
     use super::*;
+
+    #[test]
+    fn callback_signing() {
+        let key = b"test key";
+        let data = make_callback_data(key, None);
+        assert!(data.len() <= 64);
+        assert_eq!(check_callback_data(key, &data, 1), CallbackAuth::Valid);
+        assert_eq!(check_callback_data(key, &data, 2), CallbackAuth::Valid);
+        assert_eq!(
+            check_callback_data(b"other key", &data, 1),
+            CallbackAuth::Invalid
+        );
+
+        let data = make_callback_data(key, Some(1));
+        assert!(data.len() <= 64);
+        assert_eq!(check_callback_data(key, &data, 1), CallbackAuth::Valid);
+        assert_eq!(check_callback_data(key, &data, 2), CallbackAuth::WrongUser);
+
+        let forged = data.replacen('u', "a", 1);
+        assert_eq!(check_callback_data(key, &forged, 2), CallbackAuth::Invalid);
+        assert_eq!(
+            check_callback_data(key, &Uuid::new_v4().to_string(), 1),
+            CallbackAuth::Invalid
+        );
+        assert_eq!(check_callback_data(key, "", 1), CallbackAuth::Invalid);
+        assert_eq!(check_callback_data(key, "ü", 1), CallbackAuth::Invalid);
+    }
     #[test]
     fn button_add() {
         let mut builder = InlineKeyboardBuilder::default();
@@ -0,0 +1,185 @@
+//! Moving a chat's data when telegram upgrades a group to a supergroup. The supergroup gets a
+//! new id, and the first message in it has `migrate_from_chat_id` set to the old one. Every
+//! row belonging to the old id is moved to the new id in one transaction, and cached data for
+//! both ids is dropped from redis so it is loaded again from the moved rows. Rows the bot
+//! already created for the new id are replaced, the group's settings win.
+//!
+//! Only the message in the supergroup is handled. The `migrate_to_chat_id` message in the old
+//! group is ignored so the move doesn't run twice
+
+use futures::FutureExt;
+use redis::AsyncCommands;
+use sea_orm::sea_query::{Alias, Expr, Query};
+use sea_orm::{ConnectionTrait, TransactionTrait};
+
+use crate::statics::{DB, REDIS};
+use crate::util::error::{BotError, Result};
+
+use super::admin_helpers::invalidate_warn_caches;
+
+/// Every table with rows belonging to a chat, along with the column holding the chat id
+const CHAT_TABLES: &[(&str, &str)] = &[
+    ("actions", "chat_id"),
+    ("admin_notes", "chat_id"),
+    ("antispam", "chat"),
+    ("approvals", "chat"),
+    ("birthday_settings", "chat_id"),
+    ("birthdays", "chat_id"),
+    ("blocklists", "chat"),
+    ("button_domains", "chat_id"),
+    ("captcha", "chat"),
+    ("captcha_auth", "chat"),
+    ("chat_boosts", "chat_id"),
+    ("chat_members", "chat_id"),
+    ("chat_strings", "chat_id"),
+    ("chats", "chat_id"),
+    ("command_aliases", "chat_id"),
+    ("default_locks", "chat"),
+    ("dialogs", "chat_id"),
+    ("filters", "chat"),
+    ("giveaways", "chat_id"),
+    ("link_domains", "chat_id"),
+    ("locks", "chat"),
+    ("modlog", "chat_id"),
+    ("network_chats", "chat_id"),
+    ("network_links", "chat_id"),
+    ("network_links", "other_chat"),
+    ("recurring_messages", "chat_id"),
+    ("notes", "chat"),
+    ("nsfw_filter", "chat"),
+    ("rules", "chat_id"),
+    ("shames", "chat_id"),
+    ("spam_filter", "chat"),
+    ("stickers", "chat_id"),
+    ("tags", "chat_id"),
+    ("taint", "chat"),
+    ("taint_chat", "chat"),
+    ("topic_locks", "chat"),
+    ("username_history", "chat_id"),
+    ("warns", "chat_id"),
+    ("welcome", "chat"),
+    ("welcome_messages", "chat"),
+    ("welcome_mute", "chat"),
+];
+
+/// Moves every row from the old chat id to the new one
+async fn migrate_rows(from: i64, to: i64) -> Result<()> {
+    DB.transaction::<_, (), BotError>(|tx| {
+        async move {
+            for (table, column) in CHAT_TABLES {
+                let delete = Query::delete()
+                    .from_table(Alias::new(*table))
+                    .and_where(Expr::col(Alias::new(*column)).eq(to))
+                    .to_owned();
+                tx.execute(tx.get_database_backend().build(&delete)).await?;
+
+                let update = Query::update()
+                    .table(Alias::new(*table))
+                    .value(Alias::new(*column), to)
+                    .and_where(Expr::col(Alias::new(*column)).eq(from))
+                    .to_owned();
+                tx.execute(tx.get_database_backend().build(&update)).await?;
+            }
+            Ok(())
+        }
+        .boxed()
+    })
+    .await?;
+    Ok(())
+}
+
+/// Deletes every redis key ending in the chat id or with it between colons
+async fn clear_cached_chat(chat: i64) -> Result<()> {
+    for pattern in [format!("*:{}", chat), format!("*:{}:*", chat)] {
+        let keys: Vec<String> = REDIS
+            .query(|mut q| async move {
+                let mut keys = Vec::new();
+                let mut iter = q.scan_match::<_, String>(&pattern).await?;
+                while let Some(key) = iter.next_item().await {
+                    keys.push(key);
+                }
+                Ok(keys)
+            })
+            .await?;
+        if !keys.is_empty() {
+            REDIS.sq(|q| q.del(&keys)).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves a group's settings, notes, filters, warns, and everything else to the supergroup it
+/// was upgraded to
+pub async fn migrate_chat(from: i64, to: i64) -> Result<()> {
+    log::info!("migrating chat {} to {}", from, to);
+    migrate_rows(from, to).await?;
+    clear_cached_chat(from).await?;
+    clear_cached_chat(to).await?;
+    for chat in [from, to] {
+        invalidate_warn_caches(chat).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+    use std::path::Path;
+
+    use super::*;
+
+    /// Tables without a chat id of their own. Rows in most of them hang off a row in a chat
+    /// table and move with it, the rest are global or only live as long as a conversation
+    const OTHER_TABLES: &[&str] = &[
+        "blocklist_triggers",
+        "button",
+        "conversation_states",
+        "conversation_transitions",
+        "conversations",
+        "entitylist",
+        "fbans",
+        "fedadmins",
+        "federations",
+        "gbans",
+        "giveaway_entries",
+        "message_entity",
+        "module_schemas",
+        "networks",
+        "triggers",
+        "users",
+    ];
+
+    fn entity_tables(dir: &Path, tables: &mut BTreeSet<String>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                entity_tables(&path, tables);
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                let source = std::fs::read_to_string(&path).unwrap();
+                for part in source.split("table_name = \"").skip(1) {
+                    tables.insert(part.split('"').next().unwrap().to_owned());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_table_is_listed() {
+        let mut tables = BTreeSet::new();
+        entity_tables(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("src"),
+            &mut tables,
+        );
+        for table in &tables {
+            assert!(
+                CHAT_TABLES.iter().any(|(name, _)| name == table)
+                    || OTHER_TABLES.contains(&table.as_str()),
+                "table {} is missing from CHAT_TABLES or OTHER_TABLES",
+                table
+            );
+        }
+        for (table, _) in CHAT_TABLES {
+            assert!(tables.contains(*table), "no entity for table {}", table);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Keeping chats tidy by deleting commands. Chats with clean commands on have commands from
+//! admins deleted once every module has handled them, and can choose their own delay before
+//! the bot's confirmations are deleted. Noisy notices like permission errors and admin cache
+//! refreshes are deleted in those chats too. Deletions after a delay go through the scheduler
+//! so they still happen if the bot restarts in the meantime
+
+use botapi::gen_types::{Chat, Message};
+use chrono::Duration;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::EntityTrait;
+
+use crate::persist::core::dialogs;
+use crate::statics::{CONFIG, DB};
+use crate::util::error::Result;
+use crate::util::string::Speak;
+
+use super::command::{Cmd, Context};
+use super::dialog::{dialog_scope, get_dialog, get_dialog_by_id};
+use super::permissions::IsAdmin;
+use super::scheduler::delete_message_later;
+
+/// Turns deleting admin commands on or off, optionally with the number of seconds before
+/// confirmations are deleted
+pub async fn set_clean_commands(
+    chat: &Chat,
+    enabled: bool,
+    confirm_time: Option<i64>,
+) -> Result<()> {
+    let mut model = dialogs::Model::from_chat(chat).await?;
+    model.clean_commands = Set(enabled);
+    model.clean_confirm_time = Set(confirm_time);
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_columns([
+                    dialogs::Column::CleanCommands,
+                    dialogs::Column::CleanConfirmTime,
+                ])
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat.get_id()).invalidate().await?;
+    Ok(())
+}
+
+/// Gets the delay before a confirmation in a chat is deleted, None to keep it
+async fn confirm_time(chat: &Chat) -> Result<Option<Duration>> {
+    let time = match get_dialog(chat).await? {
+        Some(dialogs::Model {
+            clean_commands: true,
+            clean_confirm_time: Some(time),
+            ..
+        }) => Some(time),
+        _ => CONFIG.load().timing.confirmation_delete_time,
+    };
+    Ok(time.and_then(Duration::try_seconds))
+}
+
+/// Gets how long noisy notices stay in a chat, None to keep them. Only chats cleaning
+/// commands delete them
+pub async fn notice_ttl(chat: i64) -> Result<Option<Duration>> {
+    let time = match get_dialog_by_id(chat).await? {
+        Some(dialog) if dialog.clean_commands => Some(
+            dialog
+                .clean_confirm_time
+                .unwrap_or(CONFIG.load().timing.notice_delete_time),
+        ),
+        _ => None,
+    };
+    Ok(time.and_then(Duration::try_seconds))
+}
+
+impl Context {
+    /// Replies with a noisy notice, deleted after a while if the chat cleans commands
+    pub async fn reply_notice<T>(&self, message: T) -> Result<Option<Message>>
+    where
+        T: AsRef<str> + Send + Sync,
+    {
+        let chat = self.message()?.get_chat().get_id();
+        match notice_ttl(chat).await? {
+            Some(ttl) => self.reply_ephemeral(message, ttl).await,
+            None => self.reply(message).await,
+        }
+    }
+
+    /// Schedules deleting a confirmation using the chat's delay, or the config's if the
+    /// chat doesn't set one
+    pub(crate) async fn clean_context_confirmation(&self, message: &Option<Message>) {
+        let Some(message) = message else {
+            return;
+        };
+        let chat = message.get_chat().get_id();
+        let res = match confirm_time(message.get_chat()).await {
+            Ok(Some(time)) => delete_message_later(chat, message.get_message_id(), time).await,
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = res {
+            log::warn!("failed to schedule deleting confirmation: {}", err);
+            err.record_stats();
+        }
+    }
+
+    async fn clean_command(&self) -> Result<()> {
+        let Some(&Cmd { message, .. }) = self.cmd() else {
+            return Ok(());
+        };
+        if self.is_dm() {
+            return Ok(());
+        }
+        let chat = message.get_chat();
+        if !get_dialog(chat)
+            .await?
+            .map(|d| d.clean_commands)
+            .unwrap_or(false)
+        {
+            return Ok(());
+        }
+        let Some(user) = message.get_from() else {
+            return Ok(());
+        };
+        if user.is_admin(chat).await? {
+            message.delete().await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the current command if it came from an admin in a chat cleaning commands.
+    /// Runs after every module has handled the command
+    pub async fn handle_clean_command(&self) {
+        if let Err(err) = self.clean_command().await {
+            log::debug!("failed to clean command: {}", err);
+            err.record_stats();
+        }
+    }
+}
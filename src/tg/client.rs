@@ -7,48 +7,102 @@ use std::collections::HashMap;
 
 use super::{
     admin_helpers::is_dm,
-    button::InlineKeyboardBuilder,
-    command::{Context, TextArgs},
+    bot_commands::register_commands,
+    bots::{all_bots, with_bot},
+    business::{handle_business_connection, BUSINESS_MODULES},
+    button::{
+        callback_throttled, run_button_action, verify_callback_data, CallbackAuth, CallbackReply,
+        InlineKeyboardBuilder, MultiReply,
+    },
+    command::{Cmd, Context, OwnedTextArg, OwnedTextArgs, StaticContext, TextArgs},
+    command_replies::rerun_edited_command,
+    dedup::claim_update,
+    deeplink::DeepLink,
     dialog::{dialog_from_update, Conversation, ConversationState},
+    entity_gc,
+    middleware::{report_handler_error, MiddlewareChain, Outcome},
     permissions::*,
+    polling::long_poll,
+    scheduler,
     user::RecordUser,
+    warn_decay,
+    webhook::serve_webhook,
+    write_behind,
 };
 use crate::{
-    metadata::{markdownify, Metadata},
-    modules,
+    metadata::{markdownify, Metadata, ModuleRegistry},
+    persist::{
+        local,
+        metrics::{count_metric, Metric, START_TIME},
+    },
     tg::{
         admin_helpers::IntoChatUser,
-        command::{post_deep_link, PopSlice},
-        markdown::MarkupBuilder,
+        command::PopSlice,
+        markdown::{Escape, MarkupBuilder},
     },
     util::{
         callback::{MultiCallback, MultiCb, SingleCallback, SingleCb},
         error::Fail,
+        error_codes::UNKNOWN_USER,
+        error_sink::ErrorContext,
         string::{should_ignore_chat, Speak},
+        trace::with_trace,
     },
 };
 use crate::{
     statics::{CONFIG, ME, TG},
     util::error::Result,
-    util::string::get_chat_lang,
+    util::string::{get_chat_lang, Lang},
 };
 use botapi::{
-    bot::{ApiError, Bot, BotBuilder},
-    ext::{BotUrl, LongPoller, Webhook},
+    bot::{Bot, BotBuilder},
     gen_types::{
         CallbackQuery, InlineKeyboardButton, InlineKeyboardButtonBuilder,
-        LinkPreviewOptionsBuilder, Message, ReplyParametersBuilder, UpdateExt,
+        LinkPreviewOptionsBuilder, MaybeInaccessibleMessage, Message, ReplyParametersBuilder,
+        Update, UpdateExt, User,
     },
 };
 use convert_case::Case;
 use convert_case::Casing;
 use dashmap::DashMap;
-use futures::{future::BoxFuture, Future, StreamExt};
+use futures::{
+    future::{self, BoxFuture},
+    Future, FutureExt,
+};
 use macros::{lang_fmt, message_fmt};
+use once_cell::sync::OnceCell;
 use std::sync::Arc;
+use std::time::Instant;
+use tokio::task::JoinHandle;
+use tracing::Instrument;
 
 static INVALID: &str = "invalid";
 
+/// Gets the language of the chat a button was pressed in
+async fn callback_lang(callback: &CallbackQuery) -> Result<Lang> {
+    if let Some(MaybeInaccessibleMessage::Message(message)) = callback.get_message() {
+        get_chat_lang(message.get_chat().get_id()).await
+    } else {
+        Ok(Lang::En)
+    }
+}
+
+/// Tells a user pushing a button bound to someone else that it isn't theirs
+async fn reject_callback(callback: &CallbackQuery) -> Result<()> {
+    let lang = callback_lang(callback).await?;
+    CallbackReply::alert(lang_fmt!(lang, "callbackwronguser"))
+        .answer(callback)
+        .await
+}
+
+/// Tells a user pressing a button too often to slow down
+async fn throttle_callback(callback: &CallbackQuery) -> Result<()> {
+    let lang = callback_lang(callback).await?;
+    CallbackReply::toast(lang_fmt!(lang, "callbackslowdown"))
+        .answer(callback)
+        .await
+}
+
 /// List of module info for populating bot help
 #[derive(Debug)]
 pub struct MetadataCollection(HashMap<String, Arc<Metadata>>);
@@ -61,7 +115,23 @@ impl MetadataCollection {
                 let helps = v
                     .commands
                     .iter()
-                    .map(|(c, h)| format!("/{}: {}", c, markdownify(h)))
+                    .map(|(c, h)| {
+                        let help = match v.usage.get(c).and_then(|u| Lang::En.get_string(u)) {
+                            Some(usage) => {
+                                format!("{} {}", markdownify(h).trim_end(), usage.escape(false))
+                            }
+                            None => markdownify(h),
+                        };
+                        match v.perms.get(c) {
+                            Some(perms) if !perms.is_empty() => format!(
+                                "/{}: {} [_requires {}]",
+                                c,
+                                help,
+                                perms.iter().map(|p| p.name).collect::<Vec<_>>().join(", ")
+                            ),
+                            _ => format!("/{}: {}", c, help),
+                        }
+                    })
                     .collect::<Vec<String>>()
                     .join("\n");
 
@@ -86,10 +156,9 @@ impl MetadataCollection {
             "help".to_owned(),
             lang_fmt!(lang, "welcome", me.get_first_name()),
             message.get_chat().get_id(),
-            message
-                .get_from()
-                .map(|u| u.get_id())
-                .ok_or_else(|| message.fail_err("User does not exist"))?,
+            message.get_from().map(|u| u.get_id()).ok_or_else(|| {
+                message.fail_err_code(UNKNOWN_USER, lang_fmt!(lang, "errunknownuser"))
+            })?,
             "button",
         )?;
 
@@ -198,9 +267,15 @@ impl std::fmt::Debug for UpdateHandler {
 pub struct TgClient {
     pub client: Bot,
     pub modules: Arc<MetadataCollection>,
+    /// modules handling updates for this bot
+    pub registry: Arc<ModuleRegistry>,
+    /// checks run around the modules for every update
+    pub middleware: Arc<MiddlewareChain>,
     pub token: String,
-    pub button_events: Arc<DashMap<String, SingleCb<CallbackQuery, Result<()>>>>,
-    pub button_repeat: Arc<DashMap<String, MultiCb<CallbackQuery, Result<bool>>>>,
+    pub button_events: Arc<DashMap<String, SingleCb<CallbackQuery, Result<CallbackReply>>>>,
+    pub button_repeat: Arc<DashMap<String, MultiCb<CallbackQuery, Result<(bool, CallbackReply)>>>>,
+    /// this bot's own user, fetched from telegram on startup
+    pub me: OnceCell<User>,
     handler: UpdateHandler,
 }
 
@@ -283,7 +358,8 @@ pub(crate) async fn show_help<'a>(
                 .build()
                 .await?;
         } else {
-            let url = post_deep_link(args_raw, help_key).await?;
+            let page = args_raw.args.iter().map(|a| a.get_text().to_owned());
+            let url = DeepLink::Help(page.collect()).url().await?;
             let mut button = InlineKeyboardBuilder::default();
 
             button.button(
@@ -304,35 +380,88 @@ pub(crate) async fn show_help<'a>(
     Ok(true)
 }
 
-#[inline(always)]
-pub fn help_key(key: &str) -> String {
-    format!("gethelp:{}", key)
+/// Handles the help and start commands. Returns true if the update was one of them and
+/// shouldn't be passed on to modules
+pub(crate) async fn handle_builtin_command(
+    ctx: &Context,
+    helps: Arc<MetadataCollection>,
+) -> Result<bool> {
+    let Some(&Cmd {
+        cmd,
+        ref args,
+        message,
+        lang,
+        ..
+    }) = ctx.cmd()
+    else {
+        return Ok(false);
+    };
+    match cmd {
+        "help" => show_help(ctx, message, helps, args).await,
+        "start" => match args.args.first().map(|a| a.get_text()) {
+            Some(v) => {
+                if let Some(DeepLink::Help(page)) = ctx.deep_link().await? {
+                    let deep = OwnedTextArgs {
+                        text: page.join(" "),
+                        args: page.iter().map(|v| OwnedTextArg::Arg(v.clone())).collect(),
+                    };
+                    show_help(ctx, message, helps, &deep.get_ref()).await?;
+                    Ok(true)
+                } else if v.starts_with("help") {
+                    show_help(ctx, message, helps, args).await?;
+                    Ok(true)
+                } else {
+                    Ok(false)
+                }
+            }
+            None => {
+                log::info!("start with lang {:?}", lang);
+                message.reply(lang_fmt!(lang, "startcmd")).await?;
+                Ok(true)
+            }
+        },
+        _ => scheduler::handle_deferred_command(ctx).await,
+    }
 }
 
 impl TgClient {
     /// Register a button callback to be called when the corresponding callback button sends an update
     /// This callback will only fire once and be removed afterwards
-    pub(crate) fn register_button<F, Fut>(&self, button: &InlineKeyboardButton, func: F)
+    pub(crate) fn register_button<F, Fut, R>(&self, button: &InlineKeyboardButton, func: F)
     where
         F: FnOnce(CallbackQuery) -> Fut + Sync + Send + 'static,
-        Fut: Future<Output = Result<()>> + Send + 'static,
+        Fut: Future<Output = Result<R>> + Send + 'static,
+        R: Into<CallbackReply> + Send + 'static,
     {
         if let Some(data) = button.get_callback_data() {
-            self.button_events
-                .insert(data.to_owned(), SingleCb::new(func));
+            self.button_events.insert(
+                data.to_owned(),
+                SingleCb::new(move |cb| func(cb).map(|r| r.map(|r| r.into()))),
+            );
+        }
+    }
+
+    /// Removes the callbacks registered for a button, if it has any
+    pub(crate) fn unregister_button(&self, button: &InlineKeyboardButton) {
+        if let Some(data) = button.get_callback_data() {
+            self.button_events.remove(data);
+            self.button_repeat.remove(data);
         }
     }
 
     /// Register a button callback to be called when the corresponding callback button sends an update
-    /// This callback will be called any number of times until the callback returns false
-    pub(crate) fn register_button_multi<F, Fut>(&self, button: &InlineKeyboardButton, func: F)
+    /// This callback will be called any number of times until the callback returns true
+    pub(crate) fn register_button_multi<F, Fut, R>(&self, button: &InlineKeyboardButton, func: F)
     where
         F: Fn(CallbackQuery) -> Fut + Sync + Send + 'static,
-        Fut: Future<Output = Result<bool>> + Send + 'static,
+        Fut: Future<Output = Result<R>> + Send + 'static,
+        R: MultiReply,
     {
         if let Some(data) = button.get_callback_data() {
-            self.button_repeat
-                .insert(data.to_owned(), MultiCb::new(func));
+            self.button_repeat.insert(
+                data.to_owned(),
+                MultiCb::new(move |cb| func(cb).map(|r| r.map(|r| r.into_reply()))),
+            );
         }
     }
 
@@ -341,7 +470,8 @@ impl TgClient {
     where
         T: Into<String>,
     {
-        let metadata = modules::get_metadata();
+        let registry = ModuleRegistry::builtin();
+        let metadata = registry.metadata();
         let metadata = MetadataCollection(
             metadata
                 .into_iter()
@@ -356,8 +486,11 @@ impl TgClient {
                 .build(),
             token,
             modules: Arc::new(metadata),
+            middleware: Arc::new(MiddlewareChain::builtin(&registry)),
+            registry: Arc::new(registry),
             button_events: Arc::new(DashMap::new()),
             button_repeat: Arc::new(DashMap::new()),
+            me: OnceCell::new(),
             handler: UpdateHandler(None),
         }
     }
@@ -373,6 +506,7 @@ impl TgClient {
                 .map(|v| (v.name.clone(), Arc::new(v)))
                 .collect(),
         );
+        let registry = ModuleRegistry::builtin();
         let token = token.into();
         Self {
             client: BotBuilder::new(token.clone())
@@ -381,41 +515,117 @@ impl TgClient {
                 .build(),
             token,
             modules: Arc::new(metadata),
+            middleware: Arc::new(MiddlewareChain::builtin(&registry)),
+            registry: Arc::new(registry),
             button_events: Arc::new(DashMap::new()),
             button_repeat: Arc::new(DashMap::new()),
+            me: OnceCell::new(),
             handler,
         }
     }
 
-    /// Processes a single update from telegram
-    async fn handle_update(&self, update: std::result::Result<UpdateExt, ApiError>) {
-        let modules = Arc::clone(&self.modules);
+    /// Gets this bot's user id, which is the part of the token before the colon
+    pub fn bot_id(&self) -> i64 {
+        self.token
+            .split(':')
+            .next()
+            .and_then(|id| id.parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// Fetches this bot's own user from telegram
+    pub(crate) async fn fetch_me(&self) -> Result<()> {
+        let me = self.client.get_me().await?;
+        self.me.set(me).ok();
+        Ok(())
+    }
+
+    /// Processes a single update from telegram on a new task, returning the task so callers
+    /// can wait for the update to be handled. Updates already handled are dropped
+    pub(crate) fn spawn_update(&'static self, update: Update) -> JoinHandle<()> {
         let callbacks = Arc::clone(&self.button_events);
         let repeats = Arc::clone(&self.button_repeat);
-        let custom_handler = self.handler.clone();
-        tokio::spawn(async move {
-            match update {
-                Ok(UpdateExt::CallbackQuery(callbackquery)) => {
+        let update_id = update.get_update_id();
+        let trace = with_trace(update_id, self.bot_id(), async move {
+            match claim_update(self.bot_id(), update_id).await {
+                Ok(true) => (),
+                Ok(false) => {
+                    tracing::info!("dropping duplicate update");
+                    if let Err(err) = count_metric(Metric::DuplicateUpdate).await {
+                        log::warn!("failed to count duplicate update: {}", err);
+                        err.record_stats();
+                    }
+                    return;
+                }
+                Err(err) => {
+                    log::warn!("failed to check for duplicate update: {}", err);
+                    err.record_stats();
+                }
+            }
+            if let Err(err) = count_metric(Metric::Update).await {
+                log::warn!("failed to count update: {}", err);
+                err.record_stats();
+            }
+            match UpdateExt::from(update) {
+                UpdateExt::CallbackQuery(callbackquery) => {
                     if let Some(data) = callbackquery.get_data() {
                         let data: String = data.to_owned();
-                        if let Some(cb) = callbacks.remove(&data) {
-                            if let Err(err) = cb.1.cb(callbackquery.clone()).await {
-                                log::warn!("button handler err {}", err);
+                        match verify_callback_data(&data, callbackquery.get_from().get_id()) {
+                            CallbackAuth::Valid => (),
+                            CallbackAuth::WrongUser => {
+                                if let Err(err) = reject_callback(&callbackquery).await {
+                                    log::warn!("failed to reject callback {}", err);
+                                    err.record_stats();
+                                }
+                                return;
+                            }
+                            CallbackAuth::Invalid => {
+                                log::warn!(
+                                    "dropping forged callback data from {}",
+                                    callbackquery.get_from().get_id()
+                                );
+                                return;
+                            }
+                        }
+                        match callback_throttled(&data, callbackquery.get_from().get_id()).await {
+                            Ok(false) => (),
+                            Ok(true) => {
+                                if let Err(err) = throttle_callback(&callbackquery).await {
+                                    log::warn!("failed to throttle callback {}", err);
+                                    err.record_stats();
+                                }
+                                return;
+                            }
+                            Err(err) => {
+                                log::warn!("failed to check callback ratelimit {}", err);
                                 err.record_stats();
                             }
                         }
+                        let mut reply = None;
+                        if let Some(cb) = callbacks.remove(&data) {
+                            match cb.1.cb(callbackquery.clone()).await {
+                                Ok(v) => reply = Some(v),
+                                Err(err) => {
+                                    log::warn!("button handler err {}", err);
+                                    err.record_stats();
+                                    reply = Some(CallbackReply::default());
+                                }
+                            }
+                        }
 
                         let remove = if let Some(cb) = repeats.get(&data) {
-                            match cb.cb(callbackquery).await {
+                            match cb.cb(callbackquery.clone()).await {
                                 Err(err) => {
                                     log::warn!("failed multi handler {}", err);
                                     err.record_stats();
+                                    reply = Some(CallbackReply::default());
                                     true
                                 }
-                                Ok(v) => {
+                                Ok((v, r)) => {
                                     if v {
                                         log::info!("removing multi callback");
                                     }
+                                    reply = Some(r);
                                     v
                                 }
                             }
@@ -426,10 +636,29 @@ impl TgClient {
                         if remove {
                             repeats.remove(&data);
                         }
+
+                        // buttons created before a restart only exist in redis
+                        let reply = match reply {
+                            Some(reply) => reply,
+                            None => match run_button_action(&data, callbackquery.clone()).await {
+                                Ok(reply) => reply,
+                                Err(err) => {
+                                    log::warn!("failed persisted button handler {}", err);
+                                    err.record_stats();
+                                    CallbackReply::default()
+                                }
+                            },
+                        };
+
+                        // always answer so the client stops showing a loading indicator
+                        if let Err(err) = reply.answer(&callbackquery).await {
+                            log::warn!("failed to answer callback {}", err);
+                            err.record_stats();
+                        }
                     }
                 }
-                Ok(update) => {
-                    if let Err(err) = update_self_admin(&update).await {
+                update => {
+                    if let Err(err) = update_cached_admins(&update).await {
                         log::warn!("failed to update admin change: {}", err);
                         err.record_stats();
                     }
@@ -444,24 +673,76 @@ impl TgClient {
                         err.record_stats();
                     }
 
-                    if let Err(err) =
-                        crate::modules::process_updates(update, modules, custom_handler).await
-                    {
-                        log::warn!("process updates error: {}", err);
+                    if let Err(err) = self.process_update(update).await {
+                        tracing::warn!(error = %err, "process updates error");
                         err.record_stats()
                     }
                 }
-                Err(err) => {
-                    log::warn!("failed to process update: {}", err);
-                }
             }
         });
+        tokio::spawn(with_bot(self, trace))
+    }
+
+    /// Runs an update through the middleware chain and every module in the registry. Modules
+    /// and the custom handler only see updates no pre-handler stopped
+    async fn process_update(&self, update: UpdateExt) -> Result<()> {
+        let (update, reply_to_edit) = rerun_edited_command(update).await;
+        let ctx = StaticContext::get_context(update).await?;
+        ctx.set_reply_to_edit(reply_to_edit);
+        let ctx = ctx.yoke();
+        let chat = ctx.chat().map(|chat| chat.get_id());
+        if let Some(chat) = chat {
+            tracing::Span::current().record("chat", chat);
+        }
+        ErrorContext::from_update(ctx.update(), chat)
+            .scope(self.run_pipeline(&ctx))
+            .await;
+        Ok(())
+    }
+
+    async fn run_pipeline(&self, ctx: &Context) {
+        let start = Instant::now();
+        let stopped_by = self.middleware.before(ctx).await;
+        if stopped_by.is_none() {
+            let business = ctx.is_business();
+            // the owner's own messages in their business chats aren't answered
+            let from_owner = ctx.from_business_owner();
+            if !business {
+                self.handler.handle_update(ctx).await;
+            }
+            if let Err(err) = handle_business_connection(ctx.update()).await {
+                report_handler_error("Business", err).await;
+            }
+            for module in self.registry.iter() {
+                // customers in business chats only get the modules that make sense there
+                if business
+                    && (from_owner || !BUSINESS_MODULES.contains(&module.metadata().name.as_str()))
+                {
+                    continue;
+                }
+                let span = tracing::debug_span!("module", name = %module.metadata().name);
+                if let Err(err) = module.handle_update(ctx).instrument(span).await {
+                    report_handler_error(&module.metadata().name, err).await;
+                }
+            }
+        }
+        let outcome = Outcome {
+            stopped_by,
+            elapsed: start.elapsed(),
+        };
+        self.middleware.after(ctx, &outcome).await;
+    }
+
+    /// Runs an update through the module pipeline on the current task. Used for replaying
+    /// updates that were not received from telegram directly, like deferred commands
+    pub(crate) async fn dispatch_update(&self, update: UpdateExt) -> Result<()> {
+        self.process_update(update).await
     }
 
     /// Handles updates from telegram forever either using webhooks or long polling
     /// depending on toml config
-    pub async fn run(&self) -> Result<()> {
-        log::info!("run");
+    pub async fn run(&'static self) -> Result<()> {
+        log::info!("run {}", self.bot_id());
         let updates = Some(
             vec![
                 "update_id",
@@ -479,43 +760,24 @@ impl TgClient {
                 "my_chat_member",
                 "chat_member",
                 "chat_join_request",
+                "chat_boost",
+                "removed_chat_boost",
+                "business_connection",
+                "business_message",
+                "edited_business_message",
             ]
             .into_iter()
             .map(|v| v.to_owned())
             .collect(),
         );
-        match CONFIG.webhook.enable_webhook {
-            false => {
-                self.client
-                    .build_delete_webhook()
-                    .drop_pending_updates(true) // TODO: change this
-                    .build()
-                    .await?;
-                LongPoller::new(&self.client, updates)
-                    .get_updates()
-                    .await
-                    .for_each_concurrent(
-                        None,
-                        |update| async move { self.handle_update(update).await },
-                    )
-                    .await
-            }
-            true => {
-                Webhook::new(
-                    &self.client,
-                    BotUrl::Host(CONFIG.webhook.webhook_url.to_owned()),
-                    false,
-                    CONFIG.webhook.listen.to_owned(),
-                    updates,
-                )
-                .get_updates()
-                .await?
-                .for_each_concurrent(
-                    None,
-                    |update| async move { self.handle_update(update).await },
-                )
-                .await
-            }
+        if let Err(err) = register_commands(self).await {
+            log::warn!("failed to register commands: {}", err);
+            err.record_stats();
+        }
+        let enable_webhook = CONFIG.load().webhook.enable_webhook;
+        match enable_webhook {
+            false => long_poll(self, updates).await?,
+            true => serve_webhook(self, updates).await?,
         }
         Ok(())
     }
@@ -525,14 +787,30 @@ impl TgClient {
     }
 }
 
+/// Starts the background tasks shared by every bot, then handles updates for all of them
+/// until one fails
+pub async fn run_bots() -> Result<()> {
+    lazy_static::initialize(&START_TIME);
+    scheduler::spawn_scheduler();
+    write_behind::spawn_write_behind();
+    warn_decay::spawn_warn_decay();
+    entity_gc::spawn_entity_gc();
+    local::spawn_invalidation_listener();
+    future::try_join_all(all_bots().iter().map(|bot| bot.run())).await?;
+    Ok(())
+}
+
 impl Clone for TgClient {
     fn clone(&self) -> Self {
         TgClient {
             token: self.token.clone(),
             client: self.client.clone(),
             modules: Arc::clone(&self.modules),
+            registry: Arc::clone(&self.registry),
+            middleware: Arc::clone(&self.middleware),
             button_events: Arc::clone(&self.button_events),
             button_repeat: Arc::clone(&self.button_repeat),
+            me: self.me.clone(),
             handler: UpdateHandler(self.handler.0.clone()),
         }
     }
@@ -4,48 +4,46 @@
 //! different character, currently "!". Command arguments are parsed using regex currently
 //! but in the near future will be switched to a context-free grammar
 
-use crate::statics::{AT_HANDLE, USERNAME};
+use crate::statics::ME;
 use crate::util::error::Fail;
-use crate::util::string::AlignCharBoundry;
-use crate::{
-    persist::redis::RedisStr,
-    statics::{CONFIG, REDIS},
-    util::{
-        error::{BotError, Result},
-        string::{get_chat_lang, Lang, Speak},
-    },
+use crate::util::error_codes::{INVALID_ARGUMENT, MISSING_ARGUMENT};
+use crate::util::string::{AlignCharBoundry, MAX_MESSAGE_LEN};
+use crate::util::{
+    error::{BotError, Result},
+    string::{get_chat_lang_strings, CustomStrings, Lang, SendOptions, Speak},
 };
 use async_trait::async_trait;
-use base64::{engine::general_purpose, Engine};
 use botapi::gen_types::{
-    Chat, MaybeInaccessibleMessage, Message, MessageBuilder, MessageEntity, UpdateExt, User,
+    Chat, EReplyMarkup, MaybeInaccessibleMessage, Message, MessageBuilder, MessageEntity,
+    UpdateExt, User,
 };
 use lazy_static::lazy_static;
 use macros::lang_fmt;
-use redis::AsyncCommands;
 use regex::Regex;
 use serde::Deserialize;
-use serde::{de::DeserializeOwned, Serialize};
+use serde::Serialize;
 use std::collections::VecDeque;
-use std::sync::Arc;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
-use uuid::Uuid;
+use tokio::sync::OnceCell;
 use yoke::{Yoke, Yokeable};
 
 use super::admin_helpers::is_dm;
+use super::aliases::{get_command_aliases, resolve_alias, CommandAliases};
+use super::business::message_business_chat;
+use super::deeplink::DeepLink;
+use super::spam::SpamScore;
 use super::{
     admin_helpers::{ChatUser, IntoChatUser, UpdateHelpers},
-    button::get_url,
-    markdown::EntityMessage,
+    markdown::{EntityMessage, Escape, MarkupBuilder},
     permissions::{BotPermissions, IsGroupAdmin, NamedBotPermissions, NamedPermission},
 };
 
 lazy_static! {
-    static ref COMMOND: Regex = Regex::new(&format!(r#"^(!|/)\w+(@{})?\s+.*"#, *USERNAME)).unwrap();
-    static ref COMMOND_HEAD: Regex =
-        Regex::new(&format!(r#"^(!|/)\w+(@{}|\s|$)"#, *USERNAME)).unwrap();
+    static ref COMMOND_HEAD: Regex = Regex::new(r#"^(!|/)\w+(@\w+)?(\s|$)"#).unwrap();
     static ref TOKENS: Regex = Regex::new(r#"([^\s"!/]+|"|^!|^/)"#).unwrap();
-    static ref ARGS: Regex = Regex::new(r#"(".*"|[^"\s]+)"#).unwrap();
+    static ref ARGS: Regex = Regex::new(r#"("[^"]*"|[^"\s]+)"#).unwrap();
     static ref QUOTE: Regex = Regex::new(r#"".*""#).unwrap();
 }
 
@@ -72,17 +70,14 @@ fn get_input_type<'a>(
 /// Helper to parse a command with either the argument to the command as text or
 /// the text of the message the command is replying to
 pub fn get_content<'a>(
-    message: &'a Message,
+    ctx: &'a Context,
     textargs: &'a TextArgs<'a>,
 ) -> crate::util::error::Result<InputType<'a>> {
+    let message = ctx.message()?;
     match single_arg(textargs.text) {
         Some((TextArg::Arg(name), _, end)) => Ok(get_input_type(message, textargs, name, end)),
         Some((TextArg::Quote(name), _, end)) => Ok(get_input_type(message, textargs, name, end)),
-        _ => Err(BotError::speak(
-            "Invalid argument, need to specify name",
-            message.chat.id,
-            Some(message.message_id),
-        )),
+        _ => ctx.fail_code(MISSING_ARGUMENT, lang_fmt!(ctx, "errsavenotename")),
     }
 }
 
@@ -205,6 +200,105 @@ impl<'a> TextArgs<'a> {
             args: self.args.as_slice(),
         }
     }
+
+    /// Parse flags and key=value pairs out of the argument list
+    pub fn matches(&self) -> ArgMatches<'a> {
+        ArgMatches::parse(&self.args)
+    }
+}
+
+impl<'a> ArgSlice<'a> {
+    /// Parse flags and key=value pairs out of the argument list
+    pub fn matches(&self) -> ArgMatches<'a> {
+        ArgMatches::parse(self.args)
+    }
+}
+
+/// Flags and options parsed from a command's arguments. `--name` is a flag, `-abc` is one
+/// or more single letter flags, and `key=value` or `--key=value` is a value. Values with
+/// spaces can be quoted as in `reason="spamming links"`. Everything else is kept in order as
+/// positional arguments
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArgMatches<'a> {
+    flags: Vec<&'a str>,
+    short: Vec<char>,
+    values: Vec<(&'a str, &'a str)>,
+    positional: Vec<TextArg<'a>>,
+}
+
+fn is_option_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+impl<'a> ArgMatches<'a> {
+    /// Sorts an argument list into flags, values, and positional arguments
+    pub fn parse(args: &[TextArg<'a>]) -> Self {
+        let mut res = Self::default();
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next() {
+            let TextArg::Arg(text) = arg else {
+                res.positional.push(arg.r());
+                continue;
+            };
+            let (long, option) = match text.strip_prefix("--") {
+                Some(long) if !long.is_empty() => (true, long),
+                _ => (false, *text),
+            };
+            if let Some((key, value)) = option.split_once('=').filter(|(k, _)| is_option_key(k)) {
+                let value = match args.peek() {
+                    Some(TextArg::Quote(quote)) if value.is_empty() => {
+                        args.next();
+                        *quote
+                    }
+                    _ => value,
+                };
+                res.values.push((key, value));
+            } else if long {
+                res.flags.push(option);
+            } else if let Some(short) = text
+                .strip_prefix('-')
+                .filter(|s| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphabetic()))
+            {
+                res.short.extend(short.chars());
+            } else {
+                res.positional.push(arg.r());
+            }
+        }
+        res
+    }
+
+    /// Checks if a flag was given either as `--long` or `-s`
+    pub fn flag(&self, long: &str, short: char) -> bool {
+        self.flags.contains(&long) || self.short.contains(&short)
+    }
+
+    /// Gets the value given for a key. If a key was given more than once the last one wins
+    pub fn value(&self, key: &str) -> Option<&'a str> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| *v)
+    }
+
+    /// Parses the value given for a key, None if it wasn't given
+    pub fn parse_value<T: FromStr>(&self, key: &str) -> std::result::Result<Option<T>, T::Err> {
+        self.value(key).map(T::from_str).transpose()
+    }
+
+    /// Arguments that aren't flags or values, in order
+    pub fn positional(&self) -> &'_ [TextArg<'a>] {
+        &self.positional
+    }
+
+    /// Checks for `--dryrun` or `-n`, asking a destructive command to report what it would do
+    /// without doing it
+    pub fn dry_run(&self) -> bool {
+        self.flag("dryrun", 'n')
+    }
 }
 
 impl<'a, 'b> PopSlice<'b, 'a> for TextArgs<'a>
@@ -294,6 +388,20 @@ pub struct Cmd<'a> {
 pub struct StaticContext {
     pub update: UpdateExt,
     pub lang: Lang,
+    /// strings the chat's admins replaced with their own text
+    pub strings: CustomStrings,
+    /// command aliases defined in the chat, only loaded for messages that look like commands
+    pub aliases: CommandAliases,
+    /// spam score for the update's message, computed on first use
+    pub spam: OnceCell<Option<SpamScore>>,
+    /// whether the member joining in this update joined during a burst, counted on first use
+    pub join_burst: OnceCell<bool>,
+    /// deep link the update's /start command was sent with, parsed on first use
+    pub deep_link: OnceCell<Option<DeepLink>>,
+    /// reply to edit instead of sending a new one when re-running an edited command
+    pub reply_to_edit: Mutex<Option<i64>>,
+    /// for messages from a business chat, the business account owner's chat with the bot
+    pub business_chat: Option<i64>,
 }
 
 /// Everything needed to interact with user messages. Contains command and arguments, the message
@@ -316,6 +424,14 @@ impl Clone for Context {
     }
 }
 
+/// Checks if the @handle after a command is addressed to the bot handling the update, since
+/// several bots can share a chat
+fn is_my_handle(handle: &str) -> bool {
+    ME.get()
+        .and_then(|me| me.get_username())
+        .map_or(false, |name| handle.trim_start_matches('@') == name)
+}
+
 impl StaticContext {
     pub(crate) fn yoke(self: Arc<Self>) -> Context {
         let v = Yoke::attach_to_cart(self, |v| {
@@ -345,13 +461,25 @@ impl StaticContext {
 
     /// Parse individual components of a /command or !command
     pub fn parse_cmd(&self) -> Option<(&'_ str, TextArgs<'_>, Entities<'_>)> {
-        if let Ok(message) = self.message() {
+        // customers can't run commands through a business account
+        if let Some(message) = self
+            .message()
+            .ok()
+            .filter(|m| m.get_business_connection_id().is_none())
+        {
             if let Some(cmd) = message
                 .get_text()
                 .map_or_else(|| message.get_caption(), Some)
             {
                 log::info!("cmd {}", cmd);
-                if let Some(head) = COMMOND_HEAD.find(cmd) {
+                if let Some(head) = COMMOND_HEAD
+                    .captures(cmd)
+                    .filter(|c| {
+                        c.get(2)
+                            .map_or(true, |handle| is_my_handle(handle.as_str()))
+                    })
+                    .and_then(|c| c.get(0))
+                {
                     let entities = if let Some(entities) = message.get_entities() {
                         let mut entities = entities
                             .iter()
@@ -384,10 +512,13 @@ impl StaticContext {
                     let mut cb = 1;
                     cb = head.as_str().align_char_boundry(cb);
 
+                    let name = head.as_str()[cb..head.end()]
+                        .trim_end()
+                        .split('@')
+                        .next()
+                        .unwrap_or_default();
                     Some((
-                        (head.as_str()[cb..head.end()]
-                            .trim_end()
-                            .trim_end_matches(&*AT_HANDLE)),
+                        resolve_alias(&self.aliases, name),
                         TextArgs {
                             text: tail,
                             args: raw_args,
@@ -428,6 +559,11 @@ impl StaticContext {
         &self.lang
     }
 
+    /// Gets the chat's own text for a string if its admins replaced it
+    pub fn string_override(&self, key: &str) -> Option<&str> {
+        self.strings.get(key).map(|v| v.as_str())
+    }
+
     pub fn chat(&self) -> Option<&'_ Chat> {
         match self.update {
             UpdateExt::Message(ref m) => Some(m.get_chat()),
@@ -437,6 +573,9 @@ impl StaticContext {
                 MaybeInaccessibleMessage::InaccessibleMessage(m) => m.get_chat(),
             }),
             UpdateExt::ChatMember(ref m) => Some(m.get_chat()),
+            UpdateExt::ChatJoinRequest(ref m) => Some(m.get_chat()),
+            UpdateExt::ChatBoost(ref m) => Some(m.get_chat()),
+            UpdateExt::RemovedChatBoost(ref m) => Some(m.get_chat()),
             _ => None,
         }
     }
@@ -453,14 +592,38 @@ impl StaticContext {
                 chat: m.get_chat(),
                 user: m.get_from(),
             }),
+            UpdateExt::ChatJoinRequest(ref m) => Some(ChatUser {
+                chat: m.get_chat(),
+                user: m.get_from(),
+            }),
             _ => None,
         }
     }
 
     /// Get a context from an update. Returns none if one or more fields aren't present
-    /// Currently only Message updates return Some
+    /// Currently only Message updates return Some. Messages from business chats become
+    /// regular messages, using the language and strings of the business account owner's chat
     pub async fn get_context(update: UpdateExt) -> Result<Arc<Self>> {
-        let lang = if let Some(chat) = match update {
+        let update = match update {
+            UpdateExt::BusinessMessage(m) => UpdateExt::Message(m),
+            UpdateExt::EditedBusinessMessage(m) => UpdateExt::EditedMessage(m),
+            update => update,
+        };
+        let business_chat = match update {
+            UpdateExt::Message(ref m) | UpdateExt::EditedMessage(ref m) => {
+                match message_business_chat(m).await {
+                    Ok(chat) => chat,
+                    Err(err) => {
+                        // handled like a connection the bot can't reply through
+                        log::warn!("failed to get business connection: {}", err);
+                        err.record_stats();
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        let (lang, strings) = if let Some(chat) = match update {
             UpdateExt::Message(ref m) => Some(m.chat.id),
             UpdateExt::EditedMessage(ref m) => Some(m.chat.id),
             UpdateExt::CallbackQuery(ref m) => m.get_message().map(|m| {
@@ -471,13 +634,37 @@ impl StaticContext {
                 .id
             }),
             UpdateExt::ChatMember(ref m) => Some(m.chat.id),
+            UpdateExt::ChatJoinRequest(ref m) => Some(m.chat.id),
+            UpdateExt::ChatBoost(ref m) => Some(m.chat.id),
+            UpdateExt::RemovedChatBoost(ref m) => Some(m.chat.id),
             _ => None,
         } {
-            get_chat_lang(chat).await?
+            let chat = business_chat.unwrap_or(chat);
+            get_chat_lang_strings(chat).await?
         } else {
-            Lang::En
+            (Lang::En, CustomStrings::new())
+        };
+        let aliases = match update {
+            UpdateExt::Message(ref m) | UpdateExt::EditedMessage(ref m)
+                if m.get_text()
+                    .map_or_else(|| m.get_caption(), Some)
+                    .map_or(false, |text| text.starts_with(['/', '!'])) =>
+            {
+                get_command_aliases(m.chat.id).await?
+            }
+            _ => CommandAliases::new(),
         };
-        Ok(Arc::new(Self { update, lang }))
+        Ok(Arc::new(Self {
+            update,
+            lang,
+            strings,
+            aliases,
+            spam: OnceCell::new(),
+            join_burst: OnceCell::new(),
+            deep_link: OnceCell::new(),
+            reply_to_edit: Mutex::new(None),
+            business_chat,
+        }))
     }
 }
 
@@ -497,6 +684,42 @@ impl Context {
     pub fn is_dm(&self) -> bool {
         self.chat().map(is_dm).unwrap_or(false)
     }
+
+    /// Whether the update is a message from a chat the bot answers for a business account
+    pub fn is_business(&self) -> bool {
+        match self.update() {
+            UpdateExt::Message(ref m) | UpdateExt::EditedMessage(ref m) => {
+                m.get_business_connection_id().is_some()
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the update is a message the business account owner sent in one of their
+    /// business chats
+    pub fn from_business_owner(&self) -> bool {
+        let Some(owner) = self
+            .get_static()
+            .business_chat
+            .filter(|_| self.is_business())
+        else {
+            return false;
+        };
+        self.message()
+            .ok()
+            .and_then(|m| m.get_from())
+            .is_some_and(|user| user.get_id() == owner)
+    }
+
+    /// The chat whose settings apply to this update. For business chats this is the business
+    /// account owner's chat with the bot, None if the bot can't reply for the account
+    pub fn settings_chat(&self) -> Option<i64> {
+        if self.is_business() {
+            self.get_static().business_chat
+        } else {
+            self.chat().map(|chat| chat.get_id())
+        }
+    }
     pub fn update(&self) -> &'_ UpdateExt {
         &self.0.get().0.update
     }
@@ -535,6 +758,9 @@ impl Context {
                 MaybeInaccessibleMessage::InaccessibleMessage(m) => m.get_chat(),
             }),
             Some(UpdateExt::ChatMember(ref m)) => Some(m.get_chat()),
+            Some(UpdateExt::ChatJoinRequest(ref m)) => Some(m.get_chat()),
+            Some(UpdateExt::ChatBoost(ref m)) => Some(m.get_chat()),
+            Some(UpdateExt::RemovedChatBoost(ref m)) => Some(m.get_chat()),
             _ => None,
         }
     }
@@ -551,6 +777,23 @@ impl Context {
     pub fn cmd(&self) -> Option<&'_ Cmd<'_>> {
         self.get().as_ref().and_then(|v| v.command.as_ref())
     }
+
+    /// Fails with a command's usage in the chat's language, for commands given arguments they
+    /// don't accept. `key` is the string with the usage, the same one named by `usage` in the
+    /// command's metadata
+    pub fn usage_err<R>(&self, key: &str) -> Result<R> {
+        let usage = self
+            .string_override(key)
+            .or_else(|| self.lang().get_string(key))
+            .map(|usage| usage.escape(false).into_owned());
+        match usage {
+            Some(usage) => self.fail_code(INVALID_ARGUMENT, usage),
+            None => {
+                let command = self.cmd().map(|cmd| cmd.cmd).unwrap_or_default();
+                self.fail_code(INVALID_ARGUMENT, lang_fmt!(self, "badusage", command))
+            }
+        }
+    }
 }
 
 #[async_trait]
@@ -594,6 +837,11 @@ impl Context {
     pub fn lang(&self) -> &'_ Lang {
         &self.get_static().lang
     }
+
+    /// Gets the chat's own text for a string if its admins replaced it
+    pub fn string_override(&self, key: &str) -> Option<&str> {
+        self.get_static().string_override(key)
+    }
 }
 
 #[async_trait]
@@ -609,15 +857,79 @@ impl Speak for Context {
         self.message()?.speak_fmt(messsage).await
     }
 
+    async fn speak_with<T>(&self, message: T, options: SendOptions) -> Result<Option<Message>>
+    where
+        T: AsRef<str> + Send + Sync,
+    {
+        self.message()?.speak_with(message, options).await
+    }
+
+    async fn speak_entities(
+        &self,
+        text: &str,
+        entities: &[MessageEntity],
+        options: SendOptions,
+    ) -> Result<Option<Message>> {
+        self.message()?
+            .speak_entities(text, entities, options)
+            .await
+    }
+
+    fn reply_target(&self) -> Option<i64> {
+        self.message().ok().map(|message| message.get_message_id())
+    }
+
+    fn thread(&self) -> Option<i64> {
+        self.message().ok().and_then(|message| message.thread())
+    }
+
     async fn reply_fmt(&self, messsage: EntityMessage) -> Result<Option<Message>> {
-        self.message()?.reply_fmt(messsage).await
+        if let Some(reply) = self.take_reply_to_edit() {
+            let mut builder = messsage.builder.clone();
+            let (text, entities, buttons) = builder.build_murkdown_nofail_ref().await;
+            let markup = messsage.reply_markup.clone().or_else(|| {
+                buttons
+                    .filter(|_| !messsage.disable_murkdown)
+                    .map(|b| b.clone())
+            });
+            if self
+                .edit_command_reply(reply, text, entities, markup.as_ref())
+                .await?
+            {
+                return Ok(None);
+            }
+        }
+        let reply = self.message()?.reply_fmt(messsage).await?;
+        self.track_command_reply(&reply).await;
+        Ok(reply)
     }
 
     async fn reply<T>(&self, message: T) -> Result<Option<Message>>
     where
         T: AsRef<str> + Send + Sync,
     {
-        self.message()?.reply(message).await
+        let edit = self.take_reply_to_edit();
+        if let Some(reply) = edit {
+            let (text, entities, markup) = MarkupBuilder::new(None)
+                .set_text(message.as_ref().to_owned())
+                .filling(true)
+                .header(false)
+                .chatuser(self.message()?.get_chatuser().as_ref())
+                .build_murkdown_nofail()
+                .await;
+            let markup = EReplyMarkup::InlineKeyboardMarkup(markup.build());
+            // telegram counts the message length in utf-16 code units
+            if text.encode_utf16().count() <= MAX_MESSAGE_LEN
+                && self
+                    .edit_command_reply(reply, &text, &entities, Some(&markup))
+                    .await?
+            {
+                return Ok(None);
+            }
+        }
+        let reply = self.message()?.reply(message).await?;
+        self.track_command_reply(&reply).await;
+        Ok(reply)
     }
 
     async fn force_reply<T>(&self, message: T, reply: i64) -> Result<Option<Message>>
@@ -626,6 +938,10 @@ impl Speak for Context {
     {
         self.message()?.force_reply(message, reply).await
     }
+
+    async fn clean_confirmation(&self, message: &Option<Message>) {
+        self.clean_context_confirmation(message).await
+    }
 }
 
 #[async_trait]
@@ -638,49 +954,11 @@ impl UpdateHelpers for Context {
         self.update().should_moderate().await
     }
 }
-pub async fn post_deep_link<T, F>(value: T, key_func: F) -> Result<String>
-where
-    T: Serialize,
-    F: FnOnce(&str) -> String,
-{
-    let ser = RedisStr::new(&value)?;
-    let r = Uuid::new_v4();
-    let key = key_func(&r.to_string());
-    REDIS
-        .pipe(|q| q.set(&key, ser).expire(&key, CONFIG.timing.cache_timeout))
-        .await?;
-    let bs = general_purpose::URL_SAFE_NO_PAD.encode(r.into_bytes());
-    let bs = get_url(bs)?;
-    log::info!("post_deep_link {}", bs);
-    Ok(bs)
-}
-
-pub async fn handle_deep_link<F, R>(ctx: &Context, key_func: F) -> Result<Option<R>>
-where
-    F: FnOnce(&str) -> String,
-    R: DeserializeOwned,
-{
-    if let Some(Cmd { ref args, .. }) = ctx.cmd() {
-        if let Some(u) = args.args.first().map(|a| a.get_text()) {
-            if let Ok(base) = general_purpose::URL_SAFE_NO_PAD.decode(u) {
-                if let Ok(base) = Uuid::from_slice(base.as_slice()) {
-                    let key = key_func(&base.to_string());
-                    let base: Option<RedisStr> = REDIS.sq(|q| q.get(&key)).await?;
-                    if let Some(base) = base {
-                        return Ok(Some(base.get()?));
-                    }
-                }
-            }
-        }
-    }
-    Ok(None)
-}
-
 #[allow(dead_code)]
 mod test {
-    use botapi::gen_types::UserBuilder;
+    use arc_swap::ArcSwap;
 
-    use crate::statics::{Args, Config, ARGS, CONFIG_BACKEND, ME};
+    use crate::statics::{Args, Config, ARGS, CONFIG_BACKEND};
 
     use super::*;
 
@@ -697,16 +975,19 @@ mod test {
     fn default_context(text: String) -> Result<Context> {
         let message = default_message(text)?;
         ARGS.set(Args::default()).ok();
-        CONFIG_BACKEND.set(Config::default()).ok();
-        ME.set(
-            UserBuilder::new(0, true, "test".to_owned())
-                .set_username("testbot".to_owned())
-                .build(),
-        )
-        .ok();
+        CONFIG_BACKEND
+            .set(ArcSwap::from_pointee(Config::default()))
+            .ok();
         let ctx = StaticContext {
             update: UpdateExt::Message(message),
             lang: Lang::En,
+            strings: CustomStrings::new(),
+            aliases: CommandAliases::new(),
+            spam: OnceCell::new(),
+            join_burst: OnceCell::new(),
+            deep_link: OnceCell::new(),
+            reply_to_edit: Mutex::new(None),
+            business_chat: None,
         };
         let ctx = Arc::new(ctx);
         Ok(ctx.yoke())
@@ -770,6 +1051,74 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn multiple_quotes() {
+        let ctx = default_context("/This \"first quote\" \"second quote\"".to_owned()).unwrap();
+
+        let (_, textargs, _) = ctx.parse_cmd().unwrap();
+        assert_eq!(
+            textargs.args,
+            vec![
+                TextArg::Quote("first quote"),
+                TextArg::Quote("second quote")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn arg_matches() {
+        let ctx = default_context(
+            "/This @user 1h --silent -dq time=2d reason=\"spamming links\" -5 https://a.b/?c=d"
+                .to_owned(),
+        )
+        .unwrap();
+
+        let (_, textargs, _) = ctx.parse_cmd().unwrap();
+        let matches = textargs.matches();
+        assert!(matches.flag("silent", 's'));
+        assert!(matches.flag("delete", 'd'));
+        assert!(matches.flag("quiet", 'q'));
+        assert!(!matches.flag("force", 'f'));
+        assert_eq!(matches.value("time"), Some("2d"));
+        assert_eq!(matches.value("reason"), Some("spamming links"));
+        assert_eq!(matches.value("missing"), None);
+        assert_eq!(matches.parse_value::<i64>("time").ok(), None);
+        assert_eq!(
+            matches.positional(),
+            &[
+                TextArg::Arg("@user"),
+                TextArg::Arg("1h"),
+                TextArg::Arg("-5"),
+                TextArg::Arg("https://a.b/?c=d")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn arg_matches_long_values() {
+        let ctx = default_context("/This --limit=5 --limit=6 --name= \"a b\"".to_owned()).unwrap();
+
+        let (_, textargs, _) = ctx.parse_cmd().unwrap();
+        let matches = textargs.matches();
+        assert_eq!(matches.parse_value::<i64>("limit"), Ok(Some(6)));
+        assert_eq!(matches.value("name"), Some("a b"));
+        assert!(matches.positional().is_empty());
+    }
+
+    #[tokio::test]
+    async fn arg_matches_dry_run() {
+        let ctx = default_context("/zombies clean --dryrun".to_owned()).unwrap();
+
+        let (_, textargs, _) = ctx.parse_cmd().unwrap();
+        let matches = textargs.matches();
+        assert!(matches.dry_run());
+        assert_eq!(matches.positional(), &[TextArg::Arg("clean")]);
+
+        let ctx = default_context("/zombies clean".to_owned()).unwrap();
+        let (_, textargs, _) = ctx.parse_cmd().unwrap();
+        assert!(!textargs.matches().dry_run());
+    }
+
     async fn command_emoji() {
         let ctx = default_context("/😍🧋".to_owned()).unwrap();
 
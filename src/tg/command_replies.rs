@@ -0,0 +1,195 @@
+//! Re-running edited commands. The bot remembers its reply to each command for
+//! `timing.command_edit_time` seconds, and when a user edits one of those commands it is run
+//! again with the new text. The first reply from the re-run edits the old reply instead of
+//! posting a second one, falling back to a new reply if the old one is gone. Only commands
+//! that are safe to run twice are re-run, so editing a /warn or /ban doesn't act again
+
+use botapi::gen_types::{
+    EReplyMarkup, LinkPreviewOptionsBuilder, Message, MessageEntity, UpdateExt,
+};
+use redis::AsyncCommands;
+
+use crate::statics::{CONFIG, REDIS, TG};
+use crate::util::error::Result;
+use crate::util::string::should_ignore_chat;
+
+use super::command::{Context, StaticContext};
+
+#[inline(always)]
+fn get_reply_key(chat: i64, message: i64) -> String {
+    format!("cmdreply:{}:{}", chat, message)
+}
+
+/// Commands that only show something or set a value, so running them again with the edited
+/// text has no extra effect
+const RERUN_COMMANDS: &[&str] = &[
+    "8ball",
+    "adminnotes",
+    "admins",
+    "aliases",
+    "birthdays",
+    "blocklist",
+    "boosters",
+    "captchakick",
+    "captchamode",
+    "domains",
+    "filters",
+    "get",
+    "help",
+    "history",
+    "id",
+    "info",
+    "joinpolicy",
+    "linkdomains",
+    "listapprovals",
+    "lockaction",
+    "locks",
+    "logchannel",
+    "modlog",
+    "network",
+    "notes",
+    "restrictions",
+    "rules",
+    "schedules",
+    "setlang",
+    "settings",
+    "settz",
+    "shamelist",
+    "spamaction",
+    "strings",
+    "topiclocks",
+    "usernames",
+    "warnlimit",
+    "warnmode",
+    "warns",
+    "warntime",
+    "welcomes",
+];
+
+/// Checks if message text is a /command or !command that is safe to re-run before touching
+/// redis
+fn is_rerunnable_command(message: &Message) -> bool {
+    let Some(text) = message.get_text().or_else(|| message.get_caption()) else {
+        return false;
+    };
+    let Some(head) = text.strip_prefix('/').or_else(|| text.strip_prefix('!')) else {
+        return false;
+    };
+    let command = head
+        .split(|c: char| c.is_whitespace() || c == '@')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    RERUN_COMMANDS.contains(&command.as_str())
+}
+
+/// Remembers the bot's reply to a command so edits to the command can edit the reply
+pub async fn track_reply(command: &Message, reply: &Message) -> Result<()> {
+    let key = get_reply_key(command.get_chat().get_id(), command.get_message_id());
+    REDIS
+        .pipe(|q| {
+            q.set(&key, reply.get_message_id())
+                .expire(&key, CONFIG.load().timing.command_edit_time)
+        })
+        .await?;
+    Ok(())
+}
+
+/// Gets the id of the bot's reply to a command, if it is still remembered
+pub async fn get_tracked_reply(command: &Message) -> Result<Option<i64>> {
+    let key = get_reply_key(command.get_chat().get_id(), command.get_message_id());
+    let reply: Option<i64> = REDIS.sq(|q| q.get(&key)).await?;
+    Ok(reply)
+}
+
+/// Turns an edit to a command the bot replied to back into a message update so the command
+/// runs again, returning the reply to edit along with it. Other updates are returned as is
+pub async fn rerun_edited_command(update: UpdateExt) -> (UpdateExt, Option<i64>) {
+    match update {
+        UpdateExt::EditedMessage(message) if is_rerunnable_command(&message) => {
+            match get_tracked_reply(&message).await {
+                Ok(Some(reply)) => (UpdateExt::Message(message), Some(reply)),
+                Ok(None) => (UpdateExt::EditedMessage(message), None),
+                Err(err) => {
+                    log::warn!("failed to get reply for edited command: {}", err);
+                    err.record_stats();
+                    (UpdateExt::EditedMessage(message), None)
+                }
+            }
+        }
+        update => (update, None),
+    }
+}
+
+/// Edits a previous reply to a command. Returns false if it couldn't be edited, usually
+/// because it was deleted, in which case the caller should send a new reply
+pub async fn edit_reply(
+    command: &Message,
+    reply: i64,
+    text: &str,
+    entities: &Vec<MessageEntity>,
+    markup: Option<&EReplyMarkup>,
+) -> Result<bool> {
+    let mut call = TG
+        .client
+        .build_edit_message_text(text)
+        .chat_id(command.get_chat().get_id())
+        .message_id(reply)
+        .entities(entities)
+        .link_preview_options(
+            &LinkPreviewOptionsBuilder::new()
+                .set_is_disabled(true)
+                .build(),
+        );
+    if let Some(EReplyMarkup::InlineKeyboardMarkup(markup)) = markup {
+        call = call.reply_markup(markup);
+    }
+    match call.build().await {
+        Ok(_) => Ok(true),
+        Err(err) if err.get_tg_error().contains("message is not modified") => Ok(true),
+        Err(err) => {
+            log::debug!("failed to edit reply {}: {}", reply, err);
+            Ok(false)
+        }
+    }
+}
+
+impl StaticContext {
+    /// Sets the reply the next reply from this update edits instead of being sent
+    pub fn set_reply_to_edit(&self, reply: Option<i64>) {
+        *self.reply_to_edit.lock().unwrap() = reply;
+    }
+}
+
+impl Context {
+    /// Takes the reply to edit for a re-run command. Only the first reply edits it
+    pub(crate) fn take_reply_to_edit(&self) -> Option<i64> {
+        self.get_static().reply_to_edit.lock().unwrap().take()
+    }
+
+    /// Edits the reply to a re-run command. Returns true if the reply was handled, either
+    /// edited or dropped because the chat is ignored
+    pub(crate) async fn edit_command_reply(
+        &self,
+        reply: i64,
+        text: &str,
+        entities: &Vec<MessageEntity>,
+        markup: Option<&EReplyMarkup>,
+    ) -> Result<bool> {
+        let command = self.message()?;
+        if should_ignore_chat(command.get_chat().get_id()).await? {
+            return Ok(true);
+        }
+        edit_reply(command, reply, text, entities, markup).await
+    }
+
+    /// Remembers a reply sent to this update's command, if it has one
+    pub(crate) async fn track_command_reply(&self, reply: &Option<Message>) {
+        if let (Some(cmd), Some(reply)) = (self.cmd(), reply) {
+            if let Err(err) = track_reply(cmd.message, reply).await {
+                log::warn!("failed to track command reply: {}", err);
+                err.record_stats();
+            }
+        }
+    }
+}
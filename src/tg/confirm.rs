@@ -0,0 +1,170 @@
+//! Confirmation for high-impact admin commands. In chats with /confirmactions on, commands
+//! like /ban and /fban post a prompt with Confirm and Cancel buttons instead of running right
+//! away. The buttons only work for the admin who sent the command. Confirming calls the
+//! command's handler again with the original update marked as confirmed, so it runs with the
+//! same arguments and permission checks as if it had never been stopped, without passing the
+//! update through every other module again. Prompts expire after [`CONFIRM_TIMEOUT`].
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use botapi::gen_types::{
+    CallbackQuery, Chat, EReplyMarkup, InlineKeyboardButton, InlineKeyboardButtonBuilder,
+    MaybeInaccessibleMessage, ReplyParametersBuilder,
+};
+use futures::future::BoxFuture;
+use macros::lang_fmt;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::EntityTrait;
+
+use crate::persist::core::dialogs;
+use crate::statics::{DB, TG};
+use crate::util::error::Result;
+use crate::util::string::Lang;
+
+use super::bots::spawn;
+use super::button::{callback_data, CallbackReply, InlineKeyboardBuilder, OnPush};
+use super::command::Context;
+use super::dialog::{dialog_or_default, dialog_scope};
+
+/// How long a prompt's buttons work for
+pub const CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+
+tokio::task_local! {
+    static CONFIRMED: bool;
+}
+
+/// Turns confirmation prompts for high-impact commands on or off
+pub async fn set_confirm_actions(chat: &Chat, enabled: bool) -> Result<()> {
+    let mut model = dialogs::Model::from_chat(chat).await?;
+    model.confirm_actions = Set(enabled);
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_column(dialogs::Column::ConfirmActions)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat.get_id()).invalidate().await?;
+    Ok(())
+}
+
+/// Edits a prompt's text, which also removes its buttons
+async fn close_prompt(chat: i64, message: i64, text: String) -> Result<()> {
+    TG.client
+        .build_edit_message_text(&text)
+        .chat_id(chat)
+        .message_id(message)
+        .build()
+        .await?;
+    Ok(())
+}
+
+fn unregister(buttons: &[InlineKeyboardButton]) {
+    for button in buttons {
+        TG.unregister_button(button);
+    }
+}
+
+/// Message id of the prompt a button was pushed on
+fn prompt_id(callback: &CallbackQuery) -> Option<i64> {
+    match callback.get_message()? {
+        MaybeInaccessibleMessage::Message(message) => Some(message.get_message_id()),
+        MaybeInaccessibleMessage::InaccessibleMessage(message) => Some(message.get_message_id()),
+    }
+}
+
+/// Checks whether a high-impact command should run now. Returns true if the chat doesn't
+/// ask for confirmation or the command was already confirmed. Otherwise posts a prompt and
+/// returns false, and the command should stop without doing anything. `handler` is the
+/// command's handler, called with this context once the prompt is confirmed. It is boxed
+/// since it usually calls the handler `confirmed` is called from
+pub async fn confirmed<F>(ctx: &Context, handler: F) -> Result<bool>
+where
+    F: FnOnce(Context) -> BoxFuture<'static, Result<()>> + Send + Sync + 'static,
+{
+    if CONFIRMED.try_with(|confirmed| *confirmed).unwrap_or(false) {
+        return Ok(true);
+    }
+    let message = ctx.message()?;
+    let chat = message.get_chat();
+    let Some(admin) = message.get_from().map(|user| user.get_id()) else {
+        return Ok(true);
+    };
+    if !dialog_or_default(chat).await?.confirm_actions {
+        return Ok(true);
+    }
+
+    let lang: Lang = *ctx.lang();
+    let chat = chat.get_id();
+    let command = message.get_text().unwrap_or_default().to_owned();
+    let confirm = InlineKeyboardButtonBuilder::new(lang_fmt!(lang, "confirmbutton"))
+        .set_callback_data(callback_data(Some(admin)))
+        .build();
+    let cancel = InlineKeyboardButtonBuilder::new(lang_fmt!(lang, "cancelbutton"))
+        .set_callback_data(callback_data(Some(admin)))
+        .build();
+    let buttons = Arc::new([confirm.clone(), cancel.clone()]);
+
+    // whichever of confirm, cancel, and expiry happens first closes the prompt
+    let closed = Arc::new(AtomicBool::new(false));
+
+    let original = ctx.clone();
+    let confirm_closed = Arc::clone(&closed);
+    let confirm_buttons = Arc::clone(&buttons);
+    confirm.on_push(move |callback| async move {
+        unregister(confirm_buttons.as_slice());
+        if confirm_closed.swap(true, Ordering::SeqCst) {
+            return Ok(CallbackReply::default());
+        }
+        if let Some(prompt) = prompt_id(&callback) {
+            TG.client.build_delete_message(chat, prompt).build().await?;
+        }
+        CONFIRMED.scope(true, handler(original)).await?;
+        Ok(CallbackReply::default())
+    });
+
+    let cancel_closed = Arc::clone(&closed);
+    let cancel_buttons = Arc::clone(&buttons);
+    cancel.on_push(move |callback| async move {
+        unregister(cancel_buttons.as_slice());
+        if cancel_closed.swap(true, Ordering::SeqCst) {
+            return Ok(CallbackReply::default());
+        }
+        if let Some(prompt) = prompt_id(&callback) {
+            close_prompt(chat, prompt, lang_fmt!(lang, "confirmcancelled")).await?;
+        }
+        Ok(CallbackReply::toast(lang_fmt!(lang, "confirmcancelled")))
+    });
+
+    let mut keyboard = InlineKeyboardBuilder::default();
+    keyboard.button(confirm).button(cancel);
+    let prompt = TG
+        .client
+        .build_send_message(
+            chat,
+            &lang_fmt!(lang, "confirmaction", command, CONFIRM_TIMEOUT.as_secs()),
+        )
+        .reply_markup(&EReplyMarkup::InlineKeyboardMarkup(keyboard.build()))
+        .reply_parameters(&ReplyParametersBuilder::new(message.get_message_id()).build())
+        .build()
+        .await?;
+
+    let prompt = prompt.get_message_id();
+    spawn(async move {
+        tokio::time::sleep(CONFIRM_TIMEOUT).await;
+        if closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        unregister(buttons.as_slice());
+        if let Err(err) = close_prompt(chat, prompt, lang_fmt!(lang, "confirmexpired")).await {
+            log::warn!("failed to expire confirmation prompt: {}", err);
+            err.record_stats();
+        }
+    });
+    Ok(false)
+}
@@ -0,0 +1,21 @@
+//! Guarding against handling an update twice. Telegram resends webhook updates it didn't see
+//! an answer to, and a poller restarted partway through a batch fetches the batch again, so
+//! every update is claimed in redis before it is handled and updates that were already
+//! claimed are dropped. Without this a retried /ban or /warn would run twice
+
+use crate::statics::REDIS;
+use crate::util::error::Result;
+
+/// Seconds an update id is remembered. Telegram gives up on retrying well before this
+const CLAIM_TIME: i64 = 60 * 10;
+
+#[inline(always)]
+fn get_claim_key(bot: i64, update: i64) -> String {
+    format!("updclaim:{}:{}", bot, update)
+}
+
+/// Claims an update for handling. Returns false if the update was already claimed
+pub async fn claim_update(bot: i64, update: i64) -> Result<bool> {
+    let key = get_claim_key(bot, update);
+    REDIS.set_nx_ex(&key, CLAIM_TIME).await
+}
@@ -0,0 +1,288 @@
+//! Structured /start payloads. Every feature that sends users to the bot's dm shares the one
+//! /start entry point, so payloads name the feature they lead to along with what it needs,
+//! like `rules_<chat>` or `note_<chat>_<name>`. Payloads are signed, so users can't craft
+//! links to notes or captchas of chats nobody sent them a link for. Links too long for
+//! telegram's 64 character limit are kept in redis behind a random id instead. Payloads are
+//! signed with `callback_secret`, so links keep working after a restart. Links posted before
+//! payloads were signed are still accepted while they are cached
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use botapi::gen_types::{Chat, User};
+
+use crate::persist::redis::RedisStr;
+use crate::statics::{CONFIG, REDIS};
+use crate::util::error::Result;
+
+use super::button::{callback_key, get_url};
+use super::command::{Cmd, Context, OwnedTextArgs};
+
+/// Longest start parameter telegram accepts
+const MAX_PAYLOAD: usize = 64;
+
+/// Bytes of the hmac kept in a payload's signature
+const SIGNATURE_LEN: usize = 8;
+
+/// Length of the signature once encoded
+const SIGNATURE_CHARS: usize = (SIGNATURE_LEN * 4 + 2) / 3;
+
+#[inline(always)]
+fn get_deep_link_key(id: &str) -> String {
+    format!("dl:{}", id)
+}
+
+/// Keys links were cached under before payloads were signed, by what they led to
+#[inline(always)]
+fn get_legacy_keys(id: &str) -> [String; 5] {
+    [
+        format!("gethelp:{}", id),
+        format!("bdlk:{}", id),
+        format!("dlrules:{}", id),
+        format!("ccback:{}", id),
+        format!("ccap:{}", id),
+    ]
+}
+
+/// Where a /start deep link leads
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum DeepLink {
+    /// The help menu, opened at the page reached by following these names
+    Help(Vec<String>),
+    /// A note from a chat, for private notes and note buttons
+    Note { chat: i64, name: String },
+    /// A chat's rules
+    Rules(i64),
+    /// An appeal of a ban in a chat
+    Appeal(i64),
+    /// A captcha for a chat, only usable by `user` if one is set
+    Captcha { chat: i64, user: Option<i64> },
+}
+
+impl DeepLink {
+    fn body(&self) -> String {
+        match self {
+            Self::Help(page) if page.is_empty() => "help".to_owned(),
+            Self::Help(page) => format!("help_{}", URL_SAFE_NO_PAD.encode(page.join(" "))),
+            Self::Note { chat, name } => {
+                format!("note_{}_{}", chat, URL_SAFE_NO_PAD.encode(name))
+            }
+            Self::Rules(chat) => format!("rules_{}", chat),
+            Self::Appeal(chat) => format!("appeal_{}", chat),
+            Self::Captcha { chat, user: None } => format!("captcha_{}", chat),
+            Self::Captcha {
+                chat,
+                user: Some(user),
+            } => format!("captcha_{}_{}", chat, user),
+        }
+    }
+
+    fn from_body(body: &str) -> Option<Self> {
+        let (kind, rest) = body.split_once('_').unwrap_or((body, ""));
+        let link = match kind {
+            "help" if rest.is_empty() => Self::Help(Vec::new()),
+            "help" => {
+                let page = String::from_utf8(URL_SAFE_NO_PAD.decode(rest).ok()?).ok()?;
+                Self::Help(page.split_whitespace().map(|v| v.to_owned()).collect())
+            }
+            "note" => {
+                let (chat, name) = rest.split_once('_')?;
+                Self::Note {
+                    chat: chat.parse().ok()?,
+                    name: String::from_utf8(URL_SAFE_NO_PAD.decode(name).ok()?).ok()?,
+                }
+            }
+            "rules" => Self::Rules(rest.parse().ok()?),
+            "appeal" => Self::Appeal(rest.parse().ok()?),
+            "captcha" => match rest.split_once('_') {
+                Some((chat, user)) => Self::Captcha {
+                    chat: chat.parse().ok()?,
+                    user: Some(user.parse().ok()?),
+                },
+                None => Self::Captcha {
+                    chat: rest.parse().ok()?,
+                    user: None,
+                },
+            },
+            _ => return None,
+        };
+        Some(link)
+    }
+
+    /// Gets a url opening the bot's dm with this link
+    pub async fn url(&self) -> Result<String> {
+        let payload = sign_with(&callback_key(), self);
+        if payload.len() <= MAX_PAYLOAD {
+            return get_url(payload);
+        }
+
+        let id = Uuid::new_v4();
+        let key = get_deep_link_key(&id.to_string());
+        let link = RedisStr::new(self)?;
+        REDIS
+            .pipe(|q| {
+                q.set(&key, link)
+                    .expire(&key, CONFIG.load().timing.cache_timeout)
+            })
+            .await?;
+        get_url(URL_SAFE_NO_PAD.encode(id.into_bytes()))
+    }
+
+    /// Parses a start parameter generated by [`DeepLink::url`], returning None if it wasn't
+    /// one of ours or the link expired
+    pub async fn parse(payload: &str) -> Result<Option<Self>> {
+        if let Some(link) = parse_with(&callback_key(), payload) {
+            return Ok(Some(link));
+        }
+        let Some(id) = URL_SAFE_NO_PAD
+            .decode(payload)
+            .ok()
+            .and_then(|id| Uuid::from_slice(&id).ok())
+        else {
+            return Ok(None);
+        };
+        let id = id.to_string();
+        let [help, note, rules, captcha, burst] = get_legacy_keys(&id);
+        let (link, help, note, rules, captcha, burst): (
+            Option<RedisStr>,
+            Option<RedisStr>,
+            Option<RedisStr>,
+            Option<i64>,
+            Option<RedisStr>,
+            Option<i64>,
+        ) = REDIS
+            .pipe(|q| {
+                q.get(&get_deep_link_key(&id))
+                    .get(&help)
+                    .get(&note)
+                    .get(&rules)
+                    .get(&captcha)
+                    .get(&burst)
+            })
+            .await?;
+
+        let link = if let Some(link) = link {
+            link.get()?
+        } else if let Some(help) = help {
+            let help: OwnedTextArgs = help.get()?;
+            Self::Help(help.text.split_whitespace().map(|v| v.to_owned()).collect())
+        } else if let Some(note) = note {
+            let (chat, name): (i64, String) = note.get()?;
+            Self::Note { chat, name }
+        } else if let Some(chat) = rules {
+            Self::Rules(chat)
+        } else if let Some(captcha) = captcha {
+            let (chat, user): (Chat, User) = captcha.get()?;
+            Self::Captcha {
+                chat: chat.get_id(),
+                user: Some(user.get_id()),
+            }
+        } else if let Some(chat) = burst {
+            Self::Captcha { chat, user: None }
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(link))
+    }
+}
+
+fn link_mac(key: &[u8], body: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(body.as_bytes());
+    mac
+}
+
+fn sign_with(key: &[u8], link: &DeepLink) -> String {
+    let body = link.body();
+    let sig = link_mac(key, &body).finalize().into_bytes();
+    format!("{}_{}", body, URL_SAFE_NO_PAD.encode(&sig[..SIGNATURE_LEN]))
+}
+
+fn parse_with(key: &[u8], payload: &str) -> Option<DeepLink> {
+    let split = payload.len().checked_sub(SIGNATURE_CHARS + 1)?;
+    let (body, sig) = (payload.get(..split)?, payload.get(split..)?);
+    let sig = URL_SAFE_NO_PAD.decode(sig.strip_prefix('_')?).ok()?;
+    if sig.len() != SIGNATURE_LEN || link_mac(key, body).verify_truncated_left(&sig).is_err() {
+        return None;
+    }
+    DeepLink::from_body(body)
+}
+
+impl Context {
+    /// Gets the deep link this update's /start command was sent with, if any. Parsed once
+    /// per update however many modules ask
+    pub async fn deep_link(&self) -> Result<Option<&DeepLink>> {
+        let link = self
+            .get_static()
+            .deep_link
+            .get_or_try_init(|| async {
+                match self.cmd() {
+                    Some(&Cmd {
+                        cmd: "start",
+                        ref args,
+                        ..
+                    }) => match args.args.first() {
+                        Some(arg) => DeepLink::parse(arg.get_text()).await,
+                        None => Ok(None),
+                    },
+                    _ => Ok(None),
+                }
+            })
+            .await?;
+        Ok(link.as_ref())
+    }
+}
+
+#[allow(unused_imports)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn deep_link_signing() {
+        let key = b"test key";
+        let links = [
+            DeepLink::Help(vec![]),
+            DeepLink::Help(vec!["notes".to_owned(), "examples".to_owned()]),
+            DeepLink::Note {
+                chat: -1001234567890,
+                name: "ru_les".to_owned(),
+            },
+            DeepLink::Rules(-1001234567890),
+            DeepLink::Appeal(-1001234567890),
+            DeepLink::Captcha {
+                chat: -1001234567890,
+                user: None,
+            },
+            DeepLink::Captcha {
+                chat: -1001234567890,
+                user: Some(1234567890),
+            },
+        ];
+        for link in links {
+            let payload = sign_with(key, &link);
+            assert!(payload
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'));
+            assert_eq!(parse_with(key, &payload), Some(link));
+            assert_eq!(parse_with(b"other key", &payload), None);
+        }
+    }
+
+    #[test]
+    fn deep_link_forged() {
+        let key = b"test key";
+        let payload = sign_with(key, &DeepLink::Rules(-100));
+        let forged = payload.replacen("-100", "-101", 1);
+        assert_eq!(parse_with(key, &forged), None);
+        assert_eq!(parse_with(key, "rules_-100"), None);
+        assert_eq!(parse_with(key, "help"), None);
+        assert_eq!(parse_with(key, ""), None);
+        assert_eq!(parse_with(key, "üüüüüüüüüüüü"), None);
+    }
+}
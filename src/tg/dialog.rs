@@ -31,16 +31,21 @@ use std::str::FromStr;
 use uuid::Uuid;
 
 use crate::persist::core::{chat_members, dialogs};
-use crate::persist::redis::{default_cache_query, CachedQueryTrait, RedisStr, ToRedisStr};
+use crate::persist::redis::{
+    default_cache_query, CacheScope, CachedQueryTrait, RedisStr, ToRedisStr,
+};
 use crate::statics::{CONFIG, DB, REDIS, TG};
 use crate::tg::button::OnPush;
 use crate::util::error::BotError;
+use crate::util::string::invalidate_chat_lang;
 use log::info;
 
 use std::sync::Arc;
 
 use super::admin_helpers::IntoChatUser;
-use super::button::InlineKeyboardBuilder;
+use super::button::{
+    callback_data, persist_action, ButtonAction, CallbackReply, InlineKeyboardBuilder,
+};
 use super::command::Context;
 use super::markdown::MarkupBuilder;
 pub const TYPE_DIALOG: &str = "DialogDb";
@@ -50,12 +55,24 @@ fn get_conversation_key_prefix(chat: i64, user: i64, prefix: &str) -> String {
     format!("{}:{}:{}", prefix, chat, user)
 }
 
+#[inline(always)]
+fn get_conversation_state_key(conversation: &Uuid) -> String {
+    format!("convst:{}", conversation)
+}
+
 /// get the key for storing chat settings
 #[inline(always)]
 pub fn get_dialog_key(chat: i64) -> String {
     format!("dia:{}", chat)
 }
 
+/// Cache scope for a chat's settings and anything cached from them, like the chat's
+/// language and timezone. Invalidate this after changing any chat setting
+#[inline(always)]
+pub fn dialog_scope(chat: i64) -> CacheScope {
+    CacheScope::new(chat, "dialog")
+}
+
 /// Attempt to record the current dialog from a message.
 /// TODO: Remove this later once all existing chats are updated?
 pub async fn dialog_from_update(update: &UpdateExt) -> Result<()> {
@@ -68,14 +85,18 @@ pub async fn dialog_from_update(update: &UpdateExt) -> Result<()> {
 
 /// Get chat settings for a specific chat
 pub async fn get_dialog(chat: &Chat) -> Result<Option<dialogs::Model>> {
-    let chat_id = chat.get_id();
-    let key = get_dialog_key(chat.get_id());
+    get_dialog_by_id(chat.get_id()).await
+}
+
+/// Get chat settings for a chat id
+pub async fn get_dialog_by_id(chat_id: i64) -> Result<Option<dialogs::Model>> {
+    let key = dialog_scope(chat_id).key(get_dialog_key(chat_id)).await?;
     let res = default_cache_query(
         |_, _| async move {
             let res = dialogs::Entity::find_by_id(chat_id).one(*DB).await?;
             Ok(res)
         },
-        Duration::try_seconds(CONFIG.timing.cache_timeout).unwrap(),
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
     )
     .query(&key, &())
     .await?;
@@ -87,10 +108,7 @@ pub async fn upsert_dialog<T>(db: &T, model: dialogs::ActiveModel) -> Result<()>
 where
     T: ConnectionTrait,
 {
-    if let Set(key) = model.chat_id {
-        let key = get_dialog_key(key);
-        REDIS.sq(|q| q.del(&key)).await?;
-    }
+    let chat = model.chat_id.clone();
     dialogs::Entity::insert(model)
         .on_conflict(
             OnConflict::column(dialogs::Column::ChatId)
@@ -115,12 +133,18 @@ where
         )
         .exec(db)
         .await?;
+    if let Set(chat) = chat {
+        dialog_scope(chat).invalidate().await?;
+        invalidate_chat_lang(chat).await?;
+    }
     Ok(())
 }
 
 /// Get chat settings for a chat or initialize it with the default values
 pub async fn dialog_or_default(chat: &Chat) -> Result<dialogs::Model> {
-    let key = get_dialog_key(chat.get_id());
+    let key = dialog_scope(chat.get_id())
+        .key(get_dialog_key(chat.get_id()))
+        .await?;
     let model = if let Some(model) = get_dialog(chat).await? {
         model
     } else {
@@ -151,7 +175,7 @@ pub async fn dialog_or_default(chat: &Chat) -> Result<dialogs::Model> {
         REDIS
             .try_pipe(|q| {
                 Ok(q.set(&key, d.to_redis()?)
-                    .expire(&key, CONFIG.timing.cache_timeout))
+                    .expire(&key, CONFIG.load().timing.cache_timeout))
             })
             .await?;
         d
@@ -210,7 +234,10 @@ pub async fn update_chat(
         Ok(Box::new(members.into_iter().map(|v| v.into_active_model())))
     } else {
         let (o, _): (Vec<i64>, bool) = REDIS
-            .pipe(|p| p.smembers(&key).expire(&key, CONFIG.timing.cache_timeout))
+            .pipe(|p| {
+                p.smembers(&key)
+                    .expire(&key, CONFIG.load().timing.cache_timeout)
+            })
             .await?;
         Ok(Box::new(o.into_iter().map(move |v| {
             chat_members::ActiveModel {
@@ -289,7 +316,10 @@ impl Context {
 pub async fn record_chat_member(user: i64, chat: i64) -> Result<()> {
     let key = get_member_key(user);
     let (updated, _): (i64, bool) = REDIS
-        .pipe(|q| q.sadd(&key, chat).expire(&key, CONFIG.timing.cache_timeout))
+        .pipe(|q| {
+            q.sadd(&key, chat)
+                .expire(&key, CONFIG.load().timing.cache_timeout)
+        })
         .await?;
     log::info!("record_chat_member {}", updated);
     if updated > 0 {
@@ -313,7 +343,10 @@ pub async fn record_chat_member(user: i64, chat: i64) -> Result<()> {
 pub async fn record_chat_member_banned(user: i64, chat: i64, banned: bool) -> Result<()> {
     let key = get_member_key(user);
     let (updated, _): (i64, bool) = REDIS
-        .pipe(|q| q.sadd(&key, chat).expire(&key, CONFIG.timing.cache_timeout))
+        .pipe(|q| {
+            q.sadd(&key, chat)
+                .expire(&key, CONFIG.load().timing.cache_timeout)
+        })
         .await?;
     log::info!("record_chat_member {}", updated);
     if updated > 0 {
@@ -364,6 +397,12 @@ pub struct ConversationState {
     start: Uuid,
     pub transitions: BTreeMap<(Uuid, String), FSMTransition>,
     rediskey: String,
+    /// only the conversation's user can push its buttons
+    #[serde(default)]
+    owner_only: bool,
+    /// actions run when a persisted button enters a state
+    #[serde(default)]
+    enter_actions: HashMap<Uuid, ButtonAction>,
     #[serde(default, skip)]
     state_callback: Option<Box<dyn Fn(Uuid, Conversation) + Send + Sync>>,
 }
@@ -475,6 +514,19 @@ impl ConversationState {
         self
     }
 
+    /// only let the conversation's user push its buttons, for conversations posted in groups
+    pub fn owner_only(&mut self) -> &mut Self {
+        self.owner_only = true;
+        self
+    }
+
+    /// run an action when a persisted button enters a state. The state is only entered if
+    /// the action is done, otherwise the action's reply is shown and the state stays the same
+    pub fn on_enter(&mut self, state: Uuid, action: ButtonAction) -> &mut Self {
+        self.enter_actions.insert(state, action);
+        self
+    }
+
     /// creates a new Conversation with a redis prefix. This helps if you want
     /// multiple copies of the same conversation each with a different state
     pub fn new_prefix(
@@ -498,6 +550,8 @@ impl ConversationState {
             user,
             transitions: BTreeMap::new(),
             rediskey: get_conversation_key_prefix(chat, user, prefix),
+            owner_only: false,
+            enter_actions: HashMap::new(),
             state_callback: None,
         };
 
@@ -612,17 +666,26 @@ impl Conversation {
                 .chat_id(message.get_chat().get_id())
                 .build()
                 .await?;
-
-            TG.client()
-                .build_answer_callback_query(callback.get_id())
-                .build()
-                .await?;
         }
         Ok(())
     }
 
+    /// Stores the whole conversation so its buttons can be handled after a restart
+    async fn store(&self) -> Result<()> {
+        let key = get_conversation_state_key(&self.0.conversation_id);
+        let conversation = RedisStr::new(self)?;
+        REDIS
+            .pipe(|q| {
+                q.set(&key, conversation)
+                    .expire(&key, CONFIG.load().timing.cache_timeout)
+            })
+            .await?;
+        Ok(())
+    }
+
     /// convert this conversation into a button menu automatically
-    /// Returns markup to add to a message
+    /// Returns markup to add to a message. Buttons for conversations without a state
+    /// callback are persisted and keep working after a restart
     pub fn get_current_markup(
         &self,
         row_limit: usize,
@@ -630,40 +693,67 @@ impl Conversation {
         let me = self.clone();
         async move {
             let state = me.get_current().await?;
-            let markup =
+            let persist = me.0.state_callback.is_none();
+            if persist {
+                me.store().await?;
+            }
+            let mut builder = InlineKeyboardBuilder::default();
+            for t in
                 me.0.transitions
-                    .iter()
-                    .filter(|(_, t)| t.start_state == state.state_id)
-                    .map(|((_, _), t)| {
-                        let b = InlineKeyboardButtonBuilder::new(t.name.clone())
-                            .set_callback_data(Uuid::new_v4().to_string())
-                            .build();
-                        let trans = t.end_state.to_owned();
-                        if let Some(newstate) = me.0.states.get(&t.end_state) {
-                            let content = newstate.content.to_owned();
+                    .values()
+                    .filter(|t| t.start_state == state.state_id)
+            {
+                let b = InlineKeyboardButtonBuilder::new(t.name.clone())
+                    .set_callback_data(callback_data(None))
+                    .build();
+                let trans = t.end_state.to_owned();
+                if persist {
+                    let action = ButtonAction::Transition {
+                        conversation: me.0.conversation_id,
+                        state: trans,
+                        row_limit,
+                    };
+                    persist_action(&b, &action).await?;
+                } else if let Some(newstate) = me.0.states.get(&t.end_state) {
+                    let content = newstate.content.to_owned();
+                    let me = me.clone();
+                    if me.0.owner_only {
+                        // pushes from other users leave the button in place for the owner
+                        b.on_push_multi(move |callback| {
                             let me = me.clone();
-                            b.on_push(move |callback| async move {
+                            let content = content.clone();
+                            async move {
+                                if callback.get_from().get_id() != me.0.user {
+                                    return Ok(false);
+                                }
                                 if let Err(err) = me
                                     .edit_button_transition(trans, content, &callback, row_limit)
                                     .await
                                 {
                                     log::warn!("failed to transition: {}", err);
                                 }
-                                Ok(())
-                            });
-                        }
-                        b
-                    })
-                    .fold(&mut InlineKeyboardBuilder::default(), |builder, st| {
-                        if builder.row_len() < row_limit {
-                            builder.button(st)
-                        } else {
-                            builder.newline().button(st)
-                        }
-                    })
-                    .to_owned()
-                    .build();
-            Ok(markup)
+                                Ok(true)
+                            }
+                        });
+                    } else {
+                        b.on_push(move |callback| async move {
+                            if let Err(err) = me
+                                .edit_button_transition(trans, content, &callback, row_limit)
+                                .await
+                            {
+                                log::warn!("failed to transition: {}", err);
+                            }
+                            Ok(())
+                        });
+                    }
+                }
+                if builder.row_len() < row_limit {
+                    builder.button(b);
+                } else {
+                    builder.newline().button(b);
+                }
+            }
+            Ok(builder.build())
         }
         .boxed()
     }
@@ -679,6 +769,38 @@ impl Conversation {
     }
 }
 
+/// Handles a persisted transition button for a stored conversation, running the new
+/// state's enter action first if it has one
+pub(crate) async fn transition_stored(
+    conversation: Uuid,
+    state: Uuid,
+    row_limit: usize,
+    callback: &CallbackQuery,
+) -> Result<CallbackReply> {
+    let key = get_conversation_state_key(&conversation);
+    let conversation: Option<RedisStr> = REDIS.sq(|q| q.get(&key)).await?;
+    let mut reply = CallbackReply::default();
+    if let Some(conversation) = conversation {
+        let conversation: Conversation = conversation.get()?;
+        if conversation.0.owner_only && callback.get_from().get_id() != conversation.0.user {
+            return Ok(reply);
+        }
+        if let Some(action) = conversation.0.enter_actions.get(&state) {
+            let (done, action_reply) = action.clone().run(callback.clone()).await?;
+            if !done {
+                return Ok(action_reply);
+            }
+            reply = action_reply;
+        }
+        if let Some(content) = conversation.get_state(&state).map(|s| s.content.clone()) {
+            conversation
+                .edit_button_transition(state, content, callback, row_limit)
+                .await?;
+        }
+    }
+    Ok(reply)
+}
+
 /// gets the current conversation for the chat-user pair (from a message's sender)
 pub async fn get_conversation(message: &Message) -> Result<Option<Conversation>> {
     let key = get_conversation_key_message(message)?;
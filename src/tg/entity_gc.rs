@@ -0,0 +1,275 @@
+//! Garbage collection for formatting entities and buttons. Notes, filters and welcomes
+//! keep their entities and buttons under a row in `entitylist`, and overwriting one of
+//! them leaves the old list behind with nothing pointing at it. A background task
+//! periodically deletes lists nobody references, taking their entities and buttons with
+//! them, and keeps counts of what it removed for /gcstats.
+//!
+//! A list is referenced by anything with a foreign key to it. Lists are always inserted in
+//! the same transaction as the row referencing them, so the collector can never see a list
+//! that is about to be used.
+//!
+//! Only one instance collects at a time, claimed in redis, so bots sharing a database
+//! don't fight over the same rows
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use redis::AsyncCommands;
+use sea_orm::{
+    ColumnTrait, DatabaseBackend, EntityTrait, FromQueryResult, PaginatorTrait, QueryFilter,
+    Statement,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::persist::core::{button, entity, messageentity};
+use crate::persist::db::ReadReplica;
+use crate::persist::redis::{RedisStr, ToRedisStr};
+use crate::statics::{DB, REDIS};
+use crate::util::error::Result;
+
+/// Interval between collections
+const GC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Most lists deleted by a single statement
+const BATCH_SIZE: i64 = 1000;
+
+/// Most batches deleted in one collection, the rest wait for the next one
+const MAX_BATCHES: usize = 50;
+
+/// Key claimed by the instance running the current collection
+const GC_CLAIM_KEY: &str = "entitygc:claim";
+
+/// Key holding the most recent collection that removed something
+const GC_LAST_KEY: &str = "entitygc:last";
+
+/// Hash counting everything ever removed
+const GC_TOTAL_KEY: &str = "entitygc:total";
+
+/// Rows removed by the collector
+#[derive(Serialize, Deserialize, Clone, Debug, Default, FromQueryResult)]
+pub struct GcCounts {
+    pub lists: i64,
+    pub entities: i64,
+    pub buttons: i64,
+}
+
+impl GcCounts {
+    fn add(&mut self, other: &GcCounts) {
+        self.lists += other.lists;
+        self.entities += other.entities;
+        self.buttons += other.buttons;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.lists == 0 && self.entities == 0 && self.buttons == 0
+    }
+}
+
+/// A collection that removed something
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GcRun {
+    pub time: DateTime<Utc>,
+    pub removed: GcCounts,
+}
+
+/// Table sizes and collector history for /gcstats
+pub struct GcStats {
+    pub lists: u64,
+    pub entities: u64,
+    pub buttons: u64,
+
+    /// lists nothing references that the next collection will remove
+    pub orphans: i64,
+    pub last: Option<GcRun>,
+    pub total: GcCounts,
+}
+
+#[derive(FromQueryResult)]
+struct Orphans {
+    count: i64,
+}
+
+#[derive(FromQueryResult)]
+struct Reference {
+    table_name: String,
+    column_name: String,
+}
+
+/// Columns with a foreign key to `entitylist`, apart from the entities and buttons it owns.
+/// Read from the catalog so module tables are covered without this knowing about them
+async fn get_references() -> Result<Vec<Reference>> {
+    let references = Reference::find_by_statement(Statement::from_string(
+        DatabaseBackend::Postgres,
+        format!(
+            "SELECT c.conrelid::regclass::text AS table_name, a.attname::text AS column_name
+            FROM pg_constraint c
+            JOIN pg_attribute a ON a.attrelid = c.conrelid AND a.attnum = ANY(c.conkey)
+            WHERE c.contype = 'f' AND c.confrelid = '{}'::regclass
+            AND c.conrelid NOT IN ('{}'::regclass, '{}'::regclass)",
+            entity::Entity.to_string(),
+            messageentity::Entity.to_string(),
+            button::Entity.to_string(),
+        ),
+    ))
+    .all(*DB)
+    .await?;
+    Ok(references)
+}
+
+/// Sql condition true for rows of `entitylist` that nothing references
+async fn orphan_condition() -> Result<String> {
+    let list = entity::Entity.to_string();
+    let id = entity::Column::Id.to_string();
+    let condition = get_references()
+        .await?
+        .into_iter()
+        .map(|Reference { table_name, column_name }| {
+            format!(
+                "NOT EXISTS (SELECT 1 FROM {table_name} WHERE {table_name}.{column_name} = {list}.{id})"
+            )
+        })
+        .chain(["TRUE".to_owned()])
+        .collect::<Vec<String>>()
+        .join(" AND ");
+    Ok(condition)
+}
+
+/// Deletes one batch of unreferenced lists, their entities and buttons going with them
+/// through the cascading foreign keys
+async fn collect_batch(orphans: &str) -> Result<GcCounts> {
+    let list = entity::Entity.to_string();
+    let id = entity::Column::Id.to_string();
+    let entities = messageentity::Entity.to_string();
+    let entities_owner = messageentity::Column::OwnerId.to_string();
+    let buttons = button::Entity.to_string();
+    let buttons_owner = button::Column::OwnerId.to_string();
+    let statement = format!(
+        "WITH orphans AS (
+            SELECT {id} FROM {list} WHERE {orphans} LIMIT {BATCH_SIZE} FOR UPDATE SKIP LOCKED
+        ), lists AS (
+            DELETE FROM {list} WHERE {id} IN (SELECT {id} FROM orphans) RETURNING {id}
+        )
+        SELECT
+            (SELECT count(*) FROM lists) AS lists,
+            (SELECT count(*) FROM {entities} WHERE {entities_owner} IN (SELECT {id} FROM orphans))
+                AS entities,
+            (SELECT count(*) FROM {buttons} WHERE {buttons_owner} IN (SELECT {id} FROM orphans))
+                AS buttons"
+    );
+    let counts =
+        GcCounts::find_by_statement(Statement::from_string(DatabaseBackend::Postgres, statement))
+            .one(*DB)
+            .await?
+            .unwrap_or_default();
+    Ok(counts)
+}
+
+/// Deletes unreferenced lists in batches, then buttons that lost their owner
+async fn collect() -> Result<GcCounts> {
+    let orphans = orphan_condition().await?;
+    let mut removed = GcCounts::default();
+    for _ in 0..MAX_BATCHES {
+        let batch = collect_batch(&orphans).await?;
+        removed.add(&batch);
+        if batch.lists < BATCH_SIZE {
+            break;
+        }
+    }
+
+    let ownerless = button::Entity::delete_many()
+        .filter(button::Column::OwnerId.is_null())
+        .exec(*DB)
+        .await?;
+    removed.buttons += ownerless.rows_affected as i64;
+    Ok(removed)
+}
+
+/// Runs a collection if no other instance is, recording what it removed
+async fn run_gc() -> Result<()> {
+    let claimed = REDIS
+        .set_nx_ex(GC_CLAIM_KEY, GC_INTERVAL.as_secs() as i64 - 1)
+        .await?;
+    if !claimed {
+        return Ok(());
+    }
+
+    let removed = collect().await?;
+    if removed.is_empty() {
+        return Ok(());
+    }
+    log::info!(
+        "entity gc removed {} lists, {} entities, {} buttons",
+        removed.lists,
+        removed.entities,
+        removed.buttons
+    );
+    let run = GcRun {
+        time: Utc::now(),
+        removed,
+    };
+    REDIS
+        .try_pipe(|p| {
+            Ok(p.set(GC_LAST_KEY, run.to_redis()?)
+                .hincr(GC_TOTAL_KEY, "lists", run.removed.lists)
+                .hincr(GC_TOTAL_KEY, "entities", run.removed.entities)
+                .hincr(GC_TOTAL_KEY, "buttons", run.removed.buttons))
+        })
+        .await?;
+    Ok(())
+}
+
+/// Counts rows in the entity tables and reports what the collector has done
+pub async fn gc_stats() -> Result<GcStats> {
+    let lists = entity::Entity::find().count(DB.read()).await?;
+    let entities = messageentity::Entity::find().count(DB.read()).await?;
+    let buttons = button::Entity::find().count(DB.read()).await?;
+    let orphans = Orphans::find_by_statement(Statement::from_string(
+        DatabaseBackend::Postgres,
+        format!(
+            "SELECT count(*) AS count FROM {} WHERE {}",
+            entity::Entity.to_string(),
+            orphan_condition().await?
+        ),
+    ))
+    .one(DB.read())
+    .await?
+    .map(|orphans| orphans.count)
+    .unwrap_or(0);
+
+    let last: Option<RedisStr> = REDIS.sq(|q| q.get(GC_LAST_KEY)).await?;
+    let last = last.map(|last| last.get()).transpose()?;
+    let (lists_total, entities_total, buttons_total): (Option<i64>, Option<i64>, Option<i64>) =
+        REDIS
+            .pipe(|p| {
+                p.hget(GC_TOTAL_KEY, "lists")
+                    .hget(GC_TOTAL_KEY, "entities")
+                    .hget(GC_TOTAL_KEY, "buttons")
+            })
+            .await?;
+    Ok(GcStats {
+        lists,
+        entities,
+        buttons,
+        orphans,
+        last,
+        total: GcCounts {
+            lists: lists_total.unwrap_or(0),
+            entities: entities_total.unwrap_or(0),
+            buttons: buttons_total.unwrap_or(0),
+        },
+    })
+}
+
+/// Start the background task removing unreferenced entities and buttons
+pub fn spawn_entity_gc() -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(GC_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = run_gc().await {
+                log::warn!("entity gc failed: {}", err);
+                err.record_stats();
+            }
+        }
+    })
+}
@@ -0,0 +1,267 @@
+//! Checks new members against banlists maintained outside the bot, like Combot Anti-Spam
+//! (CAS) and any extra http banlists listed in the config. Chats opt in with /antispam and
+//! choose what happens to members found on a list.
+//!
+//! Lookups are cached per user in redis. Failed lookups are logged and treated as not
+//! listed so an unreachable banlist never blocks anyone from joining. They aren't cached, so
+//! the user is checked again next time. Hits are reported to the chat's log channel
+
+use std::time::Duration as StdDuration;
+
+use botapi::gen_types::{ChatMemberUpdated, User};
+use chrono::Duration;
+use lazy_static::lazy_static;
+use macros::lang_fmt;
+use redis::AsyncCommands;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::EntityTrait;
+use serde::Deserialize;
+
+use crate::persist::admin::antispam::{self, AntiSpamAction};
+use crate::persist::redis::{default_cache_query, CachedQueryTrait, RedisCache, RedisStr};
+use crate::statics::{CONFIG, DB, ME, REDIS, TG};
+use crate::util::error::Result;
+
+use super::admin_helpers::kick;
+use super::command::Context;
+use super::dialog::record_chat_member_banned;
+use super::log_channel::send_log;
+use super::permissions::IsAdmin;
+use super::user::Username;
+
+/// Name shown to admins for hits on the CAS api
+const CAS_NAME: &str = "CAS";
+
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+/// Gets the timeout for a single banlist request, read each time so /reloadconfig applies
+fn lookup_timeout() -> StdDuration {
+    StdDuration::from_millis(CONFIG.load().external_bans.timeout)
+}
+
+#[derive(Deserialize)]
+struct CasResponse {
+    ok: bool,
+}
+
+#[inline(always)]
+fn get_antispam_key(chat: i64) -> String {
+    format!("aspam:{}", chat)
+}
+
+#[inline(always)]
+fn get_lookup_key(user: i64) -> String {
+    format!("extban:{}", user)
+}
+
+/// Gets the antispam settings for a chat, None if they were never set
+pub async fn get_antispam(chat: i64) -> Result<Option<antispam::Model>> {
+    let key = get_antispam_key(chat);
+    let res = default_cache_query(
+        |_, _| async move {
+            let res = antispam::Entity::find_by_id(chat).one(*DB).await?;
+            Ok(res)
+        },
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
+    )
+    .query(&key, &())
+    .await?;
+    Ok(res)
+}
+
+async fn update_antispam(
+    chat: i64,
+    model: antispam::ActiveModel,
+    column: antispam::Column,
+) -> Result<antispam::Model> {
+    let key = get_antispam_key(chat);
+    let model = antispam::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(antispam::Column::Chat)
+                .update_column(column)
+                .to_owned(),
+        )
+        .exec_with_returning(*DB)
+        .await?;
+    model.cache(key).await?;
+    Ok(model)
+}
+
+/// Enables or disables checking new members against external banlists in a chat
+pub async fn set_antispam_enabled(chat: i64, enabled: bool) -> Result<antispam::Model> {
+    let model = antispam::ActiveModel {
+        chat: Set(chat),
+        enabled: Set(enabled),
+        action: NotSet,
+    };
+    update_antispam(chat, model, antispam::Column::Enabled).await
+}
+
+/// Sets what happens to new members found on an external banlist in a chat
+pub async fn set_antispam_action(chat: i64, action: AntiSpamAction) -> Result<antispam::Model> {
+    let model = antispam::ActiveModel {
+        chat: Set(chat),
+        enabled: NotSet,
+        action: Set(action),
+    };
+    update_antispam(chat, model, antispam::Column::Action).await
+}
+
+async fn check_cas(user: i64) -> Result<bool> {
+    let url = format!("{}?user_id={}", CONFIG.load().external_bans.cas_url, user);
+    let body = CLIENT
+        .get(url)
+        .timeout(lookup_timeout())
+        .send()
+        .await?
+        .bytes()
+        .await?;
+    let res: CasResponse = serde_json::from_slice(&body)?;
+    Ok(res.ok)
+}
+
+async fn check_banlist(url: &str, user: i64) -> Result<bool> {
+    let url = url.replace("{user}", &user.to_string());
+    let res = CLIENT.get(url).timeout(lookup_timeout()).send().await?;
+    Ok(res.status().is_success())
+}
+
+/// Gets the hostname of a banlist url to show to admins
+fn banlist_name(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_owned()))
+        .unwrap_or_else(|| url.to_owned())
+}
+
+/// Looks a user up on every configured banlist, returning the first one they are on. Fails
+/// if they weren't found on any list but one of the lookups failed
+async fn lookup(user: i64) -> Result<Option<String>> {
+    let config = CONFIG.load_full();
+    let config = &config.external_bans;
+    let mut failed = None;
+    if config.cas {
+        match check_cas(user).await {
+            Ok(true) => return Ok(Some(CAS_NAME.to_owned())),
+            Ok(false) => (),
+            Err(err) => {
+                log::warn!("CAS lookup for {} failed: {}", user, err);
+                failed = Some(err);
+            }
+        }
+    }
+
+    for url in config.banlists.iter() {
+        match check_banlist(url, user).await {
+            Ok(true) => return Ok(Some(banlist_name(url))),
+            Ok(false) => (),
+            Err(err) => {
+                log::warn!("banlist lookup for {} failed: {}", user, err);
+                failed = Some(err);
+            }
+        }
+    }
+    match failed {
+        Some(err) => Err(err),
+        None => Ok(None),
+    }
+}
+
+/// Checks a user against every configured external banlist, returning the name of the
+/// first list they are on
+pub async fn check_external_bans(user: i64) -> Result<Option<String>> {
+    let key = get_lookup_key(user);
+    let cached: Option<RedisStr> = REDIS.sq(|q| q.get(&key)).await?;
+    if let Some(cached) = cached {
+        return cached.get();
+    }
+
+    let Ok(res) = lookup(user).await else {
+        return Ok(None);
+    };
+    let r = RedisStr::new(&res)?;
+    REDIS
+        .pipe(|q| {
+            q.set(&key, r)
+                .expire(&key, CONFIG.load().external_bans.cache_time)
+        })
+        .await?;
+    Ok(res)
+}
+
+async fn apply_action(
+    ctx: &Context,
+    member: &ChatMemberUpdated,
+    action: AntiSpamAction,
+) -> Result<()> {
+    let chat = member.get_chat();
+    let user = member.get_from().get_id();
+    match action {
+        AntiSpamAction::Ban => {
+            TG.client
+                .build_ban_chat_member(chat.get_id(), user)
+                .build()
+                .await?;
+            record_chat_member_banned(user, chat.get_id(), true).await?;
+        }
+        AntiSpamAction::Kick => kick(user, chat.get_id()).await?,
+        AntiSpamAction::Mute => ctx.mute(user, chat, None).await?,
+    }
+    Ok(())
+}
+
+async fn handle_hit(
+    ctx: &Context,
+    member: &ChatMemberUpdated,
+    config: &antispam::Model,
+    user: &User,
+    list: String,
+) -> Result<()> {
+    apply_action(ctx, member, config.action).await?;
+    let chat = member.get_chat();
+    let text = lang_fmt!(
+        ctx,
+        "antispamhit",
+        user.name_humanreadable(),
+        user.get_id(),
+        list,
+        config.action.get_name()
+    );
+    send_log(chat, &text).await?;
+    Ok(())
+}
+
+/// Checks a member joining a chat with antispam enabled against external banlists,
+/// applying the chat's action if they are listed
+pub async fn check_new_member(ctx: &Context, member: &ChatMemberUpdated) -> Result<()> {
+    let chat = member.get_chat();
+    let Some(config) = get_antispam(chat.get_id()).await?.filter(|c| c.enabled) else {
+        return Ok(());
+    };
+    let user = member.get_from();
+    if user.get_id() == ME.get().unwrap().get_id() || user.is_admin(chat).await? {
+        return Ok(());
+    }
+
+    if let Some(list) = check_external_bans(user.get_id()).await? {
+        handle_hit(ctx, member, &config, user, list).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn banlist_names() {
+        assert_eq!(
+            banlist_name("https://bans.example.com/check/{user}"),
+            "bans.example.com"
+        );
+        assert_eq!(banlist_name("not a url"), "not a url");
+    }
+}
@@ -0,0 +1,295 @@
+//! Extractors for command handlers. Instead of checking permissions, resolving users, and
+//! parsing durations by hand at the top of every command, handlers list what they need as
+//! parameters and [`Context::run`] resolves them in order before calling the handler.
+//! Anything that can't be resolved fails with the same localized error the hand written
+//! checks would give, and the handler isn't called.
+//!
+//! ```ignore
+//! async fn warn(ctx: &Context, _: RequirePerm<CanRestrictMembers>, target: TargetUser<'_>) -> Result<()> {
+//!     ctx.warn_with_action(target.id, None, None).await?;
+//!     Ok(())
+//! }
+//!
+//! match cmd {
+//!     "warn" => ctx.run(warn).await,
+//!     _ => Ok(()),
+//! }
+//! ```
+//!
+//! Commands are still routed with a match on the command name, since the name can't be part
+//! of a parameter's type
+
+use std::future::Future;
+use std::marker::PhantomData;
+
+use async_trait::async_trait;
+use chrono::Duration;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use macros::lang_fmt;
+
+use crate::util::error::{BotError, Fail, Result};
+
+use super::command::{ArgSlice, Context, TextArgs};
+use super::permissions::{IsGroupAdmin, NamedBotPermissions, NamedPermission};
+use super::user::resolve_user_target;
+
+/// A value a command handler can take as a parameter, resolved from the update
+#[async_trait]
+pub trait FromContext<'a>: Sized + Send {
+    /// Resolves the value, failing with a localized error if the update doesn't have it
+    async fn from_context(ctx: &'a Context) -> Result<Self>;
+}
+
+/// Fails unless the command was used in a supergroup
+pub struct InGroup;
+
+#[async_trait]
+impl<'a> FromContext<'a> for InGroup {
+    async fn from_context(ctx: &'a Context) -> Result<Self> {
+        ctx.is_group_or_die().await?;
+        Ok(Self)
+    }
+}
+
+/// An admin permission checked by [`RequirePerm`] or required by a command in metadata!
+pub trait Permission: Send {
+    /// Name shown in /help
+    const NAME: &'static str;
+
+    fn get(permissions: NamedBotPermissions) -> NamedPermission;
+}
+
+macro_rules! permission {
+    ( $( $(#[$doc:meta])* $name:ident => $field:ident ),+ $(,)? ) => {
+        $(
+            $(#[$doc])*
+            pub struct $name;
+
+            impl Permission for $name {
+                const NAME: &'static str = stringify!($name);
+
+                fn get(permissions: NamedBotPermissions) -> NamedPermission {
+                    permissions.$field
+                }
+            }
+        )+
+    };
+}
+
+permission!(
+    /// Manage the chat
+    CanManageChat => can_manage_chat,
+    /// Ban, mute, and restrict members
+    CanRestrictMembers => can_restrict_members,
+    /// Delete messages from other members
+    CanDeleteMessages => can_delete_messages,
+    /// Change the chat's title, photo, and settings
+    CanChangeInfo => can_change_info,
+    /// Add new admins
+    CanPromoteMembers => can_promote_members,
+    /// Pin messages
+    CanPinMessages => can_pin_messages,
+    /// Create, rename, close, and reopen forum topics
+    CanManageTopics => can_manage_topics,
+    /// Invite users and manage invite links
+    CanInviteUsers => can_invite_users,
+    /// Post in a channel
+    CanPostMessages => can_post_messages,
+    /// Edit other admins' posts in a channel
+    CanEditMessages => can_edit_messages,
+    /// Start and manage video chats
+    CanManageVideoChats => can_manage_video_chats,
+    /// Posting anonymously as the chat
+    IsAnonymous => is_anonymous,
+);
+
+/// A permission a command requires, declared with `perms = [...]` in metadata!. Required
+/// permissions are checked before any module sees the command
+#[derive(Clone, Copy)]
+pub struct RequiredPermission {
+    pub name: &'static str,
+    pub get: fn(NamedBotPermissions) -> NamedPermission,
+}
+
+impl RequiredPermission {
+    pub fn of<P: Permission>() -> Self {
+        Self {
+            name: P::NAME,
+            get: P::get,
+        }
+    }
+}
+
+/// Fails unless the sender has the admin permission `P`
+pub struct RequirePerm<P: Permission>(PhantomData<P>);
+
+#[async_trait]
+impl<'a, P: Permission> FromContext<'a> for RequirePerm<P> {
+    async fn from_context(ctx: &'a Context) -> Result<Self> {
+        ctx.check_permissions(P::get).await?;
+        Ok(Self(PhantomData))
+    }
+}
+
+/// The user a command is about, from a reply, an @ handle, a text mention, or a user id.
+/// Users given by id don't have to be known to the bot
+pub struct TargetUser<'a> {
+    pub id: i64,
+    /// arguments after the user
+    pub args: Option<ArgSlice<'a>>,
+}
+
+#[async_trait]
+impl<'a> FromContext<'a> for TargetUser<'a> {
+    async fn from_context(ctx: &'a Context) -> Result<Self> {
+        match resolve_user_target(ctx).await {
+            Ok((Some(target), args)) => Ok(Self {
+                id: target.get_id(),
+                args,
+            }),
+            Ok((None, _)) => ctx.fail(lang_fmt!(ctx, "specifyuser")),
+            Err(BotError::UserNotFound) => ctx.fail(lang_fmt!(ctx, "usernotfound")),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// An optional duration like 5m or 1d2h, taken from the first argument after the user the
+/// command is about if there is one. Durations that don't parse fail instead of being ignored
+pub struct DurationArg(pub Option<Duration>);
+
+#[async_trait]
+impl<'a> FromContext<'a> for DurationArg {
+    async fn from_context(ctx: &'a Context) -> Result<Self> {
+        let args = match resolve_user_target(ctx).await {
+            Ok((Some(_), args)) => args,
+            _ => ctx.cmd().map(|cmd| cmd.args.as_slice()),
+        };
+        let matches = args.map(|args| args.matches()).unwrap_or_default();
+        let duration = matches
+            .positional()
+            .first()
+            .map(|arg| ctx.parse_duration_arg(arg.get_text()))
+            .transpose()?;
+        Ok(Self(duration))
+    }
+}
+
+/// An optional duration followed by an optional reason, like `1d spamming`, taken from the
+/// arguments after the user the command is about. If the first argument isn't a duration it
+/// starts the reason instead
+pub struct ActionArgs {
+    pub duration: Option<Duration>,
+    pub reason: Option<String>,
+}
+
+#[async_trait]
+impl<'a> FromContext<'a> for ActionArgs {
+    async fn from_context(ctx: &'a Context) -> Result<Self> {
+        let args = match resolve_user_target(ctx).await {
+            Ok((Some(_), args)) => args,
+            _ => ctx.cmd().map(|cmd| cmd.args.as_slice()),
+        };
+        let matches = args.map(|args| args.matches()).unwrap_or_default();
+        let mut positional = matches.positional().iter().map(|arg| arg.get_text());
+        let mut words = Vec::new();
+        let duration = match positional.next() {
+            Some(first) => match ctx.parse_duration_arg(first) {
+                Ok(duration) => Some(duration),
+                Err(_) => {
+                    words.push(first);
+                    None
+                }
+            },
+            None => None,
+        };
+        words.extend(positional);
+        let reason = Some(words.join(" ")).filter(|reason| !reason.is_empty());
+        Ok(Self { duration, reason })
+    }
+}
+
+/// The arguments of the command
+pub struct CommandArgs<'a>(pub &'a TextArgs<'a>);
+
+#[async_trait]
+impl<'a> FromContext<'a> for CommandArgs<'a> {
+    async fn from_context(ctx: &'a Context) -> Result<Self> {
+        ctx.cmd()
+            .map(|cmd| Self(&cmd.args))
+            .ok_or_else(|| BotError::generic("not a command"))
+    }
+}
+
+macro_rules! tuple_from_context {
+    ( $( $ty:ident ),+ ) => {
+        #[async_trait]
+        impl<'a, $( $ty ),+> FromContext<'a> for ( $( $ty, )+ )
+        where
+            $( $ty: FromContext<'a> ),+
+        {
+            async fn from_context(ctx: &'a Context) -> Result<Self> {
+                Ok(( $( $ty::from_context(ctx).await?, )+ ))
+            }
+        }
+    };
+}
+
+tuple_from_context!(A);
+tuple_from_context!(A, B);
+tuple_from_context!(A, B, C);
+tuple_from_context!(A, B, C, D);
+
+/// A command handler taking the context followed by extractors, resolved in order
+pub trait Handler<'a, T>: Send {
+    fn call(self, ctx: &'a Context) -> BoxFuture<'a, Result<()>>;
+}
+
+impl<'a, F, Fut> Handler<'a, ()> for F
+where
+    F: FnOnce(&'a Context) -> Fut + Send + 'a,
+    Fut: Future<Output = Result<()>> + Send + 'a,
+{
+    fn call(self, ctx: &'a Context) -> BoxFuture<'a, Result<()>> {
+        self(ctx).boxed()
+    }
+}
+
+macro_rules! handler {
+    ( $( $ty:ident ),+ ) => {
+        #[allow(non_snake_case)]
+        impl<'a, F, Fut, $( $ty ),+> Handler<'a, ( $( $ty, )+ )> for F
+        where
+            F: FnOnce(&'a Context, $( $ty ),+) -> Fut + Send + 'a,
+            Fut: Future<Output = Result<()>> + Send + 'a,
+            $( $ty: FromContext<'a> + 'a ),+
+        {
+            fn call(self, ctx: &'a Context) -> BoxFuture<'a, Result<()>> {
+                async move {
+                    $( let $ty = $ty::from_context(ctx).await?; )+
+                    self(ctx, $( $ty ),+).await
+                }
+                .boxed()
+            }
+        }
+    };
+}
+
+handler!(A);
+handler!(A, B);
+handler!(A, B, C);
+handler!(A, B, C, D);
+
+impl Context {
+    /// Resolves extractors from this context, see [`FromContext`]
+    pub async fn extract<'a, T: FromContext<'a>>(&'a self) -> Result<T> {
+        T::from_context(self).await
+    }
+
+    /// Resolves a handler's extractors in order and calls it. If an extractor fails the
+    /// handler isn't called and the error, usually already told to the user, is returned
+    pub async fn run<'a, T, H: Handler<'a, T>>(&'a self, handler: H) -> Result<()> {
+        handler.call(self).await
+    }
+}
@@ -10,7 +10,8 @@ use crate::{
     },
     statics::{BAN_GOVERNER, CONFIG, DB, REDIS, TG},
     util::error::{BotError, Fail, Result, SpeakErr},
-    util::string::Speak,
+    util::error_codes::NOT_FOUND,
+    util::string::{get_chat_lang, Speak},
 };
 
 use botapi::gen_types::{
@@ -25,8 +26,8 @@ use redis::AsyncCommands;
 
 use sea_orm::{
     sea_query::OnConflict, ActiveValue::NotSet, ActiveValue::Set, ColumnTrait, ConnectionTrait,
-    EntityTrait, FromQueryResult, IntoActiveModel, JoinType, ModelTrait, QueryFilter, QuerySelect,
-    Statement,
+    EntityTrait, FromQueryResult, IntoActiveModel, JoinType, ModelTrait, PaginatorTrait,
+    QueryFilter, QuerySelect, Statement,
 };
 use sea_query::{
     Alias, ColumnRef, CommonTableExpression, Expr, Query, QueryStatementBuilder, UnionType,
@@ -36,7 +37,8 @@ use uuid::Uuid;
 
 use super::{
     admin_helpers::insert_user,
-    button::{InlineKeyboardBuilder, OnPush},
+    bots,
+    button::{callback_data, CallbackReply, InlineKeyboardBuilder, OnPush},
     command::Context,
     dialog::{get_user_banned_chats, record_chat_member_banned, reset_banned_chats, upsert_dialog},
     markdown::MarkupType,
@@ -229,8 +231,9 @@ pub async fn is_user_fbanned(user: i64, chat: i64, reply: i64) -> Result<Option<
                 return Ok(None);
             }
         }
+        let lang = get_chat_lang(chat).await?;
         Err(BotError::speak(
-            "retries exceeded for updating fban cache",
+            lang_fmt!(lang, "errfbancache"),
             chat,
             Some(reply),
         ))
@@ -269,7 +272,7 @@ pub async fn create_federation(ctx: &Context, federation: federations::Model) ->
             REDIS
                 .try_pipe(|q| {
                     Ok(q.set(&key, Some(v).to_redis()?)
-                        .expire(&key, CONFIG.timing.cache_timeout))
+                        .expire(&key, CONFIG.load().timing.cache_timeout))
                 })
                 .await?;
         }
@@ -348,7 +351,7 @@ pub async fn is_fedmember(chat: i64) -> Result<Option<Uuid>> {
             .pipe(|p| {
                 p.exists(&key)
                     .hget(&key, chat)
-                    .expire(&key, CONFIG.timing.cache_timeout)
+                    .expire(&key, CONFIG.load().timing.cache_timeout)
             })
             .await?;
         match (exists, member) {
@@ -408,6 +411,51 @@ async fn iter_unfban_user(user: i64, fed: &Uuid) -> Result<()> {
     Ok(())
 }
 
+/// Lifts a user's fban in a federation, unbanning them in every chat it was enforced in.
+/// Returns false if the user wasn't fbanned
+pub async fn remove_fban(user: i64, fed: &Uuid) -> Result<bool> {
+    let key = get_fban_set_key(fed);
+    iter_unfban_user(user, fed).await?;
+    let res = fbans::Entity::delete_many()
+        .filter(
+            fbans::Column::Federation
+                .eq(*fed)
+                .and(fbans::Column::User.eq(user)),
+        )
+        .exec(*DB)
+        .await?;
+    REDIS.sq(|q| q.del(&key)).await?;
+    Ok(res.rows_affected > 0)
+}
+
+/// Gets a user's fban in a federation, if they have one
+pub async fn get_fban(user: i64, fed: &Uuid) -> Result<Option<fbans::Model>> {
+    let res = fbans::Entity::find()
+        .filter(
+            fbans::Column::Federation
+                .eq(*fed)
+                .and(fbans::Column::User.eq(user)),
+        )
+        .one(*DB)
+        .await?;
+    Ok(res)
+}
+
+/// Counts the chats that joined a federation
+pub async fn count_fed_chats(fed: &Uuid) -> Result<u64> {
+    let res = dialogs::Entity::find()
+        .filter(dialogs::Column::Federation.eq(*fed))
+        .count(*DB)
+        .await?;
+    Ok(res)
+}
+
+/// Gets a federation by its id
+pub async fn get_fed_by_id(fed: &Uuid) -> Result<Option<federations::Model>> {
+    let res = federations::Entity::find_by_id(*fed).one(*DB).await?;
+    Ok(res)
+}
+
 pub async fn fstat(user: i64) -> Result<impl Iterator<Item = (fbans::Model, federations::Model)>> {
     let res = fbans::Entity::find()
         .filter(fbans::Column::User.eq(user))
@@ -440,7 +488,7 @@ pub async fn is_user_gbanned(user: i64) -> Result<Option<(gbans::Model, users::M
                 .await?;
             Ok(o)
         },
-        Duration::try_seconds(CONFIG.timing.cache_timeout).unwrap(),
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
     )
     .query(&key, &())
     .await?;
@@ -545,11 +593,11 @@ pub async fn try_update_fed_cache(chat: i64) -> Result<()> {
             for (chat, fed) in feds {
                 if let Some(fed) = fed {
                     p.hset(&key, chat.chat_id, fed.fed_id.to_redis()?)
-                        .expire(&key, CONFIG.timing.cache_timeout);
+                        .expire(&key, CONFIG.load().timing.cache_timeout);
 
                     let key = get_fed_key(fed.owner);
                     p.set(&key, Some(fed).to_redis()?)
-                        .expire(&key, CONFIG.timing.cache_timeout);
+                        .expire(&key, CONFIG.load().timing.cache_timeout);
                 }
             }
             Ok(p)
@@ -633,7 +681,7 @@ pub async fn try_update_fban_cache(user: i64) -> Result<()> {
 
                 let fed_key = get_fed_key(federation_model.owner);
                 p.set(&fed_key, Some(&federation_model).to_redis()?)
-                    .expire(&fed_key, CONFIG.timing.cache_timeout);
+                    .expire(&fed_key, CONFIG.load().timing.cache_timeout);
                 if let (Some(fban_id), Some(federation), Some(user), Some(chat_id)) =
                     (fban_id, federation, user, chat_id)
                 {
@@ -650,7 +698,7 @@ pub async fn try_update_fban_cache(user: i64) -> Result<()> {
                     let fban_key = get_fban_key(&fbans.fban_id);
 
                     p.set(&fban_key, fbans.to_redis()?)
-                        .expire(&fban_key, CONFIG.timing.cache_timeout);
+                        .expire(&fban_key, CONFIG.load().timing.cache_timeout);
 
                     if let Some((cache, _)) = fban_cache.get_mut(&federation_model.fed_id) {
                         cache.insert((user, fbans.fban_id));
@@ -746,60 +794,37 @@ impl Context {
             let mut builder = InlineKeyboardBuilder::default();
 
             let confirm = InlineKeyboardButtonBuilder::new("Confirm".to_owned())
-                .set_callback_data(Uuid::new_v4().to_string())
+                .set_callback_data(callback_data(Some(user)))
                 .build();
 
             let cancel = InlineKeyboardButtonBuilder::new("Cancel".to_owned())
-                .set_callback_data(Uuid::new_v4().to_string())
+                .set_callback_data(callback_data(Some(user)))
                 .build();
             let lang = *self.lang();
             confirm.on_push_multi(move |callback| async move {
                 if callback.get_from().get_id() != user {
-                    TG.client
-                        .build_answer_callback_query(callback.get_id())
-                        .show_alert(true)
-                        .text(&lang_fmt!(lang, "fpromotenotauth"))
-                        .build()
-                        .await?;
-                    return Ok(false);
+                    let reply = CallbackReply::alert(lang_fmt!(lang, "fpromotenotauth"));
+                    return Ok((false, reply));
                 }
                 if let Some(MaybeInaccessibleMessage::Message(message)) = callback.get_message() {
                     TG.client
                         .build_delete_message(chat, message.get_message_id())
                         .build()
                         .await?;
-                    match fpromote(fed.fed_id, user).await {
-                        Ok(_) => {
-                            TG.client
-                                .build_answer_callback_query(callback.get_id())
-                                .show_alert(true)
-                                .text(&lang_fmt!(lang, "fpromoted"))
-                                .build()
-                                .await
-                        }
-                        Err(err) => {
-                            TG.client
-                                .build_answer_callback_query(callback.get_id())
-                                .show_alert(true)
-                                .text(&lang_fmt!(lang, "failfpromote", err))
-                                .build()
-                                .await
-                        }
-                    }?;
+                    let reply = match fpromote(fed.fed_id, user).await {
+                        Ok(_) => CallbackReply::alert(lang_fmt!(lang, "fpromoted")),
+                        Err(err) => CallbackReply::alert(lang_fmt!(lang, "failfpromote", err)),
+                    };
+                    return Ok((true, reply));
                 }
 
-                Ok(true)
+                Ok((true, CallbackReply::default()))
             });
 
             cancel.on_push_multi(move |callback| async move {
                 if callback.get_from().get_id() != me {
-                    TG.client
-                        .build_answer_callback_query(callback.get_id())
-                        .show_alert(true)
-                        .text("You are not the fed owner")
-                        .build()
-                        .await?;
-                    return Ok(false);
+                    let reply = CallbackReply::alert("You are not the fed owner");
+                    return Ok((false, reply));
                 }
                 if let Some(MaybeInaccessibleMessage::Message(message)) = callback.get_message() {
                     TG.client
@@ -816,12 +841,8 @@ impl Context {
                         .build()
                         .await?;
                 }
-                TG.client
-                    .build_answer_callback_query(callback.get_id())
-                    .build()
-                    .await?;
 
-                Ok(true)
+                Ok((true, CallbackReply::default()))
             });
 
             builder.button(confirm);
@@ -852,30 +873,38 @@ impl Context {
         let delete = gbans::Entity::delete_by_id(user).exec(*DB).await?;
         if delete.rows_affected > 0 {
             REDIS.sq(|q| q.del(&key)).await?;
-            tokio::spawn(async move { iter_unban_user(user).await.log() });
+            bots::spawn(async move { iter_unban_user(user).await.log() });
 
             Ok(())
         } else {
-            self.fail("User is not gbanned")
+            self.fail_code(NOT_FOUND, lang_fmt!(self, "errnotgbanned"))
         }
     }
 
-    pub async fn handle_gbans(&self) {
+    /// Bans the sender of a message if they are gbanned or fbanned in a federation the chat is
+    /// in. Returns true if the sender was banned
+    pub async fn handle_gbans(&self) -> bool {
         if let UpdateExt::Message(ref message) = self.update() {
             if message.get_sender_chat().is_none() {
                 if let Some(user) = message.get_from() {
-                    if let Err(err) = self.single_gban(user.get_id()).await {
-                        log::warn!("Failed to gban {}: {}", user.name_humanreadable(), err);
-                        err.record_stats();
+                    match self.single_gban(user.get_id()).await {
+                        Ok(banned) => return banned,
+                        Err(err) => {
+                            log::warn!("Failed to gban {}: {}", user.name_humanreadable(), err);
+                            err.record_stats();
+                        }
                     }
                 }
             }
         }
+        false
     }
 
-    async fn single_gban(&self, user: i64) -> Result<()> {
+    async fn single_gban(&self, user: i64) -> Result<bool> {
         let chat = self.try_get()?.chat.get_id();
+        let mut banned = false;
         if let Some((gban, user)) = is_user_gbanned(user).await? {
+            banned = true;
             record_chat_member_banned(user.user_id, chat, true).await?;
 
             TG.client
@@ -891,6 +920,7 @@ impl Context {
         }
 
         if let Some(model) = is_user_fbanned(user, chat, self.message()?.message_id).await? {
+            banned = true;
             TG.client
                 .build_ban_chat_member(chat, model.user)
                 .build()
@@ -902,6 +932,6 @@ impl Context {
             ))
             .await?;
         }
-        Ok(())
+        Ok(banned)
     }
 }
@@ -0,0 +1,332 @@
+//! Giveaways. /giveaway posts a prize with a button members push to enter. Entries are
+//! stored in the database, one per member, and bots or users who aren't members of the chat
+//! can't enter. When the giveaway ends the scheduler draws the winners at random and
+//! announces them in reply to the post. If the announcement fails the draw is tried again
+//! later with the same winners. Admins can draw someone else with /reroll when a
+//! winner doesn't claim their prize, and members who already won aren't drawn again
+
+use botapi::gen_types::{
+    CallbackQuery, EReplyMarkup, InlineKeyboardButtonBuilder, InlineKeyboardMarkup,
+};
+use chrono::{Duration, Utc};
+use macros::lang_fmt;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter, QueryOrder,
+};
+
+use crate::persist::core::{giveaway_entries, giveaways};
+use crate::statics::{DB, TG};
+use crate::util::error::{BotError, Result};
+use crate::util::string::{get_chat_lang, Lang, Speak};
+use crate::util::time::ChatTime;
+
+use super::button::{
+    callback_data, persist_action_for, ButtonAction, CallbackReply, InlineKeyboardBuilder,
+};
+use super::markdown::{EntityMessage, Escape};
+use super::scheduler::{schedule_job, Job, JobKind};
+use super::user::{is_member, GetUser};
+
+/// Most winners a giveaway can have
+pub const MAX_WINNERS: i32 = 50;
+
+/// Extra seconds the enter button is kept after a giveaway ends, so late pushes are told
+/// the giveaway is over
+const EXPIRE_GRACE: i64 = 60 * 60;
+
+/// Seconds between attempts to announce a giveaway's winners when announcing fails
+const DRAW_RETRY_DELAY: i64 = 5 * 60;
+
+/// Seconds after a giveaway ends that announcing its winners is still retried
+const DRAW_RETRY_WINDOW: i64 = 24 * 60 * 60;
+
+/// Gets a giveaway by id
+pub async fn get_giveaway(id: i64) -> Result<Option<giveaways::Model>> {
+    Ok(giveaways::Entity::find_by_id(id).one(*DB).await?)
+}
+
+/// Gets the giveaway posted as a message
+pub async fn get_giveaway_by_message(chat: i64, message: i64) -> Result<Option<giveaways::Model>> {
+    Ok(giveaways::Entity::find()
+        .filter(
+            giveaways::Column::ChatId
+                .eq(chat)
+                .and(giveaways::Column::MessageId.eq(message)),
+        )
+        .one(*DB)
+        .await?)
+}
+
+/// Gets the chat's most recently started giveaway
+pub async fn last_giveaway(chat: i64) -> Result<Option<giveaways::Model>> {
+    Ok(giveaways::Entity::find()
+        .filter(giveaways::Column::ChatId.eq(chat))
+        .order_by_desc(giveaways::Column::Id)
+        .one(*DB)
+        .await?)
+}
+
+async fn post_text(lang: &Lang, giveaway: &giveaways::Model) -> Result<String> {
+    let local = ChatTime::get(giveaway.chat_id).await?;
+    Ok(lang_fmt!(
+        lang,
+        "giveawaypost",
+        giveaway.prize.escape(false),
+        giveaway.winners,
+        local.format_lang(&giveaway.ends, lang)
+    ))
+}
+
+/// Posts a giveaway in a chat and schedules the draw for when it ends
+pub async fn start_giveaway(
+    chat: i64,
+    lang: &Lang,
+    author: i64,
+    duration: Duration,
+    winners: i32,
+    prize: String,
+) -> Result<giveaways::Model> {
+    let ends = Utc::now() + duration;
+    let giveaway = giveaways::ActiveModel {
+        id: NotSet,
+        chat_id: Set(chat),
+        message_id: Set(None),
+        prize: Set(prize),
+        winners: Set(winners),
+        author: Set(author),
+        ends: Set(ends),
+        ended: Set(false),
+        announced: Set(false),
+    };
+    let giveaway = giveaways::Entity::insert(giveaway)
+        .exec_with_returning(*DB)
+        .await?;
+
+    let enter = InlineKeyboardButtonBuilder::new(lang_fmt!(lang, "giveawayenter"))
+        .set_callback_data(callback_data(None))
+        .build();
+    let action = ButtonAction::GiveawayEnter { id: giveaway.id };
+    persist_action_for(&enter, &action, duration.num_seconds() + EXPIRE_GRACE).await?;
+    let mut buttons = InlineKeyboardBuilder::default();
+    buttons.button(enter);
+    let post = EntityMessage::from_text(chat, post_text(lang, &giveaway).await?)
+        .reply_markup(EReplyMarkup::InlineKeyboardMarkup(buttons.build()));
+    let message = match chat.speak_fmt(post).await {
+        Ok(Some(message)) => message,
+        res => {
+            giveaways::Entity::delete_by_id(giveaway.id)
+                .exec(*DB)
+                .await?;
+            res?;
+            return Err(BotError::generic("giveaway post wasn't sent"));
+        }
+    };
+
+    let mut model = giveaway.into_active_model();
+    model.message_id = Set(Some(message.get_message_id()));
+    let giveaway = model.update(*DB).await?;
+    schedule_job(&Job::new(
+        chat,
+        ends,
+        JobKind::DrawGiveaway { id: giveaway.id },
+    ))
+    .await?;
+    Ok(giveaway)
+}
+
+/// Handles a member pushing the button to enter a giveaway
+pub(crate) async fn enter_pushed(
+    callback: &CallbackQuery,
+    id: i64,
+) -> Result<(bool, CallbackReply)> {
+    let Some(giveaway) = get_giveaway(id).await? else {
+        return Ok((true, CallbackReply::default()));
+    };
+    let lang = get_chat_lang(giveaway.chat_id).await?;
+    if giveaway.ended {
+        return Ok((true, CallbackReply::alert(lang_fmt!(lang, "giveawayover"))));
+    }
+    let user = callback.get_from();
+    if user.get_is_bot() {
+        return Ok((
+            false,
+            CallbackReply::alert(lang_fmt!(lang, "giveawaynobots")),
+        ));
+    }
+    if !is_member(giveaway.chat_id, user.get_id()).await? {
+        return Ok((
+            false,
+            CallbackReply::alert(lang_fmt!(lang, "giveawaynotmember")),
+        ));
+    }
+
+    let entry = giveaway_entries::ActiveModel {
+        giveaway_id: Set(id),
+        user_id: Set(user.get_id()),
+        entered: Set(Utc::now()),
+        won: Set(false),
+    };
+    let inserted = giveaway_entries::Entity::insert(entry)
+        .on_conflict(
+            OnConflict::columns([
+                giveaway_entries::Column::GiveawayId,
+                giveaway_entries::Column::UserId,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .exec_without_returning(*DB)
+        .await?;
+    let text = if inserted == 0 {
+        lang_fmt!(lang, "giveawayalreadyentered")
+    } else {
+        lang_fmt!(lang, "giveawayentered", giveaway.prize)
+    };
+    Ok((false, CallbackReply::toast(text)))
+}
+
+/// Gets the winners already drawn for a giveaway
+async fn drawn_winners(id: i64) -> Result<Vec<i64>> {
+    Ok(giveaway_entries::Entity::find()
+        .filter(
+            giveaway_entries::Column::GiveawayId
+                .eq(id)
+                .and(giveaway_entries::Column::Won.eq(true)),
+        )
+        .all(*DB)
+        .await?
+        .into_iter()
+        .map(|entry| entry.user_id)
+        .collect())
+}
+
+/// Draws up to `count` winners at random from the entrants who haven't won yet
+async fn draw_winners(id: i64, count: usize) -> Result<Vec<i64>> {
+    let entrants = giveaway_entries::Entity::find()
+        .filter(
+            giveaway_entries::Column::GiveawayId
+                .eq(id)
+                .and(giveaway_entries::Column::Won.eq(false)),
+        )
+        .all(*DB)
+        .await?
+        .into_iter()
+        .map(|entry| entry.user_id)
+        .collect::<Vec<i64>>();
+    let winners = entrants
+        .choose_multiple(&mut thread_rng(), count)
+        .copied()
+        .collect::<Vec<i64>>();
+    if !winners.is_empty() {
+        giveaway_entries::Entity::update_many()
+            .col_expr(giveaway_entries::Column::Won, Expr::value(true))
+            .filter(
+                giveaway_entries::Column::GiveawayId
+                    .eq(id)
+                    .and(giveaway_entries::Column::UserId.is_in(winners.clone())),
+            )
+            .exec(*DB)
+            .await?;
+    }
+    Ok(winners)
+}
+
+async fn announce_winners(
+    giveaway: &giveaways::Model,
+    winners: &[i64],
+    reroll: bool,
+) -> Result<()> {
+    let lang = get_chat_lang(giveaway.chat_id).await?;
+    let prize = giveaway.prize.escape(false);
+    let text = match (winners.is_empty(), reroll) {
+        (true, false) => lang_fmt!(lang, "giveawaynoentries", prize),
+        (true, true) => lang_fmt!(lang, "giveawaynorerolls", prize),
+        (false, _) => {
+            let mut names = Vec::with_capacity(winners.len());
+            for winner in winners {
+                names.push(winner.cached_name().await?.escape(false).into_owned());
+            }
+            let names = names.join(", ");
+            if reroll {
+                lang_fmt!(lang, "giveawayrerolled", names, prize)
+            } else {
+                lang_fmt!(lang, "giveawaywinners", names, prize)
+            }
+        }
+    };
+    match giveaway.message_id {
+        Some(message) => giveaway.chat_id.force_reply(text, message).await?,
+        None => giveaway.chat_id.speak(text).await?,
+    };
+    Ok(())
+}
+
+/// Ends a giveaway, drawing and announcing its winners. Run by the scheduler, and scheduled
+/// again if the winners couldn't be announced
+pub(crate) async fn run_draw_giveaway(id: i64) -> Result<()> {
+    let Some(giveaway) = get_giveaway(id).await? else {
+        return Ok(());
+    };
+    if giveaway.announced {
+        return Ok(());
+    }
+    if !giveaway.ended {
+        // ending it first stops new entries
+        giveaways::Entity::update_many()
+            .col_expr(giveaways::Column::Ended, Expr::value(true))
+            .filter(giveaways::Column::Id.eq(id))
+            .exec(*DB)
+            .await?;
+
+        if let Some(message) = giveaway.message_id {
+            if let Err(err) = TG
+                .client
+                .build_edit_message_reply_markup()
+                .reply_markup(&InlineKeyboardMarkup::default())
+                .message_id(message)
+                .chat_id(giveaway.chat_id)
+                .build()
+                .await
+            {
+                log::debug!("failed to remove giveaway button: {}", err);
+            }
+        }
+    }
+
+    // a retry announces the winners drawn the first time instead of drawing new ones
+    let mut winners = drawn_winners(id).await?;
+    if winners.is_empty() {
+        winners = draw_winners(id, giveaway.winners.max(1) as usize).await?;
+    }
+    if let Err(err) = announce_winners(&giveaway, &winners, false).await {
+        if (Utc::now() - giveaway.ends).num_seconds() < DRAW_RETRY_WINDOW {
+            let retry = Utc::now() + Duration::try_seconds(DRAW_RETRY_DELAY).unwrap();
+            schedule_job(&Job::new(
+                giveaway.chat_id,
+                retry,
+                JobKind::DrawGiveaway { id },
+            ))
+            .await?;
+        }
+        return Err(err);
+    }
+
+    giveaways::Entity::update_many()
+        .col_expr(giveaways::Column::Announced, Expr::value(true))
+        .filter(giveaways::Column::Id.eq(id))
+        .exec(*DB)
+        .await?;
+    Ok(())
+}
+
+/// Draws `count` new winners for a giveaway that ended, from the entrants who haven't won
+/// yet, and announces them
+pub async fn reroll_giveaway(giveaway: &giveaways::Model, count: usize) -> Result<Vec<i64>> {
+    let winners = draw_winners(giveaway.id, count).await?;
+    announce_winners(giveaway, &winners, true).await?;
+    Ok(winners)
+}
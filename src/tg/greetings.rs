@@ -1,49 +1,59 @@
 use std::ops::DerefMut;
 
 use crate::persist::admin::captchastate::CaptchaType;
+use crate::persist::core::entity::get_entity_groups;
 use crate::persist::core::media::SendMediaReply;
-use crate::persist::redis::{
-    default_cache_query, CachedQueryTrait, RedisCache, RedisStr, ToRedisStr,
-};
+use crate::persist::redis::{default_cache_query, CacheScope, CachedQueryTrait, RedisCache};
 use crate::statics::{ME, TG};
 use crate::util::error::BotError;
-use crate::util::string::{should_ignore_chat, Speak};
+use crate::util::string::{should_ignore_chat, Confirm, Speak};
 use crate::{
     langs::Lang,
     persist::{
-        admin::{authorized, captchastate},
-        core::{media::MediaType, welcomes},
+        admin::{
+            authorized, captchastate,
+            welcomemute::{self, WelcomeMuteMode},
+        },
+        core::{
+            media::MediaType,
+            welcome_messages,
+            welcomes::{self, WelcomeRotation},
+        },
     },
     statics::{CONFIG, DB, REDIS},
     util::error::Result,
 };
-use base64::engine::general_purpose;
-use base64::Engine;
 use botapi::gen_types::{
     CallbackQuery, Chat, ChatMemberUpdated, EReplyMarkup, InlineKeyboardButton,
     InlineKeyboardButtonBuilder, MaybeInaccessibleMessage, Message, MessageEntity,
     ReplyParametersBuilder, UpdateExt, User,
 };
 use captcha::gen;
-use chrono::Duration;
+use chrono::{Duration, Utc};
 use futures::FutureExt;
 use macros::lang_fmt;
 use rand::seq::SliceRandom;
 use rand::{thread_rng, Rng};
 use redis::{AsyncCommands, Script};
 use sea_orm::ActiveValue::{NotSet, Set};
-use sea_orm::{ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter};
+use sea_orm::{ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter, QueryOrder};
 use sea_query::OnConflict;
+use serde::{Deserialize, Serialize};
 use tokio::time::sleep;
-use uuid::Uuid;
 
 use super::admin_helpers::{kick, DeleteAfterTime, UpdateHelpers, UserChanged};
-use super::button::{get_url, InlineKeyboardBuilder, OnPush};
+use super::bots;
+use super::button::{
+    callback_data, persist_action, ButtonAction, CallbackReply, InlineKeyboardBuilder, OnPush,
+};
 use super::command::Context;
+use super::deeplink::DeepLink;
+use super::join_requests::{approve_pending, decline_pending};
 use super::markdown::get_markup_for_buttons;
 use super::notes::handle_transition;
 use super::permissions::{IsAdmin, IsGroupAdmin};
-use super::user::{GetChat, Username};
+use super::scheduler::{schedule_job, Job, JobKind};
+use super::user::{is_member, GetChat, Username};
 
 pub(crate) fn auth_key(chat: i64) -> String {
     format!("cauth:{}", chat)
@@ -63,7 +73,7 @@ pub async fn update_auth_cache(chat: i64) -> Result<()> {
                 for row in rows {
                     p.sadd(&key, row.user);
                 }
-                p.expire(&key, CONFIG.timing.cache_timeout)
+                p.expire(&key, CONFIG.load().timing.cache_timeout)
             })
             .await?;
     }
@@ -72,19 +82,140 @@ pub async fn update_auth_cache(chat: i64) -> Result<()> {
 
 /// Gets a deep link url for retrieving a captcha from the bot's dm
 pub(crate) async fn get_captcha_url(chat: &Chat, user: &User) -> Result<String> {
-    let ser = (chat, user).to_redis()?;
-    let r = Uuid::new_v4();
-    let key = get_callback_key(&r.to_string());
-    REDIS
-        .pipe(|q| q.set(&key, ser).expire(&key, CONFIG.timing.cache_timeout))
+    DeepLink::Captcha {
+        chat: chat.get_id(),
+        user: Some(user.get_id()),
+    }
+    .url()
+    .await
+}
+
+#[inline(always)]
+fn get_welcome_key(chat: i64) -> String {
+    format!("welcomes:{}", chat)
+}
+
+/// Cache scope for a chat's welcome and goodbye messages. Invalidate this after changing
+/// either
+#[inline(always)]
+pub fn welcome_scope(chat: i64) -> CacheScope {
+    CacheScope::new(chat, "welcome")
+}
+
+/// Welcome settings for a chat with nothing set, for upserting single columns
+pub(crate) fn empty_welcome_settings(chat: i64) -> welcomes::ActiveModel {
+    welcomes::ActiveModel {
+        chat: Set(chat),
+        text: NotSet,
+        media_id: NotSet,
+        media_type: NotSet,
+        goodbye_text: NotSet,
+        goodbye_media_id: NotSet,
+        goodbye_media_type: NotSet,
+        enabled: NotSet,
+        welcome_entity_id: NotSet,
+        goodbye_entity_id: NotSet,
+        rotation: NotSet,
+    }
+}
+
+/// Turns welcome and goodbye messages on or off for a chat
+pub async fn set_welcome_enabled(chat: i64, enabled: bool) -> Result<()> {
+    let mut model = empty_welcome_settings(chat);
+    model.enabled = Set(enabled);
+
+    welcomes::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(welcomes::Column::Chat)
+                .update_column(welcomes::Column::Enabled)
+                .to_owned(),
+        )
+        .exec_with_returning(*DB)
         .await?;
-    let bs = general_purpose::URL_SAFE_NO_PAD.encode(r.into_bytes());
-    let bs = get_url(bs)?;
-    Ok(bs)
+    welcome_scope(chat).invalidate().await?;
+    Ok(())
 }
 
-pub(crate) fn get_callback_key(key: &str) -> String {
-    format!("ccback:{}", key)
+#[inline(always)]
+fn welcome_mute_key(chat: i64) -> String {
+    format!("wmute:{}", chat)
+}
+
+/// Gets the welcome mute settings for a chat, None if they were never set
+pub async fn get_welcome_mute(chat: i64) -> Result<Option<welcomemute::Model>> {
+    let key = welcome_mute_key(chat);
+    let res = default_cache_query(
+        |_, _| async move {
+            let res = welcomemute::Entity::find_by_id(chat).one(*DB).await?;
+            Ok(res)
+        },
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
+    )
+    .query(&key, &())
+    .await?;
+    Ok(res)
+}
+
+/// Sets the welcome mute mode for a chat. The kick time for strict mode is only changed
+/// if one is given
+pub async fn set_welcome_mute(
+    chat: i64,
+    mode: WelcomeMuteMode,
+    kick_time: Option<i64>,
+) -> Result<welcomemute::Model> {
+    let key = welcome_mute_key(chat);
+    let mut columns = vec![welcomemute::Column::Mode];
+    if kick_time.is_some() {
+        columns.push(welcomemute::Column::KickTime);
+    }
+    let model = welcomemute::ActiveModel {
+        chat: Set(chat),
+        mode: Set(mode),
+        kick_time: kick_time.map(|v| Set(Some(v))).unwrap_or(NotSet),
+    };
+    let model = welcomemute::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(welcomemute::Column::Chat)
+                .update_columns(columns)
+                .to_owned(),
+        )
+        .exec_with_returning(*DB)
+        .await?;
+    model.cache(key).await?;
+    Ok(model)
+}
+
+/// How long members have to push the button in strict welcome mute mode before being kicked
+pub fn welcome_mute_kick_time(config: &welcomemute::Model) -> Duration {
+    config
+        .kick_time
+        .and_then(Duration::try_seconds)
+        .unwrap_or_else(|| Duration::try_minutes(5).unwrap())
+}
+
+/// Handles a push of the button attached to a welcome message for a muted new member
+pub(crate) async fn human_pushed(
+    ctx: &Context,
+    callback: &CallbackQuery,
+    user: i64,
+) -> Result<(bool, CallbackReply)> {
+    if callback.get_from().get_id() != user {
+        return Ok((
+            false,
+            CallbackReply::alert(lang_fmt!(ctx, "nothumanbutton")),
+        ));
+    }
+    ctx.authorize_user(user, ctx.try_get()?.chat).await?;
+    Ok((true, CallbackReply::toast(lang_fmt!(ctx, "verifiedhuman"))))
+}
+
+/// Kicks a member who joined under strict welcome mute if they never pushed the button.
+/// Run by the scheduler once the kick time is up
+pub(crate) async fn run_welcome_mute_kick(chat: i64, user: i64) -> Result<()> {
+    if !user_is_authorized(chat, user).await? {
+        kick(user, chat).await?;
+    }
+    Ok(())
 }
 
 /// Returns true if the user has already completed the captcha in the given chat
@@ -98,6 +229,35 @@ fn captcha_state_key(chat: &Chat) -> String {
     format!("cstate:{}", chat.get_id())
 }
 
+/// Turns captcha on or off for a chat. Turning it on keeps the chat's captcha mode and kick
+/// time if it had them
+pub async fn set_captcha_enabled(chat: &Chat, enabled: bool) -> Result<()> {
+    let key = captcha_state_key(chat);
+    if enabled {
+        let model = captchastate::ActiveModel {
+            chat: Set(chat.get_id()),
+            captcha_type: NotSet,
+            kick_time: NotSet,
+            captcha_text: NotSet,
+        };
+        let model = captchastate::Entity::insert(model)
+            .on_conflict(
+                OnConflict::column(captchastate::Column::Chat)
+                    .update_column(captchastate::Column::Chat)
+                    .to_owned(),
+            )
+            .exec_with_returning(*DB)
+            .await?;
+        model.cache(key).await?;
+    } else {
+        captchastate::Entity::delete_by_id(chat.get_id())
+            .exec(*DB)
+            .await?;
+        REDIS.sq(|q| q.del(&key)).await?;
+    }
+    Ok(())
+}
+
 /// Gets the current captcha configuration for the current update/chat, returns None if captcha is disabled
 pub async fn get_captcha_config(
     message: &ChatMemberUpdated,
@@ -109,49 +269,184 @@ pub async fn get_captcha_config(
             let res = captchastate::Entity::find_by_id(chat).one(*DB).await?;
             Ok(res)
         },
-        Duration::try_seconds(CONFIG.timing.cache_timeout).unwrap(),
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
     )
     .query(&key, &())
     .await?;
     Ok(res)
 }
 
-pub(crate) async fn goodbye_members(
+/// A welcome or goodbye message ready to send. Messages without text use the default one
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Greeting {
+    pub id: i64,
+    pub text: Option<String>,
+    pub media_id: Option<String>,
+    pub media_type: MediaType,
+    pub entities: Vec<MessageEntity>,
+    pub buttons: Option<InlineKeyboardBuilder>,
+}
+
+impl Default for Greeting {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            text: None,
+            media_id: None,
+            media_type: MediaType::Text,
+            entities: Vec::new(),
+            buttons: None,
+        }
+    }
+}
+
+/// A chat's welcome settings along with all of its welcome and goodbye messages, oldest
+/// first
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Greetings {
+    pub settings: welcomes::Model,
+    pub welcomes: Vec<Greeting>,
+    pub goodbyes: Vec<Greeting>,
+}
+
+impl Greetings {
+    /// Picks the welcome or goodbye to send according to the chat's rotation, the default
+    /// message if the chat has none
+    pub async fn pick(&self, goodbye: bool) -> Result<Greeting> {
+        let messages = if goodbye {
+            &self.goodbyes
+        } else {
+            &self.welcomes
+        };
+        if messages.len() < 2 {
+            return Ok(messages.first().cloned().unwrap_or_default());
+        }
+        let idx = match self.settings.rotation {
+            WelcomeRotation::First => 0,
+            WelcomeRotation::Random => thread_rng().gen_range(0..messages.len()),
+            WelcomeRotation::Rotate => {
+                let key = welcome_rotation_key(self.settings.chat, goodbye);
+                let count: i64 = REDIS.sq(|q| q.incr(&key, 1)).await?;
+                (count.max(1) - 1) as usize % messages.len()
+            }
+        };
+        Ok(messages[idx].clone())
+    }
+}
+
+#[inline(always)]
+fn welcome_rotation_key(chat: i64, goodbye: bool) -> String {
+    format!("wrot:{}:{}", chat, goodbye)
+}
+
+/// Gets a chat's welcome settings and messages, None if welcomes were never set up
+pub async fn get_greetings(chat: i64) -> Result<Option<Greetings>> {
+    let key = welcome_scope(chat).key(get_welcome_key(chat)).await?;
+    let res = default_cache_query(
+        |_, _| async move {
+            // loaded from the primary, the cache is dropped whenever greetings change and a
+            // lagging replica would cache the old ones
+            let Some(settings) = welcomes::Entity::find_by_id(chat).one(*DB).await? else {
+                return Ok(None);
+            };
+            let messages = welcome_messages::Entity::find()
+                .filter(welcome_messages::Column::Chat.eq(chat))
+                .order_by_asc(welcome_messages::Column::Id)
+                .all(*DB)
+                .await?;
+            let mut groups =
+                get_entity_groups(*DB, messages.iter().filter_map(|m| m.entity_id)).await?;
+            let (goodbyes, welcomes): (Vec<_>, Vec<_>) =
+                messages.into_iter().partition(|m| m.goodbye);
+            let mut convert = |messages: Vec<welcome_messages::Model>| {
+                messages
+                    .into_iter()
+                    .map(|message| {
+                        let (entities, buttons) = groups.take(message.entity_id);
+                        Greeting {
+                            id: message.id,
+                            text: message.text,
+                            media_id: message.media_id,
+                            media_type: message.media_type,
+                            entities: entities
+                                .into_iter()
+                                .map(|e| e.get())
+                                .map(|(e, u)| e.to_entity(u))
+                                .collect(),
+                            buttons: get_markup_for_buttons(buttons.into_iter().collect()),
+                        }
+                    })
+                    .collect::<Vec<Greeting>>()
+            };
+            let welcomes = convert(welcomes);
+            let goodbyes = convert(goodbyes);
+            Ok(Some(Greetings {
+                settings,
+                welcomes,
+                goodbyes,
+            }))
+        },
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
+    )
+    .query(&key, &())
+    .await?;
+    Ok(res)
+}
+
+/// Sends a welcome or goodbye with its own entities and buttons. Buttons linking to notes
+/// open the note in place, the same as on notes
+async fn send_greeting(
     ctx: &Context,
-    model: welcomes::Model,
-    entities: Vec<MessageEntity>,
+    chat: i64,
+    text: String,
+    greeting: Greeting,
     buttons: Option<InlineKeyboardBuilder>,
-    lang: &Lang,
 ) -> Result<()> {
-    let text = if let Some(text) = model.goodbye_text {
-        text
-    } else {
-        lang_fmt!(lang, "defaultgoodbye")
-    };
+    let c = ctx.clone();
+    SendMediaReply::new(ctx, greeting.media_type)
+        .button_callback(move |note, button| {
+            let c = c.clone();
+            async move {
+                button.on_push(move |b| async move {
+                    handle_transition(&c, chat, note, b).await?;
+                    Ok(())
+                });
 
-    SendMediaReply::new(ctx, model.goodbye_media_type.unwrap_or(MediaType::Text))
-        .button_callback(|_, _| async move { Ok(()) }.boxed())
+                Ok(())
+            }
+            .boxed()
+        })
         .text(Some(text))
-        .media_id(model.goodbye_media_id)
-        .extra_entities(entities)
+        .media_id(greeting.media_id)
+        .extra_entities(greeting.entities)
         .buttons(buttons)
         .send_media()
         .await?;
     Ok(())
 }
 
+pub(crate) async fn goodbye_members(ctx: &Context, greeting: Greeting, lang: &Lang) -> Result<()> {
+    let text = if let Some(text) = greeting.text.clone() {
+        text
+    } else {
+        lang_fmt!(lang, "defaultgoodbye")
+    };
+
+    let chat = ctx.try_get()?.chat.get_id();
+    let buttons = greeting.buttons.clone();
+    send_greeting(ctx, chat, text, greeting, buttons).await
+}
+
 /// Handle sending a welcome message along with a text captcha
 pub(crate) async fn welcome_members(
     ctx: &Context,
     upd: &ChatMemberUpdated,
-    model: welcomes::Model,
-    entities: Vec<MessageEntity>,
-    mut extra_buttons: Option<InlineKeyboardBuilder>,
+    greeting: Greeting,
     lang: &Lang,
     captcha: Option<&captchastate::Model>,
 ) -> Result<()> {
     log::info!("welcome {:?}", captcha);
-    let text = if let Some(text) = model.text {
+    let text = if let Some(text) = greeting.text.clone() {
         text
     } else {
         lang_fmt!(lang, "defaultwelcome")
@@ -167,40 +462,15 @@ pub(crate) async fn welcome_members(
     } else {
         vec![]
     };
-    let c = ctx.clone();
     let chat = upd.get_chat().get_id();
+    let mut extra_buttons = greeting.buttons.clone();
     let b = extra_buttons.get_or_insert_with(InlineKeyboardBuilder::default);
 
     for button in buttons {
         b.button(button);
     }
 
-    SendMediaReply::new(ctx, model.media_type.unwrap_or(MediaType::Text))
-        .button_callback(move |note, button| {
-            let c = c.clone();
-            async move {
-                button.on_push(move |b| async move {
-                    TG.client
-                        .build_answer_callback_query(b.get_id())
-                        .build()
-                        .await?;
-
-                    handle_transition(&c, chat, note, b).await?;
-                    Ok(())
-                });
-
-                Ok(())
-            }
-            .boxed()
-        })
-        .text(Some(text))
-        .media_id(model.media_id)
-        .extra_entities(entities)
-        .buttons(extra_buttons)
-        .send_media()
-        .await?;
-
-    Ok(())
+    send_greeting(ctx, chat, text, greeting, extra_buttons).await
 }
 
 fn build_captcha_sync() -> (String, Vec<u8>, Vec<char>) {
@@ -251,151 +521,182 @@ async fn incorrect_tries(callback: &CallbackQuery, incorrect_chat: i64) -> Resul
     Ok(count)
 }
 
-/// Generates a series of "incorrect" captcha answers, pushing them as InlineKeyboardButton
-/// onto a Vec of buttons
-fn insert_incorrect(
+/// Handles a push of an incorrect captcha answer, kicking the user if they run out of
+/// tries. Returns true once the button is no longer needed
+pub(crate) async fn captcha_incorrect(
+    ctx: &Context,
+    callback: &CallbackQuery,
+    unmute_chat: i64,
+) -> Result<(bool, CallbackReply)> {
+    if let Some(MaybeInaccessibleMessage::Message(message)) = callback.get_message() {
+        let count = 3 - incorrect_tries(callback, unmute_chat).await?;
+        if count > 0 {
+            Ok((
+                false,
+                CallbackReply::alert(lang_fmt!(ctx, "incorrect", count)),
+            ))
+        } else {
+            if decline_pending(unmute_chat, callback.get_from().get_id()).await? {
+                message.reply(lang_fmt!(ctx, "notriesdeclined")).await?;
+            } else if let Some(chat) = unmute_chat.get_chat().await? {
+                kick(callback.get_from().get_id(), unmute_chat).await?;
+                message
+                    .reply(lang_fmt!(ctx, "notrieskickchat", chat.name_humanreadable()))
+                    .await?;
+            } else {
+                kick(callback.get_from().get_id(), unmute_chat).await?;
+                message.reply(lang_fmt!(ctx, "notrieskick")).await?;
+            }
+            TG.client
+                .build_delete_message(message.get_chat().get_id(), message.get_message_id())
+                .build()
+                .await?;
+            reset_incorrect_tries(callback.get_from(), unmute_chat).await?;
+            Ok((true, CallbackReply::alert(lang_fmt!(ctx, "notries"))))
+        }
+    } else {
+        Ok((true, CallbackReply::default()))
+    }
+}
+
+/// Handles a push of the correct captcha answer, unmuting the user in the chat
+pub(crate) async fn captcha_correct(
     ctx: &Context,
+    callback: &CallbackQuery,
+    unmute_chat: i64,
+) -> Result<()> {
+    if let Some(MaybeInaccessibleMessage::Message(message)) = callback.get_message() {
+        let chat = unmute_chat
+            .get_chat()
+            .await?
+            .ok_or_else(|| BotError::Generic("captcha chat missing".to_owned()))?;
+        if let Some(link) = get_invite_link(unmute_chat).await? {
+            let mut button = InlineKeyboardBuilder::default();
+
+            button.button(
+                InlineKeyboardButtonBuilder::new(lang_fmt!(ctx, "backtochat"))
+                    .set_url(link)
+                    .build(),
+            );
+
+            let button = button.build();
+
+            TG.client()
+                .build_edit_message_caption()
+                .caption(&lang_fmt!(ctx, "correctchoice"))
+                .message_id(message.get_message_id())
+                .chat_id(message.get_chat().get_id())
+                .reply_markup(&button)
+                .build()
+                .await?;
+        } else {
+            TG.client()
+                .build_edit_message_caption()
+                .caption(&lang_fmt!(ctx, "correctchoice"))
+                .message_id(message.get_message_id())
+                .chat_id(message.get_chat().get_id())
+                .build()
+                .await?;
+        }
+        let user = callback.get_from().get_id();
+        // users joining by request only become members once it's approved, anyone else
+        // may have left while solving the captcha
+        if approve_pending(unmute_chat, user).await? || is_member(unmute_chat, user).await? {
+            ctx.authorize_user(user, &chat).await?;
+        }
+        reset_incorrect_tries(callback.get_from(), unmute_chat).await?;
+    }
+
+    Ok(())
+}
+
+/// Generates an "incorrect" captcha answer, pushing it as an InlineKeyboardButton
+/// onto a Vec of buttons
+async fn insert_incorrect(
     res: &mut Vec<InlineKeyboardButton>,
     correct: &str,
-    supported: &Vec<char>,
+    supported: &[char],
     unmute_chat: i64,
-) {
-    let mut rng = thread_rng();
-    let mut s = String::with_capacity(correct.len());
-    for _ in correct.chars() {
-        if let Some(ch) = supported.choose(&mut rng) {
-            s.push(*ch);
+) -> Result<()> {
+    let s = {
+        let mut rng = thread_rng();
+        let mut s = String::with_capacity(correct.len());
+        for _ in correct.chars() {
+            if let Some(ch) = supported.choose(&mut rng) {
+                s.push(*ch);
+            }
         }
-    }
-    drop(rng);
+        s
+    };
     let s = InlineKeyboardButtonBuilder::new(s)
-        .set_callback_data(Uuid::new_v4().to_string())
+        .set_callback_data(callback_data(None))
         .build();
-    let ctx = ctx.clone();
-    s.on_push_multi(move |callback| {
-        let ctx = ctx.clone();
-        async move {
-            if let Some(MaybeInaccessibleMessage::Message(message)) = callback.get_message() {
-                let count = 3 - incorrect_tries(&callback, unmute_chat).await?;
-                if count > 0 {
-                    TG.client
-                        .build_answer_callback_query(callback.get_id())
-                        .show_alert(true)
-                        .text(&lang_fmt!(ctx, "incorrect", count))
-                        .build()
-                        .await?;
-                    Ok(false)
-                } else {
-                    TG.client
-                        .build_answer_callback_query(callback.get_id())
-                        .show_alert(true)
-                        .text(&lang_fmt!(ctx, "notries"))
-                        .build()
-                        .await?;
-                    kick(callback.get_from().get_id(), unmute_chat).await?;
-                    if let Some(chat) = unmute_chat.get_chat().await? {
-                        message
-                            .reply(lang_fmt!(ctx, "notrieskickchat", chat.name_humanreadable()))
-                            .await?;
-                    } else {
-                        message.reply(lang_fmt!(ctx, "notrieskick")).await?;
-                    }
-                    TG.client
-                        .build_delete_message(message.get_chat().get_id(), message.get_message_id())
-                        .build()
-                        .await?;
-                    reset_incorrect_tries(callback.get_from(), unmute_chat).await?;
-                    Ok(true)
-                }
-            } else {
-                Ok(true)
-            }
-        }
-    });
+    let action = ButtonAction::Captcha {
+        chat: unmute_chat,
+        correct: false,
+    };
+    persist_action(&s, &action).await?;
     res.push(s);
+    Ok(())
 }
 
-async fn get_invite_link(chat: &Chat) -> Result<Option<String>> {
-    let unmute_chat = TG.client().build_get_chat(chat.get_id()).build().await?;
+async fn get_invite_link(chat: i64) -> Result<Option<String>> {
+    let unmute_chat = TG.client().build_get_chat(chat).build().await?;
 
     Ok(unmute_chat.get_invite_link().map(|v| v.to_owned()))
 }
 
-fn get_choices(
+async fn get_choices(
     correct: String,
-    supported: &Vec<char>,
+    supported: &[char],
     times: usize,
-    unmute_chat: Chat,
-    ctx: &Context,
-) -> Vec<InlineKeyboardButton> {
-    let mut rng = thread_rng();
+    unmute_chat: i64,
+) -> Result<Vec<InlineKeyboardButton>> {
     let mut res = Vec::<InlineKeyboardButton>::with_capacity(times);
-    let pos = rng.gen_range(0..=times);
-    drop(rng);
+    let pos = thread_rng().gen_range(0..=times);
     //log::info!("selected captcha correct pos {}", pos);
-    let incorrect_chat = unmute_chat.get_id();
     for _ in 0..pos {
-        insert_incorrect(ctx, &mut res, correct.as_str(), supported, incorrect_chat);
+        insert_incorrect(&mut res, correct.as_str(), supported, unmute_chat).await?;
     }
 
     let correct_button = InlineKeyboardButtonBuilder::new(correct.clone())
-        .set_callback_data(Uuid::new_v4().to_string())
+        .set_callback_data(callback_data(None))
         .build();
-    let c = ctx.clone();
-    correct_button.on_push(move |callback| async move {
-        if let Some(MaybeInaccessibleMessage::Message(message)) = callback.get_message() {
-            if let Some(link) = get_invite_link(&unmute_chat).await? {
-                let mut button = InlineKeyboardBuilder::default();
-
-                button.button(
-                    InlineKeyboardButtonBuilder::new(lang_fmt!(c, "backtochat"))
-                        .set_url(link)
-                        .build(),
-                );
-
-                let button = button.build();
-
-                TG.client()
-                    .build_edit_message_caption()
-                    .caption(&lang_fmt!(c, "correctchoice"))
-                    .message_id(message.get_message_id())
-                    .chat_id(message.get_chat().get_id())
-                    .reply_markup(&button)
-                    .build()
-                    .await?;
-            } else {
-                TG.client()
-                    .build_edit_message_caption()
-                    .caption(&lang_fmt!(c, "correctchoice"))
-                    .message_id(message.get_message_id())
-                    .chat_id(message.get_chat().get_id())
-                    .build()
-                    .await?;
-            }
-            c.authorize_user(callback.get_from().get_id(), &unmute_chat)
-                .await?;
-            reset_incorrect_tries(callback.get_from(), unmute_chat.get_id()).await?;
-        }
-        TG.client()
-            .build_answer_callback_query(callback.get_id())
-            .build()
-            .await?;
-
-        Ok(())
-    });
+    let action = ButtonAction::Captcha {
+        chat: unmute_chat,
+        correct: true,
+    };
+    persist_action(&correct_button, &action).await?;
     res.push(correct_button);
 
     for _ in (pos + 1)..times {
-        insert_incorrect(ctx, &mut res, correct.as_str(), supported, incorrect_chat);
+        insert_incorrect(&mut res, correct.as_str(), supported, unmute_chat).await?;
     }
-    res
+    Ok(res)
 }
 
 /// Sends a "text" captcha to the specified chat
 pub async fn send_captcha<'a>(message: &Message, unmute_chat: Chat, ctx: &Context) -> Result<()> {
+    send_captcha_to(
+        message.get_chat().get_id(),
+        Some(message.get_message_id()),
+        unmute_chat.get_id(),
+        ctx.lang(),
+    )
+    .await
+}
+
+/// Sends a "text" captcha for `unmute_chat` to a chat, optionally as a reply
+pub async fn send_captcha_to(
+    chat: i64,
+    reply: Option<i64>,
+    unmute_chat: i64,
+    lang: &Lang,
+) -> Result<()> {
     let (correct, bytes, supported) = build_captcha_sync();
     let mut builder = InlineKeyboardBuilder::default();
-    for (i, choice) in get_choices(correct, &supported, 9, unmute_chat, ctx)
+    for (i, choice) in get_choices(correct, &supported, 9, unmute_chat)
+        .await?
         .into_iter()
         .enumerate()
     {
@@ -405,18 +706,17 @@ pub async fn send_captcha<'a>(message: &Message, unmute_chat: Chat, ctx: &Contex
             builder.newline();
         }
     }
-    TG.client()
-        .build_send_photo(
-            message.get_chat().get_id(),
-            botapi::gen_types::FileData::Bytes(bytes),
-        )
-        .caption(&lang_fmt!(ctx, "captchawarning"))
-        .reply_parameters(&ReplyParametersBuilder::new(message.get_message_id()).build())
+    let mut photo = TG
+        .client()
+        .build_send_photo(chat, botapi::gen_types::FileData::Bytes(bytes))
+        .caption(&lang_fmt!(lang, "captchawarning"))
         .reply_markup(&botapi::gen_types::EReplyMarkup::InlineKeyboardMarkup(
             builder.build(),
-        ))
-        .build()
-        .await?;
+        ));
+    if let Some(reply) = reply {
+        photo = photo.reply_parameters(&ReplyParametersBuilder::new(reply).build());
+    }
+    photo.build().await?;
 
     Ok(())
 }
@@ -425,28 +725,19 @@ async fn button_captcha<'a>(
     ctx: &Context,
     upd: &ChatMemberUpdated,
     captcha: &captchastate::Model,
-    welcome: Option<welcomes::Model>,
-    entities: Vec<MessageEntity>,
-    buttons: Option<InlineKeyboardBuilder>,
+    welcome: Option<Greetings>,
 ) -> Result<()> {
     let unmute_button = InlineKeyboardButtonBuilder::new(lang_fmt!(ctx, "pressme"))
-        .set_callback_data(Uuid::new_v4().to_string())
+        .set_callback_data(callback_data(None))
         .build();
-    let bctx = ctx.clone();
-    unmute_button.on_push(|callback| async move {
-        bctx.authorize_user(callback.get_from().get_id(), bctx.try_get()?.chat)
-            .await?;
-        Ok(())
-    });
+    persist_action(&unmute_button, &ButtonAction::UnmuteMe).await?;
     let mut button = InlineKeyboardBuilder::default();
     button.button(unmute_button);
     if let Some(welcome) = welcome {
         welcome_members(
             ctx,
             upd,
-            welcome,
-            entities,
-            buttons,
+            welcome.pick(false).await?,
             ctx.lang(),
             Some(captcha),
         )
@@ -472,9 +763,7 @@ async fn send_captcha_chooser(
     ctx: &Context,
     upd: &ChatMemberUpdated,
     catpcha: &captchastate::Model,
-    welcome: Option<welcomes::Model>,
-    entities: Vec<MessageEntity>,
-    buttons: Option<InlineKeyboardBuilder>,
+    welcome: Option<Greetings>,
     lang: &Lang,
 ) -> Result<()> {
     let user = upd.get_from();
@@ -488,7 +777,7 @@ async fn send_captcha_chooser(
     );
 
     if let Some(welcome) = welcome {
-        welcome_members(ctx, upd, welcome, entities, buttons, lang, Some(catpcha)).await?;
+        welcome_members(ctx, upd, welcome.pick(false).await?, lang, Some(catpcha)).await?;
     } else {
         let nm = TG
             .client()
@@ -515,11 +804,7 @@ impl Context {
     async fn check_members<'a>(
         &self,
         config: &captchastate::Model,
-        welcome: Option<welcomes::Model>,
-        entities: Vec<MessageEntity>,
-        goodbye: Vec<MessageEntity>,
-        buttons: Option<InlineKeyboardBuilder>,
-        gb_buttons: Option<InlineKeyboardBuilder>,
+        welcome: Option<Greetings>,
     ) -> Result<()> {
         if let Some(UserChanged::UserJoined(message)) = self.update().user_event() {
             let me = ME.get().unwrap();
@@ -540,7 +825,7 @@ impl Context {
                 if let Some(kicktime) = config.kick_time {
                     let chatid = chat.get_id();
                     let userid = user.get_id();
-                    tokio::spawn(async move {
+                    bots::spawn(async move {
                         let kicktime = Duration::try_seconds(kicktime)
                             .unwrap_or_else(|| Duration::try_minutes(5).unwrap());
                         sleep(kicktime.to_std()?).await;
@@ -551,66 +836,109 @@ impl Context {
                         Ok::<(), BotError>(())
                     });
                 }
+                if self.buffer_burst_join(true).await? {
+                    return Ok(());
+                }
                 match config.captcha_type {
                     CaptchaType::Text => {
-                        send_captcha_chooser(
-                            self,
-                            message,
-                            config,
-                            welcome,
-                            entities,
-                            buttons,
-                            self.lang(),
-                        )
-                        .await?
-                    }
-                    CaptchaType::Button => {
-                        button_captcha(self, message, config, welcome, entities, buttons).await?
+                        send_captcha_chooser(self, message, config, welcome, self.lang()).await?
                     }
+                    CaptchaType::Button => button_captcha(self, message, config, welcome).await?,
                 }
             } else if let Some(welcome) = welcome {
-                self.handle_welcome(welcome, entities, goodbye, buttons, gb_buttons, None)
-                    .await?;
+                self.handle_welcome(welcome, None).await?;
             }
         } else if let Some(welcome) = welcome {
-            self.handle_welcome(welcome, entities, goodbye, buttons, gb_buttons, None)
-                .await?;
+            self.handle_welcome(welcome, None).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Mutes a new member until they push the button attached to the welcome message, or
+    /// to a separate message if welcomes are off. In strict mode a kick is scheduled for
+    /// members who don't push it in time
+    async fn welcome_mute(
+        &self,
+        config: &welcomemute::Model,
+        welcome: Option<Greetings>,
+    ) -> Result<()> {
+        if let Some(UserChanged::UserJoined(member)) = self.update().user_event() {
+            let me = ME.get().unwrap();
+            let user = member.get_from();
+            let chat = member.get_chat();
+            if user.get_id() != me.get_id()
+                && !user.is_admin(chat).await?
+                && !user_is_authorized(chat.get_id(), user.get_id()).await?
+            {
+                let kick_time = welcome_mute_kick_time(config);
+                self.mute(user.get_id(), chat, None).await?;
+                if config.mode == WelcomeMuteMode::Strict {
+                    let kind = JobKind::WelcomeMuteKick {
+                        user: user.get_id(),
+                    };
+                    schedule_job(&Job::new(chat.get_id(), Utc::now() + kick_time, kind)).await?;
+                }
+
+                let button = InlineKeyboardButtonBuilder::new(lang_fmt!(self, "imhuman"))
+                    .set_callback_data(callback_data(None))
+                    .build();
+                let action = ButtonAction::HumanCheck {
+                    user: user.get_id(),
+                };
+                persist_action(&button, &action).await?;
+                match welcome.filter(|w| w.settings.enabled) {
+                    Some(welcome) => {
+                        let mut greeting = welcome.pick(false).await?;
+                        greeting
+                            .buttons
+                            .get_or_insert_with(InlineKeyboardBuilder::default)
+                            .button(button);
+                        welcome_members(self, member, greeting, self.lang(), None).await?;
+                    }
+                    None if !should_ignore_chat(chat.get_id()).await? => {
+                        let mut markup = InlineKeyboardBuilder::default();
+                        markup.button(button);
+                        TG.client()
+                            .build_send_message(chat.get_id(), &lang_fmt!(self, "pushunmute"))
+                            .reply_markup(&EReplyMarkup::InlineKeyboardMarkup(markup.build()))
+                            .build()
+                            .await?
+                            .delete_after_time(kick_time);
+                    }
+                    None => (),
+                }
+                return Ok(());
+            }
         }
 
+        if let Some(welcome) = welcome {
+            self.handle_welcome(welcome, None).await?;
+        }
         Ok(())
     }
 
     async fn handle_welcome(
         &self,
-        welcome: welcomes::Model,
-        entities: Vec<MessageEntity>,
-        goodbye: Vec<MessageEntity>,
-        buttons: Option<InlineKeyboardBuilder>,
-        gb_buttons: Option<InlineKeyboardBuilder>,
+        welcome: Greetings,
         captcha: Option<&captchastate::Model>,
     ) -> Result<()> {
-        log::info!(
-            "handle_welcome\n entities {:?}\ngoodbyes {:?}",
-            entities,
-            buttons.is_some()
-        );
         if let Some(userchanged) = self.update().user_event() {
-            if welcome.enabled {
+            if welcome.settings.enabled {
                 match userchanged {
+                    UserChanged::UserJoined(_) if self.buffer_burst_join(false).await? => (),
                     UserChanged::UserJoined(member) => {
                         welcome_members(
                             self,
                             member,
-                            welcome,
-                            entities,
-                            buttons,
+                            welcome.pick(false).await?,
                             self.lang(),
                             captcha,
                         )
                         .await?
                     }
                     UserChanged::UserLeft(_) => {
-                        goodbye_members(self, welcome, goodbye, gb_buttons, self.lang()).await?
+                        goodbye_members(self, welcome.pick(true).await?, self.lang()).await?
                     }
                 }
             }
@@ -622,30 +950,19 @@ impl Context {
     pub async fn greeter_handle_update(&self) -> Result<()> {
         if let UpdateExt::ChatMember(ref upd) = self.update() {
             log::info!("chat_member update");
-            match (
-                self.should_welcome(upd).await?,
-                self.get_captcha_config().await?,
-            ) {
-                (Some((welcome, entities, goodbyes, buttons, gb_buttons)), None) => {
-                    self.handle_welcome(welcome, entities, goodbyes, buttons, gb_buttons, None)
-                        .await
-                }
-                (None, Some(captcha)) => {
-                    self.check_members(&captcha, None, vec![], vec![], None, None)
-                        .await
-                }
-                (Some((welcome, entities, gb_entities, buttons, gb_buttons)), Some(captcha)) => {
-                    self.check_members(
-                        &captcha,
-                        Some(welcome),
-                        entities,
-                        gb_entities,
-                        buttons,
-                        gb_buttons,
-                    )
-                    .await
+            let chat = upd.get_chat().get_id();
+            match (get_greetings(chat).await?, self.get_captcha_config().await?) {
+                (welcome, None) => {
+                    let mute = get_welcome_mute(chat)
+                        .await?
+                        .filter(|m| m.mode != WelcomeMuteMode::Off);
+                    match (welcome, mute) {
+                        (welcome, Some(mute)) => self.welcome_mute(&mute, welcome).await,
+                        (Some(welcome), None) => self.handle_welcome(welcome, None).await,
+                        (None, None) => Ok(()),
+                    }
                 }
-                (None, None) => Ok(()),
+                (welcome, Some(captcha)) => self.check_members(&captcha, welcome).await,
             }?;
         }
 
@@ -656,22 +973,7 @@ impl Context {
     pub async fn enable_captcha(&self) -> Result<()> {
         let message = self.message()?;
         self.check_permissions(|p| p.can_change_info).await?;
-        let model = captchastate::ActiveModel {
-            chat: Set(message.get_chat().get_id()),
-            captcha_type: NotSet,
-            kick_time: NotSet,
-            captcha_text: NotSet,
-        };
-        let model = captchastate::Entity::insert(model)
-            .on_conflict(
-                OnConflict::column(captchastate::Column::Chat)
-                    .update_column(captchastate::Column::Chat)
-                    .to_owned(),
-            )
-            .exec_with_returning(*DB)
-            .await?;
-        let key = captcha_state_key(message.get_chat());
-        model.cache(key).await?;
+        set_captcha_enabled(message.get_chat(), true).await?;
         message.reply("enabled captcha!").await?;
         Ok(())
     }
@@ -680,12 +982,7 @@ impl Context {
     pub async fn disable_captcha(&self) -> Result<()> {
         let message = self.message()?;
         self.check_permissions(|p| p.can_change_info).await?;
-        let key = captcha_state_key(message.get_chat());
-        captchastate::Entity::delete_by_id(message.get_chat().get_id())
-            .exec(*DB)
-            .await?;
-
-        REDIS.sq(|q| q.del(&key)).await?;
+        set_captcha_enabled(message.get_chat(), false).await?;
         message.reply(lang_fmt!(self, "disabledcaptcha")).await?;
         Ok(())
     }
@@ -726,77 +1023,24 @@ impl Context {
             log::info!("set captcha mode {:?}", model.captcha_type);
             let name = model.captcha_type.get_name();
             model.cache(key).await?;
-            message.reply(lang_fmt!(self, "captchamode", name)).await?;
+            message
+                .confirm(lang_fmt!(self, "captchamode", name))
+                .await?;
         } else {
             message.reply(lang_fmt!(self, "captchanotenabled")).await?;
         }
         Ok(())
     }
 
-    async fn should_welcome(
-        &self,
-        upd: &ChatMemberUpdated,
-    ) -> Result<
-        Option<(
-            welcomes::Model,
-            Vec<MessageEntity>,
-            Vec<MessageEntity>,
-            Option<InlineKeyboardBuilder>,
-            Option<InlineKeyboardBuilder>,
-        )>,
-    > {
-        let chat = upd.get_chat();
-        let key = format!("welcome:{}", chat.get_id());
-        let chat_id = chat.get_id();
-
-        let v: Option<RedisStr> = REDIS.sq(|q| q.get(&key)).await?;
-        let res = if let Some(v) = v {
-            Ok(v.get()?)
-        } else {
-            let res = welcomes::get_filters_join(welcomes::Column::Chat.eq(chat_id)).await?;
-            log::info!("should_welcome cache miss {:?}", res);
-            let res = res
-                .into_iter()
-                .map(|(model, (entity, goodbye, button, gb_button))| {
-                    (
-                        model,
-                        entity
-                            .into_iter()
-                            .map(|e| e.get())
-                            .map(|(e, u)| e.to_entity(u))
-                            .collect(),
-                        goodbye
-                            .into_iter()
-                            .map(|e| e.get())
-                            .map(|(e, u)| e.to_entity(u))
-                            .collect(),
-                        get_markup_for_buttons(button.into_iter().collect()),
-                        get_markup_for_buttons(gb_button.into_iter().collect()),
-                    )
-                })
-                .next();
-
-            if let Some(ref map) = res {
-                REDIS
-                    .try_pipe(|p| {
-                        Ok(p.set(&key, map.to_redis()?)
-                            .expire(&key, CONFIG.timing.cache_timeout))
-                    })
-                    .await?;
-            }
-            Ok(res)
-        };
-
-        log::info!("should_welcome {:?}", res);
-        res
-    }
-
     /// Adds a user to the list of users that have completed the captcha for the current chat.
     /// These users will not be asked to complete the captcha again
     pub async fn authorize_user<'a>(&self, user: i64, unmute_chat: &Chat) -> Result<()> {
         let key = auth_key(unmute_chat.get_id());
         let (r, _): (i64, ()) = REDIS
-            .pipe(|q| q.sadd(&key, user).expire(&key, CONFIG.timing.cache_timeout))
+            .pipe(|q| {
+                q.sadd(&key, user)
+                    .expire(&key, CONFIG.load().timing.cache_timeout)
+            })
             .await?;
         if r == 1 {
             let model = authorized::Model {
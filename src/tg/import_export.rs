@@ -30,7 +30,7 @@ use crate::{
 
 use super::{
     admin_helpers::is_dm,
-    button::{InlineKeyboardBuilder, OnPush},
+    button::{callback_data, InlineKeyboardBuilder, OnPush},
     command::Context,
     markdown::EntityMessage,
 };
@@ -77,7 +77,7 @@ pub async fn is_tainted(media_id: &str, scope: &str, chat: i64) -> Result<bool>
                 .one(*DB)
                 .await?)
         },
-        Duration::try_seconds(CONFIG.timing.cache_timeout).unwrap(),
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
     )
     .query(&key, &())
     .await?;
@@ -282,11 +282,11 @@ impl Context {
             let mut buttons = InlineKeyboardBuilder::default();
 
             let delete = InlineKeyboardButtonBuilder::new(lang_fmt!(self, "taintdelete"))
-                .set_callback_data(Uuid::new_v4().to_string())
+                .set_callback_data(callback_data(Some(user)))
                 .build();
 
             let replace = InlineKeyboardButtonBuilder::new(lang_fmt!(self, "taintreplace"))
-                .set_callback_data(Uuid::new_v4().to_string())
+                .set_callback_data(callback_data(Some(user)))
                 .build();
             let id = media_id.clone();
             let taintmessage = lang_fmt!(self, "taintforward", media_type);
@@ -0,0 +1,222 @@
+//! Welcomes during mass joins. When members join faster than a few every several seconds
+//! the chat is in a burst, usually a raid, and welcoming or posting a captcha for each of
+//! them would bury the chat. Members joining during a burst are buffered in redis instead,
+//! and once joins stop for a moment a single message welcomes all of them. Members who need
+//! a captcha are still muted, and the message carries one button leading to the captcha in
+//! the bot's dm rather than a captcha per member
+
+use std::time::Duration;
+
+use botapi::gen_types::{EReplyMarkup, InlineKeyboardButtonBuilder, User};
+use itertools::Itertools;
+use macros::lang_fmt;
+use redis::AsyncCommands;
+
+use crate::statics::{REDIS, TG};
+use crate::util::error::{BotError, Result};
+use crate::util::string::{should_ignore_chat, Lang};
+
+use super::admin_helpers::{DeleteAfterTime, UpdateHelpers, UserChanged};
+use super::bots;
+use super::button::InlineKeyboardBuilder;
+use super::command::Context;
+use super::deeplink::DeepLink;
+
+/// Seconds joins are counted over when looking for a burst. A burst ends once this long
+/// passes without a join
+const BURST_WINDOW: i64 = 10;
+
+/// Joins within the window that start a burst
+const BURST_THRESHOLD: i64 = 5;
+
+/// How often the welcome task checks whether the burst is over
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Longest members wait for their welcome during a long burst
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// Longest a buffered join is kept if the welcome is never sent
+const BUFFER_SECONDS: i64 = 5 * 60;
+
+/// Names listed in the welcome before the rest are counted
+const MAX_NAMES: usize = 10;
+
+/// How long the welcome stays in the chat
+const WELCOME_SECONDS: i64 = 10 * 60;
+
+#[inline(always)]
+fn get_count_key(chat: i64) -> String {
+    format!("jbcount:{}", chat)
+}
+
+#[inline(always)]
+fn get_active_key(chat: i64) -> String {
+    format!("jbactive:{}", chat)
+}
+
+#[inline(always)]
+fn get_buffer_key(chat: i64) -> String {
+    format!("jbbuf:{}", chat)
+}
+
+#[inline(always)]
+fn get_flush_key(chat: i64) -> String {
+    format!("jbflush:{}", chat)
+}
+
+#[inline(always)]
+fn get_captcha_key(chat: i64) -> String {
+    format!("jbcaptcha:{}", chat)
+}
+
+/// Counts a join, returning true if the chat is in a burst. Once started a burst lasts
+/// until joins stop for the length of the window
+async fn count_join(chat: i64) -> Result<bool> {
+    let key = get_count_key(chat);
+    let active = get_active_key(chat);
+    let (count, _, in_burst): (i64, (), bool) = REDIS
+        .pipe(|q| {
+            q.atomic()
+                .incr(&key, 1)
+                .expire(&key, BURST_WINDOW)
+                .exists(&active)
+        })
+        .await?;
+    if count >= BURST_THRESHOLD || in_burst {
+        REDIS
+            .pipe(|q| q.set(&active, true).expire(&active, BURST_WINDOW))
+            .await?;
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+/// Gets a deep link url for a captcha in the bot's dm that any member waiting on a captcha
+/// in the chat can use, unlike the per member links posted outside of bursts
+async fn get_chat_captcha_url(chat: i64) -> Result<String> {
+    DeepLink::Captcha { chat, user: None }.url().await
+}
+
+/// Buffers a member who joined during a burst, starting the timer for the combined welcome
+/// if it isn't running
+async fn buffer_join(chat: i64, user: &User, lang: Lang, captcha: bool) -> Result<()> {
+    let buffer = get_buffer_key(chat);
+    let flush = get_flush_key(chat);
+    let captcha_key = get_captcha_key(chat);
+    let name = user.get_first_name();
+    let (_, _, claimed, _): ((), (), bool, ()) = REDIS
+        .pipe(|q| {
+            q.atomic()
+                .rpush(&buffer, name)
+                .expire(&buffer, BUFFER_SECONDS)
+                .set_nx(&flush, true)
+                .expire(&flush, BUFFER_SECONDS)
+        })
+        .await?;
+    if captcha {
+        REDIS
+            .pipe(|q| {
+                q.set(&captcha_key, true)
+                    .expire(&captcha_key, BUFFER_SECONDS)
+            })
+            .await?;
+    }
+
+    if claimed {
+        bots::spawn(async move {
+            if let Err(err) = flush_joins(chat, lang).await {
+                log::warn!("failed to welcome burst in {}: {}", chat, err);
+                err.record_stats();
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Waits for the burst to end, then welcomes every buffered member in one message
+async fn flush_joins(chat: i64, lang: Lang) -> Result<()> {
+    let active = get_active_key(chat);
+    let mut waited = Duration::ZERO;
+    while waited < MAX_WAIT {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        waited += POLL_INTERVAL;
+        if !REDIS.sq(|q| q.exists(&active)).await? {
+            break;
+        }
+    }
+
+    let (names, _, captcha, _, _): (Vec<String>, (), Option<bool>, (), ()) = REDIS
+        .pipe(|q| {
+            q.atomic()
+                .lrange(get_buffer_key(chat), 0, -1)
+                .del(get_buffer_key(chat))
+                .get(get_captcha_key(chat))
+                .del(get_captcha_key(chat))
+                .del(get_flush_key(chat))
+        })
+        .await?;
+    if names.is_empty() || should_ignore_chat(chat).await? {
+        return Ok(());
+    }
+
+    let shown = names.iter().take(MAX_NAMES).join(", ");
+    let text = if names.len() > MAX_NAMES {
+        lang_fmt!(lang, "burstwelcomeothers", shown, names.len() - MAX_NAMES)
+    } else {
+        lang_fmt!(lang, "burstwelcome", shown)
+    };
+    let markup = if captcha.unwrap_or(false) {
+        let mut buttons = InlineKeyboardBuilder::default();
+        buttons.button(
+            InlineKeyboardButtonBuilder::new(lang_fmt!(lang, "captcha"))
+                .set_url(get_chat_captcha_url(chat).await?)
+                .build(),
+        );
+        Some(EReplyMarkup::InlineKeyboardMarkup(buttons.build()))
+    } else {
+        None
+    };
+    let mut call = TG.client().build_send_message(chat, &text);
+    if let Some(ref markup) = markup {
+        call = call.reply_markup(markup);
+    }
+    call.build()
+        .await?
+        .delete_after_time(chrono::Duration::try_seconds(WELCOME_SECONDS).unwrap());
+    Ok(())
+}
+
+impl Context {
+    /// Counts the member joining in this update, returning true if the chat is in a burst.
+    /// Joins are only counted once per update however many times this is called
+    pub async fn in_join_burst(&self) -> Result<bool> {
+        let burst = self
+            .get_static()
+            .join_burst
+            .get_or_try_init(|| async {
+                match self.update().user_event() {
+                    Some(UserChanged::UserJoined(member)) => {
+                        count_join(member.get_chat().get_id()).await
+                    }
+                    _ => Ok::<_, BotError>(false),
+                }
+            })
+            .await?;
+        Ok(*burst)
+    }
+
+    /// Buffers the member joining in this update for the combined welcome if the chat is in
+    /// a burst, returning false if they should be welcomed on their own
+    pub async fn buffer_burst_join(&self, captcha: bool) -> Result<bool> {
+        if !self.in_join_burst().await? {
+            return Ok(false);
+        }
+        let Some(UserChanged::UserJoined(member)) = self.update().user_event() else {
+            return Ok(false);
+        };
+        let user = member.get_new_chat_member().get_user();
+        buffer_join(member.get_chat().get_id(), user, *self.lang(), captcha).await?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,293 @@
+//! Automatic handling of requests to join a chat. Each chat picks a policy stored in its
+//! dialog: approve everyone, approve accounts older than a number of days, require a captcha
+//! solved in the bot's dm, or post each request with buttons for admins to decide.
+//!
+//! Requests waiting on a captcha or an admin are tracked in redis so whichever of the
+//! buttons is pushed first handles the request and the rest do nothing
+
+use botapi::gen_types::{
+    CallbackQuery, Chat, ChatJoinRequest, EReplyMarkup, InlineKeyboardButtonBuilder,
+    MaybeInaccessibleMessage,
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use macros::lang_fmt;
+use redis::AsyncCommands;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::EntityTrait;
+
+use crate::persist::core::dialogs::{self, JoinPolicy};
+use crate::statics::{CONFIG, DB, REDIS, TG};
+use crate::util::error::Result;
+use crate::util::string::{get_chat_lang, should_ignore_chat};
+use crate::util::time::ChatTime;
+
+use super::button::{
+    callback_data, persist_action, ButtonAction, CallbackReply, InlineKeyboardBuilder,
+};
+use super::command::Context;
+use super::dialog::{dialog_or_default, dialog_scope};
+use super::greetings::send_captcha_to;
+use super::log_channel::get_log_channel;
+use super::permissions::IsAdmin;
+use super::user::{GetChat, Username};
+
+/// Known user ids and roughly when they were assigned, in unix time. Telegram doesn't
+/// expose account creation dates but ids are handed out in increasing order, so
+/// interpolating between these gives a usable estimate
+const ID_DATES: &[(i64, i64)] = &[
+    (0, 1375315200),             // 2013-08
+    (100_000_000, 1425168000),   // 2015-03
+    (200_000_000, 1459468800),   // 2016-04
+    (400_000_000, 1498867200),   // 2017-07
+    (800_000_000, 1561939200),   // 2019-07
+    (1_500_000_000, 1606780800), // 2020-12
+    (2_100_000_000, 1633046400), // 2021-10
+    (5_000_000_000, 1640995200), // 2022-01
+    (6_000_000_000, 1682899200), // 2023-05
+    (7_000_000_000, 1709251200), // 2024-03
+    (7_500_000_000, 1725148800), // 2024-09
+    (8_000_000_000, 1740787200), // 2025-03
+];
+
+#[inline(always)]
+fn get_pending_key(chat: i64, user: i64) -> String {
+    format!("jreq:{}:{}", chat, user)
+}
+
+/// Estimates when an account was created from its id. Ids newer than the last known id
+/// are assumed to be brand new
+pub fn estimate_account_created(user: i64, now: DateTime<Utc>) -> DateTime<Utc> {
+    let Some(upper) = ID_DATES.iter().position(|(id, _)| *id > user) else {
+        return now;
+    };
+    let (low_id, low_time) = ID_DATES[upper.saturating_sub(1)];
+    let (high_id, high_time) = ID_DATES[upper];
+    let time = if upper == 0 {
+        low_time
+    } else {
+        low_time + (high_time - low_time) * (user - low_id) / (high_id - low_id)
+    };
+    Utc.timestamp_opt(time, 0).single().unwrap_or(now)
+}
+
+/// Checks if an account is estimated to be at least the given number of days old
+pub fn account_older_than(user: i64, days: i64) -> bool {
+    let now = Utc::now();
+    Duration::try_days(days)
+        .and_then(|d| estimate_account_created(user, now).checked_add_signed(d))
+        .map(|d| d <= now)
+        .unwrap_or(false)
+}
+
+/// Sets the join request policy for a chat. The minimum age is only used with
+/// JoinPolicy::MinAge
+pub async fn set_join_policy(chat: &Chat, policy: JoinPolicy, min_age: Option<i32>) -> Result<()> {
+    let mut model = dialogs::Model::from_chat(chat).await?;
+    model.join_policy = Set(policy);
+    model.join_min_age = Set(min_age);
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_columns([dialogs::Column::JoinPolicy, dialogs::Column::JoinMinAge])
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat.get_id()).invalidate().await?;
+    Ok(())
+}
+
+async fn set_pending(chat: i64, user: i64) -> Result<()> {
+    let key = get_pending_key(chat, user);
+    REDIS
+        .pipe(|q| {
+            q.set(&key, true)
+                .expire(&key, CONFIG.load().timing.cache_timeout)
+        })
+        .await?;
+    Ok(())
+}
+
+/// Removes a pending join request, returning true if it was still pending
+async fn take_pending(chat: i64, user: i64) -> Result<bool> {
+    let key = get_pending_key(chat, user);
+    let removed: i64 = REDIS.sq(|q| q.del(&key)).await?;
+    Ok(removed > 0)
+}
+
+/// Approves a pending join request after the user solved a captcha. Returns false if the
+/// user had no pending request in the chat
+pub async fn approve_pending(chat: i64, user: i64) -> Result<bool> {
+    if !take_pending(chat, user).await? {
+        return Ok(false);
+    }
+    TG.client
+        .build_approve_chat_join_request(chat, user)
+        .build()
+        .await?;
+    Ok(true)
+}
+
+/// Declines a pending join request after the user failed a captcha. Returns false if the
+/// user had no pending request in the chat
+pub async fn decline_pending(chat: i64, user: i64) -> Result<bool> {
+    if !take_pending(chat, user).await? {
+        return Ok(false);
+    }
+    TG.client
+        .build_decline_chat_join_request(chat, user)
+        .build()
+        .await?;
+    Ok(true)
+}
+
+async fn ask_admins(
+    ctx: &Context,
+    request: &ChatJoinRequest,
+    created: DateTime<Utc>,
+) -> Result<()> {
+    let chat = request.get_chat();
+    let user = request.get_from();
+    let target = get_log_channel(chat).await?.unwrap_or(chat.get_id());
+    if should_ignore_chat(target).await? {
+        return Ok(());
+    }
+    set_pending(chat.get_id(), user.get_id()).await?;
+
+    let mut buttons = InlineKeyboardBuilder::default();
+    for (approve, text) in [
+        (true, lang_fmt!(ctx, "approvejoin")),
+        (false, lang_fmt!(ctx, "declinejoin")),
+    ] {
+        let button = InlineKeyboardButtonBuilder::new(text)
+            .set_callback_data(callback_data(None))
+            .build();
+        let action = ButtonAction::JoinRequest {
+            chat: chat.get_id(),
+            user: user.get_id(),
+            approve,
+        };
+        persist_action(&button, &action).await?;
+        buttons.button(button);
+    }
+
+    let created = ChatTime::get(chat.get_id())
+        .await?
+        .local(&created)
+        .format("%Y-%m");
+    let text = lang_fmt!(
+        ctx,
+        "joinrequest",
+        user.name_humanreadable(),
+        user.get_id(),
+        chat.name_humanreadable(),
+        created
+    );
+    TG.client
+        .build_send_message(target, &text)
+        .reply_markup(&EReplyMarkup::InlineKeyboardMarkup(buttons.build()))
+        .build()
+        .await?;
+    Ok(())
+}
+
+/// Applies the chat's join policy to a join request
+pub async fn handle_join_request(ctx: &Context, request: &ChatJoinRequest) -> Result<()> {
+    let chat = request.get_chat();
+    let user = request.get_from().get_id();
+    let dialog = dialog_or_default(chat).await?;
+    let created = estimate_account_created(user, Utc::now());
+    match dialog.join_policy {
+        JoinPolicy::Off => (),
+        JoinPolicy::ApproveAll => {
+            TG.client
+                .build_approve_chat_join_request(chat.get_id(), user)
+                .build()
+                .await?;
+        }
+        JoinPolicy::MinAge => {
+            let days = dialog.join_min_age.unwrap_or(0) as i64;
+            if account_older_than(user, days) {
+                TG.client
+                    .build_approve_chat_join_request(chat.get_id(), user)
+                    .build()
+                    .await?;
+            } else {
+                ask_admins(ctx, request, created).await?;
+            }
+        }
+        JoinPolicy::Captcha => {
+            set_pending(chat.get_id(), user).await?;
+            send_captcha_to(request.get_user_chat_id(), None, chat.get_id(), ctx.lang()).await?;
+        }
+        JoinPolicy::Admins => ask_admins(ctx, request, created).await?,
+    }
+    Ok(())
+}
+
+/// Handles an admin pushing the approve or decline button on a posted join request
+pub(crate) async fn join_request_pushed(
+    callback: &CallbackQuery,
+    chat: i64,
+    user: i64,
+    approve: bool,
+) -> Result<(bool, CallbackReply)> {
+    let lang = get_chat_lang(chat).await?;
+    let Some(group) = chat.get_chat().await? else {
+        return Ok((true, CallbackReply::default()));
+    };
+    let from = callback.get_from();
+    if !from.get_permissions(&group).await?.can_restrict_members {
+        return Ok((
+            false,
+            CallbackReply::alert(lang_fmt!(lang, "joinrequestnoperm")),
+        ));
+    }
+
+    let handled = if approve {
+        approve_pending(chat, user).await?
+    } else {
+        decline_pending(chat, user).await?
+    };
+    if !handled {
+        return Ok((
+            true,
+            CallbackReply::toast(lang_fmt!(lang, "joinrequesthandled")),
+        ));
+    }
+
+    let text = if approve {
+        lang_fmt!(lang, "joinapproved", user, from.name_humanreadable())
+    } else {
+        lang_fmt!(lang, "joindeclined", user, from.name_humanreadable())
+    };
+    if let Some(MaybeInaccessibleMessage::Message(message)) = callback.get_message() {
+        TG.client
+            .build_edit_message_text(&text)
+            .chat_id(message.get_chat().get_id())
+            .message_id(message.get_message_id())
+            .build()
+            .await?;
+    }
+    Ok((true, CallbackReply::default()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn account_age() {
+        let now = Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap();
+        let old = estimate_account_created(150_000_000, now);
+        assert!(old > Utc.with_ymd_and_hms(2015, 3, 1, 0, 0, 0).unwrap());
+        assert!(old < Utc.with_ymd_and_hms(2016, 4, 1, 0, 0, 0).unwrap());
+        assert_eq!(estimate_account_created(9_000_000_000, now), now);
+        assert!(
+            estimate_account_created(6_500_000_000, now)
+                > estimate_account_created(6_000_000_000, now)
+        );
+    }
+}
@@ -0,0 +1,59 @@
+//! Per-chat log channel. Admins can point a chat at a channel the bot can post in and
+//! automated or deferred actions performed in that chat are reported there.
+
+use botapi::gen_types::Chat;
+use chrono::Utc;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::EntityTrait;
+
+use crate::persist::core::dialogs;
+use crate::statics::DB;
+use crate::util::error::Result;
+use crate::util::string::Speak;
+use crate::util::time::ChatTime;
+
+use super::dialog::{dialog_scope, get_dialog};
+use super::markdown::Escape;
+
+/// Sets or clears the log channel for the provided chat
+pub async fn set_log_channel(chat: &Chat, channel: Option<i64>) -> Result<()> {
+    let chat_id = chat.get_id();
+
+    let model = dialogs::ActiveModel {
+        log_channel: Set(channel),
+        ..dialogs::ActiveModel::for_chat(chat)
+    };
+
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_column(dialogs::Column::LogChannel)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat_id).invalidate().await?;
+    Ok(())
+}
+
+/// Gets the log channel configured for a chat, if any
+pub async fn get_log_channel(chat: &Chat) -> Result<Option<i64>> {
+    Ok(get_dialog(chat).await?.and_then(|d| d.log_channel))
+}
+
+/// Posts a message to the chat's log channel, prefixed with the chat's local time. Does
+/// nothing if no log channel is set
+pub async fn send_log<T>(chat: &Chat, message: T) -> Result<()>
+where
+    T: AsRef<str> + Send + Sync,
+{
+    if let Some(channel) = get_log_channel(chat).await? {
+        let time = ChatTime::get(chat.get_id()).await?.format(&Utc::now());
+        channel
+            .speak(format!("{}\n{}", time.escape(false), message.as_ref()))
+            .await?;
+    }
+    Ok(())
+}
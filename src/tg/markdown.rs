@@ -17,6 +17,7 @@ use itertools::Itertools;
 use lazy_static::lazy_static;
 use markdown::{Block, ListItem, Span};
 use pomelo::pomelo;
+use rand::Rng;
 use regex::Regex;
 
 use std::borrow::Cow;
@@ -24,7 +25,6 @@ use std::collections::BTreeSet;
 use std::fmt::Display;
 use std::sync::Arc;
 use thiserror::Error;
-use uuid::Uuid;
 
 /// Custom error type for murkdown parse failure. TODO: add additional context here
 #[derive(Debug, Error, Default)]
@@ -271,8 +271,8 @@ pomelo! {
 use parser::{Parser, Token};
 
 use super::admin_helpers::{is_dm, ChatUser};
-use super::button::InlineKeyboardBuilder;
-use super::command::post_deep_link;
+use super::button::{callback_data, InlineKeyboardBuilder};
+use super::deeplink::DeepLink;
 use super::user::Username;
 
 #[derive(Debug)]
@@ -583,6 +583,8 @@ impl<'a> From<&'a ChatUser<'a>> for OwnedChatUser {
     }
 }
 
+/// Builder for MessageEntity formatting. Generates MessageEntities from either murkdown
+/// or manually
 #[derive(Clone)]
 pub struct MarkupBuilder {
     existing_entities: Option<Vec<MessageEntity>>,
@@ -599,16 +601,8 @@ pub struct MarkupBuilder {
     chatuser: Option<OwnedChatUser>,
     pub built_markup: Option<EReplyMarkup>,
     pub fillings: BTreeSet<String>,
-}
-
-#[inline(always)]
-pub(crate) fn button_deeplink_key(key: &str) -> String {
-    format!("bdlk:{}", key)
-}
-
-#[inline(always)]
-pub(crate) fn rules_deeplink_key(key: &str) -> String {
-    format!("dlrules:{}", key)
+    spoiler: Option<i64>,
+    variants: bool,
 }
 
 pub fn get_markup_for_buttons(button: Vec<button::Model>) -> Option<InlineKeyboardBuilder> {
@@ -634,8 +628,112 @@ pub fn get_markup_for_buttons(button: Vec<button::Model>) -> Option<InlineKeyboa
     }
 }
 
-/// Builder for MessageEntity formatting. Generates MessageEntities from either murkdown
-/// or manually
+/// A range of message text covered by an entity. Spans are tracked as byte indices into the
+/// text they were created from and only converted to telegram's utf16 offsets when an entity
+/// is built, so nested formatting can't drift out of alignment with emoji or other astral
+/// characters
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EntitySpan {
+    start: usize,
+    end: usize,
+}
+
+impl EntitySpan {
+    /// Constructs a span from byte indices, failing if the range is out of bounds or does not
+    /// fall on char boundaries
+    pub fn new(text: &str, start: usize, end: usize) -> Result<Self> {
+        if start > end || end > text.len() {
+            return Err(BotError::Generic(format!(
+                "entity span {}..{} out of bounds for text of length {}",
+                start,
+                end,
+                text.len()
+            )));
+        }
+
+        if !text.is_char_boundary(start) || !text.is_char_boundary(end) {
+            return Err(BotError::Generic(format!(
+                "entity span {}..{} splits a character",
+                start, end
+            )));
+        }
+
+        Ok(Self { start, end })
+    }
+
+    /// Constructs a span from a telegram utf16 offset and length, failing if either end lands
+    /// inside a surrogate pair or past the end of the text
+    pub fn from_utf16(text: &str, offset: i64, length: i64) -> Result<Self> {
+        if offset < 0 || length < 0 {
+            return Err(BotError::Generic(format!(
+                "negative entity offset {} length {}",
+                offset, length
+            )));
+        }
+        let start = utf16_to_byte(text, offset)?;
+        let end = utf16_to_byte(text, offset + length)?;
+        Self::new(text, start, end)
+    }
+
+    /// Byte index of the start of the span
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Byte index of the end of the span (exclusive)
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Gets the text covered by this span
+    pub fn as_str<'a>(&self, text: &'a str) -> &'a str {
+        &text[self.start..self.end]
+    }
+
+    /// Converts this span to a telegram (offset, length) pair in utf16 code units
+    pub fn to_utf16(&self, text: &str) -> (i64, i64) {
+        let offset = text[..self.start].encode_utf16().count() as i64;
+        let length = self.as_str(text).encode_utf16().count() as i64;
+        (offset, length)
+    }
+}
+
+/// Maps a utf16 offset to a byte index in the text
+fn utf16_to_byte(text: &str, offset: i64) -> Result<usize> {
+    let mut pos = 0;
+    for (idx, c) in text.char_indices() {
+        if pos == offset {
+            return Ok(idx);
+        }
+        if pos > offset {
+            break;
+        }
+        pos += c.len_utf16() as i64;
+    }
+
+    if pos == offset {
+        Ok(text.len())
+    } else {
+        Err(BotError::Generic(format!(
+            "utf16 offset {} is not on a character boundary",
+            offset
+        )))
+    }
+}
+
+/// Checks that every entity lies within the text and starts and ends on a character
+/// boundary. Returns the spans covered by each entity in order
+pub fn audit_entities(text: &str, entities: &[MessageEntity]) -> Result<Vec<EntitySpan>> {
+    entities
+        .iter()
+        .map(|entity| EntitySpan::from_utf16(text, entity.get_offset(), entity.get_length()))
+        .collect()
+}
+
 impl MarkupBuilder {
     /// Constructs a new empty builder for manual formatting
     pub fn new(existing: Option<Vec<MessageEntity>>) -> Self {
@@ -658,13 +756,15 @@ impl MarkupBuilder {
             chatuser: None,
             built_markup: None,
             fillings: BTreeSet::new(),
+            spoiler: None,
+            variants: false,
         }
     }
 
     async fn rules(&mut self) -> Result<()> {
         log::info!("adding rules {}", self.chatuser.is_some());
         if let Some(ref chatuser) = self.chatuser {
-            let url = post_deep_link(chatuser.chat.get_id(), rules_deeplink_key).await?;
+            let url = DeepLink::Rules(chatuser.chat.get_id()).url().await?;
 
             let button = InlineKeyboardButtonBuilder::new("Get rules".to_owned())
                 .set_url(url)
@@ -692,7 +792,7 @@ impl MarkupBuilder {
             let tail = &button_text[idx..];
 
             let button = InlineKeyboardButtonBuilder::new(hint)
-                .set_callback_data(Uuid::new_v4().to_string())
+                .set_callback_data(callback_data(None))
                 .build();
 
             (*self.button_function)(tail.to_owned(), &button).await?;
@@ -705,7 +805,12 @@ impl MarkupBuilder {
             let chat = chat.chat.get_id();
             let tail = &button_text[1..];
 
-            let url = post_deep_link((chat, tail), button_deeplink_key).await?;
+            let url = DeepLink::Note {
+                chat,
+                name: tail.to_owned(),
+            }
+            .url()
+            .await?;
 
             InlineKeyboardButtonBuilder::new(hint).set_url(url).build()
         } else {
@@ -719,12 +824,12 @@ impl MarkupBuilder {
         Ok(())
     }
 
-    fn parse_tgspan(&mut self, span: Vec<TgSpan>) -> BoxFuture<Result<(i64, i64)>> {
+    fn parse_tgspan(&mut self, span: Vec<TgSpan>) -> BoxFuture<Result<EntitySpan>> {
         async move {
             // if topplevel {
             //     log::info!("parse_tgspan {:?}", span);
             // }
-            let mut size = 0;
+            let start = self.text.len();
 
             for span in span {
                 //a               log::info!("diff {} offset {}", self.diff, self.offset);
@@ -737,31 +842,26 @@ impl MarkupBuilder {
                         self.pre(&code, lang, None);
                     }
                     (TgSpan::Italic(s), _) => {
-                        let (s, e) = self.parse_tgspan(s).await?;
-                        size += e;
-                        self.manual("italic", s, e);
+                        let span = self.parse_tgspan(s).await?;
+                        self.manual("italic", &span);
                     }
                     (TgSpan::Bold(s), _) => {
                         self.diff += "[*".encode_utf16().count() as i64;
-                        let (s, e) = self.parse_tgspan(s).await?;
+                        let span = self.parse_tgspan(s).await?;
                         self.diff += "]".encode_utf16().count() as i64;
-                        size += e;
-                        self.manual("bold", s, e);
+                        self.manual("bold", &span);
                     }
                     (TgSpan::Strikethrough(s), _) => {
-                        let (s, e) = self.parse_tgspan(s).await?;
-                        size += e;
-                        self.manual("strikethrough", s, e);
+                        let span = self.parse_tgspan(s).await?;
+                        self.manual("strikethrough", &span);
                     }
                     (TgSpan::Underline(s), _) => {
-                        let (s, e) = self.parse_tgspan(s).await?;
-                        size += e;
-                        self.manual("underline", s, e);
+                        let span = self.parse_tgspan(s).await?;
+                        self.manual("underline", &span);
                     }
                     (TgSpan::Spoiler(s), _) => {
-                        let (s, e) = self.parse_tgspan(s).await?;
-                        size += e;
-                        self.manual("spoiler", s, e);
+                        let span = self.parse_tgspan(s).await?;
+                        self.manual("spoiler", &span);
                     }
                     (TgSpan::Button(hint, button), _) => {
                         self.button(hint, button).await?;
@@ -771,17 +871,11 @@ impl MarkupBuilder {
                         self.button(hint, button).await?;
                     }
                     (TgSpan::Link(hint, link), _) => {
-                        let (s, e) = self.parse_tgspan(hint).await?;
-                        size += e;
-                        let entity = MessageEntityBuilder::new(s, e)
-                            .set_type("text_link".to_owned())
-                            .set_url(link)
-                            .build();
+                        let span = self.parse_tgspan(hint).await?;
+                        let entity = self.span_entity(&span, "text_link").set_url(link).build();
                         self.entities.push(entity);
                     }
                     (TgSpan::Raw(s), _) => {
-                        size += s.encode_utf16().count() as i64;
-
                         self.text_internal(&s);
                     }
                     (TgSpan::Filling(filling), Some(chatuser)) if self.filling => {
@@ -789,12 +883,10 @@ impl MarkupBuilder {
                             "username" => {
                                 let user = chatuser.user.clone();
                                 let name = user.name_humanreadable().into_owned();
-                                size += name.encode_utf16().count() as i64;
                                 self.text_mention(name, user, None);
                             }
                             "first" => {
                                 let first = chatuser.user.get_first_name().to_owned();
-                                size += first.encode_utf16().count() as i64;
                                 self.text_internal(&first);
                             }
                             "last" => {
@@ -803,23 +895,19 @@ impl MarkupBuilder {
                                     .get_last_name()
                                     .map(|v| v.to_owned())
                                     .unwrap_or_else(|| "".to_owned());
-                                size += last.encode_utf16().count() as i64;
                                 self.text_internal(&last);
                             }
                             "mention" => {
                                 let user = chatuser.user.clone();
                                 let first = user.get_first_name().to_owned();
-                                size += first.encode_utf16().count() as i64;
                                 self.text_mention(first, user, None);
                             }
                             "chatname" => {
                                 let chat = chatuser.chat.name_humanreadable().into_owned();
-                                size += chat.encode_utf16().count() as i64;
                                 self.text_internal(&chat);
                             }
                             "id" => {
                                 let id = chatuser.user.get_id().to_string();
-                                size += id.encode_utf16().count() as i64;
                                 self.text_internal(&id);
                             }
                             "rules" => {
@@ -827,7 +915,6 @@ impl MarkupBuilder {
                             }
                             s => {
                                 let s = format!("{{{}}}", s);
-                                size += s.encode_utf16().count() as i64;
                                 self.text_internal(&s);
                             }
                         }
@@ -835,12 +922,10 @@ impl MarkupBuilder {
                     (TgSpan::Filling(filling), _) => {
                         if filling.trim().is_empty() {
                             let s = format!("{{{}}}", filling);
-                            size += s.encode_utf16().count() as i64;
                             self.text_internal(&s);
                         } else {
                             if self.enabled_fillings {
                                 let s = format!("{{{}}}", filling);
-                                size += s.encode_utf16().count() as i64;
                                 self.text_internal(&s);
                             }
                             self.fillings.insert(filling);
@@ -848,21 +933,30 @@ impl MarkupBuilder {
                     }
                     (TgSpan::NoOp, _) => (),
                 };
-                self.patch_entities(size);
+                self.patch_entities();
             }
 
-            let offset = self.offset - size;
-            Ok((offset, size))
+            EntitySpan::new(&self.text, start, self.text.len())
         }
         .boxed()
     }
 
-    fn patch_entities(&mut self, size: i64) {
+    /// Converts a span of the output text into an entity. The builder's utf16 offset always
+    /// refers to the end of the text, so the span's offset is counted back from there. This
+    /// keeps nested entities aligned even when the text before them was added with a
+    /// different offset than its length (escaped text or manual advances)
+    fn span_entity(&self, span: &EntitySpan, entity_type: &str) -> MessageEntityBuilder {
+        let (_, length) = span.to_utf16(&self.text);
+        let tail = self.text[span.start()..].encode_utf16().count() as i64;
+        MessageEntityBuilder::new(self.offset - tail, length).set_type(entity_type.to_owned())
+    }
+
+    fn patch_entities(&mut self) {
         if let Some(existing_entities) = self.existing_entities.as_mut() {
             if self.diff != 0 {
                 for entity in existing_entities.iter_mut() {
                     if entity.get_offset() >= self.offset {
-                        log::info!("patching entity {} {} {}", self.offset, size, self.diff);
+                        log::info!("patching entity {} {}", self.offset, self.diff);
                         entity.set_offset(entity.get_offset() - self.diff);
                     }
                 }
@@ -911,11 +1005,7 @@ impl MarkupBuilder {
                 self.text_internal(s);
                 s.encode_utf16().count() as i64
             }
-            Span::Text(text) => {
-                let i = text.encode_utf16().count() as i64;
-                self.text_internal(&text);
-                i
-            }
+            Span::Text(text) => self.spoiler_text(&text),
             Span::Code(code) => {
                 let i = code.encode_utf16().count() as i64;
                 self.code(code);
@@ -926,7 +1016,18 @@ impl MarkupBuilder {
                 self.text_link(hint, link, None);
                 i
             }
-            Span::Image(_, _, _) => 0,
+            Span::Image(alt, url, _) => {
+                if let Some(id) = url.strip_prefix(CUSTOM_EMOJI_PREFIX) {
+                    let i = alt.encode_utf16().count() as i64;
+                    self.custom_emoji(alt, id.to_owned(), None);
+                    return i;
+                }
+                // telegram can't show images inline, so link to them instead
+                let hint = if alt.is_empty() { url.clone() } else { alt };
+                let i = hint.encode_utf16().count() as i64;
+                self.text_link(hint, url, None);
+                i
+            }
             Span::Emphasis(emp) => {
                 let mut size: i64 = 0;
                 let start = self.offset;
@@ -955,14 +1056,44 @@ impl MarkupBuilder {
         }
     }
 
+    /// Pushes plain text from markdown. Standard markdown has no spoilers so `||` and
+    /// `<tg-spoiler>` tags are treated as spoiler delimiters like in telegram's own formats
+    fn spoiler_text(&mut self, text: &str) -> i64 {
+        let mut size = 0;
+        let mut prev = 0;
+        for mat in SPOILER_REGEX.find_iter(text) {
+            size += self.push_text(&text[prev..mat.start()]);
+            prev = mat.end();
+            match (mat.as_str(), self.spoiler) {
+                ("</tg-spoiler>", None) | ("<tg-spoiler>", Some(_)) => (),
+                (_, None) => self.spoiler = Some(self.offset + size),
+                (_, Some(start)) => {
+                    self.spoiler = None;
+                    let len = self.offset + size - start;
+                    if len > 0 {
+                        let entity = MessageEntityBuilder::new(start, len)
+                            .set_type("spoiler".to_owned())
+                            .build();
+                        self.entities.push(entity);
+                    }
+                }
+            }
+        }
+        size += self.push_text(&text[prev..]);
+        self.offset += size;
+        size
+    }
+
     /// Parses vanilla markdown and constructs a builder with the corresponding text
-    /// and entities
+    /// and entities. Images become links to the image and spoilers are only closed if their
+    /// closing delimiter is found
     pub fn from_markdown<T: AsRef<str>>(text: T, existing: Option<Vec<MessageEntity>>) -> Self {
         let text = text.as_ref();
         let mut s = Self::new(existing);
         markdown::tokenize(text).into_iter().for_each(|v| {
             s.parse_block(v);
         });
+        s.spoiler = None;
         s
     }
 
@@ -1026,6 +1157,7 @@ impl MarkupBuilder {
     pub async fn build_murkdown<'a>(
         mut self,
     ) -> Result<(String, Vec<MessageEntity>, InlineKeyboardBuilder)> {
+        self.pick_variant();
         let mut parser = Parser::new();
         let mut tokenizer = Lexer::new(&self.text, self.enabled_header);
         for token in tokenizer.next_token() {
@@ -1048,6 +1180,7 @@ impl MarkupBuilder {
     }
 
     async fn nofail_internal(&mut self) -> Result<()> {
+        self.pick_variant();
         let mut parser = Parser::new();
         let mut tokenizer = Lexer::new(&self.text, self.enabled_header);
         for token in tokenizer.next_token() {
@@ -1124,6 +1257,26 @@ impl MarkupBuilder {
         self
     }
 
+    /// Picks one of the variants of the text at random before parsing, for sending saved
+    /// content. Left off when saving so every variant is stored
+    pub fn variants(mut self, variants: bool) -> Self {
+        self.variants = variants;
+        self
+    }
+
+    fn pick_variant(&mut self) {
+        if !self.variants {
+            return;
+        }
+        let text = std::mem::take(&mut self.text);
+        let existing = self.existing_entities.take();
+        let has_existing = existing.is_some();
+        let (text, existing) = pick_variant(text, existing.unwrap_or_default());
+        self.text = text;
+        self.existing_entities = has_existing.then_some(existing);
+        self.variants = false;
+    }
+
     /// Appends new unformated text
     pub fn text<T: AsRef<str>>(&mut self, text: T) -> &'_ mut Self {
         self.offset += text.unescape(self.enabled_header).encode_utf16().count() as i64;
@@ -1141,10 +1294,8 @@ impl MarkupBuilder {
         self
     }
 
-    fn manual(&mut self, entity_type: &str, start: i64, end: i64) {
-        let entity = MessageEntityBuilder::new(start, end)
-            .set_type(entity_type.to_owned())
-            .build();
+    fn manual(&mut self, entity_type: &str, span: &EntitySpan) {
+        let entity = self.span_entity(span, entity_type).build();
         self.entities.push(entity);
     }
 
@@ -1390,18 +1541,89 @@ impl MarkupBuilder {
 
 lazy_static! {
     static ref FILLER_REGEX: Regex = Regex::new(r"\{\w*\}").unwrap();
+    static ref SPOILER_REGEX: Regex = Regex::new(r"\|\||</?tg-spoiler>").unwrap();
 }
 
+/// Image urls with this prefix are custom emoji in telegram's markdown dialect
+pub const CUSTOM_EMOJI_PREFIX: &str = "tg://emoji?id=";
+
 pub fn remove_fillings(text: &str) -> String {
     FILLER_REGEX.replace_all(text, "").into_owned()
 }
 
+/// Separates variants of saved content, one of which is picked each time it's sent
+pub const VARIANT_SEPARATOR: &str = "%%%";
+
+/// Splits text on lines containing only [`VARIANT_SEPARATOR`] and keeps only the variant at
+/// the index chosen by `choose` from the number of variants, moving entities so they line up
+/// with what's left. Entities crossing into other variants are cut off at the variant's ends
+pub fn pick_variant_with<F>(
+    text: String,
+    entities: Vec<MessageEntity>,
+    choose: F,
+) -> (String, Vec<MessageEntity>)
+where
+    F: FnOnce(usize) -> usize,
+{
+    let mut separators = Vec::new();
+    let mut pos = 0;
+    for line in text.split_inclusive('\n') {
+        if line.trim() == VARIANT_SEPARATOR {
+            separators.push((pos, pos + line.len()));
+        }
+        pos += line.len();
+    }
+
+    let mut variants = Vec::new();
+    let mut start = 0;
+    for (end, next) in separators
+        .into_iter()
+        .chain(std::iter::once((text.len(), text.len())))
+    {
+        let variant = &text[start..end];
+        let trimmed = variant.trim();
+        if !trimmed.is_empty() {
+            let offset = start + (variant.len() - variant.trim_start().len());
+            variants.push((offset, offset + trimmed.len()));
+        }
+        start = next;
+    }
+    if variants.len() < 2 {
+        return (text, entities);
+    }
+
+    let (start, end) = variants[choose(variants.len()).min(variants.len() - 1)];
+    let start16 = text[..start].encode_utf16().count() as i64;
+    let end16 = start16 + text[start..end].encode_utf16().count() as i64;
+    let entities = entities
+        .into_iter()
+        .filter_map(|mut entity| {
+            let from = entity.get_offset().max(start16);
+            let to = (entity.get_offset() + entity.get_length()).min(end16);
+            if from >= to {
+                return None;
+            }
+            entity.set_offset(from - start16).set_length(to - from);
+            Some(entity)
+        })
+        .collect();
+    (text[start..end].to_owned(), entities)
+}
+
+/// Picks one of the variants of saved content at random, see [`pick_variant_with`]
+pub fn pick_variant(text: String, entities: Vec<MessageEntity>) -> (String, Vec<MessageEntity>) {
+    pick_variant_with(text, entities, |count| {
+        rand::thread_rng().gen_range(0..count)
+    })
+}
+
 pub async fn retro_fillings<'a>(
     text: String,
     entities: Vec<MessageEntity>,
     mut buttons: Option<&mut InlineKeyboardBuilder>,
     chatuser: &ChatUser<'a>,
 ) -> Result<(String, Vec<MessageEntity>)> {
+    let (text, entities) = pick_variant(text, entities);
     let mut res = String::with_capacity(text.len());
     let mut extra_entities = Vec::<MessageEntity>::new();
     let mut offsets = entities
@@ -1472,7 +1694,7 @@ pub async fn retro_fillings<'a>(
             }
             "rules" => {
                 if let Some(buttons) = buttons.as_mut() {
-                    let url = post_deep_link(chatuser.chat.get_id(), rules_deeplink_key).await?;
+                    let url = DeepLink::Rules(chatuser.chat.get_id()).url().await?;
 
                     let button = InlineKeyboardButtonBuilder::new("Get rules".to_owned())
                         .set_url(url)
@@ -1700,6 +1922,17 @@ impl EntityMessage {
         }
     }
 
+    /// Builds the message into its text, entities and buttons, the same way as [`Self::call`]
+    pub async fn parts(&mut self) -> (String, Vec<MessageEntity>, Option<EReplyMarkup>) {
+        let (text, entities, buttons) = self.builder.build_murkdown_nofail_ref().await;
+        let markup = match self.reply_markup {
+            Some(ref reply_markup) => Some(reply_markup.clone()),
+            None if self.disable_murkdown => None,
+            None => buttons.map(|v| v.clone()),
+        };
+        (text.clone(), entities.clone(), markup)
+    }
+
     pub fn textentities(&self) -> (&'_ str, &'_ Vec<MessageEntity>) {
         (&self.builder.text, &self.builder.entities)
     }
@@ -1912,6 +2145,198 @@ mod test {
         }
     }
 
+    #[test]
+    fn pick_variants() {
+        let text = "Hi {mention}\n%%%\nHello there friend\n%%%\n%%% bye".to_owned();
+        let entities = vec![
+            MessageEntityBuilder::new(0, 2)
+                .set_type("bold".to_owned())
+                .build(),
+            MessageEntityBuilder::new(23, 5)
+                .set_type("bold".to_owned())
+                .build(),
+            MessageEntityBuilder::new(14, 20)
+                .set_type("italic".to_owned())
+                .build(),
+        ];
+
+        let (first, entities_first) = pick_variant_with(text.clone(), entities.clone(), |_| 0);
+        assert_eq!(first, "Hi {mention}");
+        assert_eq!(entities_first.len(), 1);
+        assert_aligned(&first, &entities_first);
+
+        let (second, entities_second) = pick_variant_with(text.clone(), entities.clone(), |n| {
+            assert_eq!(n, 3);
+            1
+        });
+        assert_eq!(second, "Hello there friend");
+        let spans = assert_aligned(&second, &entities_second);
+        assert_eq!(spans[0].as_str(&second), "there");
+        assert_eq!(spans[1].as_str(&second), "Hello there frien");
+
+        let (last, _) = pick_variant_with(text, entities, |_| 2);
+        assert_eq!(last, "%%% bye");
+
+        let (inline, _) = pick_variant_with("50%%% off%%%".to_owned(), vec![], |_| 1);
+        assert_eq!(inline, "50%%% off%%%");
+
+        let (plain, _) = pick_variant_with("100%% sure".to_owned(), vec![], |_| 1);
+        assert_eq!(plain, "100%% sure");
+    }
+
+    fn assert_aligned(text: &str, entities: &[MessageEntity]) -> Vec<EntitySpan> {
+        let spans = audit_entities(text, entities).unwrap();
+        let len = text.encode_utf16().count() as i64;
+        for entity in entities {
+            assert!(entity.get_offset() >= 0);
+            assert!(entity.get_offset() + entity.get_length() <= len);
+        }
+        spans
+    }
+
+    #[test]
+    fn entity_span_utf16() {
+        let text = "a🥟b𝕏c";
+        let span = EntitySpan::new(text, 1, 6).unwrap();
+        assert_eq!(span.as_str(text), "🥟b");
+        assert_eq!(span.to_utf16(text), (1, 3));
+        assert_eq!(EntitySpan::from_utf16(text, 1, 3).unwrap(), span);
+        assert!(EntitySpan::new(text, 2, 6).is_err());
+        assert!(EntitySpan::from_utf16(text, 2, 1).is_err());
+        assert!(EntitySpan::from_utf16(text, 0, 9).is_err());
+        assert!(EntitySpan::from_utf16(text, 0, 8).is_ok());
+    }
+
+    #[tokio::test]
+    async fn nested_code_wide() {
+        let test = "😄 [*bold [`code🥟] 𝕏] after";
+        let (text, entities, _) = MarkupBuilder::new(None)
+            .set_text(test.to_owned())
+            .filling(false)
+            .header(false)
+            .build_murkdown()
+            .await
+            .unwrap();
+
+        let spans = assert_aligned(&text, &entities);
+        let covered = entities
+            .iter()
+            .zip(spans)
+            .map(|(e, s)| (e.get_tg_type().to_owned(), s.as_str(&text).to_owned()))
+            .collect::<Vec<(String, String)>>();
+        assert!(covered.contains(&("code".to_owned(), "code🥟".to_owned())));
+        assert!(covered.contains(&("bold".to_owned(), "bold code🥟 𝕏".to_owned())));
+    }
+
+    #[test]
+    fn markdown_spoiler_media() {
+        let test = "hi ||🥟 secret|| ![cat](https://example.com/cat.png) ![](https://example.com/dog.png) ![👍](tg://emoji?id=5368324170671202286) <tg-spoiler>more</tg-spoiler>";
+        let builder = MarkupBuilder::from_markdown(test, None);
+        let (text, entities) = builder.build();
+        let spans = assert_aligned(text, entities);
+        let covered = entities
+            .iter()
+            .zip(spans)
+            .map(|(e, s)| (e.get_tg_type().to_owned(), s.as_str(text).to_owned()))
+            .collect::<Vec<(String, String)>>();
+        assert!(!text.contains("||"));
+        assert!(covered.contains(&("spoiler".to_owned(), "🥟 secret".to_owned())));
+        assert!(covered.contains(&("spoiler".to_owned(), "more".to_owned())));
+        assert!(covered.contains(&("text_link".to_owned(), "cat".to_owned())));
+        assert!(covered.contains(&("custom_emoji".to_owned(), "👍".to_owned())));
+        assert!(covered.contains(&(
+            "text_link".to_owned(),
+            "https://example.com/dog.png".to_owned()
+        )));
+    }
+
+    const WIDE_WORDS: &[&str] = &["a", "bc", "🥟", "😄", "𝕏", "é", "日本", "👩‍👩‍👧"];
+    const STYLES: &[(&str, &str)] = &[
+        ("[*", "bold"),
+        ("[_", "italic"),
+        ("[__", "underline"),
+        ("[~", "strikethrough"),
+        ("[||", "spoiler"),
+        ("[`", "code"),
+    ];
+
+    fn word_strategy() -> impl proptest::strategy::Strategy<Value = String> {
+        use proptest::prelude::*;
+        prop::collection::vec(prop::sample::select(WIDE_WORDS), 1..4).prop_map(|v| v.concat())
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn murkdown_entities_aligned(
+            words in proptest::collection::vec(
+                (proptest::option::of(0..STYLES.len()), word_strategy()),
+                1..8,
+            )
+        ) {
+            let mut source = String::new();
+            let mut expected = Vec::new();
+            for (style, word) in words.iter() {
+                if !source.is_empty() {
+                    source.push(' ');
+                }
+                if let Some(style) = style {
+                    let (open, name) = STYLES[*style];
+                    source.push_str(open);
+                    source.push_str(word);
+                    source.push(']');
+                    expected.push((name.to_owned(), word.clone()));
+                } else {
+                    source.push_str(word);
+                }
+            }
+
+            let (text, entities, _) = block_on(
+                MarkupBuilder::new(None)
+                    .set_text(source)
+                    .filling(false)
+                    .header(false)
+                    .build_murkdown(),
+            )
+            .unwrap();
+
+            let spans = assert_aligned(&text, &entities);
+            let mut covered = entities
+                .iter()
+                .zip(spans)
+                .map(|(e, s)| (e.get_tg_type().to_owned(), s.as_str(&text).to_owned()))
+                .collect::<Vec<(String, String)>>();
+            covered.sort();
+            expected.sort();
+            proptest::prop_assert_eq!(covered, expected);
+        }
+
+        #[test]
+        fn nested_entities_aligned(
+            prefix in word_strategy(),
+            inner in word_strategy(),
+            outer in word_strategy(),
+        ) {
+            let source = format!("{} [*{} [`{}] [_{}]]", prefix, outer, inner, inner);
+            let (text, entities, _) = block_on(
+                MarkupBuilder::new(None)
+                    .set_text(source)
+                    .filling(false)
+                    .header(false)
+                    .build_murkdown(),
+            )
+            .unwrap();
+
+            let spans = assert_aligned(&text, &entities);
+            let bold = format!("{} {} {}", outer, inner, inner);
+            for (entity, span) in entities.iter().zip(spans) {
+                match entity.get_tg_type() {
+                    "bold" => proptest::prop_assert_eq!(span.as_str(&text), bold.as_str()),
+                    _ => proptest::prop_assert_eq!(span.as_str(&text), inner.as_str()),
+                }
+            }
+        }
+    }
+
     #[tokio::test]
     async fn parse_help() {
         let test = r#"
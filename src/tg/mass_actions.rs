@@ -0,0 +1,178 @@
+//! Banning or unbanning many users at once with /massban and /massunban. User ids come from
+//! a text file or a list in the command, separated by spaces, commas, or new lines. Calls to
+//! telegram are rate limited by [`MASS_ACTION_GOVERNER`] so a long list doesn't trip flood
+//! limits, and the status message is edited with the progress every [`PROGRESS_INTERVAL`]
+//! users until it is replaced with a report of what failed.
+
+use std::collections::HashSet;
+
+use botapi::gen_types::{Chat, Message};
+use macros::lang_fmt;
+
+use crate::statics::{MASS_ACTION_GOVERNER, ME, TG};
+use crate::util::error::{BotError, Result};
+use crate::util::string::Lang;
+
+use super::permissions::GetCachedAdmins;
+
+/// Most users one command can act on
+pub const MASS_ACTION_LIMIT: usize = 10_000;
+
+/// Users acted on between progress edits
+pub const PROGRESS_INTERVAL: usize = 50;
+
+/// Failures listed in the final report, the rest are only counted
+const REPORT_FAILURES: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MassAction {
+    Ban,
+    Unban,
+}
+
+/// User ids parsed from a list, along with anything that wasn't an id
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct UserList {
+    pub users: Vec<i64>,
+    pub invalid: Vec<String>,
+}
+
+/// Parses a list of user ids, dropping duplicates
+pub fn parse_user_list(text: &str) -> UserList {
+    let mut seen = HashSet::new();
+    let mut list = UserList::default();
+    for word in text
+        .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+        .filter(|word| !word.is_empty())
+    {
+        match word.parse::<i64>() {
+            Ok(user) if user > 0 => {
+                if seen.insert(user) {
+                    list.users.push(user);
+                }
+            }
+            _ => list.invalid.push(word.to_owned()),
+        }
+    }
+    list
+}
+
+/// Outcome of a mass action
+#[derive(Debug, Default)]
+pub struct MassReport {
+    pub done: usize,
+
+    /// entry that failed and why
+    pub failed: Vec<(String, String)>,
+}
+
+impl MassReport {
+    /// Text of the final report, listing the first few failures
+    pub fn describe(&self, action: MassAction, total: usize, lang: &Lang) -> String {
+        let mut text = match action {
+            MassAction::Ban => lang_fmt!(lang, "massbandone", self.done, total),
+            MassAction::Unban => lang_fmt!(lang, "massunbandone", self.done, total),
+        };
+        if !self.failed.is_empty() {
+            let failed = self
+                .failed
+                .iter()
+                .take(REPORT_FAILURES)
+                .map(|(entry, reason)| format!("{}: {}", entry, reason))
+                .collect::<Vec<String>>()
+                .join("\n");
+            text.push('\n');
+            text.push_str(&lang_fmt!(lang, "massfailed", self.failed.len(), failed));
+        }
+        text
+    }
+}
+
+async fn edit_status(status: &Message, text: &str) -> Result<()> {
+    TG.client
+        .build_edit_message_text(text)
+        .chat_id(status.get_chat().get_id())
+        .message_id(status.get_message_id())
+        .build()
+        .await?;
+    Ok(())
+}
+
+/// Bans or unbans every user in the list, editing `status` with the progress and the final
+/// report. Admins and the bot itself are never banned
+pub async fn run_mass_action(
+    chat: &Chat,
+    list: UserList,
+    action: MassAction,
+    status: &Message,
+    lang: &Lang,
+) -> Result<MassReport> {
+    let admins = chat.get_cached_admins().await?;
+    let me = ME.get().map(|me| me.get_id());
+    let total = list.users.len() + list.invalid.len();
+    let mut report = MassReport {
+        done: 0,
+        failed: list
+            .invalid
+            .into_iter()
+            .map(|entry| (entry, lang_fmt!(lang, "massinvalid")))
+            .collect(),
+    };
+
+    for (idx, user) in list.users.iter().copied().enumerate() {
+        if action == MassAction::Ban && (admins.contains_key(&user) || Some(user) == me) {
+            report
+                .failed
+                .push((user.to_string(), lang_fmt!(lang, "massadmin")));
+        } else {
+            MASS_ACTION_GOVERNER.until_ready().await;
+            let res = match action {
+                MassAction::Ban => TG
+                    .client()
+                    .build_ban_chat_member(chat.get_id(), user)
+                    .build()
+                    .await
+                    .map(|_| ()),
+                MassAction::Unban => TG
+                    .client()
+                    .build_unban_chat_member(chat.get_id(), user)
+                    .only_if_banned(true)
+                    .build()
+                    .await
+                    .map(|_| ()),
+            };
+            match res.map_err(BotError::from) {
+                Ok(()) => report.done += 1,
+                Err(err) => {
+                    let reason = match err.get_tg_error() {
+                        "" => err.to_string(),
+                        reason => reason.to_owned(),
+                    };
+                    report.failed.push((user.to_string(), reason));
+                }
+            }
+        }
+
+        if (idx + 1) % PROGRESS_INTERVAL == 0 {
+            let text = lang_fmt!(lang, "massprogress", idx + 1, list.users.len());
+            if let Err(err) = edit_status(status, &text).await {
+                log::debug!("failed to edit mass action progress: {}", err);
+            }
+        }
+    }
+
+    edit_status(status, &report.describe(action, total, lang)).await?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_list() {
+        let list = parse_user_list("1 2,3\n2;;nope -4 5");
+        assert_eq!(list.users, vec![1, 2, 3, 5]);
+        assert_eq!(list.invalid, vec!["nope".to_owned(), "-4".to_owned()]);
+    }
+}
@@ -0,0 +1,227 @@
+//! Sending stored media. Notes, filters, welcomes, and rules all save a file id along with
+//! its media type, and this picks the botapi call that sends each type so callers don't each
+//! need their own match over every kind of media. Also finds the image in a message for
+//! anything that needs to look at one, like ocr and nsfw detection
+
+use botapi::gen_types::{
+    EReplyMarkup, FileData, LinkPreviewOptionsBuilder, Message, MessageEntity, PhotoSize,
+    ReplyParameters, ReplyParametersBuilder,
+};
+use bytes::Bytes;
+use macros::lang_fmt;
+
+use crate::persist::core::media::MediaType;
+use crate::statics::TG;
+use crate::util::error::{BotError, Result};
+use crate::util::string::get_chat_lang;
+
+use super::admin_helpers::get_file;
+
+/// Media ready to send, with an already formatted caption
+pub struct SendableMedia {
+    /// telegram file id, None for text
+    pub media_id: Option<String>,
+    pub media_type: MediaType,
+    /// caption for media or the message text for text. Stickers ignore this
+    pub caption: String,
+    pub entities: Vec<MessageEntity>,
+    pub buttons: Option<EReplyMarkup>,
+}
+
+/// Sets the optional reply markup, reply parameters, and business connection on a send
+/// builder and sends it
+macro_rules! send {
+    ($builder:expr, $buttons:expr, $reply:expr, $business:expr) => {{
+        let mut builder = $builder;
+        if let Some(ref buttons) = $buttons {
+            builder = builder.reply_markup(buttons);
+        }
+        if let Some(ref reply) = $reply {
+            builder = builder.reply_parameters(reply);
+        }
+        if let Some(business) = $business {
+            builder = builder.business_connection_id(business);
+        }
+        builder.build().await?
+    }};
+}
+
+async fn send(
+    chat: i64,
+    reply: Option<ReplyParameters>,
+    business: Option<&str>,
+    media: SendableMedia,
+) -> Result<Message> {
+    let SendableMedia {
+        media_id,
+        media_type,
+        caption,
+        entities,
+        buttons,
+    } = media;
+
+    // only media without a file can fail, so only those look up the chat's language
+    let invalid = match media_id {
+        Some(_) => String::new(),
+        None => {
+            let lang = get_chat_lang(chat).await?;
+            lang_fmt!(lang, "errinvalidmedia")
+        }
+    };
+    let file = || {
+        media_id.clone().map(FileData::String).ok_or_else(|| {
+            BotError::speak(
+                invalid.clone(),
+                chat,
+                reply.as_ref().map(|r| r.get_message_id()),
+            )
+        })
+    };
+    let message = match media_type {
+        MediaType::Sticker => send!(
+            TG.client.build_send_sticker(chat, file()?),
+            buttons,
+            reply,
+            business
+        ),
+        MediaType::Photo => send!(
+            TG.client
+                .build_send_photo(chat, file()?)
+                .caption(&caption)
+                .caption_entities(&entities),
+            buttons,
+            reply,
+            business
+        ),
+        MediaType::Document => send!(
+            TG.client
+                .build_send_document(chat, file()?)
+                .caption(&caption)
+                .caption_entities(&entities),
+            buttons,
+            reply,
+            business
+        ),
+        MediaType::Video => send!(
+            TG.client
+                .build_send_video(chat, file()?)
+                .caption(&caption)
+                .caption_entities(&entities),
+            buttons,
+            reply,
+            business
+        ),
+        MediaType::Audio => send!(
+            TG.client
+                .build_send_audio(chat, file()?)
+                .caption(&caption)
+                .caption_entities(&entities),
+            buttons,
+            reply,
+            business
+        ),
+        MediaType::Animation => send!(
+            TG.client
+                .build_send_animation(chat, file()?)
+                .caption(&caption)
+                .caption_entities(&entities),
+            buttons,
+            reply,
+            business
+        ),
+        MediaType::Voice => send!(
+            TG.client
+                .build_send_voice(chat, file()?)
+                .caption(&caption)
+                .caption_entities(&entities),
+            buttons,
+            reply,
+            business
+        ),
+        MediaType::Text => send!(
+            TG.client
+                .build_send_message(chat, &caption)
+                .entities(&entities)
+                .link_preview_options(
+                    &LinkPreviewOptionsBuilder::new()
+                        .set_is_disabled(true)
+                        .build(),
+                ),
+            buttons,
+            reply,
+            business
+        ),
+    };
+    Ok(message)
+}
+
+/// Sends media to a chat using the right api call for its type
+pub async fn send_media(chat: i64, media: SendableMedia) -> Result<Message> {
+    send(chat, None, None, media).await
+}
+
+/// Sends media as a reply to a message, through the message's business connection if it
+/// came from one
+pub async fn reply_media(message: &Message, media: SendableMedia) -> Result<Message> {
+    let reply = ReplyParametersBuilder::new(message.get_message_id()).build();
+    send(
+        message.get_chat().get_id(),
+        Some(reply),
+        message.get_business_connection_id(),
+        media,
+    )
+    .await
+}
+
+/// An image in a message, identified by telegram's file ids
+#[derive(Clone, Copy, Debug)]
+pub struct MessageImage<'a> {
+    /// id used to download the file
+    pub file_id: &'a str,
+
+    /// id that stays the same for the same file across bots and time, for caching results
+    pub file_unique_id: &'a str,
+}
+
+impl<'a> From<&'a PhotoSize> for MessageImage<'a> {
+    fn from(size: &'a PhotoSize) -> Self {
+        Self {
+            file_id: size.get_file_id(),
+            file_unique_id: size.get_file_unique_id(),
+        }
+    }
+}
+
+/// The image in a message worth looking at: the biggest size of a photo no larger than
+/// `max_size` bytes, a static sticker, or the thumbnail of an animated or video sticker
+pub fn message_image(message: &Message, max_size: i64) -> Option<MessageImage<'_>> {
+    let small_enough = |size: &&PhotoSize| size.get_file_size().unwrap_or_default() <= max_size;
+    if let Some(photo) = message.get_photo() {
+        return photo
+            .iter()
+            .filter(small_enough)
+            .max_by_key(|size| size.get_width() * size.get_height())
+            .map(MessageImage::from);
+    }
+    let sticker = message.get_sticker()?;
+    if sticker.get_is_animated() || sticker.get_is_video() {
+        sticker
+            .get_thumbnail()
+            .filter(small_enough)
+            .map(MessageImage::from)
+    } else {
+        Some(MessageImage {
+            file_id: sticker.get_file_id(),
+            file_unique_id: sticker.get_file_unique_id(),
+        })
+    }
+}
+
+/// Downloads an image found with [`message_image`]
+pub async fn download_image(image: MessageImage<'_>) -> Result<Bytes> {
+    let file = TG.client.build_get_file(image.file_id).build().await?;
+    let path = file
+        .get_file_path()
+        .ok_or_else(|| BotError::Generic("file has no path".to_owned()))?;
+    get_file(path).await
+}
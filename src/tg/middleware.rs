@@ -0,0 +1,429 @@
+//! Middleware for update handling. Checks every chat gets, like gban enforcement, locks,
+//! and ignoring commands in chats the bot is flooded out of, run as a chain of pre-handlers
+//! before any module sees an update. A pre-handler can stop an update, in which case the
+//! rest of the chain and every module skip it. Post-handlers, like logging and metrics, run
+//! afterwards whether or not the update was stopped.
+//!
+//! Middleware runs in order of [`Middleware::priority`], lowest first. Modules add their own
+//! middleware through [`crate::metadata::Module::middleware`], which is how locks are
+//! enforced before any other module handles a locked message
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use botapi::gen_types::UpdateExt;
+
+use crate::metadata::ModuleRegistry;
+use crate::persist::metrics::{count_metric, Metric};
+use crate::statics::TG;
+use crate::util::error::{BotError, Result};
+use crate::util::error_sink::ErrorContext;
+use crate::util::string::is_chat_ignored;
+
+use super::chat_migration::migrate_chat;
+use super::client::handle_builtin_command;
+use super::command::Context;
+use super::permissions::{IsGroupAdmin, NamedBotPermissions};
+
+/// Whether an update keeps going through the pipeline after a pre-handler has seen it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    /// Pass the update on to the next pre-handler and then the modules
+    Continue,
+    /// Skip the rest of the pre-handlers and every module
+    Stop,
+}
+
+/// What happened to an update, passed to post-handlers
+#[derive(Clone, Copy, Debug)]
+pub struct Outcome {
+    /// name of the pre-handler that stopped the update, None if modules handled it
+    pub stopped_by: Option<&'static str>,
+    /// time taken by the pre-handlers and modules
+    pub elapsed: Duration,
+}
+
+/// A step in update handling that runs around the modules
+#[async_trait]
+pub trait Middleware: Send + Sync {
+    /// Name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Position in the chain, lower runs first. Middleware with the same priority runs in the
+    /// order it was added
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    /// Runs before the modules. Errors are logged and the update continues
+    async fn before(&self, _ctx: &Context) -> Result<Flow> {
+        Ok(Flow::Continue)
+    }
+
+    /// Runs after the modules, or after the update was stopped
+    async fn after(&self, _ctx: &Context, _outcome: &Outcome) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Logs an error returned while handling an update, telling the chat about it if the error
+/// has a message for users
+pub(crate) async fn report_handler_error(name: &str, err: BotError) {
+    ErrorContext::in_module(name, || err.record_stats());
+    match err.get_message().await {
+        Err(err) => {
+            tracing::warn!(module = name, error = %err, "failed to send error message, what the FLOOP");
+            err.record_stats();
+        }
+        Ok(false) => tracing::warn!(module = name, error = %err, "handle_update error"),
+        Ok(true) => (),
+    }
+}
+
+/// Ordered list of middleware a bot runs every update through
+#[derive(Clone)]
+pub struct MiddlewareChain(Vec<&'static dyn Middleware>);
+
+impl MiddlewareChain {
+    /// Creates a chain, ordering the middleware by priority
+    pub fn new(mut middleware: Vec<&'static dyn Middleware>) -> Self {
+        middleware.sort_by_key(|m| m.priority());
+        Self(middleware)
+    }
+
+    /// Creates a chain of the built-in middleware and the middleware from every module in
+    /// the registry
+    pub fn builtin(registry: &ModuleRegistry) -> Self {
+        let builtin: [&'static dyn Middleware; 12] = [
+            &ChatMigration,
+            &ChatMembers,
+            &Gbans,
+            &SpamScore,
+            &Greetings,
+            &PendingActions,
+            &IgnoredChats,
+            &CommandPermissions,
+            &BuiltinCommands,
+            &CleanCommands,
+            &UpdateMetrics,
+            &UpdateLog,
+        ];
+        let modules = registry.iter().filter_map(|module| module.middleware());
+        Self::new(builtin.into_iter().chain(modules).collect())
+    }
+
+    /// Runs the pre-handlers, returning the name of the one that stopped the update if any
+    pub async fn before(&self, ctx: &Context) -> Option<&'static str> {
+        for middleware in self.0.iter() {
+            match middleware.before(ctx).await {
+                Ok(Flow::Continue) => (),
+                Ok(Flow::Stop) => return Some(middleware.name()),
+                Err(err) => report_handler_error(middleware.name(), err).await,
+            }
+        }
+        None
+    }
+
+    /// Runs the post-handlers
+    pub async fn after(&self, ctx: &Context, outcome: &Outcome) {
+        for middleware in self.0.iter() {
+            if let Err(err) = middleware.after(ctx, outcome).await {
+                log::warn!("{} post-handler error: {}", middleware.name(), err);
+                err.record_stats();
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for MiddlewareChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|m| m.name()))
+            .finish()
+    }
+}
+
+/// Drops commands from members of chats the bot is ignoring after being flooded out of them.
+/// Runs after the moderation pre-handlers, and other messages still reach the modules, so
+/// gbans, locks and blocklists keep working while the bot is quiet. Replies are already held
+/// back while a chat is ignored. Admins' commands still go through so they can deal with
+/// whatever flooded the chat
+struct IgnoredChats;
+
+#[async_trait]
+impl Middleware for IgnoredChats {
+    fn name(&self) -> &'static str {
+        "ignored chats"
+    }
+
+    fn priority(&self) -> i32 {
+        30
+    }
+
+    async fn before(&self, ctx: &Context) -> Result<Flow> {
+        if ctx.cmd().is_none() {
+            return Ok(Flow::Continue);
+        }
+        match ctx.chat() {
+            Some(chat) if is_chat_ignored(chat.get_id()).await? => {
+                if ctx.message()?.is_group_admin().await? {
+                    Ok(Flow::Continue)
+                } else {
+                    Ok(Flow::Stop)
+                }
+            }
+            _ => Ok(Flow::Continue),
+        }
+    }
+}
+
+/// Moves a group's data to its new id when it is upgraded to a supergroup
+struct ChatMigration;
+
+#[async_trait]
+impl Middleware for ChatMigration {
+    fn name(&self) -> &'static str {
+        "chat migration"
+    }
+
+    fn priority(&self) -> i32 {
+        -90
+    }
+
+    async fn before(&self, ctx: &Context) -> Result<Flow> {
+        if let UpdateExt::Message(ref message) = ctx.update() {
+            if let Some(from) = message.get_migrate_from_chat_id() {
+                migrate_chat(from, message.get_chat().get_id()).await?;
+            }
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+/// Keeps the chat member cache up to date
+struct ChatMembers;
+
+#[async_trait]
+impl Middleware for ChatMembers {
+    fn name(&self) -> &'static str {
+        "chat members"
+    }
+
+    fn priority(&self) -> i32 {
+        -50
+    }
+
+    async fn before(&self, ctx: &Context) -> Result<Flow> {
+        ctx.record_chat_member().await?;
+        Ok(Flow::Continue)
+    }
+}
+
+/// Bans gbanned and fbanned users, stopping their messages from being handled
+struct Gbans;
+
+#[async_trait]
+impl Middleware for Gbans {
+    fn name(&self) -> &'static str {
+        "gbans"
+    }
+
+    fn priority(&self) -> i32 {
+        -40
+    }
+
+    async fn before(&self, ctx: &Context) -> Result<Flow> {
+        if ctx.handle_gbans().await {
+            Ok(Flow::Stop)
+        } else {
+            Ok(Flow::Continue)
+        }
+    }
+}
+
+/// Scores messages for spam so modules can act on the score
+struct SpamScore;
+
+#[async_trait]
+impl Middleware for SpamScore {
+    fn name(&self) -> &'static str {
+        "spam score"
+    }
+
+    fn priority(&self) -> i32 {
+        -20
+    }
+
+    async fn before(&self, ctx: &Context) -> Result<Flow> {
+        ctx.handle_spam_check().await;
+        Ok(Flow::Continue)
+    }
+}
+
+/// Welcomes and says goodbye to members
+struct Greetings;
+
+#[async_trait]
+impl Middleware for Greetings {
+    fn name(&self) -> &'static str {
+        "greetings"
+    }
+
+    fn priority(&self) -> i32 {
+        -10
+    }
+
+    async fn before(&self, ctx: &Context) -> Result<Flow> {
+        ctx.greeter_handle_update().await?;
+        Ok(Flow::Continue)
+    }
+}
+
+/// Applies restrictions queued for users the bot hadn't seen yet
+struct PendingActions;
+
+#[async_trait]
+impl Middleware for PendingActions {
+    fn name(&self) -> &'static str {
+        "pending actions"
+    }
+
+    fn priority(&self) -> i32 {
+        -5
+    }
+
+    async fn before(&self, ctx: &Context) -> Result<Flow> {
+        ctx.handle_pending_action_update().await?;
+        Ok(Flow::Continue)
+    }
+}
+
+/// Checks the permissions commands declare in metadata!, stopping the command before any
+/// module sees it if the sender is missing one
+struct CommandPermissions;
+
+#[async_trait]
+impl Middleware for CommandPermissions {
+    fn name(&self) -> &'static str {
+        "command permissions"
+    }
+
+    fn priority(&self) -> i32 {
+        40
+    }
+
+    async fn before(&self, ctx: &Context) -> Result<Flow> {
+        let Some(cmd) = ctx.cmd() else {
+            return Ok(Flow::Continue);
+        };
+        let Some((first, rest)) = TG
+            .registry
+            .iter()
+            .find_map(|module| module.metadata().perms.get(cmd.cmd))
+            .and_then(|perms| perms.split_first())
+        else {
+            return Ok(Flow::Continue);
+        };
+        let check = |permissions: NamedBotPermissions| {
+            rest.iter()
+                .fold((first.get)(permissions.clone()), |all, perm| {
+                    all.and((perm.get)(permissions.clone()))
+                })
+        };
+        match ctx.check_permissions(check).await {
+            Ok(()) => Ok(Flow::Continue),
+            Err(err) => {
+                report_handler_error(self.name(), err).await;
+                Ok(Flow::Stop)
+            }
+        }
+    }
+}
+
+/// Handles /help, /start, and deferred commands, which modules never see
+struct BuiltinCommands;
+
+#[async_trait]
+impl Middleware for BuiltinCommands {
+    fn name(&self) -> &'static str {
+        "builtin commands"
+    }
+
+    fn priority(&self) -> i32 {
+        50
+    }
+
+    async fn before(&self, ctx: &Context) -> Result<Flow> {
+        match handle_builtin_command(ctx, Arc::clone(&TG.modules)).await {
+            Ok(false) => Ok(Flow::Continue),
+            Ok(true) => Ok(Flow::Stop),
+            Err(err) => {
+                report_handler_error(self.name(), err).await;
+                Ok(Flow::Stop)
+            }
+        }
+    }
+}
+
+/// Deletes admin commands and their confirmations in chats with /cleancommands on
+struct CleanCommands;
+
+#[async_trait]
+impl Middleware for CleanCommands {
+    fn name(&self) -> &'static str {
+        "clean commands"
+    }
+
+    fn priority(&self) -> i32 {
+        90
+    }
+
+    async fn after(&self, ctx: &Context, _: &Outcome) -> Result<()> {
+        ctx.handle_clean_command().await;
+        Ok(())
+    }
+}
+
+/// Counts commands for /stats
+struct UpdateMetrics;
+
+#[async_trait]
+impl Middleware for UpdateMetrics {
+    fn name(&self) -> &'static str {
+        "metrics"
+    }
+
+    fn priority(&self) -> i32 {
+        100
+    }
+
+    async fn after(&self, ctx: &Context, _: &Outcome) -> Result<()> {
+        if ctx.cmd().is_some() {
+            count_metric(Metric::Command).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Logs which pre-handler stopped an update and how long handling took
+struct UpdateLog;
+
+#[async_trait]
+impl Middleware for UpdateLog {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    fn priority(&self) -> i32 {
+        110
+    }
+
+    async fn after(&self, _: &Context, outcome: &Outcome) -> Result<()> {
+        match outcome.stopped_by {
+            Some(name) => log::debug!("update stopped by {} in {:?}", name, outcome.elapsed),
+            None => log::debug!("update handled in {:?}", outcome.elapsed),
+        }
+        Ok(())
+    }
+}
@@ -1,13 +1,59 @@
 pub mod admin_helpers;
+pub mod admin_notes;
+pub mod aliases;
+pub mod appeals;
+pub mod birthdays;
+pub mod boosts;
+pub mod bot_commands;
+pub mod business;
+pub mod bots;
+pub mod broadcast;
 pub mod button;
+pub mod chat_migration;
+pub mod clean_commands;
 pub mod client;
 pub mod command;
+pub mod command_replies;
+pub mod confirm;
+pub mod dedup;
+pub mod deeplink;
 pub mod dialog;
+pub mod entity_gc;
+pub mod external_bans;
+pub mod extract;
 pub mod federations;
+pub mod giveaways;
 pub mod greetings;
 pub mod import_export;
+pub mod join_burst;
+pub mod join_requests;
+pub mod log_channel;
 pub mod markdown;
+pub mod mass_actions;
+pub mod media;
+pub mod middleware;
+pub mod modlog;
+pub mod networks;
 pub mod notes;
+pub mod nsfw;
+pub mod ocr;
+pub mod parse_mode;
 pub mod permissions;
+pub mod polling;
+pub mod recurring;
+pub mod render;
+pub mod restrict;
 pub mod rosemd;
+pub mod scheduler;
+pub mod settings;
+pub mod setup;
+pub mod shame;
+pub mod spam;
+pub mod url_guard;
+pub mod video_chats;
 pub mod user;
+pub mod voteban;
+pub mod warn_decay;
+pub mod webhook;
+pub mod write_behind;
+pub mod zombies;
@@ -0,0 +1,115 @@
+//! The moderation log. Actions admins take with commands are kept in the database so
+//! /history can show everything done to a user in a chat and /modlog the latest actions,
+//! long after the log channel messages have scrolled away
+
+use botapi::gen_types::Message;
+use chrono::{Duration, Utc};
+use macros::lang_fmt;
+use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+
+use crate::persist::admin::modlog::{self, ModAction};
+use crate::statics::DB;
+use crate::util::error::Result;
+use crate::util::string::Lang;
+use crate::util::time::ChatTime;
+
+use super::markdown::Escape;
+use super::user::GetUser;
+
+/// Most entries shown by /history and /modlog
+pub const MODLOG_LIMIT: u64 = 20;
+
+/// Records an action taken by the command in `message`. Admins sending commands
+/// anonymously are recorded without an actor
+pub async fn record_action(
+    message: &Message,
+    target: i64,
+    action: ModAction,
+    reason: Option<&str>,
+    duration: Option<Duration>,
+) -> Result<()> {
+    let actor = match message.get_sender_chat() {
+        Some(_) => None,
+        None => message.get_from().map(|user| user.get_id()),
+    };
+    let model = modlog::ActiveModel {
+        id: NotSet,
+        chat_id: Set(message.get_chat().get_id()),
+        actor: Set(actor),
+        target: Set(target),
+        action: Set(action),
+        reason: Set(reason.map(|reason| reason.to_owned())),
+        duration: Set(duration.map(|duration| duration.num_seconds())),
+        created: Set(Utc::now()),
+    };
+    modlog::Entity::insert(model).exec(*DB).await?;
+    Ok(())
+}
+
+/// Gets the latest actions taken against a user in a chat, newest first
+pub async fn get_user_history(chat: i64, user: i64) -> Result<Vec<modlog::Model>> {
+    let entries = modlog::Entity::find()
+        .filter(modlog::Column::ChatId.eq(chat))
+        .filter(modlog::Column::Target.eq(user))
+        .order_by_desc(modlog::Column::Created)
+        .limit(MODLOG_LIMIT)
+        .all(*DB)
+        .await?;
+    Ok(entries)
+}
+
+/// Gets the latest actions taken in a chat, newest first
+pub async fn get_recent_actions(chat: i64) -> Result<Vec<modlog::Model>> {
+    let entries = modlog::Entity::find()
+        .filter(modlog::Column::ChatId.eq(chat))
+        .order_by_desc(modlog::Column::Created)
+        .limit(MODLOG_LIMIT)
+        .all(*DB)
+        .await?;
+    Ok(entries)
+}
+
+fn describe_action(lang: &Lang, action: ModAction) -> String {
+    match action {
+        ModAction::Ban => lang_fmt!(lang, "modlogban"),
+        ModAction::Unban => lang_fmt!(lang, "modlogunban"),
+        ModAction::Kick => lang_fmt!(lang, "modlogkick"),
+        ModAction::Mute => lang_fmt!(lang, "modlogmute"),
+        ModAction::Unmute => lang_fmt!(lang, "modlogunmute"),
+        ModAction::Restrict => lang_fmt!(lang, "modlogrestrict"),
+        ModAction::Warn => lang_fmt!(lang, "modlogwarn"),
+    }
+}
+
+/// Formats entries one per line with when they happened in the chat's timezone, who took
+/// the action, and the duration and reason if there were any
+pub async fn format_modlog(chat: i64, lang: &Lang, entries: &[modlog::Model]) -> Result<String> {
+    let time = ChatTime::get(chat).await?;
+    let mut res = String::new();
+    for entry in entries {
+        let target = entry.target.cached_name().await?.escape(false).into_owned();
+        let actor = match entry.actor {
+            Some(actor) => actor.cached_name().await?.escape(false).into_owned(),
+            None => lang_fmt!(lang, "modloganon"),
+        };
+        let mut line = lang_fmt!(
+            lang,
+            "modlogline",
+            time.format_lang(&entry.created, lang).escape(false),
+            describe_action(lang, entry.action),
+            target,
+            actor
+        );
+        if let Some(duration) = entry.duration.filter(|duration| *duration > 0) {
+            let duration = lang.format_duration(std::time::Duration::from_secs(duration as u64));
+            line.push_str(&lang_fmt!(lang, "modlogduration", duration));
+        }
+        if let Some(ref reason) = entry.reason {
+            line.push_str(&lang_fmt!(lang, "modlogreason", reason.escape(false)));
+        }
+        res.push('\n');
+        res.push_str(&line);
+    }
+    Ok(res)
+}
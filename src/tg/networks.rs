@@ -0,0 +1,321 @@
+//! Chat networks, groups of related chats sharing approvals, blocklists and optionally notes.
+//! A network has defaults for what its chats share and each chat can override them for
+//! itself, so a chat-specific setting always wins over the network's. Something is only
+//! shared between two chats if both of them share it and the two chats are linked. Chats are
+//! linked in pairs by an admin of both, so joining a network doesn't make a chat share with
+//! chats whose admins never agreed to it.
+//!
+//! Caches built from what a chat's network shares are kept in the chat's [`network_scope`],
+//! which is invalidated for every chat in a network when its chats or settings change
+
+use std::fmt::Display;
+
+use chrono::Duration;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, Condition, EntityTrait, IntoActiveModel, QueryFilter};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::persist::admin::{network_chats, network_links, networks};
+use crate::persist::redis::{default_cache_query, CacheScope, CachedQueryTrait};
+use crate::statics::{CONFIG, DB};
+use crate::util::error::Result;
+
+/// Something chats in a network can share
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Share {
+    Approvals,
+    Blocklists,
+    Notes,
+}
+
+impl Share {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "approvals" => Some(Self::Approvals),
+            "blocklists" => Some(Self::Blocklists),
+            "notes" => Some(Self::Notes),
+            _ => None,
+        }
+    }
+
+    fn network_default(&self, network: &networks::Model) -> bool {
+        match self {
+            Self::Approvals => network.share_approvals,
+            Self::Blocklists => network.share_blocklists,
+            Self::Notes => network.share_notes,
+        }
+    }
+
+    fn chat_override(&self, chat: &network_chats::Model) -> Option<bool> {
+        match self {
+            Self::Approvals => chat.share_approvals,
+            Self::Blocklists => chat.share_blocklists,
+            Self::Notes => chat.share_notes,
+        }
+    }
+}
+
+impl Display for Share {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Approvals => f.write_str("approvals"),
+            Self::Blocklists => f.write_str("blocklists"),
+            Self::Notes => f.write_str("notes"),
+        }
+    }
+}
+
+/// A network along with every chat in it and the links between them
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatNetwork {
+    pub network: networks::Model,
+    pub chats: Vec<network_chats::Model>,
+    pub links: Vec<network_links::Model>,
+}
+
+impl ChatNetwork {
+    /// Checks if a chat in the network shares something, using the chat's own setting if it
+    /// has one and the network's default otherwise
+    pub fn shares(&self, chat: i64, share: Share) -> bool {
+        self.chats
+            .iter()
+            .find(|c| c.chat_id == chat)
+            .and_then(|c| share.chat_override(c))
+            .unwrap_or_else(|| share.network_default(&self.network))
+    }
+
+    /// Checks if two chats in the network were linked with each other
+    pub fn linked(&self, chat: i64, other: i64) -> bool {
+        self.links
+            .iter()
+            .any(|l| l.chat_id == chat && l.other_chat == other)
+    }
+}
+
+/// Cache scope for everything built from a chat's network. Invalidated for every chat in a
+/// network whenever the network changes
+#[inline(always)]
+pub fn network_scope(chat: i64) -> CacheScope {
+    CacheScope::new(chat, "network")
+}
+
+#[inline(always)]
+fn get_network_key(chat: i64) -> String {
+    format!("netw:{}", chat)
+}
+
+/// Gets the network a chat is in, if any
+pub async fn get_chat_network(chat: i64) -> Result<Option<ChatNetwork>> {
+    let key = network_scope(chat).key(get_network_key(chat)).await?;
+    default_cache_query(
+        |_, _| async move {
+            let Some(member) = network_chats::Entity::find_by_id(chat).one(*DB).await? else {
+                return Ok(None);
+            };
+            let Some(network) = networks::Entity::find_by_id(member.network)
+                .one(*DB)
+                .await?
+            else {
+                return Ok(None);
+            };
+            let chats = network_chats::Entity::find()
+                .filter(network_chats::Column::Network.eq(member.network))
+                .all(*DB)
+                .await?;
+            let links = network_links::Entity::find()
+                .filter(network_links::Column::Network.eq(member.network))
+                .all(*DB)
+                .await?;
+            Ok(Some(ChatNetwork {
+                network,
+                chats,
+                links,
+            }))
+        },
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
+    )
+    .query(&key, &())
+    .await
+}
+
+/// Gets the other chats linked with a chat sharing something with it. Empty if the chat
+/// isn't in a network or doesn't share it itself
+pub async fn shared_chats(chat: i64, share: Share) -> Result<Vec<i64>> {
+    let Some(network) = get_chat_network(chat).await? else {
+        return Ok(vec![]);
+    };
+    if !network.shares(chat, share) {
+        return Ok(vec![]);
+    }
+    Ok(network
+        .chats
+        .iter()
+        .map(|c| c.chat_id)
+        .filter(|&c| c != chat && network.linked(chat, c) && network.shares(c, share))
+        .collect())
+}
+
+/// Gets every chat in a network
+async fn network_chat_ids(network: Uuid) -> Result<Vec<i64>> {
+    let chats = network_chats::Entity::find()
+        .filter(network_chats::Column::Network.eq(network))
+        .all(*DB)
+        .await?;
+    Ok(chats.into_iter().map(|c| c.chat_id).collect())
+}
+
+/// Invalidates the network scope of each chat
+async fn invalidate_chats(chats: &[i64]) -> Result<()> {
+    for &chat in chats {
+        network_scope(chat).invalidate().await?;
+    }
+    Ok(())
+}
+
+/// Invalidates the network scope of every chat in a network
+async fn invalidate_network(network: Uuid) -> Result<()> {
+    invalidate_chats(&network_chat_ids(network).await?).await
+}
+
+/// Result of linking a chat into a network
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkResult {
+    Linked,
+    /// The two chats were already linked
+    AlreadyLinked,
+    /// The chat is in a different network and has to leave it first
+    OtherNetwork,
+}
+
+/// Links `chat` and `other`, adding `other` to `chat`'s network or creating a network owned by
+/// `owner` and named `name` if `chat` isn't in one yet. Chats already in the same network are
+/// only linked with each other
+pub async fn link_chat(chat: i64, other: i64, owner: i64, name: String) -> Result<LinkResult> {
+    let current = network_chats::Entity::find_by_id(chat).one(*DB).await?;
+    let other_current = network_chats::Entity::find_by_id(other).one(*DB).await?;
+    match (&current, &other_current) {
+        (Some(c), Some(o)) if c.network == o.network => {
+            if network_links::Entity::find_by_id((chat, other))
+                .one(*DB)
+                .await?
+                .is_some()
+            {
+                return Ok(LinkResult::AlreadyLinked);
+            }
+            add_link(chat, other, c.network).await?;
+            invalidate_network(c.network).await?;
+            return Ok(LinkResult::Linked);
+        }
+        (_, Some(_)) => return Ok(LinkResult::OtherNetwork),
+        _ => (),
+    }
+
+    let network = match current {
+        Some(current) => current.network,
+        None => {
+            let network =
+                networks::Entity::insert(networks::Model::new(owner, name).into_active_model())
+                    .exec_with_returning(*DB)
+                    .await?;
+            add_chat(chat, network.network_id).await?;
+            network.network_id
+        }
+    };
+    add_chat(other, network).await?;
+    add_link(chat, other, network).await?;
+    invalidate_network(network).await?;
+    Ok(LinkResult::Linked)
+}
+
+/// Links two chats in a network in both directions
+async fn add_link(chat: i64, other: i64, network: Uuid) -> Result<()> {
+    network_links::Entity::insert_many([
+        network_links::Model {
+            chat_id: chat,
+            other_chat: other,
+            network,
+        }
+        .into_active_model(),
+        network_links::Model {
+            chat_id: other,
+            other_chat: chat,
+            network,
+        }
+        .into_active_model(),
+    ])
+    .exec(*DB)
+    .await?;
+    Ok(())
+}
+
+async fn add_chat(chat: i64, network: Uuid) -> Result<()> {
+    network_chats::Entity::insert(
+        network_chats::Model {
+            chat_id: chat,
+            network,
+            share_approvals: None,
+            share_blocklists: None,
+            share_notes: None,
+        }
+        .into_active_model(),
+    )
+    .exec(*DB)
+    .await?;
+    Ok(())
+}
+
+/// Removes a chat from its network, deleting the network once fewer than two chats are left
+/// in it. Returns false if the chat wasn't in a network
+pub async fn unlink_chat(chat: i64) -> Result<bool> {
+    let Some(member) = network_chats::Entity::find_by_id(chat).one(*DB).await? else {
+        return Ok(false);
+    };
+    let chats = network_chat_ids(member.network).await?;
+    network_chats::Entity::delete_by_id(chat).exec(*DB).await?;
+    network_links::Entity::delete_many()
+        .filter(
+            Condition::any()
+                .add(network_links::Column::ChatId.eq(chat))
+                .add(network_links::Column::OtherChat.eq(chat)),
+        )
+        .exec(*DB)
+        .await?;
+    if chats.len() <= 2 {
+        networks::Entity::delete_by_id(member.network)
+            .exec(*DB)
+            .await?;
+    }
+    invalidate_chats(&chats).await?;
+    Ok(true)
+}
+
+/// Sets whether a chat shares something with its network, None to use the network's default
+pub async fn set_chat_share(chat: i64, share: Share, value: Option<bool>) -> Result<()> {
+    let Some(member) = network_chats::Entity::find_by_id(chat).one(*DB).await? else {
+        return Ok(());
+    };
+    let mut model = member.clone().into_active_model();
+    match share {
+        Share::Approvals => model.share_approvals = Set(value),
+        Share::Blocklists => model.share_blocklists = Set(value),
+        Share::Notes => model.share_notes = Set(value),
+    }
+    network_chats::Entity::update(model).exec(*DB).await?;
+    invalidate_network(member.network).await
+}
+
+/// Sets whether chats in a network share something unless they say otherwise
+pub async fn set_network_default(network: Uuid, share: Share, value: bool) -> Result<()> {
+    let mut model = networks::ActiveModel {
+        network_id: Set(network),
+        ..Default::default()
+    };
+    match share {
+        Share::Approvals => model.share_approvals = Set(value),
+        Share::Blocklists => model.share_blocklists = Set(value),
+        Share::Notes => model.share_notes = Set(value),
+    }
+    networks::Entity::update(model).exec(*DB).await?;
+    invalidate_network(network).await
+}
@@ -3,28 +3,40 @@
 //!
 //! this module has helper functions for storing, retrieving, and printing notes
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 
 use botapi::gen_types::{CallbackQuery, MaybeInaccessibleMessage, MessageEntity};
 use futures::{future::BoxFuture, FutureExt};
 use itertools::Itertools;
 use redis::AsyncCommands;
-use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QuerySelect, TransactionTrait};
+use sea_orm::{
+    sea_query::{LikeExpr, SimpleExpr},
+    ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter, QuerySelect, TransactionTrait,
+};
 
 use crate::{
     persist::{
-        core::{entity, media::SendMediaReply, notes},
+        core::{chat_members, entity, media::SendMediaReply, notes},
         redis::{CachedQuery, CachedQueryTrait, RedisStr},
     },
-    statics::{CONFIG, DB, REDIS, TG},
+    statics::{CONFIG, DB, REDIS},
     tg::button::OnPush,
     util::error::{BotError, Result},
 };
 
-use super::{button::InlineKeyboardBuilder, command::Context, markdown::get_markup_for_buttons};
+use super::{
+    button::InlineKeyboardBuilder,
+    command::Context,
+    markdown::get_markup_for_buttons,
+    networks::{shared_chats, Share},
+    permissions::is_admin_in,
+};
 
 pub const MODULE_NAME: &str = "notes";
 
+/// Most notes offered for one inline query
+const INLINE_NOTES: usize = 20;
+
 #[inline(always)]
 pub(crate) fn get_hash_key(chat: i64) -> String {
     format!("ncch:{}", chat)
@@ -72,7 +84,7 @@ pub async fn refresh_notes(
                 if !st.is_empty() {
                     q.hset_multiple(&hash_key, st.as_slice());
                 }
-                q.expire(&hash_key, CONFIG.timing.cache_timeout)
+                q.expire(&hash_key, CONFIG.load().timing.cache_timeout)
             })
             .await?;
 
@@ -114,6 +126,8 @@ pub async fn clear_notes(chat: i64) -> Result<()> {
     Ok(())
 }
 
+/// Gets a note by name, falling back to the notes of chats sharing notes with this one
+/// through a network if the chat has no note by that name itself
 pub async fn get_note_by_name(
     name: String,
     chat: i64,
@@ -123,6 +137,27 @@ pub async fn get_note_by_name(
         Vec<MessageEntity>,
         Option<InlineKeyboardBuilder>,
     )>,
+> {
+    if let Some(note) = get_chat_note_by_name(name.clone(), chat).await? {
+        return Ok(Some(note));
+    }
+    for other in shared_chats(chat, Share::Notes).await? {
+        if let Some(note) = get_chat_note_by_name(name.clone(), other).await? {
+            return Ok(Some(note));
+        }
+    }
+    Ok(None)
+}
+
+async fn get_chat_note_by_name(
+    name: String,
+    chat: i64,
+) -> Result<
+    Option<(
+        notes::Model,
+        Vec<MessageEntity>,
+        Option<InlineKeyboardBuilder>,
+    )>,
 > {
     let hash_key = get_hash_key(chat);
     let n = name.clone();
@@ -154,7 +189,7 @@ pub async fn get_note_by_name(
                 .pipe(|q| {
                     q.exists(&hash_key)
                         .hget(&hash_key, key)
-                        .expire(&hash_key, CONFIG.timing.cache_timeout)
+                        .expire(&hash_key, CONFIG.load().timing.cache_timeout)
                 })
                 .await?;
 
@@ -176,6 +211,128 @@ pub async fn get_note_by_name(
     Ok(note)
 }
 
+/// Matches note names starting with `prefix`. Wildcards typed by the user are escaped so they
+/// only match themselves
+fn name_starts_with(prefix: &str) -> SimpleExpr {
+    let prefix = prefix
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    notes::Column::Name.like(LikeExpr::new(format!("{}%", prefix)).escape('\\'))
+}
+
+/// Finds notes whose names start with `prefix` that a user can send inline in any chat: notes
+/// saved in their dm with the bot and notes of chats they are an admin of, sorted by name
+pub async fn find_inline_notes(
+    user: i64,
+    prefix: &str,
+) -> Result<
+    Vec<(
+        notes::Model,
+        Vec<MessageEntity>,
+        Option<InlineKeyboardBuilder>,
+    )>,
+> {
+    let member_of: Vec<i64> = chat_members::Entity::find()
+        .select_only()
+        .column(chat_members::Column::ChatId)
+        .filter(chat_members::Column::UserId.eq(user))
+        .into_tuple()
+        .all(*DB)
+        .await?;
+
+    // only chats with a matching note are worth asking about admin rights
+    let with_notes: Vec<i64> = notes::Entity::find()
+        .select_only()
+        .column(notes::Column::Chat)
+        .distinct()
+        .filter(notes::Column::Chat.is_in(member_of))
+        .filter(name_starts_with(prefix))
+        .into_tuple()
+        .all(*DB)
+        .await?;
+    let mut chats = vec![user];
+    for chat in with_notes {
+        match is_admin_in(user, chat).await {
+            Ok(true) => chats.push(chat),
+            Ok(false) => (),
+            Err(err) => log::debug!("failed to check admin for inline notes: {}", err),
+        }
+    }
+
+    let matching = name_starts_with(prefix).and(notes::Column::Chat.is_in(chats));
+    let notes = notes::get_filters_join(matching)
+        .await?
+        .into_iter()
+        .map(|(note, (entity, button))| {
+            (
+                note,
+                entity
+                    .into_iter()
+                    .map(|e| e.get())
+                    .map(|(e, u)| e.to_entity(u))
+                    .collect(),
+                get_markup_for_buttons(button.into_iter().collect()),
+            )
+        })
+        .sorted_by(|a, b| a.0.name.cmp(&b.0.name))
+        .take(INLINE_NOTES)
+        .collect();
+    Ok(notes)
+}
+
+/// Copies every note from one chat to another, leaving notes the destination already has a
+/// note of the same name for alone. Returns how many notes were copied and skipped
+pub async fn copy_notes(from: i64, to: i64) -> Result<(usize, usize)> {
+    let existing: HashSet<String> = notes::Entity::find()
+        .select_only()
+        .column(notes::Column::Name)
+        .filter(notes::Column::Chat.eq(to))
+        .into_tuple::<String>()
+        .all(*DB)
+        .await?
+        .into_iter()
+        .collect();
+    let (copy, skip): (Vec<_>, Vec<_>) = notes::get_filters_join(notes::Column::Chat.eq(from))
+        .await?
+        .into_iter()
+        .partition(|(note, _)| !existing.contains(&note.name));
+
+    let copied = copy.len();
+    DB.transaction::<_, (), BotError>(|tx| {
+        async move {
+            let mut models = Vec::with_capacity(copy.len());
+            for (note, (entities, buttons)) in copy {
+                let entities: Vec<MessageEntity> = entities
+                    .into_iter()
+                    .map(|e| e.get())
+                    .map(|(e, u)| e.to_entity(u))
+                    .collect();
+                let buttons = get_markup_for_buttons(buttons.into_iter().collect());
+                let entity_id = entity::insert(tx, &entities, buttons.unwrap_or_default()).await?;
+                models.push(
+                    notes::Model {
+                        chat: to,
+                        entity_id,
+                        ..note
+                    }
+                    .into_active_model(),
+                );
+            }
+            if !models.is_empty() {
+                notes::Entity::insert_many(models).exec(tx).await?;
+            }
+            Ok(())
+        }
+        .boxed()
+    })
+    .await?;
+
+    let key = get_hash_key(to);
+    REDIS.sq(|q| q.del(&key)).await?;
+    Ok((copied, skip.len()))
+}
+
 /// Handles a note button transition
 pub fn handle_transition(
     ctx: &Context,
@@ -197,11 +354,6 @@ pub fn handle_transition(
                         async move {
                             log::info!("next notes: {}", note);
                             button.on_push(move |b| async move {
-                                TG.client
-                                    .build_answer_callback_query(b.get_id())
-                                    .build()
-                                    .await?;
-
                                 handle_transition(&c, chat, note, b).await?;
                                 Ok(())
                             });
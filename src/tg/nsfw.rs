@@ -0,0 +1,191 @@
+//! Nsfw image detection. Photos and stickers in chats with /nsfwfilter on are scored by a
+//! classifier, and blocklists apply the chat's action to images at or above the chat's
+//! threshold, the same way as with spam.
+//!
+//! Classifiers implement [`NsfwCheck`]. The one set in the config gets the image POSTed as
+//! the request body and must respond with json like `{"score": 0.93}`, from 0, safe, to
+//! 1, certainly nsfw. Scores are cached in redis by the file's unique id so the same
+//! sticker isn't scored again every time it's posted. Without a classifier nothing is
+//! ever scored
+
+use std::time::Duration as StdDuration;
+
+use async_trait::async_trait;
+use botapi::gen_types::Message;
+use bytes::Bytes;
+use chrono::Duration;
+use lazy_static::lazy_static;
+use redis::AsyncCommands;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::EntityTrait;
+use serde::Deserialize;
+
+use crate::persist::admin::actions::ActionType;
+use crate::persist::admin::nsfwfilter;
+use crate::persist::redis::{default_cache_query, CachedQueryTrait, RedisCache};
+use crate::statics::{CONFIG, DB, REDIS};
+use crate::util::error::Result;
+
+use super::media::{download_image, message_image};
+
+/// Score in percent that counts as nsfw in chats that never set one
+pub const DEFAULT_THRESHOLD: i32 = 80;
+
+/// Largest file in bytes sent to the classifier
+const MAX_FILE_SIZE: i64 = 5 * 1024 * 1024;
+
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+/// Gets the classifier set in the config, read each time so /reloadconfig applies to it
+fn classifier() -> Option<Box<dyn NsfwCheck>> {
+    let config = CONFIG.load();
+    config.nsfw.classifier.as_ref().map(|url| {
+        Box::new(HttpNsfwClassifier {
+            url: url.clone(),
+            timeout: StdDuration::from_millis(config.nsfw.timeout),
+        }) as Box<dyn NsfwCheck>
+    })
+}
+
+/// Something that can score images as nsfw
+#[async_trait]
+pub trait NsfwCheck: Send + Sync {
+    /// Scores an image from 0, safe, to 1, certainly nsfw
+    async fn score(&self, image: Bytes) -> Result<f32>;
+}
+
+#[derive(Deserialize)]
+struct NsfwResponse {
+    score: f32,
+}
+
+/// A classifier reached over http
+pub struct HttpNsfwClassifier {
+    url: String,
+    timeout: StdDuration,
+}
+
+#[async_trait]
+impl NsfwCheck for HttpNsfwClassifier {
+    async fn score(&self, image: Bytes) -> Result<f32> {
+        let res = CLIENT
+            .post(&self.url)
+            .timeout(self.timeout)
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .body(image)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<NsfwResponse>()
+            .await?;
+        Ok(res.score.clamp(0.0, 1.0))
+    }
+}
+
+/// Whether a classifier is configured
+pub fn nsfw_enabled() -> bool {
+    CONFIG.load().nsfw.classifier.is_some()
+}
+
+#[inline(always)]
+fn get_nsfw_score_key(file_unique_id: &str) -> String {
+    format!("nsfw:{}", file_unique_id)
+}
+
+/// Scores the photo or sticker in a message in percent. None if the message has neither or
+/// no classifier is configured
+pub async fn score_image(message: &Message) -> Result<Option<i32>> {
+    let Some(classifier) = classifier() else {
+        return Ok(None);
+    };
+    let Some(image) = message_image(message, MAX_FILE_SIZE) else {
+        return Ok(None);
+    };
+
+    let key = get_nsfw_score_key(image.file_unique_id);
+    let cached: Option<i32> = REDIS.sq(|q| q.get(&key)).await?;
+    if let Some(score) = cached {
+        return Ok(Some(score));
+    }
+    let bytes = download_image(image).await?;
+    let score = (classifier.score(bytes).await? * 100.0).round() as i32;
+    REDIS
+        .pipe(|p| {
+            p.set(&key, score)
+                .expire(&key, CONFIG.load().nsfw.cache_time)
+        })
+        .await?;
+    Ok(Some(score))
+}
+
+#[inline(always)]
+fn get_nsfw_filter_key(chat: i64) -> String {
+    format!("nsfwf:{}", chat)
+}
+
+/// Gets the nsfw filter settings for a chat, None if they were never set
+pub async fn get_nsfw_filter(chat: i64) -> Result<Option<nsfwfilter::Model>> {
+    let key = get_nsfw_filter_key(chat);
+    let res = default_cache_query(
+        |_, _| async move {
+            let res = nsfwfilter::Entity::find_by_id(chat).one(*DB).await?;
+            Ok(res)
+        },
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
+    )
+    .query(&key, &())
+    .await?;
+    Ok(res)
+}
+
+async fn update_nsfw_filter(
+    chat: i64,
+    model: nsfwfilter::ActiveModel,
+    columns: Vec<nsfwfilter::Column>,
+) -> Result<nsfwfilter::Model> {
+    let key = get_nsfw_filter_key(chat);
+    let model = nsfwfilter::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(nsfwfilter::Column::Chat)
+                .update_columns(columns)
+                .to_owned(),
+        )
+        .exec_with_returning(*DB)
+        .await?;
+    model.cache(key).await?;
+    Ok(model)
+}
+
+/// Enables or disables scoring images in a chat
+pub async fn set_nsfw_filter_enabled(chat: i64, enabled: bool) -> Result<nsfwfilter::Model> {
+    let model = nsfwfilter::ActiveModel {
+        chat: Set(chat),
+        enabled: Set(enabled),
+        threshold: NotSet,
+        action: NotSet,
+    };
+    update_nsfw_filter(chat, model, vec![nsfwfilter::Column::Enabled]).await
+}
+
+/// Sets what happens to nsfw images in a chat, and optionally the score in percent an
+/// image needs to count as nsfw
+pub async fn set_nsfw_action(
+    chat: i64,
+    action: ActionType,
+    threshold: Option<i32>,
+) -> Result<nsfwfilter::Model> {
+    let mut columns = vec![nsfwfilter::Column::Action];
+    if threshold.is_some() {
+        columns.push(nsfwfilter::Column::Threshold);
+    }
+    let model = nsfwfilter::ActiveModel {
+        chat: Set(chat),
+        enabled: NotSet,
+        threshold: threshold.map(Set).unwrap_or(NotSet),
+        action: Set(action),
+    };
+    update_nsfw_filter(chat, model, columns).await
+}
@@ -0,0 +1,119 @@
+//! Reading text out of images and stickers, so blocklists can catch words posted as
+//! pictures in chats that turned on /lockocrprofanity. Recognizing text is done by an
+//! external service set in the config, and only when built with the `ocr` feature.
+//!
+//! The image is POSTed to the service as the request body, and the response must be json
+//! like `{"text": "whatever was read"}`. Text read from a file is cached in redis by the
+//! file's unique id, so the same sticker posted over and over is only sent once. Without
+//! the feature or a configured service no text is ever found.
+
+use botapi::gen_types::Message;
+
+use crate::util::error::Result;
+
+#[cfg(feature = "ocr")]
+use std::time::Duration;
+
+#[cfg(feature = "ocr")]
+use lazy_static::lazy_static;
+
+#[cfg(feature = "ocr")]
+use redis::AsyncCommands;
+
+#[cfg(feature = "ocr")]
+use serde::Deserialize;
+
+#[cfg(feature = "ocr")]
+use crate::statics::{CONFIG, REDIS};
+
+#[cfg(feature = "ocr")]
+use super::media::{download_image, message_image, MessageImage};
+
+/// Largest file in bytes sent to the service
+#[cfg(feature = "ocr")]
+const MAX_FILE_SIZE: i64 = 5 * 1024 * 1024;
+
+#[cfg(feature = "ocr")]
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(CONFIG.load().ocr.timeout))
+        .build()
+        .expect("failed to build ocr client");
+}
+
+/// What the service responds with
+#[cfg(feature = "ocr")]
+#[derive(Deserialize, Debug)]
+struct OcrResponse {
+    #[serde(default)]
+    text: String,
+}
+
+/// Whether text can be read from images at all
+pub fn ocr_enabled() -> bool {
+    #[cfg(feature = "ocr")]
+    {
+        CONFIG.load().ocr.url.is_some()
+    }
+    #[cfg(not(feature = "ocr"))]
+    {
+        false
+    }
+}
+
+#[cfg(feature = "ocr")]
+fn get_ocr_key(file_unique_id: &str) -> String {
+    format!("ocr:{}", file_unique_id)
+}
+
+/// Sends a file to the service and returns the text it read
+#[cfg(feature = "ocr")]
+async fn recognize(url: &str, image: MessageImage<'_>) -> Result<String> {
+    let bytes = download_image(image).await?;
+    let res = CLIENT
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+        .body(bytes)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<OcrResponse>()
+        .await?;
+    Ok(res.text)
+}
+
+/// Reads the text in a message's photo or sticker. None if the message has neither, nothing
+/// was read, or no ocr service is available
+#[cfg(feature = "ocr")]
+pub async fn extract_text(message: &Message) -> Result<Option<String>> {
+    let config = CONFIG.load();
+    let Some(url) = config.ocr.url.as_ref() else {
+        return Ok(None);
+    };
+    let Some(image) = message_image(message, MAX_FILE_SIZE) else {
+        return Ok(None);
+    };
+
+    let key = get_ocr_key(image.file_unique_id);
+    let cached: Option<String> = REDIS.sq(|q| q.get(&key)).await?;
+    let text = match cached {
+        Some(text) => text,
+        None => {
+            let text = recognize(url, image).await?;
+            // images without text are cached too, as an empty string
+            REDIS
+                .pipe(|p| p.set(&key, &text).expire(&key, config.ocr.cache_time))
+                .await?;
+            text
+        }
+    };
+    let text = text.trim();
+    Ok((!text.is_empty()).then(|| text.to_owned()))
+}
+
+/// Reads the text in a message's photo or sticker. Always None since the bot was built
+/// without the ocr feature
+#[cfg(not(feature = "ocr"))]
+pub async fn extract_text(_message: &Message) -> Result<Option<String>> {
+    Ok(None)
+}
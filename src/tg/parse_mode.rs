@@ -0,0 +1,1154 @@
+//! Converters between telegram's own parse modes and message entities. Text saved using the
+//! legacy Markdown or MarkdownV2 parse modes, for example by another bot, is converted to the
+//! text and entity list stored by this bot, and entities are converted back to MarkdownV2
+//! for backups. Entities telegram detects automatically like mentions and hashtags are not
+//! represented in either parse mode and are dropped
+
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+
+use botapi::gen_types::{MessageEntity, MessageEntityBuilder, UserBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::util::error::{BotError, Result};
+
+use super::markdown::{audit_entities, EntitySpan, CUSTOM_EMOJI_PREFIX};
+
+/// Link urls with this prefix are mentions of a user by id
+const USER_PREFIX: &str = "tg://user?id=";
+
+/// Characters that must be escaped in MarkdownV2 outside of code and pre blocks
+const V2_RESERVED: &[char] = &[
+    '_', '*', '[', ']', '(', ')', '~', '`', '>', '#', '+', '-', '=', '|', '{', '}', '.', '!', '\\',
+];
+
+/// Characters that can be escaped in legacy Markdown
+const LEGACY_RESERVED: &[char] = &['_', '*', '`', '['];
+
+/// Parse modes supported by telegram's sendMessage method
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseMode {
+    Markdown,
+    MarkdownV2,
+    Html,
+}
+
+impl ParseMode {
+    /// Converts text in this parse mode to plain text and entities
+    pub fn parse(self, text: &str) -> Result<(String, Vec<MessageEntity>)> {
+        match self {
+            Self::Markdown => parse_markdown(text),
+            Self::MarkdownV2 => parse_markdown_v2(text),
+            Self::Html => parse_html(text),
+        }
+    }
+}
+
+/// Toggled formatting in MarkdownV2, links are handled separately since they aren't
+/// closed by the same token that opens them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Style {
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+    Spoiler,
+    Link,
+    CustomEmoji,
+}
+
+impl Style {
+    fn tg_type(self) -> &'static str {
+        match self {
+            Self::Bold => "bold",
+            Self::Italic => "italic",
+            Self::Underline => "underline",
+            Self::Strikethrough => "strikethrough",
+            Self::Spoiler => "spoiler",
+            Self::Link => "text_link",
+            Self::CustomEmoji => "custom_emoji",
+        }
+    }
+}
+
+/// Accumulates plain text and the entities covering it, tracking telegram's utf16 offsets
+#[derive(Default)]
+struct EntityWriter {
+    text: String,
+    len: i64,
+    entities: Vec<MessageEntity>,
+}
+
+impl EntityWriter {
+    fn push(&mut self, ch: char) {
+        self.text.push(ch);
+        self.len += ch.len_utf16() as i64;
+    }
+
+    fn push_str(&mut self, text: &str) {
+        text.chars().for_each(|ch| self.push(ch));
+    }
+
+    /// Adds an entity from start to the current end of the text. Telegram drops empty
+    /// entities so we do too
+    fn finish<F>(&mut self, start: i64, tg_type: &str, extra: F)
+    where
+        F: FnOnce(MessageEntityBuilder) -> MessageEntityBuilder,
+    {
+        if self.len > start {
+            let entity =
+                MessageEntityBuilder::new(start, self.len - start).set_type(tg_type.to_owned());
+            self.entities.push(extra(entity).build());
+        }
+    }
+
+    /// Adds a link or mention ending at the current end of the text
+    fn finish_link(&mut self, start: i64, byte_start: usize, style: Style, url: String) {
+        if style == Style::CustomEmoji {
+            if let Some(id) = url.strip_prefix(CUSTOM_EMOJI_PREFIX) {
+                let id = id.to_owned();
+                self.finish(start, "custom_emoji", |e| e.set_custom_emoji_id(id));
+                return;
+            }
+        }
+
+        match url
+            .strip_prefix(USER_PREFIX)
+            .and_then(|id| str::parse::<i64>(id).ok())
+        {
+            Some(id) => {
+                let user = UserBuilder::new(id, false, self.text[byte_start..].to_owned()).build();
+                self.finish(start, "text_mention", |e| e.set_user(user));
+            }
+            None => self.finish(start, "text_link", |e| e.set_url(url)),
+        }
+    }
+
+    fn into_parts(mut self) -> (String, Vec<MessageEntity>) {
+        self.entities
+            .sort_by_key(|e| (e.get_offset(), Reverse(e.get_length())));
+        (self.text, self.entities)
+    }
+}
+
+/// Reads until an unescaped terminator, returning the unescaped text and the index after
+/// the terminator. Every character may be escaped
+fn read_escaped(chars: &[char], mut i: usize, end: &[char]) -> Option<(String, usize)> {
+    let mut out = String::new();
+    while i < chars.len() {
+        if chars[i] == '\\' && i + 1 < chars.len() {
+            out.push(chars[i + 1]);
+            i += 2;
+        } else if chars[i..].starts_with(end) {
+            return Some((out, i + end.len()));
+        } else {
+            out.push(chars[i]);
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Reads until a terminator with no escaping, as in legacy Markdown entities
+fn read_raw(chars: &[char], i: usize, end: &[char]) -> Option<(String, usize)> {
+    (i..chars.len())
+        .find(|&x| chars[x..].starts_with(end))
+        .map(|x| (chars[i..x].iter().collect(), x + end.len()))
+}
+
+/// Splits the optional language off of the first line of a pre block
+fn split_language(content: String) -> (Option<String>, String) {
+    match content.split_once('\n') {
+        Some((language, body)) if !language.is_empty() => {
+            (Some(language.to_owned()), body.to_owned())
+        }
+        Some((_, body)) => (None, body.to_owned()),
+        None => (None, content),
+    }
+}
+
+fn write_pre(out: &mut EntityWriter, content: String) {
+    let (language, body) = split_language(content);
+    let start = out.len;
+    out.push_str(&body);
+    out.finish(start, "pre", |e| match language {
+        Some(language) => e.set_language(language),
+        None => e,
+    });
+}
+
+fn unclosed(what: &str) -> BotError {
+    BotError::Generic(format!("unclosed {} entity", what))
+}
+
+/// Converts text using telegram's MarkdownV2 parse mode to plain text and entities.
+/// Reserved characters that don't form valid markup are kept as text rather than rejected,
+/// since content from other bots is not always escaped properly
+pub fn parse_markdown_v2(text: &str) -> Result<(String, Vec<MessageEntity>)> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut out = EntityWriter::default();
+    // open entities with their utf16 and byte start
+    let mut stack: Vec<(Style, i64, usize)> = Vec::new();
+    // open blockquote start and whether it is expandable
+    let mut quote: Option<(i64, bool)> = None;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if i == 0 || chars[i - 1] == '\n' {
+            if chars[i..].starts_with(&['*', '*', '>']) {
+                quote.get_or_insert((out.len, true));
+                i += 3;
+                continue;
+            }
+            if ch == '>' {
+                quote.get_or_insert((out.len, false));
+                i += 1;
+                continue;
+            }
+        }
+
+        match ch {
+            '\\' => {
+                let next = next.ok_or_else(|| BotError::Generic("trailing escape".to_owned()))?;
+                out.push(next);
+                i += 2;
+            }
+            '\n' => {
+                if let Some((start, expandable)) = quote {
+                    if next != Some('>') {
+                        let tg_type = if expandable {
+                            "expandable_blockquote"
+                        } else {
+                            "blockquote"
+                        };
+                        out.finish(start, tg_type, |e| e);
+                        quote = None;
+                    }
+                }
+                out.push(ch);
+                i += 1;
+            }
+            '|' if next == Some('|') => {
+                let line_end = matches!(chars.get(i + 2), None | Some('\n'));
+                let spoiler = stack.iter().any(|(s, _, _)| *s == Style::Spoiler);
+                match quote {
+                    Some((start, true)) if line_end && !spoiler => {
+                        out.finish(start, "expandable_blockquote", |e| e);
+                        quote = None;
+                    }
+                    _ => toggle(&mut stack, &mut out, Style::Spoiler),
+                }
+                i += 2;
+            }
+            '*' => {
+                toggle(&mut stack, &mut out, Style::Bold);
+                i += 1;
+            }
+            '_' if next == Some('_') => {
+                toggle(&mut stack, &mut out, Style::Underline);
+                i += 2;
+            }
+            '_' => {
+                toggle(&mut stack, &mut out, Style::Italic);
+                i += 1;
+            }
+            '~' => {
+                toggle(&mut stack, &mut out, Style::Strikethrough);
+                i += 1;
+            }
+            '`' if chars[i..].starts_with(&['`', '`', '`']) => {
+                let (content, end) =
+                    read_escaped(&chars, i + 3, &['`', '`', '`']).ok_or_else(|| unclosed("pre"))?;
+                write_pre(&mut out, content);
+                i = end;
+            }
+            '`' => {
+                let (content, end) =
+                    read_escaped(&chars, i + 1, &['`']).ok_or_else(|| unclosed("code"))?;
+                let start = out.len;
+                out.push_str(&content);
+                out.finish(start, "code", |e| e);
+                i = end;
+            }
+            '!' if next == Some('[') => {
+                stack.push((Style::CustomEmoji, out.len, out.text.len()));
+                i += 2;
+            }
+            '[' => {
+                stack.push((Style::Link, out.len, out.text.len()));
+                i += 1;
+            }
+            ']' if next == Some('(')
+                && matches!(stack.last(), Some((Style::Link | Style::CustomEmoji, _, _))) =>
+            {
+                let (url, end) =
+                    read_escaped(&chars, i + 2, &[')']).ok_or_else(|| unclosed("text_link"))?;
+                if let Some((style, start, byte_start)) = stack.pop() {
+                    out.finish_link(start, byte_start, style, url);
+                }
+                i = end;
+            }
+            ch => {
+                out.push(ch);
+                i += 1;
+            }
+        }
+    }
+
+    if let Some((start, expandable)) = quote {
+        let tg_type = if expandable {
+            "expandable_blockquote"
+        } else {
+            "blockquote"
+        };
+        out.finish(start, tg_type, |e| e);
+    }
+
+    if let Some((style, _, _)) = stack.first() {
+        return Err(unclosed(style.tg_type()));
+    }
+
+    Ok(out.into_parts())
+}
+
+/// Opens a style, or closes it if it is already open. Styles don't need to be closed in
+/// the order they were opened since overlapping entities are valid
+fn toggle(stack: &mut Vec<(Style, i64, usize)>, out: &mut EntityWriter, style: Style) {
+    if let Some(pos) = stack.iter().rposition(|(s, _, _)| *s == style) {
+        let (_, start, _) = stack.remove(pos);
+        out.finish(start, style.tg_type(), |e| e);
+    } else {
+        stack.push((style, out.len, out.text.len()));
+    }
+}
+
+/// Converts text using telegram's legacy Markdown parse mode to plain text and entities.
+/// Legacy Markdown has no nesting and no escaping inside entities
+pub fn parse_markdown(text: &str) -> Result<(String, Vec<MessageEntity>)> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut out = EntityWriter::default();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        match ch {
+            '\\' if chars
+                .get(i + 1)
+                .map(|c| LEGACY_RESERVED.contains(c))
+                .unwrap_or(false) =>
+            {
+                out.push(chars[i + 1]);
+                i += 2;
+            }
+            '*' | '_' => {
+                let style = if ch == '*' {
+                    Style::Bold
+                } else {
+                    Style::Italic
+                };
+                let (content, end) =
+                    read_raw(&chars, i + 1, &[ch]).ok_or_else(|| unclosed(style.tg_type()))?;
+                let start = out.len;
+                out.push_str(&content);
+                out.finish(start, style.tg_type(), |e| e);
+                i = end;
+            }
+            '`' if chars[i..].starts_with(&['`', '`', '`']) => {
+                let (content, end) =
+                    read_raw(&chars, i + 3, &['`', '`', '`']).ok_or_else(|| unclosed("pre"))?;
+                write_pre(&mut out, content);
+                i = end;
+            }
+            '`' => {
+                let (content, end) =
+                    read_raw(&chars, i + 1, &['`']).ok_or_else(|| unclosed("code"))?;
+                let start = out.len;
+                out.push_str(&content);
+                out.finish(start, "code", |e| e);
+                i = end;
+            }
+            '[' => {
+                let (label, end) =
+                    read_raw(&chars, i + 1, &[']', '(']).ok_or_else(|| unclosed("text_link"))?;
+                let (url, end) =
+                    read_raw(&chars, end, &[')']).ok_or_else(|| unclosed("text_link"))?;
+                let start = out.len;
+                let byte_start = out.text.len();
+                out.push_str(&label);
+                out.finish_link(start, byte_start, Style::Link, url);
+                i = end;
+            }
+            ch => {
+                out.push(ch);
+                i += 1;
+            }
+        }
+    }
+
+    Ok(out.into_parts())
+}
+
+/// An open tag in telegram's HTML parse mode and the entity it becomes when closed
+enum HtmlTag {
+    Style(&'static str),
+    Link(String),
+    CustomEmoji(String),
+    Pre(Option<String>),
+    /// code inside of pre only sets the pre block's language
+    PreCode,
+    Quote(bool),
+}
+
+/// Decodes an HTML character reference like `&amp;` starting at i, returning the character
+/// and the index after it. Unknown references are left as text like telegram does
+fn read_html_entity(chars: &[char], i: usize) -> Option<(char, usize)> {
+    let end = (i + 1..chars.len().min(i + 12)).find(|&x| chars[x] == ';')?;
+    let name = chars[i + 1..end].iter().collect::<String>();
+    let ch = match name.as_str() {
+        "lt" => '<',
+        "gt" => '>',
+        "amp" => '&',
+        "quot" => '"',
+        name => {
+            let code = match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => str::parse(name.strip_prefix('#')?).ok()?,
+            };
+            char::from_u32(code)?
+        }
+    };
+    Some((ch, end + 1))
+}
+
+fn decode_html(text: &str) -> String {
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match read_html_entity(&chars, i).filter(|_| chars[i] == '&') {
+            Some((ch, end)) => {
+                out.push(ch);
+                i = end;
+            }
+            None => {
+                out.push(chars[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Splits the inside of a tag into its lowercase name and attributes
+fn parse_tag(tag: &str) -> (String, Vec<(String, String)>) {
+    let tag = tag.trim();
+    let (name, mut rest) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+    let mut attrs = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let key_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let key = rest[..key_end].to_lowercase();
+        rest = rest[key_end..].trim_start();
+        let value = if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (value, next) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let inner = &after[1..];
+                    let end = inner.find(quote).unwrap_or(inner.len());
+                    (&inner[..end], inner.get(end + 1..).unwrap_or(""))
+                }
+                _ => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            rest = next;
+            decode_html(value)
+        } else {
+            String::new()
+        };
+        attrs.push((key, value));
+    }
+    (name.to_lowercase(), attrs)
+}
+
+fn html_attr<'a>(attrs: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    attrs
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+/// Converts text using telegram's HTML parse mode to plain text and entities. Like
+/// telegram, unsupported tags are rejected and tags must be closed in order
+pub fn parse_html(text: &str) -> Result<(String, Vec<MessageEntity>)> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut out = EntityWriter::default();
+    // open tags with their name, utf16 and byte start
+    let mut stack: Vec<(String, HtmlTag, i64, usize)> = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '<' => {
+                let end = (i + 1..chars.len())
+                    .find(|&x| chars[x] == '>')
+                    .ok_or_else(|| BotError::Generic("unclosed tag".to_owned()))?;
+                let tag = chars[i + 1..end].iter().collect::<String>();
+                i = end + 1;
+
+                if let Some(name) = tag.strip_prefix('/') {
+                    let name = name.trim().to_lowercase();
+                    let (open, kind, start, byte_start) = stack
+                        .pop()
+                        .ok_or_else(|| BotError::Generic(format!("unopened tag {}", name)))?;
+                    if !name.is_empty() && open != name {
+                        return Err(BotError::Generic(format!(
+                            "tag {} closed by {}",
+                            open, name
+                        )));
+                    }
+                    match kind {
+                        HtmlTag::Style(tg_type) => out.finish(start, tg_type, |e| e),
+                        HtmlTag::Link(url) => out.finish_link(start, byte_start, Style::Link, url),
+                        HtmlTag::CustomEmoji(id) => {
+                            out.finish(start, "custom_emoji", |e| e.set_custom_emoji_id(id))
+                        }
+                        HtmlTag::Pre(language) => out.finish(start, "pre", |e| match language {
+                            Some(language) => e.set_language(language),
+                            None => e,
+                        }),
+                        HtmlTag::PreCode => (),
+                        HtmlTag::Quote(expandable) => {
+                            let tg_type = if expandable {
+                                "expandable_blockquote"
+                            } else {
+                                "blockquote"
+                            };
+                            out.finish(start, tg_type, |e| e)
+                        }
+                    }
+                    continue;
+                }
+
+                let (name, attrs) = parse_tag(&tag);
+                let kind = match name.as_str() {
+                    "b" | "strong" => HtmlTag::Style("bold"),
+                    "i" | "em" => HtmlTag::Style("italic"),
+                    "u" | "ins" => HtmlTag::Style("underline"),
+                    "s" | "strike" | "del" => HtmlTag::Style("strikethrough"),
+                    "tg-spoiler" => HtmlTag::Style("spoiler"),
+                    "span" if html_attr(&attrs, "class") == Some("tg-spoiler") => {
+                        HtmlTag::Style("spoiler")
+                    }
+                    "a" => {
+                        let url = html_attr(&attrs, "href")
+                            .ok_or_else(|| BotError::Generic("link without href".to_owned()))?;
+                        HtmlTag::Link(url.to_owned())
+                    }
+                    "tg-emoji" => {
+                        let id = html_attr(&attrs, "emoji-id")
+                            .ok_or_else(|| BotError::Generic("emoji without id".to_owned()))?;
+                        HtmlTag::CustomEmoji(id.to_owned())
+                    }
+                    "pre" => HtmlTag::Pre(None),
+                    "code" => match stack.last_mut() {
+                        Some((_, HtmlTag::Pre(language), start, _)) if *start == out.len => {
+                            *language = html_attr(&attrs, "class")
+                                .and_then(|class| class.strip_prefix("language-"))
+                                .map(|language| language.to_owned());
+                            HtmlTag::PreCode
+                        }
+                        _ => HtmlTag::Style("code"),
+                    },
+                    "blockquote" => HtmlTag::Quote(html_attr(&attrs, "expandable").is_some()),
+                    name => {
+                        return Err(BotError::Generic(format!("unsupported tag {}", name)));
+                    }
+                };
+                stack.push((name, kind, out.len, out.text.len()));
+            }
+            '&' => match read_html_entity(&chars, i) {
+                Some((ch, end)) => {
+                    out.push(ch);
+                    i = end;
+                }
+                None => {
+                    out.push('&');
+                    i += 1;
+                }
+            },
+            ch => {
+                out.push(ch);
+                i += 1;
+            }
+        }
+    }
+
+    if let Some((name, _, _, _)) = stack.first() {
+        return Err(unclosed(name));
+    }
+
+    Ok(out.into_parts())
+}
+
+/// Returns true if the entity can be represented in MarkdownV2
+fn is_supported(entity: &MessageEntity) -> bool {
+    match entity.get_tg_type() {
+        "bold"
+        | "italic"
+        | "underline"
+        | "strikethrough"
+        | "spoiler"
+        | "code"
+        | "pre"
+        | "blockquote"
+        | "expandable_blockquote" => true,
+        "text_link" => entity.get_url().is_some(),
+        "text_mention" => entity.get_user().is_some(),
+        "custom_emoji" => entity.get_custom_emoji_id().is_some(),
+        _ => false,
+    }
+}
+
+fn is_raw(entity: &MessageEntity) -> bool {
+    matches!(entity.get_tg_type(), "code" | "pre")
+}
+
+/// When several entities start at the same place, entities with a lower rank are opened
+/// first. Blockquotes must start the line and code can't contain other entities
+fn nesting_rank(entity: &MessageEntity) -> u8 {
+    match entity.get_tg_type() {
+        "blockquote" | "expandable_blockquote" => 0,
+        "text_link" | "text_mention" | "custom_emoji" => 2,
+        "code" | "pre" => 3,
+        _ => 1,
+    }
+}
+
+/// Writes MarkdownV2 text, tracking what is needed to keep the output unambiguous
+struct V2Writer<'a> {
+    out: String,
+    active: Vec<&'a MessageEntity>,
+    // a blockquote is open and the next line needs a quote marker
+    quote_line: bool,
+    // the output ends with an underscore that is part of a marker
+    underscore: bool,
+}
+
+impl<'a> V2Writer<'a> {
+    fn in_raw(&self) -> bool {
+        self.active.iter().any(|e| is_raw(e))
+    }
+
+    fn in_quote(&self) -> bool {
+        self.active
+            .iter()
+            .any(|e| matches!(e.get_tg_type(), "blockquote" | "expandable_blockquote"))
+    }
+
+    fn text(&mut self, text: &str) {
+        let raw = self.in_raw();
+        for ch in text.chars() {
+            if self.quote_line && self.in_quote() {
+                self.out.push('>');
+            }
+            self.quote_line = ch == '\n';
+            let escape = if raw {
+                ch == '`' || ch == '\\'
+            } else {
+                V2_RESERVED.contains(&ch)
+            };
+            if escape {
+                self.out.push('\\');
+            }
+            self.out.push(ch);
+            self.underscore = false;
+        }
+    }
+
+    /// Writes a formatting marker. Consecutive underscore markers are ambiguous between
+    /// italic and underline, so they are separated by an empty entity that isn't open
+    fn marker(&mut self, marker: &str) {
+        if self.underscore && marker.starts_with('_') {
+            let separator = [("**", "bold"), ("~~", "strikethrough"), ("||", "spoiler")]
+                .into_iter()
+                .find(|(_, t)| !self.active.iter().any(|e| e.get_tg_type() == *t))
+                .map(|(s, _)| s)
+                .unwrap_or("**");
+            self.out.push_str(separator);
+        }
+        self.out.push_str(marker);
+        self.underscore = marker.ends_with('_');
+    }
+
+    fn escaped_url(url: &str) -> String {
+        url.chars().fold(String::new(), |mut acc, ch| {
+            if ch == ')' || ch == '\\' {
+                acc.push('\\');
+            }
+            acc.push(ch);
+            acc
+        })
+    }
+
+    fn open(&mut self, entity: &'a MessageEntity) {
+        match entity.get_tg_type() {
+            "bold" => self.marker("*"),
+            "italic" => self.marker("_"),
+            "underline" => self.marker("__"),
+            "strikethrough" => self.marker("~"),
+            "spoiler" => self.marker("||"),
+            "code" => self.marker("`"),
+            "pre" => {
+                let language = entity.get_language().unwrap_or("").to_owned();
+                self.marker("```");
+                // escaping the language needs pre to be open
+                self.active.push(entity);
+                self.text(&language);
+                self.active.pop();
+                self.out.push('\n');
+            }
+            "text_link" | "text_mention" => self.marker("["),
+            "custom_emoji" => self.marker("!["),
+            "blockquote" => self.marker(">"),
+            "expandable_blockquote" => self.marker("**>"),
+            _ => (),
+        }
+        self.quote_line = false;
+        self.active.push(entity);
+    }
+
+    fn close(&mut self, entity: &'a MessageEntity) {
+        match entity.get_tg_type() {
+            "bold" => self.marker("*"),
+            "italic" => self.marker("_"),
+            "underline" => self.marker("__"),
+            "strikethrough" => self.marker("~"),
+            "spoiler" => self.marker("||"),
+            "code" => self.marker("`"),
+            "pre" => self.marker("```"),
+            "text_link" => {
+                let url = Self::escaped_url(entity.get_url().unwrap_or(""));
+                self.marker(&format!("]({})", url));
+            }
+            "text_mention" => {
+                let id = entity.get_user().map(|u| u.get_id()).unwrap_or(0);
+                self.marker(&format!("]({}{})", USER_PREFIX, id));
+            }
+            "custom_emoji" => {
+                let id = Self::escaped_url(entity.get_custom_emoji_id().unwrap_or(""));
+                self.marker(&format!("]({}{})", CUSTOM_EMOJI_PREFIX, id));
+            }
+            "expandable_blockquote" => self.marker("||"),
+            _ => (),
+        }
+    }
+}
+
+/// Converts text and entities to telegram's MarkdownV2 parse mode. Overlapping entities are
+/// split where needed, and entities that can't be represented are dropped
+pub fn to_markdown_v2(text: &str, entities: &[MessageEntity]) -> Result<String> {
+    let spans = audit_entities(text, entities)?;
+    let mut supported = entities
+        .iter()
+        .zip(spans)
+        .filter(|(e, s)| !s.is_empty() && is_supported(e))
+        .collect::<Vec<(&MessageEntity, EntitySpan)>>();
+
+    // code can't contain other entities, drop anything inside or partially overlapping it
+    let raw = supported
+        .iter()
+        .filter(|(e, _)| is_raw(e))
+        .map(|(_, s)| *s)
+        .collect::<Vec<EntitySpan>>();
+    supported.retain(|(_, s)| {
+        raw.iter().all(|r| {
+            let overlaps = s.start() < r.end() && s.end() > r.start();
+            let contains = s.start() <= r.start() && s.end() >= r.end();
+            !overlaps || contains
+        })
+    });
+
+    let bounds = supported
+        .iter()
+        .flat_map(|(_, s)| [s.start(), s.end()])
+        .chain([text.len()])
+        .collect::<BTreeSet<usize>>();
+
+    let mut writer = V2Writer {
+        out: String::with_capacity(text.len()),
+        active: Vec::new(),
+        quote_line: false,
+        underscore: false,
+    };
+    let mut stack: Vec<usize> = Vec::new();
+    let mut prev = 0;
+    for pos in bounds {
+        writer.text(&text[prev..pos]);
+        prev = pos;
+
+        // closing an entity closes everything opened after it, reopen the ones that continue
+        if let Some(lowest) = stack.iter().position(|&e| supported[e].1.end() == pos) {
+            let popped = stack.split_off(lowest);
+            for &e in popped.iter().rev() {
+                writer.active.pop();
+                writer.close(supported[e].0);
+            }
+            for e in popped {
+                if supported[e].1.end() != pos {
+                    writer.open(supported[e].0);
+                    stack.push(e);
+                }
+            }
+        }
+
+        let mut starting = (0..supported.len())
+            .filter(|&e| supported[e].1.start() == pos)
+            .collect::<Vec<usize>>();
+        starting.sort_by_key(|&e| {
+            let (entity, span) = supported[e];
+            (Reverse(span.end()), nesting_rank(entity))
+        });
+        for e in starting {
+            writer.open(supported[e].0);
+            stack.push(e);
+        }
+    }
+
+    Ok(writer.out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    type Flat = (String, i64, i64, Option<String>);
+
+    fn flatten(entities: &[MessageEntity]) -> Vec<Flat> {
+        let mut v = entities
+            .iter()
+            .map(|e| {
+                let extra = e
+                    .get_url()
+                    .map(|v| v.to_owned())
+                    .or_else(|| e.get_language().map(|v| v.to_owned()))
+                    .or_else(|| e.get_custom_emoji_id().map(|v| v.to_owned()))
+                    .or_else(|| e.get_user().map(|u| u.get_id().to_string()));
+                (
+                    e.get_tg_type().to_owned(),
+                    e.get_offset(),
+                    e.get_length(),
+                    extra,
+                )
+            })
+            .collect::<Vec<Flat>>();
+        v.sort();
+        v
+    }
+
+    fn entity(tg_type: &str, offset: i64, length: i64) -> MessageEntity {
+        MessageEntityBuilder::new(offset, length)
+            .set_type(tg_type.to_owned())
+            .build()
+    }
+
+    fn round_trip(text: &str, entities: &[MessageEntity]) -> String {
+        let md = to_markdown_v2(text, entities).unwrap();
+        let (out, out_entities) = parse_markdown_v2(&md).unwrap();
+        assert_eq!(out, text, "markdown {}", md);
+        assert_eq!(flatten(&out_entities), flatten(entities), "markdown {}", md);
+        md
+    }
+
+    #[test]
+    fn v2_simple() {
+        let (text, entities) = parse_markdown_v2("*bold* _italic_ __under__ ~strike~").unwrap();
+        assert_eq!(text, "bold italic under strike");
+        assert_eq!(
+            flatten(&entities),
+            vec![
+                ("bold".to_owned(), 0, 4, None),
+                ("italic".to_owned(), 5, 6, None),
+                ("strikethrough".to_owned(), 18, 6, None),
+                ("underline".to_owned(), 12, 5, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn v2_escapes() {
+        let (text, entities) = parse_markdown_v2("1\\. \\*not bold\\* \\\\ a\\_b").unwrap();
+        assert_eq!(text, "1. *not bold* \\ a_b");
+        assert!(entities.is_empty());
+    }
+
+    #[test]
+    fn v2_unescaped_reserved() {
+        let (text, entities) = parse_markdown_v2("1. a-b (c) #tag!").unwrap();
+        assert_eq!(text, "1. a-b (c) #tag!");
+        assert!(entities.is_empty());
+    }
+
+    #[test]
+    fn v2_greedy_underline() {
+        let (text, entities) = parse_markdown_v2("___italic underline_**__").unwrap();
+        assert_eq!(text, "italic underline");
+        assert_eq!(
+            flatten(&entities),
+            vec![
+                ("italic".to_owned(), 0, 16, None),
+                ("underline".to_owned(), 0, 16, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn v2_code() {
+        let (text, entities) = parse_markdown_v2("`a \\` *b*` ```rust\nfn main() {}\n```").unwrap();
+        assert_eq!(text, "a ` *b* fn main() {}\n");
+        assert_eq!(
+            flatten(&entities),
+            vec![
+                ("code".to_owned(), 0, 7, None),
+                ("pre".to_owned(), 8, 13, Some("rust".to_owned())),
+            ]
+        );
+    }
+
+    #[test]
+    fn v2_links() {
+        let (text, entities) = parse_markdown_v2(
+            "[link](https://example.com/a\\)b) [me](tg://user?id=1234) ![👍](tg://emoji?id=5368324170671202286)",
+        )
+        .unwrap();
+        assert_eq!(text, "link me 👍");
+        assert_eq!(
+            flatten(&entities),
+            vec![
+                (
+                    "custom_emoji".to_owned(),
+                    8,
+                    2,
+                    Some("5368324170671202286".to_owned())
+                ),
+                (
+                    "text_link".to_owned(),
+                    0,
+                    4,
+                    Some("https://example.com/a)b".to_owned())
+                ),
+                ("text_mention".to_owned(), 5, 2, Some("1234".to_owned())),
+            ]
+        );
+    }
+
+    #[test]
+    fn v2_blockquote() {
+        let (text, entities) =
+            parse_markdown_v2(">quoted\n>*still* quoted\nnot quoted\n**>hidden\n>more||").unwrap();
+        assert_eq!(text, "quoted\nstill quoted\nnot quoted\nhidden\nmore");
+        assert_eq!(
+            flatten(&entities),
+            vec![
+                ("blockquote".to_owned(), 0, 19, None),
+                ("bold".to_owned(), 7, 5, None),
+                ("expandable_blockquote".to_owned(), 31, 11, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn v2_unclosed() {
+        assert!(parse_markdown_v2("*bold").is_err());
+        assert!(parse_markdown_v2("`code").is_err());
+        assert!(parse_markdown_v2("[link](https://example.com").is_err());
+        assert!(parse_markdown_v2("trailing\\").is_err());
+    }
+
+    #[test]
+    fn legacy() {
+        let (text, entities) =
+            parse_markdown("*bold* _it*alic_ `code\\` [link](https://example.com) \\_x").unwrap();
+        assert_eq!(text, "bold it*alic code\\ link _x");
+        assert_eq!(
+            flatten(&entities),
+            vec![
+                ("bold".to_owned(), 0, 4, None),
+                ("code".to_owned(), 13, 5, None),
+                ("italic".to_owned(), 5, 7, None),
+                (
+                    "text_link".to_owned(),
+                    19,
+                    4,
+                    Some("https://example.com".to_owned())
+                ),
+            ]
+        );
+        assert!(parse_markdown("*unclosed").is_err());
+    }
+
+    #[test]
+    fn round_trip_reserved() {
+        let text = "_*[]()~`>#+-=|{}.!\\ every reserved char";
+        let md = round_trip(text, &[entity("bold", 0, 19)]);
+        assert!(md.starts_with("*\\_\\*"));
+        round_trip(text, &[]);
+    }
+
+    #[test]
+    fn round_trip_nested() {
+        let text = "bold italic underline strike spoiler";
+        round_trip(
+            text,
+            &[
+                entity("bold", 0, 36),
+                entity("italic", 5, 31),
+                entity("underline", 12, 24),
+                entity("strikethrough", 22, 14),
+                entity("spoiler", 29, 7),
+            ],
+        );
+    }
+
+    #[test]
+    fn round_trip_ambiguous_underscores() {
+        let text = "abc";
+        round_trip(text, &[entity("italic", 0, 3), entity("underline", 0, 3)]);
+        round_trip(text, &[entity("italic", 0, 1), entity("italic", 1, 2)]);
+        round_trip(text, &[entity("underline", 0, 1), entity("italic", 1, 2)]);
+        round_trip(
+            text,
+            &[
+                entity("bold", 0, 3),
+                entity("italic", 0, 1),
+                entity("italic", 1, 2),
+            ],
+        );
+    }
+
+    #[test]
+    fn round_trip_overlapping() {
+        let text = "one two three";
+        let md = to_markdown_v2(text, &[entity("bold", 0, 7), entity("italic", 4, 9)]).unwrap();
+        let (out, entities) = parse_markdown_v2(&md).unwrap();
+        assert_eq!(out, text);
+        assert_eq!(
+            flatten(&entities),
+            vec![
+                ("bold".to_owned(), 0, 7, None),
+                ("italic".to_owned(), 4, 3, None),
+                ("italic".to_owned(), 7, 6, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip_code() {
+        let text = "let x = `a\\b`; done";
+        round_trip(
+            text,
+            &[
+                entity("code", 0, 13),
+                entity("bold", 0, 19),
+                entity("italic", 15, 4),
+            ],
+        );
+
+        let pre = MessageEntityBuilder::new(0, 13)
+            .set_type("pre".to_owned())
+            .set_language("c++".to_owned())
+            .build();
+        round_trip(text, &[pre]);
+        round_trip("line one\nline two", &[entity("pre", 0, 17)]);
+        round_trip("\nstarts with a newline", &[entity("pre", 0, 22)]);
+    }
+
+    #[test]
+    fn round_trip_links() {
+        let text = "link 👍 mention";
+        let link = MessageEntityBuilder::new(0, 4)
+            .set_type("text_link".to_owned())
+            .set_url("https://example.com/(a)\\b".to_owned())
+            .build();
+        let emoji = MessageEntityBuilder::new(5, 2)
+            .set_type("custom_emoji".to_owned())
+            .set_custom_emoji_id("5368324170671202286".to_owned())
+            .build();
+        let mention = MessageEntityBuilder::new(8, 7)
+            .set_type("text_mention".to_owned())
+            .set_user(UserBuilder::new(42, false, "mention".to_owned()).build())
+            .build();
+        round_trip(text, &[link, emoji, mention, entity("bold", 0, 15)]);
+    }
+
+    #[test]
+    fn round_trip_blockquote() {
+        let text = "quote *\n\nstill quote\nafter";
+        round_trip(text, &[entity("blockquote", 0, 20), entity("bold", 6, 8)]);
+        round_trip(text, &[entity("expandable_blockquote", 0, 20)]);
+    }
+
+    #[test]
+    fn round_trip_wide() {
+        let text = "🦀 crab 🦀 ünïcödé 🦀";
+        round_trip(
+            text,
+            &[
+                entity("bold", 0, 2),
+                entity("spoiler", 3, 7),
+                entity("underline", 11, 10),
+            ],
+        );
+    }
+
+    #[test]
+    fn html() {
+        let (text, entities) =
+            parse_html("<b>bold <i>both</i></b> &lt;not a tag&gt; <a href=\"https://a.b/?x=1&amp;y=2\">link</a>")
+                .unwrap();
+        assert_eq!(text, "bold both <not a tag> link");
+        let link = MessageEntityBuilder::new(22, 4)
+            .set_type("text_link".to_owned())
+            .set_url("https://a.b/?x=1&y=2".to_owned())
+            .build();
+        assert_eq!(
+            flatten(&entities),
+            flatten(&[entity("bold", 0, 9), entity("italic", 5, 4), link])
+        );
+
+        let (text, entities) = parse_html(
+            "<pre><code class=\"language-rust\">let x;</code></pre> <tg-spoiler>boo</tg-spoiler>",
+        )
+        .unwrap();
+        assert_eq!(text, "let x; boo");
+        let pre = MessageEntityBuilder::new(0, 6)
+            .set_type("pre".to_owned())
+            .set_language("rust".to_owned())
+            .build();
+        assert_eq!(flatten(&entities), flatten(&[pre, entity("spoiler", 7, 3)]));
+
+        assert!(parse_html("<b>unclosed").is_err());
+        assert!(parse_html("<b><i>crossed</b></i>").is_err());
+        assert!(parse_html("<marquee>no</marquee>").is_err());
+    }
+
+    #[test]
+    fn unsupported_dropped() {
+        let md = to_markdown_v2(
+            "#tag @user",
+            &[entity("hashtag", 0, 4), entity("mention", 5, 5)],
+        )
+        .unwrap();
+        assert_eq!(md, "\\#tag @user");
+        assert!(to_markdown_v2("a", &[entity("bold", 0, 5)]).is_err());
+    }
+}
@@ -2,17 +2,21 @@
 //! more granular permissions based on telegram's own system
 
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::{
     langs::Lang,
     persist::{
         core::dialogs,
+        keys::{self, ScopedKey},
+        local::{self, LocalCache},
         redis::{RedisStr, ToRedisStr},
     },
     statics::{CONFIG, DB, REDIS, TG},
     util::string::get_chat_lang,
     util::{
         error::{BotError, Fail, Result},
+        error_codes::{ANONYMOUS_DENIED, BOT_NOT_ADMIN, PERMISSION_DENIED, UNKNOWN_USER},
         string::Speak,
     },
 };
@@ -23,16 +27,19 @@ use botapi::gen_types::{
 };
 use chrono::Duration;
 use sea_orm::IntoActiveModel;
-use tokio::{sync::mpsc, time::sleep};
-use uuid::Uuid;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::sleep,
+};
 
 use super::{
     admin_helpers::{is_group_or_die, is_self_admin},
-    button::{InlineKeyboardBuilder, OnPush},
+    business::is_business_owner,
+    button::{callback_data, CallbackReply, InlineKeyboardBuilder, OnPush},
     command::Context,
     dialog::upsert_dialog,
     markdown::EntityMessage,
-    user::{GetUser, Username},
+    user::{get_chat, GetUser, Username},
 };
 use itertools::Itertools;
 use macros::lang_fmt;
@@ -63,6 +70,12 @@ pub struct NamedBotPermissions {
     pub can_change_info: NamedPermission,
     pub can_promote_members: NamedPermission,
     pub can_pin_messages: NamedPermission,
+    pub can_manage_topics: NamedPermission,
+    pub can_invite_users: NamedPermission,
+    pub can_post_messages: NamedPermission,
+    pub can_edit_messages: NamedPermission,
+    pub can_manage_video_chats: NamedPermission,
+    pub is_anonymous: NamedPermission,
     pub is_sudo: NamedPermission,
     pub is_support: NamedPermission,
 }
@@ -72,12 +85,13 @@ impl NamedBotPermissions {
     pub async fn from_chatmember(admin: ChatMember) -> Result<Self> {
         let user = admin.get_user().get_id();
         let mut v: NamedBotPermissions = admin.into();
-        if CONFIG.admin.sudo_users.contains(&user) {
+        let config = CONFIG.load();
+        if config.admin.sudo_users.contains(&user) {
             v.is_sudo.0.iter_mut().for_each(|v| v.val = true);
             v.is_support.0.iter_mut().for_each(|v| v.val = true);
         }
 
-        if CONFIG.admin.support_users.contains(&user) {
+        if config.admin.support_users.contains(&user) {
             v.is_support.0.iter_mut().for_each(|v| v.val = true);
         }
 
@@ -89,24 +103,35 @@ impl NamedBotPermissions {
         let mut v = if let Some(admin) = chat.is_user_admin(user.get_id()).await? {
             Ok::<Self, BotError>(admin.into())
         } else {
+            // business account owners can change the settings of their own chat with the bot,
+            // which also apply to the chats of the account
+            let own_chat =
+                chat.get_id() == user.get_id() && is_business_owner(user.get_id()).await?;
             let v: NamedBotPermissions = BotPermissions {
                 can_manage_chat: false,
                 can_restrict_members: false,
                 can_delete_messages: false,
-                can_change_info: false,
+                can_change_info: own_chat,
                 can_promote_members: false,
                 can_pin_messages: false,
+                can_manage_topics: false,
+                can_invite_users: false,
+                can_post_messages: false,
+                can_edit_messages: false,
+                can_manage_video_chats: false,
+                is_anonymous: false,
             }
             .into();
             Ok(v)
         }?;
 
-        if CONFIG.admin.sudo_users.contains(&user.get_id()) {
+        let config = CONFIG.load();
+        if config.admin.sudo_users.contains(&user.get_id()) {
             v.is_sudo.0.iter_mut().for_each(|v| v.val = true);
             v.is_support.0.iter_mut().for_each(|v| v.val = true);
         }
 
-        if CONFIG.admin.support_users.contains(&user.get_id()) {
+        if config.admin.support_users.contains(&user.get_id()) {
             v.is_support.0.iter_mut().for_each(|v| v.val = true);
         }
 
@@ -117,9 +142,10 @@ impl NamedBotPermissions {
     /// no sender
     pub async fn from_message(message: &Message) -> Result<Self> {
         let chat = message.get_chat();
-        let user = message
-            .get_from()
-            .ok_or_else(|| message.fail_err("Permission denied, user does not exist"))?;
+        let Some(user) = message.get_from() else {
+            let lang = get_chat_lang(chat.get_id()).await?;
+            return message.fail_code(UNKNOWN_USER, lang_fmt!(lang, "errunknownuser"));
+        };
         Self::from_chatuser(user, chat).await
     }
 }
@@ -133,6 +159,12 @@ impl From<ChatMemberAdministrator> for NamedBotPermissions {
             can_change_info: value.get_can_change_info(),
             can_promote_members: value.get_can_promote_members(),
             can_pin_messages: value.get_can_pin_messages().unwrap_or(false),
+            can_manage_topics: value.get_can_manage_topics().unwrap_or(false),
+            can_invite_users: value.get_can_invite_users(),
+            can_post_messages: value.get_can_post_messages().unwrap_or(false),
+            can_edit_messages: value.get_can_edit_messages().unwrap_or(false),
+            can_manage_video_chats: value.get_can_manage_video_chats(),
+            is_anonymous: value.get_is_anonymous(),
         }
         .into()
     }
@@ -142,13 +174,19 @@ impl From<ChatMember> for NamedBotPermissions {
     fn from(value: ChatMember) -> Self {
         match value {
             ChatMember::ChatMemberAdministrator(admin) => NamedBotPermissions::from(admin),
-            ChatMember::ChatMemberOwner(_) => BotPermissions {
+            ChatMember::ChatMemberOwner(owner) => BotPermissions {
                 can_manage_chat: true,
                 can_restrict_members: true,
                 can_delete_messages: true,
                 can_change_info: true,
                 can_promote_members: true,
                 can_pin_messages: true,
+                can_manage_topics: true,
+                can_invite_users: true,
+                can_post_messages: true,
+                can_edit_messages: true,
+                can_manage_video_chats: true,
+                is_anonymous: owner.get_is_anonymous(),
             }
             .into(),
             _ => BotPermissions {
@@ -158,6 +196,12 @@ impl From<ChatMember> for NamedBotPermissions {
                 can_change_info: false,
                 can_promote_members: false,
                 can_pin_messages: false,
+                can_manage_topics: false,
+                can_invite_users: false,
+                can_post_messages: false,
+                can_edit_messages: false,
+                can_manage_video_chats: false,
+                is_anonymous: false,
             }
             .into(),
         }
@@ -223,6 +267,16 @@ pub struct BotPermissions {
     pub can_change_info: bool,
     pub can_promote_members: bool,
     pub can_pin_messages: bool,
+    pub can_manage_topics: bool,
+    pub can_invite_users: bool,
+    /// Only in channels
+    pub can_post_messages: bool,
+    /// Only in channels
+    pub can_edit_messages: bool,
+    pub can_manage_video_chats: bool,
+    /// Not a permission as such, but anonymous admins can't be checked the same way as
+    /// other admins
+    pub is_anonymous: bool,
 }
 
 impl From<BotPermissions> for NamedBotPermissions {
@@ -243,6 +297,15 @@ impl From<BotPermissions> for NamedBotPermissions {
                 value.can_promote_members,
             ),
             can_pin_messages: NamedPermission::new("CanPinMessages", value.can_pin_messages),
+            can_manage_topics: NamedPermission::new("CanManageTopics", value.can_manage_topics),
+            can_invite_users: NamedPermission::new("CanInviteUsers", value.can_invite_users),
+            can_post_messages: NamedPermission::new("CanPostMessages", value.can_post_messages),
+            can_edit_messages: NamedPermission::new("CanEditMessages", value.can_edit_messages),
+            can_manage_video_chats: NamedPermission::new(
+                "CanManageVideoChats",
+                value.can_manage_video_chats,
+            ),
+            is_anonymous: NamedPermission::new("IsAnonymous", value.is_anonymous),
             is_sudo: NamedPermission::new("Sudo", false),
             is_support: NamedPermission::new("Support", false),
         }
@@ -258,6 +321,12 @@ impl From<NamedBotPermissions> for BotPermissions {
             can_change_info: value.can_change_info.is_granted(),
             can_promote_members: value.can_promote_members.is_granted(),
             can_pin_messages: value.can_pin_messages.is_granted(),
+            can_manage_topics: value.can_manage_topics.is_granted(),
+            can_invite_users: value.can_invite_users.is_granted(),
+            can_post_messages: value.can_post_messages.is_granted(),
+            can_edit_messages: value.can_edit_messages.is_granted(),
+            can_manage_video_chats: value.can_manage_video_chats.is_granted(),
+            is_anonymous: value.is_anonymous.is_granted(),
         }
     }
 }
@@ -334,7 +403,7 @@ where
 {
     let (out, mut rx) = mpsc::channel(8);
     let button = InlineKeyboardButtonBuilder::new("Push me to confirm admin".to_owned())
-        .set_callback_data(Uuid::new_v4().to_string())
+        .set_callback_data(callback_data(None))
         .build();
     let timer_out = out.clone();
 
@@ -351,13 +420,14 @@ where
             if let Some(MaybeInaccessibleMessage::Message(message)) = callback.get_message() {
                 let permission =
                     NamedBotPermissions::from_chatuser(user, message.get_chat()).await?;
-                if out.send(Some((permission, callback))).await.is_ok() {
-                    Ok(false)
+                let (reply, reply_rx) = oneshot::channel();
+                if out.send(Some((permission, callback, reply))).await.is_ok() {
+                    Ok((false, reply_rx.await.unwrap_or_default()))
                 } else {
-                    Ok(true)
+                    Ok((true, CallbackReply::default()))
                 }
             } else {
-                Ok(true)
+                Ok((true, CallbackReply::default()))
             }
         }
     });
@@ -368,14 +438,11 @@ where
 
     m.builder.text(lang_fmt!(lang, "provebutton"));
     sp.reply_fmt(m).await?;
-    while let Some(Some((perm, cb))) = rx.recv().await {
+    while let Some(Some((perm, cb, reply))) = rx.recv().await {
         let sudo = perm.is_sudo.is_granted();
         let p = func(perm);
         if p.is_granted() || sudo {
-            TG.client
-                .build_answer_callback_query(cb.get_id())
-                .build()
-                .await?;
+            reply.send(CallbackReply::default()).ok();
             if let Some(MaybeInaccessibleMessage::Message(message)) = cb.get_message() {
                 TG.client
                     .build_delete_message(message.get_chat().get_id(), message.get_message_id())
@@ -384,15 +451,14 @@ where
             }
             return Ok(());
         }
-        TG.client
-            .build_answer_callback_query(cb.get_id())
-            .text(&lang_fmt!(lang, "channeldenied"))
-            .show_alert(true)
-            .build()
-            .await?;
+        reply
+            .send(CallbackReply::alert(lang_fmt!(lang, "channeldenied")))
+            .ok();
     }
     rx.close();
-    sp.fail("Anonymous channel denied permission")
+    Err(sp
+        .fail_err_code(ANONYMOUS_DENIED, lang_fmt!(lang, "channeldenied"))
+        .notice())
 }
 
 async fn handle_perm_check<T, F>(
@@ -430,7 +496,12 @@ where
         is_group_or_die(chat).await?;
     }
     if !p.is_granted() && !sudo {
-        sp.fail(lang_fmt!(lang, "permdenied", p.get_name()))
+        Err(sp
+            .fail_err_code(
+                PERMISSION_DENIED,
+                lang_fmt!(lang, "permdenied", p.get_name()),
+            )
+            .notice())
     } else {
         Ok(())
     }
@@ -455,7 +526,7 @@ impl IsGroupAdmin for Message {
         } else if let Some(user) = self.get_from() {
             let lang = get_chat_lang(self.get_chat().get_id()).await?;
             let msg = lang_fmt!(lang, "lackingadminrights", user.name_humanreadable());
-            self.fail(msg)
+            Err(self.fail_err_code(PERMISSION_DENIED, msg).notice())
         } else {
             Err(BotError::Generic("not admin".to_owned()))
         }
@@ -499,7 +570,7 @@ impl IsAdmin for User {
         } else {
             let lang = get_chat_lang(chat.get_id()).await?;
             let msg = lang_fmt!(lang, "lackingadminrights", self.name_humanreadable());
-            chat.fail(msg)
+            Err(chat.fail_err_code(PERMISSION_DENIED, msg).notice())
         }
     }
 
@@ -537,7 +608,7 @@ impl<'a> IsAdmin for Option<&'a User> {
                     user.get_username()
                         .unwrap_or(user.get_id().to_string().as_str())
                 );
-                chat.fail(msg)
+                Err(chat.fail_err_code(PERMISSION_DENIED, msg).notice())
             }
         } else {
             Err(BotError::Generic("fail".to_owned()))
@@ -583,7 +654,7 @@ impl IsAdmin for i64 {
                 lang_fmt!(lang, "lackingadminrights", self)
             };
 
-            chat.fail(msg)
+            Err(chat.fail_err_code(PERMISSION_DENIED, msg).notice())
         }
     }
 
@@ -607,71 +678,69 @@ impl IsAdmin for i64 {
     }
 }
 
-/// Updates the admin cache with any changes in the bot's admin status
-pub async fn update_self_admin(update: &UpdateExt) -> Result<()> {
+/// Checks if a chat member is an admin or the owner
+fn is_admin_member(member: &ChatMember) -> bool {
+    matches!(
+        member,
+        ChatMember::ChatMemberAdministrator(_) | ChatMember::ChatMemberOwner(_)
+    )
+}
+
+/// Checks if a user is an admin of a chat known only by id, using the admin cache if the chat
+/// is cached and asking telegram otherwise
+pub async fn is_admin_in(user: i64, chat: i64) -> Result<bool> {
+    if let Some(chat) = get_chat(chat).await? {
+        return Ok(chat.is_user_admin(user).await?.is_some());
+    }
+    let member = TG.client.build_get_chat_member(chat, user).build().await?;
+    Ok(is_admin_member(&member))
+}
+
+/// Updates or evicts a member's entry in a chat's admin cache after their status changed.
+/// Chats without a cache are left alone since a cache holding one admin would make everyone
+/// else look like a member, the full list is fetched on the next lookup anyway
+async fn update_cached_member(chat: &Chat, old: &ChatMember, new: &ChatMember) -> Result<()> {
+    if !is_admin_member(old) && !is_admin_member(new) {
+        return Ok(());
+    }
+    let key = get_chat_admin_cache_key(chat.get_id());
+    if !REDIS.sq(|q| q.exists(&key)).await? {
+        return Ok(());
+    }
+    let user = new.get_user().get_id();
+    if is_admin_member(new) {
+        log::info!("admin {} updated in {}", user, chat.get_id());
+        let admin = new.to_redis()?;
+        REDIS.sq(|q| q.hset(&key, user, admin)).await?;
+    } else {
+        log::info!("admin {} demoted or left {}", user, chat.get_id());
+        REDIS.sq(|q| q.hdel(&key, user)).await?;
+    }
+    LOCAL_ADMINS.invalidate(&key).await?;
+    Ok(())
+}
+
+/// Updates the admin cache when the bot or anyone else is promoted, demoted, or leaves, so
+/// admin checks don't use stale rights until the cache expires
+pub async fn update_cached_admins(update: &UpdateExt) -> Result<()> {
     match update {
         UpdateExt::MyChatMember(member) => {
             let dialog = dialogs::Model::from_chat(member.get_chat()).await?;
             upsert_dialog(*DB, dialog.into_active_model()).await?;
-            let key = get_chat_admin_cache_key(member.get_chat().get_id());
-            member.get_chat().refresh_cached_admins().await?;
-            match member.get_new_chat_member() {
-                ChatMember::ChatMemberAdministrator(admin) => {
-                    log::info!("bot updated to admin");
-                    let user_id = admin.get_user().get_id();
-                    let admin = member.get_new_chat_member().to_redis()?;
-                    REDIS.sq(|q| q.hset(&key, user_id, admin)).await?;
-                }
-                ChatMember::ChatMemberOwner(owner) => {
-                    log::info!("Im soemhow the owner. What?");
-                    let user_id = owner.get_user().get_id();
-                    let admin = member.get_new_chat_member().to_redis()?;
-                    REDIS.sq(|q| q.hset(&key, user_id, admin)).await?;
-                }
-                mamber => {
-                    log::info!("Im not admin anymore ;(");
-                    let user_id = match mamber {
-                        ChatMember::ChatMemberMember(m) => m.get_user(),
-                        ChatMember::ChatMemberAdministrator(m) => m.get_user(),
-                        ChatMember::ChatMemberLeft(m) => m.get_user(),
-                        ChatMember::ChatMemberBanned(m) => m.get_user(),
-                        ChatMember::ChatMemberOwner(m) => m.get_user(),
-                        ChatMember::ChatMemberRestricted(m) => m.get_user(),
-                    }
-                    .get_id();
-                    REDIS.sq(|q| q.hdel(&key, user_id)).await?;
-                }
-            }
+            update_cached_member(
+                member.get_chat(),
+                member.get_old_chat_member(),
+                member.get_new_chat_member(),
+            )
+            .await?;
         }
         UpdateExt::ChatMember(member) => {
-            let key = get_chat_admin_cache_key(member.get_chat().get_id());
-            member.get_chat().refresh_cached_admins().await?;
-            match member.get_new_chat_member() {
-                ChatMember::ChatMemberAdministrator(admin) => {
-                    let user_id = admin.get_user().get_id();
-                    let admin = member.get_new_chat_member().to_redis()?;
-                    REDIS.sq(|q| q.hset(&key, user_id, admin)).await?;
-                }
-
-                ChatMember::ChatMemberOwner(admin) => {
-                    let user_id = admin.get_user().get_id();
-                    let admin = member.get_new_chat_member().to_redis()?;
-                    REDIS.sq(|q| q.hset(&key, user_id, admin)).await?;
-                }
-                member => {
-                    let user_id = match member {
-                        ChatMember::ChatMemberMember(m) => m.get_user(),
-                        ChatMember::ChatMemberAdministrator(m) => m.get_user(),
-                        ChatMember::ChatMemberLeft(m) => m.get_user(),
-                        ChatMember::ChatMemberBanned(m) => m.get_user(),
-                        ChatMember::ChatMemberOwner(m) => m.get_user(),
-                        ChatMember::ChatMemberRestricted(m) => m.get_user(),
-                    }
-                    .get_id();
-
-                    REDIS.sq(|q| q.hdel(&key, user_id)).await?;
-                }
-            }
+            update_cached_member(
+                member.get_chat(),
+                member.get_old_chat_member(),
+                member.get_new_chat_member(),
+            )
+            .await?;
         }
         _ => (),
     }
@@ -687,6 +756,14 @@ impl GetCachedAdmins for Chat {
 
     async fn is_user_admin(&self, user: i64) -> Result<Option<ChatMember>> {
         let key = get_chat_admin_cache_key(self.get_id());
+        if local::enabled() {
+            let admins = LOCAL_ADMINS
+                .get_or(&key, || async {
+                    Ok(Arc::new(self.get_cached_admins().await?))
+                })
+                .await?;
+            return Ok(admins.get(&user).cloned());
+        }
         let (exists, ke, admin): (bool, bool, Option<RedisStr>) = REDIS
             .pipe(|q| q.atomic().exists(&key).hexists(&key, user).hget(&key, user))
             .await?;
@@ -728,6 +805,7 @@ impl GetCachedAdmins for Chat {
         let key = get_chat_admin_cache_key(self.get_id());
         let cm = RedisStr::new(&mamber)?;
         REDIS.sq(|q| q.hset(&key, user, cm)).await?;
+        LOCAL_ADMINS.invalidate(&key).await?;
         Ok(())
     }
 
@@ -748,6 +826,7 @@ impl GetCachedAdmins for Chat {
             .await?;
         let key = get_chat_admin_cache_key(self.get_id());
         REDIS.sq(|q| q.hdel(&key, user)).await?;
+        LOCAL_ADMINS.invalidate(&key).await?;
         Ok(())
     }
 
@@ -791,9 +870,10 @@ impl GetCachedAdmins for Chat {
                         Ok::<(), BotError>(())
                     })?;
 
-                    Ok(q.expire(&key, Duration::try_hours(48).unwrap().num_seconds()))
+                    Ok(key.expire(q))
                 })
                 .await?;
+            LOCAL_ADMINS.invalidate(&key).await?;
             Ok((res, true))
         }
     }
@@ -802,14 +882,9 @@ impl GetCachedAdmins for Chat {
 impl Context {
     pub async fn force_refresh_cached_admins(&self) -> Result<()> {
         let chat = self.message()?.get_chat().get_id();
-        let lock = format!("frca:{}", chat);
+        let lock = keys::scope(chat).key_for(keys::ADMINS_REFRESH, TG.bot_id());
         if !REDIS.sq(|q| q.exists(&lock)).await? {
-            REDIS
-                .pipe(|q| {
-                    q.set(&lock, true)
-                        .expire(&lock, Duration::try_minutes(10).unwrap().num_seconds())
-                })
-                .await?;
+            REDIS.pipe(|q| lock.expire(q.set(&lock, true))).await?;
             let key = get_chat_admin_cache_key(chat);
             let admins = TG
                 .client()
@@ -827,13 +902,18 @@ impl Context {
                         Ok::<(), BotError>(())
                     })?;
 
-                    q.expire(&key, Duration::try_minutes(10).unwrap().num_seconds());
+                    // admins refreshed by hand only last until they can be refreshed again,
+                    // in case telegram returned them mid-change
+                    if let Some(ttl) = lock.ttl() {
+                        q.expire(&key, ttl);
+                    }
                     Ok(q)
                 })
                 .await?;
+            LOCAL_ADMINS.invalidate(&key).await?;
             Ok(())
         } else {
-            self.fail(lang_fmt!(self, "cachewait"))
+            self.fail_notice(lang_fmt!(self, "cachewait"))
         }
     }
 }
@@ -842,12 +922,39 @@ impl Context {
 pub(crate) async fn self_admin_or_die(chat: &Chat) -> Result<()> {
     if !is_self_admin(chat).await? {
         let lang = get_chat_lang(chat.get_id()).await?;
-        chat.fail(lang_fmt!(lang, "needtobeadmin"))
+        chat.fail_code(BOT_NOT_ADMIN, lang_fmt!(lang, "needtobeadmin"))
     } else {
         Ok(())
     }
 }
 
-fn get_chat_admin_cache_key(chat: i64) -> String {
-    format!("ca:{}", chat)
+/// Gets a chat named by id in a command's arguments, for commands copying content from or
+/// linking to another chat. Fails unless the bot knows the chat and the sender can change its info, checked
+/// through the other chat's admin cache the same way as the chat the command was sent in
+pub(crate) async fn other_chat_or_die(ctx: &Context, arg: &str) -> Result<Chat> {
+    let Ok(id) = arg.trim().parse::<i64>() else {
+        return ctx.fail(lang_fmt!(ctx, "invalidchatid"));
+    };
+    if id == ctx.try_get()?.chat.get_id() {
+        return ctx.fail(lang_fmt!(ctx, "othersamechat"));
+    }
+    let Some(chat) = get_chat(id).await? else {
+        return ctx.fail(lang_fmt!(ctx, "otherunknownchat"));
+    };
+    let Some(user) = ctx.message()?.get_from() else {
+        return ctx.fail(lang_fmt!(ctx, "othernotadmin", chat.name_humanreadable()));
+    };
+    let permissions = user.get_permissions(&chat).await?;
+    if !permissions.can_change_info {
+        return ctx.fail(lang_fmt!(ctx, "othernotadmin", chat.name_humanreadable()));
+    }
+    Ok(chat)
+}
+
+/// Chat admins kept in memory by their redis key, since admin checks run on most messages
+static LOCAL_ADMINS: LocalCache<Arc<HashMap<i64, ChatMember>>> = LocalCache::new("admins");
+
+/// Admin caches are kept per bot since each bot has its own rights in a chat
+fn get_chat_admin_cache_key(chat: i64) -> ScopedKey {
+    keys::scope(chat).key_for(keys::ADMINS, TG.bot_id())
 }
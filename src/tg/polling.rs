@@ -0,0 +1,110 @@
+//! Long polling that survives restarts. The offset of the next update is kept in redis for
+//! each bot and only moved past an update once it and every update before it were handled.
+//! Telegram keeps every update from the offset it was last asked for onward, so a restarted
+//! bot picks up exactly where the last run stopped instead of dropping or skipping updates.
+//! Updates from a batch the last run had already started are skipped by [`super::dedup`].
+//! Setting `webhook.drop_pending_updates` skips anything sent while the bot was offline instead
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use redis::AsyncCommands;
+use tokio::sync::Semaphore;
+
+use crate::statics::{CONFIG, REDIS};
+use crate::util::error::Result;
+
+use super::client::TgClient;
+
+/// Seconds telegram holds a getUpdates request open waiting for updates
+const POLL_TIMEOUT: i64 = 30;
+
+/// Time to wait before polling again after a failed request
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Most updates handled at once. Polling waits for a handler to finish once this many run
+const MAX_IN_FLIGHT: usize = 256;
+
+#[inline(always)]
+fn get_offset_key(bot: i64) -> String {
+    format!("updoff:{}", bot)
+}
+
+async fn load_offset(bot: i64) -> Result<i64> {
+    let key = get_offset_key(bot);
+    let offset: Option<i64> = REDIS.sq(|q| q.get(&key)).await?;
+    Ok(offset.unwrap_or(0))
+}
+
+async fn save_offset(bot: i64, offset: i64) -> Result<()> {
+    let key = get_offset_key(bot);
+    REDIS.sq(|q| q.set(&key, offset)).await?;
+    Ok(())
+}
+
+/// Gets updates for a bot forever, handling them concurrently without waiting for the rest
+/// of their batch. The saved offset is the oldest update still being handled, so a slow
+/// update is handled again after a restart but never holds up the updates after it
+pub(crate) async fn long_poll(bot: &'static TgClient, allowed: Option<Vec<String>>) -> Result<()> {
+    let drop_pending = CONFIG.load().webhook.drop_pending_updates;
+    bot.client
+        .build_delete_webhook()
+        .drop_pending_updates(drop_pending)
+        .build()
+        .await?;
+    let mut offset = if drop_pending {
+        0
+    } else {
+        load_offset(bot.bot_id()).await?
+    };
+
+    let permits = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+    let in_flight = Arc::new(Mutex::new(BTreeSet::new()));
+    loop {
+        let mut call = bot
+            .client
+            .build_get_updates()
+            .offset(offset)
+            .timeout(POLL_TIMEOUT);
+        if let Some(ref allowed) = allowed {
+            call = call.allowed_updates(allowed);
+        }
+        let updates = match call.build().await {
+            Ok(updates) => updates,
+            Err(err) => {
+                log::warn!("failed to get updates: {}", err);
+                tokio::time::sleep(RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let Some(last) = updates.iter().map(|u| u.get_update_id()).max() else {
+            continue;
+        };
+
+        for update in updates {
+            let id = update.get_update_id();
+            let permit = Arc::clone(&permits)
+                .acquire_owned()
+                .await
+                .expect("update semaphore is never closed");
+            in_flight.lock().unwrap().insert(id);
+            let handle = bot.spawn_update(update);
+            let in_flight = Arc::clone(&in_flight);
+            tokio::spawn(async move {
+                if let Err(err) = handle.await {
+                    log::warn!("update {} handler failed: {}", id, err);
+                }
+                in_flight.lock().unwrap().remove(&id);
+                drop(permit);
+            });
+        }
+
+        offset = last + 1;
+        let handled = in_flight.lock().unwrap().first().copied().unwrap_or(offset);
+        if let Err(err) = save_offset(bot.bot_id(), handled).await {
+            log::warn!("failed to save update offset: {}", err);
+            err.record_stats();
+        }
+    }
+}
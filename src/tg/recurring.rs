@@ -0,0 +1,446 @@
+//! Announcements posted in a chat on a recurring schedule. Each announcement owns a single
+//! scheduler job set for its next run, which posts the announcement and reschedules itself
+//! like birthday greetings do.
+//!
+//! Schedules are written as "daily 09:00", "weekly mon 09:00", "every 6h", or a five field
+//! cron expression like "30 9 * * 1-5", all in the chat's timezone. Nothing may run more
+//! than once an hour, so cron expressions need a single minute.
+
+use std::str::FromStr;
+
+use botapi::gen_types::EReplyMarkup;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Utc};
+use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
+
+use crate::persist::core::recurring_messages;
+use crate::statics::{DB, TG};
+use crate::util::duration::parse_duration;
+use crate::util::error::Result;
+use crate::util::string::should_ignore_chat;
+use crate::util::time::ChatTime;
+
+use super::markdown::MarkupBuilder;
+use super::scheduler::{cancel_job, get_chat_jobs, schedule_job, Job, JobKind};
+
+/// Most recurring announcements a chat can have
+pub const MAX_RECURRING: usize = 10;
+
+/// Days searched for the next run of a cron expression before giving up, enough to find
+/// February 29th
+const SEARCH_DAYS: i64 = 366 * 4 + 1;
+
+/// Why a schedule couldn't be parsed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScheduleError {
+    Invalid,
+    /// runs more than once an hour
+    TooFrequent,
+}
+
+/// A five field cron expression. Each field is a bitmask of the values it matches
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+            None if step > 1 => (range.parse().ok()?, max),
+            None => {
+                let value = range.parse().ok()?;
+                (value, value)
+            }
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Some(mask)
+}
+
+impl Cron {
+    /// Parses minute, hour, day of month, month, and day of week fields. Days of the week
+    /// start at 0 for Sunday, 7 is Sunday too
+    pub fn parse(fields: &[&str]) -> std::result::Result<Self, ScheduleError> {
+        let [minute, hour, day, month, weekday] = fields else {
+            return Err(ScheduleError::Invalid);
+        };
+        let parse = |field, min, max| parse_field(field, min, max).ok_or(ScheduleError::Invalid);
+        let minutes = parse(minute, 0, 59)?;
+        if minutes.count_ones() != 1 {
+            return Err(ScheduleError::TooFrequent);
+        }
+        let mut weekdays = parse(weekday, 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            minutes,
+            hours: parse(hour, 0, 23)?,
+            days: parse(day, 1, 31)?,
+            months: parse(month, 1, 12)?,
+            weekdays,
+            any_day: *day == "*",
+            any_weekday: *weekday == "*",
+        })
+    }
+
+    /// Runs every day at a time
+    fn at(time: NaiveTime, weekday: Option<chrono::Weekday>) -> Self {
+        Self {
+            minutes: 1 << time.minute(),
+            hours: 1 << time.hour(),
+            days: u64::MAX,
+            months: u64::MAX,
+            weekdays: weekday.map_or(u64::MAX, |day| 1 << day.num_days_from_sunday()),
+            any_day: true,
+            any_weekday: weekday.is_none(),
+        }
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        if self.months & (1 << date.month()) == 0 {
+            return false;
+        }
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        // like cron, a restricted day of month and day of week match either one
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    /// The first local time after `after` matching this expression
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = after.date();
+        for offset in 0..SEARCH_DAYS {
+            let date = start.checked_add_signed(Duration::try_days(offset)?)?;
+            if !self.matches_day(date) {
+                continue;
+            }
+            for hour in (0..24).filter(|h| self.hours & (1 << h) != 0) {
+                for minute in (0..60).filter(|m| self.minutes & (1 << m) != 0) {
+                    let time = date.and_hms_opt(hour, minute, 0)?;
+                    if time > after {
+                        return Some(time);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// When a recurring announcement runs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    Cron(Cron),
+    /// at a fixed interval from when the announcement was added
+    Every(Duration),
+}
+
+impl Schedule {
+    /// Parses the schedule at the start of `text`, returning it along with the rest of the
+    /// text
+    pub fn parse_prefix(text: &str) -> std::result::Result<(Self, &str), ScheduleError> {
+        let text = text.trim_start();
+        let words = text.split_whitespace().take(6).collect::<Vec<&str>>();
+        let (schedule, used) = match words.first().map(|w| w.to_lowercase()).as_deref() {
+            Some("daily") => {
+                let time = words.get(1).and_then(|t| parse_time(t));
+                (Cron::at(time.ok_or(ScheduleError::Invalid)?, None), 2)
+            }
+            Some("weekly") => {
+                let day = words.get(1).and_then(|d| chrono::Weekday::from_str(d).ok());
+                let time = words.get(2).and_then(|t| parse_time(t));
+                let (Some(day), Some(time)) = (day, time) else {
+                    return Err(ScheduleError::Invalid);
+                };
+                (Cron::at(time, Some(day)), 3)
+            }
+            Some("every") => {
+                let every = words
+                    .get(1)
+                    .and_then(|d| parse_duration(d).ok())
+                    .ok_or(ScheduleError::Invalid)?;
+                if every < Duration::try_hours(1).unwrap() {
+                    return Err(ScheduleError::TooFrequent);
+                }
+                return Ok((Self::Every(every), skip_words(text, 2)));
+            }
+            _ => (
+                Cron::parse(words.get(..5).ok_or(ScheduleError::Invalid)?)?,
+                5,
+            ),
+        };
+        Ok((Self::Cron(schedule), skip_words(text, used)))
+    }
+
+    /// The next time to post after `now`. `since` is when the announcement was added
+    pub fn next_run(
+        &self,
+        now: DateTime<Utc>,
+        since: DateTime<Utc>,
+        local: &ChatTime,
+    ) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Cron(cron) => {
+                let mut after = local.local(&now).naive_local();
+                // times moved by dst changes can land before now, look past them
+                for _ in 0..3 {
+                    let next = cron.next_after(after)?;
+                    match local.to_utc(next) {
+                        Some(time) if time > now => return Some(time),
+                        _ => after = next,
+                    }
+                }
+                None
+            }
+            Self::Every(every) => {
+                let every = every.num_seconds();
+                let elapsed = (now - since).num_seconds().max(0);
+                let runs = elapsed / every + 1;
+                since.checked_add_signed(Duration::try_seconds(runs.checked_mul(every)?)?)
+            }
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = ScheduleError;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match Self::parse_prefix(s)? {
+            (schedule, "") => Ok(schedule),
+            _ => Err(ScheduleError::Invalid),
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M").ok()
+}
+
+/// Drops the first `count` words from `text`
+fn skip_words(text: &str, count: usize) -> &str {
+    let mut rest = text.trim_start();
+    for _ in 0..count {
+        rest = rest
+            .find(char::is_whitespace)
+            .map_or("", |idx| &rest[idx..])
+            .trim_start();
+    }
+    rest
+}
+
+/// Gets a chat's recurring announcements, oldest first
+pub async fn get_recurring(chat: i64) -> Result<Vec<recurring_messages::Model>> {
+    let res = recurring_messages::Entity::find()
+        .filter(recurring_messages::Column::ChatId.eq(chat))
+        .order_by_asc(recurring_messages::Column::Id)
+        .all(*DB)
+        .await?;
+    Ok(res)
+}
+
+/// Adds a recurring announcement to a chat and schedules its first run. The schedule
+/// should already have been checked with [`Schedule::from_str`]
+pub async fn add_recurring(
+    chat: i64,
+    author: i64,
+    schedule: &str,
+    text: String,
+) -> Result<recurring_messages::Model> {
+    let model = recurring_messages::ActiveModel {
+        id: NotSet,
+        chat_id: Set(chat),
+        schedule: Set(schedule.to_owned()),
+        text: Set(text),
+        author: Set(author),
+        created: Set(Utc::now()),
+    };
+    let model = recurring_messages::Entity::insert(model)
+        .exec_with_returning(*DB)
+        .await?;
+    schedule_recurring(&model).await?;
+    Ok(model)
+}
+
+async fn cancel_recurring_job(chat: i64, id: i64) -> Result<()> {
+    for job in get_chat_jobs(chat).await? {
+        if let JobKind::Recurring { id: job_id } = job.kind {
+            if job_id == id {
+                cancel_job(chat, &job.id).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Removes a recurring announcement and its pending run. Returns false if the chat has no
+/// announcement with that id
+pub async fn remove_recurring(chat: i64, id: i64) -> Result<bool> {
+    let res = recurring_messages::Entity::delete_many()
+        .filter(
+            recurring_messages::Column::ChatId
+                .eq(chat)
+                .and(recurring_messages::Column::Id.eq(id)),
+        )
+        .exec(*DB)
+        .await?;
+    cancel_recurring_job(chat, id).await?;
+    Ok(res.rows_affected > 0)
+}
+
+/// Replaces the announcement's pending job with one at its next run. Returns when that is,
+/// None if the schedule never runs again
+pub async fn schedule_recurring(
+    model: &recurring_messages::Model,
+) -> Result<Option<DateTime<Utc>>> {
+    let chat = model.chat_id;
+    cancel_recurring_job(chat, model.id).await?;
+    let Ok(schedule) = Schedule::from_str(&model.schedule) else {
+        log::warn!("invalid recurring schedule {} in {}", model.schedule, chat);
+        return Ok(None);
+    };
+    let local = ChatTime::get(chat).await?;
+    let Some(run_at) = schedule.next_run(Utc::now(), model.created, &local) else {
+        return Ok(None);
+    };
+    schedule_job(&Job::new(chat, run_at, JobKind::Recurring { id: model.id })).await?;
+    Ok(Some(run_at))
+}
+
+/// Reschedules every announcement in a chat, after its timezone changed
+pub async fn reschedule_chat(chat: i64) -> Result<()> {
+    for model in get_recurring(chat).await? {
+        schedule_recurring(&model).await?;
+    }
+    Ok(())
+}
+
+/// Posts an announcement and schedules its next run. Run by the scheduler
+pub(crate) async fn run_recurring(chat: i64, id: i64) -> Result<()> {
+    let Some(model) = recurring_messages::Entity::find_by_id(id).one(*DB).await? else {
+        return Ok(());
+    };
+    if model.chat_id != chat {
+        // the chat was upgraded to a supergroup since this run was scheduled
+        schedule_recurring(&model).await?;
+        return Ok(());
+    }
+
+    // reschedule first so a failed post doesn't end the announcement for good
+    schedule_recurring(&model).await?;
+    if should_ignore_chat(chat).await? {
+        return Ok(());
+    }
+
+    let (text, entities, buttons) = MarkupBuilder::new(None)
+        .set_text(model.text)
+        .filling(false)
+        .header(false)
+        .build_murkdown_nofail()
+        .await;
+    let mut message = TG
+        .client
+        .build_send_message(chat, &text)
+        .entities(&entities);
+    let markup =
+        (!buttons.get().is_empty()).then(|| EReplyMarkup::InlineKeyboardMarkup(buttons.build()));
+    if let Some(ref markup) = markup {
+        message = message.reply_markup(markup);
+    }
+    message.build().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn time(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn parse_schedules() {
+        let (schedule, rest) = Schedule::parse_prefix("daily 09:00 good morning").unwrap();
+        assert_eq!(
+            schedule,
+            Schedule::Cron(Cron::parse(&["0", "9", "*", "*", "*"]).unwrap())
+        );
+        assert_eq!(rest, "good morning");
+        let (_, rest) = Schedule::parse_prefix("30 9 * * 1-5 standup").unwrap();
+        assert_eq!(rest, "standup");
+        let (schedule, _) = Schedule::parse_prefix("every 6h hi").unwrap();
+        assert_eq!(schedule, Schedule::Every(Duration::try_hours(6).unwrap()));
+        assert_eq!(
+            Schedule::parse_prefix("every 5m hi"),
+            Err(ScheduleError::TooFrequent)
+        );
+        assert_eq!(
+            Schedule::parse_prefix("*/5 * * * * hi"),
+            Err(ScheduleError::TooFrequent)
+        );
+        assert_eq!(
+            Schedule::parse_prefix("61 * * * * hi"),
+            Err(ScheduleError::Invalid)
+        );
+        assert!(Schedule::from_str("weekly mon 09:00").is_ok());
+        assert!(Schedule::from_str("weekly mon 09:00 extra").is_err());
+    }
+
+    #[test]
+    fn cron_next() {
+        let weekdays = Cron::parse(&["30", "9", "*", "*", "1-5"]).unwrap();
+        // 2024-05-03 is a friday
+        assert_eq!(
+            weekdays.next_after(time("2024-05-03 09:30")),
+            Some(time("2024-05-06 09:30"))
+        );
+        assert_eq!(
+            weekdays.next_after(time("2024-05-03 08:00")),
+            Some(time("2024-05-03 09:30"))
+        );
+        let leap = Cron::parse(&["0", "0", "29", "2", "*"]).unwrap();
+        assert_eq!(
+            leap.next_after(time("2024-03-01 00:00")),
+            Some(time("2028-02-29 00:00"))
+        );
+        let sunday = Cron::parse(&["0", "12", "*", "*", "7"]).unwrap();
+        assert_eq!(
+            sunday.next_after(time("2024-05-03 00:00")),
+            Some(time("2024-05-05 12:00"))
+        );
+    }
+
+    #[test]
+    fn every_next() {
+        let schedule = Schedule::Every(Duration::try_hours(6).unwrap());
+        let local = ChatTime::new(chrono_tz::Tz::UTC);
+        let since = Utc::now() - Duration::try_hours(7).unwrap();
+        let next = schedule.next_run(Utc::now(), since, &local).unwrap();
+        assert_eq!(next, since + Duration::try_hours(12).unwrap());
+    }
+}
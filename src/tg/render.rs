@@ -0,0 +1,207 @@
+//! Image rendering for commands like /q. Drawing text with fonts and emoji well is a lot of
+//! work, so images are rendered by an external service set in the config rather than in
+//! process, the same way spam can be scored by an external classifier.
+//!
+//! Quotes are POSTed to the renderer as json shaped like [`QuoteRequest`]: a list of
+//! messages, each with the sender's name and id, their avatar as base64 if they have one,
+//! and the text with its entities in telegram's format. The response body must be a webp
+//! image at most [`STICKER_SIZE`] pixels wide and tall, which is sent as a sticker.
+//!
+//! Rendering is off if nothing is configured. The renderer is read from the config for
+//! every quote, so it can be changed with /reloadconfig. Each user can only quote once
+//! every `render.cooldown` seconds per chat
+
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use botapi::gen_types::{Message, MessageEntity, MessageOrigin, User};
+use lazy_static::lazy_static;
+use redis::AsyncCommands;
+use serde::Serialize;
+
+use crate::statics::{CONFIG, REDIS, TG};
+use crate::util::error::{BotError, Result};
+
+use super::admin_helpers::get_file;
+
+/// Longest side of a sticker in pixels
+pub const STICKER_SIZE: u32 = 512;
+
+/// Smallest avatar size worth sending, telegram's smallest is usually 160px
+const AVATAR_SIZE: i64 = 160;
+
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+/// One message in a quote
+#[derive(Serialize, Debug, Clone)]
+pub struct QuoteMessage {
+    /// name of whoever sent the message, or of the chat it was sent as
+    pub name: String,
+
+    /// id of the sender, renderers use it to pick a color for the name
+    pub user_id: i64,
+
+    /// base64 encoded jpeg of the sender's avatar
+    pub avatar: Option<String>,
+    pub text: String,
+    pub entities: Vec<MessageEntity>,
+}
+
+/// Everything a renderer is sent to draw a quote
+#[derive(Serialize, Debug)]
+pub struct QuoteRequest<'a> {
+    /// image format the response should be in, always webp
+    pub format: &'static str,
+
+    /// largest width and height of the image
+    pub size: u32,
+    pub messages: &'a [QuoteMessage],
+}
+
+/// Whether a renderer is configured
+pub fn quotes_enabled() -> bool {
+    CONFIG.load().render.quote_renderer.is_some()
+}
+
+/// Draws messages as a quote with the configured renderer, returning a webp image
+pub async fn render_quote(messages: &[QuoteMessage]) -> Result<Vec<u8>> {
+    let config = CONFIG.load();
+    let Some(ref url) = config.render.quote_renderer else {
+        return Err(BotError::Generic("no quote renderer configured".to_owned()));
+    };
+    let body = serde_json::to_vec(&QuoteRequest {
+        format: "webp",
+        size: STICKER_SIZE,
+        messages,
+    })?;
+    let res = CLIENT
+        .post(url)
+        .timeout(Duration::from_millis(config.render.timeout))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    Ok(res.to_vec())
+}
+
+#[inline(always)]
+fn get_cooldown_key(chat: i64, user: i64) -> String {
+    format!("qcd:{}:{}", chat, user)
+}
+
+/// Starts a user's cooldown for quoting in a chat. Returns false if they quoted there too
+/// recently and have to wait. Always true if the cooldown is off
+pub async fn claim_quote(chat: i64, user: i64) -> Result<bool> {
+    let cooldown = CONFIG.load().render.cooldown;
+    if cooldown == 0 {
+        return Ok(true);
+    }
+    REDIS
+        .set_nx_ex(get_cooldown_key(chat, user), cooldown as i64)
+        .await
+}
+
+/// Ends a user's cooldown early, for quotes that failed to render
+pub async fn release_quote(chat: i64, user: i64) -> Result<()> {
+    REDIS.sq(|q| q.del(get_cooldown_key(chat, user))).await?;
+    Ok(())
+}
+
+fn user_name(user: &User) -> String {
+    match user.get_last_name() {
+        Some(last) => format!("{} {}", user.get_first_name(), last),
+        None => user.get_first_name().to_owned(),
+    }
+}
+
+/// Downloads the smallest of a user's current avatars that's still big enough to draw
+async fn get_avatar(user: i64) -> Result<Option<String>> {
+    let photos = TG
+        .client
+        .build_get_user_profile_photos(user)
+        .limit(1)
+        .build()
+        .await?;
+    let Some(photo) = photos.get_photos().first().and_then(|sizes| {
+        sizes
+            .iter()
+            .filter(|size| size.get_width() >= AVATAR_SIZE)
+            .min_by_key(|size| size.get_width())
+            .or_else(|| sizes.last())
+    }) else {
+        return Ok(None);
+    };
+    let file = TG
+        .client
+        .build_get_file(photo.get_file_id())
+        .build()
+        .await?;
+    let Some(path) = file.get_file_path() else {
+        return Ok(None);
+    };
+    let bytes = get_file(path).await?;
+    Ok(Some(STANDARD.encode(bytes)))
+}
+
+/// Builds the quote of a message, using the original sender of forwarded messages. None if
+/// the message has no text or caption to quote
+pub async fn quote_message(message: &Message) -> Result<Option<QuoteMessage>> {
+    let (text, entities) = match message.get_text() {
+        Some(text) => (text, message.get_entities()),
+        None => match message.get_caption() {
+            Some(caption) => (caption, message.get_caption_entities()),
+            None => return Ok(None),
+        },
+    };
+
+    let (name, user_id, avatar_user) = match message.get_forward_origin() {
+        Some(MessageOrigin::MessageOriginUser(origin)) => {
+            let user = origin.get_sender_user();
+            (user_name(user), user.get_id(), Some(user.get_id()))
+        }
+        Some(MessageOrigin::MessageOriginHiddenUser(origin)) => {
+            (origin.get_sender_user_name().to_owned(), 0, None)
+        }
+        Some(MessageOrigin::MessageOriginChat(origin)) => {
+            let chat = origin.get_sender_chat();
+            let name = chat.get_title().unwrap_or_default().to_owned();
+            (name, chat.get_id(), None)
+        }
+        Some(MessageOrigin::MessageOriginChannel(origin)) => {
+            let chat = origin.get_chat();
+            let name = chat.get_title().unwrap_or_default().to_owned();
+            (name, chat.get_id(), None)
+        }
+        None => match (message.get_sender_chat(), message.get_from()) {
+            (Some(chat), _) => {
+                let name = chat.get_title().unwrap_or_default().to_owned();
+                (name, chat.get_id(), None)
+            }
+            (None, Some(user)) => (user_name(user), user.get_id(), Some(user.get_id())),
+            (None, None) => return Ok(None),
+        },
+    };
+
+    // a quote without an avatar is better than no quote
+    let avatar = match avatar_user {
+        Some(user) => get_avatar(user).await.unwrap_or_else(|err| {
+            log::warn!("failed to get avatar for quote: {}", err);
+            None
+        }),
+        None => None,
+    };
+
+    Ok(Some(QuoteMessage {
+        name,
+        user_id,
+        avatar,
+        text: text.to_owned(),
+        entities: entities.map(|e| e.to_owned()).unwrap_or_default(),
+    }))
+}
@@ -0,0 +1,159 @@
+//! Fine grained restrictions. Unlike a mute, which takes away every permission, /restrict
+//! only takes away one kind of message, like media or polls, and leaves the user's other
+//! permissions as they were. /restrictions shows what a user is currently allowed to send
+
+use botapi::gen_types::{Chat, ChatMember, ChatPermissions, ChatPermissionsBuilder};
+use macros::lang_fmt;
+
+use crate::statics::TG;
+use crate::util::error::Result;
+use crate::util::string::Lang;
+
+/// A kind of message /restrict can take away
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Restriction {
+    /// Photos, videos, files, voice messages, stickers and gifs
+    Media,
+    Polls,
+    /// Link previews, telegram has no permission for the links themselves
+    Links,
+    /// Everything, the same as a mute
+    All,
+}
+
+impl Restriction {
+    pub fn from_arg(arg: &str) -> Option<Self> {
+        match arg.to_lowercase().as_str() {
+            "media" => Some(Self::Media),
+            "polls" => Some(Self::Polls),
+            "links" => Some(Self::Links),
+            "all" => Some(Self::All),
+            _ => None,
+        }
+    }
+
+    /// Takes away this restriction's permissions, leaving the rest as they are
+    pub fn apply(self, builder: ChatPermissionsBuilder) -> ChatPermissionsBuilder {
+        match self {
+            Self::Media => builder
+                .set_can_send_audios(false)
+                .set_can_send_documents(false)
+                .set_can_send_photos(false)
+                .set_can_send_videos(false)
+                .set_can_send_video_notes(false)
+                .set_can_send_voice_notes(false)
+                .set_can_send_other_messages(false),
+            Self::Polls => builder.set_can_send_polls(false),
+            Self::Links => builder.set_can_add_web_page_previews(false),
+            Self::All => Self::Links
+                .apply(Self::Polls.apply(Self::Media.apply(builder)))
+                .set_can_send_messages(false),
+        }
+    }
+}
+
+/// Gets a builder holding the permissions a chat member has now. Members who aren't
+/// restricted start with every permission
+pub fn member_permissions(member: &ChatMember) -> ChatPermissionsBuilder {
+    let builder = ChatPermissionsBuilder::new();
+    match member {
+        ChatMember::ChatMemberRestricted(m) => builder
+            .set_can_send_messages(m.get_can_send_messages())
+            .set_can_send_audios(m.get_can_send_audios())
+            .set_can_send_documents(m.get_can_send_documents())
+            .set_can_send_photos(m.get_can_send_photos())
+            .set_can_send_videos(m.get_can_send_videos())
+            .set_can_send_video_notes(m.get_can_send_video_notes())
+            .set_can_send_voice_notes(m.get_can_send_voice_notes())
+            .set_can_send_polls(m.get_can_send_polls())
+            .set_can_send_other_messages(m.get_can_send_other_messages())
+            .set_can_add_web_page_previews(m.get_can_add_web_page_previews()),
+        _ => builder
+            .set_can_send_messages(true)
+            .set_can_send_audios(true)
+            .set_can_send_documents(true)
+            .set_can_send_photos(true)
+            .set_can_send_videos(true)
+            .set_can_send_video_notes(true)
+            .set_can_send_voice_notes(true)
+            .set_can_send_polls(true)
+            .set_can_send_other_messages(true)
+            .set_can_add_web_page_previews(true),
+    }
+}
+
+/// Gets a user's current permissions in a chat with the given restrictions added
+pub async fn restricted_permissions(
+    chat: &Chat,
+    user: i64,
+    restrictions: &[Restriction],
+) -> Result<ChatPermissions> {
+    let member = TG
+        .client
+        .build_get_chat_member(chat.get_id(), user)
+        .build()
+        .await?;
+    let builder = restrictions
+        .iter()
+        .fold(member_permissions(&member), |builder, restriction| {
+            restriction.apply(builder)
+        });
+    Ok(builder.build())
+}
+
+/// Describes what a set of permissions allows, one permission per line
+pub fn describe_permissions(lang: &Lang, permissions: &ChatPermissions) -> String {
+    let lines = [
+        (
+            lang_fmt!(lang, "permmessages"),
+            permissions.get_can_send_messages(),
+        ),
+        (
+            lang_fmt!(lang, "permphotos"),
+            permissions.get_can_send_photos(),
+        ),
+        (
+            lang_fmt!(lang, "permvideos"),
+            permissions.get_can_send_videos(),
+        ),
+        (
+            lang_fmt!(lang, "permaudios"),
+            permissions.get_can_send_audios(),
+        ),
+        (
+            lang_fmt!(lang, "permdocuments"),
+            permissions.get_can_send_documents(),
+        ),
+        (
+            lang_fmt!(lang, "permvoicenotes"),
+            permissions.get_can_send_voice_notes(),
+        ),
+        (
+            lang_fmt!(lang, "permvideonotes"),
+            permissions.get_can_send_video_notes(),
+        ),
+        (
+            lang_fmt!(lang, "permother"),
+            permissions.get_can_send_other_messages(),
+        ),
+        (
+            lang_fmt!(lang, "permpolls"),
+            permissions.get_can_send_polls(),
+        ),
+        (
+            lang_fmt!(lang, "permpreviews"),
+            permissions.get_can_add_web_page_previews(),
+        ),
+    ];
+    lines
+        .into_iter()
+        .map(|(name, allowed)| {
+            if allowed.unwrap_or(false) {
+                lang_fmt!(lang, "permallowed", name)
+            } else {
+                lang_fmt!(lang, "permforbidden", name)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
@@ -0,0 +1,752 @@
+//! Persistent scheduler for deferred work.
+//!
+//! Jobs are stored in redis, indexed by a sorted set keyed on their execution time, and
+//! polled by a single background task started alongside the update loop. Claiming a job
+//! moves it to a second sorted set scored by the end of its lease, so only one of the bot
+//! instances sharing the same redis runs it. Jobs still there after their lease ran out
+//! belonged to a worker that died and are put back in the schedule.
+//!
+//! Jobs queued by the bot itself, like lifting bans, deleting messages and broadcasts, are
+//! retried with exponential backoff until they run out of attempts, then dropped after
+//! [`Job::give_up`] runs. Each process runs at most `jobs.workers` jobs at once. These jobs
+//! may run more than once if a worker dies partway through, so they should be safe to
+//! repeat.
+//!
+//! The main job type is a deferred command: a settings command with a trailing
+//! time specification ("/lock links at 22:00", "/warnlimit 5 from friday") is stored and
+//! replayed through the normal update pipeline when the time is reached, so the exact same
+//! code paths (and permission checks) as the interactive command are used.
+
+use std::ops::DerefMut;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use botapi::gen_types::{Chat, Message, UpdateExt};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use macros::lang_fmt;
+use redis::{AsyncCommands, Script};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::persist::redis::{RedisStr, ToRedisStr};
+use crate::statics::{CONFIG, ME, REDIS, TG};
+use crate::util::duration::parse_duration;
+use crate::util::error::Result;
+use crate::util::string::{get_chat_lang, Lang, Speak};
+use crate::util::time::ChatTime;
+
+use super::admin_helpers::expire_ban;
+use super::birthdays::run_birthdays;
+use super::boosts::run_refresh_boosts;
+use super::bots::{get_bot, main_bot, with_bot};
+use super::broadcast::{broadcast_failed, send_broadcast, BroadcastSend};
+use super::command::{Cmd, Context};
+use super::giveaways::run_draw_giveaway;
+use super::greetings::run_welcome_mute_kick;
+use super::log_channel::send_log;
+use super::markdown::Escape;
+use super::permissions::IsGroupAdmin;
+use super::recurring::run_recurring;
+use super::user::Username;
+use super::video_chats::run_video_chat_reminder;
+use super::voteban::close_vote;
+use super::zombies::{release_scan, run_zombie_scan, SCAN_LOCK_SECONDS};
+
+/// sorted set of pending job ids scored by unix execution time
+const SCHEDULE_KEY: &str = "sched:q";
+
+/// sorted set of claimed job ids scored by the unix time their lease ends
+const RUNNING_KEY: &str = "sched:run";
+
+/// maximum number of jobs claimed per poll
+const BATCH_SIZE: usize = 64;
+
+/// interval between polls of the schedule
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// seconds a claimed job is leased for before its worker sets its own lease
+const CLAIM_LEASE: i64 = 60;
+
+/// seconds before the first retry, doubling with each attempt
+const BACKOFF_BASE: i64 = 5;
+
+/// longest wait between retries in seconds
+const BACKOFF_MAX: i64 = 60 * 60;
+
+/// number of jobs this process is running right now
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Commands that change chat settings and may be deferred with a time specification
+const SCHEDULABLE: &[&str] = &[
+    "lock",
+    "unlock",
+    "lockaction",
+    "warnlimit",
+    "warntime",
+    "warnmode",
+    "captcha",
+    "captchamode",
+    "captchakick",
+    "welcome",
+];
+
+/// Keywords separating a command from its time specification
+const KEYWORDS: &[&str] = &["at", "from", "in"];
+
+#[inline(always)]
+fn get_job_key(id: &Uuid) -> String {
+    format!("sched:job:{}", id)
+}
+
+#[inline(always)]
+fn get_chat_jobs_key(chat: i64) -> String {
+    format!("sched:chat:{}", chat)
+}
+
+/// A command to be replayed as if it was sent by the original user at a later time
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeferredCommand {
+    /// the original message, used to construct the replayed update
+    pub message: Message,
+
+    /// command text with the time specification removed
+    pub command: String,
+}
+
+/// The work to perform when a job is triggered
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum JobKind {
+    DeferredCommand(DeferredCommand),
+    /// post the day's birthday greetings in the chat
+    Birthdays,
+    /// kick a member who joined under strict welcome mute unless they pushed the button
+    WelcomeMuteKick {
+        user: i64,
+    },
+    /// delete one of the chat's messages
+    DeleteMessage {
+        message: i64,
+    },
+    /// close a /voteban that didn't pass in time
+    CloseVoteBan {
+        poll: String,
+    },
+    /// post a recurring announcement
+    Recurring {
+        id: i64,
+    },
+    /// remind the chat of a video chat set with /vcschedule
+    VideoChatReminder {
+        note: Option<String>,
+    },
+    /// check which of the chat's recorded boosts are still active
+    RefreshBoosts,
+    /// draw the winners of a /giveaway
+    DrawGiveaway {
+        id: i64,
+    },
+    /// lift a ban telegram can't lift by itself
+    Unban {
+        user: i64,
+    },
+    /// lift a temporary ban on a channel posting in the chat
+    UnbanSenderChat {
+        sender: i64,
+    },
+    /// send a broadcast to the chat
+    Broadcast(BroadcastSend),
+    /// look for deleted accounts in the chat, editing `status` as the scan goes
+    ZombieScan {
+        clean: bool,
+        dry_run: bool,
+        status: Message,
+        lang: Lang,
+    },
+}
+
+/// A single scheduled job
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Job {
+    pub id: Uuid,
+    pub chat: i64,
+    pub run_at: DateTime<Utc>,
+    pub kind: JobKind,
+
+    /// user id of the bot that scheduled the job, None for jobs from before clone bots
+    #[serde(default)]
+    pub bot: Option<i64>,
+
+    /// number of times this job has failed
+    #[serde(default)]
+    pub attempts: u32,
+}
+
+impl Job {
+    pub fn new(chat: i64, run_at: DateTime<Utc>, kind: JobKind) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            chat,
+            run_at,
+            kind,
+            bot: ME.get().map(|me| me.get_id()),
+            attempts: 0,
+        }
+    }
+
+    /// Human readable description of this job for listings and logs
+    pub fn describe(&self) -> &'_ str {
+        match self.kind {
+            JobKind::DeferredCommand(ref cmd) => &cmd.command,
+            JobKind::Birthdays => "birthday greetings",
+            JobKind::WelcomeMuteKick { .. } => "kick unverified member",
+            JobKind::DeleteMessage { .. } => "delete message",
+            JobKind::CloseVoteBan { .. } => "close vote ban",
+            JobKind::Recurring { .. } => "recurring announcement",
+            JobKind::VideoChatReminder { .. } => "video chat reminder",
+            JobKind::RefreshBoosts => "refresh boosts",
+            JobKind::DrawGiveaway { .. } => "draw giveaway",
+            JobKind::Unban { .. } => "unban",
+            JobKind::UnbanSenderChat { .. } => "unban channel",
+            JobKind::Broadcast(_) => "broadcast",
+            JobKind::ZombieScan { .. } => "zombie scan",
+        }
+    }
+
+    /// Seconds a worker may spend on this job before it's assumed to have died
+    fn lease(&self) -> i64 {
+        match self.kind {
+            JobKind::ZombieScan { .. } => SCAN_LOCK_SECONDS,
+            _ => CLAIM_LEASE,
+        }
+    }
+
+    /// Times this job is tried before it is dropped. Jobs posting in the chat run once,
+    /// since a retry after a partial failure could post twice
+    fn max_attempts(&self) -> u32 {
+        match self.kind {
+            JobKind::DeleteMessage { .. }
+            | JobKind::Unban { .. }
+            | JobKind::UnbanSenderChat { .. }
+            | JobKind::Broadcast(_)
+            | JobKind::ZombieScan { .. } => CONFIG.load().jobs.max_attempts,
+            _ => 1,
+        }
+    }
+
+    /// Called once when a job fails for the last time
+    async fn give_up(&self) -> Result<()> {
+        match self.kind {
+            JobKind::Broadcast(ref send) => broadcast_failed(send).await,
+            JobKind::ZombieScan { .. } => release_scan(self.chat).await,
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Seconds to wait before retrying a job that failed `attempts` times
+fn backoff(attempts: u32) -> i64 {
+    BACKOFF_BASE
+        .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+        .min(BACKOFF_MAX)
+}
+
+/// Persist a job to be run at its scheduled time
+pub async fn schedule_job(job: &Job) -> Result<()> {
+    let key = get_job_key(&job.id);
+    let chat_key = get_chat_jobs_key(job.chat);
+    let id = job.id.to_string();
+    REDIS
+        .try_pipe(|p| {
+            p.atomic();
+            Ok(p.set(&key, job.to_redis()?)
+                .zadd(SCHEDULE_KEY, &id, job.run_at.timestamp())
+                .sadd(&chat_key, &id))
+        })
+        .await?;
+    Ok(())
+}
+
+/// Deletes a message after a delay
+pub async fn delete_message_later(chat: i64, message: i64, after: Duration) -> Result<()> {
+    let kind = JobKind::DeleteMessage { message };
+    schedule_job(&Job::new(chat, Utc::now() + after, kind)).await
+}
+
+/// Remove a pending job belonging to a chat. Returns false if no such job exists
+pub async fn cancel_job(chat: i64, id: &Uuid) -> Result<bool> {
+    let chat_key = get_chat_jobs_key(chat);
+    let key = get_job_key(id);
+    let sid = id.to_string();
+    let (owned, _, _): (bool, (), ()) = REDIS
+        .pipe(|p| {
+            p.atomic()
+                .srem(&chat_key, &sid)
+                .zrem(SCHEDULE_KEY, &sid)
+                .del(&key)
+        })
+        .await?;
+    Ok(owned)
+}
+
+/// Get all pending jobs for a chat ordered by execution time
+pub async fn get_chat_jobs(chat: i64) -> Result<Vec<Job>> {
+    let chat_key = get_chat_jobs_key(chat);
+    let ids: Vec<String> = REDIS.sq(|q| q.smembers(&chat_key)).await?;
+    let mut jobs = Vec::with_capacity(ids.len());
+    for id in ids {
+        let Ok(id) = Uuid::from_str(&id) else {
+            continue;
+        };
+        let key = get_job_key(&id);
+        let job: Option<RedisStr> = REDIS.sq(|q| q.get(&key)).await?;
+        if let Some(job) = job {
+            jobs.push(job.get::<Job>()?);
+        }
+    }
+    jobs.sort_by_key(|j| j.run_at);
+    Ok(jobs)
+}
+
+/// Atomically moves up to `count` ids scored at or below `now` from one sorted set to
+/// another, scoring them with `score`
+async fn move_due(from: &str, to: &str, now: i64, score: i64, count: usize) -> Result<Vec<String>> {
+    let ids: Vec<String> = REDIS
+        .query(|mut q| async move {
+            let ids: Vec<String> = Script::new(
+                r#"
+                    local due = redis.call(
+                        "zrangebyscore", KEYS[1], "-inf", ARGV[1], "limit", 0, ARGV[3]
+                    )
+                    for _, id in ipairs(due) do
+                        redis.call("zrem", KEYS[1], id)
+                        redis.call("zadd", KEYS[2], ARGV[2], id)
+                    end
+                    return due
+                "#,
+            )
+            .key(from)
+            .key(to)
+            .arg(now)
+            .arg(score)
+            .arg(count)
+            .invoke_async(q.deref_mut())
+            .await?;
+            Ok(ids)
+        })
+        .await?;
+    Ok(ids)
+}
+
+/// Loads a claimed job, dropping its id if the job is gone
+async fn get_claimed(id: &str) -> Result<Option<Job>> {
+    let job = if let Ok(uuid) = Uuid::from_str(id) {
+        let job: Option<RedisStr> = REDIS.sq(|q| q.get(&get_job_key(&uuid))).await?;
+        job.map(|job| job.get::<Job>()).transpose()?
+    } else {
+        None
+    };
+    if job.is_none() {
+        REDIS.sq(|q| q.zrem(RUNNING_KEY, id)).await?;
+    }
+    Ok(job)
+}
+
+async fn finish_job(job: &Job) -> Result<()> {
+    let key = get_job_key(&job.id);
+    let chat_key = get_chat_jobs_key(job.chat);
+    let id = job.id.to_string();
+    REDIS
+        .pipe(|p| {
+            p.atomic()
+                .del(&key)
+                .zrem(RUNNING_KEY, &id)
+                .srem(&chat_key, &id)
+        })
+        .await?;
+    Ok(())
+}
+
+async fn retry_job(job: &Job) -> Result<()> {
+    let key = get_job_key(&job.id);
+    let id = job.id.to_string();
+    REDIS
+        .try_pipe(|p| {
+            p.atomic();
+            Ok(p.set(&key, job.to_redis()?)
+                .zadd(SCHEDULE_KEY, &id, job.run_at.timestamp())
+                .zrem(RUNNING_KEY, &id))
+        })
+        .await?;
+    Ok(())
+}
+
+async fn run_deferred_command(chat: i64, cmd: DeferredCommand) -> Result<()> {
+    let DeferredCommand {
+        mut message,
+        command,
+    } = cmd;
+    let len = command.encode_utf16().count() as i64;
+    message.text = Some(command.clone());
+    message.caption = None;
+    if let Some(entities) = message.entities.as_mut() {
+        entities.retain(|e| e.get_offset() + e.get_length() <= len);
+    }
+
+    let lang = get_chat_lang(chat).await?;
+    let log_chat = message.get_chat().clone();
+    let user = message
+        .get_from()
+        .map(|u| u.name_humanreadable_unescape().escape(false))
+        .unwrap_or_else(|| chat.to_string());
+
+    TG.dispatch_update(UpdateExt::Message(message)).await?;
+    send_log(
+        &log_chat,
+        lang_fmt!(lang, "scheduledran", command.escape(false), user),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn execute_job(job: &Job) -> Result<()> {
+    let chat = job.chat;
+    match job.kind {
+        JobKind::DeferredCommand(ref cmd) => run_deferred_command(chat, cmd.clone()).await,
+        JobKind::Birthdays => run_birthdays(chat).await,
+        JobKind::WelcomeMuteKick { user } => run_welcome_mute_kick(chat, user).await,
+        JobKind::DeleteMessage { message } => {
+            TG.client
+                .build_delete_message(chat, message)
+                .build()
+                .await?;
+            Ok(())
+        }
+        JobKind::CloseVoteBan { ref poll } => close_vote(poll).await,
+        JobKind::Recurring { id } => run_recurring(chat, id).await,
+        JobKind::VideoChatReminder { ref note } => {
+            run_video_chat_reminder(chat, note.clone()).await
+        }
+        JobKind::RefreshBoosts => run_refresh_boosts(chat).await,
+        JobKind::DrawGiveaway { id } => run_draw_giveaway(id).await,
+        JobKind::Unban { user } => expire_ban(chat, user).await,
+        JobKind::UnbanSenderChat { sender } => {
+            TG.client
+                .build_unban_chat_sender_chat(chat, sender)
+                .build()
+                .await?;
+            Ok(())
+        }
+        JobKind::Broadcast(ref send) => send_broadcast(send).await,
+        JobKind::ZombieScan {
+            clean,
+            dry_run,
+            ref status,
+            lang,
+        } => {
+            run_zombie_scan(chat, clean, dry_run, status.clone(), lang).await?;
+            release_scan(chat).await
+        }
+    }
+}
+
+async fn run_job(mut job: Job) -> Result<()> {
+    log::info!("running scheduled job {} in {}", job.id, job.chat);
+    let lease = Utc::now().timestamp() + job.lease();
+    let id = job.id.to_string();
+    REDIS.sq(|q| q.zadd(RUNNING_KEY, &id, lease)).await?;
+
+    let Err(err) = execute_job(&job).await else {
+        return finish_job(&job).await;
+    };
+    job.attempts += 1;
+    err.record_stats();
+    if job.attempts >= job.max_attempts() {
+        log::warn!(
+            "giving up on {} job {} after {} attempts: {}",
+            job.describe(),
+            job.id,
+            job.attempts,
+            err
+        );
+        finish_job(&job).await?;
+        job.give_up().await
+    } else {
+        let wait = backoff(job.attempts);
+        log::info!(
+            "{} job {} failed, retrying in {}s: {}",
+            job.describe(),
+            job.id,
+            wait,
+            err
+        );
+        job.run_at = Utc::now() + Duration::try_seconds(wait).unwrap_or_else(Duration::zero);
+        retry_job(&job).await
+    }
+}
+
+/// Claims and starts as many due jobs as there are free workers
+async fn poll_jobs() -> Result<()> {
+    let now = Utc::now().timestamp();
+    let expired = move_due(RUNNING_KEY, SCHEDULE_KEY, now, now, BATCH_SIZE).await?;
+    if !expired.is_empty() {
+        log::warn!("requeued {} jobs with expired leases", expired.len());
+    }
+
+    let free = CONFIG
+        .load()
+        .jobs
+        .workers
+        .saturating_sub(RUNNING.load(Ordering::Acquire))
+        .min(BATCH_SIZE);
+    if free == 0 {
+        return Ok(());
+    }
+    let due = move_due(SCHEDULE_KEY, RUNNING_KEY, now, now + CLAIM_LEASE, free).await?;
+    for id in due {
+        let Some(job) = get_claimed(&id).await? else {
+            continue;
+        };
+        RUNNING.fetch_add(1, Ordering::AcqRel);
+        let bot = job.bot.and_then(get_bot).unwrap_or_else(main_bot);
+        tokio::spawn(with_bot(bot, async move {
+            if let Err(err) = run_job(job).await {
+                log::warn!("failed to run scheduled job: {}", err);
+                err.record_stats();
+            }
+            RUNNING.fetch_sub(1, Ordering::AcqRel);
+        }));
+    }
+    Ok(())
+}
+
+/// Start the background task polling for due jobs
+pub fn spawn_scheduler() -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = poll_jobs().await {
+                log::warn!("scheduler poll failed: {}", err);
+                err.record_stats();
+            }
+        }
+    })
+}
+
+fn parse_day(day: &str, today: NaiveDate) -> Option<NaiveDate> {
+    match day {
+        "today" => Some(today),
+        "tomorrow" => today.succ_opt(),
+        day => {
+            if let Ok(date) = NaiveDate::parse_from_str(day, "%Y-%m-%d") {
+                return Some(date);
+            }
+            let weekday = Weekday::from_str(day).ok()?;
+            let diff = (7 + weekday.num_days_from_monday() as i64
+                - today.weekday().num_days_from_monday() as i64)
+                % 7;
+            let diff = if diff == 0 { 7 } else { diff };
+            today.checked_add_signed(Duration::try_days(diff)?)
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M").ok()
+}
+
+/// Parse a time specification relative to `now`. Accepts a time of day ("22:00"),
+/// a day ("friday", "tomorrow", "2024-05-01"), or a day followed by a time of day.
+/// Times of day without a day refer to the next occurrence of that time. Days and times
+/// are in the chat's local time
+pub fn parse_schedule_time(
+    spec: &str,
+    now: DateTime<Utc>,
+    local: &ChatTime,
+) -> Option<DateTime<Utc>> {
+    let spec = spec.to_lowercase();
+    let tokens = spec
+        .split_whitespace()
+        .filter(|t| *t != "at")
+        .collect::<Vec<&str>>();
+    let today = local.local(&now).date_naive();
+    let res = match tokens.as_slice() {
+        [single] => {
+            if let Some(time) = parse_time(single) {
+                let res = local.to_utc(today.and_time(time))?;
+                if res <= now {
+                    local.to_utc(today.succ_opt()?.and_time(time))?
+                } else {
+                    res
+                }
+            } else {
+                local.to_utc(parse_day(single, today)?.and_time(NaiveTime::MIN))?
+            }
+        }
+        [day, time] => local.to_utc(parse_day(day, today)?.and_time(parse_time(time)?))?,
+        _ => return None,
+    };
+
+    if res > now {
+        Some(res)
+    } else {
+        None
+    }
+}
+
+/// Split a command's text into the command itself and the time it should be run at.
+/// Returns None if the text does not end in a valid time specification
+pub fn split_schedule<'a>(
+    text: &'a str,
+    now: DateTime<Utc>,
+    local: &ChatTime,
+) -> Option<(&'a str, DateTime<Utc>)> {
+    let positions = text
+        .char_indices()
+        .filter(|(_, c)| c.is_whitespace())
+        .filter_map(|(idx, _)| {
+            let tail = text[idx..].trim_start();
+            KEYWORDS.iter().find_map(|kw| {
+                tail.strip_prefix(kw)
+                    .filter(|rest| rest.starts_with(char::is_whitespace))
+                    .map(|rest| (idx, *kw, rest.trim()))
+            })
+        })
+        .collect::<Vec<(usize, &str, &str)>>();
+
+    positions.into_iter().find_map(|(idx, kw, rest)| {
+        let time = if kw == "in" {
+            now.checked_add_signed(parse_duration(rest).ok()?)?
+        } else {
+            parse_schedule_time(rest, now, local)?
+        };
+        Some((text[..idx].trim_end(), time))
+    })
+}
+
+/// If the current update is a schedulable command with a trailing time specification,
+/// defer it instead of running it now. Returns true if the command was deferred
+pub async fn handle_deferred_command(ctx: &Context) -> Result<bool> {
+    let Some(&Cmd { cmd, message, .. }) = ctx.cmd() else {
+        return Ok(false);
+    };
+
+    if !SCHEDULABLE.contains(&cmd) {
+        return Ok(false);
+    }
+
+    let Some(text) = message.get_text() else {
+        return Ok(false);
+    };
+
+    let local = ChatTime::get(message.get_chat().get_id()).await?;
+    let Some((command, run_at)) = split_schedule(text, Utc::now(), &local) else {
+        return Ok(false);
+    };
+
+    ctx.is_group_or_die().await?;
+    ctx.check_permissions(|p| p.can_change_info).await?;
+
+    let job = Job::new(
+        message.get_chat().get_id(),
+        run_at,
+        JobKind::DeferredCommand(DeferredCommand {
+            message: message.clone(),
+            command: command.to_owned(),
+        }),
+    );
+    schedule_job(&job).await?;
+
+    ctx.reply(lang_fmt!(
+        ctx,
+        "scheduled",
+        command.escape(false),
+        local.format(&run_at),
+        job.id
+    ))
+    .await?;
+    Ok(true)
+}
+
+/// Report a cancelled job to the log channel of the chat
+pub async fn log_cancelled(chat: &Chat, job: &Uuid, by: &str) -> Result<()> {
+    let lang = get_chat_lang(chat.get_id()).await?;
+    send_log(chat, lang_fmt!(lang, "schedulecancelled", job, by)).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn backoff_doubles() {
+        assert_eq!(backoff(1), BACKOFF_BASE);
+        assert_eq!(backoff(2), BACKOFF_BASE * 2);
+        assert_eq!(backoff(3), BACKOFF_BASE * 4);
+        assert_eq!(backoff(40), BACKOFF_MAX);
+    }
+
+    fn now() -> DateTime<Utc> {
+        // a wednesday
+        Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn time_of_day() {
+        let res = parse_schedule_time("22:00", now(), &ChatTime::default()).unwrap();
+        assert_eq!(res, Utc.with_ymd_and_hms(2024, 5, 1, 22, 0, 0).unwrap());
+        let res = parse_schedule_time("08:30", now(), &ChatTime::default()).unwrap();
+        assert_eq!(res, Utc.with_ymd_and_hms(2024, 5, 2, 8, 30, 0).unwrap());
+    }
+
+    #[test]
+    fn weekday() {
+        let res = parse_schedule_time("friday", now(), &ChatTime::default()).unwrap();
+        assert_eq!(res, Utc.with_ymd_and_hms(2024, 5, 3, 0, 0, 0).unwrap());
+        let res = parse_schedule_time("wed at 10:00", now(), &ChatTime::default()).unwrap();
+        assert_eq!(res, Utc.with_ymd_and_hms(2024, 5, 8, 10, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn past_date() {
+        assert!(parse_schedule_time("2024-04-01", now(), &ChatTime::default()).is_none());
+        assert!(parse_schedule_time("links", now(), &ChatTime::default()).is_none());
+    }
+
+    #[test]
+    fn split() {
+        let (cmd, time) =
+            split_schedule("/lock links at 22:00", now(), &ChatTime::default()).unwrap();
+        assert_eq!(cmd, "/lock links");
+        assert_eq!(time, Utc.with_ymd_and_hms(2024, 5, 1, 22, 0, 0).unwrap());
+
+        let (cmd, time) = split_schedule(
+            "/warnlimit 5 from friday at 08:00",
+            now(),
+            &ChatTime::default(),
+        )
+        .unwrap();
+        assert_eq!(cmd, "/warnlimit 5");
+        assert_eq!(time, Utc.with_ymd_and_hms(2024, 5, 3, 8, 0, 0).unwrap());
+
+        let (cmd, time) =
+            split_schedule("/unlock links in 2h", now(), &ChatTime::default()).unwrap();
+        assert_eq!(cmd, "/unlock links");
+        assert_eq!(time, Utc.with_ymd_and_hms(2024, 5, 1, 14, 0, 0).unwrap());
+
+        assert!(split_schedule("/lock links", now(), &ChatTime::default()).is_none());
+        assert!(split_schedule("/warnmode ban at once", now(), &ChatTime::default()).is_none());
+    }
+
+    #[test]
+    fn local_time() {
+        let berlin = ChatTime::new(chrono_tz::Tz::Europe__Berlin);
+        let res = parse_schedule_time("22:00", now(), &berlin).unwrap();
+        assert_eq!(res, Utc.with_ymd_and_hms(2024, 5, 1, 20, 0, 0).unwrap());
+        let res = parse_schedule_time("13:00", now(), &berlin).unwrap();
+        assert_eq!(res, Utc.with_ymd_and_hms(2024, 5, 2, 11, 0, 0).unwrap());
+        let res = parse_schedule_time("friday", now(), &berlin).unwrap();
+        assert_eq!(res, Utc.with_ymd_and_hms(2024, 5, 2, 22, 0, 0).unwrap());
+    }
+}
@@ -0,0 +1,327 @@
+//! The /settings panel. Modules with per-chat settings implement [`SettingsProvider`] to show
+//! them in one inline keyboard. The panel opens on a list of modules, and each module gets a
+//! page with a row of buttons per setting. Every push edits the panel in place instead of
+//! sending a new message. Buttons are stored with [`persist_action`] so the panel keeps
+//! working across restarts until it expires along with other cached data.
+//!
+//! The panel is opened from the group itself. Each module decides which admin permissions
+//! are needed to change its settings, and modules an admin can't change are left out
+
+use async_trait::async_trait;
+use botapi::gen_types::{
+    CallbackQuery, Chat, EReplyMarkup, InlineKeyboardButton, InlineKeyboardButtonBuilder,
+    InlineKeyboardMarkup, MaybeInaccessibleMessage,
+};
+use macros::lang_fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::modules::settings_providers;
+use crate::statics::TG;
+use crate::util::error::{Fail, Result};
+use crate::util::string::{get_chat_lang, Lang};
+
+use super::button::{
+    callback_data, persist_action, ButtonAction, CallbackReply, InlineKeyboardBuilder,
+};
+use super::command::Context;
+use super::permissions::{BotPermissions, IsAdmin, IsGroupAdmin};
+use super::user::{GetChat, Username};
+
+/// How a setting is shown on the panel and what it can be changed to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SettingKind {
+    /// Switched on and off by pushing it
+    Toggle(bool),
+    /// A number changed one step at a time with - and + buttons
+    Number { value: i64, min: i64, max: i64 },
+    /// One of a list of options, pushing it picks the next one
+    Choice {
+        value: String,
+        options: Vec<&'static str>,
+    },
+}
+
+/// A new value for a setting picked from the panel
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum SettingValue {
+    Toggle(bool),
+    Number(i64),
+    Choice(String),
+}
+
+/// A single setting shown on a module's page
+#[derive(Clone, Debug)]
+pub struct Setting {
+    /// passed back to [`SettingsProvider::set_setting`] when the setting is changed
+    pub id: String,
+    pub name: String,
+    pub kind: SettingKind,
+}
+
+/// Implemented by modules to show their per-chat settings on the /settings panel
+#[async_trait]
+pub trait SettingsProvider: Send + Sync {
+    /// Name of the module, as used for enabling and disabling modules in the config
+    fn module(&self) -> &'static str;
+
+    /// Name shown on the module's button and page
+    fn title(&self) -> &'static str;
+
+    /// Checks if an admin with these permissions may change the module's settings
+    fn allowed(&self, permissions: &BotPermissions) -> bool;
+
+    /// Gets the current settings for a chat
+    async fn get_settings(&self, chat: &Chat) -> Result<Vec<Setting>>;
+
+    /// Changes one of the settings returned by [`SettingsProvider::get_settings`]
+    async fn set_setting(&self, chat: &Chat, setting: &str, value: SettingValue) -> Result<()>;
+}
+
+/// Which page a pushed panel button leads to
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub enum SettingsPage {
+    /// The list of modules
+    Modules,
+    /// The settings of one module
+    Module(String),
+    /// Changes a setting, then shows its module again
+    Change {
+        module: String,
+        setting: String,
+        value: SettingValue,
+    },
+}
+
+impl SettingKind {
+    /// The value pushing the setting's button changes it to. Numbers use [`SettingKind::steps`]
+    /// instead
+    fn next(&self) -> Option<SettingValue> {
+        match self {
+            Self::Toggle(value) => Some(SettingValue::Toggle(!value)),
+            Self::Number { .. } => None,
+            Self::Choice { value, options } => {
+                let next = options
+                    .iter()
+                    .position(|option| option == value)
+                    .map(|idx| (idx + 1) % options.len())
+                    .unwrap_or(0);
+                options
+                    .get(next)
+                    .map(|option| SettingValue::Choice((*option).to_owned()))
+            }
+        }
+    }
+
+    /// Buttons shown next to a number to move it one step within its range
+    fn steps(&self) -> Vec<(&'static str, SettingValue)> {
+        match *self {
+            Self::Number { value, min, max } => [("-", value - 1), ("+", value + 1)]
+                .into_iter()
+                .filter(|(_, value)| (min..=max).contains(value))
+                .map(|(label, value)| (label, SettingValue::Number(value)))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    fn display(&self, lang: &Lang) -> String {
+        match self {
+            Self::Toggle(true) => lang_fmt!(lang, "settingson"),
+            Self::Toggle(false) => lang_fmt!(lang, "settingsoff"),
+            Self::Number { value, .. } => value.to_string(),
+            Self::Choice { value, .. } => value.to_owned(),
+        }
+    }
+}
+
+/// Gets the settings providers an admin with these permissions may use
+fn allowed_providers(permissions: &BotPermissions) -> Vec<&'static dyn SettingsProvider> {
+    settings_providers()
+        .into_iter()
+        .filter(|provider| provider.allowed(permissions))
+        .collect()
+}
+
+async fn page_button(text: String, chat: i64, page: SettingsPage) -> Result<InlineKeyboardButton> {
+    let button = InlineKeyboardButtonBuilder::new(text)
+        .set_callback_data(callback_data(None))
+        .build();
+    persist_action(&button, &ButtonAction::Settings { chat, page }).await?;
+    Ok(button)
+}
+
+/// Builds the text and keyboard for the module list, or for a module's page if one is given
+async fn render_page(
+    chat: &Chat,
+    provider: Option<&'static dyn SettingsProvider>,
+    permissions: &BotPermissions,
+    lang: &Lang,
+) -> Result<(String, InlineKeyboardMarkup)> {
+    let chat_id = chat.get_id();
+    let mut buttons = InlineKeyboardBuilder::default();
+    let text = if let Some(provider) = provider {
+        let module = provider.module().to_owned();
+        for setting in provider.get_settings(chat).await? {
+            if buttons.row_len() > 0 {
+                buttons.newline();
+            }
+            let label = format!("{}: {}", setting.name, setting.kind.display(lang));
+            let page = match setting.kind.next() {
+                Some(value) => SettingsPage::Change {
+                    module: module.clone(),
+                    setting: setting.id.clone(),
+                    value,
+                },
+                None => SettingsPage::Module(module.clone()),
+            };
+            buttons.button(page_button(label, chat_id, page).await?);
+            for (label, value) in setting.kind.steps() {
+                let page = SettingsPage::Change {
+                    module: module.clone(),
+                    setting: setting.id.clone(),
+                    value,
+                };
+                buttons.button(page_button(label.to_owned(), chat_id, page).await?);
+            }
+        }
+        buttons.newline();
+        let back = lang_fmt!(lang, "settingsback");
+        buttons.button(page_button(back, chat_id, SettingsPage::Modules).await?);
+        lang_fmt!(
+            lang,
+            "settingsmodule",
+            provider.title(),
+            chat.name_humanreadable()
+        )
+    } else {
+        for provider in allowed_providers(permissions) {
+            if buttons.row_len() > 0 {
+                buttons.newline();
+            }
+            let page = SettingsPage::Module(provider.module().to_owned());
+            buttons.button(page_button(provider.title().to_owned(), chat_id, page).await?);
+        }
+        lang_fmt!(lang, "settingsheader", chat.name_humanreadable())
+    };
+    Ok((text, buttons.build()))
+}
+
+/// Sends the settings panel for the current chat
+pub async fn open_settings(ctx: &Context) -> Result<()> {
+    ctx.is_group_or_die().await?;
+    let chat = ctx.try_get()?.chat;
+    let lang = ctx.lang();
+    let permissions = ctx.get_permissions().await?;
+    if allowed_providers(&permissions).is_empty() {
+        return ctx.fail(lang_fmt!(lang, "settingsnoperm"));
+    }
+
+    let (text, markup) = render_page(chat, None, &permissions, lang).await?;
+    TG.client
+        .build_send_message(chat.get_id(), &text)
+        .reply_markup(&EReplyMarkup::InlineKeyboardMarkup(markup))
+        .build()
+        .await?;
+    Ok(())
+}
+
+/// Handles a push of a button on the settings panel, changing a setting if needed and
+/// editing the panel to show the next page
+pub(crate) async fn settings_pushed(
+    callback: &CallbackQuery,
+    chat: i64,
+    page: SettingsPage,
+) -> Result<(bool, CallbackReply)> {
+    let lang = get_chat_lang(chat).await?;
+    let Some(chat) = chat.get_chat().await? else {
+        return Ok((true, CallbackReply::default()));
+    };
+    let permissions = callback.get_from().get_permissions(&chat).await?;
+    let providers = allowed_providers(&permissions);
+    let find = |module: &str| providers.iter().find(|p| p.module() == module).copied();
+    let denied = || {
+        Ok((
+            false,
+            CallbackReply::alert(lang_fmt!(lang, "settingsnoperm")),
+        ))
+    };
+
+    let (provider, reply) = match page {
+        SettingsPage::Modules if providers.is_empty() => return denied(),
+        SettingsPage::Modules => (None, CallbackReply::default()),
+        SettingsPage::Module(module) => match find(&module) {
+            Some(provider) => (Some(provider), CallbackReply::default()),
+            None => return denied(),
+        },
+        SettingsPage::Change {
+            module,
+            setting,
+            value,
+        } => match find(&module) {
+            Some(provider) => {
+                provider.set_setting(&chat, &setting, value).await?;
+                let reply = CallbackReply::toast(lang_fmt!(lang, "settingschanged"));
+                (Some(provider), reply)
+            }
+            None => return denied(),
+        },
+    };
+
+    if let Some(MaybeInaccessibleMessage::Message(message)) = callback.get_message() {
+        let (text, markup) = render_page(&chat, provider, &permissions, &lang).await?;
+        TG.client
+            .build_edit_message_text(&text)
+            .chat_id(message.get_chat().get_id())
+            .message_id(message.get_message_id())
+            .reply_markup(&markup)
+            .build()
+            .await?;
+    }
+    Ok((false, reply))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn toggle_flips() {
+        assert_eq!(
+            SettingKind::Toggle(true).next(),
+            Some(SettingValue::Toggle(false))
+        );
+        assert!(SettingKind::Toggle(false).steps().is_empty());
+    }
+
+    #[test]
+    fn choice_cycles() {
+        let options = vec!["mute", "ban", "shame"];
+        let kind = SettingKind::Choice {
+            value: "shame".to_owned(),
+            options: options.clone(),
+        };
+        assert_eq!(kind.next(), Some(SettingValue::Choice("mute".to_owned())));
+        let kind = SettingKind::Choice {
+            value: "unknown".to_owned(),
+            options,
+        };
+        assert_eq!(kind.next(), Some(SettingValue::Choice("mute".to_owned())));
+    }
+
+    #[test]
+    fn number_steps_stay_in_range() {
+        let kind = SettingKind::Number {
+            value: 1,
+            min: 1,
+            max: 3,
+        };
+        assert_eq!(kind.next(), None);
+        assert_eq!(kind.steps(), vec![("+", SettingValue::Number(2))]);
+        let kind = SettingKind::Number {
+            value: 2,
+            min: 1,
+            max: 3,
+        };
+        assert_eq!(kind.steps().len(), 2);
+    }
+}
@@ -0,0 +1,61 @@
+//! Choices made on the setup posted when the bot is added to a chat. Each choice is stored
+//! with the setup's buttons, so picking one still works after a restart
+
+use botapi::gen_types::CallbackQuery;
+use macros::lang_fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::util::error::Result;
+use crate::util::string::{get_chat_lang, set_chat_lang, Lang};
+
+use super::button::CallbackReply;
+use super::greetings::{set_captcha_enabled, set_welcome_enabled};
+use super::log_channel::{get_log_channel, set_log_channel};
+use super::permissions::IsAdmin;
+use super::user::GetChat;
+
+/// A choice made during setup, applied to the chat as soon as it is picked
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+pub enum SetupChoice {
+    Lang(Lang),
+    Welcome(bool),
+    Captcha(bool),
+    /// Finishes setup once a log channel was set with /setlog
+    LogSet,
+    /// Finishes setup without a log channel
+    NoLog,
+}
+
+/// Applies a setup choice if the user pushing it is still an admin in the chat. Returns
+/// false if the setup shouldn't move on, along with the reply explaining why
+pub(crate) async fn setup_pushed(
+    callback: &CallbackQuery,
+    chat: i64,
+    choice: SetupChoice,
+) -> Result<(bool, CallbackReply)> {
+    let lang = get_chat_lang(chat).await?;
+    let Some(chat) = chat.get_chat().await? else {
+        return Ok((false, CallbackReply::default()));
+    };
+    if !callback.get_from().is_admin(&chat).await? {
+        return Ok((
+            false,
+            CallbackReply::alert(lang_fmt!(lang, "setupnotadmin")),
+        ));
+    }
+    match choice {
+        SetupChoice::Lang(picked) => set_chat_lang(&chat, picked).await?,
+        SetupChoice::Welcome(enabled) => set_welcome_enabled(chat.get_id(), enabled).await?,
+        SetupChoice::Captcha(enabled) => set_captcha_enabled(&chat, enabled).await?,
+        SetupChoice::LogSet => {
+            if get_log_channel(&chat).await?.is_none() {
+                return Ok((
+                    false,
+                    CallbackReply::alert(lang_fmt!(lang, "setuplogmissing")),
+                ));
+            }
+        }
+        SetupChoice::NoLog => set_log_channel(&chat, None).await?,
+    }
+    Ok((true, CallbackReply::default()))
+}
@@ -0,0 +1,115 @@
+//! The shame warn mode. Instead of muting or banning a user who reaches the warn limit, the
+//! bot posts a wall of shame listing their warns, followed by a sticker or gif the chat
+//! picked with /shamemedia. The user keeps their permissions, their warns are cleared so the
+//! count starts over, and the shame is counted for the chat's /shamelist leaderboard
+
+use botapi::gen_types::Chat;
+use chrono::Utc;
+use macros::entity_fmt;
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+
+use crate::persist::admin::shames;
+use crate::persist::core::dialogs;
+use crate::persist::core::media::MediaType;
+use crate::statics::DB;
+use crate::util::error::Result;
+use crate::util::string::Speak;
+
+use super::admin_helpers::{clear_warns, get_warns};
+use super::command::Context;
+use super::dialog::{dialog_or_default, dialog_scope};
+use super::media::{send_media, SendableMedia};
+use super::user::GetUser;
+
+/// Most users shown on /shamelist
+pub const SHAME_LIST_LEN: u64 = 10;
+
+/// Counts a shame for a user in a chat
+async fn record_shame(chat: i64, user: i64) -> Result<()> {
+    let model = shames::ActiveModel {
+        chat_id: Set(chat),
+        user_id: Set(user),
+        count: Set(1),
+        last_shamed: Set(Utc::now()),
+    };
+    shames::Entity::insert(model)
+        .on_conflict(
+            OnConflict::columns([shames::Column::ChatId, shames::Column::UserId])
+                .value(
+                    shames::Column::Count,
+                    Expr::col((shames::Entity, shames::Column::Count)).add(1),
+                )
+                .update_column(shames::Column::LastShamed)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+    Ok(())
+}
+
+/// Gets the most shamed users in a chat, most shamed first
+pub async fn get_shame_list(chat: i64) -> Result<Vec<shames::Model>> {
+    let list = shames::Entity::find()
+        .filter(shames::Column::ChatId.eq(chat))
+        .order_by_desc(shames::Column::Count)
+        .order_by_desc(shames::Column::LastShamed)
+        .limit(SHAME_LIST_LEN)
+        .all(*DB)
+        .await?;
+    Ok(list)
+}
+
+/// Sets the sticker or gif posted with the wall of shame, or removes it
+pub async fn set_shame_media(chat: &Chat, media: Option<(String, MediaType)>) -> Result<()> {
+    let mut model = dialogs::Model::from_chat(chat).await?;
+    let (media_id, media_type) = media.unzip();
+    model.shame_media = Set(media_id);
+    model.shame_media_type = Set(media_type);
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_columns([dialogs::Column::ShameMedia, dialogs::Column::ShameMediaType])
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat.get_id()).invalidate().await?;
+    Ok(())
+}
+
+impl Context {
+    /// Helper function to handle the shame action after warn limit is exceeded. Posts the
+    /// user's warns and the chat's shame media without restricting them
+    pub async fn warn_shame(&self, user: i64, count: i32) -> Result<()> {
+        let message = self.message()?;
+        let chat = message.get_chat();
+        let warns = get_warns(chat, user).await?;
+
+        let mut text = entity_fmt!(self, "shamewall", count.to_string(), user.mention().await?);
+        for warn in warns {
+            if let Some(reason) = warn.reason {
+                text.builder.text(format!("\n- {}", reason));
+            }
+        }
+        message.reply_fmt(text).await?;
+
+        let dialog = dialog_or_default(chat).await?;
+        if let (Some(media_id), Some(media_type)) = (dialog.shame_media, dialog.shame_media_type) {
+            let media = SendableMedia {
+                media_id: Some(media_id),
+                media_type,
+                caption: String::new(),
+                entities: vec![],
+                buttons: None,
+            };
+            send_media(chat.get_id(), media).await?;
+        }
+
+        record_shame(chat.get_id(), user).await?;
+        clear_warns(chat, user).await?;
+        Ok(())
+    }
+}
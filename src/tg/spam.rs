@@ -0,0 +1,371 @@
+//! Spam classification. Every message in a chat with the spam filter enabled is scored by
+//! a list of checks before modules run, and the highest score is kept on the update's
+//! context for modules to act on. Blocklists apply the chat's action to messages at or
+//! above the chat's threshold.
+//!
+//! Checks implement [`SpamCheck`]. The built in heuristics are cheap enough to run on every
+//! message, and an http classifier is asked too while one is set in the config. A failing
+//! check is logged and scores nothing so a broken backend never blocks messages
+
+use std::collections::HashSet;
+use std::time::Duration as StdDuration;
+
+use async_trait::async_trait;
+use botapi::gen_types::{Message, MessageOrigin};
+use chrono::Duration;
+use futures::future::join_all;
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::EntityTrait;
+use serde::{Deserialize, Serialize};
+
+use crate::persist::admin::actions::ActionType;
+use crate::persist::admin::spamfilter;
+use crate::persist::redis::{default_cache_query, CachedQueryTrait, RedisCache};
+use crate::statics::{CONFIG, DB};
+use crate::util::error::Result;
+
+use super::admin_helpers::{is_dm, UpdateHelpers};
+use super::command::Context;
+
+/// Score in percent that counts as spam in chats that never set one
+pub const DEFAULT_THRESHOLD: i32 = 80;
+
+/// Mentions allowed in a message before it starts scoring
+const MENTION_LIMIT: usize = 4;
+
+/// Messages shorter than this in characters are never scored as repeated text
+const REPEAT_MIN_LEN: usize = 100;
+
+/// Ratio of unique words to total words below which text starts scoring as repeated
+const REPEAT_RATIO: f32 = 0.5;
+
+lazy_static! {
+    static ref CHECKS: Vec<Box<dyn SpamCheck>> = vec![
+        Box::new(ForwardedChannel),
+        Box::new(ExcessiveMentions),
+        Box::new(RepeatedText),
+        Box::new(HttpClassifier),
+    ];
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+/// A single way of scoring messages as spam
+#[async_trait]
+pub trait SpamCheck: Send + Sync {
+    /// Name shown to admins when this check flags a message
+    fn name(&self) -> &'static str;
+
+    /// Scores a message from 0, not spam, to 1, certainly spam
+    async fn score(&self, message: &Message) -> Result<f32>;
+}
+
+/// The result of running every check on a message
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpamScore {
+    /// the highest score any check gave
+    pub score: f32,
+    /// the check that gave it, None if nothing scored
+    pub check: Option<&'static str>,
+}
+
+impl SpamScore {
+    /// Gets the score in percent to compare with chat thresholds
+    pub fn percent(&self) -> i32 {
+        (self.score * 100.0).round() as i32
+    }
+}
+
+fn has_links(message: &Message) -> bool {
+    message
+        .get_entities()
+        .into_iter()
+        .chain(message.get_caption_entities())
+        .flatten()
+        .any(|e| matches!(e.get_tg_type(), "url" | "text_link"))
+}
+
+/// Forwards from channels, the usual way ads get mass posted. Forwards with links or
+/// buttons score higher
+pub struct ForwardedChannel;
+
+#[async_trait]
+impl SpamCheck for ForwardedChannel {
+    fn name(&self) -> &'static str {
+        "channel forward"
+    }
+
+    async fn score(&self, message: &Message) -> Result<f32> {
+        let score = match message.get_forward_origin() {
+            Some(MessageOrigin::MessageOriginChannel(_)) => {
+                if has_links(message) || message.get_reply_markup().is_some() {
+                    0.85
+                } else {
+                    0.6
+                }
+            }
+            _ => 0.0,
+        };
+        Ok(score)
+    }
+}
+
+/// Scores the number of mentions in a message
+pub fn mention_score(mentions: usize) -> f32 {
+    (mentions.saturating_sub(MENTION_LIMIT) as f32 * 0.2).min(1.0)
+}
+
+/// Messages pinging lots of users at once
+pub struct ExcessiveMentions;
+
+#[async_trait]
+impl SpamCheck for ExcessiveMentions {
+    fn name(&self) -> &'static str {
+        "mentions"
+    }
+
+    async fn score(&self, message: &Message) -> Result<f32> {
+        let mentions = message
+            .get_entities()
+            .into_iter()
+            .chain(message.get_caption_entities())
+            .flatten()
+            .filter(|e| matches!(e.get_tg_type(), "mention" | "text_mention"))
+            .count();
+        Ok(mention_score(mentions))
+    }
+}
+
+/// Scores how repetitive a long text is, from the ratio of unique words to total words.
+/// Text without spaces uses unique characters instead
+pub fn repetition_score(text: &str) -> f32 {
+    if text.chars().count() < REPEAT_MIN_LEN {
+        return 0.0;
+    }
+    let words = text.split_whitespace().collect_vec();
+    let ratio = if words.len() > 1 {
+        words.iter().collect::<HashSet<_>>().len() as f32 / words.len() as f32
+    } else {
+        text.chars().collect::<HashSet<_>>().len() as f32 / text.chars().count() as f32
+    };
+    ((REPEAT_RATIO - ratio) / REPEAT_RATIO).clamp(0.0, 1.0)
+}
+
+/// Long messages made of the same few words over and over
+pub struct RepeatedText;
+
+#[async_trait]
+impl SpamCheck for RepeatedText {
+    fn name(&self) -> &'static str {
+        "repeated text"
+    }
+
+    async fn score(&self, message: &Message) -> Result<f32> {
+        let text = message.get_text().or_else(|| message.get_caption());
+        Ok(text.map(repetition_score).unwrap_or(0.0))
+    }
+}
+
+#[derive(Serialize)]
+struct ClassifierRequest<'a> {
+    text: &'a str,
+    chat: i64,
+    user: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct ClassifierResponse {
+    score: f32,
+}
+
+/// An external classifier reached over http, scoring nothing while none is configured
+pub struct HttpClassifier;
+
+#[async_trait]
+impl SpamCheck for HttpClassifier {
+    fn name(&self) -> &'static str {
+        "classifier"
+    }
+
+    async fn score(&self, message: &Message) -> Result<f32> {
+        let config = CONFIG.load();
+        let Some(ref url) = config.spam.classifier else {
+            return Ok(0.0);
+        };
+        let Some(text) = message.get_text().or_else(|| message.get_caption()) else {
+            return Ok(0.0);
+        };
+        let body = serde_json::to_vec(&ClassifierRequest {
+            text,
+            chat: message.get_chat().get_id(),
+            user: message.get_from().map(|u| u.get_id()),
+        })?;
+        let res = CLIENT
+            .post(url)
+            .timeout(StdDuration::from_millis(config.spam.classifier_timeout))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await?
+            .bytes()
+            .await?;
+        let res: ClassifierResponse = serde_json::from_slice(&res)?;
+        Ok(res.score.clamp(0.0, 1.0))
+    }
+}
+
+/// Runs every check on a message, keeping the highest score
+pub async fn score_message(message: &Message) -> SpamScore {
+    let scores = join_all(CHECKS.iter().map(|check| async move {
+        match check.score(message).await {
+            Ok(score) => (score, check.name()),
+            Err(err) => {
+                log::warn!("spam check {} failed: {}", check.name(), err);
+                (0.0, check.name())
+            }
+        }
+    }))
+    .await;
+    scores
+        .into_iter()
+        .filter(|(score, _)| *score > 0.0)
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(score, check)| SpamScore {
+            score,
+            check: Some(check),
+        })
+        .unwrap_or(SpamScore {
+            score: 0.0,
+            check: None,
+        })
+}
+
+#[inline(always)]
+fn get_spam_filter_key(chat: i64) -> String {
+    format!("spamf:{}", chat)
+}
+
+/// Gets the spam filter settings for a chat, None if they were never set
+pub async fn get_spam_filter(chat: i64) -> Result<Option<spamfilter::Model>> {
+    let key = get_spam_filter_key(chat);
+    let res = default_cache_query(
+        |_, _| async move {
+            let res = spamfilter::Entity::find_by_id(chat).one(*DB).await?;
+            Ok(res)
+        },
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
+    )
+    .query(&key, &())
+    .await?;
+    Ok(res)
+}
+
+async fn update_spam_filter(
+    chat: i64,
+    model: spamfilter::ActiveModel,
+    columns: Vec<spamfilter::Column>,
+) -> Result<spamfilter::Model> {
+    let key = get_spam_filter_key(chat);
+    let model = spamfilter::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(spamfilter::Column::Chat)
+                .update_columns(columns)
+                .to_owned(),
+        )
+        .exec_with_returning(*DB)
+        .await?;
+    model.cache(key).await?;
+    Ok(model)
+}
+
+/// Enables or disables acting on spam in a chat
+pub async fn set_spam_filter_enabled(chat: i64, enabled: bool) -> Result<spamfilter::Model> {
+    let model = spamfilter::ActiveModel {
+        chat: Set(chat),
+        enabled: Set(enabled),
+        threshold: NotSet,
+        action: NotSet,
+    };
+    update_spam_filter(chat, model, vec![spamfilter::Column::Enabled]).await
+}
+
+/// Sets what happens to spam in a chat, and optionally the score in percent a message
+/// needs to count as spam
+pub async fn set_spam_action(
+    chat: i64,
+    action: ActionType,
+    threshold: Option<i32>,
+) -> Result<spamfilter::Model> {
+    let mut columns = vec![spamfilter::Column::Action];
+    if threshold.is_some() {
+        columns.push(spamfilter::Column::Threshold);
+    }
+    let model = spamfilter::ActiveModel {
+        chat: Set(chat),
+        enabled: NotSet,
+        threshold: threshold.map(Set).unwrap_or(NotSet),
+        action: Set(action),
+    };
+    update_spam_filter(chat, model, columns).await
+}
+
+impl Context {
+    /// Scores the current message as spam, running the checks at most once per update.
+    /// None if the message isn't moderated or the chat has the spam filter off
+    pub async fn spam_score(&self) -> Result<Option<&'_ SpamScore>> {
+        let score = self
+            .get_static()
+            .spam
+            .get_or_try_init(|| async {
+                let Some(message) = self.update().should_moderate().await else {
+                    return Ok(None);
+                };
+                if is_dm(message.get_chat()) {
+                    return Ok(None);
+                }
+                let enabled = get_spam_filter(message.get_chat().get_id())
+                    .await?
+                    .map(|s| s.enabled)
+                    .unwrap_or(false);
+                if !enabled {
+                    return Ok(None);
+                }
+                Ok(Some(score_message(message).await))
+            })
+            .await?;
+        Ok(score.as_ref())
+    }
+
+    /// Scores the message before modules run so they all see the same score
+    pub async fn handle_spam_check(&self) {
+        if let Err(err) = self.spam_score().await {
+            log::warn!("failed to score message for spam: {}", err);
+            err.record_stats();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mentions() {
+        assert_eq!(mention_score(0), 0.0);
+        assert_eq!(mention_score(MENTION_LIMIT), 0.0);
+        assert!(mention_score(MENTION_LIMIT + 1) > 0.0);
+        assert_eq!(mention_score(50), 1.0);
+    }
+
+    #[test]
+    fn repetition() {
+        assert_eq!(repetition_score("buy now"), 0.0);
+        assert!(repetition_score(&"buy now ".repeat(30)) > 0.9);
+        assert!(repetition_score(&"a".repeat(200)) > 0.9);
+        let normal = "the quick brown fox jumps over the lazy dog while a small cat watches \
+                      from the window and wonders why anyone would bother jumping at all";
+        assert_eq!(repetition_score(normal), 0.0);
+    }
+}
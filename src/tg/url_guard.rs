@@ -0,0 +1,501 @@
+//! Validation of urls in buttons saved in notes, welcomes, and filters. Chats can keep an
+//! allowlist or denylist of domains and buttons linking to domains pretending to be
+//! telegram are flagged automatically, to make phishing buttons planted by compromised
+//! admin accounts harder to miss.
+//!
+//! Links in messages are checked the same way by the domains lock, against a separate
+//! list so chats can trust different domains for buttons and for members' messages
+
+use botapi::gen_types::{Chat, Message};
+use chrono::Duration;
+use macros::lang_fmt;
+use redis::AsyncCommands;
+use reqwest::Url;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+use crate::persist::core::{button_domains, dialogs, link_domains};
+use crate::persist::redis::{default_cache_query, CachedQueryTrait};
+use crate::statics::{CONFIG, DB, REDIS};
+use crate::util::error::{BotError, Result};
+use crate::util::string::{get_chat_lang, Speak};
+
+use super::button::InlineKeyboardBuilder;
+use super::dialog::{dialog_scope, get_dialog};
+
+/// Domains owned by telegram. Anything that looks like these but isn't one is flagged
+const TELEGRAM_DOMAINS: &[&str] = &[
+    "t.me",
+    "telegram.me",
+    "telegram.org",
+    "telegram.dog",
+    "telesco.pe",
+    "telegra.ph",
+    "tg.dev",
+];
+
+/// Reason a button url was flagged
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UrlProblem {
+    /// The domain is on the chat's denylist
+    Denied,
+    /// The chat has an allowlist and the domain is not on it
+    NotAllowed,
+    /// The domain looks like a telegram domain but isn't one
+    Lookalike,
+}
+
+/// A button url that failed validation
+#[derive(Clone, Debug)]
+pub struct UrlFinding {
+    pub domain: String,
+    pub problem: UrlProblem,
+}
+
+/// An entry on a chat's allowlist or denylist
+pub trait DomainRule {
+    fn domain(&self) -> &str;
+    fn allow(&self) -> bool;
+}
+
+impl DomainRule for button_domains::Model {
+    fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    fn allow(&self) -> bool {
+        self.allow
+    }
+}
+
+impl DomainRule for link_domains::Model {
+    fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    fn allow(&self) -> bool {
+        self.allow
+    }
+}
+
+#[inline(always)]
+fn get_link_rules_key(chat: i64) -> String {
+    format!("linkrules:{}", chat)
+}
+
+/// Lowercases a domain and strips leading www and trailing dots
+pub fn normalize_domain(domain: &str) -> String {
+    let domain = domain.trim().trim_end_matches('.').to_lowercase();
+    domain
+        .strip_prefix("www.")
+        .map(|v| v.to_owned())
+        .unwrap_or(domain)
+}
+
+/// Returns true if the domain is the rule's domain or one of its subdomains
+pub fn domain_matches(domain: &str, rule: &str) -> bool {
+    domain == rule
+        || domain
+            .strip_suffix(rule)
+            .map(|v| v.ends_with('.'))
+            .unwrap_or(false)
+}
+
+/// Maps characters commonly used to fake domains to the characters they imitate and drops
+/// separators, so tele.gram.org and te1egrarn.org both become telegramorg
+fn skeleton(domain: &str) -> String {
+    let s = domain
+        .chars()
+        .filter(|c| !matches!(c, '.' | '-' | '_'))
+        .map(|c| match c {
+            '0' => 'o',
+            '1' | 'i' | '|' => 'l',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' => 's',
+            c => c,
+        })
+        .collect::<String>();
+    s.replace("rn", "m").replace("vv", "w")
+}
+
+/// Returns true if the strings are equal or differ by a single insertion, deletion, or
+/// substitution
+fn within_one_edit(a: &str, b: &str) -> bool {
+    let a = a.chars().collect::<Vec<char>>();
+    let b = b.chars().collect::<Vec<char>>();
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    if long.len() - short.len() > 1 {
+        return false;
+    }
+    let prefix = short
+        .iter()
+        .zip(long.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if short.len() == long.len() {
+        short[prefix..]
+            .iter()
+            .skip(1)
+            .eq(long[prefix..].iter().skip(1))
+    } else {
+        short[prefix..].iter().eq(long[prefix + 1..].iter())
+    }
+}
+
+/// Returns true if the domain imitates a telegram domain without being one
+pub fn is_lookalike(domain: &str) -> bool {
+    if TELEGRAM_DOMAINS.iter().any(|d| domain_matches(domain, d)) {
+        return false;
+    }
+
+    let sk = skeleton(domain);
+    sk.contains("telegram")
+        || TELEGRAM_DOMAINS.iter().any(|d| {
+            let tg = skeleton(d);
+            domain.starts_with(&format!("{}.", d))
+                || sk == tg
+                || (tg.len() >= 6 && within_one_edit(&sk, &tg))
+        })
+}
+
+/// Checks a single domain against the chat's rules. Denylist entries always win, allowlist
+/// entries suppress lookalike detection
+pub fn check_domain<R: DomainRule>(domain: &str, rules: &[R]) -> Option<UrlProblem> {
+    if rules
+        .iter()
+        .any(|r| !r.allow() && domain_matches(domain, r.domain()))
+    {
+        Some(UrlProblem::Denied)
+    } else if rules
+        .iter()
+        .any(|r| r.allow() && domain_matches(domain, r.domain()))
+    {
+        None
+    } else if is_lookalike(domain) {
+        Some(UrlProblem::Lookalike)
+    } else if rules.iter().any(|r| r.allow()) {
+        Some(UrlProblem::NotAllowed)
+    } else {
+        None
+    }
+}
+
+/// Gets the domain rules for a chat
+pub async fn get_domain_rules(chat: i64) -> Result<Vec<button_domains::Model>> {
+    let res = button_domains::Entity::find()
+        .filter(button_domains::Column::ChatId.eq(chat))
+        .all(*DB)
+        .await?;
+    Ok(res)
+}
+
+/// Adds a domain to the chat's allowlist or denylist, replacing any existing rule for it
+pub async fn set_domain_rule(chat: i64, domain: &str, allow: bool) -> Result<()> {
+    let model = button_domains::ActiveModel {
+        chat_id: Set(chat),
+        domain: Set(normalize_domain(domain)),
+        allow: Set(allow),
+    };
+    button_domains::Entity::insert(model)
+        .on_conflict(
+            OnConflict::columns([
+                button_domains::Column::ChatId,
+                button_domains::Column::Domain,
+            ])
+            .update_column(button_domains::Column::Allow)
+            .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+    Ok(())
+}
+
+/// Removes a domain from the chat's lists. Returns false if it wasn't on either
+pub async fn remove_domain_rule(chat: i64, domain: &str) -> Result<bool> {
+    let res = button_domains::Entity::delete_many()
+        .filter(
+            button_domains::Column::ChatId
+                .eq(chat)
+                .and(button_domains::Column::Domain.eq(normalize_domain(domain))),
+        )
+        .exec(*DB)
+        .await?;
+    Ok(res.rows_affected > 0)
+}
+
+/// Gets the domain rules links in a chat's messages are checked against
+pub async fn get_link_rules(chat: i64) -> Result<Vec<link_domains::Model>> {
+    let key = get_link_rules_key(chat);
+    let res = default_cache_query(
+        |_, _| async move {
+            let res = link_domains::Entity::find()
+                .filter(link_domains::Column::ChatId.eq(chat))
+                .all(*DB)
+                .await?;
+            Ok(Some(res))
+        },
+        Duration::try_seconds(CONFIG.load().timing.cache_timeout).unwrap(),
+    )
+    .query(&key, &())
+    .await?;
+    Ok(res.unwrap_or_default())
+}
+
+/// Adds a domain to the chat's link allowlist or denylist, replacing any existing rule
+/// for it
+pub async fn set_link_rule(chat: i64, domain: &str, allow: bool) -> Result<()> {
+    let model = link_domains::ActiveModel {
+        chat_id: Set(chat),
+        domain: Set(normalize_domain(domain)),
+        allow: Set(allow),
+    };
+    link_domains::Entity::insert(model)
+        .on_conflict(
+            OnConflict::columns([link_domains::Column::ChatId, link_domains::Column::Domain])
+                .update_column(link_domains::Column::Allow)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+    let key = get_link_rules_key(chat);
+    REDIS.sq(|q| q.del(&key)).await?;
+    Ok(())
+}
+
+/// Removes a domain from the chat's link lists. Returns false if it wasn't on either
+pub async fn remove_link_rule(chat: i64, domain: &str) -> Result<bool> {
+    let res = link_domains::Entity::delete_many()
+        .filter(
+            link_domains::Column::ChatId
+                .eq(chat)
+                .and(link_domains::Column::Domain.eq(normalize_domain(domain))),
+        )
+        .exec(*DB)
+        .await?;
+    let key = get_link_rules_key(chat);
+    REDIS.sq(|q| q.del(&key)).await?;
+    Ok(res.rows_affected > 0)
+}
+
+/// Gets the text covered by an entity. Entity offsets count utf-16 code units, not bytes
+fn entity_text(text: &str, offset: i64, length: i64) -> Option<&str> {
+    let end = offset + length;
+    let mut units = 0;
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        if units == offset {
+            start = Some(i);
+        }
+        if units == end {
+            return start.map(|s| &text[s..i]);
+        }
+        units += c.len_utf16() as i64;
+    }
+    if units == end {
+        start.map(|s| &text[s..])
+    } else {
+        None
+    }
+}
+
+/// Gets every url in a message, from both plain urls and text links in its text or caption
+pub fn message_urls(message: &Message) -> Vec<String> {
+    let (text, entities) = match (message.get_text(), message.get_caption()) {
+        (Some(text), _) => (text, message.get_entities()),
+        (None, Some(caption)) => (caption, message.get_caption_entities()),
+        (None, None) => return vec![],
+    };
+    entities
+        .into_iter()
+        .flatten()
+        .filter_map(|e| match e.get_tg_type() {
+            "url" => entity_text(text, e.get_offset(), e.get_length()).map(|v| v.to_owned()),
+            "text_link" => e.get_url().map(|v| v.to_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Gets the normalized domain of a url. Telegram marks urls without a scheme too, so
+/// those are parsed as http
+pub fn url_domain(url: &str) -> Option<String> {
+    let url = if url.contains("://") {
+        Url::parse(url)
+    } else {
+        Url::parse(&format!("http://{}", url))
+    };
+    url.ok()
+        .and_then(|url| url.host_str().map(normalize_domain))
+}
+
+/// Returns true if any link in a message goes to a domain the chat's link rules flag
+pub async fn has_flagged_link(message: &Message) -> Result<bool> {
+    let domains = message_urls(message)
+        .iter()
+        .filter_map(|url| url_domain(url))
+        .collect::<Vec<String>>();
+    if domains.is_empty() {
+        return Ok(false);
+    }
+    let rules = get_link_rules(message.get_chat().get_id()).await?;
+    Ok(domains
+        .iter()
+        .any(|domain| check_domain(domain, &rules).is_some()))
+}
+
+/// Sets whether saving buttons with flagged urls is refused instead of just warned about
+pub async fn set_strict_buttons(chat: &Chat, strict: bool) -> Result<()> {
+    let chat_id = chat.get_id();
+
+    let model = dialogs::ActiveModel {
+        button_url_strict: Set(strict),
+        ..dialogs::ActiveModel::for_chat(chat)
+    };
+
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_column(dialogs::Column::ButtonUrlStrict)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat_id).invalidate().await?;
+    Ok(())
+}
+
+/// Validates every url button against the chat's rules
+pub async fn find_bad_urls(chat: i64, buttons: &InlineKeyboardBuilder) -> Result<Vec<UrlFinding>> {
+    let urls = buttons
+        .get()
+        .iter()
+        .flat_map(|row| row.iter())
+        .filter_map(|b| b.button_url.as_ref())
+        .filter_map(|url| Url::parse(url).ok())
+        .filter_map(|url| url.host_str().map(normalize_domain))
+        .collect::<Vec<String>>();
+
+    if urls.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let rules = get_domain_rules(chat).await?;
+    Ok(urls
+        .into_iter()
+        .filter_map(|domain| {
+            check_domain(&domain, &rules).map(|problem| UrlFinding { domain, problem })
+        })
+        .collect())
+}
+
+/// Checks the buttons about to be saved from a message. The admin saving them is warned
+/// about any suspicious urls, and if the chat has strict mode enabled saving is refused
+pub async fn check_button_urls(message: &Message, buttons: &InlineKeyboardBuilder) -> Result<()> {
+    let chat = message.get_chat();
+    let findings = find_bad_urls(chat.get_id(), buttons).await?;
+    if findings.is_empty() {
+        return Ok(());
+    }
+
+    let lang = get_chat_lang(chat.get_id()).await?;
+    let list = findings
+        .iter()
+        .map(|f| match f.problem {
+            UrlProblem::Denied => lang_fmt!(lang, "buttonurldenied", f.domain),
+            UrlProblem::NotAllowed => lang_fmt!(lang, "buttonurlnotallowed", f.domain),
+            UrlProblem::Lookalike => lang_fmt!(lang, "buttonurllookalike", f.domain),
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let strict = get_dialog(chat)
+        .await?
+        .map(|d| d.button_url_strict)
+        .unwrap_or(false);
+
+    if strict {
+        Err(BotError::speak(
+            lang_fmt!(lang, "buttonurlrefused", list),
+            chat.get_id(),
+            Some(message.get_message_id()),
+        ))
+    } else {
+        message
+            .reply(lang_fmt!(lang, "buttonurlwarn", list))
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rule(domain: &str, allow: bool) -> button_domains::Model {
+        button_domains::Model {
+            chat_id: 0,
+            domain: domain.to_owned(),
+            allow,
+        }
+    }
+
+    #[test]
+    fn telegram_not_lookalike() {
+        for domain in TELEGRAM_DOMAINS {
+            assert!(!is_lookalike(domain));
+        }
+        assert!(!is_lookalike("core.telegram.org"));
+        assert!(!is_lookalike("example.com"));
+        assert!(!is_lookalike("tmetric.com"));
+    }
+
+    #[test]
+    fn lookalikes() {
+        assert!(is_lookalike("tele.gram.org"));
+        assert!(is_lookalike("te1egrarn.org"));
+        assert!(is_lookalike("telegran.me"));
+        assert!(is_lookalike("t.me.example.com"));
+        assert!(is_lookalike("telegram-support.com"));
+    }
+
+    #[test]
+    fn entity_offsets() {
+        let text = "héllo 🦀 example.com/path";
+        assert_eq!(entity_text(text, 9, 16), Some("example.com/path"));
+        assert_eq!(entity_text(text, 0, 5), Some("héllo"));
+        assert_eq!(entity_text(text, 9, 40), None);
+    }
+
+    #[test]
+    fn url_domains() {
+        assert_eq!(
+            url_domain("https://www.Example.com/x"),
+            Some("example.com".to_owned())
+        );
+        assert_eq!(url_domain("t.me/+abcdef"), Some("t.me".to_owned()));
+        assert_eq!(url_domain("not a url"), None);
+    }
+
+    #[test]
+    fn allow_deny() {
+        let rules = vec![rule("example.com", true), rule("bad.example.com", false)];
+        assert_eq!(check_domain("example.com", &rules), None);
+        assert_eq!(check_domain("sub.example.com", &rules), None);
+        assert_eq!(
+            check_domain("bad.example.com", &rules),
+            Some(UrlProblem::Denied)
+        );
+        assert_eq!(
+            check_domain("other.org", &rules),
+            Some(UrlProblem::NotAllowed)
+        );
+        assert_eq!(
+            check_domain("notexample.com", &rules),
+            Some(UrlProblem::NotAllowed)
+        );
+        assert_eq!(check_domain("other.org", &[]), None);
+    }
+}
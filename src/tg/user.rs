@@ -3,15 +3,26 @@
 //! stored in persistent database, and to allow reverse lookup of @ handles
 
 use std::borrow::Cow;
+use std::collections::HashMap;
 
+use crate::persist::core::{chats, username_history, users};
+use crate::persist::db::ReadReplica;
 use crate::persist::redis::RedisStr;
-use crate::statics::{CONFIG, REDIS, TG};
-use crate::util::error::Result;
+use crate::statics::{CONFIG, DB, REDIS, TG};
+use crate::util::error::{BotError, Fail, Result};
 use async_trait::async_trait;
-use botapi::gen_types::{Chat, MessageOrigin, UpdateExt, User};
+use botapi::gen_types::{Chat, ChatMember, MessageOrigin, UpdateExt, User};
+use chrono::Utc;
+use macros::lang_fmt;
 use redis::AsyncCommands;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
 
+use super::boosts::boost_source_user;
+use super::command::{ArgSlice, Context, EntityArg, PopSlice};
 use super::markdown::{Escape, Markup, MarkupType};
+use super::write_behind::{forget_chat, queue_chat, queue_user};
 
 fn get_user_cache_key(user: i64) -> String {
     format!("usrc:{}", user)
@@ -25,6 +36,19 @@ fn get_chat_cache_key(chat: i64) -> String {
     format!("chat:{}", chat)
 }
 
+/// Redis list of usernames waiting to be written to the username history table
+const USERNAME_HISTORY_QUEUE: &str = "unamehist";
+
+/// Most queued usernames written to the database at once
+const USERNAME_HISTORY_BATCH: isize = 500;
+
+/// Most previous usernames shown for a user
+const USERNAME_HISTORY_LIMIT: u64 = 20;
+
+fn get_last_username_key(chat: i64, user: i64) -> String {
+    format!("lastuname:{}:{}", chat, user)
+}
+
 /// Get the user for this bot. This function just caches the getMe telegram API call
 pub async fn get_me() -> Result<User> {
     let me_key = "user_me";
@@ -43,8 +67,14 @@ pub async fn get_me() -> Result<User> {
         .await
 }
 
-/// Record a user in redis for later lookup
+/// Record a user in redis for later lookup, buffering them for the users table
 pub(crate) async fn record_cache_user(user: &User) -> Result<()> {
+    cache_user(user).await?;
+    queue_user(user);
+    Ok(())
+}
+
+async fn cache_user(user: &User) -> Result<()> {
     let key = get_user_cache_key(user.get_id());
     let st = RedisStr::new(user)?;
     if let Some(username) = user.get_username() {
@@ -52,34 +82,177 @@ pub(crate) async fn record_cache_user(user: &User) -> Result<()> {
         REDIS
             .pipe(|p| {
                 p.set(&key, st)
-                    .expire(&key, CONFIG.timing.cache_timeout)
+                    .expire(&key, CONFIG.load().timing.cache_timeout)
                     .set(&uname, user.get_id())
-                    .expire(&uname, CONFIG.timing.cache_timeout)
+                    .expire(&uname, CONFIG.load().timing.cache_timeout)
             })
             .await?;
     } else {
         REDIS
-            .pipe(|p| p.set(&key, st).expire(&key, CONFIG.timing.cache_timeout))
+            .pipe(|p| {
+                p.set(&key, st)
+                    .expire(&key, CONFIG.load().timing.cache_timeout)
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// Queues a username for the history table if it changed since the user was last seen in
+/// the chat. Unchanged usernames are queued again once per cache timeout to keep the time
+/// they were last seen current
+async fn record_username(chat: i64, user: i64, username: &str) -> Result<()> {
+    let key = get_last_username_key(chat, user);
+    let (old,): (Option<String>,) = REDIS
+        .pipe(|p| {
+            p.getset(&key, username)
+                .expire(&key, CONFIG.load().timing.cache_timeout)
+                .ignore()
+        })
+        .await?;
+    if old.as_deref() != Some(username) {
+        let now = Utc::now();
+        let entry = RedisStr::new(&username_history::Model {
+            chat_id: chat,
+            user_id: user,
+            username: username.to_owned(),
+            first_seen: now,
+            last_seen: now,
+        })?;
+        REDIS.sq(|q| q.rpush(USERNAME_HISTORY_QUEUE, entry)).await?;
+    }
+    Ok(())
+}
+
+/// Writes a batch of queued usernames to the history table with a single insert. The batch
+/// is put back at the front of the queue if the insert fails
+pub async fn flush_username_history() -> Result<()> {
+    let (entries,): (Vec<RedisStr>,) = REDIS
+        .pipe(|p| {
+            p.atomic()
+                .lrange(USERNAME_HISTORY_QUEUE, 0, USERNAME_HISTORY_BATCH - 1)
+                .ltrim(USERNAME_HISTORY_QUEUE, USERNAME_HISTORY_BATCH, -1)
+                .ignore()
+        })
+        .await?;
+
+    // postgres refuses to update the same row twice in one insert
+    let mut latest = HashMap::new();
+    for entry in entries.iter() {
+        match entry.get::<username_history::Model>() {
+            Ok(entry) => {
+                latest.insert(
+                    (entry.chat_id, entry.user_id, entry.username.clone()),
+                    entry,
+                );
+            }
+            Err(err) => log::warn!("dropping invalid username history entry: {}", err),
+        }
+    }
+    if latest.is_empty() {
+        return Ok(());
+    }
+
+    let models = latest
+        .into_values()
+        .map(|entry| username_history::ActiveModel {
+            chat_id: Set(entry.chat_id),
+            user_id: Set(entry.user_id),
+            username: Set(entry.username),
+            first_seen: Set(entry.first_seen),
+            last_seen: Set(entry.last_seen),
+        });
+    let res = username_history::Entity::insert_many(models)
+        .on_conflict(
+            OnConflict::columns([
+                username_history::Column::ChatId,
+                username_history::Column::UserId,
+                username_history::Column::Username,
+            ])
+            .update_column(username_history::Column::LastSeen)
+            .to_owned(),
+        )
+        .exec(*DB)
+        .await;
+    if let Err(err) = res {
+        // lpush adds each entry to the front in turn, so reverse them to keep their order
+        let failed = entries.into_iter().rev().collect::<Vec<_>>();
+        REDIS
+            .sq(|q| q.lpush(USERNAME_HISTORY_QUEUE, failed))
             .await?;
+        return Err(err.into());
     }
     Ok(())
 }
 
+/// Gets the usernames a user has had in a chat, most recently seen first
+pub async fn get_username_history(chat: i64, user: i64) -> Result<Vec<username_history::Model>> {
+    let res = username_history::Entity::find()
+        .filter(username_history::Column::ChatId.eq(chat))
+        .filter(username_history::Column::UserId.eq(user))
+        .order_by_desc(username_history::Column::LastSeen)
+        .limit(USERNAME_HISTORY_LIMIT)
+        .all(DB.read())
+        .await?;
+    Ok(res)
+}
+
 /// Record a chat in redis for later lookup
 pub async fn record_cache_chat(chat: &Chat) -> Result<()> {
     let key = get_chat_cache_key(chat.get_id());
     let st = RedisStr::new(chat)?;
     REDIS
-        .pipe(|p| p.set(&key, st).expire(&key, CONFIG.timing.cache_timeout))
+        .pipe(|p| {
+            p.set(&key, st)
+                .expire(&key, CONFIG.load().timing.cache_timeout)
+        })
         .await?;
     Ok(())
 }
 
+/// Record a chat and its last activity in the chats table. The write is buffered and
+/// happens within a few seconds along with other chats
+pub async fn record_db_chat(chat: &Chat) -> Result<()> {
+    queue_chat(chat);
+    Ok(())
+}
+
+/// Remove a chat from the chats table after the bot leaves it
+pub async fn forget_db_chat(chat: i64) -> Result<()> {
+    forget_chat(chat);
+    chats::Entity::delete_by_id(chat).exec(*DB).await?;
+    Ok(())
+}
+
 /// Parse an update for users and chats and record them as needed
 pub async fn record_cache_update(update: &UpdateExt) -> Result<()> {
     if let Some(user) = RecordUser::get_user(update) {
         record_cache_user(user).await?;
     }
+    let seen = match update {
+        UpdateExt::Message(m) | UpdateExt::EditedMessage(m) => {
+            m.get_from().map(|u| (m.get_chat(), u))
+        }
+        UpdateExt::ChatMember(m) => Some((m.get_chat(), m.get_from())),
+        _ => None,
+    };
+    if let Some((chat, user)) = seen {
+        if let Some(username) = user.get_username() {
+            record_username(chat.get_id(), user.get_id(), username).await?;
+        }
+    }
+    match update {
+        UpdateExt::Message(m) | UpdateExt::EditedMessage(m) | UpdateExt::ChannelPost(m) => {
+            record_db_chat(m.get_chat()).await?
+        }
+        UpdateExt::MyChatMember(m) => match m.get_new_chat_member() {
+            ChatMember::ChatMemberLeft(_) | ChatMember::ChatMemberBanned(_) => {
+                forget_db_chat(m.get_chat().get_id()).await?
+            }
+            _ => record_db_chat(m.get_chat()).await?,
+        },
+        _ => (),
+    }
     if let UpdateExt::Message(m) = update {
         if let Some(m) = m.get_reply_to_message() {
             if let Some(user) = m.get_from() {
@@ -108,7 +281,8 @@ pub async fn get_user(user: i64) -> Result<Option<User>> {
     }
 }
 
-/// get a cached user by username
+/// get a cached user by username, falling back to the users table. Users found in the
+/// table are cached again so the next lookup skips the database
 pub async fn get_user_username<T: AsRef<str>>(username: T) -> Result<Option<User>> {
     let username = username.as_ref();
     let key = get_username_cache_key(username);
@@ -126,10 +300,114 @@ pub async fn get_user_username<T: AsRef<str>>(username: T) -> Result<Option<User
         .await?;
 
     if let Some(id) = id {
-        Ok(Some(id.get::<User>()?))
-    } else {
-        Ok(None)
+        return Ok(Some(id.get::<User>()?));
+    }
+
+    let user = users::Entity::find()
+        .filter(users::Column::Username.eq(username))
+        .one(*DB)
+        .await?
+        .map(User::from);
+    if let Some(ref user) = user {
+        cache_user(user).await?;
+    }
+    Ok(user)
+}
+
+/// get a cached user by id, falling back to the users table
+pub async fn get_known_user(user: i64) -> Result<Option<User>> {
+    if let Some(user) = get_user(user).await? {
+        return Ok(Some(user));
     }
+    let user = users::Entity::find_by_id(user)
+        .one(*DB)
+        .await?
+        .map(User::from);
+    if let Some(ref user) = user {
+        cache_user(user).await?;
+    }
+    Ok(user)
+}
+
+/// A user named by a command. Users only given by id may not be known to the bot
+pub enum UserTarget {
+    User(User),
+    Id(i64),
+}
+
+impl UserTarget {
+    pub fn get_id(&self) -> i64 {
+        match self {
+            Self::User(user) => user.get_id(),
+            Self::Id(id) => *id,
+        }
+    }
+}
+
+/// Finds who a command is about, checking the sender of a replied message, then an @ handle
+/// or text mention, then a user id as the first argument. Returns the target, None if the
+/// command doesn't name anyone, along with the arguments after it. Fails with
+/// BotError::UserNotFound if an @ handle isn't known
+pub async fn resolve_user_target(
+    ctx: &Context,
+) -> Result<(Option<UserTarget>, Option<ArgSlice<'_>>)> {
+    let message = ctx.message()?;
+    let command = ctx.try_get()?.command.as_ref();
+    let args = command.map(|c| &c.args);
+
+    if let Some(user) = message.get_reply_to_message().and_then(|m| m.get_from()) {
+        return Ok((
+            Some(UserTarget::User(user.clone())),
+            args.map(|a| a.as_slice()),
+        ));
+    }
+
+    let target = match command.and_then(|c| c.entities.front()) {
+        Some(EntityArg::Mention(name)) => {
+            let user = get_user_username(name)
+                .await?
+                .ok_or(BotError::UserNotFound)?;
+            Some(UserTarget::User(user))
+        }
+        Some(EntityArg::TextMention(user)) => Some(UserTarget::User((*user).clone())),
+        _ => args
+            .and_then(|a| a.args.first())
+            .and_then(|a| a.get_text().parse().ok())
+            .map(UserTarget::Id),
+    };
+    Ok((target, args.and_then(|a| a.pop_slice_tail())))
+}
+
+/// Finds the user a command is about from a reply, an @ handle, a text mention, or a user
+/// id, returning them with the arguments after them. Fails with a localized error if no
+/// user was named or the bot doesn't know them
+pub async fn resolve_user(ctx: &Context) -> Result<(User, Option<ArgSlice<'_>>)> {
+    let (target, args) = match resolve_user_target(ctx).await {
+        Err(BotError::UserNotFound) => return ctx.fail(lang_fmt!(ctx, "usernotfound")),
+        res => res?,
+    };
+    let user = match target {
+        Some(UserTarget::User(user)) => Some(user),
+        Some(UserTarget::Id(id)) => get_known_user(id).await?,
+        None => return ctx.fail(lang_fmt!(ctx, "specifyuser")),
+    };
+    match user {
+        Some(user) => Ok((user, args)),
+        None => ctx.fail(lang_fmt!(ctx, "usernotfound")),
+    }
+}
+
+/// Checks with telegram whether a user is currently a member of a chat, including members who
+/// never sent a message
+pub async fn is_member(chat: i64, user: i64) -> Result<bool> {
+    let member = TG.client.build_get_chat_member(chat, user).build().await?;
+    Ok(match member {
+        ChatMember::ChatMemberMember(_)
+        | ChatMember::ChatMemberAdministrator(_)
+        | ChatMember::ChatMemberOwner(_) => true,
+        ChatMember::ChatMemberRestricted(m) => m.get_is_member(),
+        _ => false,
+    })
 }
 
 /// get a cached chat by chatId
@@ -311,8 +589,8 @@ impl RecordUser for UpdateExt {
             UpdateExt::Invalid => None,
             UpdateExt::MessageReaction(ref reaction) => reaction.get_user(),
             UpdateExt::MessageReactionCount(_) => None,
-            UpdateExt::ChatBoost(_) => None,
-            UpdateExt::RemovedChatBoost(_) => None,
+            UpdateExt::ChatBoost(ref boost) => boost_source_user(boost.get_boost().get_source()),
+            UpdateExt::RemovedChatBoost(ref boost) => boost_source_user(boost.get_source()),
             UpdateExt::DeletedBusinessMessages(_) => None,
             UpdateExt::BusinessConnection(ref c) => Some(c.get_user()),
             UpdateExt::EditedBusinessMessage(_) => None,
@@ -0,0 +1,106 @@
+//! Video chat announcements and reminders. Telegram posts service messages when a video
+//! chat is scheduled, started, or ended, and when members are invited to one. These are
+//! reported to the log channel and, in chats with /vcannounce on, announced in the chat.
+//!
+//! Bots can't schedule video chats themselves, so /vcschedule stores a reminder with the
+//! scheduler instead, posted in the chat when the time comes. A chat can have up to
+//! [`MAX_REMINDERS`] reminders pending.
+
+use botapi::gen_types::Message;
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use macros::lang_fmt;
+
+use crate::util::error::Result;
+use crate::util::string::{get_chat_lang, should_ignore_chat, Lang, Speak};
+use crate::util::time::ChatTime;
+
+use super::dialog::dialog_or_default;
+use super::log_channel::send_log;
+use super::markdown::Escape;
+use super::scheduler::{get_chat_jobs, schedule_job, Job, JobKind};
+use super::user::Username;
+
+/// Most video chat reminders a chat can have pending
+pub const MAX_REMINDERS: usize = 10;
+
+/// Describes a video chat service message, None for any other message
+pub async fn describe_video_chat(lang: &Lang, message: &Message) -> Result<Option<String>> {
+    let from = message
+        .get_from()
+        .map(|user| user.name_humanreadable().escape(false).into_owned())
+        .unwrap_or_default();
+    let text = if message.get_video_chat_started().is_some() {
+        lang_fmt!(lang, "vcstarted", from)
+    } else if let Some(ended) = message.get_video_chat_ended() {
+        let duration = std::time::Duration::from_secs(ended.get_duration().max(0) as u64);
+        lang_fmt!(lang, "vcended", lang.format_duration(duration))
+    } else if let Some(invited) = message.get_video_chat_participants_invited() {
+        let users = invited
+            .get_users()
+            .into_iter()
+            .flatten()
+            .map(|user| user.name_humanreadable().escape(false).into_owned())
+            .join(", ");
+        lang_fmt!(lang, "vcinvited", from, users)
+    } else if let Some(scheduled) = message.get_video_chat_scheduled() {
+        let Some(start) = DateTime::from_timestamp(scheduled.get_start_date(), 0) else {
+            return Ok(None);
+        };
+        let local = ChatTime::get(message.get_chat().get_id()).await?;
+        lang_fmt!(lang, "vcscheduled", from, local.format_lang(&start, lang))
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(text))
+}
+
+/// Logs a video chat service message, and announces it if the chat turned on
+/// /vcannounce. Does nothing for other messages
+pub async fn handle_video_chat(lang: &Lang, message: &Message) -> Result<()> {
+    let Some(text) = describe_video_chat(lang, message).await? else {
+        return Ok(());
+    };
+    let chat = message.get_chat();
+    if let Err(err) = send_log(chat, &text).await {
+        log::debug!("failed to log video chat: {}", err);
+    }
+    if dialog_or_default(chat).await?.announce_video_chats {
+        message.speak(text).await?;
+    }
+    Ok(())
+}
+
+/// Schedules a reminder of a video chat to be posted in a chat. Returns None without
+/// scheduling anything if the chat already has [`MAX_REMINDERS`] reminders pending
+pub async fn schedule_video_chat_reminder(
+    chat: i64,
+    run_at: DateTime<Utc>,
+    note: Option<String>,
+) -> Result<Option<Job>> {
+    let pending = get_chat_jobs(chat)
+        .await?
+        .into_iter()
+        .filter(|job| matches!(job.kind, JobKind::VideoChatReminder { .. }))
+        .count();
+    if pending >= MAX_REMINDERS {
+        return Ok(None);
+    }
+    let job = Job::new(chat, run_at, JobKind::VideoChatReminder { note });
+    schedule_job(&job).await?;
+    Ok(Some(job))
+}
+
+/// Posts a video chat reminder. Run by the scheduler
+pub(crate) async fn run_video_chat_reminder(chat: i64, note: Option<String>) -> Result<()> {
+    if should_ignore_chat(chat).await? {
+        return Ok(());
+    }
+    let lang = get_chat_lang(chat).await?;
+    let text = match note {
+        Some(note) => lang_fmt!(lang, "vcremindernote", note.escape(false)),
+        None => lang_fmt!(lang, "vcreminder"),
+    };
+    chat.speak(text).await?;
+    Ok(())
+}
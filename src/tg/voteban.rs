@@ -0,0 +1,314 @@
+//! Community ban votes. /voteban posts a native, non-anonymous poll asking the chat whether
+//! to remove a member. Answers arrive as poll_answer updates, which don't say which chat the
+//! poll is in, so each open vote is stored in redis under its poll id along with the chat and
+//! the settings it was started with. Once enough members vote yes the target is banned or
+//! muted and the poll is stopped. Votes that don't pass in time are closed by the scheduler.
+//!
+//! To make votes harder to abuse, only members of the chat with accounts older than the
+//! chat's minimum age count, admins can't be voted on, there is only one vote per target at a
+//! time, and any admin able to restrict members can veto a vote with the button on the poll
+
+use botapi::gen_types::{
+    CallbackQuery, EReplyMarkup, InlineKeyboardButtonBuilder, InputPollOptionBuilder, PollAnswer,
+    ReplyParametersBuilder,
+};
+use chrono::{Duration, Utc};
+use macros::lang_fmt;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+
+use crate::persist::core::dialogs;
+use crate::persist::redis::{RedisStr, ToRedisStr};
+use crate::statics::{ME, REDIS, TG};
+use crate::util::error::{Fail, Result};
+use crate::util::string::get_chat_lang;
+
+use super::button::{
+    callback_data, persist_action, ButtonAction, CallbackReply, InlineKeyboardBuilder,
+};
+use super::command::Context;
+use super::dialog::{dialog_or_default, is_chat_member};
+use super::join_requests::account_older_than;
+use super::permissions::IsAdmin;
+use super::scheduler::{schedule_job, Job, JobKind};
+use super::user::{GetChat, GetUser, Username};
+
+/// Seconds a vote stays open if the chat didn't set a duration
+pub const DEFAULT_DURATION: i64 = 60 * 10;
+
+/// Days old an account has to be to start or vote in a vote if the chat didn't set an age
+pub const DEFAULT_MIN_AGE: i32 = 7;
+
+/// Extra seconds vote state is kept after a vote ends, so the scheduler can close it
+const EXPIRE_GRACE: i64 = 60;
+
+#[inline(always)]
+fn get_vote_key(poll: &str) -> String {
+    format!("vban:{}", poll)
+}
+
+#[inline(always)]
+fn get_ballots_key(poll: &str) -> String {
+    format!("vbanv:{}", poll)
+}
+
+#[inline(always)]
+fn get_target_key(chat: i64, user: i64) -> String {
+    format!("vbant:{}:{}", chat, user)
+}
+
+/// An open vote, stored under the id of its poll
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VoteBan {
+    pub chat: i64,
+    pub target: i64,
+    /// name of the target when the vote started
+    pub name: String,
+    /// id of the message with the poll
+    pub message: i64,
+    /// yes votes needed to pass
+    pub threshold: i32,
+    /// mute instead of ban if the vote passes
+    pub mute: bool,
+    /// minimum account age in days for votes to count
+    pub min_age: i32,
+}
+
+/// Gets how long a chat's votes stay open
+pub fn vote_duration(dialog: &dialogs::Model) -> Duration {
+    Duration::try_seconds(dialog.voteban_duration.unwrap_or(DEFAULT_DURATION))
+        .unwrap_or_else(|| Duration::try_seconds(DEFAULT_DURATION).unwrap())
+}
+
+/// Removes a vote so it can't pass or close twice, returning it if it was still open
+async fn take_vote(poll: &str) -> Result<Option<VoteBan>> {
+    let key = get_vote_key(poll);
+    let ballots = get_ballots_key(poll);
+    let (vote, _, _): (Option<RedisStr>, (), ()) = REDIS
+        .pipe(|q| q.atomic().get(&key).del(&key).del(&ballots))
+        .await?;
+    let Some(vote) = vote else {
+        return Ok(None);
+    };
+    let vote: VoteBan = vote.get()?;
+    REDIS
+        .sq(|q| q.del(get_target_key(vote.chat, vote.target)))
+        .await?;
+    Ok(Some(vote))
+}
+
+async fn stop_poll(vote: &VoteBan) {
+    if let Err(err) = TG
+        .client
+        .build_stop_poll(vote.chat, vote.message)
+        .build()
+        .await
+    {
+        log::debug!("failed to stop voteban poll: {}", err);
+    }
+}
+
+async fn reply_to_poll(vote: &VoteBan, text: &str) -> Result<()> {
+    TG.client
+        .build_send_message(vote.chat, text)
+        .reply_parameters(&ReplyParametersBuilder::new(vote.message).build())
+        .build()
+        .await?;
+    Ok(())
+}
+
+/// Starts a vote to remove a member of the chat the command was sent in
+pub async fn start_vote(ctx: &Context, target: i64) -> Result<()> {
+    let chat = ctx.try_get()?.chat;
+    let dialog = dialog_or_default(chat).await?;
+    let Some(threshold) = dialog.voteban_threshold else {
+        return ctx.fail(lang_fmt!(ctx, "votebanoff"));
+    };
+    let min_age = dialog.voteban_min_age.unwrap_or(DEFAULT_MIN_AGE);
+
+    let from = ctx.message()?.get_from();
+    if let Some(from) = from {
+        if !from.is_admin(chat).await? && !account_older_than(from.get_id(), min_age.into()) {
+            return ctx.fail(lang_fmt!(ctx, "votebantooyoung"));
+        }
+    }
+    if target == ME.get().unwrap().get_id() {
+        return ctx.fail(lang_fmt!(ctx, "banmyself"));
+    }
+    if target.is_admin(chat).await? {
+        return ctx.fail(lang_fmt!(ctx, "banadmin"));
+    }
+
+    let duration = vote_duration(&dialog);
+    let expire = duration.num_seconds() + EXPIRE_GRACE;
+    let target_key = get_target_key(chat.get_id(), target);
+    let started = REDIS.set_nx_ex(&target_key, expire).await?;
+    if !started {
+        return ctx.fail(lang_fmt!(ctx, "votebanrunning"));
+    }
+
+    let name = target.cached_name().await?.into_owned();
+    let question = if dialog.voteban_mute {
+        lang_fmt!(ctx, "votemutequestion", name)
+    } else {
+        lang_fmt!(ctx, "votebanquestion", name)
+    };
+    let options = vec![
+        InputPollOptionBuilder::new(lang_fmt!(ctx, "votebanyes")).build(),
+        InputPollOptionBuilder::new(lang_fmt!(ctx, "votebanno")).build(),
+    ];
+
+    let veto = InlineKeyboardButtonBuilder::new(lang_fmt!(ctx, "votebanveto"))
+        .set_callback_data(callback_data(None))
+        .build();
+    let mut buttons = InlineKeyboardBuilder::default();
+    buttons.button(veto.clone());
+
+    let message = TG
+        .client
+        .build_send_poll(chat.get_id(), &question, &options)
+        .is_anonymous(false)
+        .reply_markup(&EReplyMarkup::InlineKeyboardMarkup(buttons.build()))
+        .build()
+        .await;
+    let message = match message {
+        Ok(message) => message,
+        Err(err) => {
+            REDIS.sq(|q| q.del(&target_key)).await?;
+            return Err(err.into());
+        }
+    };
+    let Some(poll) = message.get_poll() else {
+        return Ok(());
+    };
+    let poll = poll.get_id().to_owned();
+
+    let vote = VoteBan {
+        chat: chat.get_id(),
+        target,
+        name,
+        message: message.get_message_id(),
+        threshold,
+        mute: dialog.voteban_mute,
+        min_age,
+    };
+    let key = get_vote_key(&poll);
+    REDIS
+        .try_pipe(|q| Ok(q.set(&key, vote.to_redis()?).expire(&key, expire)))
+        .await?;
+    persist_action(&veto, &ButtonAction::VoteBanVeto { poll: poll.clone() }).await?;
+    let job = Job::new(
+        chat.get_id(),
+        Utc::now() + duration,
+        JobKind::CloseVoteBan { poll },
+    );
+    schedule_job(&job).await?;
+    Ok(())
+}
+
+/// Bans or mutes the target of a vote that passed
+async fn pass_vote(ctx: &Context, vote: VoteBan) -> Result<()> {
+    stop_poll(&vote).await;
+    let lang = get_chat_lang(vote.chat).await?;
+    let Some(chat) = vote.chat.get_chat().await? else {
+        return Ok(());
+    };
+    let text = if vote.mute {
+        ctx.mute(vote.target, &chat, None).await?;
+        lang_fmt!(lang, "votemutepassed", vote.name)
+    } else {
+        TG.client
+            .build_ban_chat_member(vote.chat, vote.target)
+            .build()
+            .await?;
+        lang_fmt!(lang, "votebanpassed", vote.name)
+    };
+    reply_to_poll(&vote, &text).await
+}
+
+/// Counts an answer to a vote's poll, passing the vote once enough members voted yes.
+/// Answers from members who aren't eligible are ignored
+pub async fn handle_poll_answer(ctx: &Context, answer: &PollAnswer) -> Result<()> {
+    let poll = answer.get_poll_id();
+    let key = get_vote_key(poll);
+    let vote: Option<RedisStr> = REDIS.sq(|q| q.get(&key)).await?;
+    let Some(vote) = vote else {
+        return Ok(());
+    };
+    let vote: VoteBan = vote.get()?;
+
+    // anonymous admins vote as the chat, they can veto instead
+    let Some(user) = answer.get_user() else {
+        return Ok(());
+    };
+    let user = user.get_id();
+    let ballots = get_ballots_key(poll);
+    if answer.get_option_ids().is_empty() {
+        REDIS.sq(|q| q.hdel(&ballots, user)).await?;
+        return Ok(());
+    }
+    if user == vote.target
+        || !account_older_than(user, vote.min_age.into())
+        || !is_chat_member(user, vote.chat).await?
+    {
+        return Ok(());
+    }
+
+    let yes = answer.get_option_ids().contains(&0);
+    let ttl: i64 = REDIS.sq(|q| q.ttl(&key)).await?;
+    let (_, _, ballots): ((), (), Vec<bool>) = REDIS
+        .pipe(|q| {
+            q.hset(&ballots, user, yes)
+                .expire(&ballots, ttl.max(1))
+                .hvals(&ballots)
+        })
+        .await?;
+    let count = ballots.into_iter().filter(|yes| *yes).count();
+    if count >= vote.threshold.max(1) as usize {
+        if let Some(vote) = take_vote(poll).await? {
+            pass_vote(ctx, vote).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Closes a vote that didn't pass in time
+pub async fn close_vote(poll: &str) -> Result<()> {
+    if let Some(vote) = take_vote(poll).await? {
+        stop_poll(&vote).await;
+        let lang = get_chat_lang(vote.chat).await?;
+        reply_to_poll(&vote, &lang_fmt!(lang, "votebanfailed", vote.name)).await?;
+    }
+    Ok(())
+}
+
+/// Handles an admin pushing the veto button on a vote
+pub(crate) async fn veto_pushed(
+    callback: &CallbackQuery,
+    poll: &str,
+) -> Result<(bool, CallbackReply)> {
+    let key = get_vote_key(poll);
+    let vote: Option<RedisStr> = REDIS.sq(|q| q.get(&key)).await?;
+    let Some(vote) = vote else {
+        return Ok((true, CallbackReply::default()));
+    };
+    let vote: VoteBan = vote.get()?;
+    let lang = get_chat_lang(vote.chat).await?;
+    let Some(chat) = vote.chat.get_chat().await? else {
+        return Ok((true, CallbackReply::default()));
+    };
+    let from = callback.get_from();
+    if !from.get_permissions(&chat).await?.can_restrict_members {
+        return Ok((
+            false,
+            CallbackReply::alert(lang_fmt!(lang, "votebannoveto")),
+        ));
+    }
+
+    if let Some(vote) = take_vote(poll).await? {
+        stop_poll(&vote).await;
+        let text = lang_fmt!(lang, "votebanvetoed", vote.name, from.name_humanreadable());
+        reply_to_poll(&vote, &text).await?;
+    }
+    Ok((true, CallbackReply::default()))
+}
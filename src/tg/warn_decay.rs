@@ -0,0 +1,101 @@
+//! Expiring warns. Chats with a /warntime give each warn an expiry, and a background task
+//! periodically deletes warns past it, drops the cached warns of everyone affected, and
+//! posts in each chat's log channel whose warns decayed. Until the task gets to them
+//! expired warns are left out of /warns but still in the database.
+//!
+//! Only one instance sweeps at a time, claimed in redis, so bots sharing a database don't
+//! log the same warns twice
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use chrono::Utc;
+use macros::lang_fmt;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QuerySelect};
+
+use crate::persist::admin::warns;
+use crate::statics::{DB, REDIS};
+use crate::util::error::Result;
+use crate::util::string::get_chat_lang;
+
+use super::admin_helpers::invalidate_warns;
+use super::bots::{main_bot, with_bot};
+use super::log_channel::send_log;
+use super::markdown::Escape;
+use super::user::{GetChat, GetUser};
+
+/// Interval between sweeps for expired warns
+const DECAY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Most warns deleted in a single sweep, the rest wait for the next one
+const BATCH_SIZE: u64 = 1000;
+
+/// Key claimed by the instance running the current sweep
+const DECAY_CLAIM_KEY: &str = "warndecay:claim";
+
+/// Deletes expired warns, returning how many each user lost in each chat
+async fn delete_expired() -> Result<BTreeMap<(i64, i64), usize>> {
+    let expired = warns::Entity::find()
+        .filter(warns::Column::Expires.lt(Utc::now()))
+        .limit(BATCH_SIZE)
+        .all(*DB)
+        .await?;
+    if expired.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    warns::Entity::delete_many()
+        .filter(warns::Column::Id.is_in(expired.iter().map(|warn| warn.id)))
+        .exec(*DB)
+        .await?;
+
+    let mut decayed = BTreeMap::new();
+    for warn in expired {
+        *decayed.entry((warn.chat_id, warn.user_id)).or_insert(0) += 1;
+    }
+    Ok(decayed)
+}
+
+async fn log_decay(chat: i64, user: i64, count: usize) -> Result<()> {
+    let Some(chat) = chat.get_chat().await? else {
+        return Ok(());
+    };
+    let lang = get_chat_lang(chat.get_id()).await?;
+    let name = user.cached_name().await?;
+    send_log(
+        &chat,
+        lang_fmt!(lang, "warnsdecayed", count, name.escape(false)),
+    )
+    .await
+}
+
+/// Deletes expired warns and logs them in their chats
+async fn decay_warns() -> Result<()> {
+    let claimed = REDIS
+        .set_nx_ex(DECAY_CLAIM_KEY, DECAY_INTERVAL.as_secs() as i64 - 1)
+        .await?;
+    if !claimed {
+        return Ok(());
+    }
+
+    for ((chat, user), count) in delete_expired().await? {
+        invalidate_warns(chat, user).await?;
+        if let Err(err) = log_decay(chat, user, count).await {
+            log::debug!("failed to log decayed warns in {}: {}", chat, err);
+        }
+    }
+    Ok(())
+}
+
+/// Start the background task deleting expired warns
+pub fn spawn_warn_decay() -> tokio::task::JoinHandle<()> {
+    tokio::spawn(with_bot(main_bot(), async move {
+        let mut interval = tokio::time::interval(DECAY_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = decay_warns().await {
+                log::warn!("warn decay failed: {}", err);
+                err.record_stats();
+            }
+        }
+    }))
+}
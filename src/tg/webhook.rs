@@ -0,0 +1,56 @@
+//! Receiving updates through a webhook. Telegram posts each update as json to the bot's
+//! webhook url, which a reverse proxy forwards to the socket the bot listens on. Updates are
+//! answered as soon as they are received and handled on their own task, since telegram
+//! resends any update it doesn't get an answer to in time.
+//!
+//! Each time the webhook is registered telegram is given a new random secret, which it sends
+//! back with every update. Requests without it didn't come from telegram and are rejected
+
+use botapi::gen_types::Update;
+use rand::distributions::{Alphanumeric, DistString};
+use rand::thread_rng;
+use warp::http::StatusCode;
+use warp::Filter;
+
+use crate::statics::CONFIG;
+use crate::util::error::Result;
+
+use super::bots::webhook_target;
+use super::client::TgClient;
+
+/// Header telegram sends the webhook's secret in
+const SECRET_HEADER: &str = "X-Telegram-Bot-Api-Secret-Token";
+
+/// Length of the secret, telegram allows up to 256 characters
+const SECRET_LEN: usize = 64;
+
+/// Registers a bot's webhook with telegram and handles updates posted to it forever
+pub(crate) async fn serve_webhook(
+    bot: &'static TgClient,
+    allowed: Option<Vec<String>>,
+) -> Result<()> {
+    let (url, listen) = webhook_target(bot)?;
+    let secret = Alphanumeric.sample_string(&mut thread_rng(), SECRET_LEN);
+    let mut call = bot
+        .client
+        .build_set_webhook(&url)
+        .secret_token(&secret)
+        .drop_pending_updates(CONFIG.load().webhook.drop_pending_updates);
+    if let Some(ref allowed) = allowed {
+        call = call.allowed_updates(allowed);
+    }
+    call.build().await?;
+
+    let route = warp::post()
+        .and(warp::header::optional::<String>(SECRET_HEADER))
+        .and(warp::body::json())
+        .map(move |token: Option<String>, update: Update| {
+            if token.as_deref() != Some(secret.as_str()) {
+                return warp::reply::with_status(warp::reply(), StatusCode::UNAUTHORIZED);
+            }
+            bot.spawn_update(update);
+            warp::reply::with_status(warp::reply(), StatusCode::OK)
+        });
+    warp::serve(route).run(listen).await;
+    Ok(())
+}
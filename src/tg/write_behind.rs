@@ -0,0 +1,164 @@
+//! Write-behind buffering for users and chats. Nearly every update carries a user and a
+//! chat worth keeping in the database, so rather than writing them one at a time they are
+//! kept in memory, deduplicated by id, and written every few seconds with a single insert
+//! per table. Queued usernames for the history table are written by the same task.
+//! Records that fail to write are queued again for the next flush, and anything still
+//! buffered is written when the bot shuts down
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use botapi::gen_types::{Chat, User};
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::{NotSet, Set};
+use sea_orm::EntityTrait;
+
+use crate::persist::core::{chats, users};
+use crate::statics::DB;
+use crate::util::error::Result;
+
+use super::user::flush_username_history;
+
+/// Interval between writes of buffered records
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Most rows written in a single insert, keeping well under postgres' parameter limit
+const INSERT_CHUNK: usize = 1000;
+
+lazy_static! {
+    static ref USERS: Mutex<HashMap<i64, User>> = Mutex::new(HashMap::new());
+    static ref CHATS: Mutex<HashMap<i64, (Chat, DateTime<Utc>)>> = Mutex::new(HashMap::new());
+}
+
+/// Buffers a user to be written to the users table, replacing any older copy
+pub fn queue_user(user: &User) {
+    USERS.lock().unwrap().insert(user.get_id(), user.clone());
+}
+
+/// Buffers a chat and the time it was active to be written to the chats table
+pub fn queue_chat(chat: &Chat) {
+    CHATS
+        .lock()
+        .unwrap()
+        .insert(chat.get_id(), (chat.clone(), Utc::now()));
+}
+
+/// Drops a buffered chat so it isn't written back after being removed from the database
+pub fn forget_chat(chat: i64) {
+    CHATS.lock().unwrap().remove(&chat);
+}
+
+/// Puts records that failed to write back in their buffer, unless a newer copy was queued
+/// in the meantime
+fn requeue<T>(buffer: &Mutex<HashMap<i64, T>>, failed: impl Iterator<Item = (i64, T)>) {
+    let mut buffer = buffer.lock().unwrap();
+    for (id, value) in failed {
+        buffer.entry(id).or_insert(value);
+    }
+}
+
+async fn flush_users() -> Result<()> {
+    let pending = std::mem::take(&mut *USERS.lock().unwrap())
+        .into_values()
+        .collect_vec();
+    for (i, chunk) in pending.chunks(INSERT_CHUNK).enumerate() {
+        let models = chunk
+            .iter()
+            .map(|user| users::ActiveModel {
+                user_id: Set(user.get_id()),
+                first_name: Set(user.get_first_name().to_owned()),
+                last_name: Set(user.get_last_name().map(|v| v.to_owned())),
+                username: Set(user.get_username().map(|v| v.to_owned())),
+                is_bot: Set(user.get_is_bot()),
+            })
+            .collect_vec();
+        let res = users::Entity::insert_many(models)
+            .on_conflict(
+                OnConflict::column(users::Column::UserId)
+                    .update_columns([
+                        users::Column::FirstName,
+                        users::Column::LastName,
+                        users::Column::Username,
+                        users::Column::IsBot,
+                    ])
+                    .to_owned(),
+            )
+            .exec_without_returning(*DB)
+            .await;
+        if let Err(err) = res {
+            let failed = pending[i * INSERT_CHUNK..].iter();
+            requeue(&USERS, failed.map(|user| (user.get_id(), user.clone())));
+            return Err(err.into());
+        }
+    }
+    Ok(())
+}
+
+async fn flush_chats() -> Result<()> {
+    let pending = std::mem::take(&mut *CHATS.lock().unwrap())
+        .into_values()
+        .collect_vec();
+    for (i, chunk) in pending.chunks(INSERT_CHUNK).enumerate() {
+        let models = chunk
+            .iter()
+            .map(|(chat, seen)| chats::ActiveModel {
+                chat_id: Set(chat.get_id()),
+                chat_type: Set(chat.get_tg_type().to_owned()),
+                broadcast: NotSet,
+                title: Set(chat.get_title().map(|v| v.to_owned())),
+                last_activity: Set(*seen),
+            })
+            .collect_vec();
+        let res = chats::Entity::insert_many(models)
+            .on_conflict(
+                OnConflict::column(chats::Column::ChatId)
+                    .update_columns([
+                        chats::Column::ChatType,
+                        chats::Column::Title,
+                        chats::Column::LastActivity,
+                    ])
+                    .to_owned(),
+            )
+            .exec_without_returning(*DB)
+            .await;
+        if let Err(err) = res {
+            let failed = pending[i * INSERT_CHUNK..].iter();
+            requeue(
+                &CHATS,
+                failed.map(|(chat, seen)| (chat.get_id(), (chat.clone(), *seen))),
+            );
+            return Err(err.into());
+        }
+    }
+    Ok(())
+}
+
+/// Writes everything buffered to the database. Each table is written even if another fails
+pub async fn flush() {
+    let results = [
+        ("users", flush_users().await),
+        ("chats", flush_chats().await),
+        ("username history", flush_username_history().await),
+    ];
+    for (name, res) in results {
+        if let Err(err) = res {
+            log::warn!("failed to write buffered {}: {}", name, err);
+            err.record_stats();
+        }
+    }
+}
+
+/// Start the background task writing buffered records to the database
+pub fn spawn_write_behind() -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            flush().await;
+        }
+    })
+}
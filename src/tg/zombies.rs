@@ -0,0 +1,184 @@
+//! Finding deleted accounts. Telegram has no way to list a chat's members, so the members
+//! the bot has seen in a chat are checked one by one, throttled so big chats don't hit the
+//! flood limits. Deleted accounts keep their place in the chat until someone removes them,
+//! which /zombies clean does. Scans run as scheduled jobs and edit a status message
+//! as they go, only one at a time per chat
+
+use botapi::gen_types::{ChatMember, Message, User};
+use itertools::Itertools;
+use macros::lang_fmt;
+use redis::AsyncCommands;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+use crate::persist::core::chat_members;
+use crate::statics::{BAN_GOVERNER, DB, MEMBER_SCAN_GOVERNER, REDIS, TG};
+use crate::util::error::Result;
+use crate::util::string::Lang;
+
+use super::admin_helpers::kick;
+
+/// How many members are checked or kicked between progress updates
+const PROGRESS_INTERVAL: usize = 50;
+
+/// Longest a scan can hold a chat's lock, in case the bot restarts during one
+pub const SCAN_LOCK_SECONDS: i64 = 60 * 60;
+
+/// Most user ids listed by a dry run
+const DRY_RUN_IDS: usize = 50;
+
+#[inline(always)]
+fn get_scan_key(chat: i64) -> String {
+    format!("zombies:{}", chat)
+}
+
+/// Checks if a user's account was deleted, going by telegram's flag rather than the name
+/// since anyone can call themselves "Deleted Account"
+pub fn is_deleted(user: &User) -> bool {
+    user.get_is_deleted().unwrap_or(false)
+}
+
+/// Claims a chat for a scan, returning false if one is already running there
+pub async fn claim_scan(chat: i64) -> Result<bool> {
+    let key = get_scan_key(chat);
+    REDIS.set_nx_ex(&key, SCAN_LOCK_SECONDS).await
+}
+
+/// Lets another scan run in a chat
+pub async fn release_scan(chat: i64) -> Result<()> {
+    REDIS.sq(|q| q.del(&get_scan_key(chat))).await?;
+    Ok(())
+}
+
+/// Shows a scan's progress. Failing to edit the status, say because someone deleted it,
+/// doesn't stop the scan
+async fn edit_status(status: &Message, text: &str) {
+    if let Err(err) = TG
+        .client
+        .build_edit_message_text(text)
+        .chat_id(status.get_chat().get_id())
+        .message_id(status.get_message_id())
+        .build()
+        .await
+    {
+        log::debug!("failed to edit zombie scan status: {}", err);
+    }
+}
+
+/// Checks every member the bot has seen in a chat, returning the ones with deleted accounts
+/// still in the chat
+pub async fn find_zombies(chat: i64, status: &Message, lang: &Lang) -> Result<Vec<i64>> {
+    let members = chat_members::Entity::find()
+        .filter(chat_members::Column::ChatId.eq(chat))
+        .all(*DB)
+        .await?;
+    let total = members.len();
+    let mut zombies = Vec::new();
+    for (i, member) in members.into_iter().enumerate() {
+        MEMBER_SCAN_GOVERNER.until_ready().await;
+        let member = match TG
+            .client
+            .build_get_chat_member(chat, member.user_id)
+            .build()
+            .await
+        {
+            Ok(member) => member,
+            Err(err) => {
+                log::debug!("failed to get member {}: {}", member.user_id, err);
+                continue;
+            }
+        };
+        let user = match member {
+            ChatMember::ChatMemberMember(ref m) => m.get_user(),
+            ChatMember::ChatMemberRestricted(ref m) if m.get_is_member() => m.get_user(),
+            _ => continue,
+        };
+        if is_deleted(user) {
+            zombies.push(user.get_id());
+        }
+
+        if (i + 1) % PROGRESS_INTERVAL == 0 {
+            let text = lang_fmt!(
+                lang,
+                "zombiesscanning",
+                lang.format_number((i + 1) as u64),
+                lang.format_number(total as u64),
+                lang.format_number(zombies.len() as u64)
+            );
+            edit_status(status, &text).await;
+        }
+    }
+    Ok(zombies)
+}
+
+/// Kicks deleted accounts from a chat, returning how many were kicked
+pub async fn kick_zombies(
+    chat: i64,
+    zombies: &[i64],
+    status: &Message,
+    lang: &Lang,
+) -> Result<usize> {
+    let mut kicked = 0;
+    for (i, &zombie) in zombies.iter().enumerate() {
+        BAN_GOVERNER.until_ready().await;
+        match kick(zombie, chat).await {
+            Ok(()) => kicked += 1,
+            Err(err) => log::debug!("failed to kick zombie {}: {}", zombie, err),
+        }
+
+        if (i + 1) % PROGRESS_INTERVAL == 0 {
+            let text = lang_fmt!(
+                lang,
+                "zombieskicking",
+                lang.format_number((i + 1) as u64),
+                lang.format_number(zombies.len() as u64)
+            );
+            edit_status(status, &text).await;
+        }
+    }
+
+    chat_members::Entity::delete_many()
+        .filter(chat_members::Column::ChatId.eq(chat))
+        .filter(chat_members::Column::UserId.is_in(zombies.iter().copied()))
+        .exec(*DB)
+        .await?;
+    Ok(kicked)
+}
+
+/// Scans a chat for deleted accounts and kicks them if `clean` is set, reporting the result
+/// in the status message. With `dry_run` nobody is kicked, the result lists who would be
+pub async fn run_zombie_scan(
+    chat: i64,
+    clean: bool,
+    dry_run: bool,
+    status: Message,
+    lang: Lang,
+) -> Result<()> {
+    let zombies = find_zombies(chat, &status, &lang).await?;
+    let text = if zombies.is_empty() {
+        lang_fmt!(lang, "nozombies")
+    } else if clean && dry_run {
+        let ids = zombies.iter().take(DRY_RUN_IDS).join(", ");
+        lang_fmt!(
+            lang,
+            "zombiesdryrun",
+            lang.format_number(zombies.len() as u64),
+            ids
+        )
+    } else if clean {
+        let kicked = kick_zombies(chat, &zombies, &status, &lang).await?;
+        lang_fmt!(
+            lang,
+            "zombiescleaned",
+            lang.format_number(kicked as u64),
+            lang.format_number(zombies.len() as u64)
+        )
+    } else {
+        lang_fmt!(
+            lang,
+            "zombiesfound",
+            lang.format_number(zombies.len() as u64)
+        )
+    };
+    edit_status(&status, &text).await;
+    Ok(())
+}
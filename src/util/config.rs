@@ -0,0 +1,262 @@
+//! Reloading the config file without restarting. The file passed with --config is read again
+//! and validated before it replaces the running config. Settings read each time they are used,
+//! like sudo users, timing, and external banlists, take effect straight away. Settings only
+//! read at startup, like the bot token, database connections, and webhook, keep their running
+//! values until the next restart and are reported as such
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use confy::load_path;
+use itertools::Itertools;
+use serde_json::Value;
+
+use crate::statics::{Config, ARGS, CONFIG};
+use crate::util::error::{BotError, Result};
+
+/// Top level sections that are only read at startup
+const RESTART_ONLY: [&str; 9] = [
+    "bot_token",
+    "modules",
+    "persistence",
+    "webhook",
+    "logging",
+    "compute_threads",
+    "spam",
+    "callback_secret",
+    "clones",
+];
+
+/// `callback_secret` from the example configs, which anyone could sign buttons with
+const EXAMPLE_SECRET: &str = "changeme";
+
+/// Top level sections whose values are never shown
+const SECRET: [&str; 4] = ["bot_token", "persistence", "callback_secret", "clones"];
+
+/// A setting that differs between the running config and the reloaded file
+pub struct ConfigChange {
+    /// dotted path of the setting, like timing.cache_timeout
+    pub name: String,
+    pub old: String,
+    pub new: String,
+
+    /// false if the setting only changes after a restart
+    pub applied: bool,
+}
+
+fn section(name: &str) -> &str {
+    name.split('.').next().unwrap_or(name)
+}
+
+/// Flattens a serialized config into dotted paths and printable values. Lists are sorted
+/// since sets serialize in no particular order
+fn flatten(prefix: &str, value: &Value, out: &mut BTreeMap<String, String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let name = if prefix.is_empty() {
+                    key.to_owned()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&name, value, out);
+            }
+        }
+        Value::Array(values) => {
+            let list = values.iter().map(|v| v.to_string()).sorted().join(", ");
+            out.insert(prefix.to_owned(), format!("[{}]", list));
+        }
+        Value::String(value) => {
+            out.insert(prefix.to_owned(), value.to_owned());
+        }
+        Value::Null => {
+            out.insert(prefix.to_owned(), "none".to_owned());
+        }
+        value => {
+            out.insert(prefix.to_owned(), value.to_string());
+        }
+    }
+}
+
+fn flatten_config(config: &Config) -> Result<BTreeMap<String, String>> {
+    let mut out = BTreeMap::new();
+    flatten("", &serde_json::to_value(config)?, &mut out);
+    Ok(out)
+}
+
+/// Lists every setting that differs between two configs
+pub fn diff_config(old: &Config, new: &Config) -> Result<Vec<ConfigChange>> {
+    let old = flatten_config(old)?;
+    let mut new = flatten_config(new)?;
+    let mut changes = Vec::new();
+    for (name, old_value) in old {
+        let new_value = new.remove(&name).unwrap_or_else(|| "none".to_owned());
+        if old_value != new_value {
+            changes.push((name, old_value, new_value));
+        }
+    }
+    changes.extend(
+        new.into_iter()
+            .map(|(name, v)| (name, "none".to_owned(), v)),
+    );
+
+    let changes = changes
+        .into_iter()
+        .map(|(name, old, new)| {
+            let (old, new) = if SECRET.contains(&section(&name)) {
+                ("hidden".to_owned(), "hidden".to_owned())
+            } else {
+                (old, new)
+            };
+            ConfigChange {
+                applied: !RESTART_ONLY.contains(&section(&name)),
+                name,
+                old,
+                new,
+            }
+        })
+        .collect();
+    Ok(changes)
+}
+
+/// Rejects settings that would break the bot if applied
+pub fn validate_config(config: &Config) -> Result<()> {
+    let timing = &config.timing;
+    let positive = [
+        ("timing.cache_timeout", timing.cache_timeout),
+        (
+            "timing.antifloodwait_count",
+            timing.antifloodwait_count as i64,
+        ),
+        ("timing.antifloodwait_time", timing.antifloodwait_time),
+        ("timing.ignore_chat_time", timing.ignore_chat_time),
+        ("timing.callback_burst", timing.callback_burst as i64),
+        ("timing.command_edit_time", timing.command_edit_time),
+        ("external_bans.cache_time", config.external_bans.cache_time),
+    ];
+    if let Some((name, _)) = positive.iter().find(|(_, v)| *v <= 0) {
+        return Err(BotError::generic(format!(
+            "{} must be greater than 0",
+            name
+        )));
+    }
+    if timing.callback_interval < 0 {
+        return Err(BotError::generic(
+            "timing.callback_interval can't be negative",
+        ));
+    }
+
+    let urls =
+        std::iter::once(&config.external_bans.cas_url).chain(config.external_bans.banlists.iter());
+    for url in urls {
+        reqwest::Url::parse(&url.replace("{user}", "0"))
+            .map_err(|err| BotError::generic(format!("invalid banlist url {}: {}", url, err)))?;
+    }
+    Ok(())
+}
+
+/// Rejects a missing `callback_secret` or one left at the example value. Buttons and deep links
+/// are signed with it, so it has to be private and stay the same across restarts. Only
+/// checked at startup since reloading never changes it
+pub fn validate_callback_secret(config: &Config) -> Result<()> {
+    match config.callback_secret.as_deref().map(str::trim) {
+        None | Some("") => Err(BotError::generic("callback_secret must be set")),
+        Some(EXAMPLE_SECRET) => Err(BotError::generic(
+            "callback_secret is still the example value, set it to a random string",
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Copies the settings only read at startup from the running config so the config always
+/// matches what the bot is actually using
+fn keep_startup_settings(config: &mut Config, running: &Config) {
+    config.bot_token = running.bot_token.clone();
+    config.modules = running.modules.clone();
+    config.persistence = running.persistence.clone();
+    config.webhook = running.webhook.clone();
+    config.logging = running.logging.clone();
+    config.compute_threads = running.compute_threads;
+    config.spam = running.spam.clone();
+    config.callback_secret = running.callback_secret.clone();
+    config.clones = running.clones.clone();
+}
+
+/// Reads the config file again and swaps it in, returning what changed. Nothing is changed
+/// if the file can't be read or doesn't validate
+pub fn reload_config() -> Result<Vec<ConfigChange>> {
+    let path = &ARGS
+        .get()
+        .ok_or_else(|| BotError::generic("no config file to reload"))?
+        .config;
+    let mut config: Config = load_path(path)
+        .map_err(|err| BotError::generic(format!("failed to read config: {}", err)))?;
+    validate_config(&config)?;
+
+    let running = CONFIG.load_full();
+    let changes = diff_config(&running, &config)?;
+    keep_startup_settings(&mut config, &running);
+    CONFIG.store(Arc::new(config));
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diff_reports_changes() {
+        let old = Config::default();
+        let mut new = Config::default();
+        new.admin.sudo_users.insert(1234);
+        new.timing.cache_timeout = 60;
+        new.bot_token = "other".to_owned();
+
+        let changes = diff_config(&old, &new).unwrap();
+        let names = changes.iter().map(|c| c.name.as_str()).collect_vec();
+        assert_eq!(
+            names,
+            vec!["admin.sudo_users", "bot_token", "timing.cache_timeout"]
+        );
+        assert!(changes[0].applied);
+        assert_eq!(changes[0].new, "[1234]");
+        assert!(!changes[1].applied);
+        assert_eq!(changes[1].new, "hidden");
+        assert_eq!(changes[2].new, "60");
+    }
+
+    #[test]
+    fn diff_ignores_set_order() {
+        let mut old = Config::default();
+        let mut new = Config::default();
+        for user in 0..64 {
+            old.admin.support_users.insert(user);
+        }
+        for user in (0..64).rev() {
+            new.admin.support_users.insert(user);
+        }
+        assert!(diff_config(&old, &new).unwrap().is_empty());
+    }
+
+    #[test]
+    fn validate_rejects_bad_timing() {
+        assert!(validate_config(&Config::default()).is_ok());
+        let mut config = Config::default();
+        config.timing.cache_timeout = 0;
+        assert!(validate_config(&config).is_err());
+
+        let mut config = Config::default();
+        config.external_bans.banlists.push("not a url".to_owned());
+        assert!(validate_config(&config).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_example_secret() {
+        let mut config = Config::default();
+        assert!(validate_callback_secret(&config).is_err());
+        config.callback_secret = Some("changeme".to_owned());
+        assert!(validate_callback_secret(&config).is_err());
+        config.callback_secret = Some("9f2c7e1a".to_owned());
+        assert!(validate_callback_secret(&config).is_ok());
+    }
+}
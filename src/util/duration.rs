@@ -0,0 +1,136 @@
+//! Parsing durations entered by users. A duration is one or more numbers each followed by a
+//! unit, like 30m, 2w, or 1d2h30m, and a bare number is read as seconds. Months are always
+//! 30 days since durations aren't tied to a calendar
+
+use chrono::Duration;
+use itertools::Itertools;
+use macros::lang_fmt;
+
+use super::string::Lang;
+
+/// Why a duration couldn't be parsed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DurationError {
+    /// nothing to parse
+    Empty,
+    /// a unit without a number in front of it
+    MissingNumber(String),
+    /// a unit that isn't supported
+    UnknownUnit(String),
+    /// too long to represent
+    OutOfRange(String),
+}
+
+impl DurationError {
+    /// Gets a message explaining the error to users
+    pub fn describe(&self, lang: &Lang) -> String {
+        match self {
+            Self::Empty => lang_fmt!(lang, "durationempty"),
+            Self::MissingNumber(unit) => lang_fmt!(lang, "durationnumber", unit),
+            Self::UnknownUnit(unit) => lang_fmt!(lang, "durationunit", unit),
+            Self::OutOfRange(text) => lang_fmt!(lang, "dateoutofrange", text),
+        }
+    }
+}
+
+fn unit_duration(unit: &str, count: i64) -> Option<Option<Duration>> {
+    let res = match unit {
+        "" | "s" | "sec" | "secs" | "second" | "seconds" => Duration::try_seconds(count),
+        "m" | "min" | "mins" | "minute" | "minutes" => Duration::try_minutes(count),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Duration::try_hours(count),
+        "d" | "day" | "days" => Duration::try_days(count),
+        "w" | "wk" | "wks" | "week" | "weeks" => Duration::try_weeks(count),
+        "mo" | "month" | "months" => count.checked_mul(30).and_then(Duration::try_days),
+        _ => return None,
+    };
+    Some(res)
+}
+
+/// Parses a duration like 1d2h30m. Spaces between parts and case are ignored
+pub fn parse_duration(text: &str) -> Result<Duration, DurationError> {
+    let text = text.trim().to_lowercase();
+    if text.is_empty() {
+        return Err(DurationError::Empty);
+    }
+
+    let mut total = Duration::zero();
+    let mut chars = text.chars().filter(|c| !c.is_whitespace()).peekable();
+    while chars.peek().is_some() {
+        let number = chars
+            .peeking_take_while(|c| c.is_ascii_digit())
+            .collect::<String>();
+        let unit = chars
+            .peeking_take_while(|c| !c.is_ascii_digit())
+            .collect::<String>();
+        if number.is_empty() {
+            return Err(DurationError::MissingNumber(unit));
+        }
+        let count = number
+            .parse::<i64>()
+            .map_err(|_| DurationError::OutOfRange(text.clone()))?;
+        let part = unit_duration(&unit, count)
+            .ok_or_else(|| DurationError::UnknownUnit(unit.clone()))?
+            .ok_or_else(|| DurationError::OutOfRange(text.clone()))?;
+        total = total
+            .checked_add(&part)
+            .ok_or_else(|| DurationError::OutOfRange(text.clone()))?;
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn single_units() {
+        assert_eq!(
+            parse_duration("30s"),
+            Ok(Duration::try_seconds(30).unwrap())
+        );
+        assert_eq!(parse_duration("5m"), Ok(Duration::try_minutes(5).unwrap()));
+        assert_eq!(parse_duration("2h"), Ok(Duration::try_hours(2).unwrap()));
+        assert_eq!(parse_duration("1d"), Ok(Duration::try_days(1).unwrap()));
+        assert_eq!(parse_duration("2w"), Ok(Duration::try_weeks(2).unwrap()));
+        assert_eq!(parse_duration("1mo"), Ok(Duration::try_days(30).unwrap()));
+        assert_eq!(
+            parse_duration("3 Hours"),
+            Ok(Duration::try_hours(3).unwrap())
+        );
+    }
+
+    #[test]
+    fn compound() {
+        let expected = Duration::try_days(1).unwrap()
+            + Duration::try_hours(2).unwrap()
+            + Duration::try_minutes(30).unwrap();
+        assert_eq!(parse_duration("1d2h30m"), Ok(expected));
+        assert_eq!(parse_duration("1d 2h 30m"), Ok(expected));
+    }
+
+    #[test]
+    fn bare_seconds() {
+        assert_eq!(parse_duration("90"), Ok(Duration::try_seconds(90).unwrap()));
+        assert_eq!(
+            parse_duration("1m30"),
+            Ok(Duration::try_seconds(90).unwrap())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_duration(""), Err(DurationError::Empty));
+        assert_eq!(
+            parse_duration("m"),
+            Err(DurationError::MissingNumber("m".to_owned()))
+        );
+        assert_eq!(
+            parse_duration("5x"),
+            Err(DurationError::UnknownUnit("x".to_owned()))
+        );
+        assert!(matches!(
+            parse_duration("99999999999999999999d"),
+            Err(DurationError::OutOfRange(_))
+        ));
+    }
+}
@@ -6,8 +6,10 @@
 //! sending formatted errors to the user via telegram
 use std::time::SystemTimeError;
 
+use crate::tg::clean_commands::notice_ttl;
 use crate::tg::command::Context;
 use crate::tg::markdown::DefaultParseErr;
+use crate::tg::scheduler::delete_message_later;
 use async_trait::async_trait;
 use bb8::RunError;
 use botapi::bot::{ApiError, Response};
@@ -19,6 +21,7 @@ use sqlx::error::DatabaseError;
 use thiserror::Error;
 use tokio::task::JoinError;
 
+use super::error_codes::ErrorCode;
 use super::string::Speak;
 
 /// Type alias for universal result type
@@ -190,6 +193,7 @@ impl<T: Send, E: Into<BotError> + Send> SpeakErr<T> for std::result::Result<T, E
     async fn silent(self) -> Result<T> {
         match self.map_err(|e| e.into()) {
             Err(BotError::Speak { err: Some(err), .. }) => Err(BotError::Silent(err)),
+            Err(BotError::Notice(err)) => Err(BotError::Silent(err)),
             Err(BotError::Speak { say, err: None, .. }) => {
                 Err(BotError::Silent(Box::new(BotError::Generic(say))))
             }
@@ -204,6 +208,20 @@ pub trait Fail {
     fn fail<T: AsRef<str>, R>(&self, message: T) -> Result<R>;
     /// construct a BotError::Speak
     fn fail_err<T: AsRef<str>>(&self, message: T) -> BotError;
+    /// construct a result that always returns Err(BotError::Notice), for noisy errors like
+    /// permission errors
+    fn fail_notice<T: AsRef<str>, R>(&self, message: T) -> Result<R> {
+        Err(self.fail_err(message).notice())
+    }
+    /// construct a result that always returns Err(BotError::Speak) showing an error code
+    /// users can look up with /error
+    fn fail_code<T: AsRef<str>, R>(&self, code: ErrorCode, message: T) -> Result<R> {
+        Err(self.fail_err(message).with_code(code))
+    }
+    /// construct a BotError::Speak showing an error code
+    fn fail_err_code<T: AsRef<str>>(&self, code: ErrorCode, message: T) -> BotError {
+        self.fail_err(message).with_code(code)
+    }
 }
 
 impl Fail for Context {
@@ -262,9 +280,15 @@ pub enum BotError {
         chat: i64,
         message: Option<i64>,
         err: Option<Box<BotError>>,
+        /// code sent after the message, see [`crate::util::error_codes`]
+        code: Option<ErrorCode>,
     },
     #[error("{0}")]
     Silent(Box<BotError>),
+    /// A speak error for noisy notices. In chats cleaning commands the message is deleted
+    /// after a while
+    #[error("{0}")]
+    Notice(Box<BotError>),
     #[error("Telegram API error: {0}")]
     ApiError(#[from] ApiError),
     #[error("Invalid conversation: {0}")]
@@ -337,6 +361,27 @@ impl From<TransactionError<BotError>> for BotError {
     }
 }
 
+async fn speak_error(
+    say: &str,
+    code: Option<ErrorCode>,
+    chat: i64,
+    message: Option<i64>,
+) -> Result<Option<Message>> {
+    let coded;
+    let say = if let Some(code) = code {
+        coded = format!("{} ({})", say, code);
+        &coded
+    } else {
+        say
+    };
+    if let Some(message) = message {
+        chat.force_reply(say, message).await
+    } else {
+        log::warn!("attempted to speak error without reply-to message");
+        chat.speak(say).await
+    }
+}
+
 impl BotError {
     /// constructor for conversation state machine error
     pub fn conversation_err<T: Into<String>>(text: T) -> Self {
@@ -355,6 +400,38 @@ impl BotError {
             chat,
             err: None,
             message,
+            code: None,
+        }
+    }
+
+    /// attach an error code to a speak error, see [`crate::util::error_codes`]. Other
+    /// errors are returned unchanged
+    pub fn with_code(self, code: ErrorCode) -> Self {
+        match self {
+            Self::Speak {
+                say,
+                chat,
+                message,
+                err,
+                ..
+            } => Self::Speak {
+                say,
+                chat,
+                message,
+                err,
+                code: Some(code),
+            },
+            Self::Notice(err) => Self::Notice(Box::new(err.with_code(code))),
+            err => err,
+        }
+    }
+
+    /// mark a speak error as a noisy notice, see [`BotError::Notice`]. Other errors are
+    /// returned unchanged
+    pub fn notice(self) -> Self {
+        match self {
+            Self::Speak { .. } => Self::Notice(Box::new(self)),
+            err => err,
         }
     }
 
@@ -369,11 +446,42 @@ impl BotError {
             chat,
             message,
             err: Some(Box::new(err.into())),
+            code: None,
         }
     }
 
-    /// record this error using prometheus error counters. Counters used depend on error
+    /// Checks if this is a postgres serialization failure or deadlock, meaning the
+    /// transaction that hit it can be retried
+    pub fn is_serialization_failure(&self) -> bool {
+        let err = match self {
+            Self::DbError(
+                DbErr::Exec(RuntimeErr::SqlxError(err))
+                | DbErr::Query(RuntimeErr::SqlxError(err))
+                | DbErr::Conn(RuntimeErr::SqlxError(err)),
+            ) => err,
+            _ => return false,
+        };
+        err.as_database_error()
+            .and_then(|err| err.code())
+            .map(|code| code == "40001" || code == "40P01")
+            .unwrap_or(false)
+    }
+
+    /// The error to send to error sinks, None for errors meant for users
+    pub fn reportable(&self) -> Option<&BotError> {
+        match self {
+            Self::Speak { err: Some(err), .. } => err.reportable(),
+            Self::Speak { .. } => None,
+            Self::Silent(err) | Self::Notice(err) => err.reportable(),
+            err => Some(err),
+        }
+    }
+
+    /// record this error using prometheus error counters, and send it to error sinks if
+    /// any are set up. Counters used depend on error
     pub fn record_stats(&self) {
+        #[cfg(feature = "error-sink")]
+        crate::util::error_sink::report(self);
         if let Self::ApiError(ref error) = self {
             if let Some(error) = error.get_response() {
                 log::warn!(
@@ -398,6 +506,15 @@ impl BotError {
         }
     }
 
+    /// get the error code telegram returned, if this is a telegram error
+    pub fn get_tg_code(&self) -> Option<i64> {
+        if let BotError::ApiError(err) = self {
+            err.get_response().and_then(|r| r.error_code)
+        } else {
+            None
+        }
+    }
+
     /// get humanreadable error string to print to user via telegram
     pub fn get_tg_error(&self) -> &'_ str {
         if let BotError::ApiError(err) = self {
@@ -413,15 +530,32 @@ impl BotError {
     pub async fn get_message(&self) -> Result<bool> {
         match self {
             Self::Speak {
-                say, chat, message, ..
+                say,
+                chat,
+                message,
+                code,
+                ..
             } => {
-                if let Some(message) = message {
-                    chat.force_reply(say, *message).await?;
+                speak_error(say, *code, *chat, *message).await?;
+                Ok(true)
+            }
+            Self::Notice(err) => {
+                if let Self::Speak {
+                    say,
+                    chat,
+                    message,
+                    code,
+                    ..
+                } = err.as_ref()
+                {
+                    let sent = speak_error(say, *code, *chat, *message).await?;
+                    if let (Some(sent), Some(ttl)) = (sent, notice_ttl(*chat).await?) {
+                        delete_message_later(*chat, sent.get_message_id(), ttl).await?;
+                    }
+                    Ok(true)
                 } else {
-                    log::warn!("attempted to speak error without reply-to message");
-                    chat.speak(say).await?;
+                    Ok(false)
                 }
-                Ok(true)
             }
             Self::Silent(_) => Ok(true),
             _ => Ok(false),
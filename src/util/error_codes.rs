@@ -0,0 +1,104 @@
+//! Stable codes for errors shown to users. A coded error is sent with its code after the
+//! message, like "(E004)", and `/error E004` explains what went wrong in the chat's
+//! language. Codes never change meaning once released, so they can be quoted in support
+//! chats and documentation regardless of what language the error was shown in.
+
+use std::fmt::{self, Display};
+
+/// An error users can look up with /error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorCode {
+    pub code: u16,
+
+    /// string key of the explanation shown by /error
+    pub help: &'static str,
+}
+
+impl ErrorCode {
+    pub const fn new(code: u16, help: &'static str) -> Self {
+        Self { code, help }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "E{:03}", self.code)
+    }
+}
+
+/// Something went wrong inside the bot rather than with the command
+pub const INTERNAL: ErrorCode = ErrorCode::new(1, "errhelpinternal");
+
+/// The user is missing an admin right the command needs
+pub const PERMISSION_DENIED: ErrorCode = ErrorCode::new(2, "errhelppermission");
+
+/// An anonymous channel tried to use an admin command and nobody confirmed it
+pub const ANONYMOUS_DENIED: ErrorCode = ErrorCode::new(3, "errhelpanonymous");
+
+/// The message has no sender the bot can check
+pub const UNKNOWN_USER: ErrorCode = ErrorCode::new(4, "errhelpunknownuser");
+
+/// The bot needs to be an admin with more rights
+pub const BOT_NOT_ADMIN: ErrorCode = ErrorCode::new(5, "errhelpbotadmin");
+
+/// An argument to the command isn't one of the accepted values
+pub const INVALID_ARGUMENT: ErrorCode = ErrorCode::new(6, "errhelpinvalidarg");
+
+/// The command is missing an argument or a replied message
+pub const MISSING_ARGUMENT: ErrorCode = ErrorCode::new(7, "errhelpmissingarg");
+
+/// Whatever the command refers to doesn't exist
+pub const NOT_FOUND: ErrorCode = ErrorCode::new(8, "errhelpnotfound");
+
+/// Telegram didn't return the chat's permissions
+pub const CHAT_PERMISSIONS: ErrorCode = ErrorCode::new(9, "errhelpchatpermissions");
+
+/// The message has no media the command can use
+pub const UNSUPPORTED_MEDIA: ErrorCode = ErrorCode::new(10, "errhelpmedia");
+
+/// Every error code, for /error
+pub const ALL: &[ErrorCode] = &[
+    INTERNAL,
+    PERMISSION_DENIED,
+    ANONYMOUS_DENIED,
+    UNKNOWN_USER,
+    BOT_NOT_ADMIN,
+    INVALID_ARGUMENT,
+    MISSING_ARGUMENT,
+    NOT_FOUND,
+    CHAT_PERMISSIONS,
+    UNSUPPORTED_MEDIA,
+];
+
+/// Finds an error code written like E004, e4 or 4
+pub fn find_error_code(text: &str) -> Option<ErrorCode> {
+    let text = text.trim();
+    let number = text
+        .strip_prefix('E')
+        .or_else(|| text.strip_prefix('e'))
+        .unwrap_or(text);
+    let code = number.parse::<u16>().ok()?;
+    ALL.iter().find(|c| c.code == code).copied()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_codes() {
+        assert_eq!(PERMISSION_DENIED.to_string(), "E002");
+        assert_eq!(find_error_code("E002"), Some(PERMISSION_DENIED));
+        assert_eq!(find_error_code(" e2 "), Some(PERMISSION_DENIED));
+        assert_eq!(find_error_code("10"), Some(UNSUPPORTED_MEDIA));
+        assert_eq!(find_error_code("E999"), None);
+        assert_eq!(find_error_code("nope"), None);
+    }
+
+    #[test]
+    fn codes_unique() {
+        for (i, code) in ALL.iter().enumerate() {
+            assert!(ALL[i + 1..].iter().all(|other| other.code != code.code));
+        }
+    }
+}
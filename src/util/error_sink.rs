@@ -0,0 +1,298 @@
+//! Forwarding errors to an external error tracker. Prometheus only counts errors, a sink
+//! gets each error along with the chat, update type, and module it happened in and the
+//! update's trace id, so it can be matched with the logs.
+//!
+//! Errors are tagged using an [`ErrorContext`] set while an update is handled. The sinks
+//! themselves are only built with the `error-sink` feature: a generic webhook receiving
+//! each error as json, and with the `sentry` feature, sentry. Anything embedding the bot
+//! can add its own with [`register_sink`]. Errors meant for users, like permission errors,
+//! are never reported.
+
+use std::future::Future;
+
+use botapi::gen_types::UpdateExt;
+use serde::Serialize;
+
+#[cfg(feature = "error-sink")]
+use std::sync::{Arc, RwLock};
+
+#[cfg(feature = "error-sink")]
+use std::time::Duration;
+
+#[cfg(feature = "error-sink")]
+use lazy_static::lazy_static;
+
+#[cfg(feature = "error-sink")]
+use crate::statics::CONFIG;
+
+#[cfg(feature = "error-sink")]
+use crate::util::error::BotError;
+
+#[cfg(feature = "error-sink")]
+use crate::util::trace::current_trace_id;
+
+#[cfg(feature = "error-sink")]
+use tokio::sync::Semaphore;
+
+/// How long a webhook gets to accept a report
+#[cfg(feature = "error-sink")]
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many reports can be on their way to a webhook at once. Reports past this are dropped
+/// so a slow or unreachable webhook can't pile up tasks during an error storm
+#[cfg(feature = "error-sink")]
+const WEBHOOK_MAX_PENDING: usize = 32;
+
+tokio::task_local! {
+    static CONTEXT: ErrorContext;
+}
+
+/// Where an error happened
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ErrorContext {
+    pub chat: Option<i64>,
+    pub update_type: Option<&'static str>,
+    pub module: Option<String>,
+}
+
+impl ErrorContext {
+    /// Context of errors while handling an update
+    pub fn from_update(update: &UpdateExt, chat: Option<i64>) -> Self {
+        Self {
+            chat,
+            update_type: Some(update_type(update)),
+            module: None,
+        }
+    }
+
+    /// The context errors are currently reported with
+    pub fn current() -> Self {
+        CONTEXT.try_with(|ctx| ctx.clone()).unwrap_or_default()
+    }
+
+    /// Runs a future with this context
+    pub async fn scope<F: Future>(self, fut: F) -> F::Output {
+        CONTEXT.scope(self, fut).await
+    }
+
+    /// Runs a closure with the current context moved to a module
+    pub fn in_module<F: FnOnce() -> R, R>(module: &str, f: F) -> R {
+        let ctx = Self {
+            module: Some(module.to_owned()),
+            ..Self::current()
+        };
+        CONTEXT.sync_scope(ctx, f)
+    }
+}
+
+/// Name of an update's type, the same as the name in the bot api
+pub fn update_type(update: &UpdateExt) -> &'static str {
+    match update {
+        UpdateExt::Message(_) => "message",
+        UpdateExt::EditedMessage(_) => "edited_message",
+        UpdateExt::ChannelPost(_) => "channel_post",
+        UpdateExt::EditedChannelPost(_) => "edited_channel_post",
+        UpdateExt::InlineQuery(_) => "inline_query",
+        UpdateExt::ChosenInlineResult(_) => "chosen_inline_result",
+        UpdateExt::CallbackQuery(_) => "callback_query",
+        UpdateExt::ShippingQuery(_) => "shipping_query",
+        UpdateExt::PreCheckoutQuery(_) => "pre_checkout_query",
+        UpdateExt::Poll(_) => "poll",
+        UpdateExt::PollAnswer(_) => "poll_answer",
+        UpdateExt::MyChatMember(_) => "my_chat_member",
+        UpdateExt::ChatJoinRequest(_) => "chat_join_request",
+        UpdateExt::ChatMember(_) => "chat_member",
+        UpdateExt::MessageReaction(_) => "message_reaction",
+        UpdateExt::MessageReactionCount(_) => "message_reaction_count",
+        UpdateExt::ChatBoost(_) => "chat_boost",
+        UpdateExt::RemovedChatBoost(_) => "removed_chat_boost",
+        UpdateExt::BusinessConnection(_) => "business_connection",
+        UpdateExt::BusinessMessage(_) => "business_message",
+        UpdateExt::EditedBusinessMessage(_) => "edited_business_message",
+        UpdateExt::DeletedBusinessMessages(_) => "deleted_business_messages",
+        UpdateExt::Invalid => "invalid",
+    }
+}
+
+/// An error as sent to sinks
+#[derive(Clone, Debug, Serialize)]
+pub struct ErrorReport {
+    pub error: String,
+    pub trace_id: Option<String>,
+    #[serde(flatten)]
+    pub context: ErrorContext,
+}
+
+impl ErrorReport {
+    #[cfg(feature = "error-sink")]
+    fn new(error: String) -> Self {
+        Self {
+            error,
+            trace_id: current_trace_id().map(|id| id.to_string()),
+            context: ErrorContext::current(),
+        }
+    }
+}
+
+/// Somewhere errors are forwarded to. Called on the task the error happened on, so
+/// anything slow should be spawned
+#[cfg(feature = "error-sink")]
+pub trait ErrorSink: Send + Sync {
+    fn report(&self, err: &BotError, report: &ErrorReport);
+}
+
+#[cfg(feature = "error-sink")]
+lazy_static! {
+    static ref SINKS: RwLock<Vec<Box<dyn ErrorSink>>> = RwLock::new(Vec::new());
+    static ref HTTP: reqwest::Client = reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()
+        .expect("failed to build error sink client");
+}
+
+/// Adds a sink every reported error is sent to
+#[cfg(feature = "error-sink")]
+pub fn register_sink(sink: Box<dyn ErrorSink>) {
+    if let Ok(mut sinks) = SINKS.write() {
+        sinks.push(sink);
+    }
+}
+
+/// Registers the sinks set in the config
+#[cfg(feature = "error-sink")]
+pub fn init_sinks() {
+    let config = CONFIG.load();
+    if let Some(ref url) = config.error_sink.webhook_url {
+        register_sink(Box::new(WebhookSink::new(url.clone())));
+    }
+    #[cfg(feature = "sentry")]
+    if let Some(ref dsn) = config.error_sink.sentry_dsn {
+        let guard = sentry::init((
+            dsn.as_str(),
+            sentry::ClientOptions {
+                release: sentry::release_name!(),
+                environment: config.error_sink.environment.clone().map(Into::into),
+                ..Default::default()
+            },
+        ));
+        // the client lives as long as the bot
+        std::mem::forget(guard);
+        register_sink(Box::new(SentrySink));
+    }
+}
+
+/// Sends an error to every sink, unless it's an error meant for users
+#[cfg(feature = "error-sink")]
+pub fn report(err: &BotError) {
+    let Some(err) = err.reportable() else {
+        return;
+    };
+    let Ok(sinks) = SINKS.read() else {
+        return;
+    };
+    if sinks.is_empty() {
+        return;
+    }
+    let report = ErrorReport::new(err.to_string());
+    for sink in sinks.iter() {
+        sink.report(err, &report);
+    }
+}
+
+/// Posts each error as json to a url
+#[cfg(feature = "error-sink")]
+pub struct WebhookSink {
+    pub url: String,
+    pending: Arc<Semaphore>,
+}
+
+#[cfg(feature = "error-sink")]
+impl WebhookSink {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            pending: Arc::new(Semaphore::new(WEBHOOK_MAX_PENDING)),
+        }
+    }
+}
+
+#[cfg(feature = "error-sink")]
+impl ErrorSink for WebhookSink {
+    fn report(&self, _: &BotError, report: &ErrorReport) {
+        let body = match serde_json::to_vec(report) {
+            Ok(body) => body,
+            Err(err) => {
+                log::warn!("failed to serialize error report: {}", err);
+                return;
+            }
+        };
+        let Ok(permit) = self.pending.clone().try_acquire_owned() else {
+            log::warn!("too many pending error reports, dropping one");
+            return;
+        };
+        let url = self.url.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            let res = HTTP
+                .post(url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body)
+                .send()
+                .await
+                .and_then(|res| res.error_for_status());
+            // not recorded as an error, which would report it again
+            if let Err(err) = res {
+                log::warn!("failed to send error report: {}", err);
+            }
+        });
+    }
+}
+
+/// Captures each error as a sentry event tagged with its context
+#[cfg(feature = "sentry")]
+pub struct SentrySink;
+
+#[cfg(feature = "sentry")]
+impl ErrorSink for SentrySink {
+    fn report(&self, err: &BotError, report: &ErrorReport) {
+        sentry::with_scope(
+            |scope| {
+                if let Some(ref trace_id) = report.trace_id {
+                    scope.set_tag("trace_id", trace_id);
+                }
+                if let Some(chat) = report.context.chat {
+                    scope.set_tag("chat", chat);
+                }
+                if let Some(update_type) = report.context.update_type {
+                    scope.set_tag("update_type", update_type);
+                }
+                if let Some(ref module) = report.context.module {
+                    scope.set_tag("module", module);
+                }
+            },
+            || sentry::capture_error(err),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn context_scope() {
+        assert_eq!(ErrorContext::current().chat, None);
+        let ctx = ErrorContext {
+            chat: Some(-100),
+            update_type: Some("message"),
+            module: None,
+        };
+        ctx.scope(async {
+            let module = ErrorContext::in_module("bans", ErrorContext::current);
+            assert_eq!(module.chat, Some(-100));
+            assert_eq!(module.module.as_deref(), Some("bans"));
+            assert_eq!(ErrorContext::current().module, None);
+        })
+        .await;
+    }
+}
@@ -1,7 +1,14 @@
 #[allow(dead_code)]
 pub mod callback;
+pub mod config;
+pub mod duration;
 pub mod error;
+pub mod error_codes;
+pub mod error_sink;
 //pub mod filter;
 pub mod glob;
 pub mod scripting;
 pub mod string;
+pub mod trace;
+pub mod triggers;
+pub mod time;
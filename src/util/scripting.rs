@@ -14,7 +14,7 @@ use crate::statics::CONFIG;
 use super::error::{BotError, Result};
 
 lazy_static! {
-    pub static ref COMPUTE_TP: ThreadPool = ThreadPool::new(CONFIG.compute_threads);
+    pub static ref COMPUTE_TP: ThreadPool = ThreadPool::new(CONFIG.load().compute_threads);
 }
 
 thread_local! {
@@ -3,22 +3,27 @@
 //! and ratelimiting to work
 
 pub use crate::langs::*;
-use crate::persist::core::dialogs;
-use crate::persist::redis::{default_cache_query, CachedQueryTrait, RedisStr};
+use crate::persist::core::{chat_strings, dialogs};
+use crate::persist::local::{self, LocalCache};
+use crate::persist::redis::{default_cache_query, CachedQueryTrait};
 use crate::statics::{CHAT_GOVERNER, CONFIG, DB, REDIS, TG};
-use crate::tg::admin_helpers::IntoChatUser;
+use crate::tg::admin_helpers::{ChatUser, DeleteAfterTime, IntoChatUser};
+use crate::tg::dialog::dialog_scope;
 use crate::tg::markdown::{EntityMessage, MarkupBuilder};
-use crate::util::error::Result;
+use crate::tg::parse_mode::ParseMode;
+use crate::tg::scheduler::delete_message_later;
+use crate::util::error::{BotError, Result};
 use async_trait::async_trait;
-use botapi::bot::Part;
 use botapi::gen_types::{
-    Chat, EReplyMarkup, FileData, LinkPreviewOptionsBuilder, Message, ReplyParametersBuilder,
+    Chat, EReplyMarkup, LinkPreviewOptionsBuilder, Message, MessageEntity, ReplyParametersBuilder,
 };
-use chrono::Duration;
-use redis::Script;
+use chrono::{DateTime, Datelike, Duration, TimeZone};
+use redis::{AsyncCommands, Script};
 use sea_orm::sea_query::OnConflict;
 use sea_orm::ActiveValue::Set;
-use sea_orm::{EntityTrait, IntoActiveModel};
+use sea_orm::{ColumnTrait, EntityTrait, IntoActiveModel, QueryFilter};
+use std::collections::HashMap;
+use std::fmt::{Display, Write};
 use std::ops::DerefMut;
 
 /// Returns false if ratelimiting is triggered. This function should be called before
@@ -43,9 +48,9 @@ pub async fn should_ignore_chat(chat: i64) -> Result<bool> {
                 "#,
             )
             .key(&counterkey)
-            .arg(CONFIG.timing.antifloodwait_time)
-            .arg(CONFIG.timing.antifloodwait_count)
-            .arg(CONFIG.timing.ignore_chat_time)
+            .arg(CONFIG.load().timing.antifloodwait_time)
+            .arg(CONFIG.load().timing.antifloodwait_count)
+            .arg(CONFIG.load().timing.ignore_chat_time)
             .invoke_async(q.deref_mut())
             .await?;
             Ok(count)
@@ -53,7 +58,18 @@ pub async fn should_ignore_chat(chat: i64) -> Result<bool> {
         .await?;
 
     CHAT_GOVERNER.until_key_ready(&chat).await;
-    Ok(count >= CONFIG.timing.antifloodwait_count)
+    Ok(count >= CONFIG.load().timing.antifloodwait_count)
+}
+
+/// Checks if the bot is currently ignoring a chat, either because it was ignored with
+/// [`ignore_chat`] or because the bot sent too many messages there. Unlike
+/// [`should_ignore_chat`] this doesn't count as sending a message
+pub async fn is_chat_ignored(chat: i64) -> Result<bool> {
+    let ignored = format!("ign:{}", chat);
+    let counterkey = format!("ignc:{}", chat);
+    let (ignored, count): (bool, Option<usize>) =
+        REDIS.pipe(|q| q.exists(&ignored).get(&counterkey)).await?;
+    Ok(ignored || count.unwrap_or(0) >= CONFIG.load().timing.antifloodwait_count)
 }
 
 /// Sets a redis key that causes all official methods of sending messages to suspend
@@ -66,426 +82,350 @@ pub async fn ignore_chat(chat: i64, time: &Duration) -> Result<()> {
     Ok(())
 }
 
+/// How a message is sent by [`Speak`] and [`send_split`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SendOptions {
+    /// Message the first part replies to
+    pub reply: Option<i64>,
+    /// Send without a notification
+    pub silent: bool,
+    /// Forum topic to send the message to
+    pub thread: Option<i64>,
+}
+
 /// Extension trait with fuctions for sending messages. Types that implement this trait should be
 /// types containing distinct references to chats or objects that can be replied to.
 #[async_trait]
 pub trait Speak {
     /// Send a text message to the chat associated with this type. Murkdown is parsed if valid
     async fn speak<T>(&self, message: T) -> Result<Option<Message>>
+    where
+        T: AsRef<str> + Send + Sync,
+    {
+        self.speak_with(message, self.send_options(false)).await
+    }
+
+    /// Sends a text message with the given options. Murkdown is parsed if valid
+    async fn speak_with<T>(&self, message: T, options: SendOptions) -> Result<Option<Message>>
     where
         T: AsRef<str> + Send + Sync;
 
+    /// Sends text that already has its entities, with no murkdown parsing
+    async fn speak_entities(
+        &self,
+        text: &str,
+        entities: &[MessageEntity],
+        options: SendOptions,
+    ) -> Result<Option<Message>>;
+
+    /// The message replies are sent to, if this type has one
+    fn reply_target(&self) -> Option<i64> {
+        None
+    }
+
+    /// The forum topic messages are sent to, if this type is in one
+    fn thread(&self) -> Option<i64> {
+        None
+    }
+
+    /// Options for sending to this type's forum topic, replying if `reply` is set
+    fn send_options(&self, reply: bool) -> SendOptions {
+        SendOptions {
+            reply: self.reply_target().filter(|_| reply),
+            silent: false,
+            thread: self.thread(),
+        }
+    }
+
     /// Sends a telegram api send_message builder, potentially with existing MessageEntities or
     /// other formatting
-    async fn speak_fmt(&self, messsage: EntityMessage) -> Result<Option<Message>>;
+    async fn speak_fmt(&self, message: EntityMessage) -> Result<Option<Message>> {
+        speak_entity_message(message, self.send_options(false)).await
+    }
 
     /// Replies with a telegram api send_message builder, potentially with existing MessageEntities or
     /// other formatting
-    async fn reply_fmt(&self, messsage: EntityMessage) -> Result<Option<Message>>;
+    async fn reply_fmt(&self, message: EntityMessage) -> Result<Option<Message>> {
+        speak_entity_message(message, self.send_options(true)).await
+    }
 
     /// Replies with a text message to the chat associated with this type. Murkdown is parsed if valid
     async fn reply<T>(&self, message: T) -> Result<Option<Message>>
     where
-        T: AsRef<str> + Send + Sync;
+        T: AsRef<str> + Send + Sync,
+    {
+        self.speak_with(message, self.send_options(true)).await
+    }
 
     /// Replies with a text message to the chat associated with this type. Murkdown is parsed if valid
     async fn force_reply<T>(&self, message: T, reply: i64) -> Result<Option<Message>>
-    where
-        T: AsRef<str> + Send + Sync;
-}
-
-#[async_trait]
-impl Speak for i64 {
-    async fn speak<T>(&self, message: T) -> Result<Option<Message>>
     where
         T: AsRef<str> + Send + Sync,
     {
-        if !should_ignore_chat(*self).await? {
-            if message.as_ref().len() > 4096 {
-                let bytes = FileData::Part(
-                    Part::text(message.as_ref().to_owned()).file_name("message.txt"),
-                );
-                let message = TG.client.build_send_document(*self, bytes).build().await?;
-                return Ok(Some(message));
-            }
-
-            let (text, entities, markup) = MarkupBuilder::new(None)
-                .set_text(message.as_ref().to_owned())
-                .filling(true)
-                .header(false)
-                .build_murkdown_nofail()
-                .await;
-
-            let m = TG
-                .client()
-                .build_send_message(*self, &text)
-                .entities(&entities)
-                .reply_markup(&EReplyMarkup::InlineKeyboardMarkup(markup.build()))
-                .link_preview_options(
-                    &LinkPreviewOptionsBuilder::new()
-                        .set_is_disabled(true)
-                        .build(),
-                )
-                .build()
-                .await?;
-
-            Ok(Some(m))
-        } else {
-            Ok(None)
-        }
+        let options = SendOptions {
+            reply: Some(reply),
+            ..self.send_options(false)
+        };
+        self.speak_with(message, options).await
     }
 
-    async fn speak_fmt(&self, mut message: EntityMessage) -> Result<Option<Message>> {
-        if !should_ignore_chat(*self).await? {
-            Ok(Some(
-                message
-                    .call()
-                    .await
-                    .link_preview_options(
-                        &LinkPreviewOptionsBuilder::new()
-                            .set_is_disabled(true)
-                            .build(),
-                    )
-                    .build()
-                    .await?,
-            ))
-        } else {
-            Ok(None)
-        }
+    /// Replies without a notification, for messages nobody needs to be pinged about
+    async fn reply_silent<T>(&self, message: T) -> Result<Option<Message>>
+    where
+        T: AsRef<str> + Send + Sync,
+    {
+        let options = SendOptions {
+            silent: true,
+            ..self.send_options(true)
+        };
+        self.speak_with(message, options).await
     }
 
-    async fn reply_fmt(&self, mut message: EntityMessage) -> Result<Option<Message>> {
-        if !should_ignore_chat(*self).await? {
-            Ok(Some(
-                message
-                    .call()
-                    .await
-                    .link_preview_options(
-                        &LinkPreviewOptionsBuilder::new()
-                            .set_is_disabled(true)
-                            .build(),
-                    )
-                    .build()
-                    .await?,
-            ))
-        } else {
-            Ok(None)
-        }
+    /// Sends a text message to a forum topic. Murkdown is parsed if valid
+    async fn speak_to_thread<T>(&self, message: T, thread: i64) -> Result<Option<Message>>
+    where
+        T: AsRef<str> + Send + Sync,
+    {
+        let options = SendOptions {
+            thread: Some(thread),
+            ..Default::default()
+        };
+        self.speak_with(message, options).await
     }
 
-    async fn reply<T>(&self, message: T) -> Result<Option<Message>>
+    /// Replies with text in telegram's MarkdownV2 parse mode, sent as plain text if it
+    /// doesn't parse
+    async fn reply_md<T>(&self, message: T) -> Result<Option<Message>>
     where
         T: AsRef<str> + Send + Sync,
     {
-        self.speak(message).await
+        self.reply_parse_mode(message.as_ref(), ParseMode::MarkdownV2)
+            .await
     }
 
-    async fn force_reply<T>(&self, message: T, reply: i64) -> Result<Option<Message>>
+    /// Replies with text in telegram's HTML parse mode, sent as plain text if it doesn't
+    /// parse
+    async fn reply_html<T>(&self, message: T) -> Result<Option<Message>>
     where
         T: AsRef<str> + Send + Sync,
     {
-        if !should_ignore_chat(*self).await? {
-            if message.as_ref().len() > 4096 {
-                let bytes = FileData::Part(
-                    Part::text(message.as_ref().to_owned()).file_name("message.txt"),
-                );
-                let message = TG.client.build_send_document(*self, bytes).build().await?;
-                return Ok(Some(message));
-            }
-
-            let (text, entities, markup) = MarkupBuilder::new(None)
-                .set_text(message.as_ref().to_owned())
-                .filling(true)
-                .header(false)
-                .build_murkdown_nofail()
-                .await;
-
-            let m = TG
-                .client()
-                .build_send_message(*self, &text)
-                .entities(&entities)
-                .reply_markup(&EReplyMarkup::InlineKeyboardMarkup(markup.build()))
-                .reply_parameters(&ReplyParametersBuilder::new(reply).build())
-                .link_preview_options(
-                    &LinkPreviewOptionsBuilder::new()
-                        .set_is_disabled(true)
-                        .build(),
-                )
-                .build()
-                .await?;
+        self.reply_parse_mode(message.as_ref(), ParseMode::Html)
+            .await
+    }
 
-            Ok(Some(m))
-        } else {
-            Ok(None)
+    /// Replies with text in one of telegram's parse modes, sent as plain text if it doesn't
+    /// parse
+    async fn reply_parse_mode(&self, message: &str, mode: ParseMode) -> Result<Option<Message>> {
+        let options = self.send_options(true);
+        match mode.parse(message) {
+            Ok((text, entities)) => self.speak_entities(&text, &entities, options).await,
+            Err(err) => {
+                log::debug!("failed to parse {:?}, sending plain text: {}", mode, err);
+                self.speak_entities(message, &[], options).await
+            }
         }
     }
-}
 
-#[async_trait]
-impl Speak for Message {
-    async fn speak<T>(&self, message: T) -> Result<Option<Message>>
+    /// Replies with a message that is deleted after `ttl`. The deletion goes through the
+    /// scheduler so it still happens if the bot restarts
+    async fn reply_ephemeral<T>(&self, message: T, ttl: Duration) -> Result<Option<Message>>
     where
         T: AsRef<str> + Send + Sync,
     {
-        if !should_ignore_chat(self.get_chat().get_id()).await? {
-            if message.as_ref().len() > 4096 {
-                let bytes = FileData::Part(
-                    Part::text(message.as_ref().to_owned()).file_name("message.txt"),
-                );
-                let message = TG
-                    .client
-                    .build_send_document(self.get_chat().get_id(), bytes)
-                    .build()
-                    .await?;
-                return Ok(Some(message));
-            }
-
-            let (text, entities, markup) = MarkupBuilder::new(None)
-                .set_text(message.as_ref().to_owned())
-                .filling(true)
-                .header(false)
-                .chatuser(self.get_chatuser().as_ref())
-                .build_murkdown_nofail()
-                .await;
-
-            let m = TG
-                .client()
-                .build_send_message(self.get_chat().get_id(), &text)
-                .entities(&entities)
-                .reply_markup(&EReplyMarkup::InlineKeyboardMarkup(markup.build()))
-                .link_preview_options(
-                    &LinkPreviewOptionsBuilder::new()
-                        .set_is_disabled(true)
-                        .build(),
-                )
-                .build()
+        let message = self.reply(message).await?;
+        if let Some(ref message) = message {
+            delete_message_later(message.get_chat().get_id(), message.get_message_id(), ttl)
                 .await?;
-
-            Ok(Some(m))
-        } else {
-            Ok(None)
         }
+        Ok(message)
     }
 
-    async fn speak_fmt(&self, mut message: EntityMessage) -> Result<Option<Message>> {
-        if !should_ignore_chat(self.get_chat().get_id()).await? {
-            Ok(Some(
-                message
-                    .call()
-                    .await
-                    .link_preview_options(
-                        &LinkPreviewOptionsBuilder::new()
-                            .set_is_disabled(true)
-                            .build(),
-                    )
-                    .build()
-                    .await?,
-            ))
-        } else {
-            Ok(None)
-        }
+    /// Cleans up a confirmation sent through [`Confirm`]. By default confirmations are deleted
+    /// after `timing.confirmation_delete_time` if it is set
+    async fn clean_confirmation(&self, message: &Option<Message>) {
+        delete_confirmation(message);
     }
+}
 
-    async fn reply_fmt(&self, mut message: EntityMessage) -> Result<Option<Message>> {
-        if !should_ignore_chat(self.get_chat().get_id()).await? {
-            Ok(Some(
-                message
-                    .call()
-                    .await
-                    .reply_parameters(&ReplyParametersBuilder::new(self.message_id).build())
-                    .link_preview_options(
-                        &LinkPreviewOptionsBuilder::new()
-                            .set_is_disabled(true)
-                            .build(),
-                    )
-                    .build()
-                    .await?,
-            ))
-        } else {
-            Ok(None)
-        }
+/// Sends a formatted message to the chat it was built for
+async fn speak_entity_message(
+    mut message: EntityMessage,
+    options: SendOptions,
+) -> Result<Option<Message>> {
+    if should_ignore_chat(message.chat).await? {
+        return Ok(None);
+    }
+    let (text, entities, markup) = message.parts().await;
+    let m = send_split(
+        message.chat,
+        None,
+        &text,
+        &entities,
+        markup.as_ref(),
+        options,
+    )
+    .await?;
+    Ok(Some(m))
+}
+
+/// Sends a murkdown message, with fillings for the user and chat if there are any
+async fn speak_murkdown(
+    chat: i64,
+    business: Option<&str>,
+    message: &str,
+    chatuser: Option<&ChatUser<'_>>,
+    options: SendOptions,
+) -> Result<Option<Message>> {
+    if should_ignore_chat(chat).await? {
+        return Ok(None);
     }
+    let (text, entities, markup) = MarkupBuilder::new(None)
+        .set_text(message.to_owned())
+        .filling(true)
+        .header(false)
+        .chatuser(chatuser)
+        .build_murkdown_nofail()
+        .await;
 
-    async fn reply<T>(&self, message: T) -> Result<Option<Message>>
+    let markup = EReplyMarkup::InlineKeyboardMarkup(markup.build());
+    let m = send_split(chat, business, &text, &entities, Some(&markup), options).await?;
+    Ok(Some(m))
+}
+
+/// Sends text with its entities
+async fn speak_plain(
+    chat: i64,
+    business: Option<&str>,
+    text: &str,
+    entities: &[MessageEntity],
+    options: SendOptions,
+) -> Result<Option<Message>> {
+    if should_ignore_chat(chat).await? {
+        return Ok(None);
+    }
+    let m = send_split(chat, business, text, entities, None, options).await?;
+    Ok(Some(m))
+}
+
+/// Extension trait for short confirmations of settings changes. If
+/// `timing.confirmation_delete_time` is set in the config, confirmations are deleted after that
+/// many seconds to keep groups tidy, and chats cleaning commands can pick their own delay.
+/// Modules that want a notice to stick around regardless should use [`Speak::reply`] instead
+#[async_trait]
+pub trait Confirm: Speak + Sync {
+    /// Replies with a confirmation that is deleted after the configured delay
+    async fn confirm<T>(&self, message: T) -> Result<Option<Message>>
     where
         T: AsRef<str> + Send + Sync,
     {
-        if !should_ignore_chat(self.get_chat().get_id()).await? {
-            if message.as_ref().len() > 4096 {
-                let bytes = FileData::Part(
-                    Part::text(message.as_ref().to_owned()).file_name("message.txt"),
-                );
-
-                let message = TG
-                    .client
-                    .build_send_document(self.get_chat().get_id(), bytes)
-                    .reply_parameters(&ReplyParametersBuilder::new(self.get_message_id()).build())
-                    .build()
-                    .await?;
-                return Ok(Some(message));
-            }
+        let message = self.reply(message).await?;
+        self.clean_confirmation(&message).await;
+        Ok(message)
+    }
 
-            let (text, entities, markup) = MarkupBuilder::new(None)
-                .set_text(message.as_ref().to_owned())
-                .filling(true)
-                .header(false)
-                .chatuser(self.get_chatuser().as_ref())
-                .build_murkdown_nofail()
-                .await;
-
-            let m = TG
-                .client()
-                .build_send_message(self.get_chat().get_id(), &text)
-                .entities(&entities)
-                .reply_markup(&EReplyMarkup::InlineKeyboardMarkup(markup.build()))
-                .reply_parameters(&ReplyParametersBuilder::new(self.get_message_id()).build())
-                .link_preview_options(
-                    &LinkPreviewOptionsBuilder::new()
-                        .set_is_disabled(true)
-                        .build(),
-                )
-                .build()
-                .await?;
-            Ok(Some(m))
-        } else {
-            Ok(None)
-        }
+    /// Replies with a formatted confirmation that is deleted after the configured delay
+    async fn confirm_fmt(&self, message: EntityMessage) -> Result<Option<Message>> {
+        let message = self.reply_fmt(message).await?;
+        self.clean_confirmation(&message).await;
+        Ok(message)
     }
+}
 
-    async fn force_reply<T>(&self, message: T, reply: i64) -> Result<Option<Message>>
+impl<T> Confirm for T where T: Speak + Send + Sync {}
+
+fn delete_confirmation(message: &Option<Message>) {
+    if let Some(time) = CONFIG
+        .load()
+        .timing
+        .confirmation_delete_time
+        .and_then(Duration::try_seconds)
+    {
+        message.delete_after_time(time);
+    }
+}
+
+#[async_trait]
+impl Speak for i64 {
+    async fn speak_with<T>(&self, message: T, options: SendOptions) -> Result<Option<Message>>
     where
         T: AsRef<str> + Send + Sync,
     {
-        if !should_ignore_chat(self.get_chat().get_id()).await? {
-            if message.as_ref().len() > 4096 {
-                let bytes = FileData::Part(
-                    Part::text(message.as_ref().to_owned()).file_name("message.txt"),
-                );
-
-                let message = TG
-                    .client
-                    .build_send_document(self.get_chat().get_id(), bytes)
-                    .reply_parameters(&ReplyParametersBuilder::new(self.get_message_id()).build())
-                    .build()
-                    .await?;
-                return Ok(Some(message));
-            }
+        speak_murkdown(*self, None, message.as_ref(), None, options).await
+    }
 
-            let (text, entities, markup) = MarkupBuilder::new(None)
-                .set_text(message.as_ref().to_owned())
-                .filling(true)
-                .header(false)
-                .chatuser(self.get_chatuser().as_ref())
-                .build_murkdown_nofail()
-                .await;
-
-            let m = TG
-                .client()
-                .build_send_message(self.get_chat().get_id(), &text)
-                .entities(&entities)
-                .reply_markup(&EReplyMarkup::InlineKeyboardMarkup(markup.build()))
-                .reply_parameters(&ReplyParametersBuilder::new(reply).build())
-                .link_preview_options(
-                    &LinkPreviewOptionsBuilder::new()
-                        .set_is_disabled(true)
-                        .build(),
-                )
-                .build()
-                .await?;
-            Ok(Some(m))
-        } else {
-            Ok(None)
-        }
+    async fn speak_entities(
+        &self,
+        text: &str,
+        entities: &[MessageEntity],
+        options: SendOptions,
+    ) -> Result<Option<Message>> {
+        speak_plain(*self, None, text, entities, options).await
     }
 }
 
 #[async_trait]
-impl Speak for Chat {
-    async fn speak<T>(&self, message: T) -> Result<Option<Message>>
+impl Speak for Message {
+    async fn speak_with<T>(&self, message: T, options: SendOptions) -> Result<Option<Message>>
     where
         T: AsRef<str> + Send + Sync,
     {
-        if !should_ignore_chat(self.get_id()).await? {
-            let m = TG
-                .client()
-                .build_send_message(self.get_id(), message.as_ref())
-                .link_preview_options(
-                    &LinkPreviewOptionsBuilder::new()
-                        .set_is_disabled(true)
-                        .build(),
-                )
-                .build()
-                .await?;
-            Ok(Some(m))
-        } else {
-            Ok(None)
-        }
+        let chatuser = self.get_chatuser();
+        speak_murkdown(
+            self.get_chat().get_id(),
+            self.get_business_connection_id(),
+            message.as_ref(),
+            chatuser.as_ref(),
+            options,
+        )
+        .await
     }
 
-    async fn speak_fmt(&self, mut message: EntityMessage) -> Result<Option<Message>> {
-        if !should_ignore_chat(self.get_id()).await? {
-            Ok(Some(
-                message
-                    .call()
-                    .await
-                    .link_preview_options(
-                        &LinkPreviewOptionsBuilder::new()
-                            .set_is_disabled(true)
-                            .build(),
-                    )
-                    .build()
-                    .await?,
-            ))
-        } else {
-            Ok(None)
-        }
+    async fn speak_entities(
+        &self,
+        text: &str,
+        entities: &[MessageEntity],
+        options: SendOptions,
+    ) -> Result<Option<Message>> {
+        speak_plain(
+            self.get_chat().get_id(),
+            self.get_business_connection_id(),
+            text,
+            entities,
+            options,
+        )
+        .await
     }
 
-    async fn reply_fmt(&self, mut message: EntityMessage) -> Result<Option<Message>> {
-        if !should_ignore_chat(self.get_id()).await? {
-            Ok(Some(
-                message
-                    .call()
-                    .await
-                    .link_preview_options(
-                        &LinkPreviewOptionsBuilder::new()
-                            .set_is_disabled(true)
-                            .build(),
-                    )
-                    .build()
-                    .await?,
-            ))
+    fn reply_target(&self) -> Option<i64> {
+        Some(self.get_message_id())
+    }
+
+    fn thread(&self) -> Option<i64> {
+        if self.get_is_topic_message().unwrap_or(false) {
+            self.get_message_thread_id()
         } else {
-            Ok(None)
+            None
         }
     }
+}
 
-    async fn reply<T>(&self, message: T) -> Result<Option<Message>>
+#[async_trait]
+impl Speak for Chat {
+    /// Chats are sent plain text, without murkdown
+    async fn speak_with<T>(&self, message: T, options: SendOptions) -> Result<Option<Message>>
     where
         T: AsRef<str> + Send + Sync,
     {
-        self.speak(message).await
+        speak_plain(self.get_id(), None, message.as_ref(), &[], options).await
     }
-    async fn force_reply<T>(&self, message: T, reply: i64) -> Result<Option<Message>>
-    where
-        T: AsRef<str> + Send + Sync,
-    {
-        if !should_ignore_chat(self.get_id()).await? {
-            let m = TG
-                .client()
-                .build_send_message(self.get_id(), message.as_ref())
-                .reply_parameters(&ReplyParametersBuilder::new(reply).build())
-                .link_preview_options(
-                    &LinkPreviewOptionsBuilder::new()
-                        .set_is_disabled(true)
-                        .build(),
-                )
-                .build()
-                .await?;
-            Ok(Some(m))
-        } else {
-            Ok(None)
-        }
+
+    async fn speak_entities(
+        &self,
+        text: &str,
+        entities: &[MessageEntity],
+        options: SendOptions,
+    ) -> Result<Option<Message>> {
+        speak_plain(self.get_id(), None, text, entities, options).await
     }
 }
 
@@ -493,38 +433,42 @@ fn get_lang_key(chat: i64) -> String {
     format!("lang:{}", chat)
 }
 
+/// Chat languages kept in memory, since nearly every reply looks one up
+static LOCAL_LANGS: LocalCache<Lang> = LocalCache::new("lang");
+
 /// Gets the language config for the current chat
 pub async fn get_chat_lang(chat: i64) -> Result<Lang> {
-    let key = get_lang_key(chat);
-    let res = default_cache_query(
-        |_, _| async move {
-            Ok(Some(
-                dialogs::Entity::find_by_id(chat)
-                    .one(*DB)
-                    .await?
-                    .map(|v| v.language)
-                    .unwrap_or_else(|| Lang::En),
-            ))
-        },
-        Duration::try_hours(12).unwrap(),
-    )
-    .query(&key, &())
-    .await?;
-    Ok(res.unwrap_or(Lang::En))
+    LOCAL_LANGS
+        .get_or(&chat.to_string(), || async move {
+            let key = dialog_scope(chat).key(get_lang_key(chat)).await?;
+            let res = default_cache_query(
+                |_, _| async move {
+                    Ok(Some(
+                        dialogs::Entity::find_by_id(chat)
+                            .one(*DB)
+                            .await?
+                            .map(|v| v.language)
+                            .unwrap_or_else(|| Lang::En),
+                    ))
+                },
+                Duration::try_hours(12).unwrap(),
+            )
+            .query(&key, &())
+            .await?;
+            Ok(res.unwrap_or(Lang::En))
+        })
+        .await
+}
+
+/// Drops a chat's language from every instance's local cache after it changed
+pub async fn invalidate_chat_lang(chat: i64) -> Result<()> {
+    LOCAL_LANGS.invalidate(&chat.to_string()).await
 }
 
 /// Sets the current langauge config for the chat
 pub async fn set_chat_lang(chat: &Chat, lang: Lang) -> Result<()> {
-    let r = RedisStr::new(&lang)?;
     let mut c = dialogs::Model::from_chat(chat).await?;
     c.language = Set(lang);
-    let key = get_lang_key(chat.get_id());
-    REDIS
-        .pipe(|p| {
-            p.set(&key, r)
-                .expire(&key, Duration::try_hours(12).unwrap().num_seconds())
-        })
-        .await?;
     dialogs::Entity::insert(c.into_active_model())
         .on_conflict(
             OnConflict::column(dialogs::Column::ChatId)
@@ -534,9 +478,570 @@ pub async fn set_chat_lang(chat: &Chat, lang: Lang) -> Result<()> {
         .exec(*DB)
         .await?;
 
+    dialog_scope(chat.get_id()).invalidate().await?;
+    invalidate_chat_lang(chat.get_id()).await?;
+    Ok(())
+}
+
+#[inline(always)]
+fn get_custom_strings_key(chat: i64) -> String {
+    format!("cstr:{}", chat)
+}
+
+/// Strings a chat's admins replaced with their own text, by string key
+pub type CustomStrings = HashMap<String, String>;
+
+/// Gets the strings a chat's admins replaced with their own text. lang_fmt! uses these
+/// instead of the chat's language when formatting with a context from that chat
+pub async fn get_custom_strings(chat: i64) -> Result<CustomStrings> {
+    let key = dialog_scope(chat).key(get_custom_strings_key(chat)).await?;
+    let res = default_cache_query(
+        |_, _| async move {
+            let strings = chat_strings::Entity::find()
+                .filter(chat_strings::Column::ChatId.eq(chat))
+                .all(*DB)
+                .await?
+                .into_iter()
+                .map(|s| (s.key, s.value))
+                .collect::<CustomStrings>();
+            Ok(Some(strings))
+        },
+        Duration::try_hours(12).unwrap(),
+    )
+    .query(&key, &())
+    .await?;
+    Ok(res.unwrap_or_default())
+}
+
+/// Gets a chat's language along with its custom strings, as needed for every update. Both
+/// are read from redis in one round trip and only looked up separately if one isn't cached
+pub async fn get_chat_lang_strings(chat: i64) -> Result<(Lang, CustomStrings)> {
+    if local::enabled() {
+        return Ok((get_chat_lang(chat).await?, get_custom_strings(chat).await?));
+    }
+    let cached = dialog_scope(chat)
+        .get_all(&[get_lang_key(chat), get_custom_strings_key(chat)])
+        .await?;
+    let (lang, strings) = match cached.as_slice() {
+        [lang, strings] => (lang.as_ref(), strings.as_ref()),
+        _ => (None, None),
+    };
+    let lang = match lang {
+        Some(lang) => lang.get::<Option<Lang>>()?.unwrap_or(Lang::En),
+        None => get_chat_lang(chat).await?,
+    };
+    let strings = match strings {
+        Some(strings) => strings.get::<Option<CustomStrings>>()?.unwrap_or_default(),
+        None => get_custom_strings(chat).await?,
+    };
+    Ok((lang, strings))
+}
+
+async fn invalidate_custom_strings(chat: i64) -> Result<()> {
+    let key = dialog_scope(chat).key(get_custom_strings_key(chat)).await?;
+    REDIS.sq(|q| q.del(&key)).await?;
     Ok(())
 }
 
+/// Replaces a string with custom text in a chat
+pub async fn set_custom_string(chat: i64, key: &str, value: String) -> Result<()> {
+    chat_strings::Entity::insert(
+        chat_strings::Model {
+            chat_id: chat,
+            key: key.to_owned(),
+            value,
+        }
+        .into_active_model(),
+    )
+    .on_conflict(
+        OnConflict::columns([chat_strings::Column::ChatId, chat_strings::Column::Key])
+            .update_column(chat_strings::Column::Value)
+            .to_owned(),
+    )
+    .exec(*DB)
+    .await?;
+    invalidate_custom_strings(chat).await
+}
+
+/// Goes back to the chat language's text for a string, returning false if the chat never
+/// replaced it
+pub async fn reset_custom_string(chat: i64, key: &str) -> Result<bool> {
+    let res = chat_strings::Entity::delete_by_id((chat, key.to_owned()))
+        .exec(*DB)
+        .await?;
+    invalidate_custom_strings(chat).await?;
+    Ok(res.rows_affected > 0)
+}
+
+/// Counts the `{}` placeholders in a format string, skipping `{{` and `}}` escapes
+pub fn count_placeholders(format: &str) -> usize {
+    format
+        .replace("{{", "")
+        .replace("}}", "")
+        .matches("{}")
+        .count()
+}
+
+/// Formats a custom string at runtime the way format! formats compiled ones. Only plain `{}`
+/// placeholders are filled, in order. Placeholders without an argument are left out
+pub fn format_custom_string(format: &str, args: &[&dyn Display]) -> String {
+    let mut res = String::with_capacity(format.len());
+    let mut args = args.iter();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                res.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                if let Some(arg) = args.next() {
+                    write!(res, "{}", arg).ok();
+                }
+            }
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+/// Plural categories from the unicode plural rules. Languages use some subset of these to
+/// pick the form of a word that goes with a number
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Plural {
+    One,
+    Few,
+    Many,
+    Other,
+}
+
+impl Plural {
+    fn suffix(&self) -> &'static str {
+        match self {
+            Self::One => "one",
+            Self::Few => "few",
+            Self::Many => "many",
+            Self::Other => "other",
+        }
+    }
+}
+
+const MONTHS_EN: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const MONTHS_ES: [&str; 12] = [
+    "enero",
+    "febrero",
+    "marzo",
+    "abril",
+    "mayo",
+    "junio",
+    "julio",
+    "agosto",
+    "septiembre",
+    "octubre",
+    "noviembre",
+    "diciembre",
+];
+
+/// Ukrainian months are written in the genitive case after a day
+const MONTHS_UK: [&str; 12] = [
+    "січня",
+    "лютого",
+    "березня",
+    "квітня",
+    "травня",
+    "червня",
+    "липня",
+    "серпня",
+    "вересня",
+    "жовтня",
+    "листопада",
+    "грудня",
+];
+
+const MONTHS_FA: [&str; 12] = [
+    "ژانویه",
+    "فوریه",
+    "مارس",
+    "آوریل",
+    "مه",
+    "ژوئن",
+    "ژوئیه",
+    "اوت",
+    "سپتامبر",
+    "اکتبر",
+    "نوامبر",
+    "دسامبر",
+];
+
+const MONTHS_HI: [&str; 12] = [
+    "जनवरी",
+    "फ़रवरी",
+    "मार्च",
+    "अप्रैल",
+    "मई",
+    "जून",
+    "जुलाई",
+    "अगस्त",
+    "सितंबर",
+    "अक्तूबर",
+    "नवंबर",
+    "दिसंबर",
+];
+
+const MONTHS_BN: [&str; 12] = [
+    "জানুয়ারী",
+    "ফেব্রুয়ারী",
+    "মার্চ",
+    "এপ্রিল",
+    "মে",
+    "জুন",
+    "জুলাই",
+    "আগস্ট",
+    "সেপ্টেম্বর",
+    "অক্টোবর",
+    "নভেম্বর",
+    "ডিসেম্বর",
+];
+
+const MONTHS_TA: [&str; 12] = [
+    "ஜனவரி",
+    "பிப்ரவரி",
+    "மார்ச்",
+    "ஏப்ரல்",
+    "மே",
+    "ஜூன்",
+    "ஜூலை",
+    "ஆகஸ்ட்",
+    "செப்டம்பர்",
+    "அக்டோபர்",
+    "நவம்பர்",
+    "டிசம்பர்",
+];
+
+/// Units durations are broken into, largest first, with the seconds in each
+const DURATION_UNITS: [(&str, u64); 4] = [
+    ("day", 24 * 60 * 60),
+    ("hour", 60 * 60),
+    ("minute", 60),
+    ("second", 1),
+];
+
+impl Lang {
+    /// Gets the zero digit of the digits this language writes numbers with, other digits
+    /// follow it in order
+    fn zero_digit(&self) -> char {
+        match self {
+            Self::Fa => '\u{06F0}',
+            Self::Bn => '\u{09E6}',
+            _ => '0',
+        }
+    }
+
+    /// Gets the separator between digit groups and whether groups after the first are two
+    /// digits long, as in the indian numbering system
+    fn grouping(&self) -> (&'static str, bool) {
+        match self {
+            Self::Es => (".", false),
+            Self::Uk => ("\u{A0}", false),
+            Self::Fa => ("\u{066C}", false),
+            Self::Hi | Self::Bn | Self::Ta => (",", true),
+            _ => (",", false),
+        }
+    }
+
+    /// Gets the plural category of a whole number in this language
+    pub fn plural(&self, n: u64) -> Plural {
+        match self {
+            Self::Ja | Self::Ko | Self::ZhTw => Plural::Other,
+            Self::Hi | Self::Bn | Self::Fa if n <= 1 => Plural::One,
+            Self::Uk => match (n % 10, n % 100) {
+                (1, m) if m != 11 => Plural::One,
+                (2..=4, m) if !(12..=14).contains(&m) => Plural::Few,
+                _ => Plural::Many,
+            },
+            _ if n == 1 => Plural::One,
+            _ => Plural::Other,
+        }
+    }
+
+    /// Writes a number with this language's digits and digit grouping
+    pub fn format_number<T: Into<i128>>(&self, n: T) -> String {
+        let n: i128 = n.into();
+        let digits = n.unsigned_abs().to_string();
+        let (separator, indian) = self.grouping();
+        // spanish doesn't group four digit numbers
+        let min_grouped = if *self == Self::Es { 5 } else { 4 };
+
+        let mut groups = Vec::new();
+        let mut rest = digits.as_str();
+        if rest.len() >= min_grouped {
+            let mut size = 3;
+            while rest.len() > size {
+                let idx = rest.len() - size;
+                let (Some(head), Some(tail)) = (rest.get(..idx), rest.get(idx..)) else {
+                    break;
+                };
+                groups.push(tail);
+                rest = head;
+                if indian {
+                    size = 2;
+                }
+            }
+        }
+        groups.push(rest);
+        groups.reverse();
+
+        let sign = if n < 0 { "-" } else { "" };
+        format!("{}{}", sign, self.localize_digits(&groups.join(separator)))
+    }
+
+    /// Gets the form of a string for a number, keyed `<key>_<category>`, falling back to the
+    /// `other` form for categories the language doesn't translate
+    fn get_plural_string(&self, key: &str, n: u64) -> Option<&'static str> {
+        self.get_string(&format!("{}_{}", key, self.plural(n).suffix()))
+            .or_else(|| self.get_string(&format!("{}_other", key)))
+    }
+
+    /// Writes a duration in words, like 1 hour 30 minutes, using this language's plurals and
+    /// digits
+    pub fn format_duration(&self, duration: std::time::Duration) -> String {
+        let mut secs = duration.as_secs();
+        let mut parts = Vec::new();
+        for (unit, size) in DURATION_UNITS {
+            let count = secs / size;
+            secs %= size;
+            if count == 0 && !(parts.is_empty() && size == 1) {
+                continue;
+            }
+            let key = format!("duration{}", unit);
+            if let Some(format) = self.get_plural_string(&key, count) {
+                parts.push(format_custom_string(format, &[&self.format_number(count)]));
+            }
+        }
+        parts.join(" ")
+    }
+
+    /// Writes a date and time with this language's month names, digits and date order
+    pub fn format_date<Tz>(&self, time: &DateTime<Tz>) -> String
+    where
+        Tz: TimeZone,
+        Tz::Offset: Display,
+    {
+        let day = self.format_number(time.day());
+        // years aren't grouped like other numbers
+        let year = self.localize_digits(&time.year().to_string());
+        let clock = time.format("%H:%M %Z").to_string();
+        let clock = self.localize_digits(&clock);
+        let month = time.month0() as usize;
+        let months = match self {
+            Self::Es => Some(&MONTHS_ES),
+            Self::Uk => Some(&MONTHS_UK),
+            Self::Fa => Some(&MONTHS_FA),
+            Self::Hi => Some(&MONTHS_HI),
+            Self::Bn => Some(&MONTHS_BN),
+            Self::Ta => Some(&MONTHS_TA),
+            _ => None,
+        };
+        match self {
+            Self::Ja | Self::ZhTw => {
+                let month = self.format_number(time.month());
+                format!("{}年{}月{}日 {}", year, month, day, clock)
+            }
+            Self::Ko => {
+                let month = self.format_number(time.month());
+                format!("{}년 {}월 {}일 {}", year, month, day, clock)
+            }
+            Self::Es => format!("{} de {} de {} {}", day, MONTHS_ES[month], year, clock),
+            _ => match months {
+                Some(months) => format!("{} {} {} {}", day, months[month], year, clock),
+                None => format!("{} {}, {} {}", MONTHS_EN[month], day, year, clock),
+            },
+        }
+    }
+
+    /// Replaces ascii digits in text with this language's digits
+    fn localize_digits(&self, text: &str) -> String {
+        let zero = self.zero_digit() as u32;
+        text.chars()
+            .map(|c| match c.to_digit(10) {
+                Some(d) => char::from_u32(zero + d).unwrap_or(c),
+                None => c,
+            })
+            .collect()
+    }
+}
+
+/// Longest message telegram accepts, in utf-16 code units
+pub const MAX_MESSAGE_LEN: usize = 4096;
+
+/// Entities that stop meaning anything if they are cut in half
+const ATOMIC_ENTITIES: &[&str] = &[
+    "mention",
+    "hashtag",
+    "cashtag",
+    "bot_command",
+    "url",
+    "email",
+    "phone_number",
+    "text_link",
+    "text_mention",
+    "custom_emoji",
+];
+
+/// Finds where to end a chunk starting at `start`, returning the end of the chunk and the
+/// start of the next one. Chunks end at the last newline that fits, then the last space,
+/// preferably outside of any entity and never inside an entity that can't be split
+fn split_point(units: &[u16], entities: &[MessageEntity], start: usize) -> (usize, usize) {
+    let limit = start + MAX_MESSAGE_LEN;
+    if limit >= units.len() {
+        return (units.len(), units.len());
+    }
+    let inside = |p: usize, atomic_only: bool| {
+        entities.iter().any(|e| {
+            let (offset, end) = (
+                e.get_offset() as usize,
+                (e.get_offset() + e.get_length()) as usize,
+            );
+            offset < p && p < end && (!atomic_only || ATOMIC_ENTITIES.contains(&e.get_tg_type()))
+        })
+    };
+    let find = |sep: Option<u16>, atomic_only: bool| {
+        (start + 1..=limit).rev().find(|&p| {
+            let fits = match sep {
+                Some(sep) => units.get(p) == Some(&sep),
+                // don't split surrogate pairs
+                None => !(0xDC00..=0xDFFF).contains(&units[p]),
+            };
+            fits && !inside(p, atomic_only)
+        })
+    };
+
+    for atomic_only in [false, true] {
+        for sep in [b'\n' as u16, b' ' as u16] {
+            if let Some(p) = find(Some(sep), atomic_only) {
+                // the separator itself is dropped
+                return (p, p + 1);
+            }
+        }
+    }
+    let p = find(None, true)
+        .or_else(|| find(None, false))
+        .unwrap_or(limit);
+    (p, p)
+}
+
+/// Splits a message that is too long for telegram into several that fit. Entities are
+/// moved along with their text, and entities spanning two chunks are split between them
+pub fn split_message(text: &str, entities: &[MessageEntity]) -> Vec<(String, Vec<MessageEntity>)> {
+    let units = text.encode_utf16().collect::<Vec<u16>>();
+    if units.len() <= MAX_MESSAGE_LEN {
+        return vec![(text.to_owned(), entities.to_vec())];
+    }
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < units.len() {
+        let (end, next) = split_point(&units, entities, start);
+        let chunk = String::from_utf16_lossy(&units[start..end]);
+        let chunk_entities = entities
+            .iter()
+            .filter_map(|e| {
+                let offset = (e.get_offset() as usize).max(start);
+                let e_end = ((e.get_offset() + e.get_length()) as usize).min(end);
+                (offset < e_end).then(|| {
+                    let mut e = e.clone();
+                    e.offset = (offset - start) as i64;
+                    e.length = (e_end - offset) as i64;
+                    e
+                })
+            })
+            .collect();
+        chunks.push((chunk, chunk_entities));
+        start = next;
+    }
+    chunks
+}
+
+/// Sends a message, split into several if it is too long for telegram. The first part
+/// replies to the message in `options` and the last part gets the buttons. Returns the last
+/// part sent. If telegram rejects the entities the text is sent without them. With a
+/// `business` connection id the message is sent on behalf of the connected business account
+pub async fn send_split(
+    chat: i64,
+    business: Option<&str>,
+    text: &str,
+    entities: &[MessageEntity],
+    markup: Option<&EReplyMarkup>,
+    options: SendOptions,
+) -> Result<Message> {
+    let chunks = split_message(text, entities);
+    let count = chunks.len();
+    let mut sent = None;
+    for (i, (text, entities)) in chunks.into_iter().enumerate() {
+        let reply = options.reply.filter(|_| i == 0);
+        let markup = markup.filter(|_| i + 1 == count);
+        let message =
+            match send_chunk(chat, business, &text, &entities, markup, reply, &options).await {
+                Err(err) if !entities.is_empty() && err.get_tg_error().contains("entit") => {
+                    log::debug!("telegram rejected entities, sending plain text: {}", err);
+                    send_chunk(chat, business, &text, &[], markup, reply, &options).await?
+                }
+                res => res?,
+            };
+        sent = Some(message);
+    }
+    sent.ok_or_else(|| BotError::generic("tried to send an empty message"))
+}
+
+async fn send_chunk(
+    chat: i64,
+    business: Option<&str>,
+    text: &str,
+    entities: &[MessageEntity],
+    markup: Option<&EReplyMarkup>,
+    reply: Option<i64>,
+    options: &SendOptions,
+) -> Result<Message> {
+    let preview = LinkPreviewOptionsBuilder::new()
+        .set_is_disabled(true)
+        .build();
+    let reply = reply.map(|reply| ReplyParametersBuilder::new(reply).build());
+    let mut call = TG
+        .client()
+        .build_send_message(chat, text)
+        .entities(entities)
+        .link_preview_options(&preview)
+        .disable_notification(options.silent);
+    if let Some(ref reply) = reply {
+        call = call.reply_parameters(reply);
+    }
+    if let Some(markup) = markup {
+        call = call.reply_markup(markup);
+    }
+    if let Some(thread) = options.thread {
+        call = call.message_thread_id(thread);
+    }
+    if let Some(business) = business {
+        call = call.business_connection_id(business);
+    }
+    Ok(call.build().await?)
+}
+
 pub trait AlignCharBoundry {
     fn align_char_boundry(&self, idx: usize) -> usize;
 }
@@ -558,7 +1063,137 @@ impl AlignCharBoundry for String {
 
 #[cfg(test)]
 mod test {
-    use super::AlignCharBoundry;
+    use botapi::gen_types::{MessageEntity, MessageEntityBuilder};
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{
+        count_placeholders, format_custom_string, split_message, AlignCharBoundry, Lang, Plural,
+        MAX_MESSAGE_LEN,
+    };
+
+    fn entity(tg_type: &str, offset: i64, length: i64) -> MessageEntity {
+        MessageEntityBuilder::new(offset, length)
+            .set_type(tg_type.to_owned())
+            .build()
+    }
+
+    fn utf16_len(text: &str) -> usize {
+        text.encode_utf16().count()
+    }
+
+    #[test]
+    fn custom_string_format() {
+        let format = "{} banned {{user}} {}, {}";
+        assert_eq!(count_placeholders(format), 3);
+        assert_eq!(
+            format_custom_string(format, &[&"admin", &42]),
+            "admin banned {user} 42, "
+        );
+        assert_eq!(format_custom_string("no args", &[&1]), "no args");
+        assert_eq!(format_custom_string("{ lone }", &[]), "{ lone }");
+    }
+
+    #[test]
+    fn locale_numbers() {
+        assert_eq!(Lang::En.format_number(1234567), "1,234,567");
+        assert_eq!(Lang::En.format_number(-1000), "-1,000");
+        assert_eq!(Lang::En.format_number(999), "999");
+        assert_eq!(Lang::Hi.format_number(1234567), "12,34,567");
+        assert_eq!(Lang::Es.format_number(1234), "1234");
+        assert_eq!(Lang::Es.format_number(12345), "12.345");
+        assert_eq!(Lang::Fa.format_number(1234), "۱٬۲۳۴");
+        assert_eq!(Lang::Bn.format_number(7), "৭");
+    }
+
+    #[test]
+    fn locale_plurals() {
+        assert_eq!(Lang::En.plural(1), Plural::One);
+        assert_eq!(Lang::En.plural(0), Plural::Other);
+        assert_eq!(Lang::Fa.plural(0), Plural::One);
+        assert_eq!(Lang::Uk.plural(21), Plural::One);
+        assert_eq!(Lang::Uk.plural(3), Plural::Few);
+        assert_eq!(Lang::Uk.plural(12), Plural::Many);
+        assert_eq!(Lang::Uk.plural(11), Plural::Many);
+        assert_eq!(Lang::Ja.plural(1), Plural::Other);
+    }
+
+    #[test]
+    fn locale_durations_and_dates() {
+        let duration = std::time::Duration::from_secs(5400);
+        assert_eq!(Lang::En.format_duration(duration), "1 hour 30 minutes");
+        assert_eq!(
+            Lang::En.format_duration(std::time::Duration::ZERO),
+            "0 seconds"
+        );
+        let time = Utc.with_ymd_and_hms(2026, 10, 16, 12, 30, 0).unwrap();
+        assert_eq!(Lang::En.format_date(&time), "October 16, 2026 12:30 UTC");
+        assert_eq!(Lang::Ja.format_date(&time), "2026年10月16日 12:30 UTC");
+        assert_eq!(Lang::Uk.format_date(&time), "16 жовтня 2026 12:30 UTC");
+    }
+
+    #[test]
+    fn split_short() {
+        let chunks = split_message("short", &[entity("bold", 0, 5)]);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].0, "short");
+        assert_eq!(chunks[0].1.len(), 1);
+    }
+
+    #[test]
+    fn split_on_newlines() {
+        let line = "a".repeat(99);
+        let text = vec![line.as_str(); 100].join("\n");
+        let chunks = split_message(&text, &[]);
+        assert!(chunks.len() > 1);
+        for (chunk, _) in &chunks {
+            assert!(utf16_len(chunk) <= MAX_MESSAGE_LEN);
+            assert!(chunk.split('\n').all(|l| l == line));
+        }
+        let joined = chunks
+            .into_iter()
+            .map(|(chunk, _)| chunk)
+            .collect::<Vec<String>>()
+            .join("\n");
+        assert_eq!(joined, text);
+    }
+
+    #[test]
+    fn split_entities() {
+        let text = "a".repeat(4200);
+        let link = entity("text_link", 4090, 20);
+        let bold = entity("bold", 0, 4200);
+        let chunks = split_message(&text, &[link, bold]);
+        assert_eq!(chunks.len(), 2);
+
+        // the link can't be cut, so the first chunk ends before it
+        assert_eq!(utf16_len(&chunks[0].0), 4090);
+        assert_eq!(utf16_len(&chunks[1].0), 110);
+        let flat = |entities: &[MessageEntity]| {
+            let mut flat = entities
+                .iter()
+                .map(|e| (e.get_tg_type().to_owned(), e.get_offset(), e.get_length()))
+                .collect::<Vec<_>>();
+            flat.sort();
+            flat
+        };
+        assert_eq!(flat(&chunks[0].1), vec![("bold".to_owned(), 0, 4090)]);
+        assert_eq!(
+            flat(&chunks[1].1),
+            vec![("bold".to_owned(), 0, 110), ("text_link".to_owned(), 0, 20)]
+        );
+    }
+
+    #[test]
+    fn split_surrogates() {
+        let text = "🦀".repeat(3000);
+        let chunks = split_message(&text, &[]);
+        assert_eq!(chunks.len(), 2);
+        for (chunk, _) in &chunks {
+            assert!(utf16_len(chunk) <= MAX_MESSAGE_LEN);
+            assert!(chunk.chars().all(|c| c == '🦀'));
+        }
+    }
 
     #[test]
     fn align_cyrillic_shit() {
@@ -0,0 +1,195 @@
+//! Timezone aware handling of times shown to or entered by users. Chats can set an IANA
+//! timezone with /settz which is stored in the dialogs table. Times are always stored and
+//! scheduled in utc and only converted to the chat's timezone when parsed or displayed
+
+use crate::persist::core::dialogs;
+use crate::persist::redis::{default_cache_query, CachedQueryTrait};
+use crate::statics::DB;
+use crate::tg::dialog::dialog_scope;
+use crate::util::error::Result;
+use crate::util::string::Lang;
+use botapi::gen_types::Chat;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::ActiveValue::Set;
+use sea_orm::EntityTrait;
+
+/// Format used when showing a date and time to users
+const DISPLAY_FORMAT: &str = "%Y-%m-%d %H:%M %Z";
+
+#[inline(always)]
+fn get_tz_key(chat: i64) -> String {
+    format!("tz:{}", chat)
+}
+
+/// Parses an IANA timezone name like Europe/Berlin, ignoring case
+pub fn parse_timezone(name: &str) -> Option<Tz> {
+    let name = name.trim();
+    name.parse::<Tz>().ok().or_else(|| {
+        chrono_tz::TZ_VARIANTS
+            .iter()
+            .find(|tz| tz.name().eq_ignore_ascii_case(name))
+            .copied()
+    })
+}
+
+/// Gets the timezone configured for a chat, defaulting to utc
+pub async fn get_chat_tz(chat: i64) -> Result<Tz> {
+    let key = dialog_scope(chat).key(get_tz_key(chat)).await?;
+    let res = default_cache_query(
+        |_, _| async move {
+            Ok(Some(
+                dialogs::Entity::find_by_id(chat)
+                    .one(*DB)
+                    .await?
+                    .and_then(|v| v.timezone)
+                    .unwrap_or_else(|| Tz::UTC.name().to_owned()),
+            ))
+        },
+        Duration::try_hours(12).unwrap(),
+    )
+    .query(&key, &())
+    .await?;
+    Ok(res.and_then(|v| parse_timezone(&v)).unwrap_or(Tz::UTC))
+}
+
+/// Sets the timezone for a chat
+pub async fn set_chat_tz(chat: &Chat, tz: Tz) -> Result<()> {
+    let mut model = dialogs::Model::from_chat(chat).await?;
+    model.timezone = Set(Some(tz.name().to_owned()));
+    dialogs::Entity::insert(model)
+        .on_conflict(
+            OnConflict::column(dialogs::Column::ChatId)
+                .update_column(dialogs::Column::Timezone)
+                .to_owned(),
+        )
+        .exec(*DB)
+        .await?;
+
+    dialog_scope(chat.get_id()).invalidate().await?;
+    Ok(())
+}
+
+/// Converts between utc and a chat's local time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChatTime {
+    tz: Tz,
+}
+
+impl Default for ChatTime {
+    fn default() -> Self {
+        Self::new(Tz::UTC)
+    }
+}
+
+impl ChatTime {
+    pub fn new(tz: Tz) -> Self {
+        Self { tz }
+    }
+
+    /// Gets the local time for a chat using its configured timezone
+    pub async fn get(chat: i64) -> Result<Self> {
+        Ok(Self::new(get_chat_tz(chat).await?))
+    }
+
+    pub fn tz(&self) -> Tz {
+        self.tz
+    }
+
+    /// Converts a utc time to the chat's local time
+    pub fn local(&self, time: &DateTime<Utc>) -> DateTime<Tz> {
+        time.with_timezone(&self.tz)
+    }
+
+    /// The current date in the chat
+    pub fn today(&self) -> NaiveDate {
+        self.local(&Utc::now()).date_naive()
+    }
+
+    /// Converts a local time in the chat to utc. Ambiguous times during dst changes resolve
+    /// to the earlier time and times skipped by dst changes are moved forward an hour
+    pub fn to_utc(&self, time: NaiveDateTime) -> Option<DateTime<Utc>> {
+        self.tz
+            .from_local_datetime(&time)
+            .earliest()
+            .or_else(|| {
+                self.tz
+                    .from_local_datetime(&(time + Duration::try_hours(1)?))
+                    .earliest()
+            })
+            .map(|v| v.with_timezone(&Utc))
+    }
+
+    /// Formats a utc time as local time for display
+    pub fn format(&self, time: &DateTime<Utc>) -> String {
+        self.local(time).format(DISPLAY_FORMAT).to_string()
+    }
+
+    /// Formats a utc time in the chat's timezone the way the chat's language writes dates
+    pub fn format_lang(&self, time: &DateTime<Utc>, lang: &Lang) -> String {
+        lang.format_date(&self.local(time))
+    }
+
+    /// Returns the next midnight in the chat after `now`
+    pub fn next_midnight(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        self.local(&now)
+            .date_naive()
+            .succ_opt()
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+            .and_then(|d| self.to_utc(d))
+            .unwrap_or(now + Duration::try_days(1).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn timezones() {
+        assert_eq!(parse_timezone("Europe/Berlin"), Some(Tz::Europe__Berlin));
+        assert_eq!(parse_timezone("europe/berlin"), Some(Tz::Europe__Berlin));
+        assert_eq!(parse_timezone("utc"), Some(Tz::UTC));
+        assert_eq!(parse_timezone("Mars/Olympus_Mons"), None);
+    }
+
+    #[test]
+    fn format_local() {
+        let time = Utc.with_ymd_and_hms(2024, 7, 1, 12, 30, 0).unwrap();
+        let berlin = ChatTime::new(Tz::Europe__Berlin);
+        assert_eq!(berlin.format(&time), "2024-07-01 14:30 CEST");
+        assert_eq!(ChatTime::default().format(&time), "2024-07-01 12:30 UTC");
+    }
+
+    #[test]
+    fn midnight() {
+        let now = Utc.with_ymd_and_hms(2024, 5, 1, 22, 30, 0).unwrap();
+        assert_eq!(
+            ChatTime::default().next_midnight(now),
+            Utc.with_ymd_and_hms(2024, 5, 2, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            ChatTime::new(Tz::Europe__Berlin).next_midnight(now),
+            Utc.with_ymd_and_hms(2024, 5, 2, 22, 0, 0).unwrap()
+        );
+        assert_eq!(
+            ChatTime::new(Tz::America__New_York).next_midnight(now),
+            Utc.with_ymd_and_hms(2024, 5, 2, 4, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn dst_gap() {
+        // clocks in berlin skip from 02:00 to 03:00 on 2024-03-31
+        let berlin = ChatTime::new(Tz::Europe__Berlin);
+        let gap = NaiveDate::from_ymd_opt(2024, 3, 31)
+            .unwrap()
+            .and_hms_opt(2, 30, 0)
+            .unwrap();
+        assert_eq!(
+            berlin.to_utc(gap),
+            Some(Utc.with_ymd_and_hms(2024, 3, 31, 1, 30, 0).unwrap())
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! Per-update trace ids. Every update is handled inside an `update` span carrying a random
+//! trace id, so every log line written while handling it, including from redis and the
+//! database, can be found by that id even when many chats are busy at once. Errors that
+//! are logged include the id too, see [`current_trace_id`].
+
+use std::fmt::{self, Display};
+use std::future::Future;
+
+use tracing::Instrument;
+
+tokio::task_local! {
+    static TRACE_ID: TraceId;
+}
+
+/// Random id of one update's handling
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceId(u64);
+
+impl TraceId {
+    pub fn new() -> Self {
+        Self(rand::random())
+    }
+}
+
+impl Default for TraceId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Trace id of the update being handled, None outside of update handling
+pub fn current_trace_id() -> Option<TraceId> {
+    TRACE_ID.try_with(|id| *id).ok()
+}
+
+/// Runs the handling of one update under a new trace id and `update` span. The span's
+/// `chat` field is filled in once the update's chat is known
+pub async fn with_trace<F: Future>(update_id: i64, bot: i64, fut: F) -> F::Output {
+    let id = TraceId::new();
+    let span = tracing::info_span!(
+        "update",
+        trace_id = %id,
+        update_id,
+        bot,
+        chat = tracing::field::Empty
+    );
+    TRACE_ID.scope(id, fut.instrument(span)).await
+}
+
+/// Keeps the current trace id and span in a future that will run on another task
+pub fn propagate<F: Future>(fut: F) -> impl Future<Output = F::Output> {
+    let span = tracing::Span::current();
+    let id = current_trace_id();
+    async move {
+        match id {
+            Some(id) => TRACE_ID.scope(id, fut.instrument(span)).await,
+            None => fut.instrument(span).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn trace_id_scope() {
+        assert_eq!(current_trace_id(), None);
+        let id = with_trace(1, 2, async { current_trace_id() }).await;
+        assert!(id.is_some());
+        let inner = with_trace(1, 2, async {
+            let outer = current_trace_id();
+            let inner = tokio::spawn(propagate(async { current_trace_id() }))
+                .await
+                .unwrap();
+            assert_eq!(outer, inner);
+            inner
+        })
+        .await;
+        assert!(inner.is_some());
+    }
+}
@@ -0,0 +1,265 @@
+//! Matching messages against every trigger in a chat at once. A chat's triggers are
+//! compiled into a single aho-corasick automaton, so finding the ones appearing in a
+//! message takes one pass over the message however many triggers the chat has. Globs are
+//! found through the longest run of plain text in their pattern and then checked with
+//! [`WildMatch`]. Globs without any plain text are checked against every message.
+//!
+//! Compiled matchers are kept in memory per redis hash of triggers, along with the
+//! generation stored in that hash. Anything changing the triggers in a hash writes a new
+//! generation with [`next_generation`], and the matcher is rebuilt the next time it's used.
+//! Only the most recently used matchers are kept, and ones left unused for a while are
+//! dropped, so chats that stopped talking don't hold on to their automatons.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use moka::sync::Cache;
+use redis::AsyncCommands;
+
+use crate::statics::REDIS;
+use crate::util::error::{BotError, Result};
+use crate::util::glob::WildMatch;
+
+/// Most matchers kept in memory per [`MatcherCache`]
+const MATCHER_CAPACITY: u64 = 10_000;
+
+/// How long a matcher is kept without being used
+const MATCHER_IDLE: Duration = Duration::from_secs(60 * 60);
+
+/// Hash field holding the generation of the triggers in the rest of the hash
+pub const GENERATION_FIELD: &str = "";
+
+/// Returns a generation to store after changing a hash of triggers
+pub fn next_generation() -> i64 {
+    rand::random::<i64>().saturating_abs().max(1)
+}
+
+/// How a trigger matches text
+#[derive(Debug, Clone)]
+pub enum TriggerPattern {
+    /// matches text containing this string
+    Literal(String),
+    /// matches text matching this glob
+    Glob(String),
+}
+
+impl TriggerPattern {
+    /// The text every match has to contain
+    fn needle(&self) -> &str {
+        match self {
+            Self::Literal(text) => text,
+            Self::Glob(glob) => glob
+                .split(['*', '?'])
+                .max_by_key(|part| part.len())
+                .unwrap_or(""),
+        }
+    }
+}
+
+struct Trigger<T> {
+    text: String,
+    glob: Option<WildMatch>,
+    value: T,
+}
+
+/// A set of triggers compiled for matching
+pub struct TriggerMatcher<T> {
+    automaton: AhoCorasick,
+
+    /// index into `triggers` of each pattern in the automaton
+    needles: Vec<usize>,
+
+    /// globs without plain text to search for
+    unanchored: Vec<usize>,
+    triggers: Vec<Trigger<T>>,
+}
+
+impl<T> TriggerMatcher<T> {
+    pub fn new<I>(triggers: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (TriggerPattern, T)>,
+    {
+        let mut needles = Vec::new();
+        let mut patterns = Vec::new();
+        let mut unanchored = Vec::new();
+        let triggers = triggers
+            .into_iter()
+            .enumerate()
+            .map(|(idx, (pattern, value))| {
+                let needle = pattern.needle();
+                if needle.is_empty() {
+                    unanchored.push(idx);
+                } else {
+                    needles.push(idx);
+                    patterns.push(needle.to_owned());
+                }
+                match pattern {
+                    TriggerPattern::Literal(text) => Trigger {
+                        text,
+                        glob: None,
+                        value,
+                    },
+                    TriggerPattern::Glob(text) => Trigger {
+                        glob: Some(WildMatch::new(&text)),
+                        text,
+                        value,
+                    },
+                }
+            })
+            .collect();
+        let automaton = AhoCorasick::builder()
+            .match_kind(MatchKind::Standard)
+            .build(patterns)
+            .map_err(|err| BotError::generic(err.to_string()))?;
+        Ok(Self {
+            automaton,
+            needles,
+            unanchored,
+            triggers,
+        })
+    }
+
+    /// Number of triggers in this matcher
+    pub fn len(&self) -> usize {
+        self.triggers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triggers.is_empty()
+    }
+
+    /// Triggers matching `text` along with where their plain text was found, in the order
+    /// they appear in the text. Globs without plain text come last with no position
+    pub fn matches<'a>(&'a self, text: &str) -> Vec<(&'a str, &'a T, Option<usize>)> {
+        let mut seen = vec![false; self.triggers.len()];
+        let mut found = Vec::new();
+        for m in self.automaton.find_overlapping_iter(text) {
+            let idx = self.needles[m.pattern().as_usize()];
+            if seen[idx] {
+                continue;
+            }
+            seen[idx] = true;
+            let trigger = &self.triggers[idx];
+            if trigger
+                .glob
+                .as_ref()
+                .map_or(true, |glob| glob.matches(text))
+            {
+                found.push((trigger.text.as_str(), &trigger.value, Some(m.start())));
+            }
+        }
+        for &idx in self.unanchored.iter() {
+            let trigger = &self.triggers[idx];
+            if trigger
+                .glob
+                .as_ref()
+                .map_or(true, |glob| glob.matches(text))
+            {
+                found.push((trigger.text.as_str(), &trigger.value, None));
+            }
+        }
+        found
+    }
+}
+
+/// Compiled matchers for redis hashes of triggers, rebuilt when a hash's generation
+/// changes. `M` is whatever a module compiles its triggers into, usually a [`TriggerMatcher`]
+pub struct MatcherCache<M> {
+    matchers: Cache<String, (i64, Arc<M>)>,
+}
+
+impl<M> Default for MatcherCache<M>
+where
+    M: Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self {
+            matchers: Cache::builder()
+                .max_capacity(MATCHER_CAPACITY)
+                .time_to_idle(MATCHER_IDLE)
+                .build(),
+        }
+    }
+}
+
+impl<M> MatcherCache<M>
+where
+    M: Send + Sync + 'static,
+{
+    /// Returns the matcher for the triggers in `hash_key`, compiling them with `build` if
+    /// they changed since it was last compiled. Returns None if the hash isn't cached
+    pub async fn get<F, Fut>(&self, hash_key: &str, build: F) -> Result<Option<Arc<M>>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<M>>,
+    {
+        let generation: Option<i64> = REDIS.sq(|q| q.hget(hash_key, GENERATION_FIELD)).await?;
+        let Some(generation) = generation else {
+            self.matchers.invalidate(hash_key);
+            return Ok(None);
+        };
+        if let Some((cached, matcher)) = self.matchers.get(hash_key) {
+            if cached == generation {
+                return Ok(Some(matcher));
+            }
+        }
+        let matcher = Arc::new(build().await?);
+        self.matchers
+            .insert(hash_key.to_owned(), (generation, Arc::clone(&matcher)));
+        Ok(Some(matcher))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn matcher() -> TriggerMatcher<i64> {
+        TriggerMatcher::new([
+            (TriggerPattern::Literal("hi".to_owned()), 1),
+            (TriggerPattern::Literal("hi there".to_owned()), 2),
+            (TriggerPattern::Glob("*thing".to_owned()), 3),
+            (TriggerPattern::Glob("*".to_owned()), 4),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn finds_overlapping_triggers() {
+        let matcher = matcher();
+        let found = matcher
+            .matches("hi there")
+            .into_iter()
+            .map(|(_, v, _)| *v)
+            .collect::<Vec<i64>>();
+        assert_eq!(found, vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn checks_globs() {
+        let matcher = matcher();
+        let found = matcher
+            .matches("doof mything fue")
+            .into_iter()
+            .map(|(_, v, _)| *v)
+            .collect::<Vec<i64>>();
+        assert_eq!(found, vec![3, 4]);
+        let found = matcher
+            .matches("doof mythings fue")
+            .into_iter()
+            .map(|(_, v, _)| *v)
+            .collect::<Vec<i64>>();
+        assert_eq!(found, vec![4]);
+    }
+
+    #[test]
+    fn glob_needle() {
+        assert_eq!(
+            TriggerPattern::Glob("*b?tcoin*".to_owned()).needle(),
+            "tcoin"
+        );
+        assert_eq!(TriggerPattern::Glob("**".to_owned()).needle(), "");
+    }
+}